@@ -0,0 +1,161 @@
+//! A `CodeMapper` that memoizes the line lookups of another one, for
+//! emitters that resolve many positions on the same few lines.
+
+use crate::syntax_pos::{BytePos, CharPos, FileLinesResult, FileMap, FileName, Loc, Pos, Span};
+use crate::CodeMapper;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+/// The number of lines `CachingCodemapView` remembers.
+const CACHE_SIZE: usize = 4;
+
+#[derive(Clone)]
+struct CacheEntry {
+    /// The filemap the line is in. Entries are matched against the filemap
+    /// itself rather than its name, so filemaps added or replaced later
+    /// can't be confused with it.
+    file: Rc<FileMap>,
+    /// The (1-based) line number.
+    line: usize,
+    /// The start of the line, and of the line after it (or one past the end
+    /// of the file).
+    start: BytePos,
+    end: BytePos,
+    /// The multibyte chars of the line, as indices into
+    /// `file.multibyte_chars`.
+    mbc_start: usize,
+    mbc_end: usize,
+    /// The lengths of `file.lines` and `file.multibyte_chars` when the entry
+    /// was made. Both grow while a file is being lexed, which can move the
+    /// end of its last line.
+    lines_len: usize,
+    mbcs_len: usize,
+    time_stamp: usize,
+}
+
+impl CacheEntry {
+    fn is_valid(&self) -> bool {
+        self.file.lines.borrow().len() == self.lines_len
+            && self.file.multibyte_chars.borrow().len() == self.mbcs_len
+    }
+
+    fn contains(&self, pos: BytePos) -> bool {
+        self.start <= pos && pos < self.end
+    }
+
+    fn col(&self, pos: BytePos) -> CharPos {
+        let mbcs = self.file.multibyte_chars.borrow();
+        let extra_bytes: usize = mbcs[self.mbc_start..self.mbc_end]
+            .iter()
+            .take_while(|mbc| mbc.pos < pos)
+            .map(|mbc| mbc.bytes - 1)
+            .sum();
+        CharPos((pos - self.start).to_usize() - extra_bytes)
+    }
+}
+
+/// Wraps a `CodeMapper`, answering `lookup_char_pos` from a cache of the
+/// most recently used lines where possible. Everything else is passed
+/// through unchanged.
+pub struct CachingCodemapView {
+    codemap: Rc<dyn CodeMapper>,
+    lines: RefCell<Vec<CacheEntry>>,
+    time_stamp: Cell<usize>,
+}
+
+impl CachingCodemapView {
+    pub fn new(codemap: Rc<dyn CodeMapper>) -> CachingCodemapView {
+        CachingCodemapView {
+            codemap,
+            lines: RefCell::new(Vec::with_capacity(CACHE_SIZE)),
+            time_stamp: Cell::new(0),
+        }
+    }
+
+    /// The uncached `CodeMapper`.
+    pub fn codemap(&self) -> &Rc<dyn CodeMapper> {
+        &self.codemap
+    }
+
+    fn cached_loc(&self, pos: BytePos, time_stamp: usize) -> Option<Loc> {
+        let mut lines = self.lines.borrow_mut();
+        lines.retain(CacheEntry::is_valid);
+        let entry = lines.iter_mut().find(|entry| entry.contains(pos))?;
+        entry.time_stamp = time_stamp;
+        Some(Loc {
+            file: entry.file.clone(),
+            line: entry.line,
+            col: entry.col(pos),
+        })
+    }
+
+    fn cache_line(&self, loc: &Loc, time_stamp: usize) {
+        let file = &loc.file;
+        let file_lines = file.lines.borrow();
+        let mbcs = file.multibyte_chars.borrow();
+        if loc.line == 0 || loc.line > file_lines.len() {
+            return;
+        }
+        let start = file_lines[loc.line - 1];
+        let end = match file_lines.get(loc.line) {
+            Some(&next) => next,
+            None => file.end_pos + BytePos(1),
+        };
+        let mbc_start = mbcs.iter().take_while(|mbc| mbc.pos < start).count();
+        let mbc_end = mbc_start
+            + mbcs[mbc_start..]
+                .iter()
+                .take_while(|mbc| mbc.pos < end)
+                .count();
+        let entry = CacheEntry {
+            file: file.clone(),
+            line: loc.line,
+            start,
+            end,
+            mbc_start,
+            mbc_end,
+            lines_len: file_lines.len(),
+            mbcs_len: mbcs.len(),
+            time_stamp,
+        };
+
+        let mut lines = self.lines.borrow_mut();
+        if lines.len() < CACHE_SIZE {
+            lines.push(entry);
+        } else if let Some(oldest) = lines.iter_mut().min_by_key(|entry| entry.time_stamp) {
+            *oldest = entry;
+        }
+    }
+}
+
+impl CodeMapper for CachingCodemapView {
+    fn lookup_char_pos(&self, pos: BytePos) -> Loc {
+        let time_stamp = self.time_stamp.get() + 1;
+        self.time_stamp.set(time_stamp);
+        if let Some(loc) = self.cached_loc(pos, time_stamp) {
+            return loc;
+        }
+        let loc = self.codemap.lookup_char_pos(pos);
+        self.cache_line(&loc, time_stamp);
+        loc
+    }
+    fn span_to_lines(&self, sp: Span) -> FileLinesResult {
+        self.codemap.span_to_lines(sp)
+    }
+    fn span_to_lines_limited(&self, sp: Span, max_lines: usize) -> FileLinesResult {
+        self.codemap.span_to_lines_limited(sp, max_lines)
+    }
+    fn span_to_string(&self, sp: Span) -> String {
+        self.codemap.span_to_string(sp)
+    }
+    fn span_to_filename(&self, sp: Span) -> FileName {
+        self.codemap.span_to_filename(sp)
+    }
+    fn merge_spans(&self, sp_lhs: Span, sp_rhs: Span) -> Option<Span> {
+        self.codemap.merge_spans(sp_lhs, sp_rhs)
+    }
+    fn ensure_filemap_source_present(&self, file_map: Rc<FileMap>) -> bool {
+        self.codemap.ensure_filemap_source_present(file_map)
+    }
+}
@@ -1,15 +1,17 @@
 use crate::snippet::Style;
+pub use crate::syntax_pos::StringPart;
 use crate::syntax_pos::{MultiSpan, Span};
 use crate::CodeSuggestion;
 use crate::Level;
 use crate::RenderSpan;
 use crate::Substitution;
+use std::collections::BTreeMap;
 use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
 #[must_use]
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Diagnostic {
     pub level: Level,
     pub message: Vec<(String, Style)>,
@@ -17,10 +19,18 @@ pub struct Diagnostic {
     pub span: MultiSpan,
     pub children: Vec<SubDiagnostic>,
     pub suggestions: Vec<CodeSuggestion>,
+    /// Free-form key/value pairs attached by downstream tools (e.g. a rule id
+    /// or fingerprint). Only machine-readable emitters output them.
+    #[serde(default)]
+    pub tool_metadata: Option<BTreeMap<String, String>>,
+    /// Whether this is the summary of a session's errors and warnings from
+    /// `Handler::print_error_summary` rather than a diagnostic of its own.
+    #[serde(default)]
+    pub is_summary: bool,
 }
 
 /// For example a note attached to an error.
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
 pub struct SubDiagnostic {
     pub level: Level,
     pub message: Vec<(String, Style)>,
@@ -54,17 +64,31 @@ impl DiagnosticStyledString {
     }
 }
 
-#[derive(PartialEq, Eq)]
-pub enum StringPart {
-    Normal(String),
-    Highlighted(String),
+impl<'a> From<&'a str> for DiagnosticStyledString {
+    fn from(s: &'a str) -> DiagnosticStyledString {
+        DiagnosticStyledString::normal(s)
+    }
 }
 
-impl StringPart {
-    pub fn content(&self) -> String {
-        match self {
-            &StringPart::Normal(ref s) | &StringPart::Highlighted(ref s) => s.to_owned(),
-        }
+impl From<String> for DiagnosticStyledString {
+    fn from(s: String) -> DiagnosticStyledString {
+        DiagnosticStyledString::normal(s)
+    }
+}
+
+/// Parts styled with `Style::Highlight` are highlighted; any other style is
+/// rendered like the rest of the text.
+impl From<Vec<(String, Style)>> for DiagnosticStyledString {
+    fn from(parts: Vec<(String, Style)>) -> DiagnosticStyledString {
+        DiagnosticStyledString(
+            parts
+                .into_iter()
+                .map(|(s, style)| match style {
+                    Style::Highlight => StringPart::Highlighted(s),
+                    _ => StringPart::Normal(s),
+                })
+                .collect(),
+        )
     }
 }
 
@@ -81,6 +105,8 @@ impl Diagnostic {
             span: MultiSpan::default(),
             children: vec![],
             suggestions: vec![],
+            tool_metadata: None,
+            is_summary: false,
         }
     }
 
@@ -112,6 +138,17 @@ impl Diagnostic {
         self
     }
 
+    /// Like `span_label`, but the label may be a `DiagnosticStyledString` or
+    /// a `Vec<(String, Style)>`, to highlight parts of it.
+    pub fn span_styled_label<T: Into<DiagnosticStyledString>>(
+        &mut self,
+        span: Span,
+        label: T,
+    ) -> &mut Self {
+        self.span.push_styled_span_label(span, label.into().0);
+        self
+    }
+
     pub fn note_expected_found(
         &mut self,
         label: &dyn fmt::Display,
@@ -207,6 +244,47 @@ impl Diagnostic {
         self
     }
 
+    /// Prints out a message with a suggested edit made of several disjoint
+    /// parts, all of which should be applied together.
+    pub fn multipart_suggestion(&mut self, msg: &str, parts: Vec<(Span, String)>) -> &mut Self {
+        self.suggestions.push(CodeSuggestion {
+            substitution_parts: parts
+                .into_iter()
+                .map(|(span, part)| Substitution {
+                    span,
+                    substitutions: vec![part],
+                })
+                .collect(),
+            msg: msg.to_owned(),
+        });
+        self
+    }
+
+    /// Adds a note showing the code with `suggestion` in place of `sp`. Unlike
+    /// `span_suggestion`, which is shown after all the children or folded
+    /// into a label, the note keeps its place among the other children.
+    pub fn span_suggestion_as_note(
+        &mut self,
+        sp: Span,
+        msg: &str,
+        suggestion: String,
+    ) -> &mut Self {
+        let suggestion = CodeSuggestion {
+            substitution_parts: vec![Substitution {
+                span: sp,
+                substitutions: vec![suggestion],
+            }],
+            msg: msg.to_owned(),
+        };
+        self.sub(
+            Level::Note,
+            msg,
+            sp.into(),
+            Some(RenderSpan::Suggestion(suggestion)),
+        );
+        self
+    }
+
     pub fn set_span<S: Into<MultiSpan>>(&mut self, sp: S) -> &mut Self {
         self.span = sp.into();
         self
@@ -224,6 +302,18 @@ impl Diagnostic {
             .collect::<String>()
     }
 
+    /// Attach a piece of tool metadata, replacing any previous value for `key`.
+    pub fn tool_metadata<K: Into<String>, V: Into<String>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> &mut Self {
+        self.tool_metadata
+            .get_or_insert_with(BTreeMap::new)
+            .insert(key.into(), value.into());
+        self
+    }
+
     pub fn styled_message(&self) -> &Vec<(String, Style)> {
         &self.message
     }
@@ -237,7 +327,8 @@ impl Diagnostic {
     pub fn copy_details_not_message(&mut self, from: &Diagnostic) {
         self.span = from.span.clone();
         self.code = from.code.clone();
-        self.children.extend(from.children.iter().cloned())
+        self.children.extend(from.children.iter().cloned());
+        self.tool_metadata = from.tool_metadata.clone();
     }
 
     /// Convenience function for internal use, clients should use one of the
@@ -72,15 +72,22 @@ impl<'a> DiagnosticBuilder<'a> {
             return;
         }
 
-        match self.level {
-            Level::Bug | Level::Fatal | Level::PhaseFatal | Level::Error => {
-                self.handler.bump_err_count();
-            }
+        if !self.handler.is_new_diagnostic(&self.diagnostic) {
+            self.cancel();
+            return;
+        }
 
-            Level::Warning | Level::Note | Level::Help | Level::Cancelled => {}
+        if self.level.is_error() {
+            self.handler.bump_err_count();
+            if self.level.is_fatal() {
+                self.handler.bump_fatal_count();
+            }
+        } else if self.level == Level::Warning {
+            self.handler.bump_warn_count();
         }
 
-        self.handler.emitter.borrow_mut().emit(&self);
+        self.handler.record_emitted_code(&self.diagnostic);
+        self.handler.emit_db(&self);
         self.cancel();
 
         if self.level == Level::Error {
@@ -92,6 +99,40 @@ impl<'a> DiagnosticBuilder<'a> {
         // }
     }
 
+    /// Emit the diagnostic, unless `condition` holds, in which case it is
+    /// cancelled instead. Either way the builder is consumed; nothing is
+    /// counted for a cancelled diagnostic.
+    pub fn emit_unless(mut self, condition: bool) {
+        if condition {
+            self.cancel();
+        } else {
+            self.emit();
+        }
+    }
+
+    /// Cancel the diagnostic if `condition` holds, making a later `emit` a
+    /// no-op that is not counted.
+    pub fn cancel_if(&mut self, condition: bool) -> &mut Self {
+        if condition {
+            self.cancel();
+        }
+        self
+    }
+
+    /// Turn an error into a warning. Diagnostics are only counted once they
+    /// are emitted, so it is then counted as a warning and not as an error,
+    /// or dropped if the handler doesn't emit warnings. Other levels are
+    /// left alone.
+    pub fn downgrade_to_warning(&mut self) -> &mut Self {
+        if self.level == Level::Error {
+            self.level = Level::Warning;
+            if !self.handler.can_emit_warnings {
+                self.cancel();
+            }
+        }
+        self
+    }
+
     /// Add a span/label to be included in the resulting snippet.
     /// This is pushed onto the `MultiSpan` that was created when the
     /// diagnostic was first built. If you don't call this function at
@@ -103,6 +144,26 @@ impl<'a> DiagnosticBuilder<'a> {
         self
     }
 
+    /// Like `span_label`, but parts of the label may be highlighted.
+    pub fn span_styled_label<T: Into<DiagnosticStyledString>>(
+        &mut self,
+        span: Span,
+        label: T,
+    ) -> &mut Self {
+        self.diagnostic.span_styled_label(span, label);
+        self
+    }
+
+    /// Attach a piece of tool metadata, replacing any previous value for `key`.
+    pub fn tool_metadata<K: Into<String>, V: Into<String>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> &mut Self {
+        self.diagnostic.tool_metadata(key, value);
+        self
+    }
+
     forward!(pub fn note_expected_found(&mut self,
                                         label: &dyn fmt::Display,
                                         expected: DiagnosticStyledString,
@@ -139,6 +200,15 @@ impl<'a> DiagnosticBuilder<'a> {
                                      msg: &str,
                                      suggestions: Vec<String>)
                                      -> &mut Self);
+    forward!(pub fn multipart_suggestion(&mut self,
+                                         msg: &str,
+                                         parts: Vec<(Span, String)>)
+                                         -> &mut Self);
+    forward!(pub fn span_suggestion_as_note(&mut self,
+                                            sp: Span,
+                                            msg: &str,
+                                            suggestion: String)
+                                            -> &mut Self);
     forward!(pub fn set_span<S: Into<MultiSpan>>(&mut self, sp: S) -> &mut Self);
     forward!(pub fn code(&mut self, s: String) -> &mut Self);
 
@@ -162,6 +232,15 @@ impl<'a> DiagnosticBuilder<'a> {
         }
     }
 
+    /// Convenience function for internal use, clients should use one of the
+    /// struct_* methods on Handler.
+    pub fn new_diagnostic(handler: &'a Handler, diagnostic: Diagnostic) -> DiagnosticBuilder<'a> {
+        DiagnosticBuilder {
+            handler: handler,
+            diagnostic: diagnostic,
+        }
+    }
+
     pub fn into_diagnostic(mut self) -> Diagnostic {
         // annoyingly, the Drop impl means we can't actually move
         let result = self.diagnostic.clone();
@@ -1,12 +1,18 @@
 use self::Destination::*;
 
-use crate::syntax_pos::{CharPos, FileMap, MultiSpan, Span, DUMMY_SP};
+use crate::syntax_pos::{
+    CharPos, CodeRegion, FileMap, FileName, Loc, MacroBacktrace, MultiSpan, Span, StringPart,
+    DUMMY_SP,
+};
 
+use crate::caching_codemap_view::CachingCodemapView;
 use crate::snippet::{Annotation, AnnotationType, Line, MultilineAnnotation, Style, StyledString};
 use crate::styled_buffer::StyledBuffer;
 use crate::RenderSpan::*;
-use crate::{CodeMapper, CodeSuggestion, DiagnosticBuilder, Level, SubDiagnostic};
+use crate::{CodeMapper, CodeSuggestion, Diagnostic, DiagnosticBuilder, Level, SubDiagnostic};
 
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::cmp::min;
 use std::collections::HashMap;
 use std::io;
@@ -20,6 +26,31 @@ pub trait Emitter {
     fn emit(&mut self, db: &DiagnosticBuilder);
 }
 
+/// Collects diagnostics instead of rendering them. Clones share the same
+/// buffer, so a clone kept by the caller sees everything emitted through the
+/// one given to a `Handler`.
+#[derive(Clone, Default)]
+pub struct BufferedEmitter {
+    diagnostics: Rc<RefCell<Vec<Diagnostic>>>,
+}
+
+impl BufferedEmitter {
+    pub fn new() -> BufferedEmitter {
+        BufferedEmitter::default()
+    }
+
+    /// The diagnostics emitted so far, leaving the buffer empty.
+    pub fn take_diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.replace(Vec::new())
+    }
+}
+
+impl Emitter for BufferedEmitter {
+    fn emit(&mut self, db: &DiagnosticBuilder) {
+        self.diagnostics.borrow_mut().push((**db).clone());
+    }
+}
+
 impl Emitter for EmitterWriter {
     fn emit(&mut self, db: &DiagnosticBuilder) {
         let mut primary_span = db.span.clone();
@@ -37,7 +68,11 @@ impl Emitter for EmitterWriter {
                sugg.substitution_parts[0].substitutions[0].find('\n').is_none()
             {
                 let substitution = &sugg.substitution_parts[0].substitutions[0];
-                let msg = format!("help: {} `{}`", sugg.msg, substitution);
+                let msg = if sugg.is_deletion(0) {
+                    format!("help: {}: remove this", sugg.msg)
+                } else {
+                    format!("help: {} `{}`", sugg.msg, substitution)
+                };
                 primary_span.push_span_label(sugg.substitution_spans().next().unwrap(), msg);
             } else {
                 // if there are multiple suggestions, print them all in full
@@ -55,7 +90,16 @@ impl Emitter for EmitterWriter {
             }
         }
 
+        if let Some(ref cm) = self.cm {
+            let retargeted = self
+                .macro_backtrace
+                .retarget_external(&**cm, &mut primary_span);
+            children.extend(retargeted);
+        }
         self.fix_multispans_in_std_macros(&mut primary_span, &mut children);
+        if let (Some(cm), Some(span)) = (self.cm.as_ref(), db.span.primary_span()) {
+            children.extend(self.macro_backtrace.notes(&**cm, span));
+        }
         self.emit_messages_default(
             &db.level,
             &db.styled_message(),
@@ -72,6 +116,9 @@ pub const MAX_HIGHLIGHT_LINES: usize = 6;
 ///
 /// Arbitrary, but taken from trait import suggestion limit
 pub const MAX_SUGGESTIONS: usize = 4;
+/// maximum number of unannotated lines in a row shown between annotated ones,
+/// more are elided with `...`
+const MAX_UNANNOTATED_GAP: usize = 1;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ColorConfig {
@@ -90,9 +137,216 @@ impl ColorConfig {
     }
 }
 
+fn is_macro_expansion(filename: &FileName) -> bool {
+    matches!(*filename, FileName::MacroExpansion { .. })
+}
+
+/// How much of the chain of macro expansions that produced a diagnostic's
+/// primary span is shown, as notes after the diagnostic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MacroBacktracePolicy {
+    /// A note for every expansion.
+    Always,
+    /// A note for every expansion of a local macro, and a single note for
+    /// each run of expansions of external macros.
+    OnlyLocal,
+    /// No notes.
+    Never,
+}
+
+/// Decides which macro expansions a diagnostic's backtrace shows.
+///
+/// A macro is external if it was defined in an imported filemap, whose
+/// source isn't available, or in a file matched by `external_files`.
+#[derive(Clone)]
+pub struct MacroBacktraceFilter {
+    policy: MacroBacktracePolicy,
+    external_files: Option<Rc<dyn Fn(&FileName) -> bool>>,
+}
+
+impl Default for MacroBacktraceFilter {
+    fn default() -> MacroBacktraceFilter {
+        MacroBacktraceFilter::new(MacroBacktracePolicy::Never)
+    }
+}
+
+impl MacroBacktraceFilter {
+    pub fn new(policy: MacroBacktracePolicy) -> MacroBacktraceFilter {
+        MacroBacktraceFilter {
+            policy,
+            external_files: None,
+        }
+    }
+
+    /// Also treats macros defined in the files for which `is_external`
+    /// returns true as external.
+    pub fn external_files<F>(mut self, is_external: F) -> MacroBacktraceFilter
+    where
+        F: Fn(&FileName) -> bool + 'static,
+    {
+        self.external_files = Some(Rc::new(is_external));
+        self
+    }
+
+    pub fn policy(&self) -> MacroBacktracePolicy {
+        self.policy
+    }
+
+    fn is_external(&self, cm: &dyn CodeMapper, def_site: Option<Span>) -> bool {
+        let def_site = match def_site {
+            Some(def_site) if def_site != DUMMY_SP => def_site,
+            _ => return false,
+        };
+        let file = cm.lookup_char_pos(def_site.lo).file;
+        file.src.is_none()
+            || self
+                .external_files
+                .as_ref()
+                .map_or(false, |f| f(&file.name))
+    }
+
+    /// If every primary span of `span` points into the definition of the
+    /// external macro that produced it, which the user may never have seen,
+    /// moves them to the code that invoked the outermost macro, and returns a
+    /// note naming the macro. Spans are left as they are under
+    /// `MacroBacktracePolicy::Always`.
+    pub fn retarget_external(
+        &self,
+        cm: &dyn CodeMapper,
+        span: &mut MultiSpan,
+    ) -> Option<SubDiagnostic> {
+        if self.policy == MacroBacktracePolicy::Always {
+            return None;
+        }
+        let primary_spans = span.primary_spans().to_vec();
+        let mut name = None;
+        for sp in &primary_spans {
+            let info = sp.ctxt.outer().expn_info()?;
+            if !self.is_external(cm, Some(*sp)) || !self.is_external(cm, info.callee.span) {
+                return None;
+            }
+            name.get_or_insert(info.callee.name());
+        }
+        let name = name?;
+        for sp in primary_spans {
+            span.replace(sp, sp.source_callsite());
+        }
+        Some(SubDiagnostic {
+            level: Level::Note,
+            message: vec![(
+                format!("this error originates in the macro `{}`", name),
+                Style::NoStyle,
+            )],
+            span: MultiSpan::default(),
+            render_span: None,
+        })
+    }
+
+    /// Whether each step of `backtrace` is left out of the notes, or folded
+    /// into a note standing for a run of external expansions.
+    pub fn collapsed(&self, cm: &dyn CodeMapper, backtrace: &[MacroBacktrace]) -> Vec<bool> {
+        backtrace
+            .iter()
+            .map(|trace| match self.policy {
+                MacroBacktracePolicy::Always => false,
+                MacroBacktracePolicy::OnlyLocal => self.is_external(cm, trace.def_site_span),
+                MacroBacktracePolicy::Never => true,
+            })
+            .collect()
+    }
+
+    /// The notes showing the backtrace of `span`, innermost expansion first.
+    fn notes(&self, cm: &dyn CodeMapper, span: Span) -> Vec<SubDiagnostic> {
+        if self.policy == MacroBacktracePolicy::Never {
+            return vec![];
+        }
+        let backtrace = span.macro_backtrace();
+        let collapsed = self.collapsed(cm, &backtrace);
+        let mut notes = vec![];
+        for (i, trace) in backtrace.iter().enumerate() {
+            let message = if !collapsed[i] {
+                format!("in this expansion of {}", trace.macro_decl_name)
+            } else if collapsed.get(i + 1) != Some(&true) {
+                // the outermost of a run of external expansions, which is
+                // the one invoked from the code around it
+                format!(
+                    "in this expansion of {} (from an external macro)",
+                    trace.macro_decl_name
+                )
+            } else {
+                continue;
+            };
+            notes.push(SubDiagnostic {
+                level: Level::Note,
+                message: vec![(message, Style::NoStyle)],
+                span: MultiSpan::from_span(trace.call_site),
+                render_span: None,
+            });
+        }
+        notes
+    }
+}
+
 pub struct EmitterWriter {
     dst: Destination,
     cm: Option<Rc<dyn CodeMapper>>,
+    tab_width: usize,
+    macro_backtrace: MacroBacktraceFilter,
+}
+
+/// The number of columns a tab in the source is shown as, unless set with
+/// `EmitterWriter::tab_width`.
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// The column at which the character at `col`, counted in characters, of the
+/// source line `line` is displayed once its tabs are expanded to `tab_width`
+/// spaces each, and with wide characters taking up two columns.
+pub fn display_column(line: &str, col: usize, tab_width: usize) -> usize {
+    line.chars().take(col).fold(col, |column, c| match c {
+        '\t' => column - 1 + tab_width,
+        c => column - 1 + char_width(c),
+    })
+}
+
+/// The number of columns a terminal shows `c` in: two for the East Asian
+/// wide and fullwidth characters, such as CJK ideographs, and for emoji, and
+/// one otherwise.
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F680..=0x1F6FF
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x2FFFD
+        | 0x30000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+/// `line` with its tabs expanded to `tab_width` spaces each, since a terminal
+/// would align them to its own tab stops rather than to the labels below.
+fn expand_tabs(line: &str, tab_width: usize) -> Cow<str> {
+    if line.contains('\t') {
+        Cow::Owned(line.replace('\t', &" ".repeat(tab_width)))
+    } else {
+        Cow::Borrowed(line)
+    }
+}
+
+/// Wraps `cm` in a `CachingCodemapView`, since rendering a diagnostic looks
+/// up the same few lines over and over.
+fn caching(cm: Rc<dyn CodeMapper>) -> Rc<dyn CodeMapper> {
+    Rc::new(CachingCodemapView::new(cm))
 }
 
 struct FileWithAnnotatedLines {
@@ -110,12 +364,16 @@ impl EmitterWriter {
             let dst = Destination::from_stderr();
             EmitterWriter {
                 dst: dst,
-                cm: code_map,
+                cm: code_map.map(caching),
+                tab_width: DEFAULT_TAB_WIDTH,
+                macro_backtrace: MacroBacktraceFilter::default(),
             }
         } else {
             EmitterWriter {
                 dst: Raw(Box::new(io::stderr())),
-                cm: code_map,
+                cm: code_map.map(caching),
+                tab_width: DEFAULT_TAB_WIDTH,
+                macro_backtrace: MacroBacktraceFilter::default(),
             }
         }
     }
@@ -123,558 +381,65 @@ impl EmitterWriter {
     pub fn new(dst: Box<dyn Write + Send>, code_map: Option<Rc<dyn CodeMapper>>) -> EmitterWriter {
         EmitterWriter {
             dst: Raw(dst),
-            cm: code_map,
+            cm: code_map.map(caching),
+            tab_width: DEFAULT_TAB_WIDTH,
+            macro_backtrace: MacroBacktraceFilter::default(),
         }
     }
 
-    fn preprocess_annotations(&self, msp: &MultiSpan) -> Vec<FileWithAnnotatedLines> {
-        fn add_annotation_to_file(
-            file_vec: &mut Vec<FileWithAnnotatedLines>,
-            file: Rc<FileMap>,
-            line_index: usize,
-            ann: Annotation,
-        ) {
-            for slot in file_vec.iter_mut() {
-                // Look through each of our files for the one we're adding to
-                if slot.file.name == file.name {
-                    // See if we already have a line for it
-                    for line_slot in &mut slot.lines {
-                        if line_slot.line_index == line_index {
-                            line_slot.annotations.push(ann);
-                            return;
-                        }
-                    }
-                    // We don't have a line yet, create one
-                    slot.lines.push(Line {
-                        line_index: line_index,
-                        annotations: vec![ann],
-                    });
-                    slot.lines.sort();
-                    return;
-                }
-            }
-            // This is the first time we're seeing the file
-            file_vec.push(FileWithAnnotatedLines {
-                file: file,
-                lines: vec![Line {
-                    line_index: line_index,
-                    annotations: vec![ann],
-                }],
-                multiline_depth: 0,
-            });
-        }
-
-        let mut output = vec![];
-        let mut multiline_annotations = vec![];
-
-        if let Some(ref cm) = self.cm {
-            for span_label in msp.span_labels() {
-                if span_label.span == DUMMY_SP {
-                    continue;
-                }
-                let lo = cm.lookup_char_pos(span_label.span.lo);
-                let mut hi = cm.lookup_char_pos(span_label.span.hi);
-
-                // Watch out for "empty spans". If we get a span like 6..6, we
-                // want to just display a `^` at 6, so convert that to
-                // 6..7. This is degenerate input, but it's best to degrade
-                // gracefully -- and the parser likes to supply a span like
-                // that for EOF, in particular.
-                if lo.col == hi.col && lo.line == hi.line {
-                    hi.col = CharPos(lo.col.0 + 1);
-                }
-
-                let ann_type = if lo.line != hi.line {
-                    let ml = MultilineAnnotation {
-                        depth: 1,
-                        line_start: lo.line,
-                        line_end: hi.line,
-                        start_col: lo.col.0,
-                        end_col: hi.col.0,
-                        is_primary: span_label.is_primary,
-                        label: span_label.label.clone(),
-                    };
-                    multiline_annotations.push((lo.file.clone(), ml.clone()));
-                    AnnotationType::Multiline(ml)
-                } else {
-                    AnnotationType::Singleline
-                };
-                let ann = Annotation {
-                    start_col: lo.col.0,
-                    end_col: hi.col.0,
-                    is_primary: span_label.is_primary,
-                    label: span_label.label.clone(),
-                    annotation_type: ann_type,
-                };
-
-                if !ann.is_multiline() {
-                    add_annotation_to_file(&mut output, lo.file, lo.line, ann);
-                }
-            }
-        }
-
-        // Find overlapping multiline annotations, put them at different depths
-        multiline_annotations
-            .sort_by(|a, b| (a.1.line_start, a.1.line_end).cmp(&(b.1.line_start, b.1.line_end)));
-        for item in multiline_annotations.clone() {
-            let ann = item.1;
-            for item in multiline_annotations.iter_mut() {
-                let ref mut a = item.1;
-                // Move all other multiline annotations overlapping with this one
-                // one level to the right.
-                if &ann != a
-                    && num_overlap(ann.line_start, ann.line_end, a.line_start, a.line_end, true)
-                {
-                    a.increase_depth();
-                } else {
-                    break;
-                }
-            }
-        }
-
-        let mut max_depth = 0; // max overlapping multiline spans
-        for (file, ann) in multiline_annotations {
-            if ann.depth > max_depth {
-                max_depth = ann.depth;
-            }
-            add_annotation_to_file(&mut output, file.clone(), ann.line_start, ann.as_start());
-            let middle = min(ann.line_start + 4, ann.line_end);
-            for line in ann.line_start + 1..middle {
-                add_annotation_to_file(&mut output, file.clone(), line, ann.as_line());
-            }
-            if middle < ann.line_end - 1 {
-                for line in ann.line_end - 1..ann.line_end {
-                    add_annotation_to_file(&mut output, file.clone(), line, ann.as_line());
-                }
-            }
-            add_annotation_to_file(&mut output, file, ann.line_end, ann.as_end());
-        }
-        for file_vec in output.iter_mut() {
-            file_vec.multiline_depth = max_depth;
-        }
-        output
-    }
-
-    fn render_source_line(
-        &self,
-        buffer: &mut StyledBuffer,
-        file: Rc<FileMap>,
-        line: &Line,
-        width_offset: usize,
-        code_offset: usize,
-    ) -> Vec<(usize, Style)> {
-        let source_string = match file.get_line(line.line_index - 1) {
-            Some(s) => s,
-            None => return Vec::new(),
+    /// Creates an emitter writing to `dst`, styled with ANSI escape codes
+    /// for `ColorConfig::Always` and as plain text otherwise. There is no
+    /// way to tell whether an arbitrary writer is a terminal, so
+    /// `ColorConfig::Auto` is plain text too.
+    pub fn with_color(
+        dst: Box<dyn Write + Send>,
+        color_config: ColorConfig,
+        code_map: Option<Rc<dyn CodeMapper>>,
+    ) -> EmitterWriter {
+        let dst = match color_config {
+            ColorConfig::Always => Ansi(dst),
+            ColorConfig::Auto | ColorConfig::Never => Raw(dst),
         };
-
-        let line_offset = buffer.num_lines();
-
-        // First create the source line we will highlight.
-        buffer.puts(line_offset, code_offset, &source_string, Style::Quotation);
-        buffer.puts(
-            line_offset,
-            0,
-            &(line.line_index.to_string()),
-            Style::LineNumber,
-        );
-
-        draw_col_separator(buffer, line_offset, width_offset - 2);
-
-        // Special case when there's only one annotation involved, it is the start of a multiline
-        // span and there's no text at the beginning of the code line. Instead of doing the whole
-        // graph:
-        //
-        // 2 |   fn foo() {
-        //   |  _^
-        // 3 | |
-        // 4 | | }
-        //   | |_^ test
-        //
-        // we simplify the output to:
-        //
-        // 2 | / fn foo() {
-        // 3 | |
-        // 4 | | }
-        //   | |_^ test
-        if line.annotations.len() == 1 {
-            if let Some(ref ann) = line.annotations.get(0) {
-                if let AnnotationType::MultilineStart(depth) = ann.annotation_type {
-                    if source_string[0..ann.start_col].trim() == "" {
-                        let style = if ann.is_primary {
-                            Style::UnderlinePrimary
-                        } else {
-                            Style::UnderlineSecondary
-                        };
-                        buffer.putc(line_offset, width_offset + depth - 1, '/', style);
-                        return vec![(depth, style)];
-                    }
-                }
-            }
-        }
-
-        // We want to display like this:
-        //
-        //      vec.push(vec.pop().unwrap());
-        //      ---      ^^^               - previous borrow ends here
-        //      |        |
-        //      |        error occurs here
-        //      previous borrow of `vec` occurs here
-        //
-        // But there are some weird edge cases to be aware of:
-        //
-        //      vec.push(vec.pop().unwrap());
-        //      --------                    - previous borrow ends here
-        //      ||
-        //      |this makes no sense
-        //      previous borrow of `vec` occurs here
-        //
-        // For this reason, we group the lines into "highlight lines"
-        // and "annotations lines", where the highlight lines have the `^`.
-
-        // Sort the annotations by (start, end col)
-        let mut annotations = line.annotations.clone();
-        annotations.sort();
-        annotations.reverse();
-
-        // First, figure out where each label will be positioned.
-        //
-        // In the case where you have the following annotations:
-        //
-        //      vec.push(vec.pop().unwrap());
-        //      --------                    - previous borrow ends here [C]
-        //      ||
-        //      |this makes no sense [B]
-        //      previous borrow of `vec` occurs here [A]
-        //
-        // `annotations_position` will hold [(2, A), (1, B), (0, C)].
-        //
-        // We try, when possible, to stick the rightmost annotation at the end
-        // of the highlight line:
-        //
-        //      vec.push(vec.pop().unwrap());
-        //      ---      ---               - previous borrow ends here
-        //
-        // But sometimes that's not possible because one of the other
-        // annotations overlaps it. For example, from the test
-        // `span_overlap_label`, we have the following annotations
-        // (written on distinct lines for clarity):
-        //
-        //      fn foo(x: u32) {
-        //      --------------
-        //             -
-        //
-        // In this case, we can't stick the rightmost-most label on
-        // the highlight line, or we would get:
-        //
-        //      fn foo(x: u32) {
-        //      -------- x_span
-        //      |
-        //      fn_span
-        //
-        // which is totally weird. Instead we want:
-        //
-        //      fn foo(x: u32) {
-        //      --------------
-        //      |      |
-        //      |      x_span
-        //      fn_span
-        //
-        // which is...less weird, at least. In fact, in general, if
-        // the rightmost span overlaps with any other span, we should
-        // use the "hang below" version, so we can at least make it
-        // clear where the span *starts*. There's an exception for this
-        // logic, when the labels do not have a message:
-        //
-        //      fn foo(x: u32) {
-        //      --------------
-        //             |
-        //             x_span
-        //
-        // instead of:
-        //
-        //      fn foo(x: u32) {
-        //      --------------
-        //      |      |
-        //      |      x_span
-        //      <EMPTY LINE>
-        //
-        let mut annotations_position = vec![];
-        let mut line_len = 0;
-        let mut p = 0;
-        for (i, annotation) in annotations.iter().enumerate() {
-            for (j, next) in annotations.iter().enumerate() {
-                if overlaps(next, annotation, 0)  // This label overlaps with another one and both
-                    && annotation.has_label()     // take space (they have text and are not
-                    && j > i                      // multiline lines).
-                    && p == 0
-                // We're currently on the first line, move the label one line down
-                {
-                    // This annotation needs a new line in the output.
-                    p += 1;
-                    break;
-                }
-            }
-            annotations_position.push((p, annotation));
-            for (j, next) in annotations.iter().enumerate() {
-                if j > i {
-                    let l = if let Some(ref label) = next.label {
-                        label.len() + 2
-                    } else {
-                        0
-                    };
-                    if (overlaps(next, annotation, l) // Do not allow two labels to be in the same
-                                                     // line if they overlap including padding, to
-                                                     // avoid situations like:
-                                                     //
-                                                     //      fn foo(x: u32) {
-                                                     //      -------^------
-                                                     //      |      |
-                                                     //      fn_spanx_span
-                                                     //
-                        && annotation.has_label()    // Both labels must have some text, otherwise
-                        && next.has_label())         // they are not overlapping.
-                                                     // Do not add a new line if this annotation
-                                                     // or the next are vertical line placeholders.
-                        || (annotation.takes_space() // If either this or the next annotation is
-                            && next.has_label())     // multiline start/end, move it to a new line
-                        || (annotation.has_label()   // so as not to overlap the orizontal lines.
-                            && next.takes_space())
-                        || (annotation.takes_space()
-                            && next.takes_space())
-                    {
-                        // This annotation needs a new line in the output.
-                        p += 1;
-                        break;
-                    }
-                }
-            }
-            if line_len < p {
-                line_len = p;
-            }
-        }
-
-        if line_len != 0 {
-            line_len += 1;
-        }
-
-        // If there are no annotations or the only annotations on this line are
-        // MultilineLine, then there's only code being shown, stop processing.
-        if line.annotations.is_empty()
-            || line
-                .annotations
-                .iter()
-                .filter(|a| !a.is_line())
-                .collect::<Vec<_>>()
-                .len()
-                == 0
-        {
-            return vec![];
-        }
-
-        // Write the colunmn separator.
-        //
-        // After this we will have:
-        //
-        // 2 |   fn foo() {
-        //   |
-        //   |
-        //   |
-        // 3 |
-        // 4 |   }
-        //   |
-        for pos in 0..line_len + 1 {
-            draw_col_separator(buffer, line_offset + pos + 1, width_offset - 2);
-            buffer.putc(
-                line_offset + pos + 1,
-                width_offset - 2,
-                '|',
-                Style::LineNumber,
-            );
-        }
-
-        // Write the horizontal lines for multiline annotations
-        // (only the first and last lines need this).
-        //
-        // After this we will have:
-        //
-        // 2 |   fn foo() {
-        //   |  __________
-        //   |
-        //   |
-        // 3 |
-        // 4 |   }
-        //   |  _
-        for &(pos, annotation) in &annotations_position {
-            let style = if annotation.is_primary {
-                Style::UnderlinePrimary
-            } else {
-                Style::UnderlineSecondary
-            };
-            let pos = pos + 1;
-            match annotation.annotation_type {
-                AnnotationType::MultilineStart(depth) | AnnotationType::MultilineEnd(depth) => {
-                    draw_range(
-                        buffer,
-                        '_',
-                        line_offset + pos,
-                        width_offset + depth,
-                        code_offset + annotation.start_col,
-                        style,
-                    );
-                }
-                _ => (),
-            }
-        }
-
-        // Write the vertical lines for labels that are on a different line as the underline.
-        //
-        // After this we will have:
-        //
-        // 2 |   fn foo() {
-        //   |  __________
-        //   | |    |
-        //   | |
-        // 3 |
-        // 4 | | }
-        //   | |_
-        for &(pos, annotation) in &annotations_position {
-            let style = if annotation.is_primary {
-                Style::UnderlinePrimary
-            } else {
-                Style::UnderlineSecondary
-            };
-            let pos = pos + 1;
-
-            if pos > 1 && (annotation.has_label() || annotation.takes_space()) {
-                for p in line_offset + 1..line_offset + pos + 1 {
-                    buffer.putc(p, code_offset + annotation.start_col, '|', style);
-                }
-            }
-            match annotation.annotation_type {
-                AnnotationType::MultilineStart(depth) => {
-                    for p in line_offset + pos + 1..line_offset + line_len + 2 {
-                        buffer.putc(p, width_offset + depth - 1, '|', style);
-                    }
-                }
-                AnnotationType::MultilineEnd(depth) => {
-                    for p in line_offset..line_offset + pos + 1 {
-                        buffer.putc(p, width_offset + depth - 1, '|', style);
-                    }
-                }
-                _ => (),
-            }
-        }
-
-        // Write the labels on the annotations that actually have a label.
-        //
-        // After this we will have:
-        //
-        // 2 |   fn foo() {
-        //   |  __________
-        //   |      |
-        //   |      something about `foo`
-        // 3 |
-        // 4 |   }
-        //   |  _  test
-        for &(pos, annotation) in &annotations_position {
-            let style = if annotation.is_primary {
-                Style::LabelPrimary
-            } else {
-                Style::LabelSecondary
-            };
-            let (pos, col) = if pos == 0 {
-                (pos + 1, annotation.end_col + 1)
-            } else {
-                (pos + 2, annotation.start_col)
-            };
-            if let Some(ref label) = annotation.label {
-                buffer.puts(line_offset + pos, code_offset + col, &label, style);
-            }
+        EmitterWriter {
+            dst,
+            cm: code_map.map(caching),
+            tab_width: DEFAULT_TAB_WIDTH,
+            macro_backtrace: MacroBacktraceFilter::default(),
         }
+    }
 
-        // Sort from biggest span to smallest span so that smaller spans are
-        // represented in the output:
-        //
-        // x | fn foo()
-        //   | ^^^---^^
-        //   | |  |
-        //   | |  something about `foo`
-        //   | something about `fn foo()`
-        annotations_position.sort_by(|a, b| {
-            // Decreasing order
-            a.1.len().cmp(&b.1.len()).reverse()
-        });
-
-        // Write the underlines.
-        //
-        // After this we will have:
-        //
-        // 2 |   fn foo() {
-        //   |  ____-_____^
-        //   |      |
-        //   |      something about `foo`
-        // 3 |
-        // 4 |   }
-        //   |  _^  test
-        for &(_, annotation) in &annotations_position {
-            let (underline, style) = if annotation.is_primary {
-                ('^', Style::UnderlinePrimary)
-            } else {
-                ('-', Style::UnderlineSecondary)
-            };
-            for p in annotation.start_col..annotation.end_col {
-                buffer.putc(line_offset + 1, code_offset + p, underline, style);
-            }
-        }
-        annotations_position
-            .iter()
-            .filter_map(|&(_, annotation)| match annotation.annotation_type {
-                AnnotationType::MultilineStart(p) | AnnotationType::MultilineEnd(p) => {
-                    let style = if annotation.is_primary {
-                        Style::LabelPrimary
-                    } else {
-                        Style::LabelSecondary
-                    };
-                    Some((p, style))
-                }
-                _ => None,
-            })
-            .collect::<Vec<_>>()
+    /// Sets the number of columns each tab in the source is shown as.
+    pub fn tab_width(mut self, tab_width: usize) -> EmitterWriter {
+        self.tab_width = tab_width;
+        self
     }
 
-    fn get_multispan_max_line_num(&mut self, msp: &MultiSpan) -> usize {
-        let mut max = 0;
-        if let Some(ref cm) = self.cm {
-            for primary_span in msp.primary_spans() {
-                if primary_span != &DUMMY_SP {
-                    let hi = cm.lookup_char_pos(primary_span.hi);
-                    if hi.line > max {
-                        max = hi.line;
-                    }
-                }
-            }
-            for span_label in msp.span_labels() {
-                if span_label.span != DUMMY_SP {
-                    let hi = cm.lookup_char_pos(span_label.span.hi);
-                    if hi.line > max {
-                        max = hi.line;
-                    }
-                }
-            }
-        }
-        max
+    /// Sets which of the macro expansions behind a diagnostic are shown,
+    /// none by default.
+    pub fn macro_backtrace(mut self, filter: MacroBacktraceFilter) -> EmitterWriter {
+        self.macro_backtrace = filter;
+        self
     }
 
     fn get_max_line_num(&mut self, span: &MultiSpan, children: &Vec<SubDiagnostic>) -> usize {
+        let cm = match self.cm {
+            Some(ref cm) => cm,
+            None => return 0,
+        };
         let mut max = 0;
 
-        let primary = self.get_multispan_max_line_num(span);
+        let primary = multispan_max_line_num(&**cm, span);
         max = if primary > max { primary } else { max };
 
         for sub in children {
-            let sub_result = self.get_multispan_max_line_num(&sub.span);
-            max = if sub_result > max { primary } else { max };
+            let sub_result = multispan_max_line_num(&**cm, &sub.span);
+            max = if sub_result > max { sub_result } else { max };
+            // deletions are shown against the original source, with line numbers
+            if let Some(Suggestion(ref cs)) = sub.render_span {
+                for span in cs.substitution_spans() {
+                    max = max.max(cm.lookup_char_pos(span.hi).line);
+                }
+            }
         }
         max
     }
@@ -694,7 +459,7 @@ impl EmitterWriter {
                 if *sp == DUMMY_SP {
                     continue;
                 }
-                if cm.span_to_filename(sp.clone()).contains("macros>") {
+                if is_macro_expansion(&cm.span_to_filename(sp.clone())) {
                     let v = sp.macro_backtrace();
                     if let Some(use_site) = v.last() {
                         before_after.push((sp.clone(), use_site.call_site.clone()));
@@ -708,7 +473,7 @@ impl EmitterWriter {
                             continue;
                         }
                         // Check to make sure we're not in any <*macros>
-                        if !cm.span_to_filename(def_site).contains("macros>")
+                        if !is_macro_expansion(&cm.span_to_filename(def_site))
                             && !trace.macro_decl_name.starts_with("#[")
                         {
                             new_labels
@@ -725,10 +490,7 @@ impl EmitterWriter {
                 if sp_label.span == DUMMY_SP {
                     continue;
                 }
-                if cm
-                    .span_to_filename(sp_label.span.clone())
-                    .contains("macros>")
-                {
+                if is_macro_expansion(&cm.span_to_filename(sp_label.span.clone())) {
                     let v = sp_label.span.macro_backtrace();
                     if let Some(use_site) = v.last() {
                         before_after.push((sp_label.span.clone(), use_site.call_site.clone()));
@@ -885,170 +647,101 @@ impl EmitterWriter {
 
         // Preprocess all the annotations so that they are grouped by file and by line number
         // This helps us quickly iterate over the whole message (including secondary file spans)
-        let mut annotated_files = self.preprocess_annotations(msp);
-
-        // Make sure our primary file comes first
-        let primary_lo = if let (Some(ref cm), Some(ref primary_span)) =
-            (self.cm.as_ref(), msp.primary_span().as_ref())
-        {
-            if primary_span != &&DUMMY_SP {
-                cm.lookup_char_pos(primary_span.lo)
-            } else {
+        let mut annotated_files = match self.cm {
+            Some(ref cm) => preprocess_annotations(&**cm, msp),
+            None => vec![],
+        };
+
+        // If we don't have span information, emit and exit
+        let cm = match (self.cm.as_ref(), msp.primary_span()) {
+            (Some(cm), Some(primary_span)) if primary_span != DUMMY_SP => cm.clone(),
+            _ => {
                 emit_to_destination(&buffer.render(), level, &mut self.dst)?;
                 return Ok(());
             }
-        } else {
-            // If we don't have span information, emit and exit
-            emit_to_destination(&buffer.render(), level, &mut self.dst)?;
-            return Ok(());
         };
-        if let Ok(pos) =
-            annotated_files.binary_search_by(|x| x.file.name.cmp(&primary_lo.file.name))
-        {
-            annotated_files.swap(0, pos);
+
+        // Find the first primary span of each file, so that every file holding a primary span
+        // gets a `-->` header pointing at it, and order the files by their first primary span.
+        // Files that only hold secondary labels keep their relative order and come last.
+        let mut primary_locs = vec![];
+        for primary_span in msp.primary_spans() {
+            if *primary_span == DUMMY_SP {
+                continue;
+            }
+            let loc = cm.lookup_char_pos(primary_span.lo);
+            if !primary_locs
+                .iter()
+                .any(|l: &Loc| l.file.name == loc.file.name)
+            {
+                primary_locs.push(loc);
+            }
         }
+        annotated_files.sort_by_key(|annotated_file| {
+            primary_locs
+                .iter()
+                .position(|loc| loc.file.name == annotated_file.file.name)
+                .unwrap_or(primary_locs.len())
+        });
 
         // Print out the annotate source lines that correspond with the error
+        let mut is_first_file = true;
         for annotated_file in annotated_files {
             // we can't annotate anything if the source is unavailable.
-            if annotated_file.file.src.is_none() {
+            if !cm.ensure_filemap_source_present(annotated_file.file.clone()) {
                 continue;
             }
 
-            // print out the span location and spacer before we print the annotated source
-            // to do this, we need to know if this span will be primary
-            let is_primary = primary_lo.file.name == annotated_file.file.name;
-            if is_primary {
-                // remember where we are in the output buffer for easy reference
-                let buffer_msg_line_offset = buffer.num_lines();
-
-                buffer.prepend(buffer_msg_line_offset, "--> ", Style::LineNumber);
-                let loc = primary_lo.clone();
-                buffer.append(
-                    buffer_msg_line_offset,
-                    &format!("{}:{}:{}", loc.file.name, loc.line, loc.col.0 + 1),
-                    Style::LineAndColumn,
-                );
-                for _ in 0..max_line_num_len {
-                    buffer.prepend(buffer_msg_line_offset, " ", Style::NoStyle);
-                }
-            } else {
-                // remember where we are in the output buffer for easy reference
-                let buffer_msg_line_offset = buffer.num_lines();
+            // remember where we are in the output buffer for easy reference
+            let mut buffer_msg_line_offset = buffer.num_lines();
 
-                // Add spacing line
+            // Add spacing line between the snippets of different files
+            if !is_first_file {
                 draw_col_separator(&mut buffer, buffer_msg_line_offset, max_line_num_len + 1);
-
-                // Then, the secondary file indicator
-                buffer.prepend(buffer_msg_line_offset + 1, "::: ", Style::LineNumber);
-                buffer.append(
-                    buffer_msg_line_offset + 1,
-                    &annotated_file.file.name,
-                    Style::LineAndColumn,
-                );
-                for _ in 0..max_line_num_len {
-                    buffer.prepend(buffer_msg_line_offset + 1, " ", Style::NoStyle);
-                }
+                buffer_msg_line_offset += 1;
             }
+            is_first_file = false;
 
-            // Put in the spacer between the location and annotated source
-            let buffer_msg_line_offset = buffer.num_lines();
-            draw_col_separator_no_space(&mut buffer, buffer_msg_line_offset, max_line_num_len + 1);
-
-            // Contains the vertical lines' positions for active multiline annotations
-            let mut multilines = HashMap::new();
-
-            // Next, output the annotate source for this file
-            for line_idx in 0..annotated_file.lines.len() {
-                let previous_buffer_line = buffer.num_lines();
-
-                let width_offset = 3 + max_line_num_len;
-                let code_offset = if annotated_file.multiline_depth == 0 {
-                    width_offset
-                } else {
-                    width_offset + annotated_file.multiline_depth + 1
-                };
-
-                let depths = self.render_source_line(
-                    &mut buffer,
-                    annotated_file.file.clone(),
-                    &annotated_file.lines[line_idx],
-                    width_offset,
-                    code_offset,
-                );
-
-                let mut to_add = HashMap::new();
-
-                for (depth, style) in depths {
-                    if multilines.get(&depth).is_some() {
-                        multilines.remove(&depth);
-                    } else {
-                        to_add.insert(depth, style);
-                    }
-                }
-
-                // Set the multiline annotation vertical lines to the left of
-                // the code in this line.
-                for (depth, style) in &multilines {
-                    for line in previous_buffer_line..buffer.num_lines() {
-                        draw_multiline_line(&mut buffer, line, width_offset, *depth, *style);
-                    }
-                }
-                // check to see if we need to print out or elide lines that come between
-                // this annotated line and the next one.
-                if line_idx < (annotated_file.lines.len() - 1) {
-                    let line_idx_delta = annotated_file.lines[line_idx + 1].line_index
-                        - annotated_file.lines[line_idx].line_index;
-                    if line_idx_delta > 2 {
-                        let last_buffer_line_num = buffer.num_lines();
-                        buffer.puts(last_buffer_line_num, 0, "...", Style::LineNumber);
-
-                        // Set the multiline annotation vertical lines on `...` bridging line.
-                        for (depth, style) in &multilines {
-                            draw_multiline_line(
-                                &mut buffer,
-                                last_buffer_line_num,
-                                width_offset,
-                                *depth,
-                                *style,
-                            );
-                        }
-                    } else if line_idx_delta == 2 {
-                        let unannotated_line = annotated_file
-                            .file
-                            .get_line(annotated_file.lines[line_idx].line_index)
-                            .unwrap_or("");
-
-                        let last_buffer_line_num = buffer.num_lines();
-
-                        buffer.puts(
-                            last_buffer_line_num,
-                            0,
-                            &(annotated_file.lines[line_idx + 1].line_index - 1).to_string(),
-                            Style::LineNumber,
-                        );
-                        draw_col_separator(&mut buffer, last_buffer_line_num, 1 + max_line_num_len);
-                        buffer.puts(
-                            last_buffer_line_num,
-                            code_offset,
-                            &unannotated_line,
-                            Style::Quotation,
-                        );
-
-                        for (depth, style) in &multilines {
-                            draw_multiline_line(
-                                &mut buffer,
-                                last_buffer_line_num,
-                                width_offset,
-                                *depth,
-                                *style,
-                            );
-                        }
-                    }
+            // print out the span location and spacer before we print the annotated source
+            // to do this, we need to know if this file contains a primary span
+            let primary_lo = primary_locs
+                .iter()
+                .find(|loc| loc.file.name == annotated_file.file.name);
+            let (indicator, line, col) = match primary_lo {
+                Some(loc) => ("--> ", loc.line, loc.col.0),
+                // Point the secondary file indicator at the first annotation in the file
+                None => {
+                    let first_line = &annotated_file.lines[0];
+                    let col = first_line
+                        .annotations
+                        .iter()
+                        .map(|ann| ann.start_col)
+                        .min()
+                        .unwrap_or(0);
+                    ("::: ", first_line.line_index, col)
                 }
-
-                multilines.extend(&to_add);
+            };
+            buffer.prepend(buffer_msg_line_offset, indicator, Style::LineNumber);
+            buffer.append(
+                buffer_msg_line_offset,
+                &format!("{}:{}:{}", annotated_file.file.name, line, col + 1),
+                Style::LineAndColumn,
+            );
+            for _ in 0..max_line_num_len {
+                buffer.prepend(buffer_msg_line_offset, " ", Style::NoStyle);
             }
+
+            // Put in the spacer between the location and annotated source
+            let buffer_msg_line_offset = buffer.num_lines();
+            draw_col_separator_no_space(&mut buffer, buffer_msg_line_offset, max_line_num_len + 1);
+
+            render_annotated_lines(
+                &mut buffer,
+                &annotated_file,
+                max_line_num_len,
+                true,
+                self.tab_width,
+            );
         }
 
         // final step: take our styled buffer, render it, then output it
@@ -1068,9 +761,15 @@ impl EmitterWriter {
         if let Some(ref cm) = self.cm {
             let mut buffer = StyledBuffer::new();
 
-            let lines = cm.span_to_lines(primary_span).unwrap();
-
-            assert!(!lines.lines.is_empty());
+            // A span that can't be turned into lines, e.g. into a file that
+            // has since been replaced, gets the message without the snippet.
+            let has_lines = match cm.span_to_lines_limited(primary_span, MAX_HIGHLIGHT_LINES) {
+                Ok(lines) => {
+                    assert!(!lines.lines.is_empty());
+                    true
+                }
+                Err(_) => false,
+            };
 
             buffer.append(0, &level.to_string(), Style::Level(level.clone()));
             buffer.append(0, ": ", Style::HeaderMsg);
@@ -1082,9 +781,37 @@ impl EmitterWriter {
                 Some(Style::HeaderMsg),
             );
 
-            let suggestions = suggestion.splice_lines(cm.borrow());
+            let suggestions = if has_lines {
+                suggestion.splice_lines(cm.borrow())
+            } else {
+                Vec::new()
+            };
             let mut row_num = 1;
-            for complete in suggestions.iter().take(MAX_SUGGESTIONS) {
+            for (i, complete) in suggestions.iter().enumerate().take(MAX_SUGGESTIONS) {
+                // an empty splice says nothing about what goes away, so show
+                // the original source with the removed code marked instead
+                if suggestion.is_deletion(i) {
+                    let mut msp = MultiSpan::default();
+                    for part in &suggestion.substitution_parts {
+                        msp.push_span_label(part.span, "remove this".to_owned());
+                    }
+                    draw_col_separator_no_space(&mut buffer, row_num, max_line_num_len + 1);
+                    for annotated_file in preprocess_annotations(cm.borrow(), &msp) {
+                        if !cm.ensure_filemap_source_present(annotated_file.file.clone()) {
+                            continue;
+                        }
+                        render_annotated_lines(
+                            &mut buffer,
+                            &annotated_file,
+                            max_line_num_len,
+                            true,
+                            self.tab_width,
+                        );
+                    }
+                    row_num = buffer.num_lines();
+                    continue;
+                }
+
                 // print the suggestion without any line numbers, but leave
                 // space for them. This helps with lining up with previous
                 // snippets from the actual error being reported.
@@ -1181,6 +908,736 @@ impl EmitterWriter {
     }
 }
 
+/// Whether `render_snippet` draws the line numbers and `|` separator that a
+/// diagnostic shows to the left of the source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LabelStyle {
+    Gutter,
+    NoGutter,
+}
+
+/// Renders the source lines that `msp` points at, with its labels below them,
+/// laid out as in a diagnostic but as plain text without colors, message or
+/// `-->` header, for embedding in other UIs. The snippets of different files
+/// are separated by an empty line. Trailing whitespace is trimmed from every
+/// line, and the result is empty if none of the source is available.
+pub fn render_snippet(cm: &dyn CodeMapper, msp: &MultiSpan, label_style: LabelStyle) -> String {
+    let gutter = label_style == LabelStyle::Gutter;
+    let max_line_num_len = multispan_max_line_num(cm, msp).to_string().len();
+
+    let mut buffer = StyledBuffer::new();
+    let mut is_first_file = true;
+    for annotated_file in preprocess_annotations(cm, msp) {
+        if !cm.ensure_filemap_source_present(annotated_file.file.clone()) {
+            continue;
+        }
+        if !is_first_file {
+            let line = buffer.num_lines();
+            buffer.ensure_lines(line);
+        }
+        is_first_file = false;
+        render_annotated_lines(
+            &mut buffer,
+            &annotated_file,
+            max_line_num_len,
+            gutter,
+            DEFAULT_TAB_WIDTH,
+        );
+    }
+
+    let mut rendered = String::new();
+    for line in buffer.render() {
+        let text: String = line.iter().map(|part| &*part.text).collect();
+        rendered.push_str(text.trim_end());
+        rendered.push('\n');
+    }
+    rendered
+}
+
+fn preprocess_annotations(cm: &dyn CodeMapper, msp: &MultiSpan) -> Vec<FileWithAnnotatedLines> {
+    fn add_annotation_to_file(
+        file_vec: &mut Vec<FileWithAnnotatedLines>,
+        file: Rc<FileMap>,
+        line_index: usize,
+        ann: Annotation,
+    ) {
+        for slot in file_vec.iter_mut() {
+            // Look through each of our files for the one we're adding to
+            if slot.file.name == file.name {
+                // See if we already have a line for it
+                for line_slot in &mut slot.lines {
+                    if line_slot.line_index == line_index {
+                        line_slot.annotations.push(ann);
+                        return;
+                    }
+                }
+                // We don't have a line yet, create one
+                slot.lines.push(Line {
+                    line_index: line_index,
+                    annotations: vec![ann],
+                });
+                slot.lines.sort();
+                return;
+            }
+        }
+        // This is the first time we're seeing the file
+        file_vec.push(FileWithAnnotatedLines {
+            file: file,
+            lines: vec![Line {
+                line_index: line_index,
+                annotations: vec![ann],
+            }],
+            multiline_depth: 0,
+        });
+    }
+
+    let mut output = vec![];
+    let mut multiline_annotations = vec![];
+
+    for span_label in msp.span_labels() {
+        if span_label.span == DUMMY_SP {
+            continue;
+        }
+        let lo = cm.lookup_char_pos(span_label.span.lo);
+        let mut hi = cm.lookup_char_pos(span_label.span.hi);
+
+        // Watch out for "empty spans". If we get a span like 6..6, we
+        // want to just display a `^` at 6, so convert that to
+        // 6..7. This is degenerate input, but it's best to degrade
+        // gracefully -- and the parser likes to supply a span like
+        // that for EOF, in particular.
+        if lo.col == hi.col && lo.line == hi.line {
+            hi.col = CharPos(lo.col.0 + 1);
+        }
+
+        let ann_type = if lo.line != hi.line {
+            let ml = MultilineAnnotation {
+                depth: 1,
+                line_start: lo.line,
+                line_end: hi.line,
+                start_col: lo.col.0,
+                end_col: hi.col.0,
+                is_primary: span_label.is_primary,
+                label: span_label.label.clone(),
+                label_parts: span_label.label_parts.clone(),
+            };
+            multiline_annotations.push((lo.file.clone(), ml.clone()));
+            AnnotationType::Multiline(ml)
+        } else {
+            AnnotationType::Singleline
+        };
+        let ann = Annotation {
+            start_col: lo.col.0,
+            end_col: hi.col.0,
+            is_primary: span_label.is_primary,
+            label: span_label.label.clone(),
+            label_parts: span_label.label_parts.clone(),
+            annotation_type: ann_type,
+        };
+
+        if !ann.is_multiline() {
+            add_annotation_to_file(&mut output, lo.file, lo.line, ann);
+        }
+    }
+
+    // Find overlapping multiline annotations, put them at different depths
+    multiline_annotations
+        .sort_by(|a, b| (a.1.line_start, a.1.line_end).cmp(&(b.1.line_start, b.1.line_end)));
+    for item in multiline_annotations.clone() {
+        let ann = item.1;
+        for item in multiline_annotations.iter_mut() {
+            let ref mut a = item.1;
+            // Move all other multiline annotations overlapping with this one
+            // one level to the right.
+            if &ann != a
+                && num_overlap(ann.line_start, ann.line_end, a.line_start, a.line_end, true)
+            {
+                a.increase_depth();
+            } else {
+                break;
+            }
+        }
+    }
+
+    let mut max_depth = 0; // max overlapping multiline spans
+    for (file, ann) in multiline_annotations {
+        if ann.depth > max_depth {
+            max_depth = ann.depth;
+        }
+        add_annotation_to_file(&mut output, file.clone(), ann.line_start, ann.as_start());
+        let middle = min(ann.line_start + 4, ann.line_end);
+        for line in ann.line_start + 1..middle {
+            add_annotation_to_file(&mut output, file.clone(), line, ann.as_line());
+        }
+        if middle < ann.line_end - 1 {
+            for line in ann.line_end - 1..ann.line_end {
+                add_annotation_to_file(&mut output, file.clone(), line, ann.as_line());
+            }
+        }
+        add_annotation_to_file(&mut output, file, ann.line_end, ann.as_end());
+    }
+    for file_vec in output.iter_mut() {
+        file_vec.multiline_depth = max_depth;
+    }
+    output
+}
+
+fn render_source_line(
+    buffer: &mut StyledBuffer,
+    file: Rc<FileMap>,
+    line: &Line,
+    width_offset: usize,
+    code_offset: usize,
+    gutter: bool,
+    tab_width: usize,
+) -> Vec<(usize, Style)> {
+    let source_string = match file.get_line(line.line_index - 1) {
+        Some(s) => s,
+        None => return Vec::new(),
+    };
+
+    // The line is shown with its tabs expanded, so its annotations are laid
+    // out by the columns their characters are displayed at.
+    let mut display_line = line.clone();
+    for ann in &mut display_line.annotations {
+        ann.start_col = display_column(&source_string, ann.start_col, tab_width);
+        ann.end_col = display_column(&source_string, ann.end_col, tab_width);
+    }
+    let line = &display_line;
+    let source_string = expand_tabs(&source_string, tab_width);
+
+    let line_offset = buffer.num_lines();
+
+    // First create the source line we will highlight.
+    buffer.ensure_lines(line_offset);
+    buffer.puts(line_offset, code_offset, &source_string, Style::Quotation);
+    if gutter {
+        buffer.puts(
+            line_offset,
+            0,
+            &(line.line_index.to_string()),
+            Style::LineNumber,
+        );
+
+        draw_col_separator(buffer, line_offset, width_offset - 2);
+    }
+
+    // Special case when there's only one annotation involved, it is the start of a multiline
+    // span and there's no text at the beginning of the code line. Instead of doing the whole
+    // graph:
+    //
+    // 2 |   fn foo() {
+    //   |  _^
+    // 3 | |
+    // 4 | | }
+    //   | |_^ test
+    //
+    // we simplify the output to:
+    //
+    // 2 | / fn foo() {
+    // 3 | |
+    // 4 | | }
+    //   | |_^ test
+    if line.annotations.len() == 1 {
+        if let Some(ref ann) = line.annotations.get(0) {
+            if let AnnotationType::MultilineStart(depth) = ann.annotation_type {
+                if source_string
+                    .chars()
+                    .take(ann.start_col)
+                    .all(char::is_whitespace)
+                {
+                    let style = if ann.is_primary {
+                        Style::UnderlinePrimary
+                    } else {
+                        Style::UnderlineSecondary
+                    };
+                    buffer.putc(line_offset, width_offset + depth - 1, '/', style);
+                    return vec![(depth, style)];
+                }
+            }
+        }
+    }
+
+    // We want to display like this:
+    //
+    //      vec.push(vec.pop().unwrap());
+    //      ---      ^^^               - previous borrow ends here
+    //      |        |
+    //      |        error occurs here
+    //      previous borrow of `vec` occurs here
+    //
+    // But there are some weird edge cases to be aware of:
+    //
+    //      vec.push(vec.pop().unwrap());
+    //      --------                    - previous borrow ends here
+    //      ||
+    //      |this makes no sense
+    //      previous borrow of `vec` occurs here
+    //
+    // For this reason, we group the lines into "highlight lines"
+    // and "annotations lines", where the highlight lines have the `^`.
+
+    // Sort the annotations by (start, end col)
+    let mut annotations = line.annotations.clone();
+    annotations.sort();
+    annotations.reverse();
+
+    // First, figure out where each label will be positioned.
+    //
+    // In the case where you have the following annotations:
+    //
+    //      vec.push(vec.pop().unwrap());
+    //      --------                    - previous borrow ends here [C]
+    //      ||
+    //      |this makes no sense [B]
+    //      previous borrow of `vec` occurs here [A]
+    //
+    // `annotations_position` will hold [(2, A), (1, B), (0, C)].
+    //
+    // We try, when possible, to stick the rightmost annotation at the end
+    // of the highlight line:
+    //
+    //      vec.push(vec.pop().unwrap());
+    //      ---      ---               - previous borrow ends here
+    //
+    // But sometimes that's not possible because one of the other
+    // annotations overlaps it. For example, from the test
+    // `span_overlap_label`, we have the following annotations
+    // (written on distinct lines for clarity):
+    //
+    //      fn foo(x: u32) {
+    //      --------------
+    //             -
+    //
+    // In this case, we can't stick the rightmost-most label on
+    // the highlight line, or we would get:
+    //
+    //      fn foo(x: u32) {
+    //      -------- x_span
+    //      |
+    //      fn_span
+    //
+    // which is totally weird. Instead we want:
+    //
+    //      fn foo(x: u32) {
+    //      --------------
+    //      |      |
+    //      |      x_span
+    //      fn_span
+    //
+    // which is...less weird, at least. In fact, in general, if
+    // the rightmost span overlaps with any other span, we should
+    // use the "hang below" version, so we can at least make it
+    // clear where the span *starts*. There's an exception for this
+    // logic, when the labels do not have a message:
+    //
+    //      fn foo(x: u32) {
+    //      --------------
+    //             |
+    //             x_span
+    //
+    // instead of:
+    //
+    //      fn foo(x: u32) {
+    //      --------------
+    //      |      |
+    //      |      x_span
+    //      <EMPTY LINE>
+    //
+    let mut annotations_position = vec![];
+    let mut line_len = 0;
+    let mut p = 0;
+    for (i, annotation) in annotations.iter().enumerate() {
+        for (j, next) in annotations.iter().enumerate() {
+            if overlaps(next, annotation, 0)  // This label overlaps with another one and both
+                && annotation.has_label()     // take space (they have text and are not
+                && j > i                      // multiline lines).
+                && p == 0
+            // We're currently on the first line, move the label one line down
+            {
+                // This annotation needs a new line in the output.
+                p += 1;
+                break;
+            }
+        }
+        annotations_position.push((p, annotation));
+        for (j, next) in annotations.iter().enumerate() {
+            if j > i {
+                let l = if let Some(ref label) = next.label {
+                    label.len() + 2
+                } else {
+                    0
+                };
+                if (overlaps(next, annotation, l) // Do not allow two labels to be in the same
+                                                 // line if they overlap including padding, to
+                                                 // avoid situations like:
+                                                 //
+                                                 //      fn foo(x: u32) {
+                                                 //      -------^------
+                                                 //      |      |
+                                                 //      fn_spanx_span
+                                                 //
+                    && annotation.has_label()    // Both labels must have some text, otherwise
+                    && next.has_label())         // they are not overlapping.
+                                                 // Do not add a new line if this annotation
+                                                 // or the next are vertical line placeholders.
+                    || (annotation.takes_space() // If either this or the next annotation is
+                        && next.has_label())     // multiline start/end, move it to a new line
+                    || (annotation.has_label()   // so as not to overlap the orizontal lines.
+                        && next.takes_space())
+                    || (annotation.takes_space()
+                        && next.takes_space())
+                {
+                    // This annotation needs a new line in the output.
+                    p += 1;
+                    break;
+                }
+            }
+        }
+        if line_len < p {
+            line_len = p;
+        }
+    }
+
+    if line_len != 0 {
+        line_len += 1;
+    }
+
+    // If there are no annotations or the only annotations on this line are
+    // MultilineLine, then there's only code being shown, stop processing.
+    if line.annotations.is_empty()
+        || line
+            .annotations
+            .iter()
+            .filter(|a| !a.is_line())
+            .collect::<Vec<_>>()
+            .len()
+            == 0
+    {
+        return vec![];
+    }
+
+    // Write the colunmn separator.
+    //
+    // After this we will have:
+    //
+    // 2 |   fn foo() {
+    //   |
+    //   |
+    //   |
+    // 3 |
+    // 4 |   }
+    //   |
+    if gutter {
+        for pos in 0..line_len + 1 {
+            draw_col_separator(buffer, line_offset + pos + 1, width_offset - 2);
+            buffer.putc(
+                line_offset + pos + 1,
+                width_offset - 2,
+                '|',
+                Style::LineNumber,
+            );
+        }
+    }
+
+    // Write the horizontal lines for multiline annotations
+    // (only the first and last lines need this).
+    //
+    // After this we will have:
+    //
+    // 2 |   fn foo() {
+    //   |  __________
+    //   |
+    //   |
+    // 3 |
+    // 4 |   }
+    //   |  _
+    for &(pos, annotation) in &annotations_position {
+        let style = if annotation.is_primary {
+            Style::UnderlinePrimary
+        } else {
+            Style::UnderlineSecondary
+        };
+        let pos = pos + 1;
+        match annotation.annotation_type {
+            AnnotationType::MultilineStart(depth) | AnnotationType::MultilineEnd(depth) => {
+                draw_range(
+                    buffer,
+                    '_',
+                    line_offset + pos,
+                    width_offset + depth,
+                    code_offset + annotation.start_col,
+                    style,
+                );
+            }
+            _ => (),
+        }
+    }
+
+    // Write the vertical lines for labels that are on a different line as the underline.
+    //
+    // After this we will have:
+    //
+    // 2 |   fn foo() {
+    //   |  __________
+    //   | |    |
+    //   | |
+    // 3 |
+    // 4 | | }
+    //   | |_
+    for &(pos, annotation) in &annotations_position {
+        let style = if annotation.is_primary {
+            Style::UnderlinePrimary
+        } else {
+            Style::UnderlineSecondary
+        };
+        let pos = pos + 1;
+
+        if pos > 1 && (annotation.has_label() || annotation.takes_space()) {
+            for p in line_offset + 1..line_offset + pos + 1 {
+                buffer.putc(p, code_offset + annotation.start_col, '|', style);
+            }
+        }
+        match annotation.annotation_type {
+            AnnotationType::MultilineStart(depth) => {
+                for p in line_offset + pos + 1..line_offset + line_len + 2 {
+                    buffer.putc(p, width_offset + depth - 1, '|', style);
+                }
+            }
+            AnnotationType::MultilineEnd(depth) => {
+                for p in line_offset..line_offset + pos + 1 {
+                    buffer.putc(p, width_offset + depth - 1, '|', style);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Write the labels on the annotations that actually have a label.
+    //
+    // After this we will have:
+    //
+    // 2 |   fn foo() {
+    //   |  __________
+    //   |      |
+    //   |      something about `foo`
+    // 3 |
+    // 4 |   }
+    //   |  _  test
+    for &(pos, annotation) in &annotations_position {
+        let style = if annotation.is_primary {
+            Style::LabelPrimary
+        } else {
+            Style::LabelSecondary
+        };
+        let (pos, col) = if pos == 0 {
+            (pos + 1, annotation.end_col + 1)
+        } else {
+            (pos + 2, annotation.start_col)
+        };
+        if annotation.label.is_some() {
+            let mut col = code_offset + col;
+            for part in &annotation.label_parts {
+                let (text, style) = match *part {
+                    StringPart::Normal(ref text) => (text, style),
+                    StringPart::Highlighted(ref text) => (text, Style::Highlight),
+                };
+                buffer.puts(line_offset + pos, col, text, style);
+                col += text.chars().count();
+            }
+        }
+    }
+
+    // Sort from biggest span to smallest span so that smaller spans are
+    // represented in the output:
+    //
+    // x | fn foo()
+    //   | ^^^---^^
+    //   | |  |
+    //   | |  something about `foo`
+    //   | something about `fn foo()`
+    annotations_position.sort_by(|a, b| {
+        // Decreasing order
+        a.1.len().cmp(&b.1.len()).reverse()
+    });
+
+    // Write the underlines.
+    //
+    // After this we will have:
+    //
+    // 2 |   fn foo() {
+    //   |  ____-_____^
+    //   |      |
+    //   |      something about `foo`
+    // 3 |
+    // 4 |   }
+    //   |  _^  test
+    for &(_, annotation) in &annotations_position {
+        let (underline, style) = if annotation.is_primary {
+            ('^', Style::UnderlinePrimary)
+        } else {
+            ('-', Style::UnderlineSecondary)
+        };
+        for p in annotation.start_col..annotation.end_col {
+            buffer.putc(line_offset + 1, code_offset + p, underline, style);
+        }
+    }
+    annotations_position
+        .iter()
+        .filter_map(|&(_, annotation)| match annotation.annotation_type {
+            AnnotationType::MultilineStart(p) | AnnotationType::MultilineEnd(p) => {
+                let style = if annotation.is_primary {
+                    Style::LabelPrimary
+                } else {
+                    Style::LabelSecondary
+                };
+                Some((p, style))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+}
+
+/// Renders the annotated lines of `annotated_file`, and the unannotated lines
+/// between them that are shown, below the current end of `buffer`. Without a
+/// gutter, the lines are rendered without line numbers or column separators.
+fn render_annotated_lines(
+    buffer: &mut StyledBuffer,
+    annotated_file: &FileWithAnnotatedLines,
+    max_line_num_len: usize,
+    gutter: bool,
+    tab_width: usize,
+) {
+    // Contains the vertical lines' positions for active multiline annotations
+    let mut multilines = HashMap::new();
+
+    // The annotated lines, with the unannotated lines between them shown where there
+    // are few enough of them in a row and elided with `...` elsewhere.
+    let regions = CodeRegion::merge(
+        annotated_file
+            .lines
+            .iter()
+            .map(|line| {
+                CodeRegion::new(
+                    annotated_file.file.clone(),
+                    line.line_index,
+                    line.line_index,
+                )
+            })
+            .collect(),
+        MAX_UNANNOTATED_GAP,
+    );
+
+    // Next, output the annotate source for this file
+    for line_idx in 0..annotated_file.lines.len() {
+        let previous_buffer_line = buffer.num_lines();
+
+        let width_offset = if gutter { 3 + max_line_num_len } else { 0 };
+        let code_offset = if annotated_file.multiline_depth == 0 {
+            width_offset
+        } else {
+            width_offset + annotated_file.multiline_depth + 1
+        };
+
+        let depths = render_source_line(
+            buffer,
+            annotated_file.file.clone(),
+            &annotated_file.lines[line_idx],
+            width_offset,
+            code_offset,
+            gutter,
+            tab_width,
+        );
+
+        let mut to_add = HashMap::new();
+
+        for (depth, style) in depths {
+            if multilines.get(&depth).is_some() {
+                multilines.remove(&depth);
+            } else {
+                to_add.insert(depth, style);
+            }
+        }
+
+        // Set the multiline annotation vertical lines to the left of
+        // the code in this line.
+        for (depth, style) in &multilines {
+            for line in previous_buffer_line..buffer.num_lines() {
+                draw_multiline_line(buffer, line, width_offset, *depth, *style);
+            }
+        }
+        // print out or elide the lines that come between this annotated line and the
+        // next one.
+        if line_idx < (annotated_file.lines.len() - 1) {
+            let line_index = annotated_file.lines[line_idx].line_index;
+            let next_line_index = annotated_file.lines[line_idx + 1].line_index;
+            let region = regions
+                .iter()
+                .find(|region| region.contains_line(line_index))
+                .expect("every annotated line is in a region");
+            if !region.contains_line(next_line_index) {
+                let last_buffer_line_num = buffer.num_lines();
+                buffer.puts(last_buffer_line_num, 0, "...", Style::LineNumber);
+
+                // Set the multiline annotation vertical lines on `...` bridging line.
+                for (depth, style) in &multilines {
+                    draw_multiline_line(buffer, last_buffer_line_num, width_offset, *depth, *style);
+                }
+            }
+            for unannotated in line_index + 1..next_line_index {
+                if !region.contains_line(unannotated) {
+                    break;
+                }
+                let last_buffer_line_num = buffer.num_lines();
+
+                buffer.ensure_lines(last_buffer_line_num);
+                if gutter {
+                    buffer.puts(
+                        last_buffer_line_num,
+                        0,
+                        &unannotated.to_string(),
+                        Style::LineNumber,
+                    );
+                    draw_col_separator(buffer, last_buffer_line_num, 1 + max_line_num_len);
+                }
+                buffer.puts(
+                    last_buffer_line_num,
+                    code_offset,
+                    &expand_tabs(region.line(unannotated), tab_width),
+                    Style::Quotation,
+                );
+
+                for (depth, style) in &multilines {
+                    draw_multiline_line(buffer, last_buffer_line_num, width_offset, *depth, *style);
+                }
+            }
+        }
+
+        multilines.extend(&to_add);
+    }
+}
+
+fn multispan_max_line_num(cm: &dyn CodeMapper, msp: &MultiSpan) -> usize {
+    let mut max = 0;
+    for primary_span in msp.primary_spans() {
+        if primary_span != &DUMMY_SP {
+            let hi = cm.lookup_char_pos(primary_span.hi);
+            if hi.line > max {
+                max = hi.line;
+            }
+        }
+    }
+    for span_label in msp.span_labels() {
+        if span_label.span != DUMMY_SP {
+            let hi = cm.lookup_char_pos(span_label.span.hi);
+            if hi.line > max {
+                max = hi.line;
+            }
+        }
+    }
+    max
+}
+
 fn draw_col_separator(buffer: &mut StyledBuffer, line: usize, col: usize) {
     buffer.puts(line, col, "| ", Style::LineNumber);
 }
@@ -1318,6 +1775,8 @@ pub enum Destination {
     Terminal(Box<term::StderrTerminal>),
     BufferedTerminal(Box<BufferedStderr>),
     Raw(Box<dyn Write + Send>),
+    /// A writer that is styled with ANSI escape codes, whatever it is.
+    Ansi(Box<dyn Write + Send>),
 }
 
 /// Buffered writer gives us a way on Unix to buffer up an entire error message before we output
@@ -1439,6 +1898,14 @@ impl Destination {
                 t.attr(attr)?;
             }
             Raw(_) => {}
+            Ansi(ref mut w) => match attr {
+                term::Attr::Bold => w.write_all(b"\x1b[1m")?,
+                term::Attr::ForegroundColor(color) if color < 8 => {
+                    write!(w, "\x1b[{}m", 30 + color)?
+                }
+                term::Attr::ForegroundColor(color) => write!(w, "\x1b[{}m", 90 + color - 8)?,
+                _ => {}
+            },
         }
         Ok(())
     }
@@ -1452,6 +1919,7 @@ impl Destination {
                 t.reset()?;
             }
             Raw(_) => {}
+            Ansi(ref mut w) => w.write_all(b"\x1b[0m")?,
         }
         Ok(())
     }
@@ -1462,14 +1930,14 @@ impl Write for Destination {
         match *self {
             Terminal(ref mut t) => t.write(bytes),
             BufferedTerminal(ref mut t) => t.write(bytes),
-            Raw(ref mut w) => w.write(bytes),
+            Raw(ref mut w) | Ansi(ref mut w) => w.write(bytes),
         }
     }
     fn flush(&mut self) -> io::Result<()> {
         match *self {
             Terminal(ref mut t) => t.flush(),
             BufferedTerminal(ref mut t) => t.flush(),
-            Raw(ref mut w) => w.flush(),
+            Raw(ref mut w) | Ansi(ref mut w) => w.flush(),
         }
     }
 }
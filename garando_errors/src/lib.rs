@@ -1,15 +1,21 @@
 use garando_pos as syntax_pos;
 
-pub use crate::emitter::ColorConfig;
+pub use crate::emitter::{render_snippet, ColorConfig, LabelStyle};
 
 use self::Level::*;
 
 use crate::emitter::{Emitter, EmitterWriter};
+use crate::registry::Registry;
 
 use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashSet};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
 use std::rc::Rc;
-use std::{error, fmt};
+use std::{error, fmt, mem};
 
+pub mod caching_codemap_view;
 pub mod diagnostic;
 pub mod diagnostic_builder;
 pub mod emitter;
@@ -20,9 +26,11 @@ pub mod styled_buffer;
 
 use serde::{Deserialize, Serialize};
 
-use crate::syntax_pos::{BytePos, FileLinesResult, FileName, Loc, MultiSpan, Span, NO_EXPANSION};
+use crate::syntax_pos::{
+    BytePos, FileLinesResult, FileMap, FileName, Loc, MultiSpan, Span, NO_EXPANSION,
+};
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
 pub enum RenderSpan {
     /// A FullSpan renders with both with an initial line for the
     /// message, prefixed by file:linenum, followed by a summary of
@@ -33,10 +41,12 @@ pub enum RenderSpan {
     /// message, prefixed by file:linenum, followed by a summary
     /// of hypothetical source code, where each `String` is spliced
     /// into the lines in place of the code covered by each span.
+    /// In JSON, it becomes spans with their `suggested_replacement` set.
+    /// See `Diagnostic::span_suggestion_as_note`.
     Suggestion(CodeSuggestion),
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
 pub struct CodeSuggestion {
     /// Each substitute can have multiple variants due to multiple
     /// applicable suggestions
@@ -60,19 +70,68 @@ pub struct CodeSuggestion {
     pub msg: String,
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
 /// See the docs on `CodeSuggestion::substitutions`
 pub struct Substitution {
     pub span: Span,
     pub substitutions: Vec<String>,
 }
 
+/// What a single substitution does to the source it replaces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SuggestionKind {
+    /// New code at a zero-width span.
+    Insertion,
+    /// An empty substitution for existing code.
+    Deletion,
+    /// Existing code swapped for something else.
+    Replacement,
+}
+
+impl SuggestionKind {
+    /// Classifies a substitution from the length of the span it replaces and
+    /// the length of the text it puts there.
+    pub fn new(span_len: usize, substitution_len: usize) -> SuggestionKind {
+        if span_len == 0 {
+            SuggestionKind::Insertion
+        } else if substitution_len == 0 {
+            SuggestionKind::Deletion
+        } else {
+            SuggestionKind::Replacement
+        }
+    }
+}
+
+impl Substitution {
+    /// Returns the kind of the `i`th alternative of this part.
+    pub fn kind(&self, i: usize) -> SuggestionKind {
+        let span_len = (self.span.hi.0 - self.span.lo.0) as usize;
+        SuggestionKind::new(span_len, self.substitutions[i].len())
+    }
+}
+
 pub trait CodeMapper {
     fn lookup_char_pos(&self, pos: BytePos) -> Loc;
     fn span_to_lines(&self, sp: Span) -> FileLinesResult;
+    /// Like `span_to_lines`, but returns at most `max_lines` lines (and at
+    /// least one), setting `truncated` if the span covers more.
+    fn span_to_lines_limited(&self, sp: Span, max_lines: usize) -> FileLinesResult {
+        self.span_to_lines(sp).map(|mut lines| {
+            let max_lines = max_lines.max(1);
+            if lines.lines.len() > max_lines {
+                lines.lines.truncate(max_lines);
+                lines.truncated = true;
+            }
+            lines
+        })
+    }
     fn span_to_string(&self, sp: Span) -> String;
     fn span_to_filename(&self, sp: Span) -> FileName;
     fn merge_spans(&self, sp_lhs: Span, sp_rhs: Span) -> Option<Span>;
+    /// Makes the source code of `file_map` available if possible, returning
+    /// whether it is.
+    fn ensure_filemap_source_present(&self, file_map: Rc<FileMap>) -> bool;
 }
 
 impl CodeSuggestion {
@@ -81,6 +140,15 @@ impl CodeSuggestion {
         self.substitution_parts[0].substitutions.len()
     }
 
+    /// Returns whether every part of the `i`th alternative removes code.
+    pub fn is_deletion(&self, i: usize) -> bool {
+        !self.substitution_parts.is_empty()
+            && self
+                .substitution_parts
+                .iter()
+                .all(|part| part.kind(i) == SuggestionKind::Deletion)
+    }
+
     /// Returns the number of substitutions
     pub fn substitution_spans<'a>(
         &'a self,
@@ -92,7 +160,8 @@ impl CodeSuggestion {
         self.substitution_parts.iter().map(substitution_span)
     }
 
-    /// Returns the assembled code suggestions.
+    /// Returns the assembled code suggestions, or none if the spans can't be
+    /// turned into lines.
     pub fn splice_lines(&self, cm: &dyn CodeMapper) -> Vec<String> {
         use crate::syntax_pos::{CharPos, Pos};
 
@@ -134,7 +203,10 @@ impl CodeSuggestion {
             hi: hi,
             ctxt: NO_EXPANSION,
         };
-        let lines = cm.span_to_lines(bounding_span).unwrap();
+        let lines = match cm.span_to_lines(bounding_span) {
+            Ok(lines) => lines,
+            Err(_) => return Vec::new(),
+        };
         assert!(!lines.lines.is_empty());
 
         // To build up the result, we do this for each span:
@@ -157,13 +229,13 @@ impl CodeSuggestion {
             let cur_lo = cm.lookup_char_pos(sp.lo);
             for (buf, substitute) in bufs.iter_mut().zip(substitutes) {
                 if prev_hi.line == cur_lo.line {
-                    push_trailing(buf, prev_line, &prev_hi, Some(&cur_lo));
+                    push_trailing(buf, prev_line.as_deref(), &prev_hi, Some(&cur_lo));
                 } else {
-                    push_trailing(buf, prev_line, &prev_hi, None);
+                    push_trailing(buf, prev_line.as_deref(), &prev_hi, None);
                     // push lines between the previous and current span (if any)
                     for idx in prev_hi.line..(cur_lo.line - 1) {
                         if let Some(line) = fm.get_line(idx) {
-                            buf.push_str(line);
+                            buf.push_str(&line);
                             buf.push('\n');
                         }
                     }
@@ -179,7 +251,7 @@ impl CodeSuggestion {
         for buf in &mut bufs {
             // if the replacement already ends with a newline, don't print the next line
             if !buf.ends_with('\n') {
-                push_trailing(buf, prev_line, &prev_hi, None);
+                push_trailing(buf, prev_line.as_deref(), &prev_hi, None);
             }
             // remove trailing newline
             buf.pop();
@@ -232,11 +304,21 @@ pub use crate::diagnostic_builder::DiagnosticBuilder;
 /// others log errors for later reporting.
 pub struct Handler {
     err_count: Cell<usize>,
+    warn_count: Cell<usize>,
+    /// The number of bugs and fatal errors among `err_count`.
+    fatal_count: Cell<usize>,
     emitter: RefCell<Box<dyn Emitter>>,
     pub can_emit_warnings: bool,
     treat_err_as_bug: bool,
     continue_after_error: Cell<bool>,
     delayed_span_bug: RefCell<Option<(MultiSpan, String)>>,
+    deduplicate_diagnostics: Cell<bool>,
+    /// Hashes of the diagnostics emitted so far, to drop duplicates.
+    emitted_diagnostics: RefCell<HashSet<u64>>,
+    /// The error codes emitted so far, once tracking them is enabled.
+    emitted_codes: RefCell<Option<BTreeSet<String>>>,
+    /// The diagnostics held back for `flush_sorted`, once buffering is enabled.
+    buffered_diagnostics: RefCell<Option<Vec<Diagnostic>>>,
 }
 
 impl Handler {
@@ -250,6 +332,17 @@ impl Handler {
         Handler::with_emitter(can_emit_warnings, treat_err_as_bug, emitter)
     }
 
+    /// Creates a handler rendering diagnostics as human-readable text to
+    /// `dst`. See `EmitterWriter::with_color` for how `color_config` applies.
+    pub fn with_emitter_writer(
+        dst: Box<dyn Write + Send>,
+        color_config: ColorConfig,
+        cm: Option<Rc<dyn CodeMapper>>,
+    ) -> Handler {
+        let emitter = Box::new(EmitterWriter::with_color(dst, color_config, cm));
+        Handler::with_emitter(true, false, emitter)
+    }
+
     pub fn with_emitter(
         can_emit_warnings: bool,
         treat_err_as_bug: bool,
@@ -257,18 +350,134 @@ impl Handler {
     ) -> Handler {
         Handler {
             err_count: Cell::new(0),
+            warn_count: Cell::new(0),
+            fatal_count: Cell::new(0),
             emitter: RefCell::new(e),
             can_emit_warnings: can_emit_warnings,
             treat_err_as_bug: treat_err_as_bug,
             continue_after_error: Cell::new(true),
             delayed_span_bug: RefCell::new(None),
+            deduplicate_diagnostics: Cell::new(true),
+            emitted_diagnostics: RefCell::new(HashSet::new()),
+            emitted_codes: RefCell::new(None),
+            buffered_diagnostics: RefCell::new(None),
         }
     }
 
+    /// Whether to carry on once an error has been emitted. When disabled,
+    /// `span_fatal`, `fatal`, `span_err` and friends abort by panicking with
+    /// `FatalError` after emitting. Enabled by default, in which case they
+    /// only return their `FatalError` for the caller to act on, so a batch
+    /// tool can go on to the next file after a fatal error returned by the
+    /// `maybe_*` parse entry points.
     pub fn set_continue_after_error(&self, continue_after_error: bool) {
         self.continue_after_error.set(continue_after_error);
     }
 
+    pub fn continue_after_error(&self) -> bool {
+        self.continue_after_error.get()
+    }
+
+    /// Whether to drop diagnostics identical to one emitted before, down to
+    /// their spans, children and suggestions. Enabled by default; dropped
+    /// diagnostics are not counted.
+    pub fn set_deduplicate_diagnostics(&self, deduplicate_diagnostics: bool) {
+        self.deduplicate_diagnostics.set(deduplicate_diagnostics);
+    }
+
+    /// Start recording the codes of emitted diagnostics, see
+    /// `undocumented_codes`.
+    pub fn track_emitted_codes(&self) {
+        let mut emitted_codes = self.emitted_codes.borrow_mut();
+        if emitted_codes.is_none() {
+            *emitted_codes = Some(BTreeSet::new());
+        }
+    }
+
+    fn record_emitted_code(&self, diagnostic: &Diagnostic) {
+        if let (Some(codes), Some(code)) = (&mut *self.emitted_codes.borrow_mut(), &diagnostic.code)
+        {
+            codes.insert(code.clone());
+        }
+    }
+
+    /// The codes emitted since `track_emitted_codes` was called, in order.
+    pub fn emitted_codes(&self) -> Vec<String> {
+        match *self.emitted_codes.borrow() {
+            Some(ref codes) => codes.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The emitted codes (see `emitted_codes`) that have no description in
+    /// `registry`.
+    pub fn undocumented_codes(&self, registry: &Registry) -> Vec<String> {
+        self.emitted_codes()
+            .into_iter()
+            .filter(|code| registry.find_description(code).is_none())
+            .collect()
+    }
+
+    /// Whether to hold emitted diagnostics back until `flush_sorted` instead
+    /// of passing them to the emitter straight away. Buffered diagnostics are
+    /// counted, deduplicated and have their codes recorded when emitted, so
+    /// `err_count` and `has_errors` are up to date while they are held back.
+    /// Disabling buffering flushes whatever is buffered.
+    pub fn buffer_diagnostics(&self, buffer: bool) {
+        if buffer {
+            let mut buffered = self.buffered_diagnostics.borrow_mut();
+            if buffered.is_none() {
+                *buffered = Some(Vec::new());
+            }
+        } else {
+            self.flush_sorted();
+            *self.buffered_diagnostics.borrow_mut() = None;
+        }
+    }
+
+    /// Sends the buffered diagnostics to the emitter, ordered by file, then
+    /// by the start of their primary span, then by severity (errors before
+    /// warnings before notes). Files are ordered as they were loaded into
+    /// the codemap, and diagnostics without a span come last. Diagnostics
+    /// with the same key keep their emission order. Buffering stays enabled.
+    pub fn flush_sorted(&self) {
+        let mut diagnostics = match *self.buffered_diagnostics.borrow_mut() {
+            Some(ref mut buffered) => mem::replace(buffered, Vec::new()),
+            None => return,
+        };
+        diagnostics.sort_by_key(|diagnostic| {
+            let lo = diagnostic.span.primary_span().map(|sp| sp.lo);
+            (lo.is_none(), lo, diagnostic.level.severity())
+        });
+        let mut emitter = self.emitter.borrow_mut();
+        for diagnostic in diagnostics {
+            let mut db = DiagnosticBuilder::new_diagnostic(self, diagnostic);
+            emitter.emit(&db);
+            db.cancel();
+        }
+    }
+
+    /// Passes an emitted diagnostic on to the emitter, or to the buffer.
+    fn emit_db(&self, db: &DiagnosticBuilder) {
+        if let Some(ref mut buffered) = *self.buffered_diagnostics.borrow_mut() {
+            buffered.push((**db).clone());
+            return;
+        }
+        self.emitter.borrow_mut().emit(db);
+    }
+
+    /// Whether `diagnostic` should be emitted, i.e. is not a duplicate.
+    fn is_new_diagnostic(&self, diagnostic: &Diagnostic) -> bool {
+        if !self.deduplicate_diagnostics.get() {
+            return true;
+        }
+        let mut hasher = DefaultHasher::new();
+        diagnostic.hash(&mut hasher);
+        self.emitted_diagnostics
+            .borrow_mut()
+            .insert(hasher.finish())
+    }
+
     pub fn struct_dummy<'a>(&'a self) -> DiagnosticBuilder<'a> {
         DiagnosticBuilder::new(self, Level::Cancelled, "")
     }
@@ -365,6 +574,7 @@ impl Handler {
 
     fn panic_if_treat_err_as_bug(&self) {
         if self.treat_err_as_bug {
+            self.flush_sorted();
             panic!("encountered error with `-Z treat_err_as_bug");
         }
     }
@@ -438,6 +648,9 @@ impl Handler {
         }
         let mut db = DiagnosticBuilder::new(self, Fatal, msg);
         db.emit();
+        if !self.continue_after_error.get() {
+            self.abort_if_errors();
+        }
         FatalError
     }
     pub fn err(&self, msg: &str) {
@@ -475,24 +688,81 @@ impl Handler {
     pub fn has_errors(&self) -> bool {
         self.err_count.get() > 0
     }
+
+    fn bump_warn_count(&self) {
+        self.warn_count.set(self.warn_count.get() + 1);
+    }
+
+    pub fn warn_count(&self) -> usize {
+        self.warn_count.get()
+    }
+
+    fn bump_fatal_count(&self) {
+        self.fatal_count.set(self.fatal_count.get() + 1);
+    }
+
+    /// The exit code for a process whose outcome is the diagnostics emitted
+    /// so far: `101` after a bug or fatal error, or if a delayed bug is still
+    /// pending, `1` after any other error, and otherwise `0`, or `2` if there
+    /// were warnings and `policy.warnings_are_errors` is set.
+    pub fn exit_status(&self, policy: ExitPolicy) -> i32 {
+        if self.fatal_count.get() > 0
+            || (self.err_count.get() == 0 && self.delayed_span_bug.borrow().is_some())
+        {
+            101
+        } else if self.err_count.get() > 0 {
+            1
+        } else if policy.warnings_are_errors && self.warn_count.get() > 0 {
+            2
+        } else {
+            0
+        }
+    }
+
+    /// Emits a line summing up the errors and warnings emitted so far, like
+    /// `aborting due to 2 previous errors; 1 warning emitted`, or nothing if
+    /// there were neither. The summary has `is_summary` set and is not
+    /// counted itself.
+    pub fn print_error_summary(&self) {
+        // The summary comes after everything it sums up.
+        self.flush_sorted();
+        let errors = match self.err_count.get() {
+            0 => None,
+            1 => Some("aborting due to previous error".to_string()),
+            n => Some(format!("aborting due to {} previous errors", n)),
+        };
+        let warnings = match self.warn_count.get() {
+            0 => None,
+            1 => Some("1 warning emitted".to_string()),
+            n => Some(format!("{} warnings emitted", n)),
+        };
+        let (level, msg) = match (errors, warnings) {
+            (Some(errors), Some(warnings)) => (Error, format!("{}; {}", errors, warnings)),
+            (Some(errors), None) => (Error, errors),
+            (None, Some(warnings)) => (Warning, warnings),
+            (None, None) => return,
+        };
+        let mut db = DiagnosticBuilder::new(self, level, &msg);
+        db.is_summary = true;
+        self.emitter.borrow_mut().emit(&db);
+        db.cancel();
+    }
+
     pub fn abort_if_errors(&self) {
-        let s;
-        match self.err_count.get() {
-            0 => {
-                let delayed_bug = self.delayed_span_bug.borrow();
-                match *delayed_bug {
-                    Some((ref span, ref errmsg)) => {
-                        self.span_bug(span.clone(), errmsg);
-                    }
-                    _ => {}
+        if self.err_count.get() == 0 {
+            let delayed_bug = self.delayed_span_bug.borrow();
+            match *delayed_bug {
+                Some((ref span, ref errmsg)) => {
+                    self.span_bug(span.clone(), errmsg);
                 }
-
-                return;
+                _ => {}
             }
-            _ => s = "aborting due to previous error(s)".to_string(),
+
+            return;
         }
 
-        panic!(self.fatal(&s));
+        self.print_error_summary();
+        panic!(FatalError);
     }
     pub fn emit(&self, msp: &MultiSpan, msg: &str, lvl: Level) {
         if lvl == Warning && !self.can_emit_warnings {
@@ -518,7 +788,14 @@ impl Handler {
     }
 }
 
-#[derive(Copy, PartialEq, Clone, Debug, Serialize, Deserialize)]
+/// How `Handler::exit_status` maps the diagnostics emitted to an exit code.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExitPolicy {
+    /// Fail with exit code `2` when there were warnings but no errors.
+    pub warnings_are_errors: bool,
+}
+
+#[derive(Copy, PartialEq, Clone, Debug, Hash, Serialize, Deserialize)]
 pub enum Level {
     Bug,
     Fatal,
@@ -555,6 +832,36 @@ impl Level {
         }
     }
 
+    /// Returns `true` for the levels counted as errors.
+    pub fn is_error(self) -> bool {
+        match self {
+            Bug | Fatal | PhaseFatal | Error => true,
+            Warning | Note | Help | Cancelled => false,
+        }
+    }
+
+    /// Returns `true` for the levels that stop the session outright: bugs and
+    /// fatal errors.
+    pub fn is_fatal(self) -> bool {
+        match self {
+            Bug | Fatal => true,
+            _ => false,
+        }
+    }
+
+    /// Orders levels from most to least severe, for sorting.
+    fn severity(self) -> u8 {
+        match self {
+            Bug => 0,
+            Fatal | PhaseFatal => 1,
+            Error => 2,
+            Warning => 3,
+            Note => 4,
+            Help => 5,
+            Cancelled => 6,
+        }
+    }
+
     pub fn to_str(self) -> &'static str {
         match self {
             Bug => "error: internal compiler error",
@@ -2,17 +2,28 @@ use std::collections::HashMap;
 
 #[derive(Clone)]
 pub struct Registry {
-    descriptions: HashMap<&'static str, &'static str>,
+    /// Registered codes, with their long description if they have one.
+    descriptions: HashMap<String, Option<&'static str>>,
 }
 
 impl Registry {
     pub fn new(descriptions: &[(&'static str, &'static str)]) -> Registry {
         Registry {
-            descriptions: descriptions.iter().cloned().collect(),
+            descriptions: descriptions
+                .iter()
+                .map(|&(code, description)| (code.to_string(), Some(description)))
+                .collect(),
         }
     }
 
+    /// Registers `code`, e.g. one defined by a tool, replacing any earlier
+    /// description of it. Codes registered without a description are known
+    /// but count as undocumented.
+    pub fn add(&mut self, code: &str, description: Option<&'static str>) {
+        self.descriptions.insert(code.to_string(), description);
+    }
+
     pub fn find_description(&self, code: &str) -> Option<&'static str> {
-        self.descriptions.get(code).cloned()
+        self.descriptions.get(code).cloned().and_then(|d| d)
     }
 }
@@ -1,6 +1,6 @@
 //! Code for annotating snippets.
 
-use crate::syntax_pos::{FileMap, Span};
+use crate::syntax_pos::{FileMap, Span, StringPart};
 use crate::CodeMapper;
 use crate::Level;
 use serde::{Deserialize, Serialize};
@@ -41,6 +41,7 @@ pub struct MultilineAnnotation {
     pub end_col: usize,
     pub is_primary: bool,
     pub label: Option<String>,
+    pub label_parts: Vec<StringPart>,
 }
 
 impl MultilineAnnotation {
@@ -54,6 +55,7 @@ impl MultilineAnnotation {
             end_col: self.start_col + 1,
             is_primary: self.is_primary,
             label: None,
+            label_parts: vec![],
             annotation_type: AnnotationType::MultilineStart(self.depth),
         }
     }
@@ -64,6 +66,7 @@ impl MultilineAnnotation {
             end_col: self.end_col,
             is_primary: self.is_primary,
             label: self.label.clone(),
+            label_parts: self.label_parts.clone(),
             annotation_type: AnnotationType::MultilineEnd(self.depth),
         }
     }
@@ -74,6 +77,7 @@ impl MultilineAnnotation {
             end_col: 0,
             is_primary: self.is_primary,
             label: None,
+            label_parts: vec![],
             annotation_type: AnnotationType::MultilineLine(self.depth),
         }
     }
@@ -125,6 +129,9 @@ pub struct Annotation {
     /// Optional label to display adjacent to the annotation.
     pub label: Option<String>,
 
+    /// The parts of `label`, some of which may be highlighted.
+    pub label_parts: Vec<StringPart>,
+
     /// Is this a single line, multiline or multiline span minimized down to a
     /// smaller span.
     pub annotation_type: AnnotationType,
@@ -192,7 +199,7 @@ pub struct StyledString {
     pub style: Style,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
 pub enum Style {
     HeaderMsg,
     FileNameStyle,
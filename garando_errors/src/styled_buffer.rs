@@ -16,28 +16,10 @@ impl StyledBuffer {
         }
     }
 
-    pub fn copy_tabs(&mut self, row: usize) {
-        if row < self.text.len() {
-            for i in row + 1..self.text.len() {
-                for j in 0..self.text[i].len() {
-                    if self.text[row].len() > j
-                        && self.text[row][j] == '\t'
-                        && self.text[i][j] == ' '
-                    {
-                        self.text[i][j] = '\t';
-                    }
-                }
-            }
-        }
-    }
-
     pub fn render(&mut self) -> Vec<Vec<StyledString>> {
         let mut output: Vec<Vec<StyledString>> = vec![];
         let mut styled_vec: Vec<StyledString> = vec![];
 
-        // before we render, do a little patch-up work to support tabs
-        self.copy_tabs(3);
-
         for (row, row_style) in self.text.iter().zip(&self.styles) {
             let mut current_style = Style::NoStyle;
             let mut current_text = String::new();
@@ -71,7 +53,9 @@ impl StyledBuffer {
         output
     }
 
-    fn ensure_lines(&mut self, line: usize) {
+    /// Adds empty lines as needed for `line` to exist, so that it is
+    /// rendered even if nothing is written to it.
+    pub fn ensure_lines(&mut self, line: usize) {
         while line >= self.text.len() {
             self.text.push(vec![]);
             self.styles.push(vec![]);
@@ -0,0 +1,160 @@
+//! The state shared by everything using spans and symbols: the symbol
+//! interner, the hygiene data and what spans are debug-formatted through.
+//!
+//! Each thread starts out with a `Globals` of its own, which is used whenever
+//! no other has been installed with `GLOBALS.set`. Installing one explicitly
+//! lets a tool keep sessions apart, or throw one away once it is done with it.
+
+use crate::hygiene::HygieneData;
+use crate::symbol::Interner;
+use crate::SpanResolver;
+
+use std::cell::{Cell, RefCell};
+use std::ptr;
+
+pub struct Globals {
+    pub(crate) symbol_interner: RefCell<Interner>,
+    pub(crate) hygiene_data: RefCell<HygieneData>,
+    /// What `{:?}` resolves spans through, if anything; only ever set for
+    /// the duration of `with_span_resolver`, which keeps it borrowed.
+    pub(crate) span_resolver: Cell<Option<*const dyn SpanResolver>>,
+}
+
+impl Globals {
+    pub fn new() -> Globals {
+        Globals {
+            symbol_interner: RefCell::new(Interner::fresh()),
+            hygiene_data: RefCell::new(HygieneData::new()),
+            span_resolver: Cell::new(None),
+        }
+    }
+
+    /// Drops the interned strings and the hygiene data, leaving these as in a
+    /// new `Globals`, e.g. to free the memory held between batches. Taking
+    /// `&mut self` ensures this is not in use with `GLOBALS.set`.
+    ///
+    /// # Safety
+    ///
+    /// The strings of `InternedString`s created with this `Globals` in use
+    /// are freed, so none of them may be used after the reset. Symbols and
+    /// marks created before it must not be used after it either.
+    pub unsafe fn reset(&mut self) {
+        *self.symbol_interner.get_mut() = Interner::fresh();
+        *self.hygiene_data.get_mut() = HygieneData::new();
+    }
+}
+
+impl Default for Globals {
+    fn default() -> Globals {
+        Globals::new()
+    }
+}
+
+thread_local! {
+    static CURRENT: Cell<*const Globals> = const { Cell::new(ptr::null()) };
+    static THREAD_DEFAULT: Globals = Globals::new();
+}
+
+/// The `Globals` in use on the current thread, see `ScopedGlobals`.
+pub static GLOBALS: ScopedGlobals = ScopedGlobals { _private: () };
+
+/// A scoped thread-local holding the `Globals` in use; the only instance is
+/// `GLOBALS`.
+pub struct ScopedGlobals {
+    _private: (),
+}
+
+impl ScopedGlobals {
+    /// Runs `f` with `globals` in use on the current thread. The previous
+    /// `Globals` is restored when `f` returns or panics, so scopes may nest.
+    ///
+    /// # Safety
+    ///
+    /// The strings of the `InternedString`s created in `f` are owned by
+    /// `globals`, so none of them may be used once `globals` is dropped or
+    /// reset. Symbols, marks and syntax contexts are indices into `globals`
+    /// too, and mean something else, if anything, outside of `f`.
+    pub unsafe fn set<R, F: FnOnce() -> R>(&'static self, globals: &Globals, f: F) -> R {
+        struct Restore(*const Globals);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                CURRENT.with(|current| current.set(self.0));
+            }
+        }
+
+        let _restore = Restore(CURRENT.with(|current| current.replace(globals)));
+        f()
+    }
+
+    /// Whether a `Globals` has been installed with `set`, rather than the
+    /// thread's own being in use.
+    pub fn is_set(&'static self) -> bool {
+        CURRENT.with(|current| !current.get().is_null())
+    }
+
+    /// Runs `f` with the `Globals` in use on the current thread.
+    pub fn with<R, F: FnOnce(&Globals) -> R>(&'static self, f: F) -> R {
+        let globals = CURRENT.with(Cell::get);
+        if globals.is_null() {
+            THREAD_DEFAULT.with(f)
+        } else {
+            // `set` keeps the `Globals` borrowed for as long as it is current.
+            f(unsafe { &*globals })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Globals, GLOBALS};
+    use crate::hygiene::Mark;
+    use crate::symbol::{self, Symbol};
+
+    #[test]
+    fn independent_globals() {
+        let (a, b) = (Globals::new(), Globals::new());
+        let fresh = || Mark::fresh(Mark::root()).as_u32();
+
+        assert!(!GLOBALS.is_set());
+        unsafe {
+            GLOBALS.set(&a, || {
+                assert!(GLOBALS.is_set());
+                assert_eq!((fresh(), fresh()), (1, 2));
+                let x = Symbol::intern("independent_globals_x");
+                GLOBALS.set(&b, || {
+                    assert_eq!(fresh(), 1);
+                    let y = Symbol::intern("independent_globals_y");
+                    assert_eq!(y, x);
+                    assert_eq!(y.as_str(), "independent_globals_y");
+                });
+                assert_eq!(fresh(), 3);
+                assert_eq!(x.as_str(), "independent_globals_x");
+            })
+        };
+        unsafe { GLOBALS.set(&b, || assert_eq!(fresh(), 2)) };
+        assert!(!GLOBALS.is_set());
+    }
+
+    #[test]
+    fn reset_between_batches() {
+        let mut globals = Globals::new();
+        let fresh = || Mark::fresh(Mark::root()).as_u32();
+        let batch = || {
+            let before = symbol::interner_stats();
+            for i in 0..100 {
+                Symbol::intern(&format!("reset_between_batches_{}", i));
+            }
+            assert_eq!(fresh(), 1);
+            let after = symbol::interner_stats();
+            assert_eq!(after.count, before.count + 100);
+            (before, after)
+        };
+
+        let first = unsafe { GLOBALS.set(&globals, batch) };
+        // Nothing from the first batch outlives it.
+        unsafe { globals.reset() };
+        let second = unsafe { GLOBALS.set(&globals, batch) };
+        assert_eq!(first, second);
+    }
+}
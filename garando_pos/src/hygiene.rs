@@ -6,9 +6,8 @@
 //! DOI=10.1017/S0956796812000093 http://dx.doi.org/10.1017/S0956796812000093
 
 use crate::symbol::{Ident, Symbol};
-use crate::Span;
+use crate::{Span, GLOBALS};
 
-use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt;
 
@@ -59,6 +58,12 @@ impl Mark {
         self.0
     }
 
+    /// The mark of the expansion that produced the code this mark's expansion
+    /// was invoked from. The root is its own parent.
+    pub fn parent(self) -> Mark {
+        HygieneData::with(|data| data.marks[self.0 as usize].parent)
+    }
+
     pub fn from_u32(raw: u32) -> Mark {
         Mark(raw)
     }
@@ -71,6 +76,16 @@ impl Mark {
         HygieneData::with(|data| data.marks[self.0 as usize].expn_info = Some(info))
     }
 
+    /// Record the span of the code produced by this mark's expansion. Does
+    /// nothing if the mark has no expansion info.
+    pub fn set_expn_output_span(self, span: Span) {
+        HygieneData::with(|data| {
+            if let Some(ref mut info) = data.marks[self.0 as usize].expn_info {
+                info.output_span = Some(span);
+            }
+        })
+    }
+
     pub fn modern(mut self) -> Mark {
         HygieneData::with(|data| loop {
             if self == Mark::root() || data.marks[self.0 as usize].modern {
@@ -88,6 +103,8 @@ impl Mark {
         HygieneData::with(|data| data.marks[self.0 as usize].modern = true)
     }
 
+    /// Whether `ancestor` is reached by following `parent` from this mark,
+    /// which includes the mark itself. Every mark descends from the root.
     pub fn is_descendant_of(mut self, ancestor: Mark) -> bool {
         HygieneData::with(|data| {
             while self != ancestor {
@@ -101,7 +118,7 @@ impl Mark {
     }
 }
 
-struct HygieneData {
+pub(crate) struct HygieneData {
     marks: Vec<MarkData>,
     syntax_contexts: Vec<SyntaxContextData>,
     markings: HashMap<(SyntaxContext, Mark), SyntaxContext>,
@@ -109,7 +126,7 @@ struct HygieneData {
 }
 
 impl HygieneData {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         HygieneData {
             marks: vec![MarkData::default()],
             syntax_contexts: vec![SyntaxContextData::default()],
@@ -119,10 +136,7 @@ impl HygieneData {
     }
 
     fn with<T, F: FnOnce(&mut HygieneData) -> T>(f: F) -> T {
-        thread_local! {
-            static HYGIENE_DATA: RefCell<HygieneData> = RefCell::new(HygieneData::new());
-        }
-        HYGIENE_DATA.with(|data| f(&mut *data.borrow_mut()))
+        GLOBALS.with(|globals| f(&mut globals.hygiene_data.borrow_mut()))
     }
 }
 
@@ -130,6 +144,48 @@ pub fn clear_markings() {
     HygieneData::with(|data| data.markings = HashMap::new());
 }
 
+/// Renders the tree of the marks of the `Globals` in use, one mark per line
+/// indented below its parent, e.g. `#2 #[derive] at <call site>`. The
+/// call sites are formatted with `{:?}`, so with the span debug installed.
+pub fn debug_dump() -> String {
+    // Spans are formatted outside of `HygieneData::with`, as the span debug
+    // may well look at the hygiene data itself.
+    let marks: Vec<(Mark, Option<ExpnInfo>)> = HygieneData::with(|data| {
+        data.marks
+            .iter()
+            .map(|mark| (mark.parent, mark.expn_info.clone()))
+            .collect()
+    });
+
+    let mut children = vec![Vec::new(); marks.len()];
+    for (i, &(parent, _)) in marks.iter().enumerate().skip(1) {
+        children[parent.0 as usize].push(i);
+    }
+
+    let mut dump = String::new();
+    let mut stack = vec![(0, 0)];
+    while let Some((i, depth)) = stack.pop() {
+        dump.push_str(&"  ".repeat(depth));
+        dump.push_str(&format!("#{}", i));
+        match marks[i].1 {
+            Some(ref info) => {
+                let name = info.callee.name();
+                let callee = match info.callee.format {
+                    ExpnFormat::MacroBang(..) => format!("{}!", name),
+                    ExpnFormat::MacroAttribute(..) => format!("#[{}]", name),
+                    ExpnFormat::CompilerDesugaring(..) => format!("desugaring of `{}`", name),
+                };
+                dump.push_str(&format!(" {} at {:?}", callee, info.call_site));
+            }
+            None if i == 0 => dump.push_str(" root"),
+            None => {}
+        }
+        dump.push('\n');
+        stack.extend(children[i].iter().rev().map(|&child| (child, depth + 1)));
+    }
+    dump
+}
+
 impl SyntaxContext {
     pub fn empty() -> Self {
         NO_EXPANSION
@@ -242,6 +298,21 @@ impl SyntaxContext {
     pub fn outer(self) -> Mark {
         HygieneData::with(|data| data.syntax_contexts[self.0 as usize].outer_mark)
     }
+
+    /// The marks applied to make this context, outermost first, as
+    /// `remove_mark` would return them. The empty context has none.
+    pub fn marks(self) -> Vec<Mark> {
+        HygieneData::with(|data| {
+            let mut marks = Vec::new();
+            let mut ctxt = self;
+            while ctxt != NO_EXPANSION {
+                let ctxt_data = data.syntax_contexts[ctxt.0 as usize];
+                marks.push(ctxt_data.outer_mark);
+                ctxt = ctxt_data.prev_ctxt;
+            }
+            marks
+        })
+    }
 }
 
 impl fmt::Debug for SyntaxContext {
@@ -265,6 +336,9 @@ pub struct ExpnInfo {
     pub call_site: Span,
     /// Information about the expansion.
     pub callee: NameAndSpan,
+    /// The span covering the code the expansion produced, once it has been
+    /// spliced into the AST.
+    pub output_span: Option<Span>,
 }
 
 #[derive(Clone, Hash, Debug)]
@@ -275,6 +349,9 @@ pub struct NameAndSpan {
     /// features internally without forcing the whole crate to opt-in
     /// to them.
     pub allow_internal_unstable: bool,
+    /// Whether the macro is allowed to use `unsafe` internally even if
+    /// the user crate has `#![forbid(unsafe_code)]`.
+    pub allow_internal_unsafe: bool,
     /// The span of the macro definition itself. The macro may not
     /// have a sensible definition span (e.g. something defined
     /// completely inside libsyntax) in which case this is None.
@@ -341,3 +418,83 @@ impl Symbol {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{with_span_resolver, BytePos, Globals, Span, SpanResolver};
+
+    /// Prints spans as their start offset.
+    struct Offsets;
+
+    impl SpanResolver for Offsets {
+        fn span_to_string(&self, sp: Span) -> String {
+            format!("@{}", sp.lo.0)
+        }
+    }
+
+    fn expn_info(format: ExpnFormat, lo: u32) -> ExpnInfo {
+        ExpnInfo {
+            call_site: Span {
+                lo: BytePos(lo),
+                hi: BytePos(lo + 1),
+                ctxt: NO_EXPANSION,
+            },
+            callee: NameAndSpan {
+                format,
+                allow_internal_unstable: false,
+                allow_internal_unsafe: false,
+                span: None,
+            },
+            output_span: None,
+        }
+    }
+
+    #[test]
+    fn three_level_expansion() {
+        let globals = Globals::new();
+        unsafe {
+            GLOBALS.set(&globals, || {
+                let outer = Mark::fresh(Mark::root());
+                outer.set_expn_info(expn_info(ExpnFormat::MacroBang(Symbol::intern("vec")), 10));
+                let middle = Mark::fresh(outer);
+                let derive = ExpnFormat::MacroAttribute(Symbol::intern("derive"));
+                middle.set_expn_info(expn_info(derive, 20));
+                let sibling = Mark::fresh(outer);
+                let inner = Mark::fresh(middle);
+                let question = ExpnFormat::CompilerDesugaring(Symbol::intern("?"));
+                inner.set_expn_info(expn_info(question, 30));
+
+                assert_eq!(inner.parent(), middle);
+                assert_eq!(middle.parent(), outer);
+                assert_eq!(outer.parent(), Mark::root());
+                assert_eq!(Mark::root().parent(), Mark::root());
+                assert!(inner.is_descendant_of(outer));
+                assert!(inner.is_descendant_of(inner));
+                assert!(sibling.is_descendant_of(Mark::root()));
+                assert!(!outer.is_descendant_of(inner));
+                assert!(!inner.is_descendant_of(sibling));
+
+                let ctxt = SyntaxContext::empty()
+                    .apply_mark(outer)
+                    .apply_mark(middle)
+                    .apply_mark(inner);
+                assert_eq!(ctxt.marks(), [inner, middle, outer]);
+                assert_eq!(SyntaxContext::empty().marks(), []);
+                let mut removed = ctxt;
+                assert_eq!(removed.remove_mark(), inner);
+                assert_eq!(removed.marks(), [middle, outer]);
+
+                let dump = [
+                    "#0 root",
+                    "  #1 vec! at @10",
+                    "    #2 #[derive] at @20",
+                    "      #4 desugaring of `?` at @30",
+                    "    #3",
+                ];
+                let dump_with_offsets = with_span_resolver(&Offsets, debug_dump);
+                assert_eq!(dump_with_offsets, dump.join("\n") + "\n");
+            })
+        };
+    }
+}
@@ -6,9 +6,12 @@
 
 #![deny(warnings)]
 
+use std::borrow::Cow;
 use std::cell::{Cell, RefCell};
 use std::cmp;
-use std::ops::{Add, Sub};
+use std::mem;
+use std::ops::{Add, AddAssign, Range, Sub, SubAssign};
+use std::path::PathBuf;
 use std::rc::Rc;
 
 use std::fmt;
@@ -17,12 +20,160 @@ use serde::de::{self, Deserializer, SeqAccess, Unexpected, Visitor};
 use serde::ser::{SerializeSeq, Serializer};
 use serde::{Deserialize, Serialize};
 
+pub mod globals;
+pub use crate::globals::{Globals, GLOBALS};
+
 pub mod hygiene;
 pub use crate::hygiene::{ExpnFormat, ExpnInfo, NameAndSpan, SyntaxContext};
 
+pub mod position_map;
+pub use crate::position_map::PositionMap;
+
 pub mod symbol;
 
-pub type FileName = String;
+/// The name of the source of a `FileMap`.
+///
+/// Names are displayed the way they used to be spelled as plain strings,
+/// with sources that aren't files between angle brackets, e.g. `<anon>`.
+/// Converting such a string with `FileName::from` gives back the same name,
+/// which is also how names are serialized. The exception is a file on disk
+/// named like `<weird>`, which is serialized as its path after a `\0`, so
+/// that it stays real.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FileName {
+    /// A file on disk.
+    Real(PathBuf),
+    /// Source that doesn't come from anywhere in particular, numbered to
+    /// tell such sources apart. Displayed as `<anon>` for 0, and as
+    /// `<anon-n>` for any other `n`.
+    Anon(u64),
+    /// Source produced while expanding the macro `name`. Displayed as
+    /// `<name macros>`.
+    MacroExpansion { name: String },
+    /// Any other source, displayed between angle brackets.
+    Custom(String),
+}
+
+impl FileName {
+    /// Whether this names a file on disk, as opposed to the conventionally
+    /// bracketed names of other sources.
+    pub fn is_real(&self) -> bool {
+        match *self {
+            FileName::Real(_) => true,
+            FileName::Anon(_) | FileName::MacroExpansion { .. } | FileName::Custom(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for FileName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileName::Real(ref path) => write!(f, "{}", path.display()),
+            FileName::Anon(0) => write!(f, "<anon>"),
+            FileName::Anon(n) => write!(f, "<anon-{}>", n),
+            FileName::MacroExpansion { ref name } => write!(f, "<{} macros>", name),
+            FileName::Custom(ref s) => write!(f, "<{}>", s),
+        }
+    }
+}
+
+impl From<String> for FileName {
+    fn from(name: String) -> FileName {
+        if !(name.starts_with('<') && name.ends_with('>') && name.len() >= 2) {
+            return FileName::Real(PathBuf::from(name));
+        }
+        let inner = &name[1..name.len() - 1];
+        let anon = match inner.strip_prefix("anon-") {
+            // Only the spelling `Display` gives, so that the name round trips.
+            Some(n) if !n.starts_with(['0', '+']) => n.parse().ok(),
+            _ => None,
+        };
+        if inner == "anon" {
+            FileName::Anon(0)
+        } else if let Some(n) = anon {
+            FileName::Anon(n)
+        } else if let Some(macro_name) = inner.strip_suffix(" macros") {
+            FileName::MacroExpansion {
+                name: macro_name.to_string(),
+            }
+        } else {
+            FileName::Custom(inner.to_string())
+        }
+    }
+}
+
+impl<'a> From<&'a str> for FileName {
+    fn from(name: &'a str) -> FileName {
+        FileName::from(name.to_string())
+    }
+}
+
+impl From<PathBuf> for FileName {
+    fn from(path: PathBuf) -> FileName {
+        FileName::Real(path)
+    }
+}
+
+/// Non-real names become the path of their display string, as they did when
+/// file names were plain strings.
+impl From<FileName> for PathBuf {
+    fn from(name: FileName) -> PathBuf {
+        match name {
+            FileName::Real(path) => path,
+            name => PathBuf::from(name.to_string()),
+        }
+    }
+}
+
+/// Compares against the displayed name.
+impl PartialEq<str> for FileName {
+    fn eq(&self, other: &str) -> bool {
+        let bracketed = || other.strip_prefix('<').and_then(|o| o.strip_suffix('>'));
+        match *self {
+            FileName::Real(ref path) => path.to_str() == Some(other),
+            FileName::Anon(0) => other == "<anon>",
+            FileName::Anon(n) => match bracketed().and_then(|o| o.strip_prefix("anon-")) {
+                Some(o) => !o.starts_with(['0', '+']) && o.parse() == Ok(n),
+                None => false,
+            },
+            FileName::MacroExpansion { ref name } => {
+                bracketed().and_then(|o| o.strip_suffix(" macros")) == Some(name)
+            }
+            FileName::Custom(ref s) => bracketed() == Some(s),
+        }
+    }
+}
+
+impl<'a> PartialEq<&'a str> for FileName {
+    fn eq(&self, other: &&'a str) -> bool {
+        *self == **other
+    }
+}
+
+/// Marks the serialized path of a real file whose name reads back as
+/// another kind of name. Neither paths nor displayed names start with it.
+const REAL_FILE_NAME_MARKER: char = '\0';
+
+impl Serialize for FileName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            FileName::Real(ref path) if !FileName::from(self.to_string()).is_real() => {
+                serializer.collect_str(&format_args!("{}{}", REAL_FILE_NAME_MARKER, path.display()))
+            }
+            _ => serializer.collect_str(self),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FileName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<FileName, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(match name.strip_prefix(REAL_FILE_NAME_MARKER) {
+            Some(path) => FileName::Real(PathBuf::from(path)),
+            None => FileName::from(name),
+        })
+    }
+}
 
 /// Spans represent a region of code, used for error reporting. Positions in spans
 /// are *absolute* positions from the beginning of the codemap, not positions
@@ -51,29 +202,48 @@ pub struct Span {
 #[derive(Clone, Debug, Default, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MultiSpan {
     primary_spans: Vec<Span>,
-    span_labels: Vec<(Span, String)>,
+    span_labels: Vec<(Span, Vec<StringPart>)>,
+}
+
+/// A piece of a label or message, either plain or highlighted when
+/// rendered.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum StringPart {
+    Normal(String),
+    Highlighted(String),
+}
+
+impl StringPart {
+    pub fn content(&self) -> String {
+        match self {
+            &StringPart::Normal(ref s) | &StringPart::Highlighted(ref s) => s.to_owned(),
+        }
+    }
 }
 
 impl Span {
     /// Returns a new span representing just the end-point of this span
     pub fn end_point(self) -> Span {
-        let lo = cmp::max(self.hi.0 - 1, self.lo.0);
         Span {
-            lo: BytePos(lo),
+            lo: cmp::max(self.hi.saturating_sub(1), self.lo),
             ..self
         }
     }
 
-    /// Returns a new span representing the next character after the end-point of this span
-    pub fn next_point(self) -> Span {
-        let lo = cmp::max(self.hi.0, self.lo.0 + 1);
+    /// Returns an empty span at the start of this span
+    pub fn shrink_to_lo(self) -> Span {
         Span {
-            lo: BytePos(lo),
-            hi: BytePos(lo),
+            hi: self.lo,
             ..self
         }
     }
 
+    /// Returns a new span representing the next character after the end-point of this span
+    pub fn next_point(self) -> Span {
+        let lo = cmp::max(self.hi, self.lo.saturating_add(1));
+        Span { lo, hi: lo, ..self }
+    }
+
     /// Returns `self` if `self` is not the dummy span, and `other` otherwise.
     pub fn substitute_dummy(self, other: Span) -> Span {
         if self.source_equal(&DUMMY_SP) {
@@ -142,6 +312,28 @@ impl Span {
         }
     }
 
+    /// Check if a span is "internal" to a macro in which `unsafe`
+    /// can be used without triggering the `unsafe_code` lint
+    /// (that is, a macro marked with `#[allow_internal_unsafe]`).
+    pub fn allows_unsafe(&self) -> bool {
+        match self.ctxt.outer().expn_info() {
+            Some(info) => info.callee.allow_internal_unsafe,
+            None => false,
+        }
+    }
+
+    /// Return the span of the code produced by the innermost expansion this
+    /// span came from that has recorded one, walking out through call sites.
+    pub fn expansion_output_span(mut self) -> Option<Span> {
+        while let Some(info) = self.ctxt.outer().expn_info() {
+            if info.output_span.is_some() {
+                return info.output_span;
+            }
+            self = info.call_site;
+        }
+        None
+    }
+
     pub fn macro_backtrace(mut self) -> Vec<MacroBacktrace> {
         let mut prev_span = DUMMY_SP;
         let mut result = vec![];
@@ -160,6 +352,7 @@ impl Span {
                     call_site: info.call_site,
                     macro_decl_name,
                     def_site_span,
+                    output_span: info.output_span,
                 });
             }
 
@@ -214,8 +407,14 @@ pub struct SpanLabel {
 
     /// What label should we attach to this span (if any)?
     pub label: Option<String>,
+
+    /// The parts `label` is made of, for renderers that highlight some of
+    /// them. Empty if there is no label.
+    pub label_parts: Vec<StringPart>,
 }
 
+/// Formats `span` as its byte positions, which is how `{:?}` prints spans
+/// outside of `with_span_resolver`.
 fn default_span_debug(span: Span, f: &mut fmt::Formatter) -> fmt::Result {
     write!(
         f,
@@ -224,9 +423,66 @@ fn default_span_debug(span: Span, f: &mut fmt::Formatter) -> fmt::Result {
     )
 }
 
+/// Something that can describe where a span points, such as a `CodeMap`.
+pub trait SpanResolver {
+    /// The location of `sp`, as `file:line:col: line:col`.
+    fn span_to_string(&self, sp: Span) -> String;
+}
+
+struct SpanLocation<'a, R: ?Sized> {
+    span: Span,
+    resolver: &'a R,
+}
+
+impl<'a, R: SpanResolver + ?Sized> fmt::Display for SpanLocation<'a, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.resolver.span_to_string(self.span))?;
+        if self.span.ctxt != NO_EXPANSION {
+            write!(f, " ({:?})", self.span.ctxt)?;
+        }
+        Ok(())
+    }
+}
+
+impl Span {
+    /// Displays the span as its location according to `resolver`, followed
+    /// by its syntax context when it comes from a macro expansion.
+    pub fn debug_with<R: SpanResolver + ?Sized>(self, resolver: &R) -> impl fmt::Display + '_ {
+        SpanLocation {
+            span: self,
+            resolver,
+        }
+    }
+}
+
+/// Runs `f` with `{:?}` printing spans as their location according to
+/// `resolver` rather than as byte positions. The previous resolver is restored
+/// when `f` returns or panics, so scopes for different resolvers may nest.
+pub fn with_span_resolver<R, F: FnOnce() -> R>(resolver: &dyn SpanResolver, f: F) -> R {
+    struct Restore(Option<*const dyn SpanResolver>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            GLOBALS.with(|globals| globals.span_resolver.set(self.0));
+        }
+    }
+
+    // The lifetime is erased here; the resolver is only used while `f` runs,
+    // during which it stays borrowed.
+    let resolver: *const (dyn SpanResolver + 'static) =
+        unsafe { mem::transmute(resolver as *const dyn SpanResolver) };
+    let _restore = Restore(GLOBALS.with(|globals| globals.span_resolver.replace(Some(resolver))));
+    f()
+}
+
 impl fmt::Debug for Span {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        SPAN_DEBUG.with(|span_debug| span_debug.get()(*self, f))
+        match GLOBALS.with(|globals| globals.span_resolver.get()) {
+            // `with_span_resolver` keeps the resolver borrowed for as long as
+            // it is installed.
+            Some(resolver) => fmt::Display::fmt(&self.debug_with(unsafe { &*resolver }), f),
+            None => default_span_debug(*self, f),
+        }
     }
 }
 
@@ -252,6 +508,13 @@ impl MultiSpan {
     }
 
     pub fn push_span_label(&mut self, span: Span, label: String) {
+        self.span_labels
+            .push((span, vec![StringPart::Normal(label)]));
+    }
+
+    /// Like `push_span_label`, but some parts of the label may be
+    /// highlighted.
+    pub fn push_styled_span_label(&mut self, span: Span, label: Vec<StringPart>) {
         self.span_labels.push((span, label));
     }
 
@@ -297,7 +560,8 @@ impl MultiSpan {
             span_labels.push(SpanLabel {
                 span,
                 is_primary: is_primary(span),
-                label: Some(label.clone()),
+                label: Some(label.iter().map(StringPart::content).collect()),
+                label_parts: label.clone(),
             });
         }
 
@@ -307,6 +571,7 @@ impl MultiSpan {
                     span,
                     is_primary: true,
                     label: None,
+                    label_parts: vec![],
                 });
             }
         }
@@ -335,9 +600,7 @@ pub struct MultiByteChar {
 /// A single source in the CodeMap.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct FileMap {
-    /// The name of the file that the source came from, source that doesn't
-    /// originate from files has names between angle brackets by convention,
-    /// e.g. `<anon>`
+    /// The name of the file that the source came from
     pub name: FileName,
     /// True if the `name` field above has been modified by -Zremap-path-prefix
     pub name_was_remapped: bool,
@@ -347,10 +610,17 @@ pub struct FileMap {
     /// The complete source code
     #[serde(skip)]
     pub src: Option<Rc<String>>,
+    /// The source code of a filemap created without it, if it has since been
+    /// attached with `add_external_src`.
+    #[serde(skip)]
+    pub external_src: RefCell<ExternalSource>,
     /// The start position of this source in the CodeMap
     pub start_pos: BytePos,
     /// The end position of this source in the CodeMap
     pub end_pos: BytePos,
+    /// A hash of the source code, after the removal of any byte order mark,
+    /// as computed by `hash_source`.
+    pub src_hash: u128,
     /// Locations of lines beginnings in the source code
     #[serde(
         serialize_with = "serialize_lines",
@@ -359,6 +629,30 @@ pub struct FileMap {
     pub lines: RefCell<Vec<BytePos>>,
     /// Locations of multi-byte characters in the source code
     pub multibyte_chars: RefCell<Vec<MultiByteChar>>,
+    /// If `src` is a normalized copy of the file's text, maps positions in it
+    /// to offsets in the original text.
+    #[serde(skip)]
+    pub position_map: Option<Rc<PositionMap>>,
+    /// The original text of the file, if `src` is a normalized copy of it.
+    /// Lines and columns, and the lines returned by `get_line`, refer to it.
+    #[serde(skip)]
+    pub original_src: Option<Rc<String>>,
+    /// Set once the filemap has been replaced by `CodeMap::replace_filemap`,
+    /// after which spans into it no longer describe the file's contents.
+    #[serde(skip)]
+    pub stale: Cell<bool>,
+}
+
+/// The state of the source code attached to a `FileMap` after its creation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ExternalSource {
+    /// No attempt has been made to attach the source.
+    #[default]
+    Unneeded,
+    /// The source was attached.
+    Present(Rc<String>),
+    /// The source could not be obtained, or did not match the filemap.
+    AbsentErr,
 }
 
 fn invalid_crate() -> u32 {
@@ -368,6 +662,20 @@ fn invalid_crate() -> u32 {
     ::std::u32::MAX - 1
 }
 
+/// The version of the `lines` encoding written by `serialize_lines`.
+///
+/// The encoding is a sequence of:
+///
+/// - this version;
+/// - the number of lines, as a `u32`;
+/// - if there are any lines, the number of bytes used per diff (1, 2 or 4),
+///   the first line start, and the difference between each following line
+///   start and the one before it, in that many bytes.
+///
+/// Versions start at `0x80` so that they can't be mistaken for the bytes per
+/// diff that led the unversioned encoding.
+const LINES_FORMAT_VERSION: u8 = 0x81;
+
 fn serialize_lines<S>(lines: &RefCell<Vec<BytePos>>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -375,9 +683,14 @@ where
     let lines = lines.borrow();
 
     if lines.is_empty() {
-        serializer.serialize_seq(Some(0))?.end()
+        let mut seq = serializer.serialize_seq(Some(2))?;
+        seq.serialize_element(&LINES_FORMAT_VERSION)?;
+        seq.serialize_element(&0u32)?;
+        seq.end()
     } else {
-        let mut seq = serializer.serialize_seq(Some(lines.len() + 1))?;
+        let mut seq = serializer.serialize_seq(Some(lines.len() + 3))?;
+        seq.serialize_element(&LINES_FORMAT_VERSION)?;
+        seq.serialize_element(&(lines.len() as u32))?;
 
         // In order to preserve some space, we exploit the fact that
         // the lines list is sorted and individual lines are
@@ -407,7 +720,7 @@ where
         // Encode the first element.
         seq.serialize_element(&lines[0])?;
 
-        let diff_iter = (&lines[..]).windows(2).map(|w| (w[1] - w[0]));
+        let diff_iter = (&lines[..]).windows(2).map(|w| w[1] - w[0]);
 
         match bytes_per_diff {
             1 => {
@@ -438,6 +751,51 @@ where
 {
     struct LinesVisitor;
 
+    impl LinesVisitor {
+        fn next_diff<'de, A>(
+            &self,
+            seq: &mut A,
+            bytes_per_diff: u8,
+        ) -> Result<Option<u32>, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            match bytes_per_diff {
+                1 => Ok(seq.next_element::<u8>()?.map(|u| u as u32)),
+                2 => Ok(seq.next_element::<u16>()?.map(|u| u as u32)),
+                4 => seq.next_element::<u32>(),
+                _ => Err(de::Error::invalid_value(
+                    Unexpected::Unsigned(bytes_per_diff as u64),
+                    &"bytes per diff",
+                )),
+            }
+        }
+
+        /// Reads the unversioned encoding, which has no line count and ends
+        /// wherever the diffs do.
+        // FIXME: remove once caches written before `LINES_FORMAT_VERSION`
+        // are no longer read.
+        fn visit_unversioned<'de, A>(
+            &self,
+            mut seq: A,
+            bytes_per_diff: u8,
+        ) -> Result<Vec<BytePos>, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut lines = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            let mut line_start: BytePos = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(1, self))?;
+            lines.push(line_start);
+            while let Some(diff) = self.next_diff(&mut seq, bytes_per_diff)? {
+                line_start = line_start + BytePos(diff);
+                lines.push(line_start);
+            }
+            Ok(lines)
+        }
+    }
+
     impl<'de> Visitor<'de> for LinesVisitor {
         type Value = RefCell<Vec<BytePos>>;
 
@@ -449,30 +807,49 @@ where
         where
             A: SeqAccess<'de>,
         {
-            let mut lines = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            let version = match seq.next_element::<u8>()? {
+                Some(version) => version,
+                // The unversioned encoding of no lines.
+                None => return Ok(RefCell::new(vec![])),
+            };
+            match version {
+                LINES_FORMAT_VERSION => {}
+                1 | 2 | 4 => return Ok(RefCell::new(self.visit_unversioned(seq, version)?)),
+                _ => {
+                    return Err(de::Error::invalid_value(
+                        Unexpected::Unsigned(version as u64),
+                        &"a known lines format version",
+                    ));
+                }
+            }
 
-            // Read the number of bytes used per diff.
-            if let Some(bytes_per_diff) = seq.next_element::<u8>()? {
-                // Read the first element.
-                let mut line_start: BytePos = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                lines.push(line_start);
+            let count: u32 = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+            let mut lines = Vec::with_capacity(count as usize);
+            if count == 0 {
+                return Ok(RefCell::new(lines));
+            }
 
-                while let Some(diff) = match bytes_per_diff {
-                    1 => seq.next_element::<u8>()?.map(|u| u as u32),
-                    2 => seq.next_element::<u16>()?.map(|u| u as u32),
-                    4 => seq.next_element::<u32>()?,
-                    _ => {
-                        return Err(de::Error::invalid_value(
-                            Unexpected::Unsigned(bytes_per_diff as u64),
-                            &"bytes per diff",
-                        ));
-                    }
-                } {
-                    line_start = line_start + BytePos(diff);
-                    lines.push(line_start);
-                }
+            let bytes_per_diff: u8 = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+            if ![1, 2, 4].contains(&bytes_per_diff) {
+                return Err(de::Error::invalid_value(
+                    Unexpected::Unsigned(bytes_per_diff as u64),
+                    &"bytes per diff",
+                ));
+            }
+            let mut line_start: BytePos = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(3, &self))?;
+            lines.push(line_start);
+            while lines.len() < count as usize {
+                let diff = self
+                    .next_diff(&mut seq, bytes_per_diff)?
+                    .ok_or_else(|| de::Error::invalid_length(lines.len() + 3, &self))?;
+                line_start = line_start + BytePos(diff);
+                lines.push(line_start);
             }
 
             Ok(RefCell::new(lines))
@@ -482,6 +859,16 @@ where
     deserializer.deserialize_seq(LinesVisitor)
 }
 
+/// A 128-bit FNV-1a hash of `src`, used to tell whether the source of a
+/// `FileMap` has changed. It is not suitable for cryptographic use.
+pub fn hash_source(src: &str) -> u128 {
+    const OFFSET_BASIS: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+    const PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+    src.bytes().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u128::from(byte)).wrapping_mul(PRIME)
+    })
+}
+
 impl fmt::Debug for FileMap {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         write!(fmt, "FileMap({})", self.name)
@@ -508,25 +895,79 @@ impl FileMap {
 
     /// get a line from the list of pre-computed line-beginnings.
     /// line-number here is 0-based.
-    pub fn get_line(&self, line_number: usize) -> Option<&str> {
-        match self.src {
-            Some(ref src) => {
-                let lines = self.lines.borrow();
-                lines.get(line_number).map(|&line| {
-                    let begin: BytePos = line - self.start_pos;
-                    let begin = begin.to_usize();
-                    // We can't use `lines.get(line_number+1)` because we might
-                    // be parsing when we call this function and thus the current
-                    // line is the last one we have line info for.
-                    let slice = &src[begin..];
-                    match slice.find('\n') {
-                        Some(e) => &slice[..e],
-                        None => slice,
-                    }
-                })
+    pub fn get_line(&self, line_number: usize) -> Option<Cow<'_, str>> {
+        fn get_until_newline(src: &str, begin: usize) -> &str {
+            // We can't use `lines.get(line_number+1)` because we might
+            // be parsing when we call this function and thus the current
+            // line is the last one we have line info for.
+            let slice = &src[begin..];
+            match slice.find('\n') {
+                Some(e) => &slice[..e],
+                None => slice,
             }
-            None => None,
         }
+
+        if let Some(ref src) = self.original_src {
+            let begin = self.original_line_start(line_number)?;
+            let line = get_until_newline(src, begin);
+            return Some(Cow::Borrowed(line.strip_suffix('\r').unwrap_or(line)));
+        }
+
+        let begin = {
+            let lines = self.lines.borrow();
+            let line = *lines.get(line_number)?;
+            (line - self.start_pos).to_usize()
+        };
+        if let Some(ref src) = self.src {
+            Some(Cow::Borrowed(get_until_newline(src, begin)))
+        } else if let ExternalSource::Present(ref src) = *self.external_src.borrow() {
+            Some(Cow::Owned(get_until_newline(src, begin).to_owned()))
+        } else {
+            None
+        }
+    }
+
+    /// Attach the source code returned by `get_src` to a filemap that was
+    /// created without it, such as an imported one. `get_src` is called at
+    /// most once per filemap; source whose length or hash does not match the
+    /// filemap is rejected. Returns whether the filemap's source is now
+    /// available.
+    pub fn add_external_src<F>(&self, get_src: F) -> bool
+    where
+        F: FnOnce() -> Option<String>,
+    {
+        if self.src.is_some() {
+            return true;
+        }
+        let mut external_src = self.external_src.borrow_mut();
+        if *external_src == ExternalSource::Unneeded {
+            *external_src = match get_src() {
+                Some(mut src) => {
+                    // Remove utf-8 BOM if any, as `CodeMap::new_filemap` does.
+                    if src.starts_with('\u{feff}') {
+                        src.drain(..3);
+                    }
+                    if src.len() == self.byte_length() as usize
+                        && hash_source(&src) == self.src_hash
+                    {
+                        ExternalSource::Present(Rc::new(src))
+                    } else {
+                        ExternalSource::AbsentErr
+                    }
+                }
+                None => ExternalSource::AbsentErr,
+            };
+        }
+        matches!(*external_src, ExternalSource::Present(_))
+    }
+
+    /// Whether this filemap has been replaced by newer contents of the file.
+    pub fn is_stale(&self) -> bool {
+        self.stale.get()
+    }
+
+    pub fn mark_stale(&self) {
+        self.stale.set(true);
     }
 
     pub fn record_multibyte_char(&self, pos: BytePos, bytes: usize) {
@@ -535,8 +976,76 @@ impl FileMap {
         self.multibyte_chars.borrow_mut().push(mbc);
     }
 
+    /// EFFECT: register a batch of start-of-line offsets, exactly as if
+    /// `next_line` had been called for each of them in order.
+    pub fn next_lines(&self, positions: &[BytePos]) {
+        let mut lines = self.lines.borrow_mut();
+        let mut prev = lines.last().cloned();
+        for &pos in positions {
+            assert!(prev.is_none_or(|prev| prev < pos));
+            prev = Some(pos);
+        }
+        lines.extend_from_slice(positions);
+    }
+
+    /// Register a batch of multibyte chars, exactly as if
+    /// `record_multibyte_char` had been called for each of them in order.
+    pub fn record_multibyte_chars(&self, chars: &[MultiByteChar]) {
+        let mut multibyte_chars = self.multibyte_chars.borrow_mut();
+        let mut prev = multibyte_chars.last().map(|mbc| mbc.pos);
+        for mbc in chars {
+            assert!(mbc.bytes >= 2 && mbc.bytes <= 4);
+            assert!(prev.is_none_or(|prev| prev < mbc.pos));
+            prev = Some(mbc.pos);
+        }
+        multibyte_chars.extend_from_slice(chars);
+    }
+
+    /// The byte offset in the original text of the file of `pos`, which is
+    /// a position in this filemap.
+    pub fn original_offset(&self, pos: BytePos) -> usize {
+        let offset = (pos - self.start_pos).to_usize();
+        match self.position_map {
+            Some(ref map) => map.to_original(offset),
+            None => offset,
+        }
+    }
+
+    /// The byte offset in the original text of the file of the start of line
+    /// `line_number`, counted from 0.
+    fn original_line_start(&self, line_number: usize) -> Option<usize> {
+        let line = *self.lines.borrow().get(line_number)?;
+        if line == self.start_pos {
+            return Some(0);
+        }
+        // Lines start after a line break, which normalization keeps, while
+        // the text removed at the start of a line maps to its end.
+        Some(self.original_offset(line - BytePos(1)) + 1)
+    }
+
+    /// The column of `pos` in the original text of the file, counted in
+    /// chars from the start of line `line_number`, or `None` if `src` is
+    /// not a normalized copy of the file.
+    pub fn original_col(&self, line_number: usize, pos: BytePos) -> Option<CharPos> {
+        let src = self.original_src.as_ref()?;
+        let begin = self.original_line_start(line_number)?;
+        let end = self.original_offset(pos);
+        Some(CharPos(src[begin..end].chars().count()))
+    }
+
+    /// The position in this filemap of byte offset `offset` in the original
+    /// text of the file, or `None` if normalization removed it.
+    pub fn normalized_pos(&self, offset: usize) -> Option<BytePos> {
+        let offset = match self.position_map {
+            Some(ref map) => map.to_normalized(offset)?,
+            None => offset,
+        };
+        Some(self.start_pos + BytePos::from_usize(offset))
+    }
+
+    #[deprecated(note = "use `name.is_real()`")]
     pub fn is_real_file(&self) -> bool {
-        !(self.name.starts_with('<') && self.name.ends_with('>'))
+        self.name.is_real()
     }
 
     pub fn is_imported(&self) -> bool {
@@ -633,6 +1142,59 @@ impl Sub for BytePos {
     }
 }
 
+impl BytePos {
+    /// `self + rhs`, or `None` on overflow.
+    pub fn checked_add(self, rhs: u32) -> Option<BytePos> {
+        self.0.checked_add(rhs).map(BytePos)
+    }
+
+    /// `self - rhs`, or `None` on underflow.
+    pub fn checked_sub(self, rhs: u32) -> Option<BytePos> {
+        self.0.checked_sub(rhs).map(BytePos)
+    }
+
+    pub fn saturating_add(self, rhs: u32) -> BytePos {
+        BytePos(self.0.saturating_add(rhs))
+    }
+
+    pub fn saturating_sub(self, rhs: u32) -> BytePos {
+        BytePos(self.0.saturating_sub(rhs))
+    }
+
+    /// The positions from `self` up to, but not including, `end`.
+    pub fn range_to(self, end: BytePos) -> impl Iterator<Item = BytePos> {
+        (self.0..end.0).map(BytePos)
+    }
+}
+
+impl Add<u32> for BytePos {
+    type Output = BytePos;
+
+    fn add(self, rhs: u32) -> BytePos {
+        BytePos(self.0 + rhs)
+    }
+}
+
+impl Sub<u32> for BytePos {
+    type Output = BytePos;
+
+    fn sub(self, rhs: u32) -> BytePos {
+        BytePos(self.0 - rhs)
+    }
+}
+
+impl AddAssign<u32> for BytePos {
+    fn add_assign(&mut self, rhs: u32) {
+        self.0 += rhs;
+    }
+}
+
+impl SubAssign<u32> for BytePos {
+    fn sub_assign(&mut self, rhs: u32) {
+        self.0 -= rhs;
+    }
+}
+
 impl Pos for CharPos {
     fn from_usize(n: usize) -> CharPos {
         CharPos(n)
@@ -659,6 +1221,54 @@ impl Sub for CharPos {
     }
 }
 
+impl CharPos {
+    /// `self + rhs`, or `None` on overflow.
+    pub fn checked_add(self, rhs: usize) -> Option<CharPos> {
+        self.0.checked_add(rhs).map(CharPos)
+    }
+
+    /// `self - rhs`, or `None` on underflow.
+    pub fn checked_sub(self, rhs: usize) -> Option<CharPos> {
+        self.0.checked_sub(rhs).map(CharPos)
+    }
+
+    pub fn saturating_add(self, rhs: usize) -> CharPos {
+        CharPos(self.0.saturating_add(rhs))
+    }
+
+    pub fn saturating_sub(self, rhs: usize) -> CharPos {
+        CharPos(self.0.saturating_sub(rhs))
+    }
+}
+
+impl Add<usize> for CharPos {
+    type Output = CharPos;
+
+    fn add(self, rhs: usize) -> CharPos {
+        CharPos(self.0 + rhs)
+    }
+}
+
+impl Sub<usize> for CharPos {
+    type Output = CharPos;
+
+    fn sub(self, rhs: usize) -> CharPos {
+        CharPos(self.0 - rhs)
+    }
+}
+
+impl AddAssign<usize> for CharPos {
+    fn add_assign(&mut self, rhs: usize) {
+        self.0 += rhs;
+    }
+}
+
+impl SubAssign<usize> for CharPos {
+    fn sub_assign(&mut self, rhs: usize) {
+        self.0 -= rhs;
+    }
+}
+
 // _____________________________________________________________________________
 // Loc, LocWithOpt, FileMapAndLine, FileMapAndBytePos
 //
@@ -712,10 +1322,117 @@ pub struct LineInfo {
 pub struct FileLines {
     pub file: Rc<FileMap>,
     pub lines: Vec<LineInfo>,
+    /// Whether `lines` stops before the end of the span, because it was
+    /// limited to fewer lines than the span covers.
+    pub truncated: bool,
 }
 
-thread_local!(pub static SPAN_DEBUG: Cell<fn(Span, &mut fmt::Formatter) -> fmt::Result> =
-                Cell::new(default_span_debug));
+/// Lines of a file to display around one or more spans, as in the code
+/// frame of a diagnostic, with the columns each line has highlighted.
+#[derive(Clone, Debug)]
+pub struct CodeRegion {
+    pub file: Rc<FileMap>,
+    /// The number of the first line, starting from 1.
+    pub first_line: usize,
+    /// The text of each line, without its newline.
+    pub lines: Vec<String>,
+    /// For each line, the columns covered by the spans, as sorted ranges of
+    /// chars starting from 0. Lines shown for context have none.
+    pub highlights: Vec<Vec<Range<usize>>>,
+}
+
+impl CodeRegion {
+    /// Lines `first_line` to `last_line` inclusive of `file`, numbered from
+    /// 1, with nothing highlighted. Lines whose source is not available are
+    /// empty.
+    pub fn new(file: Rc<FileMap>, first_line: usize, last_line: usize) -> CodeRegion {
+        assert!(0 < first_line && first_line <= last_line);
+        let lines: Vec<String> = (first_line..last_line + 1)
+            .map(|line| {
+                file.get_line(line - 1)
+                    .map_or_else(String::new, Cow::into_owned)
+            })
+            .collect();
+        CodeRegion {
+            file,
+            first_line,
+            highlights: vec![Vec::new(); lines.len()],
+            lines,
+        }
+    }
+
+    /// The number of the last line, starting from 1.
+    pub fn last_line(&self) -> usize {
+        self.first_line + self.lines.len() - 1
+    }
+
+    pub fn contains_line(&self, line: usize) -> bool {
+        self.first_line <= line && line <= self.last_line()
+    }
+
+    /// The text of line number `line`, which must be in the region.
+    pub fn line(&self, line: usize) -> &str {
+        &self.lines[line - self.first_line]
+    }
+
+    /// Coalesces the regions of the same file whose lines overlap or have at
+    /// most `max_gap` lines between them, including the lines in between.
+    /// The result is ordered by file, in order of first appearance in
+    /// `regions`, then by line.
+    pub fn merge(regions: Vec<CodeRegion>, max_gap: usize) -> Vec<CodeRegion> {
+        let mut files: Vec<BytePos> = Vec::new();
+        for region in &regions {
+            if !files.contains(&region.file.start_pos) {
+                files.push(region.file.start_pos);
+            }
+        }
+        let mut regions = regions;
+        regions.sort_by_key(|region| {
+            let file = files
+                .iter()
+                .position(|&start_pos| start_pos == region.file.start_pos);
+            (file, region.first_line)
+        });
+
+        let mut merged: Vec<CodeRegion> = Vec::with_capacity(regions.len());
+        for region in regions {
+            match merged.last_mut() {
+                Some(last)
+                    if last.file.start_pos == region.file.start_pos
+                        && region.first_line <= last.last_line() + max_gap + 1 =>
+                {
+                    last.absorb(region)
+                }
+                _ => merged.push(region),
+            }
+        }
+        merged
+    }
+
+    /// Adds the lines and highlights of `other`, which does not start before
+    /// this region, filling any gap between the two with context lines.
+    fn absorb(&mut self, other: CodeRegion) {
+        for line in self.last_line() + 1..other.first_line {
+            let text = self.file.get_line(line - 1);
+            self.lines
+                .push(text.map_or_else(String::new, Cow::into_owned));
+            self.highlights.push(Vec::new());
+        }
+        let first_line = other.first_line;
+        for (i, (text, highlights)) in other.lines.into_iter().zip(other.highlights).enumerate() {
+            let line = first_line + i;
+            if line > self.last_line() {
+                self.lines.push(text);
+                self.highlights.push(highlights);
+            } else {
+                let existing = &mut self.highlights[line - self.first_line];
+                existing.extend(highlights);
+                existing.sort_by_key(|range| (range.start, range.end));
+                existing.dedup();
+            }
+        }
+    }
+}
 
 pub struct MacroBacktrace {
     /// span where macro was applied to generate this code
@@ -726,6 +1443,9 @@ pub struct MacroBacktrace {
 
     /// span where macro was defined (if known)
     pub def_site_span: Option<Span>,
+
+    /// span of the code the macro produced (if known)
+    pub output_span: Option<Span>,
 }
 
 // _____________________________________________________________________________
@@ -738,6 +1458,10 @@ pub type FileLinesResult = Result<FileLines, SpanLinesError>;
 pub enum SpanLinesError {
     IllFormedSpan(Span),
     DistinctSources(DistinctSources),
+    /// The span points into a filemap that has since been replaced.
+    StaleFileMap {
+        filename: FileName,
+    },
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -745,18 +1469,24 @@ pub enum SpanSnippetError {
     IllFormedSpan(Span),
     DistinctSources(DistinctSources),
     MalformedForCodemap(MalformedCodemapPositions),
-    SourceNotAvailable { filename: String },
+    SourceNotAvailable {
+        filename: FileName,
+    },
+    /// The span points into a filemap that has since been replaced.
+    StaleFileMap {
+        filename: FileName,
+    },
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct DistinctSources {
-    pub begin: (String, BytePos),
-    pub end: (String, BytePos),
+    pub begin: (FileName, BytePos),
+    pub end: (FileName, BytePos),
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct MalformedCodemapPositions {
-    pub name: String,
+    pub name: FileName,
     pub source_len: usize,
     pub begin_pos: BytePos,
     pub end_pos: BytePos,
@@ -774,7 +1504,15 @@ fn lookup_line(lines: &[BytePos], pos: BytePos) -> isize {
 
 #[cfg(test)]
 mod tests {
-    use super::{lookup_line, BytePos};
+    use super::hygiene::{Mark, SyntaxContext};
+    use super::symbol::Symbol;
+    use super::{
+        lookup_line, BytePos, CharPos, ExpnFormat, ExpnInfo, FileName, NameAndSpan, Span, DUMMY_SP,
+        NO_EXPANSION,
+    };
+    use serde::{Deserialize, Serialize};
+    use std::cell::RefCell;
+    use std::path::PathBuf;
 
     #[test]
     fn test_lookup_line() {
@@ -791,4 +1529,261 @@ mod tests {
         assert_eq!(lookup_line(lines, BytePos(28)), 2);
         assert_eq!(lookup_line(lines, BytePos(29)), 2);
     }
+
+    #[test]
+    fn byte_pos_arithmetic() {
+        let mut pos = BytePos(u32::MAX - 1);
+        assert_eq!(pos + 1, BytePos(u32::MAX));
+        assert_eq!(pos.checked_add(1), Some(BytePos(u32::MAX)));
+        assert_eq!(pos.checked_add(2), None);
+        assert_eq!(pos.saturating_add(2), BytePos(u32::MAX));
+        pos += 1;
+        assert_eq!(pos, BytePos(u32::MAX));
+        pos -= 3;
+        assert_eq!(pos - 1, BytePos(u32::MAX - 4));
+
+        let zero = BytePos(0);
+        assert_eq!(zero.checked_sub(1), None);
+        assert_eq!(zero.saturating_sub(1), zero);
+        assert_eq!(BytePos(1).checked_sub(1), Some(zero));
+
+        let positions: Vec<_> = BytePos(3).range_to(BytePos(6)).collect();
+        assert_eq!(positions, [BytePos(3), BytePos(4), BytePos(5)]);
+        assert_eq!(BytePos(u32::MAX).range_to(BytePos(u32::MAX)).count(), 0);
+        assert_eq!(BytePos(6).range_to(BytePos(3)).count(), 0);
+    }
+
+    #[test]
+    fn char_pos_arithmetic() {
+        let mut pos = CharPos(usize::MAX - 1);
+        assert_eq!(pos + 1, CharPos(usize::MAX));
+        assert_eq!(pos.checked_add(2), None);
+        assert_eq!(pos.saturating_add(2), CharPos(usize::MAX));
+        pos += 1;
+        pos -= 2;
+        assert_eq!(pos - 1, CharPos(usize::MAX - 3));
+        assert_eq!(CharPos(0).checked_sub(1), None);
+        assert_eq!(CharPos(0).saturating_sub(1), CharPos(0));
+    }
+
+    #[test]
+    fn points_at_boundaries() {
+        let span = |lo, hi| Span {
+            lo: BytePos(lo),
+            hi: BytePos(hi),
+            ctxt: NO_EXPANSION,
+        };
+        assert_eq!(span(0, 0).end_point(), span(0, 0));
+        assert_eq!(span(2, 5).end_point(), span(4, 5));
+        assert_eq!(span(2, 5).next_point(), span(5, 5));
+        assert_eq!(span(3, 3).next_point(), span(4, 4));
+        let max = u32::MAX;
+        assert_eq!(span(max, max).next_point(), span(max, max));
+    }
+
+    #[test]
+    fn file_name_is_real() {
+        for name in &["lib.rs", "src/<weird>.rs", "<weird>.rs", "weird>", "<"] {
+            assert!(FileName::from(*name).is_real(), "{}", name);
+        }
+        for name in &["<anon>", "<quote expansion>", "<format macros>", "<>"] {
+            assert!(!FileName::from(*name).is_real(), "{}", name);
+        }
+        assert!(FileName::Real(PathBuf::from("<weird>")).is_real());
+        assert!(!FileName::Custom("lib.rs".to_string()).is_real());
+    }
+
+    #[test]
+    fn file_name_display_round_trips() {
+        let names = [
+            FileName::Real(PathBuf::from("src/lib.rs")),
+            FileName::Anon(0),
+            FileName::MacroExpansion {
+                name: "format".to_string(),
+            },
+            FileName::Custom("quote expansion".to_string()),
+            FileName::Anon(7),
+            FileName::Custom("anon-07".to_string()),
+            FileName::Custom("anon-x".to_string()),
+        ];
+        for name in &names {
+            assert_eq!(FileName::from(name.to_string()), *name);
+        }
+        assert_eq!(names[0], "src/lib.rs");
+        assert_eq!(names[1], "<anon>");
+        assert_eq!(names[2], "<format macros>");
+        assert_eq!(names[3], "<quote expansion>");
+        assert_eq!(names[4], "<anon-7>");
+        assert!(names[4] != "<anon>");
+        assert_eq!(
+            PathBuf::from(names[3].clone()),
+            PathBuf::from("<quote expansion>")
+        );
+    }
+
+    #[test]
+    fn file_name_serialization() {
+        let names = [
+            FileName::Real(PathBuf::from("<weird>")),
+            FileName::Anon(3),
+            FileName::MacroExpansion {
+                name: "format".to_string(),
+            },
+            FileName::Custom("quote expansion".to_string()),
+        ];
+        for name in &names {
+            let json = serde_json::to_string(name).unwrap();
+            assert_eq!(serde_json::from_str::<FileName>(&json).unwrap(), *name);
+            let bytes = bincode::serialize(name).unwrap();
+            assert_eq!(bincode::deserialize::<FileName>(&bytes).unwrap(), *name);
+        }
+        assert_eq!(
+            serde_json::to_value(&names[0]).unwrap(),
+            serde_json::json!("\u{0}<weird>")
+        );
+
+        // Other names are serialized as their display string, as when names
+        // were plain strings.
+        let real = FileName::from("src/lib.rs");
+        assert_eq!(serde_json::to_value(&real).unwrap(), "src/lib.rs");
+        let old = bincode::serialize("src/lib.rs").unwrap();
+        assert_eq!(bincode::serialize(&real).unwrap(), old);
+        assert_eq!(bincode::deserialize::<FileName>(&old).unwrap(), real);
+        let read = |json: &str| serde_json::from_str::<FileName>(json).unwrap();
+        assert_eq!(read("\"<anon>\""), FileName::Anon(0));
+        assert_eq!(read("\"<format macros>\""), names[2]);
+    }
+
+    fn span_with_flags(allow_internal_unstable: bool, allow_internal_unsafe: bool) -> Span {
+        let mark = Mark::fresh(Mark::root());
+        mark.set_expn_info(ExpnInfo {
+            call_site: DUMMY_SP,
+            callee: NameAndSpan {
+                format: ExpnFormat::MacroBang(Symbol::intern("m")),
+                allow_internal_unstable,
+                allow_internal_unsafe,
+                span: None,
+            },
+            output_span: None,
+        });
+        Span {
+            ctxt: SyntaxContext::empty().apply_mark(mark),
+            ..DUMMY_SP
+        }
+    }
+
+    #[test]
+    fn expansion_allowances() {
+        assert!(!DUMMY_SP.allows_unstable());
+        assert!(!DUMMY_SP.allows_unsafe());
+
+        let span = span_with_flags(false, false);
+        assert!(!span.allows_unstable());
+        assert!(!span.allows_unsafe());
+
+        let span = span_with_flags(true, false);
+        assert!(span.allows_unstable());
+        assert!(!span.allows_unsafe());
+
+        let span = span_with_flags(false, true);
+        assert!(!span.allows_unstable());
+        assert!(span.allows_unsafe());
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Lines(
+        #[serde(
+            serialize_with = "super::serialize_lines",
+            deserialize_with = "super::deserialize_lines"
+        )]
+        RefCell<Vec<BytePos>>,
+    );
+
+    /// A sorted line table of `len` lines whose largest gap is `max_diff`.
+    fn random_lines(rng: &mut u64, len: usize, max_diff: u32) -> Vec<BytePos> {
+        let mut next = || {
+            // xorshift64
+            *rng ^= *rng << 13;
+            *rng ^= *rng >> 7;
+            *rng ^= *rng << 17;
+            *rng
+        };
+        let mut pos = (next() % 1000) as u32;
+        let mut lines = vec![BytePos(pos)];
+        let widest = (next() % len as u64) as usize;
+        for i in 1..len {
+            pos += if i == widest {
+                max_diff
+            } else {
+                1 + (next() % max_diff as u64) as u32
+            };
+            lines.push(BytePos(pos));
+        }
+        lines
+    }
+
+    fn assert_round_trips(lines: Vec<BytePos>) {
+        let lines = Lines(RefCell::new(lines));
+        let json = serde_json::to_string(&lines).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Lines>(&json).unwrap(),
+            lines,
+            "{}",
+            json
+        );
+        let bytes = bincode::serialize(&lines).unwrap();
+        assert_eq!(bincode::deserialize::<Lines>(&bytes).unwrap(), lines);
+
+        // bincode prefixes the sequence with the length declared to
+        // `serialize_seq`, which must be the number of elements written.
+        let mut declared = [0; 8];
+        declared.copy_from_slice(&bytes[..8]);
+        let written = serde_json::from_str::<Vec<serde_json::Value>>(&json)
+            .unwrap()
+            .len();
+        assert_eq!(u64::from_le_bytes(declared), written as u64, "{}", json);
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        assert_round_trips(vec![]);
+        assert_round_trips(vec![BytePos(0)]);
+        assert_round_trips(vec![BytePos(7)]);
+
+        let mut rng = 0x2545_f491_4f6c_dd1d;
+        for &(max_diff, bytes_per_diff) in &[(0xFF, 1), (0xFFFF, 2), (0x10_0000, 4)] {
+            for len in 2..50 {
+                let lines = random_lines(&mut rng, len, max_diff);
+                let json: serde_json::Value =
+                    serde_json::to_value(Lines(RefCell::new(lines.clone()))).unwrap();
+                assert_eq!(json[2], bytes_per_diff);
+                assert_round_trips(lines);
+            }
+        }
+    }
+
+    #[test]
+    fn lines_format_errors() {
+        let version = super::LINES_FORMAT_VERSION;
+        let read = |json: String| serde_json::from_str::<Lines>(&json).map(|l| l.0.into_inner());
+
+        assert_eq!(
+            read(format!("[{}, 2, 1, 3, 4]", version)).unwrap(),
+            [BytePos(3), BytePos(7)]
+        );
+        // Ends before the declared count.
+        assert!(read(format!("[{}, 3, 1, 3, 4]", version)).is_err());
+        assert!(read(format!("[{}, 1, 1]", version)).is_err());
+        assert!(read(format!("[{}, 1, 3, 0]", version)).is_err());
+        assert!(read(format!("[{}]", version)).is_err());
+        assert!(read("[130, 0]".to_string()).is_err());
+        assert!(read("[0, 0]".to_string()).is_err());
+
+        // The unversioned form is still read.
+        assert_eq!(read("[]".to_string()).unwrap(), []);
+        assert_eq!(
+            read("[2, 3, 4, 300]".to_string()).unwrap(),
+            [BytePos(3), BytePos(7), BytePos(307)]
+        );
+    }
 }
@@ -0,0 +1,103 @@
+//! Mapping between offsets in a source text and in an edited copy of it.
+
+/// Maps byte offsets in an edited text back and forth to offsets in the
+/// original text.
+///
+/// Only the edits are stored: each records that a range of the original was
+/// replaced by a range of the edited text, and everything between two edits
+/// was copied unchanged.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PositionMap {
+    edits: Vec<Edit>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Edit {
+    original: usize,
+    normalized: usize,
+    original_len: usize,
+    normalized_len: usize,
+}
+
+impl Edit {
+    fn original_end(&self) -> usize {
+        self.original + self.original_len
+    }
+
+    fn normalized_end(&self) -> usize {
+        self.normalized + self.normalized_len
+    }
+}
+
+impl PositionMap {
+    /// The map of a text that was not edited.
+    pub fn new() -> PositionMap {
+        PositionMap::default()
+    }
+
+    /// Whether no edits have been recorded.
+    pub fn is_identity(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Records that `original_len` bytes at offset `original` of the original
+    /// text were replaced by `normalized_len` bytes at offset `normalized` of
+    /// the edited text. Edits must be recorded in order.
+    pub fn push_edit(
+        &mut self,
+        original: usize,
+        normalized: usize,
+        original_len: usize,
+        normalized_len: usize,
+    ) {
+        if original_len == 0 && normalized_len == 0 {
+            return;
+        }
+        if let Some(last) = self.edits.last_mut() {
+            assert!(original >= last.original_end() && normalized >= last.normalized_end());
+            assert_eq!(
+                original - last.original_end(),
+                normalized - last.normalized_end(),
+                "the text between two edits must be copied unchanged"
+            );
+            if original == last.original_end() {
+                last.original_len += original_len;
+                last.normalized_len += normalized_len;
+                return;
+            }
+        } else {
+            assert_eq!(original, normalized);
+        }
+        self.edits.push(Edit {
+            original,
+            normalized,
+            original_len,
+            normalized_len,
+        });
+    }
+
+    /// The offset in the original text of `normalized`. Offsets within text
+    /// that replaced something map to the start of what was replaced.
+    pub fn to_original(&self, normalized: usize) -> usize {
+        let i = self
+            .edits
+            .partition_point(|edit| edit.normalized <= normalized);
+        match i.checked_sub(1).map(|i| self.edits[i]) {
+            None => normalized,
+            Some(edit) if normalized < edit.normalized_end() => edit.original,
+            Some(edit) => edit.original_end() + (normalized - edit.normalized_end()),
+        }
+    }
+
+    /// The offset in the edited text of `original`, or `None` if it lies
+    /// strictly within text that was removed or replaced.
+    pub fn to_normalized(&self, original: usize) -> Option<usize> {
+        let i = self.edits.partition_point(|edit| edit.original <= original);
+        match i.checked_sub(1).map(|i| self.edits[i]) {
+            None => Some(original),
+            Some(edit) if original == edit.original => Some(edit.normalized),
+            Some(edit) if original < edit.original_end() => None,
+            Some(edit) => Some(edit.normalized_end() + (original - edit.original_end())),
+        }
+    }
+}
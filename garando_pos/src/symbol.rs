@@ -3,12 +3,17 @@
 //! type, and vice versa.
 
 use crate::hygiene::SyntaxContext;
+use crate::Span;
+use crate::GLOBALS;
 
-use std::cell::RefCell;
 use std::collections::HashMap;
+use std::error;
 use std::fmt;
+use std::mem;
+use std::str::FromStr;
 
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use unicode_xid::UnicodeXID;
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Ident {
@@ -30,12 +35,135 @@ impl Ident {
         Ident::with_empty_ctxt(Symbol::intern(string))
     }
 
+    /// Maps a string to an identifier in the syntax context of `span`, or
+    /// fails if the string is not a valid identifier or is a strict keyword.
+    /// Unlike `from_str`, this never produces an identifier that pretty-prints
+    /// as something other than an identifier.
+    ///
+    /// ```
+    /// use garando_pos::symbol::{Ident, InvalidIdentError, Symbol};
+    /// use garando_pos::DUMMY_SP;
+    ///
+    /// assert_eq!(Ident::new_checked("x", DUMMY_SP), Ok(Ident::from_str("x")));
+    /// assert_eq!(
+    ///     Ident::new_checked("fn", DUMMY_SP),
+    ///     Err(InvalidIdentError::StrictKeyword(Symbol::intern("fn")))
+    /// );
+    /// ```
+    pub fn new_checked(string: &str, span: Span) -> Result<Ident, InvalidIdentError> {
+        check_ident(string)?;
+        let name = Symbol::intern(string);
+        if name >= keywords::As.name() && name <= keywords::While.name() {
+            return Err(InvalidIdentError::StrictKeyword(name));
+        }
+        Ok(Ident {
+            name,
+            ctxt: span.ctxt,
+        })
+    }
+
+    /// Whether `string` is lexically an identifier: an XID_Start character or
+    /// `_`, followed by XID_Continue characters, as in the lexer. `_` alone is
+    /// not an identifier. Keywords are accepted; see `new_checked`.
+    ///
+    /// ```
+    /// use garando_pos::symbol::Ident;
+    ///
+    /// assert!(Ident::is_valid("foo_1"));
+    /// assert!(Ident::is_valid("self"));
+    /// assert!(!Ident::is_valid("foo bar"));
+    /// assert!(!Ident::is_valid("1abc"));
+    /// ```
+    pub fn is_valid(string: &str) -> bool {
+        check_ident(string).is_ok()
+    }
+
+    /// Normalizes the syntax context to the one seen by modern (`macro`)
+    /// hygiene, so that `a.modern() == b.modern()` asks whether two
+    /// identifiers resolve to the same name under that hygiene.
+    ///
+    /// ```
+    /// use garando_pos::symbol::Ident;
+    ///
+    /// let ident = Ident::from_str("x");
+    /// assert_eq!(ident.modern(), ident);
+    /// ```
     pub fn modern(self) -> Ident {
         Ident {
             name: self.name,
             ctxt: self.ctxt.modern(),
         }
     }
+
+    /// Compares only the names of two identifiers, ignoring their syntax
+    /// contexts. Gensymed names are still distinct from the names they were
+    /// generated from.
+    ///
+    /// ```
+    /// use garando_pos::hygiene::{Mark, SyntaxContext};
+    /// use garando_pos::symbol::{Ident, Symbol};
+    ///
+    /// let a = Ident::from_str("x");
+    /// let b = Ident {
+    ///     name: Symbol::intern("x"),
+    ///     ctxt: SyntaxContext::empty().apply_mark(Mark::fresh(Mark::root())),
+    /// };
+    /// assert!(a != b);
+    /// assert!(a.unhygienic_eq(b));
+    /// assert!(!a.unhygienic_eq(a.gensym()));
+    /// ```
+    pub fn unhygienic_eq(self, other: Ident) -> bool {
+        self.name == other.name
+    }
+
+    /// Returns an identifier with the same text and syntax context but a
+    /// fresh gensymed name, which never compares equal to any other name.
+    ///
+    /// ```
+    /// use garando_pos::symbol::Ident;
+    ///
+    /// let ident = Ident::from_str("x");
+    /// let gensym = ident.gensym();
+    /// assert_eq!(gensym.name.as_str(), ident.name.as_str());
+    /// assert!(gensym != ident && gensym != ident.gensym());
+    /// ```
+    pub fn gensym(self) -> Ident {
+        Ident {
+            name: self.name.gensymed(),
+            ctxt: self.ctxt,
+        }
+    }
+
+    /// Returns an identifier with a fresh name based on `prefix`, in the
+    /// syntax context of `span`. See `Symbol::fresh`.
+    ///
+    /// ```
+    /// use garando_pos::symbol::Ident;
+    /// use garando_pos::DUMMY_SP;
+    ///
+    /// let tmp = Ident::fresh_named(DUMMY_SP, "tmp");
+    /// assert!(tmp != Ident::fresh_named(DUMMY_SP, "tmp"));
+    /// assert!(tmp.name.as_str().starts_with("tmp#"));
+    /// ```
+    pub fn fresh_named(span: Span, prefix: &str) -> Ident {
+        Ident {
+            name: Symbol::fresh(prefix),
+            ctxt: span.ctxt,
+        }
+    }
+
+    /// Whether the name of this identifier was gensymed.
+    ///
+    /// ```
+    /// use garando_pos::symbol::Ident;
+    ///
+    /// let ident = Ident::from_str("x");
+    /// assert!(!ident.is_gensymed());
+    /// assert!(ident.gensym().is_gensymed());
+    /// ```
+    pub fn is_gensymed(self) -> bool {
+        self.name.is_gensymed()
+    }
 }
 
 impl fmt::Debug for Ident {
@@ -50,6 +178,71 @@ impl fmt::Display for Ident {
     }
 }
 
+/// Parses an identifier with an empty syntax context, like
+/// `Ident::new_checked`.
+impl FromStr for Ident {
+    type Err = InvalidIdentError;
+
+    fn from_str(string: &str) -> Result<Ident, InvalidIdentError> {
+        Ident::new_checked(string, crate::DUMMY_SP)
+    }
+}
+
+/// Why a string is not a valid identifier.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InvalidIdentError {
+    Empty,
+    /// `_` alone, which is a pattern rather than an identifier.
+    Underscore,
+    /// `ch`, at byte offset `index`, can't appear there in an identifier.
+    InvalidChar {
+        index: usize,
+        ch: char,
+    },
+    StrictKeyword(Symbol),
+}
+
+impl fmt::Display for InvalidIdentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvalidIdentError::Empty => write!(f, "an identifier can't be empty"),
+            InvalidIdentError::Underscore => write!(f, "`_` is not an identifier"),
+            InvalidIdentError::InvalidChar { index: 0, ch } => {
+                write!(f, "an identifier can't start with {:?}", ch)
+            }
+            InvalidIdentError::InvalidChar { index, ch } => write!(
+                f,
+                "an identifier can't contain {:?} (at byte {})",
+                ch, index
+            ),
+            InvalidIdentError::StrictKeyword(name) => {
+                write!(f, "`{}` is a keyword, not an identifier", name)
+            }
+        }
+    }
+}
+
+impl error::Error for InvalidIdentError {}
+
+fn check_ident(string: &str) -> Result<(), InvalidIdentError> {
+    if string == "_" {
+        return Err(InvalidIdentError::Underscore);
+    }
+    let mut chars = string.char_indices();
+    match chars.next() {
+        None => return Err(InvalidIdentError::Empty),
+        Some((_, '_')) => {}
+        Some((index, ch)) if !UnicodeXID::is_xid_start(ch) => {
+            return Err(InvalidIdentError::InvalidChar { index, ch })
+        }
+        Some(_) => {}
+    }
+    match chars.find(|&(_, ch)| !UnicodeXID::is_xid_continue(ch)) {
+        Some((index, ch)) => Err(InvalidIdentError::InvalidChar { index, ch }),
+        None => Ok(()),
+    }
+}
+
 impl Serialize for Ident {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -104,10 +297,21 @@ impl Symbol {
         with_interner(|interner| interner.gensym(string))
     }
 
+    /// A gensymed name for a temporary, rendered as `prefix#N`. Two fresh
+    /// names never compare equal, and neither compares equal to the interned
+    /// symbol of the same text, so they cannot collide with user identifiers.
+    pub fn fresh(prefix: &str) -> Self {
+        with_interner(|interner| interner.fresh_name(prefix))
+    }
+
     pub fn gensymed(self) -> Self {
         with_interner(|interner| interner.gensymed(self))
     }
 
+    pub fn is_gensymed(self) -> bool {
+        with_interner(|interner| interner.is_gensymed(self))
+    }
+
     pub fn as_str(self) -> InternedString {
         with_interner(|interner| unsafe {
             InternedString {
@@ -159,8 +363,9 @@ impl<T: ::std::ops::Deref<Target = str>> PartialEq<T> for Symbol {
 
 #[derive(Default)]
 pub struct Interner {
-    names: HashMap<Box<str>, Symbol>,
-    strings: Vec<Box<str>>,
+    arena: Arena,
+    names: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>,
     gensyms: Vec<Symbol>,
 }
 
@@ -169,7 +374,9 @@ impl Interner {
         Interner::default()
     }
 
-    fn prefill(init: &[&str]) -> Self {
+    /// An interner with `init` already interned, so that `init[i]` is
+    /// `Symbol(i)` provided the strings are distinct.
+    pub fn prefill(init: &[&str]) -> Self {
         let mut this = Interner::new();
         for &string in init {
             this.intern(string);
@@ -183,8 +390,8 @@ impl Interner {
         }
 
         let name = Symbol(self.strings.len() as u32);
-        let string = string.to_string().into_boxed_str();
-        self.strings.push(string.clone());
+        let string = self.arena.alloc(string);
+        self.strings.push(string);
         self.names.insert(string, name);
         name
     }
@@ -202,17 +409,98 @@ impl Interner {
         self.gensymed(symbol)
     }
 
+    /// A gensymed name `prefix#N`, where `N` distinguishes it from the other
+    /// gensyms of this interner.
+    pub fn fresh_name(&mut self, prefix: &str) -> Symbol {
+        let string = format!("{}#{}", prefix, self.gensyms.len());
+        self.gensym(&string)
+    }
+
     fn gensymed(&mut self, symbol: Symbol) -> Symbol {
         self.gensyms.push(symbol);
         Symbol(!0 - self.gensyms.len() as u32 + 1)
     }
 
+    fn is_gensymed(&self, symbol: Symbol) -> bool {
+        symbol.0 as usize >= self.strings.len()
+    }
+
     pub fn get(&self, symbol: Symbol) -> &str {
         match self.strings.get(symbol.0 as usize) {
-            Some(ref string) => string,
+            Some(string) => string,
             None => self.get(self.gensyms[(!0 - symbol.0) as usize]),
         }
     }
+
+    /// Makes room for at least `additional` more interned strings.
+    pub fn reserve(&mut self, additional: usize) {
+        self.names.reserve(additional);
+        self.strings.reserve(additional);
+    }
+
+    pub fn stats(&self) -> InternerStats {
+        let tables = self.names.capacity() * mem::size_of::<(&str, Symbol)>()
+            + self.strings.capacity() * mem::size_of::<&str>()
+            + self.gensyms.capacity() * mem::size_of::<Symbol>();
+        InternerStats {
+            count: self.strings.len(),
+            bytes: self.arena.bytes() + tables,
+            gensym_count: self.gensyms.len(),
+        }
+    }
+}
+
+/// The text of the strings of an `Interner`, stored back to back in chunks.
+/// Most symbols are short identifiers, so this saves an allocation and its
+/// overhead per string, and lets the tables share the one copy of the text.
+#[derive(Default)]
+struct Arena {
+    chunks: Vec<String>,
+}
+
+const CHUNK_SIZE: usize = 4096;
+
+impl Arena {
+    /// Copies `string` into the arena. The copy lives as long as the arena:
+    /// a chunk is only ever appended to within its capacity, so its text is
+    /// never moved.
+    fn alloc(&mut self, string: &str) -> &'static str {
+        if string.len() > CHUNK_SIZE / 4 {
+            // Keep filling the current chunk with the short strings to come.
+            let at = self.chunks.len().saturating_sub(1);
+            self.chunks.insert(at, string.to_string());
+            return unsafe { &*(self.chunks[at].as_str() as *const str) };
+        }
+
+        let fits = match self.chunks.last() {
+            Some(chunk) => chunk.capacity() - chunk.len() >= string.len(),
+            None => false,
+        };
+        if !fits {
+            self.chunks.push(String::with_capacity(CHUNK_SIZE));
+        }
+        let chunk = self.chunks.last_mut().unwrap();
+        let start = chunk.len();
+        chunk.push_str(string);
+        unsafe { &*(&chunk[start..] as *const str) }
+    }
+
+    fn bytes(&self) -> usize {
+        let text: usize = self.chunks.iter().map(String::capacity).sum();
+        text + self.chunks.capacity() * mem::size_of::<String>()
+    }
+}
+
+/// How much an interner holds, see `interner_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InternerStats {
+    /// The number of distinct strings interned, including those the
+    /// interner was prefilled with, such as the keywords.
+    pub count: usize,
+    /// The heap memory held for the strings and the tables indexing them.
+    pub bytes: usize,
+    /// The number of gensyms created.
+    pub gensym_count: usize,
 }
 
 // In this macro, there is the requirement that the name (the number) must be monotonically
@@ -221,6 +509,9 @@ impl Interner {
 macro_rules! declare_keywords {(
     $( ($index: expr, $konst: ident, $string: expr) )*
 ) => {
+    /// The keywords and other names every interner is prefilled with. The
+    /// symbol of each is the same in every thread: its position in this list,
+    /// starting from `Invalid` at 0.
     pub mod keywords {
         use super::{Symbol, Ident};
         #[derive(Clone, Copy, PartialEq, Eq)]
@@ -243,7 +534,8 @@ macro_rules! declare_keywords {(
     }
 
     impl Interner {
-        fn fresh() -> Self {
+        /// An interner prefilled with the keywords.
+        pub(crate) fn fresh() -> Self {
             Interner::prefill(&[$($string,)*])
         }
     }
@@ -322,19 +614,35 @@ declare_keywords! {
 
     // A virtual keyword that resolves to the crate root when used in a lexical scope.
     (57, CrateRoot, "{{root}}")
+
+    // Weak keywords added since, appended so that the indices above are stable.
+    (58, Async,          "async")
+    (59, Await,          "await")
+    (60, Auto,           "auto")
 }
 
-// If an interner exists in TLS, return it. Otherwise, prepare a fresh one.
+// Run `f` with the interner of the `Globals` in use.
 fn with_interner<T, F: FnOnce(&mut Interner) -> T>(f: F) -> T {
-    thread_local!(static INTERNER: RefCell<Interner> = {
-        RefCell::new(Interner::fresh())
-    });
-    INTERNER.with(|interner| f(&mut *interner.borrow_mut()))
+    GLOBALS.with(|globals| f(&mut globals.symbol_interner.borrow_mut()))
+}
+
+/// The size of the interner of the `Globals` in use.
+pub fn interner_stats() -> InternerStats {
+    with_interner(|interner| interner.stats())
+}
+
+/// Makes room in the interner of the `Globals` in use for at least
+/// `additional` more strings, e.g. ahead of a batch of known size.
+pub fn reserve(additional: usize) {
+    with_interner(|interner| interner.reserve(additional))
 }
 
-/// Represents a string stored in the thread-local interner. Because the
-/// interner lives for the life of the thread, this can be safely treated as an
-/// immortal string, as long as it never crosses between threads.
+/// Represents a string stored in the interner of the `Globals` in use.
+/// Because the interner lives as long as its `Globals`, the thread's own for
+/// the life of the thread, this can be safely treated as an immortal string,
+/// as long as it never crosses between threads or outlives a `Globals`
+/// installed with `GLOBALS.set`, or a `reset` of it, which is why both of
+/// those are `unsafe`.
 ///
 /// FIXME(pcwalton): You must be careful about what you do in the destructors
 /// of objects stored in TLS, because they may run after the interner is
@@ -447,4 +755,193 @@ mod tests {
         // gensym of *existing* string gets new number:
         assert_eq!(i.gensym("dog"), Symbol(4294967293));
     }
+
+    #[test]
+    fn gensymed_ident_does_not_collide() {
+        let user = Ident::from_str("tmp");
+        let gensym = Ident::from_str("tmp").gensym();
+        assert!(!user.is_gensymed());
+        assert!(gensym.is_gensymed());
+        assert_eq!(gensym.to_string(), "tmp");
+        assert!(gensym != user);
+        assert!(!gensym.unhygienic_eq(user));
+        assert!(!gensym.modern().unhygienic_eq(user.modern()));
+
+        let mut names = HashMap::new();
+        names.insert(user, "user");
+        names.insert(gensym, "gensym");
+        assert_eq!(names[&Ident::from_str("tmp")], "user");
+        assert_eq!(names[&gensym], "gensym");
+    }
+
+    #[test]
+    fn fresh_names_do_not_collide() {
+        let a = Symbol::fresh("tmp");
+        let b = Symbol::fresh("tmp");
+        assert!(a != b);
+        assert!(a.is_gensymed() && b.is_gensymed());
+        assert!(a.as_str().starts_with("tmp#"));
+        assert!(a.as_str() != b.as_str());
+        assert!(a != Symbol::intern(&a.as_str()));
+        assert!(a.interned() == Symbol::intern(&a.as_str()));
+
+        let ident = Ident::fresh_named(crate::DUMMY_SP, "tmp");
+        assert!(ident.name != a && ident.name != b);
+        assert_eq!(ident.ctxt, SyntaxContext::empty());
+
+        let mut i = Interner::new();
+        let x = i.fresh_name("x");
+        assert_eq!(i.get(x), "x#0");
+        i.gensym("y");
+        let x = i.fresh_name("x");
+        assert_eq!(i.get(x), "x#2");
+    }
+
+    #[test]
+    fn interner_stats_monotonic() {
+        let mut i = Interner::prefill(&["a", "b"]);
+        let mut last = i.stats();
+        assert_eq!((last.count, last.gensym_count), (2, 0));
+
+        let long = "x".repeat(CHUNK_SIZE);
+        let mut check = |i: &Interner, count, gensym_count| {
+            let stats = i.stats();
+            assert_eq!((stats.count, stats.gensym_count), (count, gensym_count));
+            assert!(stats.bytes >= last.bytes);
+            last = stats;
+        };
+        for n in 0..1000 {
+            i.intern(&format!("s{}", n));
+            check(&i, 3 + n, 0);
+        }
+        i.intern("s0");
+        check(&i, 1002, 0);
+        i.gensym("s0");
+        i.fresh_name("t");
+        check(&i, 1003, 2);
+        let big = i.intern(&long);
+        check(&i, 1004, 2);
+        i.reserve(10_000);
+        check(&i, 1004, 2);
+
+        // Strings interned earlier are unaffected by the arena growing.
+        assert_eq!(i.get(Symbol(0)), "a");
+        assert_eq!(i.get(Symbol(2)), "s0");
+        assert_eq!(i.get(Symbol(1001)), "s999");
+        assert_eq!(i.get(big), long);
+        assert_eq!(i.intern("s999"), Symbol(1001));
+        assert_eq!(i.intern(&long), big);
+    }
+
+    #[test]
+    fn global_interner_stats() {
+        let before = interner_stats();
+        Symbol::intern("global_interner_stats");
+        Symbol::gensym("global_interner_stats");
+        reserve(100);
+        let after = interner_stats();
+        assert_eq!(after.count, before.count + 1);
+        assert_eq!(after.gensym_count, before.gensym_count + 1);
+        assert!(after.bytes >= before.bytes);
+    }
+
+    #[test]
+    fn prefilled_keyword_indices() {
+        let i = Interner::prefill(&["a", "b"]);
+        assert_eq!(i.get(Symbol(0)), "a");
+        assert_eq!(i.get(Symbol(1)), "b");
+
+        // Each thread has its own interner, prefilled the same way.
+        let indices = || {
+            Symbol::intern("unrelated");
+            [
+                Symbol::intern("").as_u32(),
+                Symbol::intern("as").as_u32(),
+                Symbol::intern("while").as_u32(),
+                Symbol::intern("'static").as_u32(),
+                Symbol::intern("{{root}}").as_u32(),
+            ]
+        };
+        let expected = [0, 1, 36, 54, 57];
+        assert_eq!(std::thread::spawn(indices).join().unwrap(), expected);
+        assert_eq!(indices(), expected);
+        assert_eq!(keywords::While.name().as_u32(), 36);
+        assert_eq!(keywords::CrateRoot.name().as_str(), "{{root}}");
+    }
+
+    #[test]
+    fn appended_keyword_indices() {
+        assert_eq!(keywords::CrateRoot.name().as_u32(), 57);
+        assert_eq!(keywords::Async.name().as_u32(), 58);
+        assert_eq!(keywords::Await.name().as_u32(), 59);
+        assert_eq!(keywords::Auto.name().as_u32(), 60);
+        assert_eq!(Symbol::intern("auto"), keywords::Auto.name());
+    }
+
+    #[test]
+    fn ident_validity() {
+        assert!(Ident::is_valid("x"));
+        assert!(Ident::is_valid("_x1"));
+        assert!(Ident::is_valid("__"));
+        assert!(Ident::is_valid("größe"));
+        assert!(Ident::is_valid("日本語"));
+        // Keywords are lexically identifiers.
+        assert!(Ident::is_valid("match"));
+
+        assert!(!Ident::is_valid(""));
+        assert!(!Ident::is_valid("_"));
+        assert!(!Ident::is_valid("1abc"));
+        assert!(!Ident::is_valid("foo bar"));
+        assert!(!Ident::is_valid("a-b"));
+        assert!(!Ident::is_valid("'a"));
+        assert!(!Ident::is_valid("\u{2764}"));
+    }
+
+    #[test]
+    fn checked_idents() {
+        let ident = |s: &str| Ident::new_checked(s, crate::DUMMY_SP);
+        assert_eq!(ident("größe"), Ok(Ident::from_str("größe")));
+        assert_eq!(ident(""), Err(InvalidIdentError::Empty));
+        assert_eq!(ident("_"), Err(InvalidIdentError::Underscore));
+        assert_eq!(
+            ident("1abc"),
+            Err(InvalidIdentError::InvalidChar { index: 0, ch: '1' })
+        );
+        assert_eq!(
+            ident("ab€"),
+            Err(InvalidIdentError::InvalidChar {
+                index: 2, ch: '€'
+            })
+        );
+        assert_eq!(
+            ident("while"),
+            Err(InvalidIdentError::StrictKeyword(keywords::While.name()))
+        );
+        assert_eq!(
+            ident("self"),
+            Err(InvalidIdentError::StrictKeyword(keywords::SelfValue.name()))
+        );
+        // Reserved keywords and contextual ones are still identifiers.
+        assert!(ident("abstract").is_ok());
+        assert!(ident("union").is_ok());
+
+        assert_eq!("x".parse::<Ident>(), Ok(Ident::from_str("x")));
+        assert!("fn".parse::<Ident>().is_err());
+        assert_eq!(
+            ident("1abc").unwrap_err().to_string(),
+            "an identifier can't start with '1'"
+        );
+        assert_eq!(
+            ident("fn").unwrap_err().to_string(),
+            "`fn` is a keyword, not an identifier"
+        );
+    }
+
+    #[test]
+    fn display() {
+        let ident = Ident::from_str("größe");
+        assert_eq!(format!("{}", ident), "größe");
+        assert_eq!(format!("{}", ident.name), "größe");
+        assert_eq!(format!("{}", ident.gensym()), "größe");
+    }
 }
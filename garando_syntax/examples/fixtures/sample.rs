@@ -0,0 +1,44 @@
+//! A small crate used by the `garando_parse` example's smoke test.
+
+use std::fmt;
+
+macro_rules! square {
+    ($e:expr) => {
+        $e * $e
+    };
+}
+
+pub struct Point {
+    x: i32,
+    y: i32,
+}
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}, {})", self.x, self.y)
+    }
+}
+
+mod shapes {
+    pub enum Shape {
+        Circle(u32),
+        Square(u32),
+    }
+
+    pub fn area(shape: &Shape) -> u32 {
+        macro_rules! three {
+            () => {
+                3
+            };
+        }
+        match *shape {
+            Shape::Circle(r) => three!() * square!(r),
+            Shape::Square(s) => square!(s),
+        }
+    }
+}
+
+fn main() {
+    let p = Point { x: 1, y: 2 };
+    println!("{}", p);
+}
@@ -0,0 +1,169 @@
+//! Parses a crate and prints a summary of its contents.
+//!
+//! Diagnostics are written to stdout as JSON, one per line, followed by the
+//! summary:
+//!
+//! ```text
+//! cargo run --example garando_parse -- path/to/lib.rs
+//! ```
+
+use garando_syntax::ast;
+use garando_syntax::codemap::FilePathMapping;
+use garando_syntax::ext::tt::collect_macro_defs;
+use garando_syntax::parse::{self, ParseSess};
+use garando_syntax::visit::{self, Visitor};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+/// What `garando_parse` reports about a crate.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Number of items of each kind, including nested ones.
+    pub items: BTreeMap<&'static str, usize>,
+    /// Number of `macro_rules!` and `macro` definitions.
+    pub macros: usize,
+    /// Total number of lines in all the files that were parsed.
+    pub lines: usize,
+    /// Number of errors emitted while parsing.
+    pub errors: usize,
+}
+
+struct ItemCounter<'a> {
+    items: &'a mut BTreeMap<&'static str, usize>,
+}
+
+impl<'a, 'ast> Visitor<'ast> for ItemCounter<'a> {
+    fn visit_item(&mut self, item: &'ast ast::Item) {
+        let kind = match item.node {
+            ast::ItemKind::ExternCrate(..) => "extern crate",
+            ast::ItemKind::Use(..) => "use",
+            ast::ItemKind::Static(..) => "static",
+            ast::ItemKind::Const(..) => "const",
+            ast::ItemKind::Fn(..) => "fn",
+            ast::ItemKind::Mod(..) => "mod",
+            ast::ItemKind::ForeignMod(..) => "extern block",
+            ast::ItemKind::GlobalAsm(..) => "global_asm",
+            ast::ItemKind::Ty(..) => "type",
+            ast::ItemKind::Enum(..) => "enum",
+            ast::ItemKind::Struct(..) => "struct",
+            ast::ItemKind::Union(..) => "union",
+            ast::ItemKind::TraitAlias(..) => "trait alias",
+            ast::ItemKind::Trait(..) => "trait",
+            ast::ItemKind::DefaultImpl(..) | ast::ItemKind::Impl(..) => "impl",
+            ast::ItemKind::Mac(..) => "macro invocation",
+            ast::ItemKind::MacroDef(..) => "macro definition",
+        };
+        *self.items.entry(kind).or_insert(0) += 1;
+        visit::walk_item(self, item);
+    }
+
+    fn visit_mac(&mut self, _mac: &'ast ast::Mac) {}
+}
+
+/// Parses the crate rooted at `path`, emitting diagnostics as JSON to `dst`.
+/// Out-of-line modules are counted, but not parsed.
+///
+/// Returns `None` if the crate could not be parsed at all.
+pub fn summarize(path: &Path, dst: Box<dyn Write + Send>) -> Option<Summary> {
+    let sess = ParseSess::with_json_emitter(FilePathMapping::empty(), dst);
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            sess.span_diagnostic
+                .err(&format!("couldn't read {}: {}", path.display(), e));
+            return None;
+        }
+    };
+    // Errors the parser can't recover from are returned rather than
+    // unwinding, whatever the input.
+    let name = path.display().to_string();
+    let krate = match parse::parse_crate_from_source_str_no_panic(name, source, &sess) {
+        Ok(krate) => krate,
+        Err(mut err) => {
+            err.emit();
+            return None;
+        }
+    };
+
+    let mut summary = Summary::default();
+    visit::walk_crate(
+        &mut ItemCounter {
+            items: &mut summary.items,
+        },
+        &krate,
+    );
+    summary.macros = collect_macro_defs(&krate).len();
+    summary.lines = sess.codemap().count_lines();
+    summary.errors = sess.span_diagnostic.err_count();
+    Some(summary)
+}
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: garando_parse <file>");
+            process::exit(2);
+        }
+    };
+
+    let summary = match summarize(Path::new(&path), Box::new(io::stdout())) {
+        Some(summary) => summary,
+        None => process::exit(1),
+    };
+    for (kind, count) in &summary.items {
+        println!("{}: {}", kind, count);
+    }
+    println!("macros: {}", summary.macros);
+    println!("lines: {}", summary.lines);
+    if summary.errors > 0 {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use garando_syntax::testing::SharedBuffer;
+
+    #[test]
+    fn summarize_fixture() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/fixtures/sample.rs");
+        let output = SharedBuffer::default();
+        let summary = summarize(&path, Box::new(output.clone())).unwrap();
+
+        let items: Vec<_> = summary.items.into_iter().collect();
+        assert_eq!(
+            items,
+            [
+                ("enum", 1),
+                ("fn", 2),
+                ("impl", 1),
+                ("macro definition", 2),
+                ("mod", 1),
+                ("struct", 1),
+                ("use", 1),
+            ]
+        );
+        assert_eq!(summary.macros, 2);
+        assert_eq!(summary.lines, 44);
+        assert_eq!(summary.errors, 0);
+        assert_eq!(output.contents(), "");
+    }
+
+    #[test]
+    fn summarize_reports_json_errors() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/fixtures/missing.rs");
+        let output = SharedBuffer::default();
+        assert!(summarize(&path, Box::new(output.clone())).is_none());
+
+        let diagnostics = output.json_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["level"], "error");
+    }
+}
@@ -1,4 +1,5 @@
 use std::fmt;
+use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
@@ -30,15 +31,18 @@ pub enum Abi {
     Unadjusted,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
 pub struct AbiData {
-    abi: Abi,
+    pub abi: Abi,
 
     /// Name of this ABI as we like it called.
-    name: &'static str,
+    pub name: &'static str,
 
     /// A generic ABI is supported on all platforms.
-    generic: bool,
+    pub generic: bool,
+
+    /// Whether functions with this ABI may take variadic arguments (`...`).
+    pub supports_varargs: bool,
 }
 
 #[allow(non_upper_case_globals)]
@@ -48,92 +52,110 @@ const AbiDatas: &'static [AbiData] = &[
         abi: Abi::Cdecl,
         name: "cdecl",
         generic: false,
+        supports_varargs: true,
     },
     AbiData {
         abi: Abi::Stdcall,
         name: "stdcall",
         generic: false,
+        supports_varargs: false,
     },
     AbiData {
         abi: Abi::Fastcall,
         name: "fastcall",
         generic: false,
+        supports_varargs: false,
     },
     AbiData {
         abi: Abi::Vectorcall,
         name: "vectorcall",
         generic: false,
+        supports_varargs: false,
     },
     AbiData {
         abi: Abi::Thiscall,
         name: "thiscall",
         generic: false,
+        supports_varargs: false,
     },
     AbiData {
         abi: Abi::Aapcs,
         name: "aapcs",
         generic: false,
+        supports_varargs: false,
     },
     AbiData {
         abi: Abi::Win64,
         name: "win64",
         generic: false,
+        supports_varargs: false,
     },
     AbiData {
         abi: Abi::SysV64,
         name: "sysv64",
         generic: false,
+        supports_varargs: false,
     },
     AbiData {
         abi: Abi::PtxKernel,
         name: "ptx-kernel",
         generic: false,
+        supports_varargs: false,
     },
     AbiData {
         abi: Abi::Msp430Interrupt,
         name: "msp430-interrupt",
         generic: false,
+        supports_varargs: false,
     },
     AbiData {
         abi: Abi::X86Interrupt,
         name: "x86-interrupt",
         generic: false,
+        supports_varargs: false,
     },
     // Cross-platform ABIs
     AbiData {
         abi: Abi::Rust,
         name: "Rust",
         generic: true,
+        supports_varargs: false,
     },
     AbiData {
         abi: Abi::C,
         name: "C",
         generic: true,
+        supports_varargs: true,
     },
     AbiData {
         abi: Abi::System,
         name: "system",
         generic: true,
+        supports_varargs: false,
     },
     AbiData {
         abi: Abi::RustIntrinsic,
         name: "rust-intrinsic",
         generic: true,
+        supports_varargs: false,
     },
     AbiData {
         abi: Abi::RustCall,
         name: "rust-call",
         generic: true,
+        supports_varargs: false,
     },
     AbiData {
         abi: Abi::PlatformIntrinsic,
         name: "platform-intrinsic",
         generic: true,
+        supports_varargs: false,
     },
     AbiData {
         abi: Abi::Unadjusted,
         name: "unadjusted",
         generic: true,
+        supports_varargs: false,
     },
 ];
 
@@ -150,6 +172,31 @@ pub fn all_names() -> Vec<&'static str> {
 }
 
 impl Abi {
+    /// Every ABI, in the order of their indices.
+    pub fn all() -> &'static [Abi] {
+        const ALL: &[Abi] = &[
+            Abi::Cdecl,
+            Abi::Stdcall,
+            Abi::Fastcall,
+            Abi::Vectorcall,
+            Abi::Thiscall,
+            Abi::Aapcs,
+            Abi::Win64,
+            Abi::SysV64,
+            Abi::PtxKernel,
+            Abi::Msp430Interrupt,
+            Abi::X86Interrupt,
+            Abi::Rust,
+            Abi::C,
+            Abi::System,
+            Abi::RustIntrinsic,
+            Abi::RustCall,
+            Abi::PlatformIntrinsic,
+            Abi::Unadjusted,
+        ];
+        ALL
+    }
+
     #[inline]
     pub fn index(&self) -> usize {
         *self as usize
@@ -167,6 +214,10 @@ impl Abi {
     pub fn generic(&self) -> bool {
         self.data().generic
     }
+
+    pub fn supports_varargs(&self) -> bool {
+        self.data().supports_varargs
+    }
 }
 
 impl fmt::Display for Abi {
@@ -175,6 +226,30 @@ impl fmt::Display for Abi {
     }
 }
 
+/// The error returned when parsing an unknown ABI.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UnknownAbi(pub String);
+
+impl fmt::Display for UnknownAbi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown ABI `{}`", self.0)
+    }
+}
+
+/// Parses an ABI as written by `Display`, e.g. `"C"`. The quotes are optional.
+impl FromStr for Abi {
+    type Err = UnknownAbi;
+
+    fn from_str(s: &str) -> Result<Abi, UnknownAbi> {
+        let name = if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+            &s[1..s.len() - 1]
+        } else {
+            s
+        };
+        lookup(name).ok_or_else(|| UnknownAbi(s.to_string()))
+    }
+}
+
 #[allow(non_snake_case)]
 #[test]
 fn lookup_Rust() {
@@ -194,6 +269,37 @@ fn lookup_baz() {
     assert!(abi.is_none());
 }
 
+#[test]
+fn display_from_str_round_trip() {
+    for abi_data in AbiDatas {
+        assert_eq!(abi_data.abi.to_string().parse(), Ok(abi_data.abi));
+    }
+    assert_eq!("C".parse(), Ok(Abi::C));
+    assert_eq!(
+        "\"stdcal\"".parse::<Abi>(),
+        Err(UnknownAbi("\"stdcal\"".to_string()))
+    );
+}
+
+#[test]
+fn all_abis() {
+    assert_eq!(Abi::all().len(), AbiDatas.len());
+    for (i, &abi) in Abi::all().iter().enumerate() {
+        assert_eq!(abi.index(), i);
+        assert_eq!(lookup(abi.name()), Some(abi));
+        assert_eq!(abi.to_string().parse(), Ok(abi));
+    }
+    assert_eq!(
+        Abi::all()
+            .iter()
+            .filter(|abi| abi.supports_varargs())
+            .map(|abi| abi.name())
+            .collect::<Vec<_>>(),
+        ["cdecl", "C"]
+    );
+    assert!(Abi::Rust.generic() && !Abi::Stdcall.generic());
+}
+
 #[test]
 fn indices_are_correct() {
     for (i, abi_data) in AbiDatas.iter().enumerate() {
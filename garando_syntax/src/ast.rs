@@ -8,13 +8,14 @@ pub use crate::symbol::{Ident, Symbol as Name};
 pub use crate::util::ThinVec;
 
 use crate::abi::Abi;
-use crate::codemap::{respan, Spanned};
+use crate::codemap::{respan, CodeMap, Spanned};
 use crate::ext::hygiene::{Mark, SyntaxContext};
+use crate::parse::token;
 use crate::print::pprust;
 use crate::ptr::P;
 use crate::rustc_data_structures::indexed_vec;
 use crate::symbol::{keywords, Symbol};
-use crate::syntax_pos::{Span, DUMMY_SP};
+use crate::syntax_pos::{BytePos, Span, DUMMY_SP};
 use crate::tokenstream::{ThinTokenStream, TokenStream};
 
 use std::collections::HashSet;
@@ -99,7 +100,8 @@ impl Path {
             && name != keywords::SelfValue.name()
             && name != keywords::Super.name()
         {
-            self.segments.insert(0, PathSegment::crate_root());
+            self.segments
+                .insert(0, PathSegment::crate_root(self.span.shrink_to_lo()));
         }
         self
     }
@@ -116,7 +118,7 @@ impl Path {
 pub struct PathSegment {
     /// The identifier portion of this path segment.
     pub identifier: Ident,
-    /// Span of the segment identifier.
+    /// Span of the segment identifier, not including its parameters.
     pub span: Span,
 
     /// Type/lifetime parameters attached to this path. They come in
@@ -137,10 +139,12 @@ impl PathSegment {
             parameters: None,
         }
     }
-    pub fn crate_root() -> Self {
+    /// The implicit first segment of a global path. It has no text of its
+    /// own, so `span` is usually empty, at the start of the path.
+    pub fn crate_root(span: Span) -> Self {
         PathSegment {
             identifier: keywords::CrateRoot.ident(),
-            span: DUMMY_SP,
+            span,
             parameters: None,
         }
     }
@@ -157,9 +161,20 @@ pub enum PathParameters {
     Parenthesized(ParenthesizedParameterData),
 }
 
+impl PathParameters {
+    pub fn span(&self) -> Span {
+        match *self {
+            PathParameters::AngleBracketed(ref data) => data.span,
+            PathParameters::Parenthesized(ref data) => data.span,
+        }
+    }
+}
+
 /// A path like `Foo<'a, T>`
-#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Hash, Debug, Default)]
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Hash, Debug)]
 pub struct AngleBracketedParameterData {
+    /// Overall span, from `<` to `>`
+    pub span: Span,
     /// The lifetime parameters for this path segment.
     pub lifetimes: Vec<Lifetime>,
     /// The type parameters for this path segment, if present.
@@ -170,6 +185,17 @@ pub struct AngleBracketedParameterData {
     pub bindings: Vec<TypeBinding>,
 }
 
+impl Default for AngleBracketedParameterData {
+    fn default() -> Self {
+        AngleBracketedParameterData {
+            span: DUMMY_SP,
+            lifetimes: Vec::new(),
+            types: Vec::new(),
+            bindings: Vec::new(),
+        }
+    }
+}
+
 impl Into<Option<P<PathParameters>>> for AngleBracketedParameterData {
     fn into(self) -> Option<P<PathParameters>> {
         let empty = self.lifetimes.is_empty() && self.types.is_empty() && self.bindings.is_empty();
@@ -317,6 +343,7 @@ impl Default for Generics {
             where_clause: WhereClause {
                 id: DUMMY_NODE_ID,
                 predicates: Vec::new(),
+                span: DUMMY_SP,
             },
             span: DUMMY_SP,
         }
@@ -328,6 +355,9 @@ impl Default for Generics {
 pub struct WhereClause {
     pub id: NodeId,
     pub predicates: Vec<WherePredicate>,
+    /// From the `where` keyword through the last predicate, or `DUMMY_SP` if
+    /// there is no `where` clause.
+    pub span: Span,
 }
 
 /// A single predicate in a `where` clause
@@ -337,10 +367,21 @@ pub enum WherePredicate {
     BoundPredicate(WhereBoundPredicate),
     /// A lifetime predicate, e.g. `'a: 'b+'c`
     RegionPredicate(WhereRegionPredicate),
-    /// An equality predicate (unsupported)
+    /// An equality predicate, e.g. `T::Output = u32`, gated by `where_clause_eq`
     EqPredicate(WhereEqPredicate),
 }
 
+impl WherePredicate {
+    /// The span of the whole predicate.
+    pub fn span(&self) -> Span {
+        match *self {
+            WherePredicate::BoundPredicate(ref p) => p.span,
+            WherePredicate::RegionPredicate(ref p) => p.span,
+            WherePredicate::EqPredicate(ref p) => p.span,
+        }
+    }
+}
+
 /// A type bound.
 ///
 /// E.g. `for<'c> Foo: Send+Clone+'c`
@@ -365,9 +406,9 @@ pub struct WhereRegionPredicate {
     pub bounds: Vec<Lifetime>,
 }
 
-/// An equality predicate (unsupported).
+/// An equality predicate.
 ///
-/// E.g. `T=int`
+/// E.g. `T::Output = u32`
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Hash, Debug)]
 pub struct WhereEqPredicate {
     pub id: NodeId,
@@ -378,7 +419,63 @@ pub struct WhereEqPredicate {
 
 /// The set of MetaItems that define the compilation environment of the crate,
 /// used to drive conditional compilation
-pub type CrateConfig = HashSet<(Name, Option<Symbol>)>;
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct CrateConfig {
+    entries: HashSet<(Name, Option<Symbol>)>,
+}
+
+impl CrateConfig {
+    pub fn new() -> CrateConfig {
+        CrateConfig {
+            entries: HashSet::new(),
+        }
+    }
+
+    /// Adds an entry to the configuration. Returns false if it was already present.
+    pub fn insert(&mut self, name: Name, value: Option<Symbol>) -> bool {
+        self.entries.insert((name, value))
+    }
+
+    /// Adds a bare name, e.g. `unix`, as set by `--cfg unix`.
+    pub fn insert_name(&mut self, name: &str) -> bool {
+        self.insert(Symbol::intern(name), None)
+    }
+
+    /// Adds a key-value pair, e.g. `feature = "serde"`, as set by `--cfg 'feature="serde"'`.
+    pub fn insert_key_value(&mut self, key: &str, value: &str) -> bool {
+        self.insert(Symbol::intern(key), Some(Symbol::intern(value)))
+    }
+
+    pub fn contains(&self, entry: &(Name, Option<Symbol>)) -> bool {
+        self.entries.contains(entry)
+    }
+
+    pub fn iter(&self) -> ::std::collections::hash_set::Iter<'_, (Name, Option<Symbol>)> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Extend<(Name, Option<Symbol>)> for CrateConfig {
+    fn extend<I: IntoIterator<Item = (Name, Option<Symbol>)>>(&mut self, iter: I) {
+        self.entries.extend(iter)
+    }
+}
+
+impl ::std::iter::FromIterator<(Name, Option<Symbol>)> for CrateConfig {
+    fn from_iter<I: IntoIterator<Item = (Name, Option<Symbol>)>>(iter: I) -> CrateConfig {
+        CrateConfig {
+            entries: iter.into_iter().collect(),
+        }
+    }
+}
 
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Hash, Debug)]
 pub struct Crate {
@@ -757,6 +854,9 @@ pub struct Local {
     pub ty: Option<P<Ty>>,
     /// Initializer expression to set the value, if any
     pub init: Option<P<Expr>>,
+    /// The `else` block of a `let ... else { ... }` statement, if any.
+    /// It is expected to diverge, but this is not checked.
+    pub els: Option<P<Block>>,
     pub id: NodeId,
     pub span: Span,
     pub attrs: ThinVec<Attribute>,
@@ -808,10 +908,22 @@ pub enum UnsafeSource {
 pub struct Expr {
     pub id: NodeId,
     pub node: ExprKind,
+    /// Covers the outer attributes of the expression as well as the
+    /// expression itself.
     pub span: Span,
+    /// The span of the expression without its outer attributes.
+    pub bare_span: Span,
     pub attrs: ThinVec<Attribute>,
 }
 
+impl Expr {
+    /// The span of the expression from its first token after any outer
+    /// attributes, e.g. of `foo()` in `#[allow(x)] foo()`.
+    pub fn span_without_attrs(&self) -> Span {
+        self.bare_span
+    }
+}
+
 impl fmt::Debug for Expr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "expr({}: {})", self.id, pprust::expr_to_string(self))
@@ -874,6 +986,12 @@ pub enum ExprKind {
     ///
     /// This is desugared to a `match` expression.
     IfLet(P<Pat>, P<Expr>, P<Block>, Option<P<Expr>>),
+    /// A `let pat = expr` operand of a `&&` chain in the condition of an
+    /// `if` or `while`, as in `if let Some(a) = x && a > 3 { block }`
+    ///
+    /// A condition that is a single binding is an `IfLet` or `WhileLet`
+    /// instead.
+    Let(P<Pat>, P<Expr>),
     /// A while loop, with an optional label
     ///
     /// `'label: while expr { block }`
@@ -904,6 +1022,10 @@ pub enum ExprKind {
     Block(P<Block>),
     /// A catch block (`catch { ... }`)
     Catch(P<Block>),
+    /// An async block (`async move { ... }`)
+    Async(CaptureBy, P<Block>),
+    /// An await expression (`expr.await`)
+    Await(P<Expr>),
 
     /// An assignment (`a = foo()`)
     Assign(P<Expr>, P<Expr>),
@@ -1001,6 +1123,7 @@ pub type Mac = Spanned<Mac_>;
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Hash, Debug)]
 pub struct Mac_ {
     pub path: Path,
+    pub delim: MacDelimiter,
     pub tts: ThinTokenStream,
 }
 
@@ -1010,6 +1133,34 @@ impl Mac_ {
     }
 }
 
+/// The delimiter a macro invocation's arguments are written between.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash, Debug)]
+pub enum MacDelimiter {
+    Parenthesis,
+    Bracket,
+    Brace,
+}
+
+impl MacDelimiter {
+    pub fn to_token(self) -> token::DelimToken {
+        match self {
+            MacDelimiter::Parenthesis => token::Paren,
+            MacDelimiter::Bracket => token::Bracket,
+            MacDelimiter::Brace => token::Brace,
+        }
+    }
+
+    /// The delimiter written as `delim`, if it can delimit macro arguments.
+    pub fn from_token(delim: token::DelimToken) -> Option<MacDelimiter> {
+        match delim {
+            token::Paren => Some(MacDelimiter::Parenthesis),
+            token::Bracket => Some(MacDelimiter::Bracket),
+            token::Brace => Some(MacDelimiter::Brace),
+            token::NoDelim => None,
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Hash, Debug)]
 pub struct MacroDef {
     pub tokens: ThinTokenStream,
@@ -1051,6 +1202,8 @@ pub enum LitKind {
     Str(Symbol, StrStyle),
     /// A byte string (`b"foo"`)
     ByteStr(Rc<Vec<u8>>),
+    /// A C string (`c"foo"`), including its terminating NUL byte
+    CStr(Rc<Vec<u8>>),
     /// A byte char (`b'f'`)
     Byte(u8),
     /// A character literal (`'a'`)
@@ -1081,6 +1234,7 @@ impl LitKind {
             // unsuffixed variants
             LitKind::Str(..)
             | LitKind::ByteStr(..)
+            | LitKind::CStr(..)
             | LitKind::Byte(..)
             | LitKind::Char(..)
             | LitKind::Int(_, LitIntType::Unsuffixed)
@@ -1113,6 +1267,7 @@ pub struct MutTy {
 pub struct MethodSig {
     pub unsafety: Unsafety,
     pub constness: Spanned<Constness>,
+    pub asyncness: IsAsync,
     pub abi: Abi,
     pub decl: P<FnDecl>,
     pub generics: Generics,
@@ -1412,6 +1567,8 @@ pub struct Arg {
     pub ty: P<Ty>,
     pub pat: P<Pat>,
     pub id: NodeId,
+    /// From the start of the pattern to the end of the type.
+    pub span: Span,
 }
 
 /// Alternative representation for `Arg`s describing `self` parameter of methods.
@@ -1458,6 +1615,29 @@ impl Arg {
         }
     }
 
+    /// `span`, extended over the comma following the argument, if there is
+    /// one and the source is available.
+    pub fn span_with_comma(&self, cm: &CodeMap) -> Span {
+        if !cm.is_span_accessible(self.span) {
+            return self.span;
+        }
+        let loc = cm.lookup_byte_offset(self.span.hi);
+        let src = match loc.fm.src {
+            Some(ref src) => &src[loc.pos.0 as usize..],
+            None => return self.span,
+        };
+        let rest = src.trim_start();
+        if rest.starts_with(',') {
+            let comma_end = src.len() - rest.len() + 1;
+            Span {
+                hi: self.span.hi + BytePos(comma_end as u32),
+                ..self.span
+            }
+        } else {
+            self.span
+        }
+    }
+
     pub fn from_self(eself: ExplicitSelf, eself_ident: SpannedIdent) -> Arg {
         let span = eself.span.to(eself_ident.span);
         let infer_ty = P(Ty {
@@ -1473,6 +1653,7 @@ impl Arg {
             }),
             ty: ty,
             id: DUMMY_NODE_ID,
+            span: span,
         };
         match eself.node {
             SelfKind::Explicit(ty, mutbl) => arg(mutbl, ty),
@@ -1514,6 +1695,13 @@ impl FnDecl {
     }
 }
 
+/// Is the trait definition an auto trait?
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Hash, Debug)]
+pub enum IsAuto {
+    Yes,
+    No,
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Hash, Debug)]
 pub enum Unsafety {
     Unsafe,
@@ -1526,6 +1714,18 @@ pub enum Constness {
     NotConst,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Hash, Debug)]
+pub enum IsAsync {
+    Async,
+    NotAsync,
+}
+
+impl IsAsync {
+    pub fn is_async(self) -> bool {
+        self == IsAsync::Async
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Hash, Debug)]
 pub enum Defaultness {
     Default,
@@ -1569,15 +1769,23 @@ pub enum FunctionRetTy {
     /// closures default to inference. Span points to where return
     /// type would be inserted.
     Default(Span),
-    /// Everything else
-    Ty(P<Ty>),
+    /// Everything else, with the span of the `->` before the type
+    Ty(P<Ty>, Span),
 }
 
 impl FunctionRetTy {
     pub fn span(&self) -> Span {
         match *self {
             FunctionRetTy::Default(span) => span,
-            FunctionRetTy::Ty(ref ty) => ty.span,
+            FunctionRetTy::Ty(ref ty, _) => ty.span,
+        }
+    }
+
+    /// The span of the `->`, if there is one.
+    pub fn arrow_span(&self) -> Option<Span> {
+        match *self {
+            FunctionRetTy::Default(_) => None,
+            FunctionRetTy::Ty(_, arrow) => Some(arrow),
         }
     }
 }
@@ -1847,6 +2055,7 @@ pub enum ItemKind {
         P<FnDecl>,
         Unsafety,
         Spanned<Constness>,
+        IsAsync,
         Abi,
         Generics,
         P<Block>,
@@ -1879,8 +2088,12 @@ pub enum ItemKind {
     Union(VariantData, Generics),
     /// A Trait declaration (`trait` or `pub trait`).
     ///
-    /// E.g. `trait Foo { .. }` or `trait Foo<T> { .. }`
-    Trait(Unsafety, Generics, TyParamBounds, Vec<TraitItem>),
+    /// E.g. `trait Foo { .. }`, `trait Foo<T> { .. }` or `auto trait Foo {}`
+    Trait(IsAuto, Unsafety, Generics, TyParamBounds, Vec<TraitItem>),
+    /// A trait alias.
+    ///
+    /// E.g. `trait Foo = Bar + Quux;` or `trait Foo<T> = Bar<T> where T: Send;`
+    TraitAlias(Generics, TyParamBounds),
     // Default trait implementation.
     ///
     /// E.g. `impl Trait for .. {}` or `impl<T> Trait<T> for .. {}`
@@ -1922,6 +2135,7 @@ impl ItemKind {
             ItemKind::Struct(..) => "struct",
             ItemKind::Union(..) => "union",
             ItemKind::Trait(..) => "trait",
+            ItemKind::TraitAlias(..) => "trait alias",
             ItemKind::Mac(..)
             | ItemKind::MacroDef(..)
             | ItemKind::Impl(..)
@@ -1948,6 +2162,10 @@ pub enum ForeignItemKind {
     /// A foreign static item (`static ext: u8`), with optional mutability
     /// (the boolean is true when mutable)
     Static(P<Ty>, bool),
+    /// A foreign type (`type Opaque;`)
+    Ty,
+    /// A macro invocation in an `extern` block
+    Macro(Mac),
 }
 
 impl ForeignItemKind {
@@ -1955,6 +2173,8 @@ impl ForeignItemKind {
         match *self {
             ForeignItemKind::Fn(..) => "foreign function",
             ForeignItemKind::Static(..) => "foreign static item",
+            ForeignItemKind::Ty => "foreign type",
+            ForeignItemKind::Macro(..) => "macro in foreign module",
         }
     }
 }
@@ -9,8 +9,8 @@ use crate::ast::{AttrId, Attribute, Ident, Name};
 use crate::ast::{Expr, ExprKind, Item, Lit, LitKind, Local, Stmt, StmtKind};
 use crate::ast::{MetaItem, MetaItemKind, NestedMetaItem, NestedMetaItemKind};
 use crate::codemap::{dummy_spanned, respan, Spanned};
-use crate::errors::Handler;
-use crate::feature_gate::{Features, GatedCfg};
+use crate::errors::{DiagnosticBuilder, Handler};
+use crate::feature_gate::{AttributeType, Features, GatedCfg, BUILTIN_ATTRIBUTES};
 use crate::parse::lexer::comments::{doc_comment_style, strip_doc_comment_decoration};
 use crate::parse::parser::Parser;
 use crate::parse::token::{self, Token};
@@ -18,10 +18,13 @@ use crate::parse::{self, PResult, ParseSess};
 use crate::ptr::P;
 use crate::symbol::Symbol;
 use crate::syntax_pos::{Span, DUMMY_SP};
-use crate::tokenstream::{Delimited, TokenStream, TokenTree};
+use crate::tokenstream::{DelimSpan, Delimited, TokenStream, TokenTree};
+use crate::util::lev_distance::find_best_match_for_str;
 use crate::util::ThinVec;
+use crate::visit::{self, Visitor};
 
 use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::iter;
 
 use log::debug;
@@ -106,6 +109,222 @@ pub fn is_known(attr: &Attribute) -> bool {
     })
 }
 
+/// Whether nothing has consumed `attr`. Whitelisted builtin attributes, which
+/// are consumed after parsing and expansion, and tool attributes such as
+/// `#[rustfmt::skip]` are never considered unused.
+fn is_unused(attr: &Attribute) -> bool {
+    if is_used(attr) || is_known(attr) || attr.path.segments.len() > 1 {
+        return false;
+    }
+    !BUILTIN_ATTRIBUTES
+        .iter()
+        .any(|&(name, ty, _)| ty == AttributeType::Whitelisted && attr.path == name)
+}
+
+struct UnusedAttrReporter<'a> {
+    diag: &'a Handler,
+}
+
+impl<'a, 'ast> Visitor<'ast> for UnusedAttrReporter<'a> {
+    fn visit_attribute(&mut self, attr: &'ast Attribute) {
+        if is_unused(attr) {
+            self.diag.span_warn(attr.span, "unused attribute");
+        }
+    }
+
+    fn visit_mac(&mut self, _mac: &'ast ast::Mac) {
+        // Attributes inside macro invocations have not been parsed.
+    }
+}
+
+pub(crate) fn report_unused_attrs(diag: &Handler, krate: &ast::Crate) {
+    visit::walk_crate(&mut UnusedAttrReporter { diag }, krate);
+}
+
+/// What to do when an attribute is repeated on the same node.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OnDuplicate {
+    /// Every repetition is a hard error (E0538).
+    ErrorOnDuplicate,
+    /// Repetitions are ignored, warn that they are unused.
+    WarnFollowing,
+    /// Repetitions are accepted for now but will become an error (E0538).
+    FutureWarn,
+    /// The attribute may be repeated freely.
+    DuplicatesOk,
+}
+
+/// Maps attribute names to how repeating them is handled. Attributes not in
+/// the table may be repeated freely.
+#[derive(Clone, Debug)]
+pub struct DuplicatePolicy {
+    rules: HashMap<Name, OnDuplicate>,
+}
+
+impl DuplicatePolicy {
+    /// A policy allowing every attribute to be repeated.
+    pub fn empty() -> DuplicatePolicy {
+        DuplicatePolicy {
+            rules: HashMap::new(),
+        }
+    }
+
+    /// Sets how repetitions of `name` are handled, replacing any previous rule.
+    pub fn insert(&mut self, name: &str, on_duplicate: OnDuplicate) -> &mut Self {
+        self.rules.insert(Symbol::intern(name), on_duplicate);
+        self
+    }
+
+    pub fn get(&self, name: Name) -> OnDuplicate {
+        self.rules
+            .get(&name)
+            .cloned()
+            .unwrap_or(OnDuplicate::DuplicatesOk)
+    }
+}
+
+impl Default for DuplicatePolicy {
+    fn default() -> DuplicatePolicy {
+        let mut policy = DuplicatePolicy::empty();
+        policy
+            .insert("inline", OnDuplicate::FutureWarn)
+            .insert("repr", OnDuplicate::DuplicatesOk)
+            .insert("path", OnDuplicate::ErrorOnDuplicate)
+            .insert("macro_use", OnDuplicate::WarnFollowing)
+            .insert("ignore", OnDuplicate::WarnFollowing);
+        policy
+    }
+}
+
+/// Reports attributes of `attrs` repeated against `policy`, pointing back at
+/// the first occurrence. Returns the number of repetitions reported.
+pub fn check_duplicates(attrs: &[Attribute], policy: &DuplicatePolicy, diag: &Handler) -> usize {
+    let mut seen: HashMap<Name, Span> = HashMap::new();
+    let mut reported = 0;
+    for attr in attrs {
+        let name = match attr.name() {
+            Some(name) => name,
+            None => continue,
+        };
+        let first = match seen.get(&name) {
+            Some(&first) => first,
+            None => {
+                seen.insert(name, attr.span);
+                continue;
+            }
+        };
+        let mut err = match policy.get(name) {
+            OnDuplicate::DuplicatesOk => continue,
+            OnDuplicate::ErrorOnDuplicate => {
+                struct_span_err!(diag, attr.span, E0538, "multiple `{}` attributes", name)
+            }
+            OnDuplicate::FutureWarn => {
+                let mut err =
+                    struct_span_warn!(diag, attr.span, E0538, "multiple `{}` attributes", name);
+                err.note(
+                    "this was previously accepted but will become a hard error \
+                     in a future release",
+                );
+                err
+            }
+            OnDuplicate::WarnFollowing => diag.struct_span_warn(attr.span, "unused attribute"),
+        };
+        err.span_label(first, "previously specified here").emit();
+        reported += 1;
+    }
+    reported
+}
+
+struct DuplicateAttrChecker<'a> {
+    policy: &'a DuplicatePolicy,
+    diag: &'a Handler,
+    reported: usize,
+}
+
+impl<'a> DuplicateAttrChecker<'a> {
+    fn check(&mut self, attrs: &[Attribute]) {
+        self.reported += check_duplicates(attrs, self.policy, self.diag);
+    }
+}
+
+impl<'a, 'ast> Visitor<'ast> for DuplicateAttrChecker<'a> {
+    fn visit_item(&mut self, i: &'ast Item) {
+        self.check(&i.attrs);
+        visit::walk_item(self, i);
+    }
+
+    fn visit_foreign_item(&mut self, i: &'ast ast::ForeignItem) {
+        self.check(&i.attrs);
+        visit::walk_foreign_item(self, i);
+    }
+
+    fn visit_trait_item(&mut self, ti: &'ast ast::TraitItem) {
+        self.check(&ti.attrs);
+        visit::walk_trait_item(self, ti);
+    }
+
+    fn visit_impl_item(&mut self, ii: &'ast ast::ImplItem) {
+        self.check(&ii.attrs);
+        visit::walk_impl_item(self, ii);
+    }
+
+    fn visit_variant(
+        &mut self,
+        v: &'ast ast::Variant,
+        g: &'ast ast::Generics,
+        item_id: ast::NodeId,
+    ) {
+        self.check(&v.node.attrs);
+        visit::walk_variant(self, v, g, item_id);
+    }
+
+    fn visit_struct_field(&mut self, s: &'ast ast::StructField) {
+        self.check(&s.attrs);
+        visit::walk_struct_field(self, s);
+    }
+
+    fn visit_local(&mut self, l: &'ast Local) {
+        self.check(&l.attrs);
+        visit::walk_local(self, l);
+    }
+
+    fn visit_arm(&mut self, a: &'ast ast::Arm) {
+        self.check(&a.attrs);
+        visit::walk_arm(self, a);
+    }
+
+    fn visit_expr(&mut self, ex: &'ast Expr) {
+        self.check(&ex.attrs);
+        visit::walk_expr(self, ex);
+    }
+
+    fn visit_mac(&mut self, _mac: &'ast ast::Mac) {
+        // Attributes inside macro invocations have not been parsed.
+    }
+}
+
+/// Runs `check_duplicates` with the default policy over the attributes of the
+/// crate and of every node in it. Returns the number of repetitions reported.
+pub fn check_crate_duplicates(krate: &ast::Crate, sess: &ParseSess) -> usize {
+    check_crate_duplicates_with_policy(krate, &DuplicatePolicy::default(), sess)
+}
+
+/// Like `check_crate_duplicates`, with a caller-provided policy.
+pub fn check_crate_duplicates_with_policy(
+    krate: &ast::Crate,
+    policy: &DuplicatePolicy,
+    sess: &ParseSess,
+) -> usize {
+    let mut checker = DuplicateAttrChecker {
+        policy,
+        diag: &sess.span_diagnostic,
+        reported: 0,
+    };
+    checker.check(&krate.attrs);
+    visit::walk_crate(&mut checker, krate);
+    checker.reported
+}
+
 impl NestedMetaItem {
     /// Returns the MetaItem if self is a NestedMetaItemKind::MetaItem.
     pub fn meta_item(&self) -> Option<&MetaItem> {
@@ -217,6 +436,22 @@ impl Attribute {
         matches
     }
 
+    /// Like `check_name`, but compares the whole path of the attribute, so
+    /// that `&["rustfmt", "skip"]` matches `#[rustfmt::skip]`.
+    pub fn path_matches(&self, path: &[&str]) -> bool {
+        let matches = self.path.segments.len() == path.len()
+            && self
+                .path
+                .segments
+                .iter()
+                .zip(path)
+                .all(|(segment, name)| segment.identifier.name == *name);
+        if matches {
+            mark_used(self);
+        }
+        matches
+    }
+
     pub fn name(&self) -> Option<Name> {
         match self.path.segments.len() {
             1 => Some(self.path.segments[0].identifier.name),
@@ -228,6 +463,12 @@ impl Attribute {
         self.meta().and_then(|meta| meta.value_str())
     }
 
+    /// Like `value_str`, but with the span of the string literal rather
+    /// than just its value.
+    pub fn value_str_spanned(&self) -> Option<Spanned<Symbol>> {
+        self.meta().and_then(|meta| meta.value_str_spanned())
+    }
+
     pub fn meta_item_list(&self) -> Option<Vec<NestedMetaItem>> {
         match self.meta() {
             Some(MetaItem {
@@ -262,11 +503,25 @@ impl MetaItem {
     }
 
     pub fn value_str(&self) -> Option<Symbol> {
+        self.value_str_spanned().map(|s| s.node)
+    }
+
+    /// Like `value_str`, but with the span of the string literal rather
+    /// than just its value.
+    pub fn value_str_spanned(&self) -> Option<Spanned<Symbol>> {
+        match self.name_value_literal() {
+            Some(&Spanned {
+                node: LitKind::Str(s, _),
+                span,
+            }) => Some(respan(span, s)),
+            _ => None,
+        }
+    }
+
+    /// The literal of a `name = value` meta item, whatever its kind.
+    pub fn name_value_literal(&self) -> Option<&Lit> {
         match self.node {
-            MetaItemKind::NameValue(ref v) => match v.node {
-                LitKind::Str(ref s, _) => Some(*s),
-                _ => None,
-            },
+            MetaItemKind::NameValue(ref v) => Some(v),
             _ => None,
         }
     }
@@ -278,6 +533,15 @@ impl MetaItem {
         }
     }
 
+    /// Like `meta_item_list`, but iterating over each nested item together
+    /// with its span.
+    pub fn meta_item_list_spanned(
+        &self,
+    ) -> Option<impl Iterator<Item = (Span, &NestedMetaItemKind)> + '_> {
+        self.meta_item_list()
+            .map(|list| list.iter().map(|item| (item.span, &item.node)))
+    }
+
     pub fn is_word(&self) -> bool {
         match self.node {
             MetaItemKind::Word => true,
@@ -305,24 +569,27 @@ impl MetaItem {
 impl Attribute {
     /// Extract the MetaItem from inside this Attribute.
     pub fn meta(&self) -> Option<MetaItem> {
-        let mut tokens = self.tokens.trees().peekable();
         Some(MetaItem {
             name: match self.path.segments.len() {
                 1 => self.path.segments[0].identifier.name,
                 _ => return None,
             },
-            node: if let Some(node) = MetaItemKind::from_tokens(&mut tokens) {
-                if tokens.peek().is_some() {
-                    return None;
-                }
-                node
-            } else {
-                return None;
-            },
+            node: self.meta_item_kind()?,
             span: self.span,
         })
     }
 
+    /// The tokens after the path of the attribute as a `MetaItemKind`,
+    /// whatever the path.
+    fn meta_item_kind(&self) -> Option<MetaItemKind> {
+        let mut tokens = self.tokens.trees().peekable();
+        let node = MetaItemKind::from_tokens(&mut tokens)?;
+        if tokens.peek().is_some() {
+            return None;
+        }
+        Some(node)
+    }
+
     pub fn parse<'a, T, F>(&self, sess: &'a ParseSess, mut f: F) -> PResult<'a, T>
     where
         F: FnMut(&mut Parser<'a>) -> PResult<'a, T>,
@@ -515,6 +782,26 @@ pub fn first_attr_value_str_by_name(attrs: &[Attribute], name: &str) -> Option<S
         .and_then(|at| at.value_str())
 }
 
+/// Whether any of `attrs` has the path `path`, e.g. `&["rustfmt", "skip"]`.
+pub fn has_path(attrs: &[Attribute], path: &[&str]) -> bool {
+    attrs.iter().any(|attr| attr.path_matches(path))
+}
+
+/// The string value of the first of `attrs` with the path `path`, as in
+/// `#[tool::name = "value"]`.
+pub fn first_attr_value_str_by_path(attrs: &[Attribute], path: &[&str]) -> Option<Symbol> {
+    attrs
+        .iter()
+        .find(|attr| attr.path_matches(path))
+        .and_then(|attr| match attr.meta_item_kind() {
+            Some(MetaItemKind::NameValue(Spanned {
+                node: LitKind::Str(value, _),
+                ..
+            })) => Some(value),
+            _ => None,
+        })
+}
+
 /* Higher-level applications */
 
 pub fn find_crate_name(attrs: &[Attribute]) -> Option<Symbol> {
@@ -525,16 +812,22 @@ pub fn find_crate_name(attrs: &[Attribute]) -> Option<Symbol> {
 pub fn find_export_name_attr(diag: &Handler, attrs: &[Attribute]) -> Option<Symbol> {
     attrs.iter().fold(None, |ia, attr| {
         if attr.check_name("export_name") {
-            if let s @ Some(_) = attr.value_str() {
+            let meta = attr.meta();
+            if let s @ Some(_) = meta.as_ref().and_then(MetaItem::value_str) {
                 s
             } else {
+                // Point at the value if there is one, as in `#[export_name = 1]`.
+                let span = match meta.as_ref().and_then(MetaItem::name_value_literal) {
+                    Some(lit) => lit.span,
+                    None => attr.span,
+                };
                 struct_span_err!(
                     diag,
-                    attr.span,
+                    span,
                     E0558,
                     "export_name attribute has invalid format"
                 )
-                .span_label(attr.span, "did you mean #[export_name=\"*\"]?")
+                .span_label(span, "did you mean #[export_name=\"*\"]?")
                 .emit();
                 None
             }
@@ -603,9 +896,123 @@ pub fn requests_inline(attrs: &[Attribute]) -> bool {
     }
 }
 
+/// How `cfg_matches` treats predicates other than `all`, `any` and `not`,
+/// such as the `version(..)` and `accessible(..)` of newer compilers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CfgCompat {
+    /// Unknown predicates are errors.
+    Strict,
+    /// Unknown predicates are false, with a warning the first time each name
+    /// is seen in the session.
+    LenientUnknownPredicates,
+}
+
+impl Default for CfgCompat {
+    fn default() -> CfgCompat {
+        CfgCompat::Strict
+    }
+}
+
+const CFG_PREDICATES: &[&str] = &["all", "any", "not"];
+
+/// Evaluates the predicate `name`, which isn't one of `CFG_PREDICATES`, under
+/// `CfgCompat::LenientUnknownPredicates`.
+fn unknown_cfg_predicate(sess: &ParseSess, name: Name, span: Span) -> bool {
+    if sess.first_unknown_cfg_predicate(name) {
+        sess.span_diagnostic
+            .struct_span_warn(span, &format!("unknown `cfg` predicate `{}`", name))
+            .note("it is treated as false")
+            .emit();
+    }
+    false
+}
+
+/// Evaluates the `cfg` whose tokens, following the attribute's path, are a
+/// single parenthesized predicate that isn't a `MetaItem`, under
+/// `CfgCompat::LenientUnknownPredicates`. Unknown predicates in it, at any
+/// depth under `any`, `all` and `not`, take a single parenthesized group of
+/// any tokens, such as the path in `accessible(std::x)`. Returns `None` for
+/// other tokens.
+pub fn cfg_matches_tokens(
+    tokens: &TokenStream,
+    sess: &ParseSess,
+    features: Option<&Features>,
+) -> Option<bool> {
+    if sess.cfg_compat() != CfgCompat::LenientUnknownPredicates {
+        return None;
+    }
+    let mut trees = tokens.trees();
+    match (trees.next(), trees.next()) {
+        (Some(TokenTree::Delimited(_, ref delimited)), None) if delimited.delim == token::Paren => {
+            cfg_predicate_matches_tokens(delimited.stream(), sess, features)
+        }
+        _ => None,
+    }
+}
+
+/// Evaluates the single predicate in `tokens` for `cfg_matches_tokens`.
+fn cfg_predicate_matches_tokens(
+    tokens: TokenStream,
+    sess: &ParseSess,
+    features: Option<&Features>,
+) -> Option<bool> {
+    let mut trees = tokens.clone().into_trees().peekable();
+    if let Some(cfg) = MetaItem::from_tokens(&mut trees) {
+        if trees.peek().is_none() {
+            return Some(cfg_matches(&cfg, sess, features));
+        }
+    }
+
+    let mut trees = tokens.trees();
+    let (name, span, args) = match (trees.next(), trees.next(), trees.next()) {
+        (
+            Some(TokenTree::Token(span, Token::Ident(ident))),
+            Some(TokenTree::Delimited(delim_span, ref delimited)),
+            None,
+        ) if delimited.delim == token::Paren => {
+            (ident.name, span.to(delim_span.close), delimited.stream())
+        }
+        _ => return None,
+    };
+    if !CFG_PREDICATES.contains(&&*name.as_str()) {
+        return Some(unknown_cfg_predicate(sess, name, span));
+    }
+
+    let mut predicates = Vec::new();
+    let mut predicate = Vec::new();
+    for tree in args.trees() {
+        match tree {
+            TokenTree::Token(_, Token::Comma) => {
+                predicates.push(TokenStream::concat(predicate));
+                predicate = Vec::new();
+            }
+            tree => predicate.push(tree.into()),
+        }
+    }
+    if !predicate.is_empty() {
+        predicates.push(TokenStream::concat(predicate));
+    }
+    let values = predicates
+        .into_iter()
+        .map(|predicate| cfg_predicate_matches_tokens(predicate, sess, features))
+        .collect::<Option<Vec<_>>>()?;
+    match &*name.as_str() {
+        "any" => Some(values.iter().any(|&value| value)),
+        "all" => Some(values.iter().all(|&value| value)),
+        _ if values.len() == 1 => Some(!values[0]),
+        _ => None,
+    }
+}
+
 /// Tests if a cfg-pattern matches the cfg set
 pub fn cfg_matches(cfg: &ast::MetaItem, sess: &ParseSess, features: Option<&Features>) -> bool {
     match cfg.node {
+        ast::MetaItemKind::List(_)
+            if sess.cfg_compat() == CfgCompat::LenientUnknownPredicates
+                && !CFG_PREDICATES.contains(&&*cfg.name.as_str()) =>
+        {
+            unknown_cfg_predicate(sess, cfg.name, cfg.span)
+        }
         ast::MetaItemKind::List(ref mis) => {
             for mi in mis.iter() {
                 if !mi.is_meta_item() {
@@ -641,13 +1048,15 @@ pub fn cfg_matches(cfg: &ast::MetaItem, sess: &ParseSess, features: Option<&Feat
                     !cfg_matches(mis[0].meta_item().unwrap(), sess, features)
                 }
                 p => {
-                    span_err!(
+                    let mut err = struct_span_err!(
                         sess.span_diagnostic,
                         cfg.span,
                         E0537,
                         "invalid predicate `{}`",
                         p
                     );
+                    suggest_similar_name(&mut err, cfg, CFG_PREDICATES);
+                    err.emit();
                     false
                 }
             }
@@ -656,11 +1065,69 @@ pub fn cfg_matches(cfg: &ast::MetaItem, sess: &ParseSess, features: Option<&Feat
             if let (Some(feats), Some(gated_cfg)) = (features, GatedCfg::gate(cfg)) {
                 gated_cfg.check_and_emit(sess, feats);
             }
-            sess.config.contains(&(cfg.name(), cfg.value_str()))
+            if sess.config.contains(&(cfg.name(), cfg.value_str())) {
+                return true;
+            }
+            // Unset names are false, and only warned about when they look
+            // like a misspelling of a known one.
+            let name = cfg.name().as_str();
+            let is_set = sess.config.iter().any(|&(set, _)| set == cfg.name());
+            if !is_set && !KNOWN_CFG_NAMES.contains(&&*name) {
+                if let Some(similar) =
+                    find_best_match_for_str(KNOWN_CFG_NAMES.iter().cloned(), &name, None)
+                {
+                    let mut err = sess.span_diagnostic.struct_span_warn(
+                        cfg.span,
+                        &format!("unexpected `cfg` condition name `{}`", name),
+                    );
+                    err.span_suggestion(name_span(cfg), "did you mean", similar.to_string());
+                    err.emit();
+                }
+            }
+            false
         }
     }
 }
 
+/// The names of the `cfg` options a compiler sets, which misspelt names are
+/// compared against.
+pub const KNOWN_CFG_NAMES: &[&str] = &[
+    "debug_assertions",
+    "doc",
+    "feature",
+    "panic",
+    "proc_macro",
+    "target_arch",
+    "target_endian",
+    "target_env",
+    "target_family",
+    "target_feature",
+    "target_has_atomic",
+    "target_os",
+    "target_pointer_width",
+    "target_thread_local",
+    "target_vendor",
+    "test",
+    "unix",
+    "windows",
+];
+
+/// The span of the name at the start of `item`.
+fn name_span(item: &MetaItem) -> Span {
+    Span {
+        hi: item.span.lo + item.name.as_str().len() as u32,
+        ..item.span
+    }
+}
+
+/// Suggests the candidate closest to the name of `item`, if any is close.
+fn suggest_similar_name(err: &mut DiagnosticBuilder, item: &MetaItem, candidates: &[&str]) {
+    let name = item.name.as_str();
+    if let Some(similar) = find_best_match_for_str(candidates.iter().cloned(), &name, None) {
+        err.span_suggestion(name_span(item), "did you mean", similar.to_string());
+    }
+}
+
 /// Represents the #[stable], #[unstable] and #[rustc_deprecated] attributes.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Stability {
@@ -740,7 +1207,8 @@ where
                     *item = Some(v);
                     true
                 } else {
-                    span_err!(diagnostic, meta.span, E0539, "incorrect meta item");
+                    let span = meta.name_value_literal().map_or(meta.span, |lit| lit.span);
+                    span_err!(diagnostic, span, E0539, "incorrect meta item");
                     false
                 }
             };
@@ -996,7 +1464,8 @@ where
                     *item = Some(v);
                     true
                 } else {
-                    span_err!(diagnostic, meta.span, E0551, "incorrect meta item");
+                    let span = meta.name_value_literal().map_or(meta.span, |lit| lit.span);
+                    span_err!(diagnostic, span, E0551, "incorrect meta item");
                     false
                 }
             };
@@ -1121,7 +1590,7 @@ pub fn find_repr_attrs(diagnostic: &Handler, attr: &Attribute) -> Vec<ReprAttr>
                         if let Some(align_error) = align_error {
                             span_err!(
                                 diagnostic,
-                                item.span,
+                                value.span,
                                 E0589,
                                 "invalid `repr(align)` attribute: {}",
                                 align_error
@@ -1250,7 +1719,7 @@ impl MetaItemKind {
                     tokens.push(item.node.tokens());
                 }
                 TokenTree::Delimited(
-                    span,
+                    DelimSpan::from_single(span),
                     Delimited {
                         delim: token::Paren,
                         tts: TokenStream::concat(tokens).into(),
@@ -1368,6 +1837,17 @@ impl LitKind {
                     .collect::<String>();
                 Token::Literal(token::Lit::ByteStr(Symbol::intern(&string)), None)
             }
+            LitKind::CStr(ref bytes) => {
+                let string = bytes
+                    .strip_suffix(&[0])
+                    .unwrap_or(bytes)
+                    .iter()
+                    .cloned()
+                    .flat_map(ascii::escape_default)
+                    .map(Into::<char>::into)
+                    .collect::<String>();
+                Token::Literal(token::Lit::CStr(Symbol::intern(&string)), None)
+            }
             LitKind::Byte(byte) => {
                 let string: String = ascii::escape_default(byte)
                     .map(Into::<char>::into)
@@ -1406,7 +1886,7 @@ impl LitKind {
             Token::Ident(ident) if ident.name == "true" => Some(LitKind::Bool(true)),
             Token::Ident(ident) if ident.name == "false" => Some(LitKind::Bool(false)),
             Token::Interpolated(ref nt) => match **nt {
-                token::NtExpr(ref v) => match v.node {
+                token::NtExpr(ref v) | token::NtLiteral(ref v) => match v.node {
                     ExprKind::Lit(ref lit) => Some(lit.node.clone()),
                     _ => None,
                 },
@@ -11,14 +11,21 @@ pub use self::ExpnFormat::*;
 pub use crate::syntax_pos::hygiene::{ExpnFormat, ExpnInfo, NameAndSpan};
 pub use crate::syntax_pos::*;
 
-use std::cell::{Ref, RefCell};
+use std::cell::{Cell, Ref, RefCell};
+use std::cmp;
+use std::mem;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
-use crate::errors::CodeMapper;
+use crate::errors::emitter::EmitterWriter;
+use crate::errors::{CodeMapper, CodeSuggestion, Handler};
+use crate::parse::lexer::{self, TokenAndSpan};
+use crate::parse::{token, ParseSess};
 use std::env;
 use std::fs;
 use std::io::{self, Read};
+#[cfg(feature = "parallel")]
+use std::thread;
 
 use log::debug;
 use serde::{Deserialize, Serialize};
@@ -64,6 +71,13 @@ pub trait FileLoader {
 
     /// Read the contents of an UTF-8 file into memory.
     fn read_file(&self, path: &Path) -> io::Result<String>;
+
+    /// Read the contents of several UTF-8 files into memory, returning the
+    /// results in the order of `paths`. Loaders which can read concurrently
+    /// may override this; the default reads the files one after another.
+    fn read_files(&self, paths: &[PathBuf]) -> Vec<io::Result<String>> {
+        paths.iter().map(|path| self.read_file(path)).collect()
+    }
 }
 
 /// A FileLoader that uses std::fs to load real files.
@@ -87,18 +101,50 @@ impl FileLoader for RealFileLoader {
         fs::File::open(path)?.read_to_string(&mut src)?;
         Ok(src)
     }
+
+    /// Reads and validates the files on a thread per available core when the
+    /// `parallel` feature is enabled.
+    #[cfg(feature = "parallel")]
+    fn read_files(&self, paths: &[PathBuf]) -> Vec<io::Result<String>> {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_len = cmp::max(1, (paths.len() + threads - 1) / threads);
+        thread::scope(|scope| {
+            let chunks = paths
+                .chunks(chunk_len)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|path| self.read_file(path))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            chunks
+                .into_iter()
+                .flat_map(|chunk| chunk.join().unwrap())
+                .collect()
+        })
+    }
 }
 
 // _____________________________________________________________________________
 // CodeMap
 //
 
+/// Returns the source code of the file with the given name, if known.
+pub type SourceProvider = Box<dyn Fn(&FileName) -> Option<String>>;
+
 pub struct CodeMap {
     pub files: RefCell<Vec<Rc<FileMap>>>,
     file_loader: Box<dyn FileLoader>,
     // This is used to apply the file path remapping as specified via
     // -Zremap-path-prefix to all FileMaps allocated within this CodeMap.
     path_mapping: FilePathMapping,
+    // Consulted for the source of filemaps which were created without it.
+    source_provider: RefCell<Option<SourceProvider>>,
+    // Applied to the source of every filemap created by `new_filemap`.
+    normalize_options: Cell<Option<NormalizeOptions>>,
 }
 
 impl CodeMap {
@@ -107,6 +153,8 @@ impl CodeMap {
             files: RefCell::new(Vec::new()),
             file_loader: Box::new(RealFileLoader),
             path_mapping: path_mapping,
+            source_provider: RefCell::new(None),
+            normalize_options: Cell::new(None),
         }
     }
 
@@ -118,6 +166,8 @@ impl CodeMap {
             files: RefCell::new(Vec::new()),
             file_loader: file_loader,
             path_mapping: path_mapping,
+            source_provider: RefCell::new(None),
+            normalize_options: Cell::new(None),
         }
     }
 
@@ -125,13 +175,56 @@ impl CodeMap {
         &self.path_mapping
     }
 
+    /// Sets the function which provides the source code of filemaps created
+    /// without it, such as imported ones, when it is needed for diagnostics.
+    pub fn set_source_provider(&self, provider: SourceProvider) {
+        *self.source_provider.borrow_mut() = Some(provider);
+    }
+
+    /// Sets how `new_filemap` normalizes the source of the filemaps it
+    /// creates, if at all. A normalized filemap's positions refer to the
+    /// normalized text; `FileMap::original_offset` maps them back, and the
+    /// lines and columns reported for them are those of the original text.
+    pub fn set_normalize_options(&self, options: Option<NormalizeOptions>) {
+        self.normalize_options.set(options);
+    }
+
+    /// Makes the source code of `fm` available through `FileMap::get_line` if
+    /// possible, asking the source provider for it if `fm` has none. Returns
+    /// whether it is available.
+    pub fn ensure_filemap_source_present(&self, fm: Rc<FileMap>) -> bool {
+        if fm.src.is_some() {
+            return true;
+        }
+        match *self.source_provider.borrow() {
+            Some(ref provider) => fm.add_external_src(|| provider(&fm.name)),
+            None => false,
+        }
+    }
+
     pub fn file_exists(&self, path: &Path) -> bool {
         self.file_loader.file_exists(path)
     }
 
     pub fn load_file(&self, path: &Path) -> io::Result<Rc<FileMap>> {
         let src = self.file_loader.read_file(path)?;
-        Ok(self.new_filemap(path.to_str().unwrap().to_string(), src))
+        Ok(self.new_filemap(FileName::Real(path.to_owned()), src))
+    }
+
+    /// Loads each of `paths`, returning the filemaps or errors in the same
+    /// order. The files are read through the file loader's `read_files`,
+    /// which may read them concurrently, but the filemaps are only created
+    /// afterwards, one at a time in the order of `paths`. Their `start_pos`es
+    /// therefore depend only on `paths` and the files' contents, never on the
+    /// order in which the reads complete. A file which fails to load takes
+    /// up no space in the codemap.
+    pub fn load_files(&self, paths: &[PathBuf]) -> Vec<io::Result<Rc<FileMap>>> {
+        let srcs = self.file_loader.read_files(paths);
+        paths
+            .iter()
+            .zip(srcs)
+            .map(|(path, src)| Ok(self.new_filemap(FileName::Real(path.clone()), src?)))
+            .collect()
     }
 
     pub fn files(&self) -> Ref<Vec<Rc<FileMap>>> {
@@ -150,7 +243,17 @@ impl CodeMap {
 
     /// Creates a new filemap without setting its line information. If you don't
     /// intend to set the line information yourself, you should use new_filemap_and_lines.
-    pub fn new_filemap(&self, filename: FileName, mut src: String) -> Rc<FileMap> {
+    pub fn new_filemap(&self, filename: FileName, src: String) -> Rc<FileMap> {
+        let (filename, was_remapped) = self.path_mapping.map_prefix(filename);
+        self.allocate_filemap(filename, was_remapped, src)
+    }
+
+    fn allocate_filemap(
+        &self,
+        filename: FileName,
+        was_remapped: bool,
+        mut src: String,
+    ) -> Rc<FileMap> {
         let start_pos = self.next_start_pos();
         let mut files = self.files.borrow_mut();
 
@@ -158,20 +261,33 @@ impl CodeMap {
         if src.starts_with("\u{feff}") {
             src.drain(..3);
         }
+        let src_hash = hash_source(&src);
 
-        let end_pos = start_pos + src.len();
+        let (position_map, original_src) = match self.normalize_options.get() {
+            Some(options) => {
+                let (normalized, map) = normalize_source(&src, options);
+                let original = mem::replace(&mut src, normalized);
+                (Some(Rc::new(map)), Some(Rc::new(original)))
+            }
+            None => (None, None),
+        };
 
-        let (filename, was_remapped) = self.path_mapping.map_prefix(filename);
+        let end_pos = start_pos + src.len();
 
         let filemap = Rc::new(FileMap {
             name: filename,
             name_was_remapped: was_remapped,
             crate_of_origin: 0,
             src: Some(Rc::new(src)),
+            src_hash,
+            external_src: RefCell::new(ExternalSource::Unneeded),
             start_pos: Pos::from_usize(start_pos),
             end_pos: Pos::from_usize(end_pos),
             lines: RefCell::new(Vec::new()),
             multibyte_chars: RefCell::new(Vec::new()),
+            position_map,
+            original_src,
+            stale: Cell::new(false),
         });
 
         files.push(filemap.clone());
@@ -179,9 +295,40 @@ impl CodeMap {
         filemap
     }
 
+    /// Replaces the contents of `old`, a filemap of this codemap, with
+    /// `new_src`. The new contents are allocated at fresh positions, so spans
+    /// into other filemaps stay valid, while `old` is marked stale: spans
+    /// into it can no longer be turned into lines or snippets. Like
+    /// `new_filemap`, the line information is left for the lexer to fill in.
+    pub fn replace_filemap(&self, old: &Rc<FileMap>, new_src: String) -> Rc<FileMap> {
+        assert!(
+            self.files.borrow().iter().any(|fm| Rc::ptr_eq(fm, old)),
+            "`{}` is not a filemap of this codemap",
+            old.name
+        );
+        old.mark_stale();
+        self.allocate_filemap(old.name.clone(), old.name_was_remapped, new_src)
+    }
+
+    /// How much of the codemap is taken up by stale filemaps, to tell when
+    /// rebuilding it from scratch is worthwhile.
+    pub fn garbage_stats(&self) -> GarbageStats {
+        let mut stats = GarbageStats::default();
+        for fm in self.files.borrow().iter() {
+            let len = fm.byte_length() as usize;
+            if fm.is_stale() {
+                stats.dead_files += 1;
+                stats.dead_bytes += len;
+            } else {
+                stats.live_bytes += len;
+            }
+        }
+        stats
+    }
+
     /// Creates a new filemap and sets its line information.
     pub fn new_filemap_and_lines(&self, filename: &str, src: &str) -> Rc<FileMap> {
-        let fm = self.new_filemap(filename.to_string(), src.to_owned());
+        let fm = self.new_filemap(filename.into(), src.to_owned());
         let mut byte_pos: u32 = fm.start_pos.0;
         for line in src.lines() {
             // register the start of this line
@@ -197,11 +344,13 @@ impl CodeMap {
     /// crate. The source code of such an "imported filemap" is not available,
     /// but we still know enough to generate accurate debuginfo location
     /// information for things inlined from other crates.
+    #[allow(clippy::too_many_arguments)]
     pub fn new_imported_filemap(
         &self,
         filename: FileName,
         name_was_remapped: bool,
         crate_of_origin: u32,
+        src_hash: u128,
         source_len: usize,
         mut file_local_lines: Vec<BytePos>,
         mut file_local_multibyte_chars: Vec<MultiByteChar>,
@@ -225,10 +374,15 @@ impl CodeMap {
             name_was_remapped: name_was_remapped,
             crate_of_origin: crate_of_origin,
             src: None,
+            src_hash,
+            external_src: RefCell::new(ExternalSource::Unneeded),
             start_pos: start_pos,
             end_pos: end_pos,
             lines: RefCell::new(file_local_lines),
             multibyte_chars: RefCell::new(file_local_multibyte_chars),
+            position_map: None,
+            original_src: None,
+            stale: Cell::new(false),
         });
 
         files.push(filemap.clone());
@@ -236,6 +390,74 @@ impl CodeMap {
         filemap
     }
 
+    /// Allocates an imported FileMap for each of the given foreign FileMaps
+    /// (e.g. ones deserialized from another compilation's metadata) and
+    /// returns the mappings needed to translate foreign spans into this
+    /// CodeMap via `translate_span`.
+    pub fn import_foreign_filemaps(&self, foreign: &[FileMap]) -> Vec<ImportedFileMapMapping> {
+        foreign
+            .iter()
+            .map(|filemap| {
+                let start_pos = filemap.start_pos;
+                let end_pos = filemap.end_pos;
+
+                // Translate line-start positions and multibyte char positions
+                // into frame of reference local to the file. `new_imported_filemap`
+                // will shift them back into the frame of this CodeMap.
+                let lines = filemap
+                    .lines
+                    .borrow()
+                    .iter()
+                    .map(|&pos| pos - start_pos)
+                    .collect();
+                let multibyte_chars = filemap
+                    .multibyte_chars
+                    .borrow()
+                    .iter()
+                    .map(|mbc| MultiByteChar {
+                        pos: mbc.pos - start_pos,
+                        bytes: mbc.bytes,
+                    })
+                    .collect();
+
+                let local_version = self.new_imported_filemap(
+                    filemap.name.clone(),
+                    filemap.name_was_remapped,
+                    filemap.crate_of_origin,
+                    filemap.src_hash,
+                    (end_pos - start_pos).to_usize(),
+                    lines,
+                    multibyte_chars,
+                );
+
+                ImportedFileMapMapping {
+                    original_start_pos: start_pos,
+                    original_end_pos: end_pos,
+                    translated_filemap: local_version,
+                }
+            })
+            .collect()
+    }
+
+    /// Translates a span from a foreign CodeMap into this one, using the
+    /// mappings returned by `import_foreign_filemaps`. The span is located by
+    /// its `lo` position; a `hi` position past the end of that file is clamped
+    /// to the end of the file. Returns `None` if no mapping contains the span.
+    pub fn translate_span(&self, span: Span, mappings: &[ImportedFileMapMapping]) -> Option<Span> {
+        let mapping = mappings.iter().find(|mapping| {
+            mapping.original_start_pos <= span.lo && span.lo <= mapping.original_end_pos
+        })?;
+
+        let hi = cmp::min(cmp::max(span.hi, span.lo), mapping.original_end_pos);
+        let translated_start_pos = mapping.translated_filemap.start_pos;
+
+        Some(Span {
+            lo: span.lo - mapping.original_start_pos + translated_start_pos,
+            hi: hi - mapping.original_start_pos + translated_start_pos,
+            ctxt: span.ctxt,
+        })
+    }
+
     pub fn mk_substr_filename(&self, sp: Span) -> String {
         let pos = self.lookup_char_pos(sp.lo);
         (format!(
@@ -265,10 +487,13 @@ impl CodeMap {
                 );
                 debug!("byte is on line: {}", line);
                 assert!(chpos >= linechpos);
+                // Normalization keeps line breaks, so only the column of a
+                // position in a normalized filemap differs from the original.
+                let col = f.original_col(a, pos).unwrap_or(chpos - linechpos);
                 Loc {
                     file: f,
                     line: line,
-                    col: chpos - linechpos,
+                    col: col,
                 }
             }
             Err(f) => Loc {
@@ -295,7 +520,7 @@ impl CodeMap {
     pub fn lookup_char_pos_adj(&self, pos: BytePos) -> LocWithOpt {
         let loc = self.lookup_char_pos(pos);
         LocWithOpt {
-            filename: loc.file.name.to_string(),
+            filename: loc.file.name.clone(),
             line: loc.line,
             col: loc.col,
             file: Some(loc.file),
@@ -342,6 +567,58 @@ impl CodeMap {
         }
     }
 
+    /// Returns the smallest span covering both `sp_lhs` and `sp_rhs`, in either order and
+    /// regardless of which lines they are on. Unlike `merge_spans`, the spans may overlap or
+    /// be far apart; `None` is returned if their ctxts differ or they are in different files.
+    pub fn span_union(&self, sp_lhs: Span, sp_rhs: Span) -> Option<Span> {
+        if sp_lhs.ctxt != sp_rhs.ctxt || self.files.borrow().is_empty() {
+            return None;
+        }
+
+        let file = self.lookup_filemap_idx(sp_lhs.lo);
+        let same_file = [sp_lhs.hi, sp_rhs.lo, sp_rhs.hi]
+            .iter()
+            .all(|&pos| self.lookup_filemap_idx(pos) == file);
+        if !same_file {
+            return None;
+        }
+
+        Some(Span {
+            lo: cmp::min(sp_lhs.lo, sp_rhs.lo),
+            hi: cmp::max(sp_lhs.hi, sp_rhs.hi),
+            ctxt: sp_lhs.ctxt,
+        })
+    }
+
+    /// Extends `sp` to start at the beginning of its first line and end at the end of its
+    /// last line, including that line's newline if it has one. A span that ends right after
+    /// a newline does not cover the following line. Returns `sp` unchanged if it cannot be
+    /// mapped to lines.
+    pub fn span_extend_to_line_boundaries(&self, sp: Span) -> Span {
+        if self.files.borrow().is_empty() {
+            return sp;
+        }
+
+        let last = if sp.hi > sp.lo {
+            sp.hi - BytePos(1)
+        } else {
+            sp.hi
+        };
+        let (first, last) = match (self.lookup_line(sp.lo), self.lookup_line(last)) {
+            (Ok(first), Ok(last)) => (first, last),
+            _ => return sp,
+        };
+        if first.fm.start_pos != last.fm.start_pos {
+            return sp;
+        }
+
+        Span {
+            lo: first.fm.line_bounds(first.line).0,
+            hi: last.fm.line_bounds(last.line).1,
+            ctxt: sp.ctxt,
+        }
+    }
+
     pub fn span_to_string(&self, sp: Span) -> String {
         if self.files.borrow().is_empty() && sp.source_equal(&DUMMY_SP) {
             return "no-location".to_string();
@@ -361,11 +638,20 @@ impl CodeMap {
     }
 
     pub fn span_to_filename(&self, sp: Span) -> FileName {
-        self.lookup_char_pos(sp.lo).file.name.to_string()
+        self.lookup_char_pos(sp.lo).file.name.clone()
     }
 
     pub fn span_to_lines(&self, sp: Span) -> FileLinesResult {
-        debug!("span_to_lines(sp={:?})", sp);
+        self.span_to_lines_limited(sp, usize::MAX)
+    }
+
+    /// Like `span_to_lines`, but returns at most `max_lines` lines (and at
+    /// least one), setting `truncated` if the span covers more.
+    pub fn span_to_lines_limited(&self, sp: Span, max_lines: usize) -> FileLinesResult {
+        debug!(
+            "span_to_lines_limited(sp={:?}, max_lines={})",
+            sp, max_lines
+        );
 
         if sp.lo > sp.hi {
             return Err(SpanLinesError::IllFormedSpan(sp));
@@ -376,6 +662,15 @@ impl CodeMap {
         let hi = self.lookup_char_pos(sp.hi);
         debug!("span_to_lines: hi={:?}", hi);
 
+        if lo.file.is_stale() {
+            return Err(SpanLinesError::StaleFileMap {
+                filename: lo.file.name.clone(),
+            });
+        }
+
+        // The length of each line is needed below.
+        self.ensure_filemap_source_present(lo.file.clone());
+
         if lo.file.start_pos != hi.file.start_pos {
             return Err(SpanLinesError::DistinctSources(DistinctSources {
                 begin: (lo.file.name.clone(), lo.file.start_pos),
@@ -384,7 +679,9 @@ impl CodeMap {
         }
         assert!(hi.line >= lo.line);
 
-        let mut lines = Vec::with_capacity(hi.line - lo.line + 1);
+        let line_count = hi.line - lo.line + 1;
+        let truncated = line_count > max_lines.max(1);
+        let mut lines = Vec::with_capacity(line_count.min(max_lines.max(1)));
 
         // The span starts partway through the first line,
         // but after that it starts from offset 0.
@@ -395,6 +692,9 @@ impl CodeMap {
         // numbers in Loc are 1-based, so we subtract 1 to get 0-based
         // lines.
         for line_index in lo.line - 1..hi.line - 1 {
+            if truncated && lines.len() == max_lines.max(1) {
+                break;
+            }
             let line_len = lo
                 .file
                 .get_line(line_index)
@@ -409,19 +709,64 @@ impl CodeMap {
         }
 
         // For the last line, it extends from `start_col` to `hi.col`:
-        lines.push(LineInfo {
-            line_index: hi.line - 1,
-            start_col: start_col,
-            end_col: hi.col,
-        });
+        if !truncated {
+            lines.push(LineInfo {
+                line_index: hi.line - 1,
+                start_col: start_col,
+                end_col: hi.col,
+            });
+        }
 
         Ok(FileLines {
             file: lo.file,
             lines: lines,
+            truncated,
         })
     }
 
-    pub fn span_to_snippet(&self, sp: Span) -> Result<String, SpanSnippetError> {
+    /// The lines `sp` covers, along with up to `context_lines` lines before
+    /// and after them, with the columns `sp` covers highlighted. An empty
+    /// span highlights the column it points at.
+    pub fn code_region(
+        &self,
+        sp: Span,
+        context_lines: usize,
+    ) -> Result<CodeRegion, SpanLinesError> {
+        let FileLines { file, lines, .. } = self.span_to_lines(sp)?;
+        let first = lines[0].line_index;
+        let last = lines[lines.len() - 1].line_index;
+        let line_count = cmp::max(file.count_lines(), last + 1);
+
+        let mut region = CodeRegion::new(
+            file,
+            first.saturating_sub(context_lines) + 1,
+            cmp::min(last + context_lines, line_count - 1) + 1,
+        );
+        for info in &lines {
+            let (start, mut end) = (info.start_col.to_usize(), info.end_col.to_usize());
+            if sp.lo == sp.hi {
+                end = start + 1;
+            } else if start == end {
+                continue;
+            }
+            let index = info.line_index + 1 - region.first_line;
+            region.highlights[index].push(start..end);
+        }
+        Ok(region)
+    }
+
+    /// Coalesces the regions of the same file that are at most `max_gap`
+    /// lines apart, see `CodeRegion::merge`.
+    pub fn merge_code_regions(regions: Vec<CodeRegion>, max_gap: usize) -> Vec<CodeRegion> {
+        CodeRegion::merge(regions, max_gap)
+    }
+
+    /// Calls `extract` with the source of the filemap `sp` is in and the range
+    /// of `sp` within it, or fails if that source is not available.
+    fn span_to_source<F, T>(&self, sp: Span, extract: F) -> Result<T, SpanSnippetError>
+    where
+        F: FnOnce(&str, usize, usize) -> T,
+    {
         if sp.lo > sp.hi {
             return Err(SpanSnippetError::IllFormedSpan(sp));
         }
@@ -434,6 +779,10 @@ impl CodeMap {
                 begin: (local_begin.fm.name.clone(), local_begin.fm.start_pos),
                 end: (local_end.fm.name.clone(), local_end.fm.start_pos),
             }));
+        } else if local_begin.fm.is_stale() {
+            Err(SpanSnippetError::StaleFileMap {
+                filename: local_begin.fm.name.clone(),
+            })
         } else {
             match local_begin.fm.src {
                 Some(ref src) => {
@@ -452,7 +801,7 @@ impl CodeMap {
                         ));
                     }
 
-                    return Ok((&src[start_index..end_index]).to_string());
+                    return Ok(extract(src, start_index, end_index));
                 }
                 None => {
                     return Err(SpanSnippetError::SourceNotAvailable {
@@ -463,6 +812,42 @@ impl CodeMap {
         }
     }
 
+    pub fn span_to_snippet(&self, sp: Span) -> Result<String, SpanSnippetError> {
+        self.span_to_source(sp, |src, start_index, end_index| {
+            src[start_index..end_index].to_string()
+        })
+    }
+
+    /// Whether `span_to_snippet` would succeed for `sp`, checked without
+    /// copying the snippet. Also false if the codemap has no files at all.
+    pub fn is_span_accessible(&self, sp: Span) -> bool {
+        if self.files.borrow().is_empty() {
+            return false;
+        }
+        self.span_to_source(sp, |src, start_index, end_index| {
+            src.is_char_boundary(start_index) && src.is_char_boundary(end_index)
+        })
+        .unwrap_or(false)
+    }
+
+    /// Whether unstable features may be used at `sp`: within a macro with
+    /// `allow_internal_unstable`, see `Span::allows_unstable`, which includes
+    /// the items added by `std_inject`, or in a filemap named after
+    /// `std_inject::STD_INJECT`, i.e. `<std_inject>`, holding source that was
+    /// injected into the crate rather than written by its author.
+    pub fn span_allows_unstable(&self, sp: Span) -> bool {
+        if sp.allows_unstable() {
+            return true;
+        }
+        if (sp.lo, sp.hi) == (DUMMY_SP.lo, DUMMY_SP.hi) || self.files.borrow().is_empty() {
+            return false;
+        }
+        match self.lookup_byte_offset(sp.lo).fm.name {
+            FileName::Custom(ref name) => name == crate::std_inject::STD_INJECT,
+            _ => false,
+        }
+    }
+
     /// Given a `Span`, try to get a shorter span ending before the first occurrence of `c` `char`
     pub fn span_until_char(&self, sp: Span, c: char) -> Span {
         match self.span_to_snippet(sp) {
@@ -470,7 +855,7 @@ impl CodeMap {
                 let snippet = snippet.split(c).nth(0).unwrap_or("").trim_end();
                 if !snippet.is_empty() && !snippet.contains('\n') {
                     Span {
-                        hi: BytePos(sp.lo.0 + snippet.len() as u32),
+                        hi: sp.lo.saturating_add(snippet.len() as u32),
                         ..sp
                     }
                 } else {
@@ -485,9 +870,23 @@ impl CodeMap {
         self.span_until_char(sp, '{')
     }
 
+    /// Whether `current_src` differs from the source of the filemap most
+    /// recently added for `path`, ignoring any byte order mark. A path with
+    /// no filemap is always stale.
+    pub fn stale(&self, path: &str, current_src: &str) -> bool {
+        let current_src = current_src.strip_prefix('\u{feff}').unwrap_or(current_src);
+        let files = self.files.borrow();
+        match files.iter().rev().find(|fm| fm.name == path) {
+            Some(fm) => fm.src_hash != hash_source(current_src),
+            None => true,
+        }
+    }
+
+    /// The live filemap named `filename`. Filemaps replaced by
+    /// `replace_filemap` are skipped.
     pub fn get_filemap(&self, filename: &str) -> Option<Rc<FileMap>> {
         for fm in self.files.borrow().iter() {
-            if filename == fm.name {
+            if fm.name == filename && !fm.is_stale() {
                 return Some(fm.clone());
             }
         }
@@ -564,6 +963,19 @@ impl CodeMap {
     }
 }
 
+impl SpanResolver for CodeMap {
+    fn span_to_string(&self, sp: Span) -> String {
+        CodeMap::span_to_string(self, sp)
+    }
+}
+
+/// Runs `f` with `{:?}` printing spans as their location in `cm` rather than
+/// as byte positions. The previous formatting is restored when `f` returns or
+/// panics, so scopes for different codemaps may nest.
+pub fn with_span_debug<R, F: FnOnce() -> R>(cm: &CodeMap, f: F) -> R {
+    crate::syntax_pos::with_span_resolver(cm, f)
+}
+
 impl CodeMapper for CodeMap {
     fn lookup_char_pos(&self, pos: BytePos) -> Loc {
         self.lookup_char_pos(pos)
@@ -571,6 +983,9 @@ impl CodeMapper for CodeMap {
     fn span_to_lines(&self, sp: Span) -> FileLinesResult {
         self.span_to_lines(sp)
     }
+    fn span_to_lines_limited(&self, sp: Span, max_lines: usize) -> FileLinesResult {
+        self.span_to_lines_limited(sp, max_lines)
+    }
     fn span_to_string(&self, sp: Span) -> String {
         self.span_to_string(sp)
     }
@@ -580,6 +995,33 @@ impl CodeMapper for CodeMap {
     fn merge_spans(&self, sp_lhs: Span, sp_rhs: Span) -> Option<Span> {
         self.merge_spans(sp_lhs, sp_rhs)
     }
+    fn ensure_filemap_source_present(&self, file_map: Rc<FileMap>) -> bool {
+        self.ensure_filemap_source_present(file_map)
+    }
+}
+
+/// The space in a `CodeMap` taken up by filemaps, as reported by
+/// `CodeMap::garbage_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GarbageStats {
+    /// The number of filemaps that have been replaced.
+    pub dead_files: usize,
+    /// The source bytes of filemaps that have been replaced.
+    pub dead_bytes: usize,
+    /// The source bytes of all other filemaps.
+    pub live_bytes: usize,
+}
+
+/// Records where a FileMap from a foreign CodeMap ended up after being
+/// imported into the local one.
+#[derive(Clone, Debug)]
+pub struct ImportedFileMapMapping {
+    /// The start of this FileMap within the foreign CodeMap.
+    pub original_start_pos: BytePos,
+    /// The end of this FileMap within the foreign CodeMap.
+    pub original_end_pos: BytePos,
+    /// The imported FileMap's representation within the local CodeMap.
+    pub translated_filemap: Rc<FileMap>,
 }
 
 #[derive(Clone)]
@@ -599,21 +1041,270 @@ impl FilePathMapping {
     /// Applies any path prefix substitution as defined by the mapping.
     /// The return value is the remapped path and a boolean indicating whether
     /// the path was affected by the mapping.
-    pub fn map_prefix(&self, path: String) -> (String, bool) {
+    /// Only the names of real files are remapped.
+    pub fn map_prefix(&self, name: FileName) -> (FileName, bool) {
+        let path = match name {
+            FileName::Real(ref path) => path.to_string_lossy(),
+            _ => return (name, false),
+        };
         // NOTE: We are iterating over the mapping entries from last to first
         //       because entries specified later on the command line should
         //       take precedence.
         for &(ref from, ref to) in self.mapping.iter().rev() {
-            if path.starts_with(from) {
-                let mapped = path.replacen(from, to, 1);
-                return (mapped, true);
+            if path.starts_with(from.as_str()) {
+                let mapped = path.replacen(from.as_str(), to, 1);
+                return (FileName::Real(PathBuf::from(mapped)), true);
             }
         }
 
-        (path, false)
+        (name, false)
     }
 }
 
+// _____________________________________________________________________________
+// Source normalization
+//
+
+/// What `normalize_source` changes in source text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NormalizeOptions {
+    /// Remove comments other than doc comments. A line comment is removed up
+    /// to its line break, and a block comment is replaced by a space followed
+    /// by the line breaks it contained, so that line numbers are preserved.
+    pub strip_comments: bool,
+    /// Replace `\r\n` line endings with `\n`.
+    pub normalize_crlf: bool,
+}
+
+impl Default for NormalizeOptions {
+    fn default() -> NormalizeOptions {
+        NormalizeOptions {
+            strip_comments: true,
+            normalize_crlf: true,
+        }
+    }
+}
+
+/// Normalizes `src` as described by `options`, returning the normalized text
+/// and a map from its offsets to those in `src`. String and character
+/// literals are left alone, apart from their line endings.
+pub fn normalize_source(src: &str, options: NormalizeOptions) -> (String, PositionMap) {
+    let mut normalizer = Normalizer {
+        src,
+        options,
+        out: String::with_capacity(src.len()),
+        map: PositionMap::new(),
+        copied: 0,
+    };
+    if options.strip_comments {
+        for (start, end) in comment_ranges(src) {
+            normalizer.strip_comment(start, end);
+        }
+    }
+    normalizer.copy_to(src.len());
+    let Normalizer { out, map, .. } = normalizer;
+    (out, map)
+}
+
+/// The byte ranges of the comments in `src` other than doc comments, as
+/// found by the lexer. Lexing stops at the first fatal error, such as an
+/// unterminated literal, after which nothing is a comment.
+fn comment_ranges(src: &str) -> Vec<(usize, usize)> {
+    let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+    let emitter = EmitterWriter::new(Box::new(io::sink()), None);
+    let handler = Handler::with_emitter(false, false, Box::new(emitter));
+    let sess = ParseSess::with_span_handler(handler, cm.clone());
+    let fm = cm.new_filemap(
+        FileName::Custom("normalized source".to_string()),
+        src.to_owned(),
+    );
+    let offset = |pos: BytePos| (pos - fm.start_pos).to_usize();
+
+    let mut ranges = Vec::new();
+    let mut reader = match lexer::StringReader::maybe_new(&sess, fm.clone()) {
+        Ok(reader) => reader,
+        Err(mut err) => {
+            err.cancel();
+            return ranges;
+        }
+    };
+    loop {
+        match reader.try_next_token() {
+            Ok(TokenAndSpan {
+                tok: token::Eof, ..
+            }) => break,
+            Ok(TokenAndSpan {
+                tok: token::Comment,
+                sp,
+            }) => ranges.push((offset(sp.lo), offset(sp.hi))),
+            Ok(_) => {}
+            Err(()) => {
+                reader.emit_fatal_errors();
+                break;
+            }
+        }
+    }
+    ranges
+}
+
+struct Normalizer<'a> {
+    src: &'a str,
+    options: NormalizeOptions,
+    out: String,
+    map: PositionMap,
+    /// The offset in `src` up to which `out` is complete.
+    copied: usize,
+}
+
+impl<'a> Normalizer<'a> {
+    /// Replace `src[start..end]` with `replacement`, copying the text before
+    /// it unchanged.
+    fn replace(&mut self, start: usize, end: usize, replacement: &str) {
+        self.out.push_str(&self.src[self.copied..start]);
+        self.map
+            .push_edit(start, self.out.len(), end - start, replacement.len());
+        self.out.push_str(replacement);
+        self.copied = end;
+    }
+
+    /// Copy the text up to `end`, normalizing its line endings.
+    fn copy_to(&mut self, end: usize) {
+        if self.options.normalize_crlf {
+            let bytes = self.src.as_bytes();
+            for pos in self.copied..end {
+                if bytes[pos] == b'\r' && bytes.get(pos + 1) == Some(&b'\n') {
+                    self.replace(pos, pos + 1, "");
+                }
+            }
+        }
+        self.out.push_str(&self.src[self.copied..end]);
+        self.copied = end;
+    }
+
+    /// Remove the comment at `src[start..end]`. A block comment is replaced by
+    /// a space followed by the line breaks it contains, so that line numbers
+    /// are preserved.
+    fn strip_comment(&mut self, start: usize, end: usize) {
+        self.copy_to(start);
+        let bytes = self.src.as_bytes();
+        let mut removed_from = start;
+        let mut replacement = if bytes[start + 1] == b'*' { " " } else { "" };
+        for pos in start..end {
+            let line_break = match bytes[pos] {
+                b'\n' => pos,
+                b'\r' if !self.options.normalize_crlf && bytes.get(pos + 1) == Some(&b'\n') => pos,
+                _ => continue,
+            };
+            if line_break >= removed_from {
+                self.replace(removed_from, line_break, replacement);
+                replacement = "";
+            }
+            removed_from = pos + 1;
+        }
+        self.replace(removed_from, end, replacement);
+    }
+}
+
+// _____________________________________________________________________________
+// Source editing
+//
+
+/// Why `apply_edits` could not apply a set of edits.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum EditError {
+    /// The span of an edit ends before it starts.
+    IllFormedSpan(Span),
+    /// The span of an edit is not within the file being edited.
+    SpanOutsideFile(Span),
+    /// The spans of two edits overlap.
+    Overlap(Span, Span),
+    /// The span of an edit starts or ends within a multi-byte character.
+    NotCharBoundary(Span),
+    SourceNotAvailable {
+        filename: FileName,
+    },
+}
+
+/// Replaces the text at the span of each edit in `file` with the edit's
+/// string, returning the new source of the file. Zero-width spans insert
+/// text; several insertions at the same position are made in the order
+/// given. The edits may be in any order but must not overlap.
+pub fn apply_edits(
+    cm: &CodeMap,
+    file: &FileMap,
+    edits: &[(Span, String)],
+) -> Result<String, EditError> {
+    let src = match file.src {
+        Some(ref src) => src.clone(),
+        None => {
+            let fm = cm.lookup_byte_offset(file.start_pos).fm;
+            cm.ensure_filemap_source_present(fm.clone());
+            let src = match *fm.external_src.borrow() {
+                ExternalSource::Present(ref src) => src.clone(),
+                _ => {
+                    return Err(EditError::SourceNotAvailable {
+                        filename: file.name.clone(),
+                    });
+                }
+            };
+            src
+        }
+    };
+
+    let mut edits: Vec<&(Span, String)> = edits.iter().collect();
+    for &&(span, _) in &edits {
+        if span.lo > span.hi {
+            return Err(EditError::IllFormedSpan(span));
+        }
+        if span.lo < file.start_pos || span.hi > file.end_pos {
+            return Err(EditError::SpanOutsideFile(span));
+        }
+    }
+    for &&(span, _) in &edits {
+        let lo = (span.lo - file.start_pos).to_usize();
+        let hi = (span.hi - file.start_pos).to_usize();
+        if !src.is_char_boundary(lo) || !src.is_char_boundary(hi) {
+            return Err(EditError::NotCharBoundary(span));
+        }
+    }
+    edits.sort_by_key(|&&(span, _)| (span.lo, span.hi));
+    for pair in edits.windows(2) {
+        if pair[1].0.lo < pair[0].0.hi {
+            return Err(EditError::Overlap(pair[0].0, pair[1].0));
+        }
+    }
+
+    let mut result = String::with_capacity(src.len());
+    let mut copied = 0;
+    for &&(span, ref replacement) in &edits {
+        let lo = (span.lo - file.start_pos).to_usize();
+        result.push_str(&src[copied..lo]);
+        result.push_str(replacement);
+        copied = (span.hi - file.start_pos).to_usize();
+    }
+    result.push_str(&src[copied..]);
+    Ok(result)
+}
+
+/// Applies `suggestion` to `file` with `apply_edits`, using the first
+/// alternative of each of its substitutions.
+pub fn apply_suggestion(
+    cm: &CodeMap,
+    file: &FileMap,
+    suggestion: &CodeSuggestion,
+) -> Result<String, EditError> {
+    let edits: Vec<_> = suggestion
+        .substitution_parts
+        .iter()
+        .filter_map(|part| {
+            part.substitutions
+                .first()
+                .map(|substitution| (part.span, substitution.clone()))
+        })
+        .collect();
+    apply_edits(cm, file, &edits)
+}
+
 // _____________________________________________________________________________
 // Tests
 //
@@ -621,33 +1312,28 @@ impl FilePathMapping {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::ops::Range;
     use std::rc::Rc;
 
     #[test]
     fn t1() {
         let cm = CodeMap::new(FilePathMapping::empty());
-        let fm = cm.new_filemap(
-            "blork.rs".to_string(),
-            "first line.\nsecond line".to_string(),
-        );
+        let fm = cm.new_filemap("blork.rs".into(), "first line.\nsecond line".to_string());
         fm.next_line(BytePos(0));
         // Test we can get lines with partial line info.
-        assert_eq!(fm.get_line(0), Some("first line."));
+        assert_eq!(fm.get_line(0).as_deref(), Some("first line."));
         // TESTING BROKEN BEHAVIOR: line break declared before actual line break.
         fm.next_line(BytePos(10));
-        assert_eq!(fm.get_line(1), Some("."));
+        assert_eq!(fm.get_line(1).as_deref(), Some("."));
         fm.next_line(BytePos(12));
-        assert_eq!(fm.get_line(2), Some("second line"));
+        assert_eq!(fm.get_line(2).as_deref(), Some("second line"));
     }
 
     #[test]
     #[should_panic]
     fn t2() {
         let cm = CodeMap::new(FilePathMapping::empty());
-        let fm = cm.new_filemap(
-            "blork.rs".to_string(),
-            "first line.\nsecond line".to_string(),
-        );
+        let fm = cm.new_filemap("blork.rs".into(), "first line.\nsecond line".to_string());
         // TESTING *REALLY* BROKEN BEHAVIOR:
         fm.next_line(BytePos(0));
         fm.next_line(BytePos(10));
@@ -656,15 +1342,9 @@ mod tests {
 
     fn init_code_map() -> CodeMap {
         let cm = CodeMap::new(FilePathMapping::empty());
-        let fm1 = cm.new_filemap(
-            "blork.rs".to_string(),
-            "first line.\nsecond line".to_string(),
-        );
-        let fm2 = cm.new_filemap("empty.rs".to_string(), "".to_string());
-        let fm3 = cm.new_filemap(
-            "blork2.rs".to_string(),
-            "first line.\nsecond line".to_string(),
-        );
+        let fm1 = cm.new_filemap("blork.rs".into(), "first line.\nsecond line".to_string());
+        let fm2 = cm.new_filemap("empty.rs".into(), "".to_string());
+        let fm3 = cm.new_filemap("blork2.rs".into(), "first line.\nsecond line".to_string());
 
         fm1.next_line(BytePos(0));
         fm1.next_line(BytePos(12));
@@ -725,11 +1405,11 @@ mod tests {
         let cm = CodeMap::new(FilePathMapping::empty());
         // € is a three byte utf8 char.
         let fm1 = cm.new_filemap(
-            "blork.rs".to_string(),
+            "blork.rs".into(),
             "fir€st €€€€ line.\nsecond line".to_string(),
         );
         let fm2 = cm.new_filemap(
-            "blork2.rs".to_string(),
+            "blork2.rs".into(),
             "first line€€.\n€ second line".to_string(),
         );
 
@@ -832,6 +1512,17 @@ mod tests {
             },
         ];
         assert_eq!(lines.lines, expected);
+        assert!(!lines.truncated);
+
+        let limited = cm.span_to_lines_limited(span, 2).unwrap();
+        assert_eq!(limited.lines, expected[..2]);
+        assert!(limited.truncated);
+        let limited = cm.span_to_lines_limited(span, 0).unwrap();
+        assert_eq!(limited.lines, expected[..1]);
+        assert!(limited.truncated);
+        let limited = cm.span_to_lines_limited(span, 3).unwrap();
+        assert_eq!(limited.lines, expected);
+        assert!(!limited.truncated);
     }
 
     #[test]
@@ -876,6 +1567,361 @@ mod tests {
         assert!(cm.merge_spans(span1, span2).is_none());
     }
 
+    /// Test unioning spans across lines, in either order and overlapping
+    #[test]
+    fn span_union() {
+        let cm = CodeMap::new(FilePathMapping::empty());
+        let inputtext = "aaaa AA\nbb BBB\ncc C";
+        let selection1 = "     ~~\n      \n    ";
+        let selection2 = "       \n      \n   ~";
+        let selection3 = "    ~~~\n~~    \n    ";
+        cm.new_filemap_and_lines("blork.rs", inputtext);
+        let span1 = span_from_selection(inputtext, selection1);
+        let span2 = span_from_selection(inputtext, selection2);
+        let span3 = span_from_selection(inputtext, selection3);
+
+        let union = cm.span_union(span1, span2).unwrap();
+        assert_eq!(cm.span_to_snippet(union).unwrap(), "AA\nbb BBB\ncc C");
+        assert_eq!(cm.span_union(span2, span1), Some(union));
+
+        let union = cm.span_union(span1, span3).unwrap();
+        assert_eq!(cm.span_to_snippet(union).unwrap(), " AA\nbb");
+
+        let mark = SyntaxContext::empty().apply_mark(hygiene::Mark::fresh(hygiene::Mark::root()));
+        assert!(cm
+            .span_union(
+                span1,
+                Span {
+                    ctxt: mark,
+                    ..span2
+                }
+            )
+            .is_none());
+    }
+
+    /// Test that spans in different files are never unioned
+    #[test]
+    fn span_union_different_files() {
+        let cm = init_code_map();
+        let span1 = Span {
+            lo: BytePos(0),
+            hi: BytePos(5),
+            ctxt: NO_EXPANSION,
+        };
+        let span2 = Span {
+            lo: BytePos(25),
+            hi: BytePos(30),
+            ctxt: NO_EXPANSION,
+        };
+        assert!(cm.span_union(span1, span2).is_none());
+    }
+
+    /// Test extending spans to the boundaries of the lines they cover
+    #[test]
+    fn span_extend_to_line_boundaries() {
+        let cm = CodeMap::new(FilePathMapping::empty());
+        let inputtext = "aaaa AA\nbb BBB\ncc C";
+        cm.new_filemap_and_lines("blork.rs", inputtext);
+        let extend = |selection: &str| {
+            let span = cm.span_extend_to_line_boundaries(span_from_selection(inputtext, selection));
+            cm.span_to_snippet(span).unwrap()
+        };
+
+        assert_eq!(extend("     ~~\n      \n    "), "aaaa AA\n");
+        assert_eq!(extend("     ~~\n~~    \n    "), "aaaa AA\nbb BBB\n");
+        // the last line of the file has no trailing newline
+        assert_eq!(extend("       \n   ~~~\n~~  "), "bb BBB\ncc C");
+        assert_eq!(extend("       \n      \n   ~"), "cc C");
+        // a span ending right after a newline does not reach into the next line
+        assert_eq!(extend("  ~~~~~~      \n    "), "aaaa AA\n");
+
+        let empty = Span {
+            lo: BytePos(10),
+            hi: BytePos(10),
+            ctxt: NO_EXPANSION,
+        };
+        let span = cm.span_extend_to_line_boundaries(empty);
+        assert_eq!(cm.span_to_snippet(span).unwrap(), "bb BBB\n");
+    }
+
+    /// The first line, text and highlights of a region.
+    fn region_parts(region: &CodeRegion) -> (usize, String, Vec<Vec<Range<usize>>>) {
+        (
+            region.first_line,
+            region.lines.join("\n"),
+            region.highlights.clone(),
+        )
+    }
+
+    #[test]
+    fn code_region_context() {
+        let cm = CodeMap::new(FilePathMapping::empty());
+        let inputtext = "aaaa AA\nbb BBB\ncc C\ndd\nee E\nff";
+        cm.new_filemap_and_lines("blork.rs", inputtext);
+        let region = |selection: &str, context_lines| {
+            let selection = format!("{:1$}", selection, inputtext.len());
+            let sp = span_from_selection(inputtext, &selection);
+            region_parts(&cm.code_region(sp, context_lines).unwrap())
+        };
+
+        // At the start of the file there are fewer context lines above.
+        assert_eq!(
+            region("~~~~", 2),
+            (
+                1,
+                "aaaa AA\nbb BBB\ncc C".to_string(),
+                vec![vec![0..4], vec![], vec![]]
+            )
+        );
+        assert_eq!(
+            region("       \n   ~~~\n~", 1),
+            (
+                1,
+                "aaaa AA\nbb BBB\ncc C\ndd".to_string(),
+                vec![vec![], vec![3..6], vec![0..1], vec![]]
+            )
+        );
+        // And at its end fewer below.
+        assert_eq!(
+            region("       \n      \n    \n  \n    \n ~", 3),
+            (
+                3,
+                "cc C\ndd\nee E\nff".to_string(),
+                vec![vec![], vec![], vec![], vec![1..2]]
+            )
+        );
+        assert_eq!(
+            region("       \n      \n  ~", 0),
+            (3, "cc C".to_string(), vec![vec![2..3]])
+        );
+
+        let empty = Span {
+            lo: BytePos(10),
+            hi: BytePos(10),
+            ctxt: NO_EXPANSION,
+        };
+        let region = cm.code_region(empty, 0).unwrap();
+        assert_eq!(
+            region_parts(&region),
+            (2, "bb BBB".to_string(), vec![vec![2..3]])
+        );
+
+        let ill_formed = Span {
+            lo: BytePos(10),
+            hi: BytePos(9),
+            ctxt: NO_EXPANSION,
+        };
+        assert_eq!(
+            cm.code_region(ill_formed, 1).unwrap_err(),
+            SpanLinesError::IllFormedSpan(ill_formed)
+        );
+    }
+
+    #[test]
+    fn merge_code_regions() {
+        let cm = CodeMap::new(FilePathMapping::empty());
+        let inputtext = "l1\nl2 x\nl3\nl4\nl5 y\nl6\nl7\nl8\nl9\nl10 z";
+        let fm = cm.new_filemap_and_lines("blork.rs", inputtext);
+        let other = cm.new_filemap_and_lines("other.rs", "o1 w\no2");
+        let at = |offset: usize, len: usize| {
+            let lo = fm.start_pos + BytePos(offset as u32);
+            Span {
+                lo,
+                hi: lo + BytePos(len as u32),
+                ctxt: NO_EXPANSION,
+            }
+        };
+        let x = at(inputtext.find('x').unwrap(), 1);
+        let y = at(inputtext.find('y').unwrap(), 1);
+        let z = at(inputtext.find('z').unwrap(), 1);
+        let w = Span {
+            lo: other.start_pos + BytePos(3),
+            hi: other.start_pos + BytePos(4),
+            ctxt: NO_EXPANSION,
+        };
+        let regions = |spans: &[Span], context_lines, max_gap| {
+            let regions = spans
+                .iter()
+                .map(|&sp| cm.code_region(sp, context_lines).unwrap())
+                .collect();
+            CodeMap::merge_code_regions(regions, max_gap)
+        };
+
+        // `x` and `y` are three lines apart, with two lines between them.
+        let merged = regions(&[y, x], 0, 2);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(
+            region_parts(&merged[0]),
+            (
+                2,
+                "l2 x\nl3\nl4\nl5 y".to_string(),
+                vec![vec![3..4], vec![], vec![], vec![3..4]]
+            )
+        );
+        let merged = regions(&[x, y], 0, 1);
+        let firsts: Vec<_> = merged
+            .iter()
+            .map(|r| (r.first_line, r.last_line()))
+            .collect();
+        assert_eq!(firsts, [(2, 2), (5, 5)]);
+
+        // Overlapping context merges, and the highlights of a shared line
+        // are combined.
+        let merged = regions(&[x, y, z, w], 1, 0);
+        let firsts: Vec<_> = merged
+            .iter()
+            .map(|r| (r.file.name.to_string(), r.first_line, r.last_line()))
+            .collect();
+        assert_eq!(
+            firsts,
+            [
+                ("blork.rs".to_string(), 1, 6),
+                ("blork.rs".to_string(), 9, 10),
+                ("other.rs".to_string(), 1, 2),
+            ]
+        );
+        let l5 = at(inputtext.find("l5").unwrap(), 2);
+        let merged = regions(&[y, l5], 0, 0);
+        assert_eq!(
+            region_parts(&merged[0]),
+            (5, "l5 y".to_string(), vec![vec![0..2, 3..4]])
+        );
+    }
+
+    /// Test translating a span through a serialized FileMap
+    #[test]
+    fn imported_filemap_translation() {
+        let foreign_cm = CodeMap::new(FilePathMapping::empty());
+        foreign_cm.new_filemap_and_lines("padding.rs", "fn pad() {}\n");
+        let fm = foreign_cm.new_filemap_and_lines("blork.rs", "aaaaa\nbb€bBB\nCCC\n");
+        fm.record_multibyte_char(fm.start_pos + BytePos(8), 3);
+        // "BB\nCCC"
+        let span = Span {
+            lo: fm.start_pos + BytePos(12),
+            hi: fm.start_pos + BytePos(18),
+            ctxt: NO_EXPANSION,
+        };
+
+        let serialized = serde_json::to_string(&*foreign_cm.files()).unwrap();
+        let foreign: Vec<FileMap> = serde_json::from_str(&serialized).unwrap();
+
+        let cm = CodeMap::new(FilePathMapping::empty());
+        cm.new_filemap_and_lines("local.rs", "fn main() {\n}\n");
+        let mappings = cm.import_foreign_filemaps(&foreign);
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings[1].translated_filemap.name, "blork.rs");
+
+        let translated = cm.translate_span(span, &mappings).unwrap();
+        assert_ne!(translated, span);
+        assert_eq!(
+            cm.span_to_string(translated),
+            foreign_cm.span_to_string(span)
+        );
+        assert_eq!(cm.span_to_string(translated), "blork.rs:2:5: 3:4");
+
+        // Positions past the end of the file are clamped.
+        let overlong = Span {
+            hi: fm.end_pos + BytePos(10),
+            ..span
+        };
+        let translated = cm.translate_span(overlong, &mappings).unwrap();
+        assert_eq!(translated.hi, mappings[1].translated_filemap.end_pos);
+
+        // Positions outside of any imported file don't translate.
+        let outside = Span {
+            lo: fm.end_pos + BytePos(10),
+            hi: fm.end_pos + BytePos(12),
+            ctxt: NO_EXPANSION,
+        };
+        assert!(cm.translate_span(outside, &mappings).is_none());
+    }
+
+    #[test]
+    fn replaced_filemaps() {
+        let cm = CodeMap::new(FilePathMapping::empty());
+        let old = cm.new_filemap_and_lines("edited.rs", "fn old() {}");
+        let other = cm.new_filemap_and_lines("other.rs", "fn other() {}");
+        let span_in = |fm: &FileMap, lo: u32, hi: u32| Span {
+            lo: fm.start_pos + BytePos(lo),
+            hi: fm.start_pos + BytePos(hi),
+            ctxt: NO_EXPANSION,
+        };
+        let old_span = span_in(&old, 3, 6);
+        let other_span = span_in(&other, 3, 8);
+
+        let new = cm.replace_filemap(&old, "fn newer() {}".to_string());
+        assert!(old.is_stale() && !new.is_stale());
+        assert_eq!(new.name, "edited.rs");
+        assert!(new.start_pos > other.end_pos);
+
+        let stale = FileName::from("edited.rs");
+        assert_eq!(
+            cm.span_to_snippet(old_span),
+            Err(SpanSnippetError::StaleFileMap {
+                filename: stale.clone()
+            })
+        );
+        match cm.span_to_lines(old_span) {
+            Err(SpanLinesError::StaleFileMap { filename }) => assert_eq!(filename, stale),
+            _ => panic!("expected the old span to be stale"),
+        }
+        assert_eq!(cm.span_to_snippet(other_span), Ok("other".to_string()));
+        assert_eq!(
+            cm.span_to_snippet(span_in(&new, 3, 8)),
+            Ok("newer".to_string())
+        );
+        assert!(!cm.stale("edited.rs", "fn newer() {}"));
+        assert!(Rc::ptr_eq(&cm.get_filemap("edited.rs").unwrap(), &new));
+
+        assert_eq!(
+            cm.garbage_stats(),
+            GarbageStats {
+                dead_files: 1,
+                dead_bytes: 11,
+                live_bytes: 26,
+            }
+        );
+    }
+
+    #[test]
+    fn stale_filemaps() {
+        let cm = CodeMap::new(FilePathMapping::empty());
+        let mut src = "fn main() {}\n".to_string();
+        cm.new_filemap_and_lines("main.rs", &src);
+        assert!(!cm.stale("main.rs", &src));
+        assert!(!cm.stale("main.rs", &format!("\u{feff}{}", src)));
+        assert!(cm.stale("other.rs", &src));
+
+        src.insert(10, 'x');
+        assert!(cm.stale("main.rs", &src));
+        // The most recently added filemap for a path is the one compared.
+        cm.new_filemap_and_lines("main.rs", &src);
+        assert!(!cm.stale("main.rs", &src));
+    }
+
+    #[test]
+    fn src_hash_round_trip() {
+        let foreign_cm = CodeMap::new(FilePathMapping::empty());
+        let src = "fn lib() {}\n";
+        let fm = foreign_cm.new_filemap_and_lines("lib.rs", src);
+        assert_eq!(fm.src_hash, hash_source(src));
+
+        let serialized = serde_json::to_string(&*foreign_cm.files()).unwrap();
+        let foreign: Vec<FileMap> = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(foreign[0].src_hash, fm.src_hash);
+
+        let cm = CodeMap::new(FilePathMapping::empty());
+        let mappings = cm.import_foreign_filemaps(&foreign);
+        let imported = &mappings[0].translated_filemap;
+        assert_eq!(imported.src_hash, fm.src_hash);
+
+        // Source of the right length but different content is rejected.
+        assert!(!imported.add_external_src(|| Some("fn fib() {}\n".to_string())));
+        let cm = CodeMap::new(FilePathMapping::empty());
+        let imported = &cm.import_foreign_filemaps(&foreign)[0].translated_filemap;
+        assert!(imported.add_external_src(|| Some(src.to_string())));
+    }
+
     /// Returns the span corresponding to the `n`th occurrence of
     /// `substring` in `source_text`.
     trait CodeMapExtension {
@@ -924,4 +1970,421 @@ mod tests {
             }
         }
     }
+
+    const CRLF_SRC: &str = "fn f() {\r\n    /* a\r\n b */ let x = 1; // c\r\n}\r\n";
+
+    /// Every byte kept by normalization maps to the same byte in `src` and
+    /// back again.
+    fn assert_round_trip(src: &str, normalized: &str, map: &PositionMap) {
+        for (n, byte) in normalized.bytes().enumerate() {
+            let o = map.to_original(n);
+            assert_eq!(map.to_normalized(o), Some(n));
+            if byte != b' ' {
+                assert_eq!(src.as_bytes()[o], byte, "at {}", n);
+            }
+        }
+        assert_eq!(map.to_original(normalized.len()), src.len());
+        assert_eq!(map.to_normalized(src.len()), Some(normalized.len()));
+    }
+
+    #[test]
+    fn normalize_crlf_and_comments() {
+        let (normalized, map) = normalize_source(CRLF_SRC, NormalizeOptions::default());
+        assert_eq!(normalized, "fn f() {\n     \n let x = 1; \n}\n");
+        assert_round_trip(CRLF_SRC, &normalized, &map);
+
+        let let_pos = normalized.find("let").unwrap();
+        assert_eq!(map.to_original(let_pos), CRLF_SRC.find("let").unwrap());
+        // Offsets within a removed comment have no normalized position, but
+        // its start maps to the space that replaced it.
+        let comment = CRLF_SRC.find("/*").unwrap();
+        assert_eq!(
+            map.to_normalized(comment),
+            Some(normalized.find("  \n").unwrap() + 1)
+        );
+        assert_eq!(map.to_normalized(comment + 1), None);
+        assert_eq!(map.to_normalized(CRLF_SRC.find(" b").unwrap() + 1), None);
+        assert_eq!(map.to_normalized(CRLF_SRC.find("\r\n").unwrap()), Some(8));
+
+        let options = NormalizeOptions {
+            strip_comments: true,
+            normalize_crlf: false,
+        };
+        let (normalized, map) = normalize_source(CRLF_SRC, options);
+        assert_eq!(normalized, "fn f() {\r\n     \r\n let x = 1; \r\n}\r\n");
+        assert_round_trip(CRLF_SRC, &normalized, &map);
+
+        let options = NormalizeOptions {
+            strip_comments: false,
+            normalize_crlf: true,
+        };
+        let (normalized, map) = normalize_source(CRLF_SRC, options);
+        assert_eq!(normalized, CRLF_SRC.replace("\r\n", "\n"));
+        assert_round_trip(CRLF_SRC, &normalized, &map);
+    }
+
+    #[test]
+    fn normalize_keeps_literals_and_doc_comments() {
+        let src = "/// doc\r\n//! inner\n/** block */ ////\nlet s = \"// a\r\n\"; \
+                   let c = '\"'; r#\"/* b \"*/\"#; br\"//\"; 'a /* c /* d */ */\n";
+        let (normalized, map) = normalize_source(src, NormalizeOptions::default());
+        assert_eq!(
+            normalized,
+            "/// doc\n//! inner\n/** block */ \nlet s = \"// a\n\"; \
+             let c = '\"'; r#\"/* b \"*/\"#; br\"//\"; 'a  \n"
+        );
+        assert_round_trip(src, &normalized, &map);
+    }
+
+    #[test]
+    fn normalized_filemap() {
+        let cm = CodeMap::new(FilePathMapping::empty());
+        cm.new_filemap("padding.rs".into(), "fn pad() {}\n".to_string());
+        cm.set_normalize_options(Some(NormalizeOptions::default()));
+        let fm = cm.new_filemap("crlf.rs".into(), CRLF_SRC.to_string());
+        let normalized = "fn f() {\n     \n let x = 1; \n}\n";
+        assert_eq!(fm.src.as_ref().unwrap().as_str(), normalized);
+        assert_eq!(fm.byte_length() as usize, normalized.len());
+
+        let let_offset = CRLF_SRC.find("let").unwrap();
+        let let_pos = fm.start_pos + BytePos::from_usize(normalized.find("let").unwrap());
+        assert_eq!(fm.original_offset(let_pos), let_offset);
+        assert_eq!(fm.normalized_pos(let_offset), Some(let_pos));
+        assert_eq!(fm.normalized_pos(let_offset - 2), None);
+        assert_eq!(fm.original_offset(fm.end_pos), CRLF_SRC.len());
+
+        // Block comments keep their line breaks, so lines are unchanged, and
+        // the lines and columns reported are those of the original text.
+        let original_lines = CRLF_SRC.lines().collect::<Vec<_>>();
+        for (i, line) in normalized.lines().enumerate() {
+            fm.next_line(fm.start_pos + BytePos::from_usize(normalized.find(line).unwrap()));
+            assert_eq!(fm.get_line(i).unwrap(), original_lines[i]);
+        }
+        let loc = cm.lookup_char_pos(let_pos);
+        assert_eq!((loc.line, loc.col), (3, CharPos(6)));
+        let let_x = Span {
+            lo: let_pos,
+            hi: let_pos + BytePos(5),
+            ctxt: NO_EXPANSION,
+        };
+        assert_eq!(cm.span_to_string(let_x), "crlf.rs:3:7: 3:12");
+        // The line break after the removed line comment follows its `\r`.
+        let loc = cm.lookup_char_pos(let_pos + BytePos::from_usize("let x = 1; ".len()));
+        assert_eq!(
+            (loc.line, loc.col),
+            (3, CharPos(original_lines[2].len() + 1))
+        );
+    }
+
+    fn edit(fm: &FileMap, lo: usize, hi: usize, text: &str) -> (Span, String) {
+        let span = Span {
+            lo: fm.start_pos + BytePos::from_usize(lo),
+            hi: fm.start_pos + BytePos::from_usize(hi),
+            ctxt: NO_EXPANSION,
+        };
+        (span, text.to_string())
+    }
+
+    #[test]
+    fn apply_edits_splices_in_order() {
+        let cm = CodeMap::new(FilePathMapping::empty());
+        cm.new_filemap("padding.rs".into(), "fn pad() {}\n".to_string());
+        let fm = cm.new_filemap("a.rs".into(), "let x = foo;".to_string());
+
+        // Adjacent replacements, given out of order, and an insertion at a
+        // zero-width span.
+        let edits = vec![
+            edit(&fm, 8, 11, "bar()"),
+            edit(&fm, 4, 5, "y"),
+            edit(&fm, 3, 4, " mut "),
+            edit(&fm, 12, 12, " // changed"),
+        ];
+        assert_eq!(
+            apply_edits(&cm, &fm, &edits),
+            Ok("let mut y = bar(); // changed".to_string())
+        );
+
+        // Insertions at the start of a replacement come first.
+        let edits = vec![edit(&fm, 8, 11, "bar"), edit(&fm, 8, 8, "&")];
+        assert_eq!(
+            apply_edits(&cm, &fm, &edits),
+            Ok("let x = &bar;".to_string())
+        );
+        assert_eq!(apply_edits(&cm, &fm, &[]), Ok("let x = foo;".to_string()));
+    }
+
+    #[test]
+    fn apply_edits_errors() {
+        let cm = CodeMap::new(FilePathMapping::empty());
+        let other = cm.new_filemap("other.rs".into(), "fn pad() {}\n".to_string());
+        let fm = cm.new_filemap("a.rs".into(), "let x = foo;".to_string());
+
+        let overlapping = vec![edit(&fm, 8, 11, "bar"), edit(&fm, 4, 9, "y = b")];
+        assert_eq!(
+            apply_edits(&cm, &fm, &overlapping),
+            Err(EditError::Overlap(overlapping[1].0, overlapping[0].0))
+        );
+        let inside = vec![edit(&fm, 4, 9, "y"), edit(&fm, 6, 6, "z")];
+        assert_eq!(
+            apply_edits(&cm, &fm, &inside),
+            Err(EditError::Overlap(inside[0].0, inside[1].0))
+        );
+
+        let elsewhere = edit(&other, 0, 2, "pub fn");
+        assert_eq!(
+            apply_edits(&cm, &fm, std::slice::from_ref(&elsewhere)),
+            Err(EditError::SpanOutsideFile(elsewhere.0))
+        );
+
+        let fm = cm.new_filemap("b.rs".into(), "let é = 1;".to_string());
+        let split = edit(&fm, 4, 5, "e");
+        assert_eq!(
+            apply_edits(&cm, &fm, std::slice::from_ref(&split)),
+            Err(EditError::NotCharBoundary(split.0))
+        );
+        let split = edit(&fm, 5, 5, "e");
+        assert_eq!(
+            apply_edits(&cm, &fm, std::slice::from_ref(&split)),
+            Err(EditError::NotCharBoundary(split.0))
+        );
+        assert_eq!(
+            apply_edits(&cm, &fm, &[edit(&fm, 4, 6, "e")]),
+            Ok("let e = 1;".to_string())
+        );
+    }
+
+    #[test]
+    fn apply_suggestion_edits() {
+        let cm = CodeMap::new(FilePathMapping::empty());
+        let fm = cm.new_filemap("a.rs".into(), "let x = foo;".to_string());
+        let (span, _) = edit(&fm, 8, 11, "");
+        let suggestion = CodeSuggestion {
+            substitution_parts: vec![crate::errors::Substitution {
+                span,
+                substitutions: vec!["bar".to_string(), "baz".to_string()],
+            }],
+            msg: "try".to_string(),
+        };
+        assert_eq!(
+            apply_suggestion(&cm, &fm, &suggestion),
+            Ok("let x = bar;".to_string())
+        );
+    }
+
+    #[test]
+    fn nested_span_debug_scopes() {
+        let outer = CodeMap::new(FilePathMapping::empty());
+        outer.new_filemap_and_lines("outer.rs", "fn outer() {}");
+        let inner = CodeMap::new(FilePathMapping::empty());
+        inner.new_filemap_and_lines("inner.rs", "\nfn inner() {}");
+        let span = Span {
+            lo: BytePos(3),
+            hi: BytePos(8),
+            ctxt: NO_EXPANSION,
+        };
+        let raw = format!("{:?}", span);
+
+        with_span_debug(&outer, || {
+            assert_eq!(format!("{:?}", span), "outer.rs:1:4: 1:9");
+            with_span_debug(&inner, || {
+                assert_eq!(format!("{:?}", span), "inner.rs:2:3: 2:8");
+            });
+            assert_eq!(format!("{:?}", span), "outer.rs:1:4: 1:9");
+        });
+        assert_eq!(format!("{:?}", span), raw);
+
+        assert_eq!(span.debug_with(&inner).to_string(), "inner.rs:2:3: 2:8");
+    }
+
+    #[test]
+    fn span_debug_restored_after_panic() {
+        let cm = CodeMap::new(FilePathMapping::empty());
+        cm.new_filemap("a.rs".into(), "fn a() {}".to_string());
+        let raw = format!("{:?}", DUMMY_SP);
+        let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+            with_span_debug(&cm, || panic!("formatting failed"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(format!("{:?}", DUMMY_SP), raw);
+    }
+
+    fn multibyte_filemap(cm: &CodeMap, name: &str, src: &str) -> Rc<FileMap> {
+        let fm = cm.new_filemap_and_lines(name, src);
+        for (i, c) in src.char_indices() {
+            if c.len_utf8() > 1 {
+                fm.record_multibyte_char(fm.start_pos + BytePos(i as u32), c.len_utf8());
+            }
+        }
+        fm
+    }
+
+    #[test]
+    fn caching_codemap_view_matches_codemap() {
+        use crate::errors::caching_codemap_view::CachingCodemapView;
+
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let cached = CachingCodemapView::new(cm.clone());
+        let src = "fn ä() {}\n// ∀ß€ 𝄞 x\n\nlet s = \"日本語\";\nend";
+        let mut files = vec![(multibyte_filemap(&cm, "a.rs", src), src)];
+
+        // A small LCG, so failures are reproducible.
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move |bound: usize| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1);
+            (state >> 33) as usize % bound
+        };
+        for round in 0..1000 {
+            if round == 500 {
+                // Filemaps added mid-session must not confuse the cache.
+                let src = "€€\n\nb𝄞 = ä;\n";
+                files.push((multibyte_filemap(&cm, "b.rs", src), src));
+            }
+            let (ref fm, src) = files[next(files.len())];
+            let offsets: Vec<_> = src.char_indices().map(|(i, _)| i).collect();
+            let pos = fm.start_pos + BytePos(offsets[next(offsets.len())] as u32);
+
+            let expected = cm.lookup_char_pos(pos);
+            let loc = CodeMapper::lookup_char_pos(&cached, pos);
+            assert!(Rc::ptr_eq(&loc.file, &expected.file), "file of {:?}", pos);
+            assert_eq!(
+                (loc.line, loc.col),
+                (expected.line, expected.col),
+                "{:?}",
+                pos
+            );
+        }
+    }
+
+    #[test]
+    fn injected_spans_allow_unstable() {
+        use crate::parse::{self, ParseSess};
+        use crate::std_inject;
+
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let krate = parse::parse_crate_from_source_str(
+            "lib.rs".to_string(),
+            "fn f() {}\n".to_string(),
+            &sess,
+        )
+        .unwrap_or_else(|mut e| {
+            e.emit();
+            panic!("failed to parse")
+        });
+        let krate = std_inject::maybe_inject_crates_ref(krate, None);
+        let cm = sess.codemap();
+
+        let (prelude_import, extern_crate, f) = match krate.module.items[..] {
+            [ref a, ref b, ref c] => (a, b, c),
+            ref items => panic!("unexpected items {:?}", items),
+        };
+        assert!(cm.span_allows_unstable(prelude_import.span));
+        assert!(cm.span_allows_unstable(prelude_import.attrs[0].span));
+        assert!(cm.span_allows_unstable(extern_crate.span));
+        assert!(!cm.span_allows_unstable(f.span));
+        assert!(!cm.span_allows_unstable(DUMMY_SP));
+
+        // Source injected through a filemap of its own is internal even
+        // though it was not expanded from a macro.
+        let injected = cm.new_filemap("<std_inject>".into(), "fn g() {}".to_string());
+        let sp = Span {
+            lo: injected.start_pos,
+            hi: injected.end_pos,
+            ctxt: NO_EXPANSION,
+        };
+        assert!(cm.span_allows_unstable(sp));
+        let other = cm.new_filemap("<inject>".into(), "fn h() {}".to_string());
+        assert!(!cm.span_allows_unstable(Span {
+            lo: other.start_pos,
+            ..sp
+        }));
+    }
+
+    #[test]
+    fn span_accessibility() {
+        let cm = CodeMap::new(FilePathMapping::empty());
+        assert!(!cm.is_span_accessible(DUMMY_SP));
+
+        let fm = cm.new_filemap("lib.rs".into(), "fn größe() {}\n".to_string());
+        let span = |lo: u32, hi: u32| Span {
+            lo: fm.start_pos + BytePos(lo),
+            hi: fm.start_pos + BytePos(hi),
+            ctxt: NO_EXPANSION,
+        };
+        for sp in vec![span(0, 2), span(3, 9), span(5, 5), span(0, 16)] {
+            assert!(cm.is_span_accessible(sp));
+            assert!(cm.span_to_snippet(sp).is_ok());
+        }
+        // Ill-formed, or in the middle of `ö`.
+        assert!(!cm.is_span_accessible(span(2, 1)));
+        assert!(!cm.is_span_accessible(span(3, 6)));
+
+        let imported =
+            cm.new_imported_filemap("dep.rs".into(), false, 1, 0, 10, vec![BytePos(0)], vec![]);
+        let in_imported = Span {
+            lo: imported.start_pos,
+            hi: imported.start_pos + BytePos(2),
+            ctxt: NO_EXPANSION,
+        };
+        assert!(!cm.is_span_accessible(in_imported));
+        assert!(!cm.is_span_accessible(Span {
+            lo: fm.start_pos,
+            ..in_imported
+        }));
+    }
+
+    #[test]
+    fn load_files_in_path_order() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let paths = vec![
+            fixtures.join("spans/lib.rs"),
+            fixtures.join("missing.rs"),
+            fixtures.join("stats/lib.rs"),
+            fixtures.join("spans/inner.rs"),
+            fixtures.join("stats/missing.rs"),
+            fixtures.join("stats/inner.rs"),
+        ];
+        let load = || {
+            let cm = CodeMap::new(FilePathMapping::empty());
+            let loaded = cm
+                .load_files(&paths)
+                .into_iter()
+                .map(|fm| fm.map(|fm| (fm.name.clone(), fm.start_pos, fm.end_pos)))
+                .map(|fm| fm.map_err(|e| e.kind()))
+                .collect::<Vec<_>>();
+            (cm, loaded)
+        };
+        let (cm, first) = load();
+        let (_, second) = load();
+        assert_eq!(first, second);
+
+        // The same as loading the files one at a time, with nothing allocated
+        // for the missing ones.
+        let serial = CodeMap::new(FilePathMapping::empty());
+        let expected = paths
+            .iter()
+            .map(|path| serial.load_file(path))
+            .map(|fm| fm.map(|fm| (fm.name.clone(), fm.start_pos, fm.end_pos)))
+            .map(|fm| fm.map_err(|e| e.kind()))
+            .collect::<Vec<_>>();
+        assert_eq!(first, expected);
+        assert_eq!(cm.files().len(), 4);
+
+        for (i, (path, loaded)) in paths.iter().zip(&first).enumerate() {
+            match *loaded {
+                Ok((ref name, ..)) => assert_eq!(*name, FileName::Real(path.clone())),
+                Err(kind) => {
+                    assert!(i == 1 || i == 4, "{:?} failed to load", path);
+                    assert_eq!(kind, io::ErrorKind::NotFound);
+                }
+            }
+        }
+        let start_pos = first
+            .iter()
+            .filter_map(|fm| fm.as_ref().ok())
+            .map(|&(_, start_pos, _)| start_pos)
+            .collect::<Vec<_>>();
+        assert!(start_pos.windows(2).all(|w| w[0] < w[1]));
+    }
 }
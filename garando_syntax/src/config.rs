@@ -1,16 +1,24 @@
 use crate::ast;
 use crate::attr::HasAttrs;
 use crate::codemap::Spanned;
+use crate::codemap::{CodeMap, FilePathMapping};
+use crate::errors::emitter::Emitter;
+use crate::errors::{DiagnosticBuilder, Handler};
 use crate::feature_gate::{
     feature_err, get_features, Features, GateIssue, EXPLAIN_STMT_ATTR_SYNTAX,
 };
-use crate::parse::{token, ParseSess};
-use crate::syntax_pos::Span;
+use crate::parse::{self, token, ParseSess};
+use crate::symbol::Symbol;
+use crate::syntax_pos::{Pos, Span, DUMMY_SP};
 use crate::{attr, fold};
 
 use crate::ptr::P;
 use crate::util::small_vector::SmallVector;
 
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::{cmp, error, fmt};
+
 /// A folder that strips out items that do not belong in the current configuration.
 pub struct StripUnconfigured<'a> {
     pub should_test: bool,
@@ -139,7 +147,8 @@ impl<'a> StripUnconfigured<'a> {
             } else if let Some(mis) = attr.meta_item_list() {
                 mis
             } else {
-                return true;
+                return attr::cfg_matches_tokens(&attr.tokens, self.sess, self.features)
+                    .unwrap_or(true);
             };
 
             if mis.len() != 1 {
@@ -404,3 +413,331 @@ fn is_cfg(attr: &ast::Attribute) -> bool {
 pub fn is_test_or_bench(attr: &ast::Attribute) -> bool {
     attr.check_name("test") || attr.check_name("bench")
 }
+
+/// An invalid `--cfg` spec passed to `CrateConfig::extend_from_cfg_specs`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CfgParseError {
+    /// The offending spec.
+    pub spec: String,
+    pub message: String,
+    /// The byte range within `spec` the error points at.
+    pub lo: usize,
+    pub hi: usize,
+}
+
+impl fmt::Display for CfgParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid cfg spec `{}`: {}", self.spec, self.message)
+    }
+}
+
+impl error::Error for CfgParseError {}
+
+impl ast::CrateConfig {
+    /// Parses each of `specs` with the syntax accepted by `--cfg`, i.e. either a bare `name`
+    /// or `key="value"`, and adds it to the configuration. Stops at the first invalid spec.
+    pub fn extend_from_cfg_specs<S: AsRef<str>>(
+        &mut self,
+        specs: &[S],
+    ) -> Result<(), CfgParseError> {
+        for spec in specs {
+            let (name, value) = parse_cfg_spec(spec.as_ref())?;
+            self.insert(name, value);
+        }
+        Ok(())
+    }
+}
+
+/// Records the first diagnostic emitted while parsing a cfg spec.
+struct CfgSpecEmitter(Rc<RefCell<Option<(String, Span)>>>);
+
+impl Emitter for CfgSpecEmitter {
+    fn emit(&mut self, db: &DiagnosticBuilder) {
+        let mut first = self.0.borrow_mut();
+        if first.is_none() {
+            *first = Some((db.message(), db.span.primary_span().unwrap_or(DUMMY_SP)));
+        }
+    }
+}
+
+fn parse_cfg_spec(spec: &str) -> Result<(ast::Name, Option<Symbol>), CfgParseError> {
+    let spec_error = |span: Span, message: String| CfgParseError {
+        spec: spec.to_owned(),
+        message,
+        lo: cmp::min(span.lo.to_usize(), spec.len()),
+        hi: cmp::min(span.hi.to_usize(), spec.len()),
+    };
+
+    // Parse the spec in a session of its own, so that positions in the spec are its byte offsets.
+    let first_error = Rc::new(RefCell::new(None));
+    let emitter = CfgSpecEmitter(first_error.clone());
+    let handler = Handler::with_emitter(false, false, Box::new(emitter));
+    let sess =
+        ParseSess::with_span_handler(handler, Rc::new(CodeMap::new(FilePathMapping::empty())));
+
+    let diagnostic_error = |mut diag: DiagnosticBuilder| {
+        let err = spec_error(diag.span.primary_span().unwrap_or(DUMMY_SP), diag.message());
+        diag.cancel();
+        err
+    };
+
+    let mut parser =
+        match parse::maybe_new_parser_from_source_str(&sess, "cfgspec".to_owned(), spec.to_owned())
+        {
+            Ok(parser) => parser,
+            Err(diag) => return Err(diagnostic_error(diag)),
+        };
+    let parsed = parser.parse_meta_item();
+    // The parser emits the errors it recovers from, which come first.
+    if let Some((message, span)) = first_error.borrow_mut().take() {
+        if let Err(mut diag) = parsed {
+            diag.cancel();
+        }
+        return Err(spec_error(span, message));
+    }
+    let meta_item = parsed.map_err(diagnostic_error)?;
+    if parser.token != token::Eof {
+        let msg = format!(
+            "expected end of cfg spec, found `{}`",
+            parser.this_token_to_string()
+        );
+        return Err(spec_error(parser.span, msg));
+    }
+
+    match meta_item.node {
+        ast::MetaItemKind::Word => Ok((meta_item.name, None)),
+        ast::MetaItemKind::NameValue(ref lit) => match lit.node {
+            ast::LitKind::Str(value, _) => Ok((meta_item.name, Some(value))),
+            _ => Err(spec_error(
+                lit.span,
+                "cfg values must be string literals".to_owned(),
+            )),
+        },
+        ast::MetaItemKind::List(..) => Err(spec_error(
+            meta_item.span,
+            "expected `name` or `key=\"value\"`".to_owned(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::CrateConfig;
+    use crate::testing::SharedBuffer;
+
+    #[test]
+    fn cfg_specs() {
+        let mut config = CrateConfig::new();
+        config
+            .extend_from_cfg_specs(&["test", "feature=\"serde\"", "feature = \"x y\""])
+            .unwrap();
+
+        assert_eq!(config.len(), 3);
+        assert!(config.contains(&(Symbol::intern("test"), None)));
+        assert!(config.contains(&(Symbol::intern("feature"), Some(Symbol::intern("serde")))));
+        assert!(config.contains(&(Symbol::intern("feature"), Some(Symbol::intern("x y")))));
+        assert!(!config.contains(&(Symbol::intern("feature"), None)));
+    }
+
+    #[test]
+    fn invalid_cfg_specs() {
+        let mut config = CrateConfig::new();
+
+        let err = config
+            .extend_from_cfg_specs(&["unix", "feature=1"])
+            .unwrap_err();
+        assert_eq!(err.spec, "feature=1");
+        assert_eq!(err.message, "cfg values must be string literals");
+        assert_eq!(&err.spec[err.lo..err.hi], "1");
+        // Specs before the invalid one have been added.
+        assert!(config.contains(&(Symbol::intern("unix"), None)));
+
+        let err = config.extend_from_cfg_specs(&["foo bar"]).unwrap_err();
+        assert_eq!(&err.spec[err.lo..err.hi], "bar");
+
+        let err = config.extend_from_cfg_specs(&["all(unix)"]).unwrap_err();
+        assert_eq!(&err.spec[err.lo..err.hi], "all(unix)");
+
+        assert!(config.extend_from_cfg_specs(&["=\"x\""]).is_err());
+        // A lexing error is returned without unwinding.
+        let err = config.extend_from_cfg_specs(&["feature=\"x"]).unwrap_err();
+        assert_eq!(err.message, "unterminated double quote string");
+        assert_eq!(&err.spec[err.lo..err.hi], "\"x");
+        assert_eq!(config.len(), 1);
+    }
+
+    #[test]
+    fn duplicate_cfg_entries() {
+        let mut config = CrateConfig::new();
+        assert!(config.insert_name("unix"));
+        assert!(!config.insert_name("unix"));
+        assert!(config.insert_key_value("feature", "serde"));
+        assert!(!config.insert_key_value("feature", "serde"));
+        config
+            .extend_from_cfg_specs(&["unix", "feature=\"serde\""])
+            .unwrap();
+        assert_eq!(config.len(), 2);
+    }
+
+    #[test]
+    fn cfg_matches_crate_config() {
+        let mut sess = ParseSess::new(FilePathMapping::empty());
+        sess.config_mut().insert_name("unix");
+        sess.config_mut().insert_key_value("feature", "serde");
+
+        let matches = |source: &str| {
+            let cfg =
+                parse::parse_meta_from_source_str("cfgtest".to_owned(), source.to_owned(), &sess)
+                    .unwrap();
+            attr::cfg_matches(&cfg, &sess, None)
+        };
+        assert!(matches("all(unix, feature = \"serde\")"));
+        assert!(!matches("feature = \"derive\""));
+        assert!(!matches("windows"));
+    }
+
+    /// The span and text of a suggested replacement.
+    type Suggestion = (u64, u64, String);
+
+    // Evaluates `cfg` against a configuration with `unix` and `my_cfg` set,
+    // returning the message and suggested replacements of each diagnostic.
+    fn cfg_diagnostics(cfg: &str) -> Vec<(String, Vec<Suggestion>)> {
+        let output = SharedBuffer::default();
+        let mut sess =
+            ParseSess::with_json_emitter(FilePathMapping::empty(), Box::new(output.clone()));
+        sess.config_mut().insert_name("unix");
+        sess.config_mut().insert_name("my_cfg");
+        let cfg =
+            parse::parse_meta_from_source_str("cfgtest".to_owned(), cfg.to_owned(), &sess).unwrap();
+        assert!(!attr::cfg_matches(&cfg, &sess, None));
+
+        output
+            .json_diagnostics()
+            .into_iter()
+            .map(|d| {
+                let suggestions = d["children"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .flat_map(|child| child["spans"].as_array().unwrap().clone())
+                    .map(|span| {
+                        (
+                            span["byte_start"].as_u64().unwrap(),
+                            span["byte_end"].as_u64().unwrap(),
+                            span["suggested_replacement"].as_str().unwrap().to_string(),
+                        )
+                    })
+                    .collect();
+                (d["message"].as_str().unwrap().to_string(), suggestions)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn misspelt_cfg_names() {
+        assert_eq!(
+            cfg_diagnostics("taget_os = \"linux\""),
+            [(
+                "unexpected `cfg` condition name `taget_os`".to_string(),
+                vec![(0, 8, "target_os".to_string())]
+            )]
+        );
+        assert_eq!(
+            cfg_diagnostics("alll(unix, windows)"),
+            [(
+                "invalid predicate `alll`".to_string(),
+                vec![(0, 4, "all".to_string())]
+            )]
+        );
+        assert_eq!(cfg_diagnostics("tset")[0].1, [(0, 4, "test".to_string())]);
+        assert_eq!(cfg_diagnostics("frobnicate(unix)")[0].1, []);
+
+        // Names that are set, known, or not close to a known one are simply
+        // false.
+        assert!(cfg_diagnostics("my_cfg = \"x\"").is_empty());
+        assert!(cfg_diagnostics("windows").is_empty());
+        assert!(cfg_diagnostics("frobnicate").is_empty());
+    }
+
+    const NEWER_CFGS: &str = "
+#[cfg(version(\"1.70\"))] fn a() {}
+#[cfg(accessible(std::x))] fn b() {}
+#[cfg(any(unix, version(\"1.70\")))] fn c() {}
+#[cfg(all(unix, accessible(std)))] fn d() {}
+#[cfg(version(\"1.71\"))] fn e() {}
+fn f() {}
+#[cfg(any(unix, accessible(std::x)))] fn g() {}
+#[cfg(all(unix, not(accessible(std::x))))] fn h() {}
+#[cfg(any(windows, accessible(std::x)))] fn i() {}
+#[cfg(accessible(std::x)(y))] fn j() {}
+";
+
+    /// Configures `NEWER_CFGS` with `unix` set and `compat`, returning the
+    /// names of the items left and the level and message of each diagnostic.
+    fn configure_newer_cfgs(compat: attr::CfgCompat) -> (Vec<String>, Vec<(String, String)>) {
+        let output = SharedBuffer::default();
+        let mut sess =
+            ParseSess::with_json_emitter(FilePathMapping::empty(), Box::new(output.clone()));
+        sess.config_mut().insert_name("unix");
+        sess.set_cfg_compat(compat);
+        let krate =
+            parse::parse_crate_from_source_str("cfgtest".to_owned(), NEWER_CFGS.to_owned(), &sess)
+                .unwrap();
+        let mut strip = StripUnconfigured {
+            should_test: false,
+            sess: &sess,
+            features: None,
+        };
+        let krate = fold::Folder::fold_crate(&mut strip, krate);
+        let items = krate
+            .module
+            .items
+            .iter()
+            .map(|item| item.ident.to_string())
+            .collect();
+
+        let diagnostics = output
+            .json_diagnostics()
+            .into_iter()
+            .map(|d| {
+                (
+                    d["level"].as_str().unwrap().to_string(),
+                    d["message"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect();
+        (items, diagnostics)
+    }
+
+    #[test]
+    fn unknown_cfg_predicates() {
+        let diagnostic = |level: &str, message: &str| (level.to_string(), message.to_string());
+
+        // `accessible(std::x)` isn't a meta item, so strict mode keeps `b`
+        // and the items after `f` without evaluating them
+        let (items, diagnostics) = configure_newer_cfgs(attr::CfgCompat::Strict);
+        assert_eq!(items, ["b", "c", "f", "g", "h", "i", "j"]);
+        assert_eq!(
+            diagnostics,
+            [
+                diagnostic("error", "unsupported literal"),
+                diagnostic("error", "invalid predicate `accessible`"),
+                diagnostic("error", "unsupported literal"),
+            ]
+        );
+
+        let (items, diagnostics) = configure_newer_cfgs(attr::CfgCompat::LenientUnknownPredicates);
+        // Unknown predicates are evaluated under `any`, `all` and `not` too,
+        // but only with a single parenthesized group of arguments, so `j` is
+        // kept as in strict mode
+        assert_eq!(items, ["c", "f", "g", "h", "j"]);
+        assert_eq!(
+            diagnostics,
+            [
+                diagnostic("warning", "unknown `cfg` predicate `version`"),
+                diagnostic("warning", "unknown `cfg` predicate `accessible`"),
+            ]
+        );
+    }
+}
@@ -262,6 +262,7 @@ let x = &tmp[1...3]; // ok!
 }
 
 register_diagnostics! {
+    E0380, // auto trait has methods or associated items
     E0538, // multiple [same] items
     E0539, // incorrect meta item
     E0540, // multiple rustc_deprecated attributes
@@ -281,6 +282,7 @@ register_diagnostics! {
     E0555, // malformed feature attribute, expected #![feature(...)]
     E0556, // malformed feature, expected just one word
     E0557, // feature has been removed
+    E0567, // auto trait has generic parameters
     E0584, // file for module `..` found at both .. and ..
     E0589, // invalid `repr(align)` attribute
 }
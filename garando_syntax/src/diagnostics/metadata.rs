@@ -39,7 +39,7 @@ impl ErrorLocation {
     pub fn from_span(ecx: &ExtCtxt, sp: Span) -> ErrorLocation {
         let loc = ecx.codemap().lookup_char_pos_adj(sp.lo);
         ErrorLocation {
-            filename: loc.filename,
+            filename: loc.filename.to_string(),
             line: loc.line,
         }
     }
@@ -216,16 +216,46 @@ where
     ) -> Box<dyn MacResult + 'cx> {
         struct AvoidInterpolatedIdents;
 
+        impl AvoidInterpolatedIdents {
+            /// Whether folding `tts` can change it. Only interpolated tokens
+            /// can be changed, so streams without any are kept as they are
+            /// rather than rebuilt.
+            fn affects(tts: &TokenStream) -> bool {
+                tts.trees().any(|tt| match tt {
+                    tokenstream::TokenTree::Token(_, token::Interpolated(..)) => true,
+                    tokenstream::TokenTree::Token(..) => false,
+                    tokenstream::TokenTree::Delimited(_, delimited) => {
+                        AvoidInterpolatedIdents::affects(&delimited.stream())
+                    }
+                })
+            }
+        }
+
         impl Folder for AvoidInterpolatedIdents {
             fn fold_tt(&mut self, tt: tokenstream::TokenTree) -> tokenstream::TokenTree {
-                if let tokenstream::TokenTree::Token(_, token::Interpolated(ref nt)) = tt {
-                    if let token::NtIdent(ident) = **nt {
-                        return tokenstream::TokenTree::Token(ident.span, token::Ident(ident.node));
+                match tt {
+                    tokenstream::TokenTree::Token(_, token::Interpolated(ref nt)) => {
+                        if let token::NtIdent(ident) = **nt {
+                            return tokenstream::TokenTree::Token(
+                                ident.span,
+                                token::Ident(ident.node),
+                            );
+                        }
                     }
+                    tokenstream::TokenTree::Token(..) => return tt,
+                    tokenstream::TokenTree::Delimited(..) => {}
                 }
                 fold::noop_fold_tt(tt, self)
             }
 
+            fn fold_tts(&mut self, tts: TokenStream) -> TokenStream {
+                if AvoidInterpolatedIdents::affects(&tts) {
+                    fold::noop_fold_tts(tts, self)
+                } else {
+                    tts
+                }
+            }
+
             fn fold_mac(&mut self, mac: ast::Mac) -> ast::Mac {
                 fold::noop_fold_mac(mac, self)
             }
@@ -311,6 +341,11 @@ pub trait MacResult {
         None
     }
 
+    /// Create zero or more items in an `extern {}` block.
+    fn make_foreign_items(self: Box<Self>) -> Option<SmallVector<ast::ForeignItem>> {
+        None
+    }
+
     /// Create a pattern.
     fn make_pat(self: Box<Self>) -> Option<P<ast::Pat>> {
         None
@@ -359,6 +394,7 @@ make_MacEager! {
     items: SmallVector<P<ast::Item>>,
     impl_items: SmallVector<ast::ImplItem>,
     trait_items: SmallVector<ast::TraitItem>,
+    foreign_items: SmallVector<ast::ForeignItem>,
     stmts: SmallVector<ast::Stmt>,
     ty: P<ast::Ty>,
 }
@@ -380,6 +416,10 @@ impl MacResult for MacEager {
         self.trait_items
     }
 
+    fn make_foreign_items(self: Box<Self>) -> Option<SmallVector<ast::ForeignItem>> {
+        self.foreign_items
+    }
+
     fn make_stmts(self: Box<Self>) -> Option<SmallVector<ast::Stmt>> {
         match self.stmts.as_ref().map_or(0, |s| s.len()) {
             0 => make_stmts_default!(self),
@@ -446,6 +486,7 @@ impl DummyResult {
             id: ast::DUMMY_NODE_ID,
             node: ast::ExprKind::Lit(P(codemap::respan(sp, ast::LitKind::Bool(false)))),
             span: sp,
+            bare_span: sp,
             attrs: ast::ThinVec::new(),
         })
     }
@@ -502,6 +543,14 @@ impl MacResult for DummyResult {
         }
     }
 
+    fn make_foreign_items(self: Box<DummyResult>) -> Option<SmallVector<ast::ForeignItem>> {
+        if self.expr_only {
+            None
+        } else {
+            Some(SmallVector::new())
+        }
+    }
+
     fn make_stmts(self: Box<DummyResult>) -> Option<SmallVector<ast::Stmt>> {
         Some(SmallVector::one(ast::Stmt {
             id: ast::DUMMY_NODE_ID,
@@ -556,9 +605,15 @@ pub enum SyntaxExtension {
     ///
     /// `bytes!` is a `NormalTT`.
     ///
-    /// The `bool` dictates whether the contents of the macro can
-    /// directly use `#[unstable]` things (true == yes).
-    NormalTT(Box<dyn TTMacroExpander>, Option<(ast::NodeId, Span)>, bool),
+    /// The first `bool` dictates whether the contents of the macro can
+    /// directly use `#[unstable]` things (true == yes), the second whether
+    /// they can use `unsafe` without it being attributed to the caller.
+    NormalTT(
+        Box<dyn TTMacroExpander>,
+        Option<(ast::NodeId, Span)>,
+        bool,
+        bool,
+    ),
 
     /// A function-like syntax extension that has an extra ident before
     /// the block.
@@ -754,6 +809,9 @@ impl<'a> ExtCtxt<'a> {
     pub fn new_parser_from_tts(&self, tts: &[tokenstream::TokenTree]) -> parser::Parser<'a> {
         parse::stream_to_parser(self.parse_sess, tts.iter().cloned().collect())
     }
+    pub fn new_parser_from_stream(&self, stream: TokenStream) -> parser::Parser<'a> {
+        parse::stream_to_parser(self.parse_sess, stream)
+    }
     pub fn codemap(&self) -> &'a CodeMap {
         self.parse_sess.codemap()
     }
@@ -864,6 +922,12 @@ impl<'a> ExtCtxt<'a> {
         self.ecfg.trace_mac = x
     }
     pub fn ident_of(&self, st: &str) -> ast::Ident {
+        // Quoted lifetimes come through here too, with their leading `'`.
+        debug_assert!(
+            ast::Ident::is_valid(st.strip_prefix('\'').unwrap_or(st)),
+            "invalid identifier {:?}",
+            st
+        );
         ast::Ident::from_str(st)
     }
     pub fn std_path(&self, components: &[&str]) -> Vec<ast::Ident> {
@@ -374,7 +374,7 @@ impl<'a> AstBuilder for ExtCtxt<'a> {
         let last_identifier = idents.pop().unwrap();
         let mut segments: Vec<ast::PathSegment> = Vec::new();
         if global {
-            segments.push(ast::PathSegment::crate_root());
+            segments.push(ast::PathSegment::crate_root(sp.shrink_to_lo()));
         }
 
         segments.extend(
@@ -387,6 +387,7 @@ impl<'a> AstBuilder for ExtCtxt<'a> {
         } else {
             Some(P(ast::PathParameters::AngleBracketed(
                 ast::AngleBracketedParameterData {
+                    span: sp,
                     lifetimes: lifetimes,
                     types: types,
                     bindings: bindings,
@@ -430,6 +431,7 @@ impl<'a> AstBuilder for ExtCtxt<'a> {
     ) -> (ast::QSelf, ast::Path) {
         let mut path = trait_path;
         let parameters = ast::AngleBracketedParameterData {
+            span: ident.span,
             lifetimes: lifetimes,
             types: types,
             bindings: bindings,
@@ -592,6 +594,7 @@ impl<'a> AstBuilder for ExtCtxt<'a> {
             pat: pat,
             ty: None,
             init: Some(ex),
+            els: None,
             id: ast::DUMMY_NODE_ID,
             span: sp,
             attrs: ast::ThinVec::new(),
@@ -621,6 +624,7 @@ impl<'a> AstBuilder for ExtCtxt<'a> {
             pat: pat,
             ty: Some(typ),
             init: Some(ex),
+            els: None,
             id: ast::DUMMY_NODE_ID,
             span: sp,
             attrs: ast::ThinVec::new(),
@@ -638,6 +642,7 @@ impl<'a> AstBuilder for ExtCtxt<'a> {
             pat: self.pat_wild(span),
             ty: Some(ty),
             init: None,
+            els: None,
             id: ast::DUMMY_NODE_ID,
             span: span,
             attrs: ast::ThinVec::new(),
@@ -681,6 +686,7 @@ impl<'a> AstBuilder for ExtCtxt<'a> {
             id: ast::DUMMY_NODE_ID,
             node: node,
             span: span,
+            bare_span: span,
             attrs: ast::ThinVec::new(),
         })
     }
@@ -877,7 +883,7 @@ impl<'a> AstBuilder for ExtCtxt<'a> {
 
     fn expr_fail(&self, span: Span, msg: Symbol) -> P<ast::Expr> {
         let loc = self.codemap().lookup_char_pos(span.lo);
-        let expr_file = self.expr_str(span, Symbol::intern(&loc.file.name));
+        let expr_file = self.expr_str(span, Symbol::intern(&loc.file.name.to_string()));
         let expr_line = self.expr_u32(span, loc.line as u32);
         let expr_file_line_tuple = self.expr_tuple(span, vec![expr_file, expr_line]);
         let expr_file_line_ptr = self.expr_addr_of(span, expr_file_line_tuple);
@@ -1107,14 +1113,17 @@ impl<'a> AstBuilder for ExtCtxt<'a> {
             ty: ty,
             pat: arg_pat,
             id: ast::DUMMY_NODE_ID,
+            span: span,
         }
     }
 
     // FIXME unused self
     fn fn_decl(&self, inputs: Vec<ast::Arg>, output: P<ast::Ty>) -> P<ast::FnDecl> {
+        // there is no `->` in the source, so mark where it would have been
+        let arrow = output.span.shrink_to_lo();
         P(ast::FnDecl {
             inputs: inputs,
-            output: ast::FunctionRetTy::Ty(output),
+            output: ast::FunctionRetTy::Ty(output, arrow),
             variadic: false,
         })
     }
@@ -1155,6 +1164,7 @@ impl<'a> AstBuilder for ExtCtxt<'a> {
                 self.fn_decl(inputs, output),
                 ast::Unsafety::Normal,
                 dummy_spanned(ast::Constness::NotConst),
+                ast::IsAsync::NotAsync,
                 Abi::Rust,
                 generics,
                 body,
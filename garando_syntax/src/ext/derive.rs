@@ -1,5 +1,5 @@
 use crate::ast;
-use crate::attr::HasAttrs;
+use crate::attr::{self, HasAttrs};
 use crate::codemap::{ExpnFormat, ExpnInfo, NameAndSpan};
 use crate::ext::base::ExtCtxt;
 use crate::ext::build::AstBuilder;
@@ -24,6 +24,7 @@ pub fn collect_derives(cx: &mut ExtCtxt, attrs: &mut Vec<ast::Attribute>) -> Vec
                 false
             }
             Ok(traits) => {
+                attr::mark_used(attr);
                 result.extend(traits);
                 true
             }
@@ -56,7 +57,9 @@ where
             format: ExpnFormat::MacroAttribute(Symbol::intern(&pretty_name)),
             span: None,
             allow_internal_unstable: true,
+            allow_internal_unsafe: false,
         },
+        output_span: None,
     });
 
     let span = Span {
@@ -1,13 +1,14 @@
+use crate::abi::Abi;
 use crate::ast::{self, Block, Ident, NodeId, PatKind, Path};
 use crate::ast::{ItemKind, MacStmtStyle, StmtKind};
 use crate::attr::{self, HasAttrs};
-use crate::codemap::{ExpnInfo, MacroAttribute, MacroBang, NameAndSpan};
+use crate::codemap::{CompilerDesugaring, ExpnInfo, MacroAttribute, MacroBang, NameAndSpan};
 use crate::config::{is_test_or_bench, StripUnconfigured};
 use crate::errors::FatalError;
 use crate::ext::base::*;
 use crate::ext::derive::{add_derived_markers, collect_derives};
 use crate::ext::hygiene::Mark;
-use crate::ext::placeholders::{placeholder, PlaceholderExpander};
+use crate::ext::placeholders::{self, placeholder, PlaceholderExpander};
 use crate::feature_gate::{self, is_builtin_attr, Features};
 use crate::fold;
 use crate::fold::*;
@@ -18,10 +19,10 @@ use crate::ptr::P;
 use crate::std_inject;
 use crate::symbol::keywords;
 use crate::symbol::Symbol;
-use crate::syntax_pos::{Span, DUMMY_SP};
-use crate::tokenstream::TokenStream;
+use crate::syntax_pos::{FileName, Span, DUMMY_SP};
+use crate::tokenstream::{TokenStream, TokenTree};
 use crate::util::small_vector::SmallVector;
-use crate::visit::Visitor;
+use crate::visit::{self, Visitor};
 
 use std::collections::HashMap;
 use std::mem;
@@ -121,6 +122,29 @@ expansions! {
         "trait item", .make_trait_items, lift .fold_trait_item, lift .visit_trait_item;
     ImplItems: SmallVector<ast::ImplItem> [SmallVector, ast::ImplItem],
         "impl item",  .make_impl_items,  lift .fold_impl_item,  lift .visit_impl_item;
+    ForeignItems: SmallVector<ast::ForeignItem> [SmallVector, ast::ForeignItem],
+        "foreign item", .make_foreign_items, lift .fold_foreign_item, lift .visit_foreign_item;
+}
+
+impl Expansion {
+    /// The span from the first to the last node of the expansion, if any.
+    fn span(&self) -> Option<Span> {
+        let spans: Vec<Span> = match *self {
+            Expansion::OptExpr(ref expr) => expr.iter().map(|expr| expr.span).collect(),
+            Expansion::Expr(ref expr) => vec![expr.span],
+            Expansion::Pat(ref pat) => vec![pat.span],
+            Expansion::Ty(ref ty) => vec![ty.span],
+            Expansion::Stmts(ref stmts) => stmts.iter().map(|stmt| stmt.span).collect(),
+            Expansion::Items(ref items) => items.iter().map(|item| item.span).collect(),
+            Expansion::TraitItems(ref items) => items.iter().map(|item| item.span).collect(),
+            Expansion::ImplItems(ref items) => items.iter().map(|item| item.span).collect(),
+            Expansion::ForeignItems(ref items) => items.iter().map(|item| item.span).collect(),
+        };
+        match (spans.first(), spans.last()) {
+            (Some(&first), Some(&last)) => Some(first.to(last)),
+            _ => None,
+        }
+    }
 }
 
 impl ExpansionKind {
@@ -153,6 +177,9 @@ pub enum InvocationKind {
     Bang {
         mac: ast::Mac,
         ident: Option<Ident>,
+        /// The attributes of the node the invocation was collected from,
+        /// which it keeps if left unexpanded.
+        attrs: Vec<ast::Attribute>,
         span: Span,
     },
     Attr {
@@ -178,6 +205,42 @@ impl Invocation {
             InvocationKind::Derive { ref path, .. } => path.span,
         }
     }
+
+    /// The node the invocation was collected from, with the macro left
+    /// unexpanded, for `ExpansionConfig::keep_macs`. Invocations nested in
+    /// an item with an attribute macro stay unexpanded as well, and an
+    /// unexpanded derive produces no items.
+    fn unexpanded(self) -> Expansion {
+        match self.kind {
+            InvocationKind::Bang {
+                mac,
+                ident,
+                attrs,
+                span,
+            } => placeholders::macro_invocation(
+                self.expansion_kind,
+                ast::DUMMY_NODE_ID,
+                mac,
+                ident,
+                attrs,
+                span,
+            ),
+            InvocationKind::Attr { attr, item, .. } => {
+                let item = match attr {
+                    Some(attr) => item.map_attrs(|mut attrs| {
+                        attrs.insert(0, attr);
+                        attrs
+                    }),
+                    None => item,
+                };
+                self.expansion_kind
+                    .expect_from_annotatables(::std::iter::once(item))
+            }
+            InvocationKind::Derive { .. } => self
+                .expansion_kind
+                .expect_from_annotatables(::std::iter::empty()),
+        }
+    }
 }
 
 pub struct MacroExpander<'a, 'b: 'a> {
@@ -336,6 +399,8 @@ impl<'a, 'b> MacroExpander<'a, 'b> {
                 } else {
                     unreachable!()
                 }
+            } else if self.cx.ecfg.keep_macs {
+                (invoc.unexpanded(), Vec::new())
             } else {
                 self.collect_invocations(invoc.expansion_kind.dummy(invoc.span()), &[])
             };
@@ -354,6 +419,9 @@ impl<'a, 'b> MacroExpander<'a, 'b> {
         let mut placeholder_expander = PlaceholderExpander::new(self.cx, self.monotonic);
         while let Some(expansions) = expansions.pop() {
             for (mark, expansion) in expansions.into_iter().rev() {
+                if let Some(span) = expansion.span() {
+                    mark.set_expn_output_span(span);
+                }
                 let derives = derives.remove(&mark).unwrap_or_else(Vec::new);
                 placeholder_expander.add(NodeId::placeholder_from_mark(mark), expansion, derives);
             }
@@ -400,6 +468,7 @@ impl<'a, 'b> MacroExpander<'a, 'b> {
     }
 
     fn expand_invoc(&mut self, invoc: Invocation, ext: Rc<SyntaxExtension>) -> Expansion {
+        let mark = invoc.expansion_data.mark;
         let result = match invoc.kind {
             InvocationKind::Bang { .. } => self.expand_bang_invoc(invoc, ext),
             InvocationKind::Attr { .. } => self.expand_attr_invoc(invoc, ext),
@@ -407,7 +476,9 @@ impl<'a, 'b> MacroExpander<'a, 'b> {
         };
 
         if self.cx.current_expansion.depth > self.cx.ecfg.recursion_limit {
-            let info = self.cx.current_expansion.mark.expn_info().unwrap();
+            // The current mark is only the invocation's scope, which need not
+            // have expansion info of its own.
+            let info = mark.expn_info().unwrap();
             let suggested_limit = self.cx.ecfg.recursion_limit * 2;
             let mut err = self.cx.struct_span_fatal(
                 info.call_site,
@@ -444,7 +515,9 @@ impl<'a, 'b> MacroExpander<'a, 'b> {
                 format: MacroAttribute(Symbol::intern(&format!("{}", attr.path))),
                 span: None,
                 allow_internal_unstable: false,
+                allow_internal_unsafe: false,
             },
+            output_span: None,
         });
 
         match *ext {
@@ -489,44 +562,51 @@ impl<'a, 'b> MacroExpander<'a, 'b> {
     fn expand_bang_invoc(&mut self, invoc: Invocation, ext: Rc<SyntaxExtension>) -> Expansion {
         let (mark, kind) = (invoc.expansion_data.mark, invoc.expansion_kind);
         let (mac, ident, span) = match invoc.kind {
-            InvocationKind::Bang { mac, ident, span } => (mac, ident, span),
+            InvocationKind::Bang {
+                mac, ident, span, ..
+            } => (mac, ident, span),
             _ => unreachable!(),
         };
         let path = &mac.node.path;
 
         let ident = ident.unwrap_or_else(|| keywords::Invalid.ident());
-        let validate_and_set_expn_info = |def_site_span, allow_internal_unstable| {
-            if ident.name != keywords::Invalid.name() {
-                return Err(format!(
-                    "macro {}! expects no ident argument, given '{}'",
-                    path, ident
-                ));
-            }
-            mark.set_expn_info(ExpnInfo {
-                call_site: span,
-                callee: NameAndSpan {
-                    format: MacroBang(Symbol::intern(&format!("{}", path))),
-                    span: def_site_span,
-                    allow_internal_unstable: allow_internal_unstable,
-                },
-            });
-            Ok(())
-        };
+        let validate_and_set_expn_info =
+            |def_site_span, allow_internal_unstable, allow_internal_unsafe| {
+                if ident.name != keywords::Invalid.name() {
+                    return Err(format!(
+                        "macro {}! expects no ident argument, given '{}'",
+                        path, ident
+                    ));
+                }
+                mark.set_expn_info(ExpnInfo {
+                    call_site: span,
+                    callee: NameAndSpan {
+                        format: MacroBang(Symbol::intern(&format!("{}", path))),
+                        span: def_site_span,
+                        allow_internal_unstable: allow_internal_unstable,
+                        allow_internal_unsafe,
+                    },
+                    output_span: None,
+                });
+                Ok(())
+            };
 
         let marked_tts = noop_fold_tts(mac.node.stream(), &mut Marker(mark));
         let opt_expanded = match *ext {
             SyntaxExtension::DeclMacro(ref expand, def_site_span) => {
-                if let Err(msg) = validate_and_set_expn_info(def_site_span, false) {
+                if let Err(msg) = validate_and_set_expn_info(def_site_span, false, false) {
                     self.cx.span_err(path.span, &msg);
                     return kind.dummy(span);
                 }
                 kind.make_from(expand.expand(self.cx, span, marked_tts))
             }
 
-            NormalTT(ref expandfun, def_info, allow_internal_unstable) => {
-                if let Err(msg) =
-                    validate_and_set_expn_info(def_info.map(|(_, s)| s), allow_internal_unstable)
-                {
+            NormalTT(ref expandfun, def_info, allow_internal_unstable, allow_internal_unsafe) => {
+                if let Err(msg) = validate_and_set_expn_info(
+                    def_info.map(|(_, s)| s),
+                    allow_internal_unstable,
+                    allow_internal_unsafe,
+                ) {
                     self.cx.span_err(path.span, &msg);
                     return kind.dummy(span);
                 }
@@ -548,7 +628,9 @@ impl<'a, 'b> MacroExpander<'a, 'b> {
                         format: MacroBang(Symbol::intern(&format!("{}", path))),
                         span: tt_span,
                         allow_internal_unstable: allow_internal_unstable,
+                        allow_internal_unsafe: false,
                     },
+                    output_span: None,
                 });
 
                 let input: Vec<_> = marked_tts.into_trees().collect();
@@ -588,7 +670,9 @@ impl<'a, 'b> MacroExpander<'a, 'b> {
                         span: None,
                         // FIXME probably want to follow macro_rules macros here.
                         allow_internal_unstable: false,
+                        allow_internal_unsafe: false,
                     },
+                    output_span: None,
                 });
 
                 let tok_result = expandfun.expand(self.cx, span, marked_tts);
@@ -640,7 +724,9 @@ impl<'a, 'b> MacroExpander<'a, 'b> {
                 format: MacroAttribute(pretty_name),
                 span: None,
                 allow_internal_unstable: false,
+                allow_internal_unsafe: false,
             },
+            output_span: None,
         };
 
         match *ext {
@@ -689,9 +775,7 @@ impl<'a, 'b> MacroExpander<'a, 'b> {
         path: &Path,
         span: Span,
     ) -> Expansion {
-        let mut parser = self
-            .cx
-            .new_parser_from_tts(&toks.into_trees().collect::<Vec<_>>());
+        let mut parser = self.cx.new_parser_from_stream(toks);
         let expansion = match parser.parse_expansion(kind, false) {
             Ok(expansion) => expansion,
             Err(mut err) => {
@@ -733,6 +817,15 @@ impl<'a> Parser<'a> {
                 }
                 Expansion::ImplItems(items)
             }
+            ExpansionKind::ForeignItems => {
+                // The ABI of the enclosing block is not known here, so variadic
+                // functions are checked against the C ABI.
+                let mut items = SmallVector::new();
+                while let Some(item) = self.parse_foreign_item(Abi::C)? {
+                    items.push(item);
+                }
+                Expansion::ForeignItems(items)
+            }
             ExpansionKind::Stmts => {
                 let mut stmts = SmallVector::new();
                 while self.token != token::Eof &&
@@ -800,12 +893,19 @@ impl<'a, 'b> InvocationCollector<'a, 'b> {
         placeholder(expansion_kind, NodeId::placeholder_from_mark(mark))
     }
 
-    fn collect_bang(&mut self, mac: ast::Mac, span: Span, kind: ExpansionKind) -> Expansion {
+    fn collect_bang(
+        &mut self,
+        mac: ast::Mac,
+        attrs: Vec<ast::Attribute>,
+        span: Span,
+        kind: ExpansionKind,
+    ) -> Expansion {
         self.collect(
             kind,
             InvocationKind::Bang {
                 mac: mac,
                 ident: None,
+                attrs: attrs,
                 span: span,
             },
         )
@@ -849,6 +949,10 @@ impl<'a, 'b> InvocationCollector<'a, 'b> {
             }
 
             if self.cx.ecfg.proc_macro_enabled() {
+                // Tool attributes are not macro invocations.
+                for tool_attr in attrs.iter().filter(|a| self.cx.parse_sess.is_tool_attr(a)) {
+                    attr::mark_known(tool_attr);
+                }
                 attr = find_attr_invoc(&mut attrs);
             }
             traits = collect_derives(&mut self.cx, &mut attrs);
@@ -879,6 +983,229 @@ pub fn find_attr_invoc(attrs: &mut Vec<ast::Attribute>) -> Option<ast::Attribute
         .map(|i| attrs.remove(i))
 }
 
+/// A macro invocation found by `collect_invocations`.
+#[derive(Clone, Debug)]
+pub struct InvocationInfo {
+    /// The path of the macro, as written.
+    pub path: Path,
+    pub kind: MacroKind,
+    /// The span of the whole invocation for bang macros, and of the
+    /// attribute or of the path in `#[derive(..)]` otherwise.
+    pub span: Span,
+    /// The number of tokens the invocation was written with, counting each
+    /// delimiter: the arguments of a bang or attribute macro. Zero for
+    /// derives, whose only input is the item.
+    pub input_tokens: usize,
+}
+
+/// Lists the macro invocations in `krate` without expanding anything, in
+/// the order they appear in the source. Attributes are classified like the
+/// `InvocationCollector` does, except that all attributes that are not
+/// builtin are taken to be attribute macros, whether or not they resolve.
+///
+/// Attributes are looked for on items, trait and impl items, foreign items,
+/// statements, struct fields and enum variants.
+///
+/// Since nothing is expanded, only invocations that are already visible in
+/// the AST are found: those in the input of another macro are just tokens
+/// until that macro is expanded, and so are not listed.
+pub fn collect_invocations(krate: &ast::Crate) -> Vec<InvocationInfo> {
+    let mut collector = InvocationLister {
+        invocations: Vec::new(),
+    };
+    collector.collect_attrs(&krate.attrs);
+    visit::walk_crate(&mut collector, krate);
+    collector.invocations
+}
+
+struct InvocationLister {
+    invocations: Vec<InvocationInfo>,
+}
+
+impl InvocationLister {
+    fn collect_attrs(&mut self, attrs: &[ast::Attribute]) {
+        for attr in attrs {
+            if attr.path == "derive" {
+                let derives = derive_paths(attr).into_iter().map(|path| InvocationInfo {
+                    span: path.span,
+                    path,
+                    kind: MacroKind::Derive,
+                    input_tokens: 0,
+                });
+                self.invocations.extend(derives);
+            } else if !attr::is_known(attr) && !is_builtin_attr(attr) {
+                self.invocations.push(InvocationInfo {
+                    path: attr.path.clone(),
+                    kind: MacroKind::Attr,
+                    span: attr.span,
+                    input_tokens: count_tokens(&attr.tokens),
+                });
+            }
+        }
+    }
+}
+
+// The paths in `#[derive(..)]`, read from its tokens without a parser, since
+// there is no `ParseSess` to report errors to. Entries that are not plain
+// paths are skipped.
+fn derive_paths(attr: &ast::Attribute) -> Vec<Path> {
+    let mut trees = attr.tokens.trees();
+    let stream = match (trees.next(), trees.next()) {
+        (Some(TokenTree::Delimited(_, ref delimited)), None) if delimited.delim == token::Paren => {
+            delimited.stream()
+        }
+        _ => return Vec::new(),
+    };
+
+    let (mut paths, mut segments) = (Vec::new(), Vec::new());
+    let (mut valid, mut expect_ident) = (true, true);
+    let end = TokenTree::Token(DUMMY_SP, token::Comma);
+    for tree in stream.trees().chain(Some(end)) {
+        match tree {
+            TokenTree::Token(span, token::Ident(ident)) if expect_ident => {
+                segments.push(ast::PathSegment::from_ident(ident, span));
+                expect_ident = false;
+            }
+            TokenTree::Token(_, token::ModSep) if !expect_ident => expect_ident = true,
+            TokenTree::Token(_, token::Comma) => {
+                let segments = mem::take(&mut segments);
+                if valid && !expect_ident {
+                    let span = segments[0].span.to(segments[segments.len() - 1].span);
+                    paths.push(Path { span, segments });
+                }
+                valid = true;
+                expect_ident = true;
+            }
+            _ => valid = false,
+        }
+    }
+    paths
+}
+
+fn count_tokens(stream: &TokenStream) -> usize {
+    stream
+        .trees()
+        .map(|tree| match tree {
+            TokenTree::Token(..) => 1,
+            TokenTree::Delimited(_, ref delimited) => 2 + count_tokens(&delimited.stream()),
+        })
+        .sum()
+}
+
+impl<'ast> Visitor<'ast> for InvocationLister {
+    fn visit_item(&mut self, item: &'ast ast::Item) {
+        self.collect_attrs(&item.attrs);
+        visit::walk_item(self, item);
+    }
+
+    fn visit_trait_item(&mut self, item: &'ast ast::TraitItem) {
+        self.collect_attrs(&item.attrs);
+        visit::walk_trait_item(self, item);
+    }
+
+    fn visit_impl_item(&mut self, item: &'ast ast::ImplItem) {
+        self.collect_attrs(&item.attrs);
+        visit::walk_impl_item(self, item);
+    }
+
+    fn visit_foreign_item(&mut self, item: &'ast ast::ForeignItem) {
+        self.collect_attrs(&item.attrs);
+        visit::walk_foreign_item(self, item);
+    }
+
+    fn visit_stmt(&mut self, stmt: &'ast ast::Stmt) {
+        // Items in statement position are listed by `visit_item`.
+        self.collect_attrs(stmt.attrs());
+        visit::walk_stmt(self, stmt);
+    }
+
+    fn visit_struct_field(&mut self, field: &'ast ast::StructField) {
+        self.collect_attrs(&field.attrs);
+        visit::walk_struct_field(self, field);
+    }
+
+    fn visit_variant(
+        &mut self,
+        variant: &'ast ast::Variant,
+        generics: &'ast ast::Generics,
+        item_id: NodeId,
+    ) {
+        self.collect_attrs(&variant.node.attrs);
+        visit::walk_variant(self, variant, generics, item_id);
+    }
+
+    fn visit_mac(&mut self, mac: &'ast ast::Mac) {
+        self.invocations.push(InvocationInfo {
+            path: mac.node.path.clone(),
+            kind: MacroKind::Bang,
+            span: mac.span,
+            input_tokens: count_tokens(&mac.node.stream()),
+        });
+    }
+}
+
+/// Where an item in an expanded crate came from, as found by `provenance`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Provenance {
+    /// The item was written in the source.
+    UserWritten,
+    /// The item was produced by expanding the macro `expn_name`, invoked at
+    /// `call_site`. Items passed through a macro unchanged keep the context
+    /// of their tokens and so are `UserWritten`.
+    MacroGenerated { expn_name: Symbol, call_site: Span },
+    /// The item was added by `std_inject`.
+    Injected,
+}
+
+impl Provenance {
+    fn of_span(span: Span) -> Provenance {
+        match span.ctxt.outer().expn_info() {
+            None => Provenance::UserWritten,
+            Some(info) => {
+                let expn_name = match info.callee.format {
+                    MacroAttribute(name) | MacroBang(name) | CompilerDesugaring(name) => name,
+                };
+                if expn_name == std_inject::STD_INJECT {
+                    Provenance::Injected
+                } else {
+                    Provenance::MacroGenerated {
+                        expn_name,
+                        call_site: info.call_site,
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Classifies every item in the expanded `krate`, including the items
+/// nested in modules and function bodies, by the syntax context of its span.
+/// The items are keyed by address, so the map is only meaningful for as long
+/// as `krate` is borrowed.
+pub fn provenance(krate: &ast::Crate) -> HashMap<*const ast::Item, Provenance> {
+    let mut collector = ProvenanceCollector {
+        provenance: HashMap::new(),
+    };
+    visit::walk_crate(&mut collector, krate);
+    collector.provenance
+}
+
+struct ProvenanceCollector {
+    provenance: HashMap<*const ast::Item, Provenance>,
+}
+
+impl<'ast> Visitor<'ast> for ProvenanceCollector {
+    fn visit_item(&mut self, item: &'ast ast::Item) {
+        self.provenance
+            .insert(item as *const _, Provenance::of_span(item.span));
+        visit::walk_item(self, item);
+    }
+
+    fn visit_mac(&mut self, _mac: &'ast ast::Mac) {
+        // Macros kept with `keep_macs` have nothing more to classify.
+    }
+}
+
 // These are pretty nasty. Ideally, we would keep the tokens around, linked from
 // the AST. However, we don't so we need to create new ones. Since the item might
 // have come from a macro expansion (possibly only in part), we can't use the
@@ -897,7 +1224,7 @@ fn stream_for_item(item: &Annotatable, parse_sess: &ParseSess) -> TokenStream {
 }
 
 fn string_to_stream(text: String, parse_sess: &ParseSess) -> TokenStream {
-    let filename = String::from("<macro expansion>");
+    let filename = FileName::Custom("macro expansion".to_string());
     filemap_to_stream(parse_sess, parse_sess.codemap().new_filemap(filename, text))
 }
 
@@ -908,7 +1235,7 @@ impl<'a, 'b> Folder for InvocationCollector<'a, 'b> {
 
         if let ast::ExprKind::Mac(mac) = expr.node {
             self.check_attributes(&expr.attrs);
-            self.collect_bang(mac, expr.span, ExpansionKind::Expr)
+            self.collect_bang(mac, expr.attrs.into(), expr.span, ExpansionKind::Expr)
                 .make_expr()
         } else {
             P(noop_fold_expr(expr, self))
@@ -921,7 +1248,7 @@ impl<'a, 'b> Folder for InvocationCollector<'a, 'b> {
 
         if let ast::ExprKind::Mac(mac) = expr.node {
             self.check_attributes(&expr.attrs);
-            self.collect_bang(mac, expr.span, ExpansionKind::OptExpr)
+            self.collect_bang(mac, expr.attrs.into(), expr.span, ExpansionKind::OptExpr)
                 .make_opt_expr()
         } else {
             Some(P(noop_fold_expr(expr, self)))
@@ -937,7 +1264,7 @@ impl<'a, 'b> Folder for InvocationCollector<'a, 'b> {
 
         pat.and_then(|pat| match pat.node {
             PatKind::Mac(mac) => self
-                .collect_bang(mac, pat.span, ExpansionKind::Pat)
+                .collect_bang(mac, Vec::new(), pat.span, ExpansionKind::Pat)
                 .make_pat(),
             _ => unreachable!(),
         })
@@ -966,7 +1293,7 @@ impl<'a, 'b> Folder for InvocationCollector<'a, 'b> {
 
         self.check_attributes(&attrs);
         let mut placeholder = self
-            .collect_bang(mac, stmt.span, ExpansionKind::Stmts)
+            .collect_bang(mac, attrs.into(), stmt.span, ExpansionKind::Stmts)
             .make_stmts();
 
         // If this is a macro invocation with a semicolon, then apply that
@@ -1009,6 +1336,7 @@ impl<'a, 'b> Folder for InvocationCollector<'a, 'b> {
                             InvocationKind::Bang {
                                 mac: mac,
                                 ident: Some(item.ident),
+                                attrs: item.attrs,
                                 span: item.span,
                             },
                         )
@@ -1086,7 +1414,7 @@ impl<'a, 'b> Folder for InvocationCollector<'a, 'b> {
             ast::TraitItemKind::Macro(mac) => {
                 let ast::TraitItem { attrs, span, .. } = item;
                 self.check_attributes(&attrs);
-                self.collect_bang(mac, span, ExpansionKind::TraitItems)
+                self.collect_bang(mac, attrs, span, ExpansionKind::TraitItems)
                     .make_trait_items()
             }
             _ => fold::noop_fold_trait_item(item, self),
@@ -1108,13 +1436,25 @@ impl<'a, 'b> Folder for InvocationCollector<'a, 'b> {
             ast::ImplItemKind::Macro(mac) => {
                 let ast::ImplItem { attrs, span, .. } = item;
                 self.check_attributes(&attrs);
-                self.collect_bang(mac, span, ExpansionKind::ImplItems)
+                self.collect_bang(mac, attrs, span, ExpansionKind::ImplItems)
                     .make_impl_items()
             }
             _ => fold::noop_fold_impl_item(item, self),
         }
     }
 
+    fn fold_foreign_item(&mut self, item: ast::ForeignItem) -> SmallVector<ast::ForeignItem> {
+        match item.node {
+            ast::ForeignItemKind::Macro(mac) => {
+                let ast::ForeignItem { attrs, span, .. } = item;
+                self.check_attributes(&attrs);
+                self.collect_bang(mac, attrs, span, ExpansionKind::ForeignItems)
+                    .make_foreign_items()
+            }
+            _ => fold::noop_fold_foreign_item(item, self),
+        }
+    }
+
     fn fold_ty(&mut self, ty: P<ast::Ty>) -> P<ast::Ty> {
         let ty = match ty.node {
             ast::TyKind::Mac(_) => ty.unwrap(),
@@ -1122,7 +1462,9 @@ impl<'a, 'b> Folder for InvocationCollector<'a, 'b> {
         };
 
         match ty.node {
-            ast::TyKind::Mac(mac) => self.collect_bang(mac, ty.span, ExpansionKind::Ty).make_ty(),
+            ast::TyKind::Mac(mac) => self
+                .collect_bang(mac, Vec::new(), ty.span, ExpansionKind::Ty)
+                .make_ty(),
             _ => unreachable!(),
         }
     }
@@ -1148,13 +1490,40 @@ impl<'a, 'b> Folder for InvocationCollector<'a, 'b> {
     }
 }
 
+/// Configures a `MacroExpander`. Start from `ExpansionConfig::default` and
+/// adjust it with the builder methods:
+///
+/// ```
+/// use garando_syntax::ext::expand::ExpansionConfig;
+///
+/// let ecfg = ExpansionConfig::default("krate".to_string())
+///     .keep_macs(true)
+///     .trace_macros(false)
+///     .recursion_limit(64);
+/// assert!(ecfg.keep_macs && !ecfg.trace_mac);
+/// assert_eq!(ecfg.recursion_limit, 64);
+/// ```
 pub struct ExpansionConfig<'feat> {
+    /// The name of the crate, which is the first segment of module paths.
     pub crate_name: String,
+    /// The features enabled in the crate, which gate some macros.
     pub features: Option<&'feat Features>,
+    /// How deeply macro invocations may nest, counting invocations produced
+    /// by other invocations, before expansion fails with a fatal error.
     pub recursion_limit: usize,
+    /// Whether `macro_rules!` macros report what they expand, as with
+    /// `trace_macros!(true)`.
     pub trace_mac: bool,
-    pub should_test: bool, // If false, strip `#[test]` nodes
+    /// Whether items marked `#[test]` or `#[bench]` are kept, and made public
+    /// for the test harness. If false, they are stripped like items whose
+    /// `#[cfg]` is false.
+    pub should_test: bool,
+    /// Whether only one level of invocations is expanded, leaving the
+    /// invocations they produce as placeholders.
     pub single_step: bool,
+    /// Whether macro invocations the resolver can't resolve, and `macro_rules!`
+    /// definitions, are left in the AST as macro nodes. If false, unresolved
+    /// invocations expand to nothing and definitions are removed.
     pub keep_macs: bool,
 }
 
@@ -1184,6 +1553,42 @@ impl<'feat> ExpansionConfig<'feat> {
         }
     }
 
+    /// Sets `features`.
+    pub fn features(mut self, features: &'feat Features) -> Self {
+        self.features = Some(features);
+        self
+    }
+
+    /// Sets `recursion_limit`.
+    pub fn recursion_limit(mut self, recursion_limit: usize) -> Self {
+        self.recursion_limit = recursion_limit;
+        self
+    }
+
+    /// Sets `trace_mac`.
+    pub fn trace_macros(mut self, trace_macros: bool) -> Self {
+        self.trace_mac = trace_macros;
+        self
+    }
+
+    /// Sets `should_test`.
+    pub fn should_test(mut self, should_test: bool) -> Self {
+        self.should_test = should_test;
+        self
+    }
+
+    /// Sets `single_step`.
+    pub fn single_step(mut self, single_step: bool) -> Self {
+        self.single_step = single_step;
+        self
+    }
+
+    /// Sets `keep_macs`.
+    pub fn keep_macs(mut self, keep_macs: bool) -> Self {
+        self.keep_macs = keep_macs;
+        self
+    }
+
     feature_tests! {
         fn enable_quotes = quote,
         fn enable_asm = asm,
@@ -1192,6 +1597,7 @@ impl<'feat> ExpansionConfig<'feat> {
         fn enable_concat_idents = concat_idents,
         fn enable_trace_macros = trace_macros,
         fn enable_allow_internal_unstable = allow_internal_unstable,
+        fn enable_allow_internal_unsafe = allow_internal_unsafe,
         fn enable_custom_derive = custom_derive,
         fn proc_macro_enabled = proc_macro,
     }
@@ -1215,3 +1621,472 @@ impl Folder for Marker {
         noop_fold_mac(mac, self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codemap::FilePathMapping;
+    use crate::ext::build::AstBuilder;
+    use crate::ext::tt::collect_macro_defs;
+    use crate::testing::try_string_to_crate;
+    use std::cell::RefCell;
+
+    /// Resolves bang macros by name from a fixed table.
+    struct TestResolver {
+        macros: HashMap<ast::Name, Rc<SyntaxExtension>>,
+    }
+
+    impl Resolver for TestResolver {
+        fn next_node_id(&mut self) -> ast::NodeId {
+            ast::DUMMY_NODE_ID
+        }
+        fn get_module_scope(&mut self, _id: ast::NodeId) -> Mark {
+            Mark::root()
+        }
+        fn eliminate_crate_var(&mut self, item: P<ast::Item>) -> P<ast::Item> {
+            item
+        }
+        fn is_whitelisted_legacy_custom_derive(&self, _name: ast::Name) -> bool {
+            false
+        }
+        fn visit_expansion(&mut self, _mark: Mark, _expansion: &Expansion, _derives: &[Mark]) {}
+        fn add_builtin(&mut self, ident: Ident, ext: Rc<SyntaxExtension>) {
+            self.macros.insert(ident.name, ext);
+        }
+        fn resolve_imports(&mut self) {}
+        fn find_legacy_attr_invoc(
+            &mut self,
+            _attrs: &mut Vec<ast::Attribute>,
+        ) -> Option<ast::Attribute> {
+            None
+        }
+        fn resolve_invoc(
+            &mut self,
+            invoc: &mut Invocation,
+            _scope: Mark,
+            _force: bool,
+        ) -> Result<Option<Rc<SyntaxExtension>>, Determinacy> {
+            match invoc.kind {
+                InvocationKind::Bang { ref mac, .. } => {
+                    let name = mac.node.path.segments.last().unwrap().identifier.name;
+                    match self.macros.get(&name) {
+                        Some(ext) => Ok(Some(ext.clone())),
+                        None => Err(Determinacy::Determined),
+                    }
+                }
+                _ => Err(Determinacy::Determined),
+            }
+        }
+        fn resolve_macro(
+            &mut self,
+            _scope: Mark,
+            _path: &ast::Path,
+            _kind: MacroKind,
+            _force: bool,
+        ) -> Result<Rc<SyntaxExtension>, Determinacy> {
+            Err(Determinacy::Determined)
+        }
+        fn check_unused_macros(&self) {}
+    }
+
+    #[test]
+    fn expansion_output_span() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let defs = panictry!(try_string_to_crate(
+            &sess,
+            "macro_rules! make_items { () => { fn a() {} fn b() {} } }".to_string()
+        ));
+        let krate = panictry!(try_string_to_crate(
+            &sess,
+            "make_items!();\nfn main() {}\n".to_string()
+        ));
+
+        let mut resolver = TestResolver {
+            macros: HashMap::new(),
+        };
+        let features = RefCell::new(Features::new());
+        for def in collect_macro_defs(&defs) {
+            resolver.add_builtin(def.ident(), Rc::new(def.compile(&sess, &features)));
+        }
+        let ecfg_features = Features::new();
+        let ecfg = ExpansionConfig::default("test".to_string()).features(&ecfg_features);
+        let mut cx = ExtCtxt::new(&sess, ecfg, &mut resolver);
+        let krate = MacroExpander::new(&mut cx, false).expand_crate(krate);
+
+        let names: Vec<_> = krate
+            .module
+            .items
+            .iter()
+            .map(|item| item.ident.to_string())
+            .collect();
+        assert_eq!(names, ["a", "b", "main"]);
+        let (a, b, main) = (
+            &krate.module.items[0],
+            &krate.module.items[1],
+            &krate.module.items[2],
+        );
+
+        let output_span = a.span.to(b.span);
+        let info = a.span.ctxt.outer().expn_info().unwrap();
+        assert_eq!(info.output_span, Some(output_span));
+        assert!(output_span.lo <= a.span.lo && b.span.hi <= output_span.hi);
+        assert_eq!(a.span.expansion_output_span(), Some(output_span));
+        assert_eq!(b.span.expansion_output_span(), Some(output_span));
+        assert_eq!(a.span.macro_backtrace()[0].output_span, Some(output_span));
+        assert_eq!(main.span.expansion_output_span(), None);
+    }
+
+    fn expand_foreign_fn<'cx>(
+        cx: &'cx mut ExtCtxt,
+        sp: Span,
+        _tts: &[TokenTree],
+    ) -> Box<dyn MacResult + 'cx> {
+        let decl = cx.fn_decl(Vec::new(), cx.ty(sp, ast::TyKind::Tup(Vec::new())));
+        MacEager::foreign_items(SmallVector::one(ast::ForeignItem {
+            ident: Ident::from_str("generated"),
+            attrs: Vec::new(),
+            node: ast::ForeignItemKind::Fn(decl, ast::Generics::default()),
+            id: ast::DUMMY_NODE_ID,
+            span: sp,
+            vis: ast::Visibility::Inherited,
+        }))
+    }
+
+    #[test]
+    fn foreign_item_macros() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let defs = panictry!(try_string_to_crate(
+            &sess,
+            "macro_rules! declare { ($f:ident) => { fn $f(x: u8, ...); } }".to_string()
+        ));
+        let krate = panictry!(try_string_to_crate(
+            &sess,
+            "extern \"C\" { fn first(); make_foreign!(); declare!(last); }".to_string()
+        ));
+
+        let mut resolver = TestResolver {
+            macros: HashMap::new(),
+        };
+        let features = RefCell::new(Features::new());
+        for def in collect_macro_defs(&defs) {
+            resolver.add_builtin(def.ident(), Rc::new(def.compile(&sess, &features)));
+        }
+        let expander: MacroExpanderFn = expand_foreign_fn;
+        resolver.add_builtin(
+            Ident::from_str("make_foreign"),
+            Rc::new(NormalTT(Box::new(expander), None, false, false)),
+        );
+        let ecfg_features = Features::new();
+        let ecfg = ExpansionConfig::default("test".to_string()).features(&ecfg_features);
+        let mut cx = ExtCtxt::new(&sess, ecfg, &mut resolver);
+        let krate = MacroExpander::new(&mut cx, false).expand_crate(krate);
+
+        let foreign_mod = match krate.module.items[0].node {
+            ast::ItemKind::ForeignMod(ref foreign_mod) => foreign_mod,
+            _ => panic!("expected an extern block"),
+        };
+        let names: Vec<_> = foreign_mod
+            .items
+            .iter()
+            .map(|item| item.ident.to_string())
+            .collect();
+        assert_eq!(names, ["first", "generated", "last"]);
+        assert!(foreign_mod
+            .items
+            .iter()
+            .all(|item| matches!(item.node, ast::ForeignItemKind::Fn(..))));
+        assert_eq!(
+            pprust::item_to_string(&krate.module.items[0]),
+            "extern \"C\" {\n    fn first();\n    fn generated() -> ();\n    fn last(x: u8, ...);\n}"
+        );
+    }
+
+    #[test]
+    fn provenance_after_expansion() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let defs = panictry!(try_string_to_crate(
+            &sess,
+            "macro_rules! make { () => { fn made() {} struct Made; } }\n\
+             macro_rules! pass { ($i:item) => { $i } }"
+                .to_string()
+        ));
+        let krate = panictry!(try_string_to_crate(
+            &sess,
+            "mod m { fn user() { make!(); } }\nmake!();\npass! { struct Passed; }".to_string()
+        ));
+        let krate = std_inject::maybe_inject_crates_ref(krate, None);
+
+        let mut resolver = TestResolver {
+            macros: HashMap::new(),
+        };
+        let features = RefCell::new(Features::new());
+        for def in collect_macro_defs(&defs) {
+            resolver.add_builtin(def.ident(), Rc::new(def.compile(&sess, &features)));
+        }
+        let ecfg_features = Features::new();
+        let ecfg = ExpansionConfig::default("test".to_string()).features(&ecfg_features);
+        let mut cx = ExtCtxt::new(&sess, ecfg, &mut resolver);
+        let krate = MacroExpander::new(&mut cx, false).expand_crate(krate);
+
+        let provenance = provenance(&krate);
+        assert_eq!(provenance.len(), 9);
+        let count = |f: &dyn Fn(&Provenance) -> bool| provenance.values().filter(|p| f(p)).count();
+        assert_eq!(count(&|p| *p == Provenance::Injected), 2);
+        assert_eq!(count(&|p| *p == Provenance::UserWritten), 3);
+        assert_eq!(
+            count(&|p| match *p {
+                Provenance::MacroGenerated { expn_name, .. } => expn_name == "make",
+                _ => false,
+            }),
+            4
+        );
+
+        let made = krate
+            .module
+            .items
+            .iter()
+            .find(|item| item.ident.name == "Made")
+            .unwrap();
+        match provenance[&(&**made as *const _)] {
+            Provenance::MacroGenerated { call_site, .. } => assert_eq!(
+                sess.codemap().span_to_snippet(call_site).unwrap(),
+                "make!();"
+            ),
+            ref other => panic!("unexpected provenance {:?}", other),
+        }
+    }
+
+    /// Expands `src` with the `macro_rules!` macros of `defs` and the
+    /// expansion configuration `ecfg`, returning the expanded crate's source.
+    fn expand_source(sess: &ParseSess, defs: &str, src: &str, ecfg: ExpansionConfig) -> String {
+        let defs = panictry!(try_string_to_crate(sess, defs.to_string()));
+        let krate = panictry!(try_string_to_crate(sess, src.to_string()));
+
+        let mut resolver = TestResolver {
+            macros: HashMap::new(),
+        };
+        let features = RefCell::new(Features::new());
+        for def in collect_macro_defs(&defs) {
+            resolver.add_builtin(def.ident(), Rc::new(def.compile(sess, &features)));
+        }
+        let mut cx = ExtCtxt::new(sess, ecfg, &mut resolver);
+        let krate = MacroExpander::new(&mut cx, false).expand_crate(krate);
+        pprust::to_string(|s| s.print_mod(&krate.module, &krate.attrs))
+    }
+
+    #[test]
+    fn keep_macs_leaves_unresolved_invocations() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let defs = "macro_rules! two { () => { 2 } }";
+        let src = "unknown!(item);\n\
+                   fn main() { let x: unknown!() = unknown!(x) + two!(); unknown! { s } }\n";
+        let features = Features::new();
+
+        let kept = expand_source(
+            &sess,
+            defs,
+            src,
+            ExpansionConfig::default("test".to_string())
+                .features(&features)
+                .keep_macs(true),
+        );
+        assert_eq!(
+            kept,
+            "unknown!(item);\n\
+             fn main() { let x: unknown!() = unknown!(x) + 2; unknown! { s } }"
+        );
+
+        let removed = expand_source(
+            &sess,
+            defs,
+            src,
+            ExpansionConfig::default("test".to_string()).features(&features),
+        );
+        assert!(!removed.contains("unknown"), "{}", removed);
+    }
+
+    #[test]
+    fn keep_macs_keeps_attributes() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let src = "#[doc = \"kept\"]\n#[allow(x)]\nunknown!(item);\n\
+                   trait T { #[allow(y)] unknown!(); }\n\
+                   fn main() { #[allow(z)] unknown!(s); }\n";
+        let features = Features::new();
+
+        let kept = expand_source(
+            &sess,
+            "",
+            src,
+            ExpansionConfig::default("test".to_string())
+                .features(&features)
+                .keep_macs(true),
+        );
+        assert_eq!(
+            kept,
+            "#[doc = \"kept\"]\n#[allow(x)]\nunknown!(item);\n\
+             trait T {\n    #[allow(y)]\n    unknown!();\n}\n\
+             fn main() {\n\n    #[allow(z)]\n    unknown!(s);\n}"
+        );
+    }
+
+    #[test]
+    fn should_test_keeps_test_items() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let src = "#[test]\nfn t() {}\n#[bench]\nfn b() {}\nfn main() {}\n\
+                   mod m { #[test] fn inner() {} }\n";
+        let features = Features::new();
+
+        let kept = expand_source(
+            &sess,
+            "",
+            src,
+            ExpansionConfig::default("test".to_string())
+                .features(&features)
+                .should_test(true),
+        );
+        assert_eq!(
+            kept,
+            "#[test]\npub fn t() { }\n#[bench]\npub fn b() { }\nfn main() { }\n\
+             mod m {\n    #[test]\n    pub fn inner() { }\n}"
+        );
+
+        let stripped = expand_source(
+            &sess,
+            "",
+            src,
+            ExpansionConfig::default("test".to_string()).features(&features),
+        );
+        assert_eq!(stripped, "fn main() { }\nmod m { }");
+    }
+
+    #[test]
+    fn recursion_limit_from_builder() {
+        let defs = "macro_rules! count {\n\
+                    () => { 0 };\n\
+                    ($t:tt $($rest:tt)*) => { 1 + count!($($rest)*) };\n\
+                    }";
+        let src = "fn main() { count!(a a a); }";
+        let features = Features::new();
+
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let ecfg = ExpansionConfig::default("test".to_string())
+            .features(&features)
+            .recursion_limit(4);
+        assert_eq!(
+            expand_source(&sess, defs, src, ecfg),
+            "fn main() { 1 + 1 + 1 + 0; }"
+        );
+
+        let (sess, diagnostics) = ParseSess::new_for_batch(FilePathMapping::empty());
+        let ecfg = ExpansionConfig::default("test".to_string())
+            .features(&features)
+            .recursion_limit(3);
+        let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+            expand_source(&sess, defs, src, ecfg)
+        }));
+        assert!(result.is_err());
+        assert!(diagnostics
+            .take_diagnostics()
+            .iter()
+            .any(|d| d.message().contains("recursion limit reached")));
+    }
+
+    #[test]
+    fn dummy_result_has_no_foreign_items() {
+        let items = DummyResult::any(DUMMY_SP).make_foreign_items();
+        assert_eq!(items.map(|items| items.len()), Some(0));
+        assert!(DummyResult::expr(DUMMY_SP).make_foreign_items().is_none());
+    }
+
+    #[test]
+    fn collect_invocations_without_expanding() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let src = "#[derive(Debug, serde::Serialize)]\n\
+                   struct S;\n\
+                   #[my_attr(a, b)]\n\
+                   fn f() {\n\
+                   let v = vec![1, 2, 3];\n\
+                   outer!(inner!(x));\n\
+                   }\n";
+        let krate = panictry!(try_string_to_crate(&sess, src.to_string()));
+
+        let invocations: Vec<_> = collect_invocations(&krate)
+            .into_iter()
+            .map(|invoc| {
+                let snippet = sess.codemap().span_to_snippet(invoc.span).unwrap();
+                (
+                    invoc.path.to_string(),
+                    invoc.kind,
+                    snippet,
+                    invoc.input_tokens,
+                )
+            })
+            .collect();
+        let expected = |path: &str, kind, snippet: &str, tokens| {
+            (path.to_string(), kind, snippet.to_string(), tokens)
+        };
+        assert_eq!(
+            invocations,
+            [
+                expected("Debug", MacroKind::Derive, "Debug", 0),
+                expected("serde::Serialize", MacroKind::Derive, "serde::Serialize", 0),
+                expected("my_attr", MacroKind::Attr, "#[my_attr(a, b)]", 5),
+                expected("vec", MacroKind::Bang, "vec![1, 2, 3]", 5),
+                expected("outer", MacroKind::Bang, "outer!(inner!(x))", 5),
+            ]
+        );
+    }
+
+    // The path, kind and snippet of each invocation `collect_invocations`
+    // finds in `src`.
+    fn invocation_snippets(src: &str) -> Vec<(String, MacroKind, String)> {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let krate = panictry!(try_string_to_crate(&sess, src.to_string()));
+        collect_invocations(&krate)
+            .into_iter()
+            .map(|invoc| {
+                let snippet = sess.codemap().span_to_snippet(invoc.span).unwrap();
+                (invoc.path.to_string(), invoc.kind, snippet)
+            })
+            .collect()
+    }
+
+    fn attr_and_bang(attr: &str, bang: &str) -> Vec<(String, MacroKind, String)> {
+        vec![
+            ("my_attr".to_string(), MacroKind::Attr, attr.to_string()),
+            ("m".to_string(), MacroKind::Bang, bang.to_string()),
+        ]
+    }
+
+    #[test]
+    fn collect_invocations_in_foreign_items() {
+        assert_eq!(
+            invocation_snippets("extern { #[my_attr] fn f(); m!(); }"),
+            attr_and_bang("#[my_attr]", "m!();")
+        );
+    }
+
+    #[test]
+    fn collect_invocations_in_statements() {
+        assert_eq!(
+            invocation_snippets("fn f() { #[my_attr] let x = m!(); }"),
+            attr_and_bang("#[my_attr]", "m!()")
+        );
+    }
+
+    #[test]
+    fn collect_invocations_in_struct_fields() {
+        assert_eq!(
+            invocation_snippets("struct S { #[my_attr] a: [u8; m!()] }"),
+            attr_and_bang("#[my_attr]", "m!()")
+        );
+    }
+
+    #[test]
+    fn collect_invocations_in_enum_variants() {
+        assert_eq!(
+            invocation_snippets("enum E { #[my_attr] A = m!() }"),
+            attr_and_bang("#[my_attr]", "m!()")
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use crate::ast::{self, NodeId};
-use crate::codemap::{dummy_spanned, DUMMY_SP};
+use crate::codemap::{dummy_spanned, Span, DUMMY_SP};
 use crate::ext::base::ExtCtxt;
 use crate::ext::expand::{Expansion, ExpansionKind};
 use crate::ext::hygiene::Mark;
@@ -13,46 +13,56 @@ use crate::util::small_vector::SmallVector;
 use std::collections::HashMap;
 
 pub fn placeholder(kind: ExpansionKind, id: ast::NodeId) -> Expansion {
-    fn mac_placeholder() -> ast::Mac {
-        dummy_spanned(ast::Mac_ {
-            path: ast::Path {
-                span: DUMMY_SP,
-                segments: Vec::new(),
-            },
-            tts: TokenStream::empty().into(),
-        })
-    }
+    let mac = dummy_spanned(ast::Mac_ {
+        path: ast::Path {
+            span: DUMMY_SP,
+            segments: Vec::new(),
+        },
+        delim: ast::MacDelimiter::Parenthesis,
+        tts: TokenStream::empty().into(),
+    });
+    macro_invocation(kind, id, mac, None, Vec::new(), DUMMY_SP)
+}
 
-    let ident = keywords::Invalid.ident();
-    let attrs = Vec::new();
+/// A node of the given kind consisting of the invocation of `mac`, named
+/// `ident` if it is an item, with the attributes `attrs` if it can have any.
+pub fn macro_invocation(
+    kind: ExpansionKind,
+    id: ast::NodeId,
+    mac: ast::Mac,
+    ident: Option<ast::Ident>,
+    attrs: Vec<ast::Attribute>,
+    span: Span,
+) -> Expansion {
+    let ident = ident.unwrap_or_else(|| keywords::Invalid.ident());
     let vis = ast::Visibility::Inherited;
-    let span = DUMMY_SP;
-    let expr_placeholder = || {
+    let expr = |mac, attrs: Vec<ast::Attribute>| {
         P(ast::Expr {
             id: id,
             span: span,
-            attrs: ast::ThinVec::new(),
-            node: ast::ExprKind::Mac(mac_placeholder()),
+            bare_span: span,
+            attrs: attrs.into(),
+            node: ast::ExprKind::Mac(mac),
         })
     };
 
     match kind {
-        ExpansionKind::Expr => Expansion::Expr(expr_placeholder()),
-        ExpansionKind::OptExpr => Expansion::OptExpr(Some(expr_placeholder())),
+        ExpansionKind::Expr => Expansion::Expr(expr(mac, attrs)),
+        ExpansionKind::OptExpr => Expansion::OptExpr(Some(expr(mac, attrs))),
         ExpansionKind::Items => Expansion::Items(SmallVector::one(P(ast::Item {
             id: id,
             span: span,
             ident: ident,
             vis: vis,
             attrs: attrs,
-            node: ast::ItemKind::Mac(mac_placeholder()),
+            node: ast::ItemKind::Mac(mac),
         }))),
         ExpansionKind::TraitItems => Expansion::TraitItems(SmallVector::one(ast::TraitItem {
             id: id,
             span: span,
             ident: ident,
             attrs: attrs,
-            node: ast::TraitItemKind::Macro(mac_placeholder()),
+            node: ast::TraitItemKind::Macro(mac),
         })),
         ExpansionKind::ImplItems => Expansion::ImplItems(SmallVector::one(ast::ImplItem {
             id: id,
@@ -60,25 +70,31 @@ pub fn placeholder(kind: ExpansionKind, id: ast::NodeId) -> Expansion {
             ident: ident,
             vis: vis,
             attrs: attrs,
-            node: ast::ImplItemKind::Macro(mac_placeholder()),
+            node: ast::ImplItemKind::Macro(mac),
             defaultness: ast::Defaultness::Final,
         })),
+        ExpansionKind::ForeignItems => {
+            Expansion::ForeignItems(SmallVector::one(ast::ForeignItem {
+                id,
+                span,
+                ident,
+                vis,
+                attrs,
+                node: ast::ForeignItemKind::Macro(mac),
+            }))
+        }
         ExpansionKind::Pat => Expansion::Pat(P(ast::Pat {
             id: id,
             span: span,
-            node: ast::PatKind::Mac(mac_placeholder()),
+            node: ast::PatKind::Mac(mac),
         })),
         ExpansionKind::Ty => Expansion::Ty(P(ast::Ty {
             id: id,
             span: span,
-            node: ast::TyKind::Mac(mac_placeholder()),
+            node: ast::TyKind::Mac(mac),
         })),
         ExpansionKind::Stmts => Expansion::Stmts(SmallVector::one({
-            let mac = P((
-                mac_placeholder(),
-                ast::MacStmtStyle::Braces,
-                ast::ThinVec::new(),
-            ));
+            let mac = P((mac, ast::MacStmtStyle::Braces, attrs.into()));
             ast::Stmt {
                 id: id,
                 span: span,
@@ -120,12 +136,21 @@ impl<'a, 'b> PlaceholderExpander<'a, 'b> {
     fn remove(&mut self, id: ast::NodeId) -> Expansion {
         self.expansions.remove(&id).unwrap()
     }
+
+    /// Whether the macro node with the given id is a placeholder, i.e. one
+    /// added with `add`, rather than an invocation left unexpanded because of
+    /// `ExpansionConfig::keep_macs`.
+    fn is_placeholder(&self, id: ast::NodeId) -> bool {
+        self.expansions.contains_key(&id)
+    }
 }
 
 impl<'a, 'b> Folder for PlaceholderExpander<'a, 'b> {
     fn fold_item(&mut self, item: P<ast::Item>) -> SmallVector<P<ast::Item>> {
         match item.node {
-            ast::ItemKind::Mac(_) => return self.remove(item.id).make_items(),
+            ast::ItemKind::Mac(_) if self.is_placeholder(item.id) => {
+                return self.remove(item.id).make_items()
+            }
             ast::ItemKind::MacroDef(_) => return SmallVector::one(item),
             _ => {}
         }
@@ -135,35 +160,54 @@ impl<'a, 'b> Folder for PlaceholderExpander<'a, 'b> {
 
     fn fold_trait_item(&mut self, item: ast::TraitItem) -> SmallVector<ast::TraitItem> {
         match item.node {
-            ast::TraitItemKind::Macro(_) => self.remove(item.id).make_trait_items(),
+            ast::TraitItemKind::Macro(_) if self.is_placeholder(item.id) => {
+                self.remove(item.id).make_trait_items()
+            }
             _ => noop_fold_trait_item(item, self),
         }
     }
 
     fn fold_impl_item(&mut self, item: ast::ImplItem) -> SmallVector<ast::ImplItem> {
         match item.node {
-            ast::ImplItemKind::Macro(_) => self.remove(item.id).make_impl_items(),
+            ast::ImplItemKind::Macro(_) if self.is_placeholder(item.id) => {
+                self.remove(item.id).make_impl_items()
+            }
             _ => noop_fold_impl_item(item, self),
         }
     }
 
+    fn fold_foreign_item(&mut self, item: ast::ForeignItem) -> SmallVector<ast::ForeignItem> {
+        match item.node {
+            ast::ForeignItemKind::Macro(_) if self.is_placeholder(item.id) => {
+                self.remove(item.id).make_foreign_items()
+            }
+            _ => noop_fold_foreign_item(item, self),
+        }
+    }
+
     fn fold_expr(&mut self, expr: P<ast::Expr>) -> P<ast::Expr> {
         match expr.node {
-            ast::ExprKind::Mac(_) => self.remove(expr.id).make_expr(),
+            ast::ExprKind::Mac(_) if self.is_placeholder(expr.id) => {
+                self.remove(expr.id).make_expr()
+            }
             _ => expr.map(|expr| noop_fold_expr(expr, self)),
         }
     }
 
     fn fold_opt_expr(&mut self, expr: P<ast::Expr>) -> Option<P<ast::Expr>> {
         match expr.node {
-            ast::ExprKind::Mac(_) => self.remove(expr.id).make_opt_expr(),
+            ast::ExprKind::Mac(_) if self.is_placeholder(expr.id) => {
+                self.remove(expr.id).make_opt_expr()
+            }
             _ => noop_fold_opt_expr(expr, self),
         }
     }
 
     fn fold_stmt(&mut self, stmt: ast::Stmt) -> SmallVector<ast::Stmt> {
         let (style, mut expansion) = match stmt.node {
-            ast::StmtKind::Mac(mac) => (mac.1, self.remove(stmt.id).make_stmts()),
+            ast::StmtKind::Mac(ref mac) if self.is_placeholder(stmt.id) => {
+                (mac.1, self.remove(stmt.id).make_stmts())
+            }
             _ => return noop_fold_stmt(stmt, self),
         };
 
@@ -178,14 +222,14 @@ impl<'a, 'b> Folder for PlaceholderExpander<'a, 'b> {
 
     fn fold_pat(&mut self, pat: P<ast::Pat>) -> P<ast::Pat> {
         match pat.node {
-            ast::PatKind::Mac(_) => self.remove(pat.id).make_pat(),
+            ast::PatKind::Mac(_) if self.is_placeholder(pat.id) => self.remove(pat.id).make_pat(),
             _ => noop_fold_pat(pat, self),
         }
     }
 
     fn fold_ty(&mut self, ty: P<ast::Ty>) -> P<ast::Ty> {
         match ty.node {
-            ast::TyKind::Mac(_) => self.remove(ty.id).make_ty(),
+            ast::TyKind::Mac(_) if self.is_placeholder(ty.id) => self.remove(ty.id).make_ty(),
             _ => noop_fold_ty(ty, self),
         }
     }
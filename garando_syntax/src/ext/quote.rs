@@ -2,11 +2,15 @@ use crate::ast::{self, Arg, Arm, Block, Expr, Item, Pat, Stmt, Ty};
 use crate::ext::base;
 use crate::ext::base::ExtCtxt;
 use crate::ext::build::AstBuilder;
+use crate::parse;
 use crate::parse::parser::{Parser, PathStyle};
 use crate::parse::token;
 use crate::ptr::P;
+use crate::syntax_pos::hygiene::SyntaxContext;
 use crate::syntax_pos::Span;
-use crate::tokenstream::{TokenStream, TokenTree};
+use crate::tokenstream::{DelimSpan, Delimited, TokenStream, TokenTree};
+
+use std::rc::Rc;
 
 /// Quasiquoting works via token trees.
 ///
@@ -24,7 +28,7 @@ pub mod rt {
     use crate::symbol::Symbol;
     use std::rc::Rc;
 
-    use crate::tokenstream::{self, TokenStream, TokenTree};
+    use crate::tokenstream::{self, DelimSpan, TokenStream, TokenTree};
 
     pub use crate::codemap::dummy_spanned;
     pub use crate::parse::new_parser_from_tts;
@@ -253,7 +257,7 @@ pub mod rt {
             inner.push(self.tokens.clone());
 
             r.push(TokenTree::Delimited(
-                self.span,
+                DelimSpan::from_single(self.span),
                 tokenstream::Delimited {
                     delim: token::Bracket,
                     tts: TokenStream::concat(inner).into(),
@@ -273,7 +277,7 @@ pub mod rt {
     impl ToTokens for () {
         fn to_tokens(&self, _cx: &ExtCtxt) -> Vec<TokenTree> {
             vec![TokenTree::Delimited(
-                DUMMY_SP,
+                DelimSpan::dummy(),
                 tokenstream::Delimited {
                     delim: token::Paren,
                     tts: TokenStream::empty().into(),
@@ -283,15 +287,18 @@ pub mod rt {
     }
 
     impl ToTokens for ast::Lit {
-        fn to_tokens(&self, cx: &ExtCtxt) -> Vec<TokenTree> {
-            // FIXME: This is wrong
-            P(ast::Expr {
+        fn to_tokens(&self, _cx: &ExtCtxt) -> Vec<TokenTree> {
+            let nt = token::NtLiteral(P(ast::Expr {
                 id: ast::DUMMY_NODE_ID,
                 node: ast::ExprKind::Lit(P(self.clone())),
-                span: DUMMY_SP,
+                span: self.span,
+                bare_span: self.span,
                 attrs: ast::ThinVec::new(),
-            })
-            .to_tokens(cx)
+            }));
+            vec![TokenTree::Token(
+                self.span,
+                token::Interpolated(Rc::new(nt)),
+            )]
         }
     }
 
@@ -320,6 +327,7 @@ pub mod rt {
                         id: ast::DUMMY_NODE_ID,
                         node: ast::ExprKind::Lit(P(dummy_spanned(lit))),
                         span: DUMMY_SP,
+                        bare_span: DUMMY_SP,
                         attrs: ast::ThinVec::new(),
                     });
                     if *self >= 0 {
@@ -329,6 +337,7 @@ pub mod rt {
                         id: ast::DUMMY_NODE_ID,
                         node: ast::ExprKind::Unary(ast::UnOp::Neg, lit),
                         span: DUMMY_SP,
+                        bare_span: DUMMY_SP,
                         attrs: ast::ThinVec::new(),
                     })
                     .to_tokens(cx)
@@ -409,14 +418,16 @@ pub fn unflatten(tts: Vec<TokenTree>) -> Vec<TokenTree> {
 
     let mut results = Vec::new();
     let mut result = Vec::new();
+    let mut open_spans = Vec::new();
     for tree in tts {
         match tree {
-            TokenTree::Token(_, token::OpenDelim(..)) => {
+            TokenTree::Token(span, token::OpenDelim(..)) => {
+                open_spans.push(span);
                 results.push(::std::mem::replace(&mut result, Vec::new()));
             }
             TokenTree::Token(span, token::CloseDelim(delim)) => {
                 let tree = TokenTree::Delimited(
-                    span,
+                    DelimSpan::from_pair(open_spans.pop().unwrap(), span),
                     Delimited {
                         delim: delim,
                         tts: result
@@ -735,6 +746,8 @@ fn expr_mk_token(cx: &ExtCtxt, sp: Span, tok: &token::Token) -> P<ast::Expr> {
         token::Literal(token::StrRaw(i, n), suf) => return mk_lit!("StrRaw", suf, i, n),
         token::Literal(token::ByteStr(i), suf) => return mk_lit!("ByteStr", suf, i),
         token::Literal(token::ByteStrRaw(i, n), suf) => return mk_lit!("ByteStrRaw", suf, i, n),
+        token::Literal(token::CStr(i), suf) => return mk_lit!("CStr", suf, i),
+        token::Literal(token::CStrRaw(i, n), suf) => return mk_lit!("CStrRaw", suf, i, n),
 
         token::Ident(ident) => {
             return cx.expr_call(
@@ -1007,3 +1020,262 @@ fn expand_parse_call(
         expand_wrapper(cx, sp, cx_expr, expr, &[&["syntax", "ext", "quote", "rt"]])
     }
 }
+
+/// Parse `template` as an expression, replacing each `$name` placeholder in
+/// it with the expression bound to `name`.
+///
+/// Spliced expressions keep their spans, while the spans of the template's
+/// own tokens are marked with the current expansion. Like the `quote_*!`
+/// macros, this panics if the template is malformed or refers to a
+/// placeholder without a binding.
+pub fn quote_expr(cx: &ExtCtxt, template: &str, bindings: &[(&str, P<Expr>)]) -> P<Expr> {
+    let mut parser = template_parser(cx, template, bindings);
+    let expr = panictry!(parser.parse_expr());
+    expect_template_end(&mut parser);
+    expr
+}
+
+/// Parse `template` as a type, replacing each `$name` placeholder in it (for
+/// instance in an array length) with the expression bound to `name`.
+///
+/// See `quote_expr`.
+pub fn quote_ty(cx: &ExtCtxt, template: &str, bindings: &[(&str, P<Expr>)]) -> P<Ty> {
+    let mut parser = template_parser(cx, template, bindings);
+    let ty = panictry!(parser.parse_ty());
+    expect_template_end(&mut parser);
+    ty
+}
+
+/// Parse `template` as a statement, including any trailing semicolon,
+/// replacing each `$name` placeholder in it with the expression bound to
+/// `name`.
+///
+/// See `quote_expr`.
+pub fn quote_stmt(cx: &ExtCtxt, template: &str, bindings: &[(&str, P<Expr>)]) -> Stmt {
+    let mut parser = template_parser(cx, template, bindings);
+    let stmt = match panictry!(parser.parse_full_stmt(false)) {
+        Some(stmt) => stmt,
+        None => panic!("expected a statement in quote template `{}`", template),
+    };
+    expect_template_end(&mut parser);
+    stmt
+}
+
+/// A parser for `template`, whose `$name` placeholders have been replaced
+/// with the expressions bound to them.
+fn template_parser<'a>(
+    cx: &ExtCtxt<'a>,
+    template: &str,
+    bindings: &[(&str, P<Expr>)],
+) -> Parser<'a> {
+    let stream = parse::parse_stream_from_source_str(
+        "<quote template>".to_string(),
+        template.to_string(),
+        cx.parse_sess,
+    );
+    let stream = mark_placeholders(stream, cx.backtrace());
+    let stream = splice_bindings(stream, bindings);
+    parse::stream_to_parser(cx.parse_sess, stream)
+}
+
+fn expect_template_end(parser: &mut Parser) {
+    if parser.token != token::Eof {
+        panictry!(parser.unexpected());
+    }
+}
+
+/// Turn each `$name` in `stream` into a `SubstNt(name)` placeholder token,
+/// and mark the template's spans with `ctxt`.
+fn mark_placeholders(stream: TokenStream, ctxt: SyntaxContext) -> TokenStream {
+    let mut trees = stream.into_trees();
+    let mut result = Vec::new();
+    while let Some(tree) = trees.next() {
+        let tree = match tree {
+            TokenTree::Token(sp, token::Dollar) => match trees.next() {
+                Some(TokenTree::Token(name_sp, token::Ident(name))) => {
+                    let span = sp.to(name_sp);
+                    TokenTree::Token(Span { ctxt, ..span }, token::SubstNt(name))
+                }
+                _ => panic!("expected a placeholder name after `$` in quote template"),
+            },
+            TokenTree::Token(sp, tok) => TokenTree::Token(Span { ctxt, ..sp }, tok),
+            TokenTree::Delimited(sp, delimited) => TokenTree::Delimited(
+                DelimSpan::from_pair(Span { ctxt, ..sp.open }, Span { ctxt, ..sp.close }),
+                Delimited {
+                    delim: delimited.delim,
+                    tts: mark_placeholders(delimited.stream(), ctxt).into(),
+                },
+            ),
+        };
+        result.push(tree.into());
+    }
+    TokenStream::concat(result)
+}
+
+/// Replace each placeholder token in `stream` with its binding, as an
+/// interpolated expression. The parser takes that as a whole expression,
+/// keeping its span, and it is passed on as is within macro invocations.
+fn splice_bindings(stream: TokenStream, bindings: &[(&str, P<Expr>)]) -> TokenStream {
+    let result = stream
+        .into_trees()
+        .map(|tree| match tree {
+            TokenTree::Token(sp, token::SubstNt(name)) => {
+                let expr = match bindings.iter().find(|&&(n, _)| n == &*name.name.as_str()) {
+                    Some((_, expr)) => expr.clone(),
+                    None => panic!("no binding for `${}` in quote template", name),
+                };
+                TokenTree::Token(sp, token::Interpolated(Rc::new(token::NtExpr(expr))))
+            }
+            TokenTree::Token(sp, tok) => TokenTree::Token(sp, tok),
+            TokenTree::Delimited(sp, delimited) => TokenTree::Delimited(
+                sp,
+                Delimited {
+                    delim: delimited.delim,
+                    tts: splice_bindings(delimited.stream(), bindings).into(),
+                },
+            ),
+        })
+        .map(TokenStream::from)
+        .collect();
+    TokenStream::concat(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codemap::FilePathMapping;
+    use crate::ext::base::DummyResolver;
+    use crate::ext::expand::ExpansionConfig;
+    use crate::ext::hygiene::Mark;
+    use crate::parse::ParseSess;
+    use crate::print::pprust;
+
+    fn with_cx<F: FnOnce(&ExtCtxt, P<Expr>)>(f: F) {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let mut resolver = DummyResolver;
+        let mut cx = ExtCtxt::new(
+            &sess,
+            ExpansionConfig::default("test".to_string()),
+            &mut resolver,
+        );
+        cx.current_expansion.mark = Mark::fresh(Mark::root());
+        let arg = parse::parse_expr_from_source_str("arg".to_string(), "f(1)".to_string(), &sess);
+        f(&cx, panictry!(arg));
+    }
+
+    #[test]
+    fn splice_into_binary_op() {
+        with_cx(|cx, arg| {
+            let expr = quote_expr(cx, "$x + 2 * x", &[("x", arg.clone())]);
+            assert_eq!(pprust::expr_to_string(&expr), "f(1) + 2 * x");
+
+            let (lhs, rhs) = match expr.node {
+                ast::ExprKind::Binary(op, ref lhs, ref rhs) => {
+                    assert_eq!(op.node, ast::BinOpKind::Add);
+                    (lhs, rhs)
+                }
+                _ => panic!("expected a binary expression"),
+            };
+            // The spliced expression keeps its original span...
+            assert_eq!(lhs.span, arg.span);
+            // ...while the template's spans belong to the expansion.
+            assert_eq!(expr.span.ctxt, cx.backtrace());
+            assert_eq!(rhs.span.ctxt, cx.backtrace());
+            assert_eq!(cx.codemap().span_to_snippet(rhs.span).unwrap(), "2 * x");
+        });
+    }
+
+    #[test]
+    fn splice_into_ty_and_stmt() {
+        with_cx(|cx, arg| {
+            let ty = quote_ty(cx, "[u8; $n]", &[("n", arg.clone())]);
+            assert_eq!(pprust::ty_to_string(&ty), "[u8; f(1)]");
+
+            let stmt = quote_stmt(cx, "let v = vec![$a, $a];", &[("a", arg.clone())]);
+            assert_eq!(pprust::stmt_to_string(&stmt), "let v = vec![f(1), f(1)];");
+            match stmt.node {
+                ast::StmtKind::Local(..) => {}
+                _ => panic!("expected a `let` statement"),
+            }
+        });
+    }
+
+    #[test]
+    fn spliced_expr_is_a_single_operand() {
+        with_cx(|cx, _| {
+            let sum = quote_expr(cx, "1 + 2", &[]);
+            let expr = quote_expr(cx, "$x * 3", &[("x", sum)]);
+            match expr.node {
+                ast::ExprKind::Binary(op, ref lhs, _) => {
+                    assert_eq!(op.node, ast::BinOpKind::Mul);
+                    match lhs.node {
+                        ast::ExprKind::Binary(op, ..) => assert_eq!(op.node, ast::BinOpKind::Add),
+                        _ => panic!("expected the spliced sum"),
+                    }
+                }
+                _ => panic!("expected a binary expression"),
+            }
+        });
+    }
+
+    #[test]
+    fn quoted_literal_in_attribute() {
+        use super::rt::ToTokens;
+        use crate::codemap::{dummy_spanned, DUMMY_SP};
+        use crate::symbol::Symbol;
+
+        with_cx(|cx, _| {
+            let lit = dummy_spanned(ast::LitKind::Str(
+                Symbol::intern("docs"),
+                ast::StrStyle::Cooked,
+            ));
+            let mut inner = vec![
+                TokenTree::Token(DUMMY_SP, token::Ident(ast::Ident::from_str("doc"))),
+                TokenTree::Token(DUMMY_SP, token::Eq),
+            ];
+            inner.extend(lit.to_tokens(cx));
+            let tts = vec![
+                TokenTree::Token(DUMMY_SP, token::Pound),
+                TokenTree::Delimited(
+                    DelimSpan::dummy(),
+                    Delimited {
+                        delim: token::Bracket,
+                        tts: inner.into_iter().collect::<TokenStream>().into(),
+                    },
+                ),
+            ];
+
+            let attr = panictry!(cx.new_parser_from_tts(&tts).parse_attribute(false));
+            assert_eq!(attr.value_str(), Some(Symbol::intern("docs")));
+            assert_eq!(
+                attr.meta().and_then(|meta| meta.value_str()),
+                Some(Symbol::intern("docs"))
+            );
+        });
+    }
+
+    #[test]
+    fn quoted_lifetime() {
+        use crate::codemap::DUMMY_SP;
+
+        with_cx(|cx, _| {
+            let lifetime = token::Lifetime(ast::Ident::from_str("'a"));
+            let expr = expr_mk_token(cx, DUMMY_SP, &lifetime);
+            assert!(pprust::expr_to_string(&expr).ends_with("Lifetime(ext_cx.ident_of(\"\\'a\"))"));
+
+            // The expansion rebuilds the lifetime through `ident_of`.
+            let ident = cx.ident_of("'a");
+            assert_eq!(ident.name.as_str().to_string(), "'a");
+            let ty = quote_ty(cx, "&'a u8", &[]);
+            assert_eq!(pprust::ty_to_string(&ty), "&'a u8");
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "no binding for `$y` in quote template")]
+    fn unbound_placeholder() {
+        with_cx(|cx, arg| {
+            quote_expr(cx, "$x + $y", &[("x", arg)]);
+        });
+    }
+}
@@ -0,0 +1,225 @@
+//! Collection of the `macro_rules!` (and `macro`) definitions in a parsed
+//! crate, so that they can be compiled and registered before expansion.
+
+use crate::ast;
+use crate::attr;
+use crate::ext::base::SyntaxExtension;
+use crate::ext::tt::macro_rules;
+use crate::feature_gate::Features;
+use crate::parse::token;
+use crate::parse::ParseSess;
+use crate::ptr::P;
+use crate::symbol::Symbol;
+use crate::syntax_pos::Span;
+use crate::tokenstream::TokenTree;
+use crate::visit::{self, Visitor};
+
+use std::cell::RefCell;
+
+/// A macro definition found in a crate.
+#[derive(Clone, Debug)]
+pub struct MacroDef {
+    item: P<ast::Item>,
+    rules: Vec<MacroRule>,
+}
+
+/// A single `lhs => rhs` rule of a macro definition.
+#[derive(Clone, Debug)]
+pub struct MacroRule {
+    lhs: TokenTree,
+    rhs: TokenTree,
+}
+
+impl MacroDef {
+    fn new(item: &ast::Item) -> Option<MacroDef> {
+        let def = match item.node {
+            ast::ItemKind::MacroDef(ref def) => def,
+            _ => return None,
+        };
+        Some(MacroDef {
+            item: P(item.clone()),
+            rules: split_rules(def),
+        })
+    }
+
+    pub fn name(&self) -> Symbol {
+        self.item.ident.name
+    }
+
+    pub fn ident(&self) -> ast::Ident {
+        self.item.ident
+    }
+
+    /// The span of the whole definition.
+    pub fn span(&self) -> Span {
+        self.item.span
+    }
+
+    /// Whether the definition is annotated with `#[macro_export]`.
+    pub fn is_exported(&self) -> bool {
+        attr::contains_name(&self.item.attrs, "macro_export")
+    }
+
+    /// Whether this is a `macro_rules!` definition rather than a `macro` one.
+    pub fn is_legacy(&self) -> bool {
+        match self.item.node {
+            ast::ItemKind::MacroDef(ref def) => def.legacy,
+            _ => unreachable!(),
+        }
+    }
+
+    /// The definition's item, as found in the crate.
+    pub fn item(&self) -> &ast::Item {
+        &self.item
+    }
+
+    /// The rules of the definition. If the body is malformed, only the rules
+    /// preceding the first malformed one are included; `compile` reports the
+    /// error.
+    pub fn rules(&self) -> &[MacroRule] {
+        &self.rules
+    }
+
+    /// Compile the definition into a `SyntaxExtension`, suitable for
+    /// registration with a `Resolver`.
+    pub fn compile(&self, sess: &ParseSess, features: &RefCell<Features>) -> SyntaxExtension {
+        macro_rules::compile(sess, features, &self.item)
+    }
+}
+
+impl MacroRule {
+    /// The matcher, including its delimiters.
+    pub fn lhs(&self) -> &TokenTree {
+        &self.lhs
+    }
+
+    /// The transcriber, including its delimiters.
+    pub fn rhs(&self) -> &TokenTree {
+        &self.rhs
+    }
+}
+
+/// Split a definition body of the form `$( $lhs:tt => $rhs:tt );+` (`,` for
+/// `macro` definitions) into its rules.
+fn split_rules(def: &ast::MacroDef) -> Vec<MacroRule> {
+    let separator = if def.legacy {
+        token::Semi
+    } else {
+        token::Comma
+    };
+    let mut trees = def.stream().trees();
+    let mut rules = Vec::new();
+    while let Some(lhs @ TokenTree::Delimited(..)) = trees.next() {
+        match trees.next() {
+            Some(TokenTree::Token(_, token::FatArrow)) => {}
+            _ => break,
+        }
+        let rhs = match trees.next() {
+            Some(rhs @ TokenTree::Delimited(..)) => rhs,
+            _ => break,
+        };
+        rules.push(MacroRule { lhs, rhs });
+        match trees.next() {
+            Some(TokenTree::Token(_, ref tok)) if *tok == separator => {}
+            _ => break,
+        }
+    }
+    rules
+}
+
+struct MacroDefCollector {
+    defs: Vec<MacroDef>,
+}
+
+impl<'ast> Visitor<'ast> for MacroDefCollector {
+    fn visit_item(&mut self, item: &'ast ast::Item) {
+        if let Some(def) = MacroDef::new(item) {
+            self.defs.push(def);
+        }
+        visit::walk_item(self, item);
+    }
+
+    fn visit_mac(&mut self, _mac: &'ast ast::Mac) {
+        // Macro invocations are not expanded, so there is nothing to find.
+    }
+}
+
+/// Collect every macro definition in `krate`, including those nested in
+/// modules and function bodies, in source order.
+pub fn collect_macro_defs(krate: &ast::Crate) -> Vec<MacroDef> {
+    let mut collector = MacroDefCollector { defs: Vec::new() };
+    visit::walk_crate(&mut collector, krate);
+    collector.defs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codemap::FilePathMapping;
+    use crate::ext::base::SyntaxExtension;
+    use crate::print::pprust;
+    use crate::testing::{string_to_crate, try_string_to_crate};
+
+    const FIXTURE: &str = "
+        macro_rules! top { () => { 1 }; ($e:expr) => { $e } }
+
+        mod inner {
+            #[macro_export]
+            macro_rules! exported { ($($t:tt)*) => { $($t)* } }
+        }
+
+        fn main() {
+            macro_rules! local { () => {} }
+            local!();
+        }
+    ";
+
+    #[test]
+    fn collects_nested_macro_defs() {
+        let krate = string_to_crate(FIXTURE.to_string());
+        let defs = collect_macro_defs(&krate);
+
+        let names: Vec<_> = defs
+            .iter()
+            .map(|def| def.name().as_str().to_string())
+            .collect();
+        assert_eq!(names, ["top", "exported", "local"]);
+        let exported: Vec<_> = defs.iter().map(|def| def.is_exported()).collect();
+        assert_eq!(exported, [false, true, false]);
+        assert!(defs.iter().all(|def| def.is_legacy()));
+        assert!(defs.iter().all(|def| def.span().lo < def.span().hi));
+
+        let rules: Vec<_> = defs[0]
+            .rules()
+            .iter()
+            .map(|rule| {
+                (
+                    pprust::tt_to_string(rule.lhs().clone()),
+                    pprust::tt_to_string(rule.rhs().clone()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rules,
+            [
+                ("()".to_string(), "{1}".to_string()),
+                ("($e: expr)".to_string(), "{$e}".to_string()),
+            ]
+        );
+        assert_eq!(defs[1].rules().len(), 1);
+        assert_eq!(defs[2].rules().len(), 1);
+    }
+
+    #[test]
+    fn compile_macro_defs() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let krate = panictry!(try_string_to_crate(&sess, FIXTURE.to_string()));
+        let features = RefCell::new(Features::new());
+        for def in collect_macro_defs(&krate) {
+            match def.compile(&sess, &features) {
+                SyntaxExtension::NormalTT(..) => {}
+                _ => panic!("expected a NormalTT extension"),
+            }
+        }
+    }
+}
@@ -288,7 +288,13 @@ pub fn compile(sess: &ParseSess, features: &RefCell<Features>, def: &ast::Item)
 
     if body.legacy {
         let allow_internal_unstable = attr::contains_name(&def.attrs, "allow_internal_unstable");
-        NormalTT(exp, Some((def.id, def.span)), allow_internal_unstable)
+        let allow_internal_unsafe = attr::contains_name(&def.attrs, "allow_internal_unsafe");
+        NormalTT(
+            exp,
+            Some((def.id, def.span)),
+            allow_internal_unstable,
+            allow_internal_unsafe,
+        )
     } else {
         SyntaxExtension::DeclMacro(exp, Some(def.span))
     }
@@ -3,8 +3,8 @@ use crate::ext::tt::macro_parser;
 use crate::parse::{token, ParseSess};
 use crate::print::pprust;
 use crate::symbol::{keywords, Symbol};
-use crate::syntax_pos::{BytePos, Span, DUMMY_SP};
-use crate::tokenstream;
+use crate::syntax_pos::Span;
+use crate::tokenstream::{self, DelimSpan};
 
 use std::rc::Rc;
 
@@ -25,28 +25,12 @@ impl Delimited {
         token::CloseDelim(self.delim)
     }
 
-    pub fn open_tt(&self, span: Span) -> TokenTree {
-        let open_span = if span == DUMMY_SP {
-            DUMMY_SP
-        } else {
-            Span {
-                hi: span.lo + BytePos(self.delim.len() as u32),
-                ..span
-            }
-        };
-        TokenTree::Token(open_span, self.open_token())
+    pub fn open_tt(&self, span: DelimSpan) -> TokenTree {
+        TokenTree::Token(span.open, self.open_token())
     }
 
-    pub fn close_tt(&self, span: Span) -> TokenTree {
-        let close_span = if span == DUMMY_SP {
-            DUMMY_SP
-        } else {
-            Span {
-                lo: span.hi - BytePos(self.delim.len() as u32),
-                ..span
-            }
-        };
-        TokenTree::Token(close_span, self.close_token())
+    pub fn close_tt(&self, span: DelimSpan) -> TokenTree {
+        TokenTree::Token(span.close, self.close_token())
     }
 }
 
@@ -75,7 +59,7 @@ pub enum KleeneOp {
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
 pub enum TokenTree {
     Token(Span, token::Token),
-    Delimited(Span, Rc<Delimited>),
+    Delimited(DelimSpan, Rc<Delimited>),
     /// A kleene-style repetition sequence with a span
     Sequence(Span, Rc<SequenceRepetition>),
     /// Matches a nonterminal. This is only used in the left hand side of MBE macros.
@@ -133,8 +117,8 @@ impl TokenTree {
         match *self {
             TokenTree::Token(sp, _)
             | TokenTree::MetaVarDecl(sp, _, _)
-            | TokenTree::Delimited(sp, _)
             | TokenTree::Sequence(sp, _) => sp,
+            TokenTree::Delimited(sp, _) => sp.entire(),
         }
     }
 }
@@ -197,7 +181,8 @@ where
 {
     match tree {
         tokenstream::TokenTree::Token(span, token::Dollar) => match trees.next() {
-            Some(tokenstream::TokenTree::Delimited(span, delimited)) => {
+            Some(tokenstream::TokenTree::Delimited(delim_span, delimited)) => {
+                let span = delim_span.entire();
                 if delimited.delim != token::Paren {
                     let tok = pprust::token_to_string(&token::OpenDelim(delimited.delim));
                     let msg = format!("expected `(`, found `{}`", tok);
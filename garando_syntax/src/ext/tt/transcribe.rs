@@ -3,8 +3,8 @@ use crate::errors::Handler;
 use crate::ext::tt::macro_parser::{MatchedNonterminal, MatchedSeq, NamedMatch};
 use crate::ext::tt::quoted;
 use crate::parse::token::{self, NtTT, SubstNt, Token};
-use crate::syntax_pos::{Span, DUMMY_SP};
-use crate::tokenstream::{Delimited, TokenStream, TokenTree};
+use crate::syntax_pos::DUMMY_SP;
+use crate::tokenstream::{DelimSpan, Delimited, TokenStream, TokenTree};
 use crate::util::small_vector::SmallVector;
 
 use std::collections::HashMap;
@@ -17,7 +17,7 @@ enum Frame {
     Delimited {
         forest: Rc<quoted::Delimited>,
         idx: usize,
-        span: Span,
+        span: DelimSpan,
     },
     Sequence {
         forest: Rc<quoted::SequenceRepetition>,
@@ -35,7 +35,7 @@ impl Frame {
         Frame::Delimited {
             forest: forest,
             idx: 0,
-            span: DUMMY_SP,
+            span: DelimSpan::dummy(),
         }
     }
 }
@@ -23,6 +23,7 @@ use crate::errors::{DiagnosticBuilder, FatalError, Handler};
 use crate::parse::ParseSess;
 use crate::symbol::Symbol;
 use crate::syntax_pos::Span;
+use crate::util::lev_distance::find_best_match_for_str;
 use crate::visit::{self, FnKind, Visitor};
 
 use std::env;
@@ -163,6 +164,11 @@ declare_features!(
     //
     // rustc internal
     (active, allow_internal_unstable, "1.0.0", None),
+    // Allows the use of #[allow_internal_unsafe]. Like
+    // #[allow_internal_unstable], it is an attribute on macro_rules!.
+    //
+    // rustc internal
+    (active, allow_internal_unsafe, "1.0.0", None),
     // #23121. Array patterns have some hazards yet.
     (active, slice_patterns, "1.0.0", Some(23121)),
     // Allows the definition of associated constants in `trait` or `impl`
@@ -279,6 +285,16 @@ declare_features!(
     (active, macro_vis_matcher, "1.18.0", Some(41022)),
     // rustc internal
     (active, abi_thiscall, "1.19.0", None),
+    // Allows equality predicates in where clauses, e.g. `where T::Output = u32`
+    (active, where_clause_eq, "1.19.0", Some(20041)),
+    // Allows `async fn`, `async` blocks and `.await`
+    (active, async_await, "1.19.0", Some(50547)),
+    // Allows `extern { type Foo; }`
+    (active, extern_types, "1.23.0", Some(43467)),
+    // Allows `&&` chains of `let` bindings and conditions in `if` and `while`
+    (active, let_chains, "1.23.0", Some(53667)),
+    // Allows `trait Foo = Bar + Baz;`
+    (active, trait_alias, "1.24.0", Some(41517)),
 );
 
 declare_features!(
@@ -785,6 +801,16 @@ pub const BUILTIN_ATTRIBUTES: &'static [(&'static str, AttributeType, AttributeG
             cfg_fn!(allow_internal_unstable),
         ),
     ),
+    (
+        "allow_internal_unsafe",
+        Normal,
+        Gated(
+            Stability::Unstable,
+            "allow_internal_unsafe",
+            EXPLAIN_ALLOW_INTERNAL_UNSAFE,
+            cfg_fn!(allow_internal_unsafe),
+        ),
+    ),
     (
         "fundamental",
         Whitelisted,
@@ -1061,7 +1087,10 @@ macro_rules! gate_feature {
 impl<'a> Context<'a> {
     fn check_attribute(&self, attr: &ast::Attribute, is_macro: bool) {
         debug!("check_attribute(attr = {:?})", attr);
-        let name = unwrap_or!(attr.name(), return).as_str();
+        let name = match attr.name() {
+            Some(name) => name.as_str(),
+            None => return self.check_tool_attribute(attr, is_macro),
+        };
         for &(n, ty, ref gateage) in BUILTIN_ATTRIBUTES {
             if name == n {
                 if let Gated(_, name, desc, ref has_feature) = *gateage {
@@ -1102,24 +1131,56 @@ impl<'a> Context<'a> {
             // feature gate checking. Macro gating runs
             // before the plugin attributes are registered
             // so we skip this then
-            if !is_macro {
-                gate_feature!(
-                    self,
-                    custom_attribute,
+            if !is_macro && !self.features.custom_attribute && !attr.span.allows_unstable() {
+                let mut err = feature_err(
+                    self.parse_sess,
+                    "custom_attribute",
                     attr.span,
+                    GateIssue::Language,
                     &format!(
                         "The attribute `{}` is currently \
                                         unknown to the compiler and \
                                         may have meaning \
                                         added to it in the future",
                         attr.path
-                    )
+                    ),
                 );
+                let builtin = BUILTIN_ATTRIBUTES.iter().map(|&(name, ..)| name);
+                if let Some(similar) = find_best_match_for_str(builtin, &name, None) {
+                    err.span_suggestion(attr.path.span, "did you mean", similar.to_string());
+                }
+                err.emit();
             }
         }
     }
 }
 
+impl<'a> Context<'a> {
+    /// Checks an attribute with a path of several segments, which is only
+    /// known if it belongs to a registered tool.
+    fn check_tool_attribute(&self, attr: &ast::Attribute, is_macro: bool) {
+        let tool = attr.path.segments[0].identifier.name;
+        if self.parse_sess.is_registered_tool(tool) || attr::is_known(attr) {
+            debug!("check_tool_attribute: {:?} is known", attr.path);
+            return;
+        }
+        if !is_macro && !self.features.custom_attribute && !attr.span.allows_unstable() {
+            self.parse_sess
+                .span_diagnostic
+                .struct_span_warn(
+                    attr.span,
+                    &format!(
+                        "The attribute `{}` is currently unknown to the compiler and \
+                         may have meaning added to it in the future",
+                        attr.path
+                    ),
+                )
+                .note(&format!("`{}` is not a registered tool", tool))
+                .emit();
+        }
+    }
+}
+
 pub fn check_attribute(attr: &ast::Attribute, parse_sess: &ParseSess, features: &Features) {
     let cx = Context {
         features: features,
@@ -1225,6 +1286,8 @@ pub const EXPLAIN_TRACE_MACROS: &'static str =
     "`trace_macros` is not stable enough for use and is subject to change";
 pub const EXPLAIN_ALLOW_INTERNAL_UNSTABLE: &'static str =
     "allow_internal_unstable side-steps feature gating and stability checks";
+pub const EXPLAIN_ALLOW_INTERNAL_UNSAFE: &str =
+    "allow_internal_unsafe side-steps the unsafe_code lint";
 
 pub const EXPLAIN_CUSTOM_DERIVE: &'static str =
     "`#[derive]` for custom traits is deprecated and will be removed in the future.";
@@ -1370,6 +1433,11 @@ impl<'a> Visitor<'a> for PostExpansionVisitor<'a> {
         if self.context.features.proc_macro && attr::is_known(attr) {
             return;
         }
+        // The arguments of tool attributes are up to the tool, and
+        // `check_attribute` has reported any other path of several segments.
+        if attr.path.segments.len() > 1 {
+            return;
+        }
 
         let meta = panictry!(attr.parse_meta(self.context.parse_sess));
         if contains_novel_literal(&meta) {
@@ -1494,6 +1562,19 @@ impl<'a> Visitor<'a> for PostExpansionVisitor<'a> {
                 }
             }
 
+            ast::ItemKind::Trait(ast::IsAuto::Yes, ..) => {
+                gate_feature_post!(
+                    &self,
+                    optin_builtin_traits,
+                    i.span,
+                    "auto traits are experimental and possibly buggy"
+                );
+            }
+
+            ast::ItemKind::TraitAlias(..) => {
+                gate_feature_post!(&self, trait_alias, i.span, "trait aliases are experimental");
+            }
+
             ast::ItemKind::DefaultImpl(..) => {
                 gate_feature_post!(
                     &self,
@@ -1545,6 +1626,10 @@ impl<'a> Visitor<'a> for PostExpansionVisitor<'a> {
             );
         }
 
+        if let ast::ForeignItemKind::Ty = i.node {
+            gate_feature_post!(&self, extern_types, i.span, "extern types are experimental");
+        }
+
         visit::walk_foreign_item(self, i)
     }
 
@@ -1570,7 +1655,7 @@ impl<'a> Visitor<'a> for PostExpansionVisitor<'a> {
     }
 
     fn visit_fn_ret_ty(&mut self, ret_ty: &'a ast::FunctionRetTy) {
-        if let ast::FunctionRetTy::Ty(ref output_ty) = *ret_ty {
+        if let ast::FunctionRetTy::Ty(ref output_ty, _) = *ret_ty {
             if output_ty.node != ast::TyKind::Never {
                 self.visit_ty(output_ty)
             }
@@ -1625,6 +1710,15 @@ impl<'a> Visitor<'a> for PostExpansionVisitor<'a> {
                     "`catch` expression is experimental"
                 );
             }
+            ast::ExprKind::Async(..) => {
+                gate_feature_post!(&self, async_await, e.span, "async blocks are unstable");
+            }
+            ast::ExprKind::Await(_) => {
+                gate_feature_post!(&self, async_await, e.span, "async/await is unstable");
+            }
+            ast::ExprKind::Let(..) => {
+                gate_feature_post!(&self, let_chains, e.span, "`let` chains are unstable");
+            }
             _ => {}
         }
         visit::walk_expr(self, e);
@@ -1688,17 +1782,21 @@ impl<'a> Visitor<'a> for PostExpansionVisitor<'a> {
             _,
             _,
             _,
+            _,
         ) = fn_kind
         {
             gate_feature_post!(&self, const_fn, span, "const fn is unstable");
         }
+        if let FnKind::ItemFn(_, _, _, _, ast::IsAsync::Async, _, _, _) = fn_kind {
+            gate_feature_post!(&self, async_await, span, "async fn is unstable");
+        }
         // stability of const fn methods are covered in
         // visit_trait_item and visit_impl_item below; this is
         // because default methods don't pass through this
         // point.
 
         match fn_kind {
-            FnKind::ItemFn(_, _, _, _, abi, _, _)
+            FnKind::ItemFn(_, _, _, _, _, abi, _, _)
             | FnKind::Method(_, &ast::MethodSig { abi, .. }, _, _) => {
                 self.check_abi(abi, span);
             }
@@ -1722,6 +1820,9 @@ impl<'a> Visitor<'a> for PostExpansionVisitor<'a> {
                 if sig.constness.node == ast::Constness::Const {
                     gate_feature_post!(&self, const_fn, ti.span, "const fn is unstable");
                 }
+                if sig.asyncness.is_async() {
+                    gate_feature_post!(&self, async_await, ti.span, "async fn is unstable");
+                }
             }
             ast::TraitItemKind::Type(_, Some(_)) => {
                 gate_feature_post!(
@@ -1752,6 +1853,9 @@ impl<'a> Visitor<'a> for PostExpansionVisitor<'a> {
                 if sig.constness.node == ast::Constness::Const {
                     gate_feature_post!(&self, const_fn, ii.span, "const fn is unstable");
                 }
+                if sig.asyncness.is_async() {
+                    gate_feature_post!(&self, async_await, ii.span, "async fn is unstable");
+                }
             }
             _ => {}
         }
@@ -1772,6 +1876,18 @@ impl<'a> Visitor<'a> for PostExpansionVisitor<'a> {
         visit::walk_generics(self, g)
     }
 
+    fn visit_where_predicate(&mut self, predicate: &'a ast::WherePredicate) {
+        if let ast::WherePredicate::EqPredicate(ref eq) = *predicate {
+            gate_feature_post!(
+                &self,
+                where_clause_eq,
+                eq.span,
+                "equality constraints in `where` clauses are experimental"
+            );
+        }
+        visit::walk_where_predicate(self, predicate)
+    }
+
     fn visit_lifetime_def(&mut self, lifetime_def: &'a ast::LifetimeDef) {
         if !lifetime_def.attrs.is_empty() {
             gate_feature_post!(
@@ -1956,3 +2072,213 @@ fn maybe_stage_features(span_handler: &Handler, krate: &ast::Crate, unstable: Un
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codemap::FilePathMapping;
+    use crate::parse;
+    use crate::testing::SharedBuffer;
+
+    fn check(source: &str) -> Vec<serde_json::Value> {
+        check_with_tools(source, &[])
+    }
+
+    // Checks the crate `source` without any features enabled and with `tools`
+    // registered, returning the diagnostics emitted.
+    fn check_with_tools(source: &str, tools: &[&str]) -> Vec<serde_json::Value> {
+        let output = SharedBuffer::default();
+        let mut sess =
+            ParseSess::with_json_emitter(FilePathMapping::empty(), Box::new(output.clone()));
+        for tool in tools {
+            sess.register_tool(Symbol::intern(tool));
+        }
+        let krate =
+            parse::parse_crate_from_source_str("test.rs".to_owned(), source.to_owned(), &sess)
+                .unwrap();
+        check_crate(
+            &krate,
+            &sess,
+            &Features::new(),
+            &[],
+            UnstableFeatures::Allow,
+        );
+
+        output.json_diagnostics()
+    }
+
+    #[test]
+    fn misspelt_attribute_names() {
+        let diagnostics = check("#[allw(dead_code)]\nfn f() {}\n");
+        assert_eq!(diagnostics.len(), 1);
+        let suggestion = &diagnostics[0]["children"][1]["spans"][0];
+        assert_eq!(diagnostics[0]["children"][1]["message"], "did you mean");
+        assert_eq!(suggestion["suggested_replacement"], "allow");
+        assert_eq!(suggestion["byte_start"], 2);
+        assert_eq!(suggestion["byte_end"], 6);
+
+        let diagnostics = check("#[frobnicate]\nfn f() {}\n");
+        assert_eq!(diagnostics.len(), 1);
+        let children = diagnostics[0]["children"].as_array().unwrap();
+        assert!(children
+            .iter()
+            .all(|child| child["message"] != "did you mean"));
+
+        assert!(check("#[allow(dead_code)]\nfn f() {}\n").is_empty());
+    }
+
+    #[test]
+    fn where_clause_equality_gated() {
+        let diagnostics = check("fn f<T: Iterator>() where T::Item = u32 {}\n");
+        assert_eq!(diagnostics.len(), 1);
+        let message = diagnostics[0]["message"].as_str().unwrap();
+        assert!(message.starts_with("equality constraints in `where` clauses are experimental"));
+        assert_eq!(diagnostics[0]["spans"][0]["byte_start"], 26);
+        assert_eq!(diagnostics[0]["spans"][0]["byte_end"], 39);
+
+        let source = "#![feature(where_clause_eq)]\nfn f<T: Iterator>() where T::Item = u32 {}\n";
+        let output = SharedBuffer::default();
+        let sess = ParseSess::with_json_emitter(FilePathMapping::empty(), Box::new(output.clone()));
+        let krate =
+            parse::parse_crate_from_source_str("test.rs".to_owned(), source.to_owned(), &sess)
+                .unwrap();
+        let features = get_features(&sess.span_diagnostic, &krate.attrs);
+        check_crate(&krate, &sess, &features, &[], UnstableFeatures::Allow);
+        assert_eq!(output.contents(), "");
+    }
+
+    #[test]
+    fn async_await_gated() {
+        let source = "async fn f() { g().await; async move {}; }\n";
+        let messages = check(source)
+            .iter()
+            .map(|d| d["message"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(messages.len(), 3);
+        assert!(messages[0].starts_with("async fn is unstable"));
+        assert!(messages[1].starts_with("async/await is unstable"));
+        assert!(messages[2].starts_with("async blocks are unstable"));
+
+        let source = format!("#![feature(async_await)]\n{}", source);
+        let output = SharedBuffer::default();
+        let sess = ParseSess::with_json_emitter(FilePathMapping::empty(), Box::new(output.clone()));
+        let krate =
+            parse::parse_crate_from_source_str("test.rs".to_owned(), source, &sess).unwrap();
+        let features = get_features(&sess.span_diagnostic, &krate.attrs);
+        check_crate(&krate, &sess, &features, &[], UnstableFeatures::Allow);
+        assert_eq!(output.contents(), "");
+    }
+
+    #[test]
+    fn auto_traits_and_extern_types_gated() {
+        let source = "auto trait A {}\nunsafe auto trait B {}\nextern { type T; }\n";
+        let diagnostics = check(source);
+        let gated = diagnostics
+            .iter()
+            .map(|d| {
+                let span = &d["spans"][0];
+                (
+                    d["message"].as_str().unwrap().to_string(),
+                    span["byte_start"].as_u64().unwrap(),
+                    span["byte_end"].as_u64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(gated.len(), 3);
+        assert!(gated[0].0.starts_with("auto traits are experimental"));
+        assert_eq!((gated[0].1, gated[0].2), (0, 15));
+        assert!(gated[1].0.starts_with("auto traits are experimental"));
+        assert_eq!((gated[1].1, gated[1].2), (16, 38));
+        assert!(gated[2].0.starts_with("extern types are experimental"));
+        assert_eq!((gated[2].1, gated[2].2), (48, 55));
+
+        let source = format!(
+            "#![feature(optin_builtin_traits, extern_types)]\n{}",
+            source
+        );
+        let output = SharedBuffer::default();
+        let sess = ParseSess::with_json_emitter(FilePathMapping::empty(), Box::new(output.clone()));
+        let krate =
+            parse::parse_crate_from_source_str("test.rs".to_owned(), source, &sess).unwrap();
+        let features = get_features(&sess.span_diagnostic, &krate.attrs);
+        check_crate(&krate, &sess, &features, &[], UnstableFeatures::Allow);
+        assert_eq!(output.contents(), "");
+    }
+
+    #[test]
+    fn let_chains_gated() {
+        let source =
+            "fn f() { if let Some(a) = x && a > 3 {} while let Some(b) = y && let Ok(c) = b {} }";
+        let gated = check(source)
+            .iter()
+            .map(|d| {
+                let span = &d["spans"][0];
+                (
+                    d["message"].as_str().unwrap().to_string(),
+                    span["byte_start"].as_u64().unwrap(),
+                    span["byte_end"].as_u64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(gated.len(), 2);
+        assert!(gated
+            .iter()
+            .all(|g| g.0.starts_with("`let` chains are unstable")));
+        let spans: Vec<_> = gated.iter().map(|g| (g.1, g.2)).collect();
+        assert_eq!(spans, [(46, 61), (65, 78)]);
+
+        // A lone binding isn't a chain, whatever its scrutinee.
+        assert!(check("fn f() { if let Some(a) = x {} while let Some(b) = y {} }").is_empty());
+        assert!(check("fn f() { if let P = a && b {} if let true = a && b || c {} }").is_empty());
+
+        let source = format!("#![feature(let_chains)]\n{}", source);
+        let output = SharedBuffer::default();
+        let sess = ParseSess::with_json_emitter(FilePathMapping::empty(), Box::new(output.clone()));
+        let krate =
+            parse::parse_crate_from_source_str("test.rs".to_owned(), source, &sess).unwrap();
+        let features = get_features(&sess.span_diagnostic, &krate.attrs);
+        check_crate(&krate, &sess, &features, &[], UnstableFeatures::Allow);
+        assert_eq!(output.contents(), "");
+    }
+
+    #[test]
+    fn trait_alias_gated() {
+        let diagnostics = check("trait A = B + Send;\n");
+        assert_eq!(diagnostics.len(), 1);
+        let message = diagnostics[0]["message"].as_str().unwrap();
+        assert!(message.starts_with("trait aliases are experimental"));
+        assert_eq!(diagnostics[0]["spans"][0]["byte_end"], 19);
+
+        let source = "#![feature(trait_alias)]\ntrait A = B + Send;\n".to_owned();
+        let output = SharedBuffer::default();
+        let sess = ParseSess::with_json_emitter(FilePathMapping::empty(), Box::new(output.clone()));
+        let krate =
+            parse::parse_crate_from_source_str("test.rs".to_owned(), source, &sess).unwrap();
+        let features = get_features(&sess.span_diagnostic, &krate.attrs);
+        check_crate(&krate, &sess, &features, &[], UnstableFeatures::Allow);
+        assert_eq!(output.contents(), "");
+    }
+
+    #[test]
+    fn tool_attributes() {
+        let source = "#[rustfmt::skip]\n#[mytool::opt(a = \"b\")]\nfn f() {}\n";
+        assert_eq!(
+            check_with_tools(source, &["mytool"]),
+            Vec::<serde_json::Value>::new()
+        );
+
+        let diagnostics = check(source);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["level"], "warning");
+        let message = diagnostics[0]["message"].as_str().unwrap();
+        assert!(message.starts_with("The attribute `mytool::opt` is currently unknown"));
+        let children = diagnostics[0]["children"].as_array().unwrap();
+        assert!(children
+            .iter()
+            .any(|child| child["message"] == "`mytool` is not a registered tool"));
+
+        let diagnostics = check_with_tools("#[other::thing]\nfn f() {}\n", &["mytool"]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["level"], "warning");
+    }
+}
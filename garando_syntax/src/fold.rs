@@ -50,7 +50,7 @@ pub trait Folder: Sized {
         noop_fold_view_path(view_path, self)
     }
 
-    fn fold_foreign_item(&mut self, ni: ForeignItem) -> ForeignItem {
+    fn fold_foreign_item(&mut self, ni: ForeignItem) -> SmallVector<ForeignItem> {
         noop_fold_foreign_item(ni, self)
     }
 
@@ -412,7 +412,7 @@ pub fn noop_fold_foreign_mod<T: Folder>(
 ) -> ForeignMod {
     ForeignMod {
         abi: abi,
-        items: items.move_map(|x| fld.fold_foreign_item(x)),
+        items: items.move_flat_map(|x| fld.fold_foreign_item(x)),
     }
 }
 
@@ -476,11 +476,13 @@ pub fn noop_fold_angle_bracketed_parameter_data<T: Folder>(
     fld: &mut T,
 ) -> AngleBracketedParameterData {
     let AngleBracketedParameterData {
+        span,
         lifetimes,
         types,
         bindings,
     } = data;
     AngleBracketedParameterData {
+        span: fld.new_span(span),
         lifetimes: fld.fold_lifetimes(lifetimes),
         types: types.move_map(|ty| fld.fold_ty(ty)),
         bindings: bindings.move_map(|b| fld.fold_ty_binding(b)),
@@ -510,6 +512,7 @@ pub fn noop_fold_local<T: Folder>(l: P<Local>, fld: &mut T) -> P<Local> {
              pat,
              ty,
              init,
+             els,
              span,
              attrs,
          }| Local {
@@ -517,6 +520,7 @@ pub fn noop_fold_local<T: Folder>(l: P<Local>, fld: &mut T) -> P<Local> {
             pat: fld.fold_pat(pat),
             ty: ty.map(|t| fld.fold_ty(t)),
             init: init.map(|e| fld.fold_expr(e)),
+            els: els.map(|b| fld.fold_block(b)),
             span: fld.new_span(span),
             attrs: fold_attrs(attrs.into(), fld).into(),
         },
@@ -539,6 +543,7 @@ pub fn noop_fold_mac<T: Folder>(Spanned { node, span }: Mac, fld: &mut T) -> Mac
         node: Mac_ {
             tts: fld.fold_tts(node.stream()).into(),
             path: fld.fold_path(node.path),
+            delim: node.delim,
         },
         span: fld.new_span(span),
     }
@@ -577,8 +582,9 @@ pub fn noop_fold_meta_item<T: Folder>(mi: MetaItem, fld: &mut T) -> MetaItem {
     }
 }
 
-pub fn noop_fold_arg<T: Folder>(Arg { id, pat, ty }: Arg, fld: &mut T) -> Arg {
+pub fn noop_fold_arg<T: Folder>(Arg { id, pat, ty, span }: Arg, fld: &mut T) -> Arg {
     Arg {
+        span: fld.new_span(span),
         id: fld.new_id(id),
         pat: fld.fold_pat(pat),
         ty: fld.fold_ty(ty),
@@ -589,7 +595,7 @@ pub fn noop_fold_tt<T: Folder>(tt: TokenTree, fld: &mut T) -> TokenTree {
     match tt {
         TokenTree::Token(span, tok) => TokenTree::Token(fld.new_span(span), fld.fold_token(tok)),
         TokenTree::Delimited(span, delimed) => TokenTree::Delimited(
-            fld.new_span(span),
+            DelimSpan::from_pair(fld.new_span(span.open), fld.new_span(span.close)),
             Delimited {
                 tts: fld.fold_tts(delimed.stream()).into(),
                 delim: delimed.delim,
@@ -661,6 +667,7 @@ pub fn noop_fold_interpolated<T: Folder>(
         ),
         token::NtPat(pat) => token::NtPat(fld.fold_pat(pat)),
         token::NtExpr(expr) => token::NtExpr(fld.fold_expr(expr)),
+        token::NtLiteral(expr) => token::NtLiteral(fld.fold_expr(expr)),
         token::NtTy(ty) => token::NtTy(fld.fold_ty(ty)),
         token::NtIdent(id) => token::NtIdent(Spanned::<Ident> {
             node: fld.fold_ident(id.node),
@@ -696,7 +703,9 @@ pub fn noop_fold_fn_decl<T: Folder>(decl: P<FnDecl>, fld: &mut T) -> P<FnDecl> {
          }| FnDecl {
             inputs: inputs.move_map(|x| fld.fold_arg(x)),
             output: match output {
-                FunctionRetTy::Ty(ty) => FunctionRetTy::Ty(fld.fold_ty(ty)),
+                FunctionRetTy::Ty(ty, arrow) => {
+                    FunctionRetTy::Ty(fld.fold_ty(ty), fld.new_span(arrow))
+                }
                 FunctionRetTy::Default(span) => FunctionRetTy::Default(fld.new_span(span)),
             },
             variadic: variadic,
@@ -793,12 +802,17 @@ pub fn noop_fold_generics<T: Folder>(
 }
 
 pub fn noop_fold_where_clause<T: Folder>(
-    WhereClause { id, predicates }: WhereClause,
+    WhereClause {
+        id,
+        predicates,
+        span,
+    }: WhereClause,
     fld: &mut T,
 ) -> WhereClause {
     WhereClause {
         id: fld.new_id(id),
         predicates: predicates.move_map(|predicate| fld.fold_where_predicate(predicate)),
+        span: fld.new_span(span),
     }
 }
 
@@ -930,11 +944,11 @@ pub fn noop_fold_item_kind<T: Folder>(i: ItemKind, folder: &mut T) -> ItemKind {
         ItemKind::Use(view_path) => ItemKind::Use(folder.fold_view_path(view_path)),
         ItemKind::Static(t, m, e) => ItemKind::Static(folder.fold_ty(t), m, folder.fold_expr(e)),
         ItemKind::Const(t, e) => ItemKind::Const(folder.fold_ty(t), folder.fold_expr(e)),
-        ItemKind::Fn(decl, unsafety, constness, abi, generics, body) => {
+        ItemKind::Fn(decl, unsafety, constness, asyncness, abi, generics, body) => {
             let generics = folder.fold_generics(generics);
             let decl = folder.fold_fn_decl(decl);
             let body = folder.fold_block(body);
-            ItemKind::Fn(decl, unsafety, constness, abi, generics, body)
+            ItemKind::Fn(decl, unsafety, constness, asyncness, abi, generics, body)
         }
         ItemKind::Mod(m) => ItemKind::Mod(folder.fold_mod(m)),
         ItemKind::ForeignMod(nm) => ItemKind::ForeignMod(folder.fold_foreign_mod(nm)),
@@ -971,12 +985,16 @@ pub fn noop_fold_item_kind<T: Folder>(i: ItemKind, folder: &mut T) -> ItemKind {
                 impl_items.move_flat_map(|item| folder.fold_impl_item(item)),
             )
         }
-        ItemKind::Trait(unsafety, generics, bounds, items) => ItemKind::Trait(
+        ItemKind::Trait(is_auto, unsafety, generics, bounds, items) => ItemKind::Trait(
+            is_auto,
             unsafety,
             folder.fold_generics(generics),
             folder.fold_bounds(bounds),
             items.move_flat_map(|item| folder.fold_trait_item(item)),
         ),
+        ItemKind::TraitAlias(generics, bounds) => {
+            ItemKind::TraitAlias(folder.fold_generics(generics), folder.fold_bounds(bounds))
+        }
         ItemKind::Mac(m) => ItemKind::Mac(folder.fold_mac(m)),
         ItemKind::MacroDef(def) => ItemKind::MacroDef(folder.fold_macro_def(def)),
     }
@@ -1114,8 +1132,11 @@ pub fn noop_fold_item_simple<T: Folder>(
     }
 }
 
-pub fn noop_fold_foreign_item<T: Folder>(ni: ForeignItem, folder: &mut T) -> ForeignItem {
-    ForeignItem {
+pub fn noop_fold_foreign_item<T: Folder>(
+    ni: ForeignItem,
+    folder: &mut T,
+) -> SmallVector<ForeignItem> {
+    SmallVector::one(ForeignItem {
         id: folder.new_id(ni.id),
         vis: folder.fold_vis(ni.vis),
         ident: folder.fold_ident(ni.ident),
@@ -1125,9 +1146,11 @@ pub fn noop_fold_foreign_item<T: Folder>(ni: ForeignItem, folder: &mut T) -> For
                 ForeignItemKind::Fn(folder.fold_fn_decl(fdec), folder.fold_generics(generics))
             }
             ForeignItemKind::Static(t, m) => ForeignItemKind::Static(folder.fold_ty(t), m),
+            ForeignItemKind::Ty => ForeignItemKind::Ty,
+            ForeignItemKind::Macro(mac) => ForeignItemKind::Macro(folder.fold_mac(mac)),
         },
         span: folder.new_span(ni.span),
-    }
+    })
 }
 
 pub fn noop_fold_method_sig<T: Folder>(sig: MethodSig, folder: &mut T) -> MethodSig {
@@ -1136,6 +1159,7 @@ pub fn noop_fold_method_sig<T: Folder>(sig: MethodSig, folder: &mut T) -> Method
         abi: sig.abi,
         unsafety: sig.unsafety,
         constness: sig.constness,
+        asyncness: sig.asyncness,
         decl: folder.fold_fn_decl(sig.decl),
     }
 }
@@ -1209,6 +1233,7 @@ pub fn noop_fold_expr<T: Folder>(
         id,
         node,
         span,
+        bare_span,
         attrs,
     }: Expr,
     folder: &mut T,
@@ -1247,6 +1272,7 @@ pub fn noop_fold_expr<T: Folder>(
                 folder.fold_block(tr),
                 fl.map(|x| folder.fold_expr(x)),
             ),
+            ExprKind::Let(pat, expr) => ExprKind::Let(folder.fold_pat(pat), folder.fold_expr(expr)),
             ExprKind::While(cond, body, opt_ident) => ExprKind::While(
                 folder.fold_expr(cond),
                 folder.fold_block(body),
@@ -1353,14 +1379,20 @@ pub fn noop_fold_expr<T: Folder>(
                     id: sub_expr.id,
                     node: ExprKind::Paren(sub_expr),
                     span: folder.new_span(span),
+                    bare_span: folder.new_span(bare_span),
                     attrs: fold_attrs(attrs.into(), folder).into(),
                 };
             }
             ExprKind::Try(ex) => ExprKind::Try(folder.fold_expr(ex)),
             ExprKind::Catch(body) => ExprKind::Catch(folder.fold_block(body)),
+            ExprKind::Async(capture_clause, body) => {
+                ExprKind::Async(capture_clause, folder.fold_block(body))
+            }
+            ExprKind::Await(ex) => ExprKind::Await(folder.fold_expr(ex)),
         },
         id: folder.new_id(id),
         span: folder.new_span(span),
+        bare_span: folder.new_span(bare_span),
         attrs: fold_attrs(attrs.into(), folder).into(),
     }
 }
@@ -1433,7 +1465,7 @@ mod tests {
     use crate::ast::{self, Ident};
     use crate::fold;
     use crate::print::pprust;
-    use crate::util::parser_testing::{matches_codepattern, string_to_crate};
+    use crate::testing::{matches_codepattern, string_to_crate};
     use std::io;
 
     // this version doesn't care about getting comments or docstrings in.
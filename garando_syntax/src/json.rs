@@ -10,21 +10,38 @@
 // FIXME spec the JSON output properly.
 
 use crate::codemap::{CodeMap, FilePathMapping};
-use crate::errors::emitter::Emitter;
+use crate::errors::caching_codemap_view::CachingCodemapView;
+use crate::errors::emitter::{
+    display_column, ColorConfig, Emitter, EmitterWriter, MacroBacktraceFilter, DEFAULT_TAB_WIDTH,
+};
 use crate::errors::registry::Registry;
-use crate::errors::{CodeMapper, CodeSuggestion, DiagnosticBuilder, RenderSpan, SubDiagnostic};
+use crate::errors::{
+    CodeMapper, CodeSuggestion, DiagnosticBuilder, RenderSpan, SubDiagnostic, SuggestionKind,
+};
 use crate::syntax_pos::{self, MacroBacktrace, MultiSpan, Span, SpanLabel};
 
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::io::{self, Write};
+use std::ops::Not;
 use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 use std::vec;
 
 use serde::Serialize;
 
+/// The most source lines included in the `text` of a span; spans covering
+/// whole files would otherwise copy them into every diagnostic.
+pub const MAX_SPAN_LINES: usize = 20;
+
 pub struct JsonEmitter {
     dst: Box<dyn Write + Send>,
     registry: Option<Registry>,
     cm: Rc<dyn CodeMapper + 'static>,
+    /// Renders each diagnostic into its `rendered` field, if enabled.
+    human: Option<HumanRenderer>,
+    /// Decides which expansions are marked `collapsed`.
+    macro_backtrace: MacroBacktraceFilter,
 }
 
 impl JsonEmitter {
@@ -32,7 +49,9 @@ impl JsonEmitter {
         JsonEmitter {
             dst: Box::new(io::stderr()),
             registry: registry,
-            cm: code_map,
+            cm: Rc::new(CachingCodemapView::new(code_map)),
+            human: None,
+            macro_backtrace: MacroBacktraceFilter::default(),
         }
     }
 
@@ -49,15 +68,88 @@ impl JsonEmitter {
         JsonEmitter {
             dst: dst,
             registry: registry,
-            cm: code_map,
+            cm: Rc::new(CachingCodemapView::new(code_map)),
+            human: None,
+            macro_backtrace: MacroBacktraceFilter::default(),
         }
     }
+
+    /// Sets the `rendered` field of each diagnostic to the text the human
+    /// readable emitter would print for it, notes and suggestions included,
+    /// styled as `EmitterWriter::with_color` does for `color_config`.
+    pub fn with_rendered(mut self, color_config: ColorConfig) -> JsonEmitter {
+        let buffer = RenderBuffer::default();
+        let emitter = EmitterWriter::with_color(
+            Box::new(buffer.clone()),
+            color_config,
+            Some(self.cm.clone()),
+        )
+        .macro_backtrace(self.macro_backtrace.clone());
+        self.human = Some(HumanRenderer { emitter, buffer });
+        self
+    }
+
+    /// Sets which macro expansions are marked `collapsed`, being those the
+    /// human readable emitter leaves out under `filter`. Every expansion is
+    /// still included.
+    pub fn macro_backtrace(mut self, filter: MacroBacktraceFilter) -> JsonEmitter {
+        self.human = self.human.take().map(|human| HumanRenderer {
+            emitter: human.emitter.macro_backtrace(filter.clone()),
+            buffer: human.buffer,
+        });
+        self.macro_backtrace = filter;
+        self
+    }
+}
+
+/// Renders diagnostics with an `EmitterWriter`, into a string.
+struct HumanRenderer {
+    emitter: EmitterWriter,
+    buffer: RenderBuffer,
+}
+
+impl HumanRenderer {
+    fn render(&mut self, db: &DiagnosticBuilder) -> String {
+        self.emitter.emit(db);
+        let bytes = std::mem::take(&mut *self.buffer.0.lock().unwrap());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+#[derive(Clone, Default)]
+struct RenderBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for RenderBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 impl Emitter for JsonEmitter {
     fn emit(&mut self, db: &DiagnosticBuilder) {
-        let data = Diagnostic::from_diagnostic_builder(db, self);
-        if let Err(e) = serde_json::to_writer(&mut self.dst, &data) {
+        let mut retargeted = db.span.clone();
+        let mut data = match self
+            .macro_backtrace
+            .retarget_external(&*self.cm, &mut retargeted)
+        {
+            Some(note) => {
+                let mut diagnostic = (**db).clone();
+                diagnostic.span = retargeted;
+                diagnostic.children.push(note);
+                Diagnostic::from_diagnostic_builder(&diagnostic, self)
+            }
+            None => Diagnostic::from_diagnostic_builder(db, self),
+        };
+        if let Some(ref mut human) = self.human {
+            data.rendered = Some(human.render(db));
+        }
+        let result = serde_json::to_writer(&mut self.dst, &data)
+            .map_err(io::Error::from)
+            .and_then(|()| writeln!(self.dst));
+        if let Err(e) = result {
             panic!("failed to print diagnostics: {:?}", e);
         }
     }
@@ -72,13 +164,21 @@ struct Diagnostic {
     code: Option<DiagnosticCode>,
     /// "error: internal compiler error", "error", "warning", "note", "help".
     level: &'static str,
-    spans: Vec<DiagnosticSpan>,
+    /// `null` for the summary of a session.
+    spans: Option<Vec<DiagnosticSpan>>,
     /// Associated diagnostic messages.
     children: Vec<Diagnostic>,
-    /// The message as rustc would render it. Currently this is only
-    /// `Some` for "suggestions", but eventually it will include all
-    /// snippets.
+    /// The message as rustc would render it. This is `Some` for
+    /// "suggestions", and for the whole diagnostic when the emitter was
+    /// created `with_rendered`.
     rendered: Option<String>,
+    /// Metadata attached by downstream tools, omitted when there is none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_metadata: Option<BTreeMap<String, String>>,
+    /// Set on the summary of how many errors and warnings a session
+    /// emitted, omitted otherwise.
+    #[serde(skip_serializing_if = "Not::not")]
+    is_summary: bool,
 }
 
 #[derive(Serialize)]
@@ -92,11 +192,19 @@ struct DiagnosticSpan {
     /// 1-based, character offset.
     column_start: usize,
     column_end: usize,
+    /// 1-based, the column the start of the span is displayed at in rendered
+    /// diagnostics, where each tab is expanded to `DEFAULT_TAB_WIDTH` spaces
+    /// and wide characters take up two columns.
+    display_column: usize,
     /// Is this a "primary" span -- meaning the point, or one of the points,
     /// where the error occurred?
     is_primary: bool,
-    /// Source text from the start of line_start to the end of line_end.
+    /// Source text from the start of line_start to the end of line_end, or
+    /// only its first `MAX_SPAN_LINES` lines.
     text: Vec<DiagnosticSpanLine>,
+    /// Set when `text` was cut short, omitted otherwise.
+    #[serde(skip_serializing_if = "Not::not")]
+    text_truncated: bool,
     /// Label that should be placed at this location (if any)
     label: Option<String>,
     /// If we are suggesting a replacement, this will contain text
@@ -104,6 +212,10 @@ struct DiagnosticSpan {
     /// load the fully rendered version from the parent `Diagnostic`,
     /// however.
     suggested_replacement: Option<String>,
+    /// Whether `suggested_replacement` inserts, deletes or replaces code,
+    /// omitted when there is no suggestion.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suggestion_kind: Option<SuggestionKind>,
     /// Macro invocations that created the code at this span, if any.
     expansion: Option<Box<DiagnosticSpanMacroExpansion>>,
 }
@@ -130,6 +242,13 @@ struct DiagnosticSpanMacroExpansion {
 
     /// span where macro was defined (if known)
     def_site_span: Option<DiagnosticSpan>,
+
+    /// span of the code the macro produced (if known)
+    output_span: Option<DiagnosticSpan>,
+
+    /// whether the human readable output leaves this expansion out, or
+    /// folds it into a note for a run of external macros
+    collapsed: bool,
 }
 
 #[derive(Serialize)]
@@ -141,22 +260,34 @@ struct DiagnosticCode {
 }
 
 impl Diagnostic {
-    fn from_diagnostic_builder(db: &DiagnosticBuilder, je: &JsonEmitter) -> Diagnostic {
+    fn from_diagnostic_builder(db: &crate::errors::Diagnostic, je: &JsonEmitter) -> Diagnostic {
         let sugg = db.suggestions.iter().flat_map(|sugg| {
-            je.render(sugg).into_iter().map(move |rendered| Diagnostic {
+            // A suggestion whose source can't be read, e.g. in a file that
+            // has since been replaced, is kept without the rendered code.
+            let rendered = match je.render(sugg) {
+                ref rendered if rendered.is_empty() => vec![None],
+                rendered => rendered.into_iter().map(Some).collect(),
+            };
+            rendered.into_iter().map(move |rendered| Diagnostic {
                 message: sugg.msg.clone(),
                 code: None,
                 level: "help",
-                spans: DiagnosticSpan::from_suggestion(sugg, je),
+                spans: Some(DiagnosticSpan::from_suggestion(sugg, je)),
                 children: vec![],
-                rendered: Some(rendered),
+                rendered: rendered,
+                tool_metadata: None,
+                is_summary: false,
             })
         });
         Diagnostic {
             message: db.message(),
             code: DiagnosticCode::map_opt_string(db.code.clone(), je),
             level: db.level.to_str(),
-            spans: DiagnosticSpan::from_multispan(&db.span, je),
+            spans: if db.is_summary {
+                None
+            } else {
+                Some(DiagnosticSpan::from_multispan(&db.span, je))
+            },
             children: db
                 .children
                 .iter()
@@ -164,6 +295,8 @@ impl Diagnostic {
                 .chain(sugg)
                 .collect(),
             rendered: None,
+            tool_metadata: db.tool_metadata.clone(),
+            is_summary: db.is_summary,
         }
     }
 
@@ -172,13 +305,16 @@ impl Diagnostic {
             message: db.message(),
             code: None,
             level: db.level.to_str(),
-            spans: db
-                .render_span
-                .as_ref()
-                .map(|sp| DiagnosticSpan::from_render_span(sp, je))
-                .unwrap_or_else(|| DiagnosticSpan::from_multispan(&db.span, je)),
+            spans: Some(
+                db.render_span
+                    .as_ref()
+                    .map(|sp| DiagnosticSpan::from_render_span(sp, je))
+                    .unwrap_or_else(|| DiagnosticSpan::from_multispan(&db.span, je)),
+            ),
             children: vec![],
             rendered: None,
+            tool_metadata: None,
+            is_summary: false,
         }
     }
 }
@@ -204,7 +340,13 @@ impl DiagnosticSpan {
         // backtrace ourselves, but the `macro_backtrace` helper makes
         // some decision, such as dropping some frames, and I don't
         // want to duplicate that logic here.
-        let backtrace = span.macro_backtrace().into_iter();
+        let backtrace = span.macro_backtrace();
+        let collapsed = je.macro_backtrace.collapsed(&*je.cm, &backtrace);
+        let backtrace = backtrace
+            .into_iter()
+            .zip(collapsed)
+            .collect::<Vec<_>>()
+            .into_iter();
         DiagnosticSpan::from_span_full(span, is_primary, label, suggestion, backtrace, je)
     }
 
@@ -213,33 +355,49 @@ impl DiagnosticSpan {
         is_primary: bool,
         label: Option<String>,
         suggestion: Option<&String>,
-        mut backtrace: vec::IntoIter<MacroBacktrace>,
+        mut backtrace: vec::IntoIter<(MacroBacktrace, bool)>,
         je: &JsonEmitter,
     ) -> DiagnosticSpan {
         let start = je.cm.lookup_char_pos(span.lo);
         let end = je.cm.lookup_char_pos(span.hi);
-        let backtrace_step = backtrace.next().map(|bt| {
+        let backtrace_step = backtrace.next().map(|(bt, collapsed)| {
             let call_site = Self::from_span_full(bt.call_site, false, None, None, backtrace, je);
             let def_site_span = bt
                 .def_site_span
                 .map(|sp| Self::from_span_full(sp, false, None, None, vec![].into_iter(), je));
+            let output_span = bt
+                .output_span
+                .map(|sp| Self::from_span_full(sp, false, None, None, vec![].into_iter(), je));
             Box::new(DiagnosticSpanMacroExpansion {
                 span: call_site,
                 macro_decl_name: bt.macro_decl_name,
                 def_site_span: def_site_span,
+                output_span,
+                collapsed,
             })
         });
+        let (text, text_truncated) = DiagnosticSpanLine::from_span(span, je);
         DiagnosticSpan {
-            file_name: start.file.name.clone(),
+            file_name: start.file.name.to_string(),
             byte_start: span.lo.0,
             byte_end: span.hi.0,
             line_start: start.line,
             line_end: end.line,
             column_start: start.col.0 + 1,
             column_end: end.col.0 + 1,
+            display_column: start
+                .file
+                .get_line(start.line - 1)
+                .map_or(start.col.0, |line| {
+                    display_column(&line, start.col.0, DEFAULT_TAB_WIDTH)
+                })
+                + 1,
             is_primary: is_primary,
-            text: DiagnosticSpanLine::from_span(span, je),
+            text,
+            text_truncated,
             suggested_replacement: suggestion.cloned(),
+            suggestion_kind: suggestion
+                .map(|s| SuggestionKind::new((span.hi.0 - span.lo.0) as usize, s.len())),
             expansion: backtrace_step,
             label: label,
         }
@@ -262,6 +420,7 @@ impl DiagnosticSpan {
                         span: substitution.span,
                         is_primary: true,
                         label: None,
+                        label_parts: vec![],
                     };
                     DiagnosticSpan::from_span_label(span_label, Some(suggestion), je)
                 })
@@ -272,9 +431,9 @@ impl DiagnosticSpan {
     fn from_render_span(rsp: &RenderSpan, je: &JsonEmitter) -> Vec<DiagnosticSpan> {
         match *rsp {
             RenderSpan::FullSpan(ref msp) => DiagnosticSpan::from_multispan(msp, je),
-            // regular diagnostics don't produce this anymore
-            // FIXME(oli_obk): remove it entirely
-            RenderSpan::Suggestion(_) => unreachable!(),
+            RenderSpan::Suggestion(ref suggestion) => {
+                DiagnosticSpan::from_suggestion(suggestion, je)
+            }
         }
     }
 }
@@ -287,7 +446,7 @@ impl DiagnosticSpanLine {
         h_end: usize,
     ) -> DiagnosticSpanLine {
         DiagnosticSpanLine {
-            text: fm.get_line(index).unwrap_or("").to_owned(),
+            text: fm.get_line(index).map_or_else(String::new, Cow::into_owned),
             highlight_start: h_start,
             highlight_end: h_end,
         }
@@ -295,13 +454,14 @@ impl DiagnosticSpanLine {
 
     /// Create a list of DiagnosticSpanLines from span - each line with any part
     /// of `span` gets a DiagnosticSpanLine, with the highlight indicating the
-    /// `span` within the line.
-    fn from_span(span: Span, je: &JsonEmitter) -> Vec<DiagnosticSpanLine> {
+    /// `span` within the line. Only the first `MAX_SPAN_LINES` lines are
+    /// included; the flag says whether any were left out.
+    fn from_span(span: Span, je: &JsonEmitter) -> (Vec<DiagnosticSpanLine>, bool) {
         je.cm
-            .span_to_lines(span)
+            .span_to_lines_limited(span, MAX_SPAN_LINES)
             .map(|lines| {
                 let fm = &*lines.file;
-                lines
+                let text = lines
                     .lines
                     .iter()
                     .map(|line| {
@@ -312,9 +472,10 @@ impl DiagnosticSpanLine {
                             line.end_col.0 + 1,
                         )
                     })
-                    .collect()
+                    .collect();
+                (text, lines.truncated)
             })
-            .unwrap_or_else(|_| vec![])
+            .unwrap_or_else(|_| (vec![], false))
     }
 }
 
@@ -339,3 +500,768 @@ impl JsonEmitter {
         suggestion.splice_lines(&*self.cm)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::emitter::EmitterWriter;
+    use crate::errors::snippet::Style;
+    use crate::errors::{Diagnostic, Handler, Level};
+    use crate::syntax_pos::{BytePos, FileName};
+    use crate::testing::SharedBuffer;
+    use std::cell::Cell;
+
+    fn emit_json<F: FnOnce(&mut DiagnosticBuilder)>(f: F) -> serde_json::Value {
+        emit_json_with_codemap(Rc::new(CodeMap::new(FilePathMapping::empty())), f)
+    }
+
+    fn emit_json_with_codemap<F>(cm: Rc<CodeMap>, f: F) -> serde_json::Value
+    where
+        F: FnOnce(&mut DiagnosticBuilder),
+    {
+        let output = SharedBuffer::default();
+        let emitter = JsonEmitter::new(Box::new(output.clone()), None, cm);
+        let handler = Handler::with_emitter(true, false, Box::new(emitter));
+        let mut db = handler.struct_err("oops");
+        f(&mut db);
+        db.emit();
+        output.json_diagnostics().remove(0)
+    }
+
+    #[test]
+    fn rendered_human_readable() {
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let fm = cm.new_filemap_and_lines("a.rs", "fn main() { let x = 1 }");
+        let span = Span {
+            lo: fm.start_pos + BytePos(20),
+            hi: fm.start_pos + BytePos(21),
+            ctxt: syntax_pos::NO_EXPANSION,
+        };
+
+        let output = SharedBuffer::default();
+        let emitter =
+            JsonEmitter::new(Box::new(output.clone()), None, cm).with_rendered(ColorConfig::Never);
+        let handler = Handler::with_emitter(true, false, Box::new(emitter));
+        handler
+            .struct_span_err(span, "missing semicolon")
+            .span_label(span, "here")
+            .note("statements end with `;`")
+            .emit();
+        handler.struct_err("no span").emit();
+
+        let mut lines = output.json_diagnostics().into_iter();
+        let json = lines.next().unwrap();
+        let rendered = json["rendered"].as_str().unwrap();
+        let expected = "\
+error: missing semicolon
+ --> a.rs:1:21
+  |
+1 | fn main() { let x = 1 }
+  |                     ^ here
+  |
+  = note: statements end with `;`
+";
+        assert!(rendered.starts_with(expected), "{}", rendered);
+        assert!(json["children"][0]["rendered"].is_null());
+
+        let json = lines.next().unwrap();
+        assert_eq!(json["rendered"].as_str().unwrap().trim(), "error: no span");
+    }
+
+    #[test]
+    fn display_column_expands_tabs() {
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let fm = cm.new_filemap_and_lines("a.rs", "fn f() {\n\t\tlet x = 1\n}");
+        let lo = fm.start_pos + BytePos(15);
+        let span = Span {
+            lo,
+            hi: lo + BytePos(1),
+            ctxt: syntax_pos::NO_EXPANSION,
+        };
+
+        let json = emit_json_with_codemap(cm, |db| {
+            db.set_span(span);
+        });
+        let span = &json["spans"][0];
+        assert_eq!(span["line_start"], 2);
+        assert_eq!(span["column_start"], 7);
+        assert_eq!(span["display_column"], 13);
+    }
+
+    #[test]
+    fn styled_labels_are_flattened() {
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let fm = cm.new_filemap_and_lines("a.rs", "let x = 1");
+        let span = Span {
+            lo: fm.start_pos + BytePos(4),
+            hi: fm.start_pos + BytePos(5),
+            ctxt: syntax_pos::NO_EXPANSION,
+        };
+
+        let plain = format!("`{}` bound here", "x");
+        let json = emit_json_with_codemap(cm, |db| {
+            db.span_label(span, &plain);
+            db.span_styled_label(
+                span,
+                vec![
+                    ("of type `".to_string(), Style::NoStyle),
+                    ("i32".to_string(), Style::Highlight),
+                    ("`".to_string(), Style::NoStyle),
+                ],
+            );
+        });
+        let labels: Vec<_> = json["spans"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|span| span["label"].clone())
+            .collect();
+        assert_eq!(labels, ["`x` bound here", "of type `i32`"]);
+    }
+
+    #[test]
+    fn suggestion_kinds() {
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let fm = cm.new_filemap_and_lines("a.rs", "let x = (1);");
+        let span = |lo: u32, hi: u32| Span {
+            lo: fm.start_pos + BytePos(lo),
+            hi: fm.start_pos + BytePos(hi),
+            ctxt: syntax_pos::NO_EXPANSION,
+        };
+
+        let json = emit_json_with_codemap(cm.clone(), |db| {
+            db.set_span(span(4, 5));
+            db.multipart_suggestion(
+                "fix it",
+                vec![
+                    (span(8, 8), "-".to_string()),
+                    (span(8, 9), "".to_string()),
+                    (span(4, 5), "y".to_string()),
+                ],
+            );
+        });
+        assert!(json["spans"][0].get("suggestion_kind").is_none());
+        let kinds: Vec<_> = json["children"][0]["spans"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|span| span["suggestion_kind"].clone())
+            .collect();
+        assert_eq!(kinds, ["insertion", "deletion", "replacement"]);
+    }
+
+    #[test]
+    fn suggestion_as_note() {
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let fm = cm.new_filemap_and_lines("a.rs", "let x = 1");
+        let span = |lo: u32, hi: u32| Span {
+            lo: fm.start_pos + BytePos(lo),
+            hi: fm.start_pos + BytePos(hi),
+            ctxt: syntax_pos::NO_EXPANSION,
+        };
+
+        let json = emit_json_with_codemap(cm, |db| {
+            db.set_span(span(8, 9));
+            db.note("before");
+            db.span_suggestion_as_note(span(9, 9), "add a semicolon", ";".to_string());
+        });
+        let children = json["children"].as_array().unwrap();
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0]["message"], "before");
+        let note = &children[1];
+        assert_eq!(note["level"], "note");
+        assert_eq!(note["message"], "add a semicolon");
+        let spans = note["spans"].as_array().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0]["suggested_replacement"], ";");
+        assert_eq!(spans[0]["suggestion_kind"], "insertion");
+        assert_eq!(spans[0]["is_primary"], true);
+        assert_eq!(
+            (&spans[0]["column_start"], &spans[0]["column_end"]),
+            (&10.into(), &10.into())
+        );
+    }
+
+    #[test]
+    fn tool_metadata_omitted_when_absent() {
+        let json = emit_json(|_| {});
+        assert_eq!(json["message"], "oops");
+        assert!(json.get("tool_metadata").is_none());
+    }
+
+    #[test]
+    fn tool_metadata_serialized() {
+        let json = emit_json(|db| {
+            db.tool_metadata("rule", "no-unwrap")
+                .tool_metadata("fingerprint", "abc123");
+        });
+        assert_eq!(
+            json["tool_metadata"],
+            serde_json::json!({ "fingerprint": "abc123", "rule": "no-unwrap" })
+        );
+    }
+
+    #[test]
+    fn tool_metadata_survives_copies() {
+        let handler = Handler::with_emitter(true, false, Box::new(JsonEmitter::basic()));
+        let mut db = handler.struct_warn("original");
+        db.tool_metadata("rule", "no-unwrap");
+        let original = db.into_diagnostic();
+        assert_eq!(original.clone(), original);
+
+        let mut copy = Diagnostic::new(Level::Warning, "copy");
+        assert!(copy != original);
+        copy.copy_details_not_message(&original);
+        assert_eq!(copy.tool_metadata, original.tool_metadata);
+        assert_eq!(copy.tool_metadata.unwrap()["rule"], "no-unwrap");
+    }
+
+    #[test]
+    fn conditional_emission() {
+        let output = SharedBuffer::default();
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let emitter = JsonEmitter::new(Box::new(output.clone()), None, cm);
+        let handler = Handler::with_emitter(true, false, Box::new(emitter));
+
+        handler.struct_err("dropped").emit_unless(true);
+        assert_eq!(handler.err_count(), 0);
+        handler.struct_err("kept").emit_unless(false);
+        assert_eq!(handler.err_count(), 1);
+
+        handler.struct_err("cancelled").cancel_if(true).emit();
+        handler.struct_err("not cancelled").cancel_if(false).emit();
+        assert_eq!(handler.err_count(), 2);
+
+        handler
+            .struct_err("downgraded")
+            .downgrade_to_warning()
+            .emit();
+        handler.struct_fatal("fatal").downgrade_to_warning().emit();
+        assert_eq!((handler.err_count(), handler.warn_count()), (3, 1));
+
+        let emitted: Vec<_> = output
+            .json_diagnostics()
+            .into_iter()
+            .map(|json| (json["message"].clone(), json["level"].clone()))
+            .collect();
+        assert_eq!(
+            emitted,
+            [
+                (serde_json::json!("kept"), serde_json::json!("error")),
+                (
+                    serde_json::json!("not cancelled"),
+                    serde_json::json!("error")
+                ),
+                (
+                    serde_json::json!("downgraded"),
+                    serde_json::json!("warning")
+                ),
+                (serde_json::json!("fatal"), serde_json::json!("error")),
+            ]
+        );
+
+        let quiet = Handler::with_emitter(false, false, Box::new(JsonEmitter::basic()));
+        quiet.struct_err("downgraded").downgrade_to_warning().emit();
+        assert_eq!((quiet.err_count(), quiet.warn_count()), (0, 0));
+    }
+
+    #[test]
+    fn runtime_registered_codes() {
+        let mut registry = Registry::new(&[("E0001", "A built-in explanation.")]);
+        registry.add("T0001", Some("A tool's explanation."));
+        registry.add("T0002", None);
+
+        let output = SharedBuffer::default();
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let emitter = JsonEmitter::new(Box::new(output.clone()), Some(registry.clone()), cm);
+        let handler = Handler::with_emitter(true, false, Box::new(emitter));
+        handler
+            .struct_err("untracked")
+            .code("T0003".to_string())
+            .emit();
+        handler.track_emitted_codes();
+        for code in &["T0001", "T0002", "T0003", "E0001", "T0001"] {
+            handler.struct_err(code).code(code.to_string()).emit();
+        }
+        handler.struct_err("no code").emit();
+
+        let explanations: Vec<_> = output
+            .json_diagnostics()
+            .into_iter()
+            .skip(1)
+            .map(|json| json["code"]["explanation"].clone())
+            .take(4)
+            .collect();
+        assert_eq!(
+            explanations,
+            [
+                serde_json::json!("A tool's explanation."),
+                serde_json::Value::Null,
+                serde_json::Value::Null,
+                serde_json::json!("A built-in explanation."),
+            ]
+        );
+
+        assert_eq!(
+            handler.emitted_codes(),
+            ["E0001", "T0001", "T0002", "T0003"]
+        );
+        assert_eq!(handler.undocumented_codes(&registry), ["T0002", "T0003"]);
+    }
+
+    const IMPORTED_SRC: &str = "fn main() {\n    let x = 1;\n}\n";
+
+    /// A codemap holding `IMPORTED_SRC` as an imported filemap, and the span
+    /// of `let x = 1;` in it.
+    fn imported_filemap() -> (Rc<CodeMap>, Span) {
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let fm = cm.new_imported_filemap(
+            "lib.rs".into(),
+            false,
+            1,
+            syntax_pos::hash_source(IMPORTED_SRC),
+            IMPORTED_SRC.len(),
+            vec![BytePos(0), BytePos(12), BytePos(27)],
+            vec![],
+        );
+        let lo = fm.start_pos + BytePos(16);
+        let span = Span {
+            lo,
+            hi: lo + BytePos(10),
+            ctxt: syntax_pos::NO_EXPANSION,
+        };
+        (cm, span)
+    }
+
+    #[test]
+    fn imported_filemap_without_source() {
+        let (cm, span) = imported_filemap();
+        let json = emit_json_with_codemap(cm, |db| {
+            db.set_span(span);
+        });
+        assert_eq!(json["spans"][0]["text"][0]["text"], "");
+    }
+
+    #[test]
+    fn imported_filemap_with_source_provider() {
+        let (cm, span) = imported_filemap();
+        let calls = Rc::new(Cell::new(0));
+        let calls2 = calls.clone();
+        cm.set_source_provider(Box::new(move |name| {
+            calls2.set(calls2.get() + 1);
+            if name == "lib.rs" {
+                Some(IMPORTED_SRC.to_string())
+            } else {
+                None
+            }
+        }));
+
+        let json = emit_json_with_codemap(cm.clone(), |db| {
+            db.set_span(span);
+        });
+        let text = &json["spans"][0]["text"][0];
+        assert_eq!(text["text"], "    let x = 1;");
+        assert_eq!(text["highlight_start"], 5);
+        assert_eq!(text["highlight_end"], 15);
+
+        // The terminal emitter shows the snippet too.
+        let output = SharedBuffer::default();
+        let emitter = EmitterWriter::new(Box::new(output.clone()), Some(cm.clone()));
+        let handler = Handler::with_emitter(true, false, Box::new(emitter));
+        handler.span_err(span, "oops");
+        let output = output.contents();
+        assert!(output.contains("2 |     let x = 1;"), "{}", output);
+
+        // The source is attached to the filemap on first use.
+        let fm = cm.lookup_char_pos(span.lo).file;
+        assert_eq!(calls.get(), 1);
+        assert_eq!(fm.get_line(2).as_deref(), Some("}"));
+    }
+
+    #[test]
+    fn suggestion_into_replaced_file() {
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let old = cm.new_filemap_and_lines("edited.rs", "fn main() { let x = 1 }");
+        let span = Span {
+            lo: old.start_pos + BytePos(20),
+            hi: old.start_pos + BytePos(21),
+            ctxt: syntax_pos::NO_EXPANSION,
+        };
+        cm.replace_filemap(&old, "fn main() {}".to_string());
+
+        // The suggestion's snippet is left out rather than panicking.
+        let output = SharedBuffer::default();
+        let emitter = EmitterWriter::new(Box::new(output.clone()), Some(cm.clone()));
+        let handler = Handler::with_emitter(true, false, Box::new(emitter));
+        handler
+            .struct_span_err(span, "oops")
+            .span_suggestion(span, "add a semicolon", "1;\n".to_string())
+            .emit();
+        let output = output.contents();
+        assert!(output.contains("help: add a semicolon\n"), "{}", output);
+        assert!(!output.contains("1;"), "{}", output);
+
+        let json = emit_json_with_codemap(cm.clone(), |db| {
+            db.set_span(span);
+            db.span_suggestion(span, "add a semicolon", "1;\n".to_string());
+        });
+        let help = &json["children"][0];
+        assert_eq!(help["message"], "add a semicolon");
+        assert_eq!(help["rendered"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn whole_file_span_text_truncated() {
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let src: String = (0..100_000).map(|i| format!("// line {}\n", i)).collect();
+        let fm = cm.new_filemap_and_lines("huge.rs", &src);
+        let span = Span {
+            lo: fm.start_pos,
+            hi: fm.end_pos,
+            ctxt: syntax_pos::NO_EXPANSION,
+        };
+        let json = emit_json_with_codemap(cm.clone(), |db| {
+            db.set_span(span);
+        });
+        let span_json = &json["spans"][0];
+        assert_eq!(span_json["text_truncated"], true);
+        assert_eq!(span_json["line_end"], 100_000);
+        let text = span_json["text"].as_array().unwrap();
+        assert_eq!(text.len(), MAX_SPAN_LINES);
+        assert_eq!(text[MAX_SPAN_LINES - 1]["text"], "// line 19");
+        assert!(serde_json::to_string(&json).unwrap().len() < 10_000);
+
+        let short = Span {
+            lo: fm.start_pos,
+            hi: fm.start_pos + BytePos(15),
+            ctxt: syntax_pos::NO_EXPANSION,
+        };
+        let json = emit_json_with_codemap(cm, |db| {
+            db.set_span(short);
+        });
+        assert!(json["spans"][0].get("text_truncated").is_none());
+        assert_eq!(json["spans"][0]["text"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn source_provider_length_mismatch() {
+        let (cm, span) = imported_filemap();
+        cm.set_source_provider(Box::new(|_| Some("fn main() {}\n".to_string())));
+        let json = emit_json_with_codemap(cm, |db| {
+            db.set_span(span);
+        });
+        assert_eq!(json["spans"][0]["text"][0]["text"], "");
+    }
+
+    #[test]
+    fn real_file_names_are_stable() {
+        for name in &["src/lib.rs", "<weird>.rs", "/abs/path/main.rs"] {
+            let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+            let fm = cm.new_filemap_and_lines(name, IMPORTED_SRC);
+            assert!(fm.name.is_real());
+            let span = Span {
+                lo: fm.start_pos,
+                hi: fm.start_pos + BytePos(2),
+                ctxt: syntax_pos::NO_EXPANSION,
+            };
+            let json = emit_json_with_codemap(cm, |db| {
+                db.set_span(span);
+            });
+            assert_eq!(json["spans"][0]["file_name"], *name);
+
+            let serialized = serde_json::to_value(&fm.name).unwrap();
+            assert_eq!(serialized, *name);
+            let name: FileName = serde_json::from_value(serialized).unwrap();
+            assert_eq!(name, fm.name);
+        }
+    }
+
+    const EXPANSION_SRC: &str = "macro_rules! local { ($x:expr) => { outer!($x) } }
+fn main() {
+    local!(x);
+}
+";
+
+    /// A codemap with `EXPANSION_SRC`, and the span of its `x` as passed
+    /// through `local!`, defined alongside it, and then `outer!` and
+    /// `inner!`, defined in an imported filemap.
+    fn expansion_chain() -> (Rc<CodeMap>, Span) {
+        use crate::codemap::{ExpnInfo, MacroBang, NameAndSpan};
+        use crate::ext::hygiene::{Mark, SyntaxContext};
+        use crate::symbol::Symbol;
+
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let fm = cm.new_filemap_and_lines("test.rs", EXPANSION_SRC);
+        let dep =
+            cm.new_imported_filemap("dep.rs".into(), false, 1, 0, 100, vec![BytePos(0)], vec![]);
+        let span = |start: BytePos, lo: usize, len: usize| Span {
+            lo: start + BytePos(lo as u32),
+            hi: start + BytePos((lo + len) as u32),
+            ctxt: syntax_pos::NO_EXPANSION,
+        };
+        let find = |s: &str| span(fm.start_pos, EXPANSION_SRC.find(s).unwrap(), s.len());
+
+        let steps = [
+            (
+                "local",
+                find("macro_rules! local { ($x:expr) => { outer!($x) } }"),
+                find("outer!($x)"),
+            ),
+            (
+                "outer",
+                span(dep.start_pos, 0, 40),
+                span(dep.start_pos, 10, 10),
+            ),
+            (
+                "inner",
+                span(dep.start_pos, 50, 40),
+                span(fm.start_pos, EXPANSION_SRC.find("(x)").unwrap() + 1, 1),
+            ),
+        ];
+        let mut call_site = find("local!(x)");
+        for &(name, def_site, expanded) in &steps {
+            let mark = Mark::fresh(Mark::root());
+            mark.set_expn_info(ExpnInfo {
+                call_site,
+                callee: NameAndSpan {
+                    format: MacroBang(Symbol::intern(name)),
+                    span: Some(def_site),
+                    allow_internal_unstable: false,
+                    allow_internal_unsafe: false,
+                },
+                output_span: None,
+            });
+            call_site = Span {
+                ctxt: SyntaxContext::empty().apply_mark(mark),
+                ..expanded
+            };
+        }
+        (cm, call_site)
+    }
+
+    fn render_backtrace(filter: MacroBacktraceFilter) -> String {
+        let (cm, span) = expansion_chain();
+        let output = SharedBuffer::default();
+        let emitter =
+            EmitterWriter::new(Box::new(output.clone()), Some(cm)).macro_backtrace(filter);
+        let handler = Handler::with_emitter(true, false, Box::new(emitter));
+        handler.span_err(span, "oops");
+        output.contents()
+    }
+
+    const BACKTRACE_ERROR: &str = "error: oops
+ --> test.rs:3:12
+  |
+3 |     local!(x);
+  |     -------^- in this macro invocation
+";
+
+    const OUTER_NOTE: &str = " --> test.rs:1:37
+  |
+1 | macro_rules! local { ($x:expr) => { outer!($x) } }
+  |                                     ^^^^^^^^^^
+";
+
+    const LOCAL_NOTE: &str = " --> test.rs:3:5
+  |
+3 |     local!(x);
+  |     ^^^^^^^^^
+
+";
+
+    #[test]
+    fn macro_backtrace_notes() {
+        use crate::errors::emitter::MacroBacktracePolicy::*;
+
+        assert_eq!(
+            render_backtrace(MacroBacktraceFilter::new(Always)),
+            format!(
+                "{}  |\nnote: in this expansion of inner!\n\
+                 note: in this expansion of outer!\n{}\
+                 note: in this expansion of local!\n{}",
+                BACKTRACE_ERROR, OUTER_NOTE, LOCAL_NOTE
+            )
+        );
+        // `inner!` and `outer!` come from `dep.rs`, which has no source
+        assert_eq!(
+            render_backtrace(MacroBacktraceFilter::new(OnlyLocal)),
+            format!(
+                "{}  |\nnote: in this expansion of outer! (from an external macro)\n{}\
+                 note: in this expansion of local!\n{}",
+                BACKTRACE_ERROR, OUTER_NOTE, LOCAL_NOTE
+            )
+        );
+        assert_eq!(
+            render_backtrace(
+                MacroBacktraceFilter::new(OnlyLocal)
+                    .external_files(|name| *name == FileName::from("test.rs"))
+            ),
+            // with every file external, the error is moved out of the macros
+            format!(
+                "error: oops
+ --> test.rs:3:5
+  |
+3 |     local!(x);
+  |     ^^^^^^^^^
+  |
+  = note: this error originates in the macro `inner`
+note: in this expansion of local! (from an external macro)
+{}",
+                LOCAL_NOTE
+            )
+        );
+        assert_eq!(
+            render_backtrace(MacroBacktraceFilter::new(Never)),
+            format!("{}\n", BACKTRACE_ERROR)
+        );
+    }
+
+    #[test]
+    fn macro_backtrace_collapsed() {
+        use crate::errors::emitter::MacroBacktracePolicy::*;
+
+        // the expansions, innermost first, and the number of notes rendered
+        let collapsed = |filter: MacroBacktraceFilter| {
+            let (cm, span) = expansion_chain();
+            let output = SharedBuffer::default();
+            let emitter = JsonEmitter::new(Box::new(output.clone()), None, cm)
+                .with_rendered(ColorConfig::Never)
+                .macro_backtrace(filter);
+            let handler = Handler::with_emitter(true, false, Box::new(emitter));
+            handler.span_err(span, "oops");
+            let json = output.json_diagnostics().remove(0);
+            let mut expansion = &json["spans"][0]["expansion"];
+            let mut steps = vec![];
+            while !expansion.is_null() {
+                steps.push((
+                    expansion["macro_decl_name"].as_str().unwrap().to_string(),
+                    expansion["collapsed"].as_bool().unwrap(),
+                ));
+                expansion = &expansion["span"]["expansion"];
+            }
+            let notes = json["rendered"]
+                .as_str()
+                .unwrap()
+                .matches("in this expansion of")
+                .count();
+            (steps, notes)
+        };
+        let steps = |collapsed: [bool; 3]| {
+            vec![
+                ("inner!".to_string(), collapsed[0]),
+                ("outer!".to_string(), collapsed[1]),
+                ("local!".to_string(), collapsed[2]),
+            ]
+        };
+
+        assert_eq!(
+            collapsed(MacroBacktraceFilter::new(Always)),
+            (steps([false, false, false]), 3)
+        );
+        assert_eq!(
+            collapsed(MacroBacktraceFilter::new(OnlyLocal)),
+            (steps([true, true, false]), 2)
+        );
+        assert_eq!(
+            collapsed(MacroBacktraceFilter::new(Never)),
+            (steps([true, true, true]), 0)
+        );
+    }
+
+    /// A codemap with `test.rs` invoking `ext!`, defined in an imported
+    /// filemap; the span of the invocation; and a span `ext!` produced from
+    /// its definition.
+    fn external_expansion() -> (Rc<CodeMap>, Span, Span) {
+        use crate::codemap::{ExpnInfo, MacroBang, NameAndSpan};
+        use crate::ext::hygiene::{Mark, SyntaxContext};
+        use crate::symbol::Symbol;
+
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let src = "fn main() {\n    ext!();\n}\n";
+        let fm = cm.new_filemap_and_lines("test.rs", src);
+        let dep =
+            cm.new_imported_filemap("dep.rs".into(), false, 1, 0, 100, vec![BytePos(0)], vec![]);
+        let lo = fm.start_pos + BytePos(src.find("ext!()").unwrap() as u32);
+        let call_site = Span {
+            lo,
+            hi: lo + BytePos(6),
+            ctxt: syntax_pos::NO_EXPANSION,
+        };
+        let mark = Mark::fresh(Mark::root());
+        mark.set_expn_info(ExpnInfo {
+            call_site,
+            callee: NameAndSpan {
+                format: MacroBang(Symbol::intern("ext")),
+                span: Some(Span {
+                    lo: dep.start_pos,
+                    hi: dep.start_pos + BytePos(40),
+                    ctxt: syntax_pos::NO_EXPANSION,
+                }),
+                allow_internal_unstable: false,
+                allow_internal_unsafe: false,
+            },
+            output_span: None,
+        });
+        let span = Span {
+            lo: dep.start_pos + BytePos(10),
+            hi: dep.start_pos + BytePos(15),
+            ctxt: SyntaxContext::empty().apply_mark(mark),
+        };
+        (cm, call_site, span)
+    }
+
+    #[test]
+    fn external_expansions_retargeted() {
+        use crate::errors::emitter::MacroBacktracePolicy::*;
+
+        let emit = |policy| {
+            let (cm, call_site, span) = external_expansion();
+            let output = SharedBuffer::default();
+            let emitter = JsonEmitter::new(Box::new(output.clone()), None, cm)
+                .with_rendered(ColorConfig::Never)
+                .macro_backtrace(MacroBacktraceFilter::new(policy));
+            let handler = Handler::with_emitter(true, false, Box::new(emitter));
+            handler
+                .struct_span_err(span, "oops")
+                .span_label(span, "here")
+                .emit();
+            let json = output.json_diagnostics().remove(0);
+            (json, call_site)
+        };
+
+        for &policy in &[OnlyLocal, Never] {
+            let (json, call_site) = emit(policy);
+            let span = &json["spans"][0];
+            assert_eq!(json["spans"].as_array().unwrap().len(), 1);
+            assert_eq!(span["file_name"], "test.rs");
+            assert_eq!(
+                (span["byte_start"].as_u64(), span["byte_end"].as_u64()),
+                (Some(call_site.lo.0 as u64), Some(call_site.hi.0 as u64))
+            );
+            assert_eq!(span["is_primary"], true);
+            assert_eq!(span["label"], "here");
+            assert_eq!(
+                json["children"][0]["message"],
+                "this error originates in the macro `ext`"
+            );
+        }
+
+        let (json, _) = emit(Never);
+        assert_eq!(
+            json["rendered"],
+            "error: oops
+ --> test.rs:2:5
+  |
+2 |     ext!();
+  |     ^^^^^^ here
+  |
+  = note: this error originates in the macro `ext`
+
+"
+        );
+
+        let (json, _) = emit(Always);
+        assert_eq!(json["spans"][0]["file_name"], "dep.rs");
+        assert_eq!(json["children"].as_array().unwrap().len(), 0);
+    }
+}
@@ -55,9 +55,11 @@ pub mod util {
     pub mod move_map;
     pub mod node_count;
     pub mod parser;
-    #[cfg(test)]
-    pub mod parser_testing;
+    pub mod separators;
     pub mod small_vector;
+    pub mod span_index;
+    pub mod span_validator;
+    pub mod stable_hash;
 
     mod thin_vec;
     pub use self::thin_vec::ThinVec;
@@ -88,8 +90,10 @@ pub mod ptr;
 pub mod show_span;
 pub mod std_inject;
 pub mod str;
+pub mod suggestions;
 pub use crate::syntax_pos::symbol;
 pub mod test;
+pub mod testing;
 pub mod tokenstream;
 pub mod visit;
 
@@ -109,10 +113,13 @@ pub mod ext {
     pub mod source_util;
 
     pub mod tt {
+        pub mod macro_defs;
         pub mod macro_parser;
         pub mod macro_rules;
         pub mod quoted;
         pub mod transcribe;
+
+        pub use self::macro_defs::{collect_macro_defs, MacroDef, MacroRule};
     }
 }
 
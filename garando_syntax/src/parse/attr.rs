@@ -4,7 +4,9 @@ use crate::codemap::respan;
 use crate::parse::common::SeqSep;
 use crate::parse::parser::{Parser, PathStyle, TokenType};
 use crate::parse::token::{self, Nonterminal};
-use crate::parse::PResult;
+use crate::parse::{self, PResult};
+use crate::symbol::Symbol;
+use crate::syntax_pos::Span;
 use crate::tokenstream::TokenStream;
 
 use log::debug;
@@ -91,6 +93,9 @@ impl<'a> Parser<'a> {
         );
         let (span, path, tokens, mut style) = match self.token {
             token::Pound => {
+                // Taken before bumping, so that the span runs from the `#` to
+                // the `]` whatever whitespace separates the tokens; tools
+                // remove attributes by their span.
                 let lo = self.span;
                 self.bump();
 
@@ -125,8 +130,8 @@ impl<'a> Parser<'a> {
                 (lo.to(hi), path, tokens, style)
             }
             _ => {
-                let token_str = self.this_token_to_string();
-                return Err(self.fatal(&format!("expected `#`, found `{}`", token_str)));
+                let token_str = self.this_token_descr();
+                return Err(self.fatal(&format!("expected `#`, found {}", token_str)));
             }
         };
 
@@ -205,6 +210,79 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_unsuffixed_lit(&mut self) -> PResult<'a, ast::Lit> {
+        let lit = self.parse_single_unsuffixed_lit()?;
+        match lit.node {
+            ast::LitKind::Str(..) if self.is_str_lit() => self.parse_adjacent_str_lits(lit),
+            _ => Ok(lit),
+        }
+    }
+
+    fn is_str_lit(&self) -> bool {
+        matches!(
+            self.token,
+            token::Literal(token::Str_(..), _) | token::Literal(token::StrRaw(..), _)
+        )
+    }
+
+    /// Unlike in C, adjacent string literals are not concatenated. In an
+    /// attribute they are an error, but their contents are still folded into
+    /// a single literal so that nothing is lost during recovery.
+    fn parse_adjacent_str_lits(&mut self, first: ast::Lit) -> PResult<'a, ast::Lit> {
+        let mut value = String::new();
+        let mut spans = Vec::new();
+        let mut lit = first;
+        loop {
+            if let ast::LitKind::Str(s, _) = lit.node {
+                value.push_str(&s.as_str());
+            }
+            spans.push(lit.span);
+            if !self.is_str_lit() {
+                break;
+            }
+            lit = self.parse_single_unsuffixed_lit()?;
+        }
+
+        let (first, last) = (spans[0], spans[spans.len() - 1]);
+        let span = first.to(last);
+        let mut concat = vec![(
+            Span {
+                hi: first.lo,
+                ..first
+            },
+            "concat!(".to_string(),
+        )];
+        concat.extend(
+            spans
+                .windows(2)
+                .map(|pair| (pair[0].between(pair[1]), ", ".to_string())),
+        );
+        concat.push((
+            Span {
+                lo: last.hi,
+                ..last
+            },
+            ")".to_string(),
+        ));
+        self.diagnostic()
+            .struct_span_err(
+                span,
+                "adjacent string literals are not allowed in attributes",
+            )
+            .multipart_suggestion("join them with `concat!`", concat)
+            .span_suggestion(
+                span,
+                "or write a single literal",
+                format!("\"{}\"", parse::escape_default(&value)),
+            )
+            .emit();
+
+        Ok(respan(
+            span,
+            ast::LitKind::Str(Symbol::intern(&value), ast::StrStyle::Cooked),
+        ))
+    }
+
+    fn parse_single_unsuffixed_lit(&mut self) -> PResult<'a, ast::Lit> {
         let lit = self.parse_lit()?;
         debug!("Checking if {:?} is unusuffixed.", lit);
 
@@ -286,18 +364,29 @@ impl<'a> Parser<'a> {
             Err(ref mut err) => self.diagnostic().cancel(err),
         }
 
-        let found = self.this_token_to_string();
+        let found = self.this_token_descr();
         let msg = format!("expected unsuffixed literal or identifier, found {}", found);
         Err(self.diagnostic().struct_span_err(lo, &msg))
     }
 
     /// matches meta_seq = ( COMMASEP(meta_item_inner) )
+    ///
+    /// The whole list is consumed even if it has errors, but only the first
+    /// is returned, for the caller to emit or cancel; the others are likely
+    /// to follow from it.
     fn parse_meta_seq(&mut self) -> PResult<'a, Vec<ast::NestedMetaItem>> {
-        self.parse_unspanned_seq(
+        let (items, _, mut errors) = self.parse_delimited_seq(
             &token::OpenDelim(token::Paren),
             &token::CloseDelim(token::Paren),
             SeqSep::trailing_allowed(token::Comma),
             |p: &mut Parser<'a>| p.parse_meta_item_inner(),
-        )
+        )?;
+        if errors.is_empty() {
+            return Ok(items);
+        }
+        for err in &mut errors[1..] {
+            err.cancel();
+        }
+        Err(errors.swap_remove(0))
     }
 }
@@ -5,11 +5,14 @@ use crate::parse::token;
 /// `SeqSep` : a sequence separator (token)
 /// and whether a trailing separator is allowed.
 pub struct SeqSep {
+    /// The token between the elements of the sequence, if there is one.
     pub sep: Option<token::Token>,
+    /// Whether the sequence may end with `sep`, as in `(a, b,)`.
     pub trailing_sep_allowed: bool,
 }
 
 impl SeqSep {
+    /// Elements separated by `t`, optionally after the last one as well.
     pub fn trailing_allowed(t: token::Token) -> SeqSep {
         SeqSep {
             sep: Some(t),
@@ -17,6 +20,7 @@ impl SeqSep {
         }
     }
 
+    /// Elements without separators between them.
     pub fn none() -> SeqSep {
         SeqSep {
             sep: None,
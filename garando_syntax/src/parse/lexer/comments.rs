@@ -32,19 +32,82 @@ pub struct Comment {
     pub pos: BytePos,
 }
 
-pub fn is_doc_comment(s: &str) -> bool {
-    (s.starts_with("///") && super::is_doc_comment(s))
-        || s.starts_with("//!")
-        || (s.starts_with("/**") && is_block_doc_comment(s))
-        || s.starts_with("/*!")
+/// The kind of a comment, as determined by its leading characters.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CommentKind {
+    /// A plain `// ...` comment
+    Line,
+    /// A plain `/* ... */` comment, including `/**/` and `/***...*/`
+    Block,
+    /// A `/// ...` or `//! ...` doc comment
+    LineDoc(ast::AttrStyle),
+    /// A `/** ... */` or `/*! ... */` doc comment
+    BlockDoc(ast::AttrStyle),
 }
 
-pub fn doc_comment_style(comment: &str) -> ast::AttrStyle {
-    assert!(is_doc_comment(comment));
-    if comment.starts_with("//!") || comment.starts_with("/*!") {
+impl CommentKind {
+    pub fn is_doc(self) -> bool {
+        match self {
+            CommentKind::LineDoc(..) | CommentKind::BlockDoc(..) => true,
+            CommentKind::Line | CommentKind::Block => false,
+        }
+    }
+
+    pub fn is_inner(self) -> bool {
+        self.doc_style() == Some(ast::AttrStyle::Inner)
+    }
+
+    pub fn is_block(self) -> bool {
+        match self {
+            CommentKind::Block | CommentKind::BlockDoc(..) => true,
+            CommentKind::Line | CommentKind::LineDoc(..) => false,
+        }
+    }
+
+    /// The attribute style a doc comment desugars to, or `None` for plain comments.
+    pub fn doc_style(self) -> Option<ast::AttrStyle> {
+        match self {
+            CommentKind::LineDoc(style) | CommentKind::BlockDoc(style) => Some(style),
+            CommentKind::Line | CommentKind::Block => None,
+        }
+    }
+}
+
+/// Classifies the full text of a comment, delimiters included.
+///
+/// Panics if `comment` does not start with `//` or `/*`.
+pub fn classify(comment: &str) -> CommentKind {
+    let inner = comment.starts_with("//!") || comment.starts_with("/*!");
+    let style = if inner {
         ast::AttrStyle::Inner
     } else {
         ast::AttrStyle::Outer
+    };
+    if comment.starts_with("//") {
+        if super::is_doc_comment(comment) {
+            CommentKind::LineDoc(style)
+        } else {
+            CommentKind::Line
+        }
+    } else if comment.starts_with("/*") {
+        if is_block_doc_comment(comment) {
+            CommentKind::BlockDoc(style)
+        } else {
+            CommentKind::Block
+        }
+    } else {
+        panic!("not a comment: {}", comment);
+    }
+}
+
+pub fn is_doc_comment(s: &str) -> bool {
+    (s.starts_with("//") || s.starts_with("/*")) && classify(s).is_doc()
+}
+
+pub fn doc_comment_style(comment: &str) -> ast::AttrStyle {
+    match classify(comment).doc_style() {
+        Some(style) => style,
+        None => panic!("not a doc-comment: {}", comment),
     }
 }
 
@@ -71,42 +134,55 @@ pub fn strip_doc_comment_decoration(comment: &str) -> String {
     }
 
     /// remove a "[ \t]*\*" block from each line, if possible
-    fn horizontal_trim(lines: Vec<String>) -> Vec<String> {
+    fn horizontal_trim(lines: &[String]) -> Option<Vec<String>> {
         let mut i = usize::MAX;
-        let mut can_trim = true;
         let mut first = true;
-        for line in &lines {
+        for line in lines {
             for (j, c) in line.chars().enumerate() {
                 if j > i || !"* \t".contains(c) {
-                    can_trim = false;
-                    break;
+                    return None;
                 }
                 if c == '*' {
                     if first {
                         i = j;
                         first = false;
                     } else if i != j {
-                        can_trim = false;
+                        return None;
                     }
                     break;
                 }
             }
             if i > line.len() {
-                can_trim = false;
-            }
-            if !can_trim {
-                break;
+                return None;
             }
         }
 
-        if can_trim {
+        Some(
             lines
                 .iter()
                 .map(|line| (&line[i + 1..line.len()]).to_string())
-                .collect()
-        } else {
-            lines
-        }
+                .collect(),
+        )
+    }
+
+    /// remove the whitespace prefix shared by all non-blank lines
+    fn unindent(lines: &[String]) -> Vec<String> {
+        let indent = lines
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.len() - line.trim_start_matches(|c| c == ' ' || c == '\t').len())
+            .min()
+            .unwrap_or(0);
+        lines
+            .iter()
+            .map(|line| {
+                if line.len() >= indent {
+                    line[indent..].to_string()
+                } else {
+                    String::new()
+                }
+            })
+            .collect()
     }
 
     // one-line comments lose their prefix
@@ -123,8 +199,27 @@ pub fn strip_doc_comment_decoration(comment: &str) -> String {
             .map(|s| s.to_string())
             .collect::<Vec<String>>();
 
+        // text on the same line as the opening `/**` is kept as written,
+        // only the lines after it are decorated or indented
+        let multiline = lines.len() > 1;
+        let skip =
+            if multiline && !lines[0].trim().is_empty() && !lines[0].chars().all(|c| c == '*') {
+                1
+            } else {
+                0
+            };
+
         let lines = vertical_trim(lines);
-        let lines = horizontal_trim(lines);
+        let rest = match horizontal_trim(&lines[skip..]) {
+            Some(rest) => rest,
+            None if lines.len() - skip > 1 => unindent(&lines[skip..]),
+            None => lines[skip..].to_vec(),
+        };
+        let lines = lines[..skip]
+            .iter()
+            .cloned()
+            .chain(rest)
+            .collect::<Vec<_>>();
 
         return lines.join("\n");
     }
@@ -235,13 +330,20 @@ fn read_block_comment(rdr: &mut StringReader, code_to_the_left: bool, comments:
 
     // doc-comments are not really comments, they are attributes
     if (rdr.ch_is('*') && !rdr.nextch_is('*')) || rdr.ch_is('!') {
-        while !(rdr.ch_is('*') && rdr.nextch_is('/')) && !rdr.is_eof() {
-            curr_line.push(rdr.ch.unwrap());
-            rdr.bump();
-        }
-        if !rdr.is_eof() {
-            curr_line.push_str("*/");
-            rdr.bump();
+        // doc-comments nest just like ordinary block comments
+        let mut level: isize = 1;
+        while level > 0 && !rdr.is_eof() {
+            if rdr.ch_is('/') && rdr.nextch_is('*') {
+                level += 1;
+                curr_line.push_str("/*");
+                rdr.bump();
+            } else if rdr.ch_is('*') && rdr.nextch_is('/') {
+                level -= 1;
+                curr_line.push_str("*/");
+                rdr.bump();
+            } else {
+                curr_line.push(rdr.ch.unwrap());
+            }
             rdr.bump();
         }
         if is_block_doc_comment(&curr_line[..]) {
@@ -338,7 +440,7 @@ pub fn gather_comments_and_literals(
     srdr.read_to_end(&mut src).unwrap();
     let src = String::from_utf8(src).unwrap();
     let cm = CodeMap::new(sess.codemap().path_mapping().clone());
-    let filemap = cm.new_filemap(path, src);
+    let filemap = cm.new_filemap(path.into(), src);
     let mut rdr = lexer::StringReader::new_raw(sess, filemap);
 
     let mut comments: Vec<Comment> = Vec::new();
@@ -414,7 +516,7 @@ mod tests {
     fn test_block_doc_comment_3() {
         let comment = "/**\n let a: *i32;\n *a = 5;\n*/";
         let stripped = strip_doc_comment_decoration(comment);
-        assert_eq!(stripped, " let a: *i32;\n *a = 5;");
+        assert_eq!(stripped, "let a: *i32;\n*a = 5;");
     }
 
     #[test]
@@ -424,6 +526,69 @@ mod tests {
         assert_eq!(stripped, " test");
     }
 
+    #[test]
+    fn test_block_doc_comment_inline_first_line() {
+        let comment = "/** Test\n * Test\n *   Test\n */";
+        let stripped = strip_doc_comment_decoration(comment);
+        assert_eq!(stripped, " Test\n Test\n   Test");
+    }
+
+    #[test]
+    fn test_block_doc_comment_common_indent() {
+        let comment = "/**\n        Test\n\n          Test\n    */";
+        let stripped = strip_doc_comment_decoration(comment);
+        assert_eq!(stripped, "Test\n\n  Test");
+    }
+
+    #[test]
+    fn test_block_doc_comment_nested() {
+        let stripped = strip_doc_comment_decoration("/** outer /* inner */ */");
+        assert_eq!(stripped, " outer /* inner */ ");
+    }
+
+    #[test]
+    fn test_block_doc_comment_crlf() {
+        let comment = "/**\r\n * Test\r\n *  Test\r\n */";
+        let stripped = strip_doc_comment_decoration(comment);
+        assert_eq!(stripped, " Test\n  Test");
+    }
+
+    #[test]
+    fn test_classify() {
+        use crate::ast::AttrStyle::{Inner, Outer};
+
+        assert_eq!(classify("// test"), CommentKind::Line);
+        assert_eq!(classify("//// test"), CommentKind::Line);
+        assert_eq!(classify("/// test"), CommentKind::LineDoc(Outer));
+        assert_eq!(classify("//! test"), CommentKind::LineDoc(Inner));
+        assert_eq!(classify("/* test */"), CommentKind::Block);
+        assert_eq!(classify("/**/"), CommentKind::Block);
+        assert_eq!(classify("/*** test */"), CommentKind::Block);
+        assert_eq!(classify("/** test */"), CommentKind::BlockDoc(Outer));
+        assert_eq!(classify("/*! test */"), CommentKind::BlockDoc(Inner));
+
+        assert!(classify("//! test").is_inner());
+        assert!(!classify("/** test */").is_inner());
+        assert!(classify("/*! test */").is_block());
+        assert!(!classify("/// test").is_block());
+        assert!(!classify("/* test */").is_doc());
+    }
+
+    #[test]
+    fn test_gather_nested_block_doc_comment() {
+        let sess = ParseSess::new(crate::codemap::FilePathMapping::empty());
+        let src = "/** outer /* inner */ */\nfn f() {} /* a /* b */ */\n";
+        let (comments, literals) =
+            gather_comments_and_literals(&sess, "a".to_string(), &mut src.as_bytes());
+        assert!(literals.is_empty());
+        let lines = comments
+            .iter()
+            .filter(|c| c.style != BlankLine)
+            .map(|c| c.lines.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, [vec!["/* a /* b */ */".to_string()]]);
+    }
+
     #[test]
     fn test_line_doc_comment() {
         let stripped = strip_doc_comment_decoration("/// test");
@@ -1,14 +1,15 @@
 use crate::ast::{self, Ident};
 use crate::codemap::{CodeMap, FilePathMapping};
-use crate::errors::{DiagnosticBuilder, FatalError};
-use crate::parse::{token, ParseSess};
+use crate::errors::{DiagnosticBuilder, FatalError, Handler};
+use crate::parse::{c_str_lit, token, PResult, ParseSess};
 use crate::str::char_at;
-use crate::symbol::{keywords, Symbol};
-use crate::syntax_pos::{self, BytePos, CharPos, Pos, Span, NO_EXPANSION};
+use crate::symbol::Symbol;
+use crate::syntax_pos::{self, BytePos, CharPos, MultiByteChar, Pos, Span, NO_EXPANSION};
 
 use std::borrow::Cow;
+use std::cell::Cell;
 use std::char;
-use std::mem::replace;
+use std::mem::{self, replace};
 use std::rc::Rc;
 
 use log::debug;
@@ -18,6 +19,10 @@ pub mod comments;
 mod tokentrees;
 mod unicode_chars;
 
+/// Number of source bytes the lexer may scan before flushing the line starts
+/// and multibyte chars it has found into its `FileMap`.
+const LINE_INFO_CHUNK_SIZE: u32 = 4096;
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct TokenAndSpan {
     pub tok: token::Token,
@@ -50,6 +55,12 @@ pub struct StringReader<'a> {
     /// This is only necessary the first time a filemap is lexed.
     /// If part of a filemap is being re-lexed, this should be set to false.
     pub save_new_lines_and_multibyte: bool,
+    /// Line starts found since the last flush into `filemap`.
+    pending_lines: Cell<Vec<BytePos>>,
+    /// Multibyte chars found since the last flush into `filemap`.
+    pending_multibyte_chars: Cell<Vec<MultiByteChar>>,
+    /// The position up to which line info has been flushed into `filemap`.
+    flushed_pos: Cell<BytePos>,
     // cached:
     pub peek_tok: token::Token,
     pub peek_span: Span,
@@ -57,12 +68,21 @@ pub struct StringReader<'a> {
     // cache a direct reference to the source text, so that we don't have to
     // retrieve it via `self.filemap.src.as_ref().unwrap()` all the time.
     source_text: Rc<String>,
+    /// The number of tokens returned by `real_token` and the like, not
+    /// counting `Eof`.
+    pub real_tokens: usize,
     /// Stack of open delimiters and their spans. Used for error message.
     token: token::Token,
     span: Span,
     open_braces: Vec<(token::DelimToken, Span)>,
 }
 
+impl<'a> Drop for StringReader<'a> {
+    fn drop(&mut self) {
+        self.flush_line_info();
+    }
+}
+
 fn mk_sp(lo: BytePos, hi: BytePos) -> Span {
     Span {
         lo: lo,
@@ -95,6 +115,9 @@ impl<'a> StringReader<'a> {
                 _ => break,
             }
         }
+        if t.tok != token::Eof {
+            self.real_tokens += 1;
+        }
         self.token = t.tok.clone();
         self.span = t.sp;
         Ok(t)
@@ -103,6 +126,11 @@ impl<'a> StringReader<'a> {
         let res = self.try_real_token();
         self.unwrap_or_abort(res)
     }
+    /// Like `real_token`, but returns a fatal lexing error instead of
+    /// emitting it and aborting.
+    pub(crate) fn maybe_real_token(&mut self) -> PResult<'a, TokenAndSpan> {
+        self.try_real_token().map_err(|()| self.take_fatal_error())
+    }
     fn is_eof(&self) -> bool {
         if self.ch.is_none() {
             return true;
@@ -132,6 +160,16 @@ impl<'a> StringReader<'a> {
         }
         self.fatal_errs.clear();
     }
+    /// Take the fatal error that stopped the lexer, emitting any that were
+    /// buffered before it.
+    fn take_fatal_error(&mut self) -> DiagnosticBuilder<'a> {
+        let mut errs = mem::take(&mut self.fatal_errs);
+        let err = errs.pop().expect("lexer failed without a fatal error");
+        for mut err in errs {
+            err.emit();
+        }
+        err
+    }
     pub fn peek(&self) -> TokenAndSpan {
         // FIXME(pcwalton): Bad copy!
         TokenAndSpan {
@@ -158,6 +196,7 @@ impl<'a> StringReader<'a> {
         }
 
         let source_text = (*filemap.src.as_ref().unwrap()).clone();
+        let start_pos = filemap.start_pos;
 
         StringReader {
             sess: sess,
@@ -168,6 +207,9 @@ impl<'a> StringReader<'a> {
             filemap: filemap,
             terminator: None,
             save_new_lines_and_multibyte: true,
+            pending_lines: Cell::new(Vec::new()),
+            pending_multibyte_chars: Cell::new(Vec::new()),
+            flushed_pos: Cell::new(start_pos),
             // dummy values; not read
             peek_tok: token::Eof,
             peek_span: syntax_pos::DUMMY_SP,
@@ -176,6 +218,7 @@ impl<'a> StringReader<'a> {
             token: token::Eof,
             span: syntax_pos::DUMMY_SP,
             open_braces: Vec::new(),
+            real_tokens: 0,
         }
     }
 
@@ -188,6 +231,16 @@ impl<'a> StringReader<'a> {
         sr
     }
 
+    /// Like `new`, but returns a fatal error in the first token instead of
+    /// emitting it and aborting.
+    pub fn maybe_new(sess: &'a ParseSess, filemap: Rc<syntax_pos::FileMap>) -> PResult<'a, Self> {
+        let mut sr = StringReader::new_raw(sess, filemap);
+        match sr.advance_token() {
+            Ok(()) => Ok(sr),
+            Err(()) => Err(sr.take_fatal_error()),
+        }
+    }
+
     pub fn retokenize(sess: &'a ParseSess, mut span: Span) -> Self {
         let begin = sess.codemap().lookup_byte_offset(span.lo);
         let end = sess.codemap().lookup_byte_offset(span.hi);
@@ -217,19 +270,44 @@ impl<'a> StringReader<'a> {
         self.ch == Some(c)
     }
 
+    /// Flush the line starts and multibyte chars recorded so far into the
+    /// `FileMap`, so that positions already scanned can be looked up.
+    fn flush_line_info(&self) {
+        let lines = self.pending_lines.take();
+        if !lines.is_empty() {
+            self.filemap.next_lines(&lines);
+        }
+        let multibyte_chars = self.pending_multibyte_chars.take();
+        if !multibyte_chars.is_empty() {
+            self.filemap.record_multibyte_chars(&multibyte_chars);
+        }
+        self.flushed_pos.set(self.pos);
+    }
+
+    /// The diagnostic handler, after flushing pending line info so that any
+    /// span reported through it can be rendered.
+    fn diagnostic(&self) -> &'a Handler {
+        self.flush_line_info();
+        &self.sess.span_diagnostic
+    }
+
     /// Report a fatal lexical error with a given span.
     pub fn fatal_span(&self, sp: Span, m: &str) -> FatalError {
-        self.sess.span_diagnostic.span_fatal(sp, m)
+        self.diagnostic().span_fatal(sp, m)
     }
 
     /// Report a lexical error with a given span.
     pub fn err_span(&self, sp: Span, m: &str) {
-        self.sess.span_diagnostic.span_err(sp, m)
+        self.diagnostic().span_err(sp, m)
     }
 
-    /// Report a fatal error spanning [`from_pos`, `to_pos`).
-    fn fatal_span_(&self, from_pos: BytePos, to_pos: BytePos, m: &str) -> FatalError {
-        self.fatal_span(mk_sp(from_pos, to_pos), m)
+    /// Buffer a fatal error spanning [`from_pos`, `to_pos`) and fail.
+    fn fail_span_<T>(&mut self, from_pos: BytePos, to_pos: BytePos, m: &str) -> Result<T, ()> {
+        let err = self
+            .diagnostic()
+            .struct_span_fatal(mk_sp(from_pos, to_pos), m);
+        self.fatal_errs.push(err);
+        Err(())
     }
 
     /// Report a lexical error spanning [`from_pos`, `to_pos`).
@@ -237,15 +315,18 @@ impl<'a> StringReader<'a> {
         self.err_span(mk_sp(from_pos, to_pos), m)
     }
 
-    /// Report a lexical error spanning [`from_pos`, `to_pos`), appending an
-    /// escaped character to the error message
-    fn fatal_span_char(&self, from_pos: BytePos, to_pos: BytePos, m: &str, c: char) -> FatalError {
-        let mut m = m.to_string();
-        m.push_str(": ");
-        for c in c.escape_default() {
-            m.push(c)
-        }
-        self.fatal_span_(from_pos, to_pos, &m[..])
+    /// Buffer a fatal error spanning [`from_pos`, `to_pos`), appending an
+    /// escaped character to the error message, and fail.
+    fn fail_span_char<T>(
+        &mut self,
+        from_pos: BytePos,
+        to_pos: BytePos,
+        m: &str,
+        c: char,
+    ) -> Result<T, ()> {
+        let err = self.struct_fatal_span_char(from_pos, to_pos, m, c);
+        self.fatal_errs.push(err);
+        Err(())
     }
     fn struct_fatal_span_char(
         &self,
@@ -259,8 +340,7 @@ impl<'a> StringReader<'a> {
         for c in c.escape_default() {
             m.push(c)
         }
-        self.sess
-            .span_diagnostic
+        self.diagnostic()
             .struct_span_fatal(mk_sp(from_pos, to_pos), &m[..])
     }
 
@@ -286,25 +366,29 @@ impl<'a> StringReader<'a> {
         for c in c.escape_default() {
             m.push(c)
         }
-        self.sess
-            .span_diagnostic
+        self.diagnostic()
             .struct_span_err(mk_sp(from_pos, to_pos), &m[..])
     }
 
-    /// Report a lexical error spanning [`from_pos`, `to_pos`), appending the
-    /// offending string to the error message
-    fn fatal_span_verbose(&self, from_pos: BytePos, to_pos: BytePos, mut m: String) -> FatalError {
+    /// Buffer a fatal error spanning [`from_pos`, `to_pos`), appending the
+    /// offending string to the error message, and fail.
+    fn fail_span_verbose<T>(
+        &mut self,
+        from_pos: BytePos,
+        to_pos: BytePos,
+        mut m: String,
+    ) -> Result<T, ()> {
         m.push_str(": ");
         let from = self.byte_offset(from_pos).to_usize();
         let to = self.byte_offset(to_pos).to_usize();
         m.push_str(&self.source_text[from..to]);
-        self.fatal_span_(from_pos, to_pos, &m[..])
+        self.fail_span_(from_pos, to_pos, &m[..])
     }
 
     /// Advance peek_tok and peek_span to refer to the next token, and
     /// possibly update the interner.
     fn advance_token(&mut self) -> Result<(), ()> {
-        match self.scan_whitespace_or_comment() {
+        match self.scan_whitespace_or_comment()? {
             Some(comment) => {
                 self.peek_span = comment.sp;
                 self.peek_tok = comment.tok;
@@ -320,6 +404,9 @@ impl<'a> StringReader<'a> {
                 };
             }
         }
+        if self.pos - self.flushed_pos.get() >= BytePos(LINE_INFO_CHUNK_SIZE) || self.is_eof() {
+            self.flush_line_info();
+        }
         Ok(())
     }
 
@@ -425,19 +512,21 @@ impl<'a> StringReader<'a> {
 
             self.ch = Some(new_ch);
             self.pos = new_pos;
-            self.next_pos = new_pos + Pos::from_usize(new_ch_len);
+            self.next_pos = new_pos + BytePos::from_usize(new_ch_len);
             if old_ch_is_newline {
                 if self.save_new_lines_and_multibyte {
-                    self.filemap.next_line(self.pos);
+                    self.pending_lines.get_mut().push(self.pos);
                 }
                 self.col = CharPos(0);
             } else {
                 self.col = self.col + CharPos(1);
             }
-            if new_ch_len > 1 {
-                if self.save_new_lines_and_multibyte {
-                    self.filemap.record_multibyte_char(self.pos, new_ch_len);
-                }
+            if new_ch_len > 1 && self.save_new_lines_and_multibyte {
+                assert!(new_ch_len <= 4);
+                self.pending_multibyte_chars.get_mut().push(MultiByteChar {
+                    pos: self.pos,
+                    bytes: new_ch_len,
+                });
             }
         } else {
             self.ch = None;
@@ -497,13 +586,11 @@ impl<'a> StringReader<'a> {
 
     /// PRECONDITION: self.ch is not whitespace
     /// Eats any kind of comment.
-    fn scan_comment(&mut self) -> Option<TokenAndSpan> {
+    fn scan_comment(&mut self) -> Result<Option<TokenAndSpan>, ()> {
         if let Some(c) = self.ch {
             if c.is_whitespace() {
                 let msg = "called consume_any_line_comment, but there was whitespace";
-                self.sess
-                    .span_diagnostic
-                    .span_err(mk_sp(self.pos, self.pos), msg);
+                self.diagnostic().span_err(mk_sp(self.pos, self.pos), msg);
             }
         }
 
@@ -537,7 +624,7 @@ impl<'a> StringReader<'a> {
                         self.bump();
                     }
 
-                    if doc_comment {
+                    Ok(if doc_comment {
                         self.with_str_from(start_bpos, |string| {
                             // comments with only more "/"s are not doc comments
                             let tok = if is_doc_comment(string) {
@@ -556,24 +643,25 @@ impl<'a> StringReader<'a> {
                             tok: token::Comment,
                             sp: mk_sp(start_bpos, self.pos),
                         })
-                    }
+                    })
                 }
                 Some('*') => {
                     self.bump();
                     self.bump();
                     self.scan_block_comment()
                 }
-                _ => None,
+                _ => Ok(None),
             }
         } else if self.ch_is('#') {
             if self.nextch_is('!') {
                 // Parse an inner attribute.
                 if self.nextnextch_is('[') {
-                    return None;
+                    return Ok(None);
                 }
 
                 // I guess this is the only way to figure out if
                 // we're at the beginning of the file...
+                self.flush_line_info();
                 let cmap = CodeMap::new(FilePathMapping::empty());
                 cmap.files.borrow_mut().push(self.filemap.clone());
                 let loc = cmap.lookup_char_pos_adj(self.pos);
@@ -584,28 +672,28 @@ impl<'a> StringReader<'a> {
                     while !self.ch_is('\n') && !self.is_eof() {
                         self.bump();
                     }
-                    return Some(TokenAndSpan {
+                    return Ok(Some(TokenAndSpan {
                         tok: token::Shebang(self.name_from(start)),
                         sp: mk_sp(start, self.pos),
-                    });
+                    }));
                 }
             }
-            None
+            Ok(None)
         } else {
-            None
+            Ok(None)
         }
     }
 
     /// If there is whitespace, shebang, or a comment, scan it. Otherwise,
     /// return None.
-    fn scan_whitespace_or_comment(&mut self) -> Option<TokenAndSpan> {
+    fn scan_whitespace_or_comment(&mut self) -> Result<Option<TokenAndSpan>, ()> {
         match self.ch.unwrap_or('\0') {
             // # to handle shebang at start of file -- this is the entry point
             // for skipping over all "junk"
             '/' | '#' => {
-                let c = self.scan_comment();
+                let c = self.scan_comment()?;
                 debug!("scanning a comment {:?}", c);
-                c
+                Ok(c)
             }
             c if is_pattern_whitespace(Some(c)) => {
                 let start_bpos = self.pos;
@@ -617,14 +705,14 @@ impl<'a> StringReader<'a> {
                     sp: mk_sp(start_bpos, self.pos),
                 });
                 debug!("scanning whitespace: {:?}", c);
-                c
+                Ok(c)
             }
-            _ => None,
+            _ => Ok(None),
         }
     }
 
     /// Might return a sugared-doc-attr
-    fn scan_block_comment(&mut self) -> Option<TokenAndSpan> {
+    fn scan_block_comment(&mut self) -> Result<Option<TokenAndSpan>, ()> {
         // block comments starting with "/**" or "/*!" are doc-comments
         let is_doc_comment = self.ch_is('*') || self.ch_is('!');
         let start_bpos = self.pos - BytePos(2);
@@ -639,7 +727,7 @@ impl<'a> StringReader<'a> {
                     "unterminated block comment"
                 };
                 let last_bpos = self.pos;
-                panic!(self.fatal_span_(start_bpos, last_bpos, msg));
+                return self.fail_span_(start_bpos, last_bpos, msg);
             }
             let n = self.ch.unwrap();
             match n {
@@ -659,7 +747,7 @@ impl<'a> StringReader<'a> {
             self.bump();
         }
 
-        self.with_str_from(start_bpos, |string| {
+        Ok(self.with_str_from(start_bpos, |string| {
             // but comments with only "*"s between two "/"s are not
             let tok = if is_block_doc_comment(string) {
                 let string = if has_cr {
@@ -680,7 +768,7 @@ impl<'a> StringReader<'a> {
                 tok: tok,
                 sp: mk_sp(start_bpos, self.pos),
             })
-        })
+        }))
     }
 
     /// Scan through any digits (base `scan_radix`) or underscores,
@@ -759,7 +847,12 @@ impl<'a> StringReader<'a> {
         }
 
         if num_digits == 0 {
-            self.err_span_(start_bpos, self.pos, "no valid digits found for number");
+            // Only a base prefix can be followed by no digits at all.
+            self.err_span_(
+                start_bpos,
+                start_bpos + 2,
+                "missing digits after base prefix",
+            );
             return token::Integer(Symbol::intern("0"));
         }
 
@@ -773,6 +866,11 @@ impl<'a> StringReader<'a> {
             if self.ch.unwrap_or('\0').is_digit(10) {
                 self.scan_digits(10, 10);
                 self.scan_float_exponent();
+                // `1.2.3` is lexed as `1.2` `.` `3`, which parses as a field
+                // access, so there is no need to consume the rest.
+                if self.ch_is('.') && self.nextch().is_some_and(|c| c.is_ascii_digit()) {
+                    self.err_span_(self.pos, self.next_pos, "unexpected second decimal point");
+                }
             }
             let pos = self.pos;
             self.check_float_base(start_bpos, pos, base);
@@ -792,7 +890,12 @@ impl<'a> StringReader<'a> {
 
     /// Scan over `n_digits` hex digits, stopping at `delim`, reporting an
     /// error if too many or too few digits are encountered.
-    fn scan_hex_digits(&mut self, n_digits: usize, delim: char, below_0x7f_only: bool) -> bool {
+    fn scan_hex_digits(
+        &mut self,
+        n_digits: usize,
+        delim: char,
+        below_0x7f_only: bool,
+    ) -> Result<bool, ()> {
         debug!("scanning {} digits until {:?}", n_digits, delim);
         let start_bpos = self.pos;
         let mut accum_int = 0;
@@ -801,11 +904,11 @@ impl<'a> StringReader<'a> {
         for _ in 0..n_digits {
             if self.is_eof() {
                 let last_bpos = self.pos;
-                panic!(self.fatal_span_(
+                return self.fail_span_(
                     start_bpos,
                     last_bpos,
-                    "unterminated numeric character escape"
-                ));
+                    "unterminated numeric character escape",
+                );
             }
             if self.ch_is(delim) {
                 let last_bpos = self.pos;
@@ -844,11 +947,11 @@ impl<'a> StringReader<'a> {
         }
 
         match char::from_u32(accum_int) {
-            Some(_) => valid,
+            Some(_) => Ok(valid),
             None => {
                 let last_bpos = self.pos;
                 self.err_span_(start_bpos, last_bpos, "invalid numeric character escape");
-                false
+                Ok(false)
             }
         }
     }
@@ -864,7 +967,7 @@ impl<'a> StringReader<'a> {
         first_source_char: char,
         ascii_only: bool,
         delim: char,
-    ) -> bool {
+    ) -> Result<bool, ()> {
         match first_source_char {
             '\\' => {
                 // '\X' for some X must be a character constant:
@@ -875,15 +978,14 @@ impl<'a> StringReader<'a> {
                     None => {} // EOF here is an error that will be checked later.
                     Some(e) => {
                         return match e {
-                            'n' | 'r' | 't' | '\\' | '\'' | '"' | '0' => true,
+                            'n' | 'r' | 't' | '\\' | '\'' | '"' | '0' => Ok(true),
                             'x' => self.scan_byte_escape(delim, !ascii_only),
                             'u' => {
                                 let valid = if self.ch_is('{') {
-                                    self.scan_unicode_escape(delim) && !ascii_only
+                                    self.scan_unicode_escape(delim)? && !ascii_only
                                 } else {
                                     let span = mk_sp(start, self.pos);
-                                    self.sess
-                                        .span_diagnostic
+                                    self.diagnostic()
                                         .struct_span_err(span, "incorrect unicode escape sequence")
                                         .span_help(
                                             span,
@@ -901,15 +1003,15 @@ impl<'a> StringReader<'a> {
                                                     byte or in a byte string",
                                     );
                                 }
-                                valid
+                                Ok(valid)
                             }
                             '\n' if delim == '"' => {
                                 self.consume_whitespace();
-                                true
+                                Ok(true)
                             }
                             '\r' if delim == '"' && self.ch_is('\n') => {
                                 self.consume_whitespace();
-                                true
+                                Ok(true)
                             }
                             c => {
                                 let pos = self.pos;
@@ -940,7 +1042,7 @@ impl<'a> StringReader<'a> {
                                     );
                                 }
                                 err.emit();
-                                false
+                                Ok(false)
                             }
                         }
                     }
@@ -958,19 +1060,19 @@ impl<'a> StringReader<'a> {
                     },
                     first_source_char,
                 );
-                return false;
+                return Ok(false);
             }
             '\r' => {
                 if self.ch_is('\n') {
                     self.bump();
-                    return true;
+                    return Ok(true);
                 } else {
                     self.err_span_(
                         start,
                         self.pos,
                         "bare CR not allowed in string, use \\r instead",
                     );
-                    return false;
+                    return Ok(false);
                 }
             }
             _ => {
@@ -982,18 +1084,18 @@ impl<'a> StringReader<'a> {
                         "byte constant must be ASCII. Use a \\xHH escape for a \
                                     non-ASCII byte",
                     );
-                    return false;
+                    return Ok(false);
                 }
             }
         }
-        true
+        Ok(true)
     }
 
     /// Scan over a \u{...} escape
     ///
     /// At this point, we have already seen the \ and the u, the { is the current character. We
     /// will read at least one digit, and up to 6, and pass over the }.
-    fn scan_unicode_escape(&mut self, delim: char) -> bool {
+    fn scan_unicode_escape(&mut self, delim: char) -> Result<bool, ()> {
         self.bump(); // past the {
         let start_bpos = self.pos;
         let mut count = 0;
@@ -1004,32 +1106,34 @@ impl<'a> StringReader<'a> {
             let c = match self.ch {
                 Some(c) => c,
                 None => {
-                    panic!(self.fatal_span_(
+                    return self.fail_span_(
                         start_bpos,
                         self.pos,
-                        "unterminated unicode escape (found EOF)"
-                    ));
+                        "unterminated unicode escape (found EOF)",
+                    );
                 }
             };
             accum_int *= 16;
-            accum_int += c.to_digit(16).unwrap_or_else(|| {
-                if c == delim {
-                    panic!(self.fatal_span_(
+            accum_int += match c.to_digit(16) {
+                Some(digit) => digit,
+                None if c == delim => {
+                    return self.fail_span_(
                         self.pos,
                         self.next_pos,
-                        "unterminated unicode escape (needed a `}`)"
-                    ));
-                } else {
+                        "unterminated unicode escape (needed a `}`)",
+                    );
+                }
+                None => {
                     self.err_span_char(
                         self.pos,
                         self.next_pos,
                         "invalid character in unicode escape",
                         c,
                     );
+                    valid = false;
+                    0
                 }
-                valid = false;
-                0
-            });
+            };
             self.bump();
             count += 1;
         }
@@ -1049,20 +1153,22 @@ impl<'a> StringReader<'a> {
         }
 
         self.bump(); // past the ending }
-        valid
+        Ok(valid)
     }
 
     /// Scan over a float exponent.
     fn scan_float_exponent(&mut self) {
         if self.ch_is('e') || self.ch_is('E') {
+            let start_bpos = self.pos;
             self.bump();
             if self.ch_is('-') || self.ch_is('+') {
                 self.bump();
             }
+            let sign_end = self.pos;
             if self.scan_digits(10, 10) == 0 {
                 self.err_span_(
-                    self.pos,
-                    self.next_pos,
+                    start_bpos,
+                    sign_end,
                     "expected at least one digit in exponent",
                 )
             }
@@ -1109,14 +1215,18 @@ impl<'a> StringReader<'a> {
         if ident_start(c)
             && match (c.unwrap(), self.nextch(), self.nextnextch()) {
                 // Note: r as in r" or r#" is part of a raw string literal,
-                // b as in b' is part of a byte literal.
+                // b as in b' is part of a byte literal, and c as in c" is part
+                // of a C string literal.
                 // They are not identifiers, and are handled further down.
                 ('r', Some('"'), _)
                 | ('r', Some('#'), _)
                 | ('b', Some('"'), _)
                 | ('b', Some('\''), _)
                 | ('b', Some('r'), Some('"'))
-                | ('b', Some('r'), Some('#')) => false,
+                | ('b', Some('r'), Some('#'))
+                | ('c', Some('"'), _)
+                | ('c', Some('r'), Some('"'))
+                | ('c', Some('r'), Some('#')) => false,
                 _ => true,
             }
         {
@@ -1124,6 +1234,9 @@ impl<'a> StringReader<'a> {
             while ident_continue(self.ch) {
                 self.bump();
             }
+            if is_unknown_char(self.ch) {
+                self.scan_unknown_chars();
+            }
 
             return Ok(self.with_str_from(start, |string| {
                 if string == "_" {
@@ -1290,11 +1403,11 @@ impl<'a> StringReader<'a> {
                     // lifetimes shouldn't end with a single quote
                     // if we find one, then this is an invalid character literal
                     if self.ch_is('\'') {
-                        panic!(self.fatal_span_verbose(
+                        return self.fail_span_verbose(
                             start_with_quote,
                             self.next_pos,
-                            String::from("character literal may only contain one codepoint")
-                        ));
+                            String::from("character literal may only contain one codepoint"),
+                        );
                     }
 
                     // Include the leading `'` in the real identifier, for macro
@@ -1304,17 +1417,8 @@ impl<'a> StringReader<'a> {
                         Ident::from_str(&format!("'{}", lifetime_name))
                     });
 
-                    // Conjure up a "keyword checking ident" to make sure that
-                    // the lifetime name is not a keyword.
-                    let keyword_checking_ident =
-                        self.with_str_from(start, |lifetime_name| Ident::from_str(lifetime_name));
-                    let keyword_checking_token = &token::Ident(keyword_checking_ident);
-                    let last_bpos = self.pos;
-                    if keyword_checking_token.is_any_keyword()
-                        && !keyword_checking_token.is_keyword(keywords::Static)
-                    {
-                        self.err_span_(start, last_bpos, "lifetimes cannot use keyword names");
-                    }
+                    // Keyword names are rejected by the parser, which knows
+                    // whether this is a lifetime or a label.
 
                     return Ok(token::Lifetime(ident));
                 }
@@ -1322,14 +1426,14 @@ impl<'a> StringReader<'a> {
                 let valid = self.scan_char_or_byte(
                     start, c2, // ascii_only =
                     false, '\'',
-                );
+                )?;
 
                 if !self.ch_is('\'') {
-                    panic!(self.fatal_span_verbose(
+                    return self.fail_span_verbose(
                         start_with_quote,
                         self.pos,
-                        String::from("character literal may only contain one codepoint")
-                    ));
+                        String::from("character literal may only contain one codepoint"),
+                    );
                 }
 
                 let id = if valid {
@@ -1344,9 +1448,19 @@ impl<'a> StringReader<'a> {
             'b' => {
                 self.bump();
                 let lit = match self.ch {
-                    Some('\'') => self.scan_byte(),
-                    Some('"') => self.scan_byte_string(),
-                    Some('r') => self.scan_raw_byte_string(),
+                    Some('\'') => self.scan_byte()?,
+                    Some('"') => self.scan_byte_string()?,
+                    Some('r') => self.scan_raw_byte_string()?,
+                    _ => unreachable!(), // Should have been a token::Ident above.
+                };
+                let suffix = self.scan_optional_raw_name();
+                Ok(token::Literal(lit, suffix))
+            }
+            'c' => {
+                self.bump();
+                let lit = match self.ch {
+                    Some('"') => self.scan_c_string()?,
+                    Some('r') => self.scan_raw_c_string()?,
                     _ => unreachable!(), // Should have been a token::Ident above.
                 };
                 let suffix = self.scan_optional_raw_name();
@@ -1359,11 +1473,11 @@ impl<'a> StringReader<'a> {
                 while !self.ch_is('"') {
                     if self.is_eof() {
                         let last_bpos = self.pos;
-                        panic!(self.fatal_span_(
+                        return self.fail_span_(
                             start_bpos,
                             last_bpos,
-                            "unterminated double quote string"
-                        ));
+                            "unterminated double quote string",
+                        );
                     }
 
                     let ch_start = self.pos;
@@ -1372,7 +1486,7 @@ impl<'a> StringReader<'a> {
                     valid &= self.scan_char_or_byte(
                         ch_start, ch, // ascii_only =
                         false, '"',
-                    );
+                    )?;
                 }
                 // adjust for the ASCII " at the start of the literal
                 let id = if valid {
@@ -1395,17 +1509,17 @@ impl<'a> StringReader<'a> {
 
                 if self.is_eof() {
                     let last_bpos = self.pos;
-                    panic!(self.fatal_span_(start_bpos, last_bpos, "unterminated raw string"));
+                    return self.fail_span_(start_bpos, last_bpos, "unterminated raw string");
                 } else if !self.ch_is('"') {
                     let last_bpos = self.pos;
                     let curr_char = self.ch.unwrap();
-                    panic!(self.fatal_span_char(
+                    return self.fail_span_char(
                         start_bpos,
                         last_bpos,
                         "found invalid character; only `#` is allowed \
                                                  in raw string delimitation",
-                        curr_char
-                    ));
+                        curr_char,
+                    );
                 }
                 self.bump();
                 let content_start_bpos = self.pos;
@@ -1414,7 +1528,7 @@ impl<'a> StringReader<'a> {
                 'outer: loop {
                     if self.is_eof() {
                         let last_bpos = self.pos;
-                        panic!(self.fatal_span_(start_bpos, last_bpos, "unterminated raw string"));
+                        return self.fail_span_(start_bpos, last_bpos, "unterminated raw string");
                     }
                     // if self.ch_is('"') {
                     // content_end_bpos = self.pos;
@@ -1490,6 +1604,11 @@ impl<'a> StringReader<'a> {
             '/' => Ok(self.binop(token::Slash)),
             '^' => Ok(self.binop(token::Caret)),
             '%' => Ok(self.binop(token::Percent)),
+            c if is_unknown_char(Some(c)) => {
+                let start = self.pos;
+                self.scan_unknown_chars();
+                Ok(self.with_str_from(start, |string| token::Ident(Ident::from_str(string))))
+            }
             c => {
                 let last_bpos = self.pos;
                 let bpos = self.next_pos;
@@ -1502,6 +1621,33 @@ impl<'a> StringReader<'a> {
         }
     }
 
+    /// Consume an identifier-shaped run of characters that contains non-ASCII
+    /// characters which can't appear in identifiers (e.g. emoji), reporting
+    /// the first of them. The run is then lexed as an identifier, so that
+    /// parsing can continue.
+    fn scan_unknown_chars(&mut self) {
+        let mut reported = false;
+        while !self.is_eof() {
+            match self.ch {
+                c if ident_continue(c) => {}
+                Some(c) if is_unknown_char(Some(c)) => {
+                    if !reported {
+                        let (lo, hi) = (self.pos, self.next_pos);
+                        if is_emoji(c) {
+                            let msg = format!("identifiers cannot contain emoji: `{}`", c);
+                            self.err_span_(lo, hi, &msg);
+                        } else {
+                            self.err_span_char(lo, hi, "unknown character", c);
+                        }
+                        reported = true;
+                    }
+                }
+                _ => break,
+            }
+            self.bump();
+        }
+    }
+
     fn consume_whitespace(&mut self) {
         while is_pattern_whitespace(self.ch) && !self.is_eof() {
             self.bump();
@@ -1541,7 +1687,7 @@ impl<'a> StringReader<'a> {
         (self.ch_is('#') && self.nextch_is('!') && !self.nextnextch_is('['))
     }
 
-    fn scan_byte(&mut self) -> token::Lit {
+    fn scan_byte(&mut self) -> Result<token::Lit, ()> {
         self.bump();
         let start = self.pos;
 
@@ -1552,17 +1698,17 @@ impl<'a> StringReader<'a> {
         let valid = self.scan_char_or_byte(
             start, c2, // ascii_only =
             true, '\'',
-        );
+        )?;
         if !self.ch_is('\'') {
             // Byte offsetting here is okay because the
             // character before position `start` are an
             // ascii single quote and ascii 'b'.
             let pos = self.pos;
-            panic!(self.fatal_span_verbose(
+            return self.fail_span_verbose(
                 start - BytePos(2),
                 pos,
-                "unterminated byte constant".to_string()
-            ));
+                "unterminated byte constant".to_string(),
+            );
         }
 
         let id = if valid {
@@ -1571,14 +1717,14 @@ impl<'a> StringReader<'a> {
             Symbol::intern("?")
         };
         self.bump(); // advance ch past token
-        token::Byte(id)
+        Ok(token::Byte(id))
     }
 
-    fn scan_byte_escape(&mut self, delim: char, below_0x7f_only: bool) -> bool {
+    fn scan_byte_escape(&mut self, delim: char, below_0x7f_only: bool) -> Result<bool, ()> {
         self.scan_hex_digits(2, delim, below_0x7f_only)
     }
 
-    fn scan_byte_string(&mut self) -> token::Lit {
+    fn scan_byte_string(&mut self) -> Result<token::Lit, ()> {
         self.bump();
         let start = self.pos;
         let mut valid = true;
@@ -1586,7 +1732,7 @@ impl<'a> StringReader<'a> {
         while !self.ch_is('"') {
             if self.is_eof() {
                 let pos = self.pos;
-                panic!(self.fatal_span_(start, pos, "unterminated double quote byte string"));
+                return self.fail_span_(start, pos, "unterminated double quote byte string");
             }
 
             let ch_start = self.pos;
@@ -1595,7 +1741,7 @@ impl<'a> StringReader<'a> {
             valid &= self.scan_char_or_byte(
                 ch_start, ch, // ascii_only =
                 true, '"',
-            );
+            )?;
         }
         let id = if valid {
             self.name_from(start)
@@ -1603,10 +1749,10 @@ impl<'a> StringReader<'a> {
             Symbol::intern("??")
         };
         self.bump();
-        token::ByteStr(id)
+        Ok(token::ByteStr(id))
     }
 
-    fn scan_raw_byte_string(&mut self) -> token::Lit {
+    fn scan_raw_byte_string(&mut self) -> Result<token::Lit, ()> {
         let start_bpos = self.pos;
         self.bump();
         let mut hash_count = 0;
@@ -1617,26 +1763,27 @@ impl<'a> StringReader<'a> {
 
         if self.is_eof() {
             let pos = self.pos;
-            panic!(self.fatal_span_(start_bpos, pos, "unterminated raw string"));
+            return self.fail_span_(start_bpos, pos, "unterminated raw string");
         } else if !self.ch_is('"') {
             let pos = self.pos;
             let ch = self.ch.unwrap();
-            panic!(self.fatal_span_char(
+            return self.fail_span_char(
                 start_bpos,
                 pos,
                 "found invalid character; only `#` is allowed in raw \
                                          string delimitation",
-                ch
-            ));
+                ch,
+            );
         }
         self.bump();
         let content_start_bpos = self.pos;
         let mut content_end_bpos;
+        let mut valid = true;
         'outer: loop {
             match self.ch {
                 None => {
                     let pos = self.pos;
-                    panic!(self.fatal_span_(start_bpos, pos, "unterminated raw string"))
+                    return self.fail_span_(start_bpos, pos, "unterminated raw string");
                 }
                 Some('"') => {
                     content_end_bpos = self.pos;
@@ -1648,6 +1795,15 @@ impl<'a> StringReader<'a> {
                     }
                     break;
                 }
+                Some('\r') if !self.nextch_is('\n') => {
+                    let pos = self.pos;
+                    self.err_span_(
+                        start_bpos,
+                        pos,
+                        "bare CR not allowed in raw byte string, use \\r instead",
+                    );
+                    valid = false;
+                }
                 Some(c) => {
                     if c > '\x7F' {
                         let pos = self.pos;
@@ -1658,13 +1814,124 @@ impl<'a> StringReader<'a> {
             self.bump();
         }
         self.bump();
-        token::ByteStrRaw(
-            self.name_from_to(content_start_bpos, content_end_bpos),
-            hash_count,
-        )
+        let id = if valid {
+            self.name_from_to(content_start_bpos, content_end_bpos)
+        } else {
+            Symbol::intern("??")
+        };
+        Ok(token::ByteStrRaw(id, hash_count))
+    }
+
+    fn scan_c_string(&mut self) -> Result<token::Lit, ()> {
+        self.bump();
+        let start = self.pos;
+        let mut valid = true;
+
+        while !self.ch_is('"') {
+            if self.is_eof() {
+                let pos = self.pos;
+                return self.fail_span_(start, pos, "unterminated double quote C string");
+            }
+
+            let ch_start = self.pos;
+            let ch = self.ch.unwrap();
+            self.bump();
+            // Unlike in string literals, `\x` escapes may denote any byte.
+            let ch_valid = if ch == '\\' && self.ch_is('x') {
+                self.bump();
+                self.scan_byte_escape('"', false)?
+            } else {
+                self.scan_char_or_byte(ch_start, ch, false, '"')?
+            };
+            if ch_valid && self.with_str_from(ch_start, |s| *c_str_lit(s) == [0, 0]) {
+                let pos = self.pos;
+                self.err_span_(ch_start, pos, C_STRING_NUL);
+                valid = false;
+            }
+            valid &= ch_valid;
+        }
+        let id = if valid {
+            self.name_from(start)
+        } else {
+            Symbol::intern("??")
+        };
+        self.bump();
+        Ok(token::CStr(id))
+    }
+
+    fn scan_raw_c_string(&mut self) -> Result<token::Lit, ()> {
+        let start_bpos = self.pos;
+        self.bump();
+        let mut hash_count = 0;
+        while self.ch_is('#') {
+            self.bump();
+            hash_count += 1;
+        }
+
+        if self.is_eof() {
+            let pos = self.pos;
+            return self.fail_span_(start_bpos, pos, "unterminated raw C string");
+        } else if !self.ch_is('"') {
+            let pos = self.pos;
+            let ch = self.ch.unwrap();
+            return self.fail_span_char(
+                start_bpos,
+                pos,
+                "found invalid character; only `#` is allowed in raw \
+                                         string delimitation",
+                ch,
+            );
+        }
+        self.bump();
+        let content_start_bpos = self.pos;
+        let mut content_end_bpos;
+        let mut valid = true;
+        'outer: loop {
+            match self.ch {
+                None => {
+                    let pos = self.pos;
+                    return self.fail_span_(start_bpos, pos, "unterminated raw C string");
+                }
+                Some('"') => {
+                    content_end_bpos = self.pos;
+                    for _ in 0..hash_count {
+                        self.bump();
+                        if !self.ch_is('#') {
+                            continue 'outer;
+                        }
+                    }
+                    break;
+                }
+                Some('\r') if !self.nextch_is('\n') => {
+                    let pos = self.pos;
+                    self.err_span_(
+                        start_bpos,
+                        pos,
+                        "bare CR not allowed in raw C string, use \\r instead",
+                    );
+                    valid = false;
+                }
+                Some('\0') => {
+                    let (pos, next_pos) = (self.pos, self.next_pos);
+                    self.err_span_(pos, next_pos, C_STRING_NUL);
+                    valid = false;
+                }
+                Some(_) => {}
+            }
+            self.bump();
+        }
+        self.bump();
+        let id = if valid {
+            self.name_from_to(content_start_bpos, content_end_bpos)
+        } else {
+            Symbol::intern("??")
+        };
+        Ok(token::CStrRaw(id, hash_count))
     }
 }
 
+const C_STRING_NUL: &str = "null characters in C string literals are not supported";
+
 // This tests the character for the unicode property 'PATTERN_WHITE_SPACE' which
 // is guaranteed to be forward compatible. http://unicode.org/reports/tr31/#R3
 pub fn is_pattern_whitespace(c: Option<char>) -> bool {
@@ -1727,6 +1994,31 @@ fn ident_continue(c: Option<char>) -> bool {
         || (c > '\x7f' && UnicodeXID::is_xid_continue(c))
 }
 
+/// A non-ASCII character that can't appear in an identifier and doesn't look
+/// like ASCII punctuation. The lexer recovers from these by treating them as
+/// part of an identifier.
+fn is_unknown_char(c: Option<char>) -> bool {
+    match c {
+        Some(c) => {
+            c > '\x7f'
+                && !ident_continue(Some(c))
+                && !c.is_whitespace()
+                && !unicode_chars::has_substitution(c)
+        }
+        None => false,
+    }
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c,
+        '\u{2300}'..='\u{23ff}'
+            | '\u{2600}'..='\u{27bf}'
+            | '\u{2b00}'..='\u{2bff}'
+            | '\u{1f000}'..='\u{1faff}'
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1736,9 +2028,10 @@ mod tests {
     use crate::errors;
     use crate::feature_gate::UnstableFeatures;
     use crate::parse::token;
+    use crate::print::pprust;
     use crate::symbol::Symbol;
     use crate::syntax_pos::{BytePos, Span, NO_EXPANSION};
-    use std::cell::RefCell;
+    use std::cell::{Cell, RefCell};
     use std::collections::HashSet;
     use std::io;
     use std::rc::Rc;
@@ -1750,14 +2043,44 @@ mod tests {
             unstable_features: UnstableFeatures::from_environment(),
             config: CrateConfig::new(),
             included_mod_stack: RefCell::new(Vec::new()),
+            mod_inclusion_callback: None,
+            parse_callbacks: RefCell::new(None),
+            items_parsed: Cell::new(0),
+            stats: RefCell::new(None),
+            nesting_limit: crate::parse::DEFAULT_NESTING_LIMIT,
+            registered_tools: HashSet::new(),
+            cfg_compat: Default::default(),
+            unknown_cfg_predicates: RefCell::new(HashSet::new()),
             code_map: cm,
             missing_fragment_specifiers: RefCell::new(HashSet::new()),
         }
     }
 
+    // lex the whole of `src`, returning the line starts and multibyte chars
+    // recorded in its filemap, relative to the start of the filemap
+    fn lex_line_info(src: &str) -> (Vec<u32>, Vec<(u32, usize)>) {
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        cm.new_filemap("padding.rs".into(), "fn main() {}\n".to_string());
+        let sh = mk_sess(cm.clone());
+        let fm = cm.new_filemap("zebra.rs".into(), src.to_string());
+        {
+            let mut string_reader = StringReader::new(&sh, fm.clone());
+            while string_reader.next_token().tok != token::Eof {}
+        }
+        let start = fm.start_pos;
+        let lines = fm.lines.borrow().iter().map(|&l| (l - start).0).collect();
+        let multibyte_chars = fm
+            .multibyte_chars
+            .borrow()
+            .iter()
+            .map(|mbc| ((mbc.pos - start).0, mbc.bytes))
+            .collect();
+        (lines, multibyte_chars)
+    }
+
     // open a string reader for the given string
     fn setup<'a>(cm: &CodeMap, sess: &'a ParseSess, teststr: String) -> StringReader<'a> {
-        let fm = cm.new_filemap("zebra.rs".to_string(), teststr);
+        let fm = cm.new_filemap("zebra.rs".into(), teststr);
         StringReader::new(sess, fm)
     }
 
@@ -1945,6 +2268,7 @@ mod tests {
         test!("b'a'", Byte, "a");
         test!("\"a\"", Str_, "a");
         test!("b\"a\"", ByteStr, "a");
+        test!("c\"a\"", CStr, "a");
         test!("1234", Integer, "1234");
         test!("0b101", Integer, "0b101");
         test!("0xABC", Integer, "0xABC");
@@ -1976,6 +2300,15 @@ mod tests {
                 Some(Symbol::intern("suffix"))
             )
         );
+        assert_eq!(
+            setup(&cm, &sh, "cr##\"raw\"##suffix".to_string())
+                .next_token()
+                .tok,
+            token::Literal(
+                token::CStrRaw(Symbol::intern("raw"), 2),
+                Some(Symbol::intern("suffix"))
+            )
+        );
     }
 
     #[test]
@@ -2014,4 +2347,93 @@ mod tests {
             token::DocComment(Symbol::intern("/// test"))
         );
     }
+
+    #[test]
+    fn line_info_crlf() {
+        let (lines, multibyte_chars) = lex_line_info("a\r\nb\r\n\r\nc\r\n");
+        assert_eq!(lines, vec![0, 3, 6, 8]);
+        assert_eq!(multibyte_chars, vec![]);
+    }
+
+    #[test]
+    fn line_info_no_final_newline() {
+        let (lines, _) = lex_line_info("fn a() {}\nfn b() {}");
+        assert_eq!(lines, vec![0, 10]);
+        let (lines, _) = lex_line_info("fn a() {}\nfn b() {}\n");
+        assert_eq!(lines, vec![0, 10]);
+    }
+
+    #[test]
+    fn line_info_multibyte_last_char() {
+        let (lines, multibyte_chars) = lex_line_info("// é\n\"ü\"\n// 😀");
+        assert_eq!(lines, vec![0, 6, 11]);
+        assert_eq!(multibyte_chars, vec![(3, 2), (7, 2), (14, 4)]);
+    }
+
+    #[test]
+    fn line_info_across_chunks() {
+        let mut src = String::new();
+        for i in 0..1000 {
+            src.push_str(&format!("// ß{}\r\nlet x = \"€\";\n", i));
+        }
+        let mut expected_lines = vec![0];
+        expected_lines.extend(
+            src.match_indices('\n')
+                .map(|(i, _)| i as u32 + 1)
+                .filter(|&i| (i as usize) < src.len()),
+        );
+        let expected_multibyte_chars: Vec<_> = src
+            .char_indices()
+            .filter(|&(_, c)| c.len_utf8() > 1)
+            .map(|(i, c)| (i as u32, c.len_utf8()))
+            .collect();
+        assert!(src.len() > 4 * LINE_INFO_CHUNK_SIZE as usize);
+        assert_eq!(
+            lex_line_info(&src),
+            (expected_lines, expected_multibyte_chars)
+        );
+    }
+
+    // lex all of `src`, returning the non-whitespace tokens as strings along
+    // with their spans and the number of errors reported
+    fn lex_recovering(src: &str) -> (Vec<(String, u32, u32)>, usize) {
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let sh = mk_sess(cm.clone());
+        let mut string_reader = setup(&cm, &sh, src.to_string());
+        let mut tokens = Vec::new();
+        loop {
+            let TokenAndSpan { tok, sp } = string_reader.real_token();
+            if tok == token::Eof {
+                break;
+            }
+            tokens.push((pprust::token_to_string(&tok), sp.lo.0, sp.hi.0));
+        }
+        (tokens, sh.span_diagnostic.err_count())
+    }
+
+    #[test]
+    fn emoji_binding() {
+        let (tokens, errors) = lex_recovering("let 🦀 = 1;");
+        assert_eq!(errors, 1);
+        assert_eq!(tokens[1], ("🦀".to_string(), 4, 8));
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(lex_line_info("let 🦀 = 1;").1, vec![(4, 4)]);
+    }
+
+    #[test]
+    fn emoji_in_fn_name() {
+        let (tokens, errors) = lex_recovering("fn crab🦀\u{fe0f}s() {}");
+        assert_eq!(errors, 1);
+        let names: Vec<_> = tokens.iter().map(|t| &t.0[..]).collect();
+        assert_eq!(names, ["fn", "crab🦀\u{fe0f}s", "(", ")", "{", "}"]);
+        assert_eq!((tokens[1].1, tokens[1].2), (3, 15));
+    }
+
+    #[test]
+    fn stray_unknown_char() {
+        let (tokens, errors) = lex_recovering("a § b");
+        assert_eq!(errors, 1);
+        let tokens: Vec<_> = tokens.iter().map(|t| (&t.0[..], t.1, t.2)).collect();
+        assert_eq!(tokens, [("a", 0, 1), ("§", 2, 4), ("b", 5, 6)]);
+    }
 }
@@ -1,8 +1,9 @@
+use crate::errors::DiagnosticBuilder;
 use crate::parse::lexer::StringReader;
-use crate::parse::{token, PResult};
+use crate::parse::{ensure_sufficient_stack, token, PResult};
 use crate::print::pprust::token_to_string;
 use crate::syntax_pos::Span;
-use crate::tokenstream::{Delimited, TokenStream, TokenTree};
+use crate::tokenstream::{DelimSpan, Delimited, TokenStream, TokenTree};
 
 impl<'a> StringReader<'a> {
     // Parse a stream of tokens into a list of `TokenTree`s, up to an `Eof`.
@@ -15,31 +16,66 @@ impl<'a> StringReader<'a> {
     }
 
     // Parse a stream of tokens into a list of `TokenTree`s, up to a `CloseDelim`.
-    fn parse_token_trees_until_close_delim(&mut self) -> TokenStream {
+    // An un-closed delimiter is reported and recovered from; only lexing errors
+    // are returned.
+    fn parse_token_trees_until_close_delim(&mut self) -> PResult<'a, TokenStream> {
         let mut tts = vec![];
         loop {
-            if let token::CloseDelim(..) = self.token {
-                return TokenStream::concat(tts);
-            }
-            match self.parse_token_tree() {
-                Ok(tt) => tts.push(tt.into()),
-                Err(mut e) => {
-                    e.emit();
-                    return TokenStream::concat(tts);
+            match self.token {
+                token::CloseDelim(..) => return Ok(TokenStream::concat(tts)),
+                token::Eof => {
+                    self.unclosed_delim_err().emit();
+                    return Ok(TokenStream::concat(tts));
                 }
+                _ => tts.push(self.parse_token_tree()?.into()),
             }
         }
     }
 
+    fn unclosed_delim_err(&self) -> DiagnosticBuilder<'a> {
+        let msg = "this file contains an un-closed delimiter";
+        let mut err = self.diagnostic().struct_span_err(self.span, msg);
+        for &(_, sp) in &self.open_braces {
+            err.span_help(sp, "did you mean to close this delimiter?");
+        }
+        err
+    }
+
+    // Skip the delimited section starting at the current `OpenDelim`, without
+    // recursing into it. Returns the span of the last token skipped.
+    fn skip_delimited(&mut self) -> PResult<'a, Span> {
+        let mut depth = 0usize;
+        let mut last = self.span;
+        while self.token != token::Eof {
+            match self.token {
+                token::OpenDelim(..) => depth += 1,
+                token::CloseDelim(..) => depth -= 1,
+                _ => {}
+            }
+            last = self.span;
+            self.maybe_real_token()?;
+            if depth == 0 {
+                break;
+            }
+        }
+        Ok(last)
+    }
+
     fn parse_token_tree(&mut self) -> PResult<'a, TokenTree> {
         match self.token {
-            token::Eof => {
-                let msg = "this file contains an un-closed delimiter";
-                let mut err = self.sess.span_diagnostic.struct_span_err(self.span, msg);
-                for &(_, sp) in &self.open_braces {
-                    err.span_help(sp, "did you mean to close this delimiter?");
-                }
-                Err(err)
+            token::Eof => Err(self.unclosed_delim_err()),
+            token::OpenDelim(delim) if self.open_braces.len() >= self.sess.nesting_limit() => {
+                let pre_span = self.span;
+                self.diagnostic()
+                    .span_err(pre_span, "delimiter nesting too deep");
+                let close_span = self.skip_delimited()?;
+                Ok(TokenTree::Delimited(
+                    DelimSpan::from_pair(pre_span, close_span),
+                    Delimited {
+                        delim,
+                        tts: TokenStream::empty().into(),
+                    },
+                ))
             }
             token::OpenDelim(delim) => {
                 // The span for beginning of the delimited section
@@ -47,18 +83,15 @@ impl<'a> StringReader<'a> {
 
                 // Parse the open delimiter.
                 self.open_braces.push((delim, self.span));
-                self.real_token();
+                self.maybe_real_token()?;
 
                 // Parse the token trees within the delimiters.
                 // We stop at any delimiter so we can try to recover if the user
                 // uses an incorrect delimiter.
-                let tts = self.parse_token_trees_until_close_delim();
+                let tts = ensure_sufficient_stack(|| self.parse_token_trees_until_close_delim())?;
 
-                // Expand to cover the entire delimited token tree
-                let span = Span {
-                    hi: self.span.hi,
-                    ..pre_span
-                };
+                // The closing delimiter, or where it was expected to be.
+                let delim_span = DelimSpan::from_pair(pre_span, self.span);
 
                 match self.token {
                     // Correct delimiter.
@@ -66,13 +99,13 @@ impl<'a> StringReader<'a> {
                         self.open_braces.pop().unwrap();
 
                         // Parse the close delimiter.
-                        self.real_token();
+                        self.maybe_real_token()?;
                     }
                     // Incorrect delimiter.
                     token::CloseDelim(other) => {
                         let token_str = token_to_string(&self.token);
                         let msg = format!("incorrect close delimiter: `{}`", token_str);
-                        let mut err = self.sess.span_diagnostic.struct_span_err(self.span, &msg);
+                        let mut err = self.diagnostic().struct_span_err(self.span, &msg);
                         // This is a conservative error: only report the last unclosed delimiter.
                         // The previous unclosed delimiters could actually be closed! The parser
                         // just hasn't gotten to them yet.
@@ -91,7 +124,7 @@ impl<'a> StringReader<'a> {
                         //     bar(baz(
                         // }  // Incorrect delimiter but matches the earlier `{`
                         if !self.open_braces.iter().any(|&(b, _)| b == other) {
-                            self.real_token();
+                            self.maybe_real_token()?;
                         }
                     }
                     token::Eof => {
@@ -103,7 +136,7 @@ impl<'a> StringReader<'a> {
                 }
 
                 Ok(TokenTree::Delimited(
-                    span,
+                    delim_span,
                     Delimited {
                         delim: delim,
                         tts: tts.into(),
@@ -115,12 +148,12 @@ impl<'a> StringReader<'a> {
                 // matching opening delimiter).
                 let token_str = token_to_string(&self.token);
                 let msg = format!("unexpected close delimiter: `{}`", token_str);
-                let err = self.sess.span_diagnostic.struct_span_err(self.span, &msg);
+                let err = self.diagnostic().struct_span_err(self.span, &msg);
                 Err(err)
             }
             _ => {
                 let tt = TokenTree::Token(self.span, self.token.clone());
-                self.real_token();
+                self.maybe_real_token()?;
                 Ok(tt)
             }
         }
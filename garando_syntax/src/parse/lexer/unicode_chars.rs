@@ -232,6 +232,11 @@ const ASCII_ARRAY: &'static [(char, &'static str)] = &[
     ('>', "Greater-Than Sign"),
 ];
 
+/// Whether `ch` is a unicode character that looks like ASCII punctuation.
+pub fn has_substitution(ch: char) -> bool {
+    UNICODE_ARRAY.iter().any(|&(c, _, _)| c == ch)
+}
+
 pub fn check_for_substitution<'a>(
     reader: &StringReader<'a>,
     ch: char,
@@ -256,7 +261,7 @@ pub fn check_for_substitution<'a>(
                 }
                 None => {
                     let msg = format!("substitution character not found for '{}'", ch);
-                    reader.sess.span_diagnostic.span_bug_no_panic(span, &msg);
+                    reader.diagnostic().span_bug_no_panic(span, &msg);
                 }
             }
         });
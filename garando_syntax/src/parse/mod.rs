@@ -1,22 +1,29 @@
 //! The main parser interface
 
 use crate::ast::{self, CrateConfig};
-use crate::codemap::{CodeMap, FilePathMapping};
+use crate::attr::CfgCompat;
+use crate::codemap::{self, CodeMap, FilePathMapping};
+use crate::errors::emitter::BufferedEmitter;
 use crate::errors::{ColorConfig, DiagnosticBuilder, Handler};
 use crate::feature_gate::UnstableFeatures;
-use crate::parse::parser::Parser;
+use crate::json::JsonEmitter;
+use crate::parse::parser::{ItemParseOutcome, Parser};
 use crate::ptr::P;
 use crate::str::char_at;
 use crate::symbol::Symbol;
-use crate::syntax_pos::{self, FileMap, Span, NO_EXPANSION};
+use crate::syntax_pos::{self, BytePos, FileMap, FileName, Span, NO_EXPANSION};
 use crate::tokenstream::{TokenStream, TokenTree};
 
-use std::cell::RefCell;
+use std::cell::{Cell, Ref, RefCell};
 use std::collections::HashSet;
+use std::fmt;
+use std::io::Write;
 use std::iter;
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::str;
+use std::time::{Duration, Instant};
 
 use log::debug;
 
@@ -33,6 +40,118 @@ pub mod classify;
 pub mod common;
 pub mod obsolete;
 
+/// The default for `ParseSess::nesting_limit`.
+pub const DEFAULT_NESTING_LIMIT: usize = 256;
+
+// Parsing one level of nesting takes tens of kilobytes of stack in debug
+// builds, so the nesting limit alone doesn't keep deep input from
+// overflowing a small thread stack. Below `RED_ZONE` bytes of remaining
+// stack, recursion continues on a new `STACK_PER_RECURSION` byte segment.
+const RED_ZONE: usize = 256 * 1024;
+const STACK_PER_RECURSION: usize = 2 * 1024 * 1024;
+
+/// Runs `f`, first growing the stack if little of it is left. Every
+/// recursive path of the lexer and parser goes through this.
+pub(crate) fn ensure_sufficient_stack<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(RED_ZONE, STACK_PER_RECURSION, f)
+}
+
+/// Callback invoked with the path and span of each out-of-line module file.
+pub type ModInclusionCallback = Box<dyn Fn(&Path, Span)>;
+
+/// Hooks through which a `ParseSess` reports the progress of parsing, e.g.
+/// to drive a progress bar or enforce a timeout. Every method does nothing
+/// by default.
+pub trait ParseCallbacks {
+    /// Called with each filemap loaded from a file to be parsed, i.e. the
+    /// crate root and its out-of-line module files.
+    fn on_filemap_loaded(&mut self, _filemap: &FileMap) {}
+
+    /// Called after each item of a module is parsed, with the item's name if
+    /// it has one, its span, and the number of module items parsed in this
+    /// session so far, including this one. Returning `ControlFlow::Break`
+    /// cancels parsing with a fatal "parsing cancelled" error.
+    fn on_item_parsed(
+        &mut self,
+        _name: Option<Symbol>,
+        _span: Span,
+        _items_so_far: usize,
+    ) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// Called when the parser starts parsing an out-of-line module file.
+    fn on_mod_file_enter(&mut self, _path: &Path) {}
+
+    /// Called when the parser is done parsing an out-of-line module file,
+    /// whether or not it succeeded.
+    fn on_mod_file_exit(&mut self, _path: &Path) {}
+}
+
+/// Numbers about the work done by a `ParseSess` since `enable_stats`, for
+/// finding where parsing spends its time. Everything lexed or parsed through
+/// the session counts, including the output of macros it parses.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParseStats {
+    /// The tokens lexed from source files, not counting whitespace and
+    /// comments.
+    pub tokens: usize,
+    pub bytes_lexed: usize,
+    pub items: usize,
+    pub macro_invocations: usize,
+    pub lexing_time: Duration,
+    /// The time spent in the `parse_*` functions of this module, less the
+    /// time spent lexing within them.
+    pub parsing_time: Duration,
+    /// The number of lines of each file lexed, in the order they were lexed.
+    pub lines_per_file: Vec<(FileName, usize)>,
+}
+
+impl fmt::Display for ParseStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows = [
+            ("tokens", self.tokens.to_string()),
+            ("bytes lexed", self.bytes_lexed.to_string()),
+            ("items", self.items.to_string()),
+            ("macro invocations", self.macro_invocations.to_string()),
+            ("lexing time", format!("{:?}", self.lexing_time)),
+            ("parsing time", format!("{:?}", self.parsing_time)),
+        ];
+        let value_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+        for &(label, ref value) in &rows {
+            writeln!(f, "{:<17}  {:>width$}", label, value, width = value_width)?;
+        }
+
+        // File names come last, as they can be long.
+        if !self.lines_per_file.is_empty() {
+            writeln!(f, "lines  file")?;
+        }
+        for &(ref name, lines) in &self.lines_per_file {
+            writeln!(f, "{:>5}  {}", lines, name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds the time spent in a parse entry point to `ParseStats::parsing_time`
+/// when dropped, so that it is counted even if parsing unwinds on a fatal
+/// error.
+struct ParseTimer<'a> {
+    sess: &'a ParseSess,
+    start: Instant,
+    lexing_time_before: Duration,
+}
+
+impl<'a> Drop for ParseTimer<'a> {
+    fn drop(&mut self) {
+        let (start, lexing_time_before) = (self.start, self.lexing_time_before);
+        self.sess.record_stats(|stats| {
+            let lexing_time = stats.lexing_time - lexing_time_before;
+            stats.parsing_time += start.elapsed().saturating_sub(lexing_time);
+        });
+    }
+}
+
 /// Info about a parsing session.
 pub struct ParseSess {
     pub span_diagnostic: Handler,
@@ -41,6 +160,22 @@ pub struct ParseSess {
     pub missing_fragment_specifiers: RefCell<HashSet<Span>>,
     /// Used to determine and report recursive mod inclusions
     included_mod_stack: RefCell<Vec<PathBuf>>,
+    /// Called whenever the parser starts parsing an out-of-line module file
+    mod_inclusion_callback: Option<ModInclusionCallback>,
+    parse_callbacks: RefCell<Option<Box<dyn ParseCallbacks>>>,
+    /// The number of module items reported to `parse_callbacks` so far
+    items_parsed: Cell<usize>,
+    /// Collected once `enable_stats` is called
+    stats: RefCell<Option<ParseStats>>,
+    /// How deeply delimiters, expressions, types and patterns may nest
+    nesting_limit: usize,
+    /// The first segments of attribute paths, such as `rustfmt` in
+    /// `#[rustfmt::skip]`, that belong to tools rather than the compiler
+    registered_tools: HashSet<Symbol>,
+    /// How `cfg` predicates other than `all`, `any` and `not` are evaluated
+    cfg_compat: CfgCompat,
+    /// The unknown `cfg` predicates warned about so far
+    unknown_cfg_predicates: RefCell<HashSet<Symbol>>,
     code_map: Rc<CodeMap>,
 }
 
@@ -51,13 +186,62 @@ impl ParseSess {
         ParseSess::with_span_handler(handler, cm)
     }
 
+    /// Creates a session whose diagnostics are emitted as JSON to `dst`, one
+    /// diagnostic per line.
+    pub fn with_json_emitter(
+        file_path_mapping: FilePathMapping,
+        dst: Box<dyn Write + Send>,
+    ) -> ParseSess {
+        let cm = Rc::new(CodeMap::new(file_path_mapping));
+        let emitter = JsonEmitter::new(dst, None, cm.clone());
+        let handler = Handler::with_emitter(true, false, Box::new(emitter));
+        ParseSess::with_span_handler(handler, cm)
+    }
+
+    /// Creates a session whose diagnostics are rendered as human-readable
+    /// text to `dst`, styled according to `color_config`.
+    pub fn with_emitter_writer(
+        file_path_mapping: FilePathMapping,
+        dst: Box<dyn Write + Send>,
+        color_config: ColorConfig,
+    ) -> ParseSess {
+        let cm = Rc::new(CodeMap::new(file_path_mapping));
+        let handler = Handler::with_emitter_writer(dst, color_config, Some(cm.clone()));
+        ParseSess::with_span_handler(handler, cm)
+    }
+
+    /// Creates a session for tools parsing many unrelated files, which
+    /// collects diagnostics into the returned `BufferedEmitter` and carries on
+    /// after errors. Files should be parsed with the `maybe_*` entry points,
+    /// which return a fatal error anywhere in a file, including in the files
+    /// of its modules, rather than aborting, so that the session can go on to
+    /// the next file.
+    pub fn new_for_batch(file_path_mapping: FilePathMapping) -> (ParseSess, BufferedEmitter) {
+        let cm = Rc::new(CodeMap::new(file_path_mapping));
+        let emitter = BufferedEmitter::new();
+        let handler = Handler::with_emitter(true, false, Box::new(emitter.clone()));
+        handler.set_continue_after_error(true);
+        (ParseSess::with_span_handler(handler, cm), emitter)
+    }
+
     pub fn with_span_handler(handler: Handler, code_map: Rc<CodeMap>) -> ParseSess {
         ParseSess {
             span_diagnostic: handler,
             unstable_features: UnstableFeatures::from_environment(),
-            config: HashSet::new(),
+            config: CrateConfig::new(),
             missing_fragment_specifiers: RefCell::new(HashSet::new()),
             included_mod_stack: RefCell::new(vec![]),
+            mod_inclusion_callback: None,
+            parse_callbacks: RefCell::new(None),
+            items_parsed: Cell::new(0),
+            stats: RefCell::new(None),
+            nesting_limit: DEFAULT_NESTING_LIMIT,
+            registered_tools: ["rustfmt", "clippy"]
+                .iter()
+                .map(|tool| Symbol::intern(tool))
+                .collect(),
+            cfg_compat: CfgCompat::default(),
+            unknown_cfg_predicates: RefCell::new(HashSet::new()),
             code_map: code_map,
         }
     }
@@ -65,6 +249,174 @@ impl ParseSess {
     pub fn codemap(&self) -> &CodeMap {
         &self.code_map
     }
+
+    /// Runs `f` with spans printed by `{:?}` as locations in this session's
+    /// codemap; see `codemap::with_span_debug`.
+    pub fn with_span_debugging<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        codemap::with_span_debug(&self.code_map, f)
+    }
+
+    pub fn config_mut(&mut self) -> &mut CrateConfig {
+        &mut self.config
+    }
+
+    /// How deeply delimiters, expressions, types, patterns and modules may nest before
+    /// the parser reports an error rather than recursing further.
+    pub fn nesting_limit(&self) -> usize {
+        self.nesting_limit
+    }
+
+    /// Sets the nesting limit. Nested input is parsed recursively, on stack
+    /// segments allocated as needed, so the limit bounds memory use rather
+    /// than the depth the thread's stack can take.
+    pub fn set_nesting_limit(&mut self, limit: usize) {
+        self.nesting_limit = limit;
+    }
+
+    pub fn cfg_compat(&self) -> CfgCompat {
+        self.cfg_compat
+    }
+
+    /// Sets how `cfg` predicates unknown to garando, such as those of newer
+    /// compilers, are evaluated. They are errors by default.
+    pub fn set_cfg_compat(&mut self, compat: CfgCompat) {
+        self.cfg_compat = compat;
+    }
+
+    /// Records the unknown `cfg` predicate `name`, returning whether it is
+    /// the first time.
+    pub(crate) fn first_unknown_cfg_predicate(&self, name: Symbol) -> bool {
+        self.unknown_cfg_predicates.borrow_mut().insert(name)
+    }
+
+    /// Registers `tool` as a tool namespace, so that attributes such as
+    /// `#[tool::name]` are accepted without being checked. `rustfmt` and
+    /// `clippy` are registered by default. Returns false if `tool` was
+    /// already registered.
+    pub fn register_tool(&mut self, tool: Symbol) -> bool {
+        self.registered_tools.insert(tool)
+    }
+
+    pub fn is_registered_tool(&self, tool: Symbol) -> bool {
+        self.registered_tools.contains(&tool)
+    }
+
+    /// Whether the path of `attr` has several segments, the first of which is
+    /// a registered tool.
+    pub fn is_tool_attr(&self, attr: &ast::Attribute) -> bool {
+        attr.path.segments.len() > 1
+            && self.is_registered_tool(attr.path.segments[0].identifier.name)
+    }
+
+    /// The paths of the module files currently being parsed, outermost first.
+    pub fn included_mod_stack(&self) -> Ref<'_, [PathBuf]> {
+        Ref::map(self.included_mod_stack.borrow(), |stack| &stack[..])
+    }
+
+    /// Removes and returns the spans of macro fragments which are missing their fragment
+    /// specifier (e.g. `$x` instead of `$x:expr`) and haven't been reported yet, in source order.
+    pub fn take_missing_fragment_specifiers(&self) -> Vec<Span> {
+        let mut spans: Vec<_> = self
+            .missing_fragment_specifiers
+            .borrow_mut()
+            .drain()
+            .collect();
+        spans.sort();
+        spans
+    }
+
+    /// Sets a callback which is invoked with the path of every out-of-line module file
+    /// (`mod foo;`) the parser descends into, along with the span of the module's name.
+    pub fn set_mod_inclusion_callback(&mut self, callback: ModInclusionCallback) {
+        self.mod_inclusion_callback = Some(callback);
+    }
+
+    /// Sets the callbacks through which parsing progress is reported, see
+    /// `ParseCallbacks`.
+    pub fn set_parse_callbacks(&mut self, callbacks: Box<dyn ParseCallbacks>) {
+        *self.parse_callbacks.get_mut() = Some(callbacks);
+    }
+
+    pub(crate) fn filemap_loaded(&self, filemap: &FileMap) {
+        if let Some(ref mut callbacks) = *self.parse_callbacks.borrow_mut() {
+            callbacks.on_filemap_loaded(filemap);
+        }
+    }
+
+    pub(crate) fn item_parsed(&self, name: Option<Symbol>, span: Span) -> ControlFlow<()> {
+        match *self.parse_callbacks.borrow_mut() {
+            Some(ref mut callbacks) => {
+                let items_so_far = self.items_parsed.get() + 1;
+                self.items_parsed.set(items_so_far);
+                callbacks.on_item_parsed(name, span, items_so_far)
+            }
+            None => ControlFlow::Continue(()),
+        }
+    }
+
+    /// Starts collecting the `ParseStats` of the session, from zero.
+    pub fn enable_stats(&self) {
+        *self.stats.borrow_mut() = Some(ParseStats::default());
+    }
+
+    /// The stats collected since `enable_stats`, or all zeros if it hasn't
+    /// been called.
+    pub fn stats(&self) -> ParseStats {
+        self.stats.borrow().clone().unwrap_or_default()
+    }
+
+    /// Updates the stats with `f` if they are being collected.
+    pub(crate) fn record_stats<F: FnOnce(&mut ParseStats)>(&self, f: F) {
+        if let Some(ref mut stats) = *self.stats.borrow_mut() {
+            f(stats);
+        }
+    }
+
+    /// The time lexing started at, if stats are being collected.
+    fn lexing_start(&self) -> Option<Instant> {
+        match *self.stats.borrow() {
+            Some(_) => Some(Instant::now()),
+            None => None,
+        }
+    }
+
+    /// A timer for `ParseStats::parsing_time`, if stats are being collected.
+    fn parse_timer(&self) -> Option<ParseTimer<'_>> {
+        match *self.stats.borrow() {
+            Some(ref stats) => Some(ParseTimer {
+                sess: self,
+                start: Instant::now(),
+                lexing_time_before: stats.lexing_time,
+            }),
+            None => None,
+        }
+    }
+
+    pub(crate) fn push_included_mod(&self, path: PathBuf, sp: Span) {
+        if let Some(ref callback) = self.mod_inclusion_callback {
+            callback(&path, sp);
+        }
+        if let Some(ref mut callbacks) = *self.parse_callbacks.borrow_mut() {
+            callbacks.on_mod_file_enter(&path);
+        }
+        self.included_mod_stack.borrow_mut().push(path);
+    }
+
+    pub(crate) fn pop_included_mod(&self) {
+        let path = self.included_mod_stack.borrow_mut().pop();
+        if let (Some(path), Some(ref mut callbacks)) =
+            (path, &mut *self.parse_callbacks.borrow_mut())
+        {
+            callbacks.on_mod_file_exit(&path);
+        }
+    }
+
+    /// Emits an "unused attribute" warning for every attribute in `krate` that
+    /// was never marked with `attr::mark_used`. Call this after configuration,
+    /// feature gating and expansion, which mark the attributes they consume.
+    pub fn report_unused_attrs(&self, krate: &ast::Crate) {
+        crate::attr::report_unused_attrs(&self.span_diagnostic, krate);
+    }
 }
 
 #[derive(Clone)]
@@ -86,6 +438,7 @@ pub enum DirectoryOwnership {
 // source_str.
 
 pub fn parse_crate_from_file<'a>(input: &Path, sess: &'a ParseSess) -> PResult<'a, ast::Crate> {
+    let _timer = sess.parse_timer();
     let mut parser = new_parser_from_file(sess, input);
     parser.parse_crate_mod()
 }
@@ -94,6 +447,7 @@ pub fn parse_crate_attrs_from_file<'a>(
     input: &Path,
     sess: &'a ParseSess,
 ) -> PResult<'a, Vec<ast::Attribute>> {
+    let _timer = sess.parse_timer();
     let mut parser = new_parser_from_file(sess, input);
     parser.parse_inner_attributes()
 }
@@ -103,14 +457,36 @@ pub fn parse_crate_from_source_str(
     source: String,
     sess: &ParseSess,
 ) -> PResult<ast::Crate> {
+    let _timer = sess.parse_timer();
     new_parser_from_source_str(sess, name, source).parse_crate_mod()
 }
 
+/// Like `parse_crate_from_source_str`, but safe to call on arbitrary input:
+/// lexing errors that would abort are returned as the error instead, and
+/// input nested past `ParseSess::nesting_limit`, whether in delimiters,
+/// expressions, types, patterns or items, is reported and skipped rather
+/// than overflowing the stack. Errors that the parser recovers from are
+/// still emitted to `sess`. This is the entry point to use for untrusted
+/// source, e.g. from a fuzzer, with the cases in `tests/fuzz_corpus.rs`
+/// checked not to unwind.
+pub fn parse_crate_from_source_str_no_panic(
+    name: String,
+    source: String,
+    sess: &ParseSess,
+) -> PResult<'_, ast::Crate> {
+    let _timer = sess.parse_timer();
+    let mut parser =
+        maybe_filemap_to_parser(sess, sess.codemap().new_filemap(name.into(), source))?;
+    parser.recurse_into_file_modules = false;
+    parser.parse_crate_mod()
+}
+
 pub fn parse_crate_attrs_from_source_str(
     name: String,
     source: String,
     sess: &ParseSess,
 ) -> PResult<Vec<ast::Attribute>> {
+    let _timer = sess.parse_timer();
     new_parser_from_source_str(sess, name, source).parse_inner_attributes()
 }
 
@@ -119,6 +495,7 @@ pub fn parse_expr_from_source_str(
     source: String,
     sess: &ParseSess,
 ) -> PResult<P<ast::Expr>> {
+    let _timer = sess.parse_timer();
     new_parser_from_source_str(sess, name, source).parse_expr()
 }
 
@@ -131,14 +508,27 @@ pub fn parse_item_from_source_str(
     source: String,
     sess: &ParseSess,
 ) -> PResult<Option<P<ast::Item>>> {
+    let _timer = sess.parse_timer();
     new_parser_from_source_str(sess, name, source).parse_item()
 }
 
+/// Like `parse_item_from_source_str`, but says why no item was found, and
+/// where parsing stopped.
+pub fn parse_item_detailed_from_source_str(
+    name: String,
+    source: String,
+    sess: &ParseSess,
+) -> PResult<'_, ItemParseOutcome> {
+    let _timer = sess.parse_timer();
+    new_parser_from_source_str(sess, name, source).parse_item_detailed()
+}
+
 pub fn parse_meta_from_source_str(
     name: String,
     source: String,
     sess: &ParseSess,
 ) -> PResult<ast::MetaItem> {
+    let _timer = sess.parse_timer();
     new_parser_from_source_str(sess, name, source).parse_meta_item()
 }
 
@@ -147,20 +537,70 @@ pub fn parse_stmt_from_source_str(
     source: String,
     sess: &ParseSess,
 ) -> PResult<Option<ast::Stmt>> {
+    let _timer = sess.parse_timer();
     new_parser_from_source_str(sess, name, source).parse_stmt()
 }
 
+/// Parses a braced block, such as `{ let x = 1; x }`, which must make up the
+/// whole source.
+pub fn parse_block_from_source_str(
+    name: String,
+    source: String,
+    sess: &ParseSess,
+) -> PResult<'_, P<ast::Block>> {
+    let _timer = sess.parse_timer();
+    let mut parser = new_parser_from_source_str(sess, name, source);
+    let block = parser.parse_block()?;
+    parser.expect(&token::Eof)?;
+    Ok(block)
+}
+
+/// Parses a sequence of statements without surrounding braces, such as the
+/// body of a function. A trailing expression without a semicolon is returned
+/// as a `StmtKind::Expr`.
+pub fn parse_stmts_from_source_str(
+    name: String,
+    source: String,
+    sess: &ParseSess,
+) -> PResult<'_, Vec<ast::Stmt>> {
+    let _timer = sess.parse_timer();
+    new_parser_from_source_str(sess, name, source).parse_stmts_until_eof()
+}
+
 pub fn parse_stream_from_source_str(name: String, source: String, sess: &ParseSess) -> TokenStream {
-    filemap_to_stream(sess, sess.codemap().new_filemap(name, source))
+    filemap_to_stream(sess, sess.codemap().new_filemap(name.into(), source))
+}
+
+/// Like `parse_stream_from_source_str`, but returns a lexing error instead of
+/// emitting it and aborting.
+pub fn maybe_parse_stream_from_source_str(
+    name: String,
+    source: String,
+    sess: &ParseSess,
+) -> PResult<'_, TokenStream> {
+    maybe_filemap_to_stream(sess, sess.codemap().new_filemap(name.into(), source))
 }
 
 // Create a new parser from a source string
 pub fn new_parser_from_source_str(sess: &ParseSess, name: String, source: String) -> Parser {
-    let mut parser = filemap_to_parser(sess, sess.codemap().new_filemap(name, source));
+    let mut parser = filemap_to_parser(sess, sess.codemap().new_filemap(name.into(), source));
     parser.recurse_into_file_modules = false;
     parser
 }
 
+/// Like `new_parser_from_source_str`, but returns a lexing error instead of
+/// emitting it and aborting.
+pub fn maybe_new_parser_from_source_str(
+    sess: &ParseSess,
+    name: String,
+    source: String,
+) -> PResult<'_, Parser<'_>> {
+    let mut parser =
+        maybe_filemap_to_parser(sess, sess.codemap().new_filemap(name.into(), source))?;
+    parser.recurse_into_file_modules = false;
+    Ok(parser)
+}
+
 /// Create a new parser, handling errors as appropriate
 /// if the file doesn't exist
 pub fn new_parser_from_file<'a>(sess: &'a ParseSess, path: &Path) -> Parser<'a> {
@@ -183,10 +623,41 @@ pub fn new_sub_parser_from_file<'a>(
     p
 }
 
+/// Like `new_sub_parser_from_file`, but returns the error if the file can't
+/// be read or has a fatal lexing error instead of emitting it and aborting.
+pub fn maybe_new_sub_parser_from_file<'a>(
+    sess: &'a ParseSess,
+    path: &Path,
+    directory_ownership: DirectoryOwnership,
+    module_name: Option<String>,
+    sp: Span,
+) -> PResult<'a, Parser<'a>> {
+    let filemap = match try_file_to_filemap(sess, path) {
+        Ok(filemap) => filemap,
+        Err(msg) => return Err(sess.span_diagnostic.struct_span_fatal(sp, &msg)),
+    };
+    let mut p = maybe_filemap_to_parser(sess, filemap)?;
+    p.directory.ownership = directory_ownership;
+    p.root_module_name = module_name;
+    Ok(p)
+}
+
 /// Given a filemap and config, return a parser
 pub fn filemap_to_parser(sess: &ParseSess, filemap: Rc<FileMap>) -> Parser {
     let end_pos = filemap.end_pos;
-    let mut parser = stream_to_parser(sess, filemap_to_stream(sess, filemap));
+    stream_to_filemap_parser(sess, filemap_to_stream(sess, filemap), end_pos)
+}
+
+/// Like `filemap_to_parser`, but returns a fatal lexing error instead of
+/// emitting it and aborting.
+pub fn maybe_filemap_to_parser(sess: &ParseSess, filemap: Rc<FileMap>) -> PResult<'_, Parser<'_>> {
+    let end_pos = filemap.end_pos;
+    let stream = maybe_filemap_to_stream(sess, filemap)?;
+    Ok(stream_to_filemap_parser(sess, stream, end_pos))
+}
+
+fn stream_to_filemap_parser(sess: &ParseSess, stream: TokenStream, end_pos: BytePos) -> Parser<'_> {
+    let mut parser = stream_to_parser(sess, stream);
 
     if parser.token == token::Eof && parser.span == syntax_pos::DUMMY_SP {
         parser.span = Span {
@@ -210,23 +681,55 @@ pub fn new_parser_from_tts(sess: &ParseSess, tts: Vec<TokenTree>) -> Parser {
 /// Given a session and a path and an optional span (for error reporting),
 /// add the path to the session's codemap and return the new filemap.
 fn file_to_filemap(sess: &ParseSess, path: &Path, spanopt: Option<Span>) -> Rc<FileMap> {
-    match sess.codemap().load_file(path) {
+    match try_file_to_filemap(sess, path) {
         Ok(filemap) => filemap,
-        Err(e) => {
-            let msg = format!("couldn't read {:?}: {}", path.display(), e);
-            match spanopt {
-                Some(sp) => panic!(sess.span_diagnostic.span_fatal(sp, &msg)),
-                None => panic!(sess.span_diagnostic.fatal(&msg)),
-            }
+        Err(msg) => match spanopt {
+            Some(sp) => panic!(sess.span_diagnostic.span_fatal(sp, &msg)),
+            None => panic!(sess.span_diagnostic.fatal(&msg)),
+        },
+    }
+}
+
+/// Like `file_to_filemap`, but returns the message of the error reading the
+/// file instead of reporting it.
+fn try_file_to_filemap(sess: &ParseSess, path: &Path) -> Result<Rc<FileMap>, String> {
+    match sess.codemap().load_file(path) {
+        Ok(filemap) => {
+            sess.filemap_loaded(&filemap);
+            Ok(filemap)
         }
+        Err(e) => Err(format!("couldn't read {:?}: {}", path.display(), e)),
     }
 }
 
 /// Given a filemap, produce a sequence of token-trees
 pub fn filemap_to_stream(sess: &ParseSess, filemap: Rc<FileMap>) -> TokenStream {
-    let mut srdr = lexer::StringReader::new(sess, filemap);
-    srdr.real_token();
-    panictry!(srdr.parse_all_token_trees())
+    panictry!(maybe_filemap_to_stream(sess, filemap))
+}
+
+/// Given a filemap, produce a sequence of token-trees, returning the first
+/// fatal lexing error instead of emitting it and aborting. Errors the lexer
+/// can recover from are still emitted.
+pub fn maybe_filemap_to_stream(sess: &ParseSess, filemap: Rc<FileMap>) -> PResult<'_, TokenStream> {
+    let start = sess.lexing_start();
+    let (stream, tokens) = {
+        let mut srdr = lexer::StringReader::maybe_new(sess, filemap.clone())?;
+        let stream = srdr
+            .maybe_real_token()
+            .and_then(|_| srdr.parse_all_token_trees());
+        (stream, srdr.real_tokens)
+    };
+    if let Some(start) = start {
+        // The reader is dropped, so the filemap has all of its lines.
+        let lines = filemap.count_lines();
+        sess.record_stats(|stats| {
+            stats.tokens += tokens;
+            stats.bytes_lexed += (filemap.end_pos - filemap.start_pos).0 as usize;
+            stats.lexing_time += start.elapsed();
+            stats.lines_per_file.push((filemap.name.clone(), lines));
+        });
+    }
+    stream
 }
 
 /// Given stream and the `ParseSess`, produce a parser
@@ -234,43 +737,57 @@ pub fn stream_to_parser(sess: &ParseSess, stream: TokenStream) -> Parser {
     Parser::new(sess, stream, None, true, false)
 }
 
+/// Stand in for an escape the lexer has already reported as invalid, so
+/// that unescaping never fails.
+const INVALID_CHAR: char = '\u{FFFD}';
+const INVALID_BYTE: u8 = b'?';
+
 /// Parse a string representing a character literal into its final form.
 /// Rather than just accepting/rejecting a given literal, unescapes it as
 /// well. Can take any slice prefixed by a character escape. Returns the
-/// character and the number of characters consumed.
+/// character and the number of characters consumed. An invalid escape, which
+/// the lexer reports, gives `U+FFFD`.
 pub fn char_lit(lit: &str) -> (char, isize) {
     use std::char;
 
     // Handle non-escaped chars first.
-    if lit.as_bytes()[0] != b'\\' {
-        // If the first byte isn't '\\' it might part of a multi-byte char, so
-        // get the char with chars().
-        let c = lit.chars().next().unwrap();
-        return (c, 1);
+    match lit.chars().next() {
+        Some('\\') => {}
+        Some(c) => return (c, 1),
+        None => return (INVALID_CHAR, 1),
     }
 
     // Handle escaped chars.
-    match lit.as_bytes()[1] as char {
-        '"' => ('"', 2),
-        'n' => ('\n', 2),
-        'r' => ('\r', 2),
-        't' => ('\t', 2),
-        '\\' => ('\\', 2),
-        '\'' => ('\'', 2),
-        '0' => ('\0', 2),
-        'x' => {
-            let v = u32::from_str_radix(&lit[2..4], 16).unwrap();
-            let c = char::from_u32(v).unwrap();
-            (c, 4)
-        }
-        'u' => {
-            assert_eq!(lit.as_bytes()[2], b'{');
-            let idx = lit.find('}').unwrap();
-            let v = u32::from_str_radix(&lit[3..idx], 16).unwrap();
-            let c = char::from_u32(v).unwrap();
-            (c, (idx + 1) as isize)
-        }
-        _ => panic!("lexer should have rejected a bad character escape {}", lit),
+    match lit.as_bytes().get(1) {
+        Some(b'"') => ('"', 2),
+        Some(b'n') => ('\n', 2),
+        Some(b'r') => ('\r', 2),
+        Some(b't') => ('\t', 2),
+        Some(b'\\') => ('\\', 2),
+        Some(b'\'') => ('\'', 2),
+        Some(b'0') => ('\0', 2),
+        Some(b'x') => {
+            let c = lit
+                .get(2..4)
+                .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                .and_then(char::from_u32);
+            match c {
+                Some(c) => (c, 4),
+                None => (INVALID_CHAR, 2),
+            }
+        }
+        Some(b'u') => {
+            let idx = match lit.find('}') {
+                Some(idx) if idx > 2 && lit.as_bytes()[2] == b'{' => idx,
+                _ => return (INVALID_CHAR, 2),
+            };
+            let c = u32::from_str_radix(&lit[3..idx], 16)
+                .ok()
+                .and_then(char::from_u32)
+                .unwrap_or(INVALID_CHAR);
+            (c, lit[..=idx].chars().count() as isize)
+        }
+        _ => (INVALID_CHAR, lit.chars().take(2).count() as isize),
     }
 }
 
@@ -287,9 +804,6 @@ pub fn str_lit(lit: &str) -> String {
     debug!("parse_str_lit: given {}", escape_default(lit));
     let mut res = String::with_capacity(lit.len());
 
-    // FIXME #8372: This could be a for-loop if it didn't borrow the iterator
-    let error = |i| format!("lexer should have rejected {} at {}", lit, i);
-
     /// Eat everything up to a non-whitespace
     fn eat<'a>(it: &mut iter::Peekable<str::CharIndices<'a>>) {
         loop {
@@ -307,20 +821,11 @@ pub fn str_lit(lit: &str) -> String {
     let mut chars = lit.char_indices().peekable();
     while let Some((i, c)) = chars.next() {
         match c {
-            '\\' => {
-                let ch = chars.peek().unwrap_or_else(|| panic!("{}", error(i))).1;
-
-                if ch == '\n' {
-                    eat(&mut chars);
-                } else if ch == '\r' {
-                    chars.next();
-                    let ch = chars.peek().unwrap_or_else(|| panic!("{}", error(i))).1;
-
-                    if ch != '\n' {
-                        panic!("lexer accepted bare CR");
-                    }
-                    eat(&mut chars);
-                } else {
+            '\\' => match chars.peek().map(|x| x.1) {
+                Some('\n') | Some('\r') => eat(&mut chars),
+                // A trailing `\`, which the lexer reports.
+                None => res.push(INVALID_CHAR),
+                Some(_) => {
                     // otherwise, a normal escape
                     let (c, n) = char_lit(&lit[i..]);
                     for _ in 0..n - 1 {
@@ -329,16 +834,8 @@ pub fn str_lit(lit: &str) -> String {
                     }
                     res.push(c);
                 }
-            }
-            '\r' => {
-                let ch = chars.peek().unwrap_or_else(|| panic!("{}", error(i))).1;
-
-                if ch != '\n' {
-                    panic!("lexer accepted bare CR");
-                }
-                chars.next();
-                res.push('\n');
-            }
+            },
+            '\r' => res.push(crlf_to_lf(&mut chars)),
             c => res.push(c),
         }
     }
@@ -354,14 +851,10 @@ pub fn raw_str_lit(lit: &str) -> String {
     debug!("raw_str_lit: given {}", escape_default(lit));
     let mut res = String::with_capacity(lit.len());
 
-    let mut chars = lit.chars().peekable();
-    while let Some(c) = chars.next() {
+    let mut chars = lit.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
         if c == '\r' {
-            if *chars.peek().unwrap() != '\n' {
-                panic!("lexer accepted bare CR");
-            }
-            chars.next();
-            res.push('\n');
+            res.push(crlf_to_lf(&mut chars));
         } else {
             res.push(c);
         }
@@ -371,6 +864,21 @@ pub fn raw_str_lit(lit: &str) -> String {
     res
 }
 
+/// Called after a `\r`: eats the `\n` following it, if any, and returns the
+/// line ending to unescape to. A lone `\r`, which the lexer reports, is kept.
+fn crlf_to_lf<T, I: Iterator<Item = (usize, T)>>(chars: &mut iter::Peekable<I>) -> T
+where
+    T: Copy + PartialEq + From<u8>,
+{
+    match chars.peek() {
+        Some(&(_, c)) if c == T::from(b'\n') => {
+            chars.next();
+            c
+        }
+        _ => T::from(b'\r'),
+    }
+}
+
 // check if `s` looks like i32 or u1234 etc.
 fn looks_like_width_suffix(first_chars: &[char], s: &str) -> bool {
     s.len() > 1
@@ -414,8 +922,16 @@ pub fn lit_token(
         token::ByteStr(i) => (true, Some(LitKind::ByteStr(byte_str_lit(&i.as_str())))),
         token::ByteStrRaw(i, _) => (
             true,
-            Some(LitKind::ByteStr(Rc::new(i.to_string().into_bytes()))),
+            Some(LitKind::ByteStr(Rc::new(
+                raw_str_lit(&i.as_str()).into_bytes(),
+            ))),
         ),
+        token::CStr(i) => (true, Some(LitKind::CStr(c_str_lit(&i.as_str())))),
+        token::CStrRaw(i, _) => {
+            let mut bytes = raw_str_lit(&i.as_str()).into_bytes();
+            bytes.push(0);
+            (true, Some(LitKind::CStr(Rc::new(bytes))))
+        }
     }
 }
 
@@ -466,41 +982,38 @@ pub fn float_lit(
 
 /// Parse a string representing a byte literal into its final form. Similar to `char_lit`
 pub fn byte_lit(lit: &str) -> (u8, usize) {
-    let err = |i| format!("lexer accepted invalid byte literal {} step {}", lit, i);
-
-    if lit.len() == 1 {
-        (lit.as_bytes()[0], 1)
-    } else {
-        assert_eq!(lit.as_bytes()[0], b'\\', "{}", err(0));
-        let b = match lit.as_bytes()[1] {
-            b'"' => b'"',
-            b'n' => b'\n',
-            b'r' => b'\r',
-            b't' => b'\t',
-            b'\\' => b'\\',
-            b'\'' => b'\'',
-            b'0' => b'\0',
-            _ => match u64::from_str_radix(&lit[2..4], 16).ok() {
-                Some(c) => {
-                    if c > 0xFF {
-                        panic!(err(2))
-                    } else {
-                        return (c as u8, 4);
-                    }
-                }
-                None => panic!(err(3)),
-            },
-        };
-        (b, 2)
+    let bytes = lit.as_bytes();
+    match bytes.first() {
+        Some(b'\\') => {}
+        Some(&b) => return (b, 1),
+        None => return (INVALID_BYTE, 1),
     }
+
+    let b = match bytes.get(1) {
+        Some(b'"') => b'"',
+        Some(b'n') => b'\n',
+        Some(b'r') => b'\r',
+        Some(b't') => b'\t',
+        Some(b'\\') => b'\\',
+        Some(b'\'') => b'\'',
+        Some(b'0') => b'\0',
+        Some(b'x') => {
+            match lit
+                .get(2..4)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            {
+                Some(b) => return (b, 4),
+                None => INVALID_BYTE,
+            }
+        }
+        _ => INVALID_BYTE,
+    };
+    (b, 2)
 }
 
 pub fn byte_str_lit(lit: &str) -> Rc<Vec<u8>> {
     let mut res = Vec::with_capacity(lit.len());
 
-    // FIXME #8372: This could be a for-loop if it didn't borrow the iterator
-    let error = |i| format!("lexer should have rejected {} at {}", lit, i);
-
     /// Eat everything up to a non-whitespace
     fn eat<I: Iterator<Item = (usize, u8)>>(it: &mut iter::Peekable<I>) {
         loop {
@@ -520,17 +1033,11 @@ pub fn byte_str_lit(lit: &str) -> Rc<Vec<u8>> {
     loop {
         match chars.next() {
             Some((i, b'\\')) => {
-                let em = error(i);
-                match chars.peek().expect(&em).1 {
-                    b'\n' => eat(&mut chars),
-                    b'\r' => {
-                        chars.next();
-                        if chars.peek().expect(&em).1 != b'\n' {
-                            panic!("lexer accepted bare CR");
-                        }
-                        eat(&mut chars);
-                    }
-                    _ => {
+                match chars.peek().map(|x| x.1) {
+                    Some(b'\n') | Some(b'\r') => eat(&mut chars),
+                    // A trailing `\`, which the lexer reports.
+                    None => res.push(INVALID_BYTE),
+                    Some(_) => {
                         // otherwise, a normal escape
                         let (c, n) = byte_lit(&lit[i..]);
                         // we don't need to move past the first \
@@ -541,14 +1048,7 @@ pub fn byte_str_lit(lit: &str) -> Rc<Vec<u8>> {
                     }
                 }
             }
-            Some((i, b'\r')) => {
-                let em = error(i);
-                if chars.peek().expect(&em).1 != b'\n' {
-                    panic!("lexer accepted bare CR");
-                }
-                chars.next();
-                res.push(b'\n');
-            }
+            Some((_, b'\r')) => res.push(crlf_to_lf(&mut chars)),
             Some((_, c)) => res.push(c),
             None => break,
         }
@@ -557,6 +1057,56 @@ pub fn byte_str_lit(lit: &str) -> Rc<Vec<u8>> {
     Rc::new(res)
 }
 
+/// Parse a string representing a C string literal into its final form: its
+/// unescaped content followed by a NUL byte. `\x` escapes denote single
+/// bytes, and all other characters are encoded as UTF-8.
+pub fn c_str_lit(lit: &str) -> Rc<Vec<u8>> {
+    debug!("c_str_lit: given {}", escape_default(lit));
+    let mut res = Vec::with_capacity(lit.len() + 1);
+
+    /// Eat everything up to a non-whitespace
+    fn eat(it: &mut iter::Peekable<str::CharIndices>) {
+        while let Some(' ') | Some('\n') | Some('\r') | Some('\t') = it.peek().map(|x| x.1) {
+            it.next();
+        }
+    }
+
+    let mut buf = [0; 4];
+    let mut chars = lit.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                let ch = chars.peek().map(|x| x.1);
+                let byte = lit
+                    .get(i + 2..i + 4)
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+                if ch == Some('\n') || ch == Some('\r') {
+                    eat(&mut chars);
+                } else if let (Some('x'), Some(byte)) = (ch, byte) {
+                    res.push(byte);
+                    for _ in 0..3 {
+                        chars.next();
+                    }
+                } else if ch.is_none() {
+                    // A trailing `\`, which the lexer reports.
+                    res.extend_from_slice(INVALID_CHAR.encode_utf8(&mut buf).as_bytes());
+                } else {
+                    let (c, n) = char_lit(&lit[i..]);
+                    for _ in 0..n - 1 {
+                        chars.next();
+                    }
+                    res.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+            }
+            '\r' => res.push(crlf_to_lf(&mut chars) as u8),
+            c => res.extend_from_slice(c.encode_utf8(&mut buf).as_bytes()),
+        }
+    }
+
+    res.push(0);
+    Rc::new(res)
+}
+
 pub fn integer_lit(
     s: &str,
     suffix: Option<Symbol>,
@@ -677,17 +1227,20 @@ mod tests {
     use super::*;
     use crate::abi::Abi;
     use crate::ast::{self, Ident, PatKind};
-    use crate::attr::first_attr_value_str_by_name;
-    use crate::codemap::Spanned;
+    use crate::attr::{first_attr_value_str_by_name, first_attr_value_str_by_path, has_path};
+    use crate::codemap::{FileLoader, Spanned};
+    use crate::errors::emitter::EmitterWriter;
     use crate::parse;
+    use crate::parse::common::SeqSep;
     use crate::parse::parser::Parser;
-    use crate::print::pprust::item_to_string;
+    use crate::print::pprust::{self, item_to_string};
     use crate::ptr::P;
     use crate::syntax_pos::{self, BytePos, Pos, Span, NO_EXPANSION};
-    use crate::tokenstream::{self, TokenTree};
-    use crate::util::parser_testing::{string_to_expr, string_to_item, string_to_stmt};
-    use crate::util::parser_testing::{string_to_parser, string_to_stream};
+    use crate::testing::{string_to_expr, string_to_item, string_to_stmt};
+    use crate::testing::{string_to_parser, string_to_stream, SharedBuffer};
+    use crate::tokenstream::{self, DelimSpan, TokenTree};
     use crate::util::ThinVec;
+    use std::io;
 
     // produce a syntax_pos::span
     fn sp(a: u32, b: u32) -> Span {
@@ -716,6 +1269,7 @@ mod tests {
                         }
                     ),
                     span: sp(0, 1),
+                    bare_span: sp(0, 1),
                     attrs: ThinVec::new(),
                 })
         )
@@ -732,13 +1286,14 @@ mod tests {
                         ast::Path {
                             span: sp(0, 6),
                             segments: vec![
-                                ast::PathSegment::crate_root(),
+                                ast::PathSegment::crate_root(sp(0, 0)),
                                 str2seg("a", 2, 3),
                                 str2seg("b", 5, 6)
                             ]
                         }
                     ),
                     span: sp(0, 6),
+                    bare_span: sp(0, 6),
                     attrs: ThinVec::new(),
                 })
         )
@@ -808,7 +1363,7 @@ mod tests {
             TokenTree::Token(sp(0, 2), token::Ident(Ident::from_str("fn"))).into(),
             TokenTree::Token(sp(3, 4), token::Ident(Ident::from_str("a"))).into(),
             TokenTree::Delimited(
-                sp(5, 14),
+                DelimSpan::from_pair(sp(5, 6), sp(13, 14)),
                 tokenstream::Delimited {
                     delim: token::DelimToken::Paren,
                     tts: TokenStream::concat(vec![
@@ -821,7 +1376,7 @@ mod tests {
             )
             .into(),
             TokenTree::Delimited(
-                sp(15, 21),
+                DelimSpan::from_pair(sp(15, 16), sp(20, 21)),
                 tokenstream::Delimited {
                     delim: token::DelimToken::Brace,
                     tts: TokenStream::concat(vec![
@@ -853,9 +1408,11 @@ mod tests {
                             }
                         ),
                         span: sp(7, 8),
+                        bare_span: sp(7, 8),
                         attrs: ThinVec::new(),
                     }))),
                     span: sp(0, 8),
+                    bare_span: sp(0, 8),
                     attrs: ThinVec::new(),
                 })
         )
@@ -876,6 +1433,7 @@ mod tests {
                             }
                         ),
                         span: sp(0, 1),
+                        bare_span: sp(0, 1),
                         attrs: ThinVec::new()
                     })),
                     id: ast::DUMMY_NODE_ID,
@@ -946,7 +1504,8 @@ mod tests {
                                 ),
                                 span: sp(6, 7)
                             }),
-                            id: ast::DUMMY_NODE_ID
+                            id: ast::DUMMY_NODE_ID,
+                            span: sp(6, 13)
                         }],
                         output: ast::FunctionRetTy::Default(sp(15, 15)),
                         variadic: false
@@ -956,6 +1515,7 @@ mod tests {
                         span: sp(0, 2),
                         node: ast::Constness::NotConst,
                     },
+                    ast::IsAsync::NotAsync,
                     Abi::Rust,
                     ast::Generics {
                         // no idea on either of these:
@@ -964,6 +1524,7 @@ mod tests {
                         where_clause: ast::WhereClause {
                             id: ast::DUMMY_NODE_ID,
                             predicates: Vec::new(),
+                            span: syntax_pos::DUMMY_SP,
                         },
                         span: syntax_pos::DUMMY_SP,
                     },
@@ -979,6 +1540,7 @@ mod tests {
                                     }
                                 ),
                                 span: sp(17, 18),
+                                bare_span: sp(17, 18),
                                 attrs: ThinVec::new()
                             })),
                             id: ast::DUMMY_NODE_ID,
@@ -1103,6 +1665,9 @@ mod tests {
             .unwrap();
         let doc = first_attr_value_str_by_name(&item.attrs, "doc").unwrap();
         assert_eq!(doc, "/// doc comment");
+        let doc = item.attrs[0].value_str_spanned().unwrap();
+        let snippet = sess.codemap().span_to_snippet(doc.span).unwrap();
+        assert_eq!(snippet, "/// doc comment");
 
         let source = "/// doc comment\r\n/// line 2\r\nfn foo() {}".to_string();
         let item = parse_item_from_source_str(name.clone(), source, &sess)
@@ -1126,39 +1691,330 @@ mod tests {
     }
 
     #[test]
-    fn ttdelim_span() {
+    fn desugared_block_doc_comments() {
         let sess = ParseSess::new(FilePathMapping::empty());
-        let expr = parse::parse_expr_from_source_str(
-            "foo".to_string(),
-            "foo!( fn main() { body } )".to_string(),
-            &sess,
-        )
-        .unwrap();
-
-        let tts: Vec<_> = match expr.node {
-            ast::ExprKind::Mac(ref mac) => mac.node.stream().trees().collect(),
-            _ => panic!("not a macro"),
+        let desugared = |source: &str| {
+            let item =
+                parse_item_from_source_str("<source>".to_string(), source.to_string(), &sess)
+                    .unwrap()
+                    .unwrap();
+            item.attrs[0].with_desugared_doc(|a| a.value_str().unwrap().to_string())
         };
 
-        let span = tts.iter().rev().next().unwrap().span();
-
-        match sess.codemap().span_to_snippet(span) {
-            Ok(s) => assert_eq!(&s[..], "{ body }"),
-            Err(_) => panic!("could not get snippet"),
-        }
+        assert_eq!(
+            desugared("/** outer /* inner */ */\nfn foo() {}"),
+            " outer /* inner */ "
+        );
+        assert_eq!(
+            desugared("/** doc comment\r\n *  with CRLF */\r\nfn foo() {}"),
+            " doc comment\n  with CRLF "
+        );
+        assert_eq!(
+            desugared("/**\n * first\n *\n * second\n */\nfn foo() {}"),
+            " first\n\n second"
+        );
     }
 
-    // This tests that when parsing a string (rather than a file) we don't try
-    // and read in a file for a module declaration and just parse a stub.
-    // See `recurse_into_file_modules` in the parser.
     #[test]
-    fn out_of_line_mod() {
+    fn parse_stmts() {
         let sess = ParseSess::new(FilePathMapping::empty());
-        let item = parse_item_from_source_str(
-            "foo".to_owned(),
-            "mod foo { struct S; mod this_does_not_exist; }".to_owned(),
-            &sess,
-        )
+        let parse = |source: &str| {
+            parse_stmts_from_source_str("<stmts>".to_string(), source.to_string(), &sess)
+        };
+
+        let stmts = parse("fn f() {}\nstruct S;\nlet x = f();\n;\nx + 1").unwrap();
+        let kinds = stmts
+            .iter()
+            .map(|stmt| match stmt.node {
+                ast::StmtKind::Item(..) => "item",
+                ast::StmtKind::Local(..) => "local",
+                ast::StmtKind::Expr(..) => "expr",
+                ast::StmtKind::Semi(..) => "semi",
+                ast::StmtKind::Mac(..) => "mac",
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, ["item", "item", "local", "expr"]);
+        match stmts[3].node {
+            ast::StmtKind::Expr(ref expr) => assert_eq!(pprust::expr_to_string(expr), "x + 1"),
+            _ => unreachable!(),
+        }
+
+        let stmts = parse("if a { b } else { c } d;").unwrap();
+        assert_eq!(stmts.len(), 2);
+
+        assert!(parse("").unwrap().is_empty());
+        assert!(parse("  // nothing\n").unwrap().is_empty());
+
+        // Statements that fail to parse are reported and skipped.
+        let output = SharedBuffer::default();
+        let sess = json_sess(&output);
+        let stmts = parse_stmts_from_source_str(
+            "<stmts>".to_string(),
+            "let x = 1; => 2; x".to_string(),
+            &sess,
+        )
+        .unwrap();
+        assert_eq!(stmts.len(), 2);
+        let errors = output.json_diagnostics();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0]["message"],
+            "expected expression, found the `=>` token"
+        );
+    }
+
+    #[test]
+    fn parse_block() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let parse = |source: &str| {
+            parse_block_from_source_str("<block>".to_string(), source.to_string(), &sess)
+        };
+
+        let block = parse("{ fn f() -> u8 { 1 } let x = f(); x }").unwrap();
+        assert_eq!(block.stmts.len(), 3);
+        match block.stmts[2].node {
+            ast::StmtKind::Expr(ref expr) => assert_eq!(pprust::expr_to_string(expr), "x"),
+            _ => panic!("expected a trailing expression"),
+        }
+
+        assert!(parse("{}").unwrap().stmts.is_empty());
+
+        let mut err = parse("").unwrap_err();
+        assert_eq!(err.message(), "expected `{`, found end of input");
+        err.cancel();
+
+        let mut err = parse("{ x } y").unwrap_err();
+        assert_eq!(err.message(), "expected end of input, found `y`");
+        err.cancel();
+    }
+
+    #[test]
+    fn fn_decl_spans() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let source = "fn f(a: u8, b: u16) -> u32 {}\nimpl S { fn g(&self , c: u8 ,) {} }";
+        let krate =
+            parse_crate_from_source_str("decl.rs".to_string(), source.to_string(), &sess).unwrap();
+        let decls = krate
+            .module
+            .items
+            .iter()
+            .map(|item| match item.node {
+                ast::ItemKind::Fn(ref decl, ..) => decl,
+                ast::ItemKind::Impl(.., ref items) => match items[0].node {
+                    ast::ImplItemKind::Method(ref sig, _) => &sig.decl,
+                    _ => panic!("expected a method"),
+                },
+                _ => panic!("expected a function or an impl"),
+            })
+            .collect::<Vec<_>>();
+        let cm = sess.codemap();
+        let snippet = |span| cm.span_to_snippet(span).unwrap();
+
+        let f = &decls[0];
+        let args = f
+            .inputs
+            .iter()
+            .map(|arg| (snippet(arg.span), snippet(arg.span_with_comma(cm))))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            args,
+            [
+                ("a: u8".to_string(), "a: u8,".to_string()),
+                ("b: u16".to_string(), "b: u16".to_string()),
+            ]
+        );
+        assert_eq!(snippet(f.output.arrow_span().unwrap()), "->");
+        assert_eq!(snippet(f.output.span()), "u32");
+
+        let g = &decls[1];
+        let args = g
+            .inputs
+            .iter()
+            .map(|arg| (snippet(arg.span), snippet(arg.span_with_comma(cm))))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            args,
+            [
+                ("&self".to_string(), "&self ,".to_string()),
+                ("c: u8".to_string(), "c: u8 ,".to_string()),
+            ]
+        );
+        assert_eq!(g.output.arrow_span(), None);
+    }
+
+    #[test]
+    fn attributes_in_expr_and_stmt_spans() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let source = "fn f() {\n    #[allow(x)] foo();\n    #[a] #[b] -x;\n    \
+                      let y = #[c] bar.baz(1);\n    #[d] m!();\n    #[e] let z = 1;\n}";
+        let item = parse_item_from_source_str("attrs.rs".to_string(), source.to_string(), &sess)
+            .unwrap()
+            .unwrap();
+        let body = match item.node {
+            ast::ItemKind::Fn(.., ref body) => body,
+            _ => panic!("expected a function"),
+        };
+        let snippet = |span| sess.codemap().span_to_snippet(span).unwrap();
+
+        let stmts = body
+            .stmts
+            .iter()
+            .map(|stmt| snippet(stmt.span))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stmts,
+            [
+                "#[allow(x)] foo();",
+                "#[a] #[b] -x;",
+                "let y = #[c] bar.baz(1);",
+                "#[d] m!();",
+                "#[e] let z = 1;",
+            ]
+        );
+
+        let exprs = body.stmts[..3]
+            .iter()
+            .map(|stmt| match stmt.node {
+                ast::StmtKind::Semi(ref expr) => expr,
+                ast::StmtKind::Local(ref local) => local.init.as_ref().unwrap(),
+                _ => panic!("expected an expression or a `let`"),
+            })
+            .map(|expr| (snippet(expr.span), snippet(expr.span_without_attrs())))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            exprs,
+            [
+                ("#[allow(x)] foo()".to_string(), "foo()".to_string()),
+                ("#[a] #[b] -x".to_string(), "-x".to_string()),
+                ("#[c] bar.baz(1)".to_string(), "bar.baz(1)".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn where_clauses() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let source = "fn f<'a, 'b, T>(t: T)\nwhere\n    T: Iterator + 'a,\n    'a: 'b,\n    \
+                      T::Item = u32,\n{\n}";
+        let item = parse_item_from_source_str("where.rs".to_string(), source.to_string(), &sess)
+            .unwrap()
+            .unwrap();
+        let generics = match item.node {
+            ast::ItemKind::Fn(_, _, _, _, _, ref generics, _) => generics,
+            _ => panic!("expected a function"),
+        };
+
+        let where_clause = &generics.where_clause;
+        let snippet = sess.codemap().span_to_snippet(where_clause.span).unwrap();
+        assert!(snippet.starts_with("where"), "{}", snippet);
+        assert!(snippet.ends_with("T::Item = u32"), "{}", snippet);
+
+        let predicates = where_clause
+            .predicates
+            .iter()
+            .map(|p| sess.codemap().span_to_snippet(p.span()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(predicates, ["T: Iterator + 'a", "'a: 'b", "T::Item = u32"]);
+        match where_clause.predicates[2] {
+            ast::WherePredicate::EqPredicate(ref eq) => {
+                assert_eq!(pprust::ty_to_string(&eq.lhs_ty), "T::Item");
+                assert_eq!(pprust::ty_to_string(&eq.rhs_ty), "u32");
+            }
+            _ => panic!("expected an equality predicate"),
+        }
+
+        let printed = item_to_string(&item);
+        assert!(
+            printed.contains("where T: Iterator + 'a, 'a: 'b, T::Item = u32"),
+            "{}",
+            printed
+        );
+        let reparsed = parse_item_from_source_str("where2.rs".to_string(), printed.clone(), &sess)
+            .unwrap()
+            .unwrap();
+        assert_eq!(item_to_string(&reparsed), printed);
+
+        let item = string_to_item("fn g<T>() where T: Copy {}".to_string()).unwrap();
+        match item.node {
+            ast::ItemKind::Fn(_, _, _, _, _, ref generics, _) => {
+                assert_eq!(generics.where_clause.span, sp(10, 23))
+            }
+            _ => panic!("expected a function"),
+        }
+        let item = string_to_item("fn g<T>() {}".to_string()).unwrap();
+        match item.node {
+            ast::ItemKind::Fn(_, _, _, _, _, ref generics, _) => {
+                assert_eq!(generics.where_clause.span, syntax_pos::DUMMY_SP)
+            }
+            _ => panic!("expected a function"),
+        }
+    }
+
+    #[test]
+    fn attribute_paths() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let source = "#[rustfmt::skip]\n#[tool::name = \"x\"]\n#[skip]\nfn f() {}".to_string();
+        let item = parse_item_from_source_str("attrs.rs".to_string(), source, &sess)
+            .unwrap()
+            .unwrap();
+
+        assert!(has_path(&item.attrs, &["rustfmt", "skip"]));
+        assert!(has_path(&item.attrs, &["skip"]));
+        assert!(!has_path(&item.attrs, &["rustfmt"]));
+        assert!(!has_path(&item.attrs, &["clippy", "skip"]));
+        assert!(item.attrs[0].path_matches(&["rustfmt", "skip"]));
+        assert!(!item.attrs[0].path_matches(&["skip"]));
+        assert_eq!(
+            first_attr_value_str_by_path(&item.attrs, &["tool", "name"]),
+            Some(Symbol::intern("x"))
+        );
+        assert_eq!(
+            first_attr_value_str_by_path(&item.attrs, &["rustfmt", "skip"]),
+            None
+        );
+        assert!(sess.is_tool_attr(&item.attrs[0]));
+        assert!(!sess.is_tool_attr(&item.attrs[1]));
+        assert!(!sess.is_tool_attr(&item.attrs[2]));
+
+        let printed = item_to_string(&item);
+        assert!(printed.contains("#[rustfmt::skip]"), "{}", printed);
+        assert!(printed.contains("#[tool::name = \"x\"]"), "{}", printed);
+    }
+
+    #[test]
+    fn ttdelim_span() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let expr = parse::parse_expr_from_source_str(
+            "foo".to_string(),
+            "foo!( fn main() { body } )".to_string(),
+            &sess,
+        )
+        .unwrap();
+
+        let tts: Vec<_> = match expr.node {
+            ast::ExprKind::Mac(ref mac) => mac.node.stream().trees().collect(),
+            _ => panic!("not a macro"),
+        };
+
+        let span = tts.iter().rev().next().unwrap().span();
+
+        match sess.codemap().span_to_snippet(span) {
+            Ok(s) => assert_eq!(&s[..], "{ body }"),
+            Err(_) => panic!("could not get snippet"),
+        }
+    }
+
+    // This tests that when parsing a string (rather than a file) we don't try
+    // and read in a file for a module declaration and just parse a stub.
+    // See `recurse_into_file_modules` in the parser.
+    #[test]
+    fn out_of_line_mod() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let item = parse_item_from_source_str(
+            "foo".to_owned(),
+            "mod foo { struct S; mod this_does_not_exist; }".to_owned(),
+            &sess,
+        )
         .unwrap()
         .unwrap();
 
@@ -1168,4 +2024,2156 @@ mod tests {
             panic!();
         }
     }
+
+    /// A FileLoader serving files from memory.
+    struct VirtualFileLoader(Vec<(&'static str, &'static str)>);
+
+    impl FileLoader for VirtualFileLoader {
+        fn file_exists(&self, path: &Path) -> bool {
+            self.0.iter().any(|&(name, _)| Path::new(name) == path)
+        }
+
+        fn abs_path(&self, path: &Path) -> Option<PathBuf> {
+            Some(path.to_path_buf())
+        }
+
+        fn read_file(&self, path: &Path) -> io::Result<String> {
+            self.0
+                .iter()
+                .find(|&&(name, _)| Path::new(name) == path)
+                .map(|&(_, src)| src.to_owned())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file"))
+        }
+    }
+
+    #[test]
+    fn mod_inclusion_callback() {
+        let loader = VirtualFileLoader(vec![
+            ("/src/main.rs", "mod a;\nmod c;\nfn main() {}\n"),
+            ("/src/a/mod.rs", "mod b;\n"),
+            ("/src/a/b.rs", "fn b() {}\n"),
+            ("/src/c.rs", "fn c() {}\n"),
+        ]);
+        let cm = Rc::new(CodeMap::with_file_loader(
+            Box::new(loader),
+            FilePathMapping::empty(),
+        ));
+        let emitter = EmitterWriter::new(Box::new(io::sink()), Some(cm.clone()));
+        let handler = Handler::with_emitter(true, false, Box::new(emitter));
+        let mut sess = ParseSess::with_span_handler(handler, cm);
+
+        let included = Rc::new(RefCell::new(vec![]));
+        let included2 = included.clone();
+        sess.set_mod_inclusion_callback(Box::new(move |path, _| {
+            included2.borrow_mut().push(path.to_path_buf())
+        }));
+
+        let krate = parse_crate_from_file(Path::new("/src/main.rs"), &sess).unwrap();
+        assert_eq!(krate.module.items.len(), 3);
+        assert_eq!(
+            *included.borrow(),
+            vec![
+                PathBuf::from("/src/a/mod.rs"),
+                PathBuf::from("/src/a/b.rs"),
+                PathBuf::from("/src/c.rs"),
+            ]
+        );
+        assert!(sess.included_mod_stack().is_empty());
+    }
+
+    /// Records every call to its methods in `events`, and cancels parsing
+    /// after `cancel_after` items.
+    struct RecordingCallbacks {
+        events: Rc<RefCell<Vec<String>>>,
+        cancel_after: Option<usize>,
+    }
+
+    impl ParseCallbacks for RecordingCallbacks {
+        fn on_filemap_loaded(&mut self, filemap: &FileMap) {
+            self.events
+                .borrow_mut()
+                .push(format!("load {}", filemap.name));
+        }
+
+        fn on_item_parsed(
+            &mut self,
+            name: Option<Symbol>,
+            _span: Span,
+            items_so_far: usize,
+        ) -> ControlFlow<()> {
+            let name = name.map_or("_".to_string(), |name| name.to_string());
+            self.events
+                .borrow_mut()
+                .push(format!("item {} {}", items_so_far, name));
+            if self.cancel_after == Some(items_so_far) {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        }
+
+        fn on_mod_file_enter(&mut self, path: &Path) {
+            self.events
+                .borrow_mut()
+                .push(format!("enter {}", path.display()));
+        }
+
+        fn on_mod_file_exit(&mut self, path: &Path) {
+            self.events
+                .borrow_mut()
+                .push(format!("exit {}", path.display()));
+        }
+    }
+
+    /// Parses the crate `/src/main.rs` of a small fixture, cancelling after
+    /// `cancel_after` items, and returns the error message if any along with
+    /// the events recorded.
+    fn parse_with_callbacks(cancel_after: Option<usize>) -> (Result<(), String>, Vec<String>) {
+        let loader = VirtualFileLoader(vec![
+            (
+                "/src/main.rs",
+                "use std::fmt;\nmod a;\nmod c;\nfn main() {}\n",
+            ),
+            ("/src/a/mod.rs", "mod b;\nimpl S {}\n"),
+            ("/src/a/b.rs", "fn b() {}\n"),
+            ("/src/c.rs", "mod d {\n    fn d() {}\n}\n"),
+        ]);
+        let cm = Rc::new(CodeMap::with_file_loader(
+            Box::new(loader),
+            FilePathMapping::empty(),
+        ));
+        let emitter = EmitterWriter::new(Box::new(io::sink()), Some(cm.clone()));
+        let handler = Handler::with_emitter(true, false, Box::new(emitter));
+        let mut sess = ParseSess::with_span_handler(handler, cm);
+
+        let events = Rc::new(RefCell::new(vec![]));
+        sess.set_parse_callbacks(Box::new(RecordingCallbacks {
+            events: events.clone(),
+            cancel_after,
+        }));
+        let result = match parse_crate_from_file(Path::new("/src/main.rs"), &sess) {
+            Ok(_) => Ok(()),
+            Err(mut err) => {
+                let message = err.message();
+                err.cancel();
+                Err(message)
+            }
+        };
+        assert!(sess.included_mod_stack().is_empty());
+        let events = events.borrow().clone();
+        (result, events)
+    }
+
+    #[test]
+    fn parse_callbacks() {
+        let (result, events) = parse_with_callbacks(None);
+        assert!(result.is_ok());
+        assert_eq!(
+            events,
+            vec![
+                "load /src/main.rs",
+                "item 1 _",
+                "enter /src/a/mod.rs",
+                "load /src/a/mod.rs",
+                "enter /src/a/b.rs",
+                "load /src/a/b.rs",
+                "item 2 b",
+                "exit /src/a/b.rs",
+                "item 3 b",
+                "item 4 _",
+                "exit /src/a/mod.rs",
+                "item 5 a",
+                "enter /src/c.rs",
+                "load /src/c.rs",
+                "item 6 d",
+                "item 7 d",
+                "exit /src/c.rs",
+                "item 8 c",
+                "item 9 main",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_callbacks_cancel() {
+        let (result, events) = parse_with_callbacks(Some(3));
+        assert_eq!(result.unwrap_err(), "parsing cancelled");
+        assert_eq!(
+            events,
+            vec![
+                "load /src/main.rs",
+                "item 1 _",
+                "enter /src/a/mod.rs",
+                "load /src/a/mod.rs",
+                "enter /src/a/b.rs",
+                "load /src/a/b.rs",
+                "item 2 b",
+                "exit /src/a/b.rs",
+                "item 3 b",
+                "exit /src/a/mod.rs",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_fragment_specifiers() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let stream = string_to_stream("$a:expr $b".to_string());
+        crate::ext::tt::quoted::parse(stream, true, &sess);
+        let stream = string_to_stream("$c:ident, $d".to_string());
+        crate::ext::tt::quoted::parse(stream, true, &sess);
+        assert_eq!(
+            sess.take_missing_fragment_specifiers(),
+            vec![sp(8, 10), sp(10, 12)]
+        );
+        assert!(sess.take_missing_fragment_specifiers().is_empty());
+    }
+
+    #[test]
+    fn parse_recovers_from_emoji_idents() {
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let emitter = EmitterWriter::new(Box::new(io::sink()), Some(cm.clone()));
+        let handler = Handler::with_emitter(true, false, Box::new(emitter));
+        let sess = ParseSess::with_span_handler(handler, cm);
+        let src = "fn main() { let 🦀 = 1; let y = 🦀 + 1; }\nfn f§() {}\n";
+        let krate =
+            parse_crate_from_source_str("emoji.rs".to_string(), src.to_string(), &sess).unwrap();
+        assert_eq!(krate.module.items.len(), 2);
+        assert_eq!(sess.span_diagnostic.err_count(), 3);
+    }
+
+    /// Parses `src` as a parenthesized list of identifiers separated by
+    /// `sep`, returning the identifiers, whether there was a trailing
+    /// separator, and the number of errors recovered from.
+    fn parse_ident_list(src: &str, sep: SeqSep) -> (Vec<String>, bool, usize) {
+        let (sess, _diagnostics) = ParseSess::new_for_batch(FilePathMapping::empty());
+        let mut parser = string_to_parser(&sess, src.to_string());
+        let (idents, trailing_sep, mut errors) = panictry!(parser.parse_delimited_seq(
+            &token::OpenDelim(token::Paren),
+            &token::CloseDelim(token::Paren),
+            sep,
+            |p| p.parse_ident(),
+        ));
+        assert_eq!(parser.token, token::Eof);
+        // The errors are left to the caller.
+        assert_eq!(sess.span_diagnostic.err_count(), 0);
+        for err in &mut errors {
+            err.cancel();
+        }
+        let idents = idents.iter().map(|ident| ident.to_string()).collect();
+        (idents, trailing_sep, errors.len())
+    }
+
+    #[test]
+    fn delimited_seq() {
+        let comma = || SeqSep::trailing_allowed(token::Comma);
+        let list = |idents: &[&str]| idents.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse_ident_list("()", comma()), (vec![], false, 0));
+        assert_eq!(
+            parse_ident_list("(a, b)", comma()),
+            (list(&["a", "b"]), false, 0)
+        );
+        assert_eq!(
+            parse_ident_list("(a, b,)", comma()),
+            (list(&["a", "b"]), true, 0)
+        );
+        assert_eq!(
+            parse_ident_list("(a b)", SeqSep::none()),
+            (list(&["a", "b"]), false, 0)
+        );
+    }
+
+    #[test]
+    fn delimited_seq_recovery() {
+        let comma = || SeqSep::trailing_allowed(token::Comma);
+        let list = |idents: &[&str]| idents.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        // A bad element in the middle is skipped up to the next separator.
+        assert_eq!(
+            parse_ident_list("(a, b, 1 + 2, c, d)", comma()),
+            (list(&["a", "b", "c", "d"]), false, 1)
+        );
+        // As is one at the end, up to the closing delimiter.
+        assert_eq!(
+            parse_ident_list("(a, [b])", comma()),
+            (list(&["a"]), false, 1)
+        );
+        // A missing separator is assumed.
+        assert_eq!(
+            parse_ident_list("(a b, c,)", comma()),
+            (list(&["a", "b", "c"]), true, 1)
+        );
+        let no_trailing = SeqSep {
+            sep: Some(token::Comma),
+            trailing_sep_allowed: false,
+        };
+        assert_eq!(
+            parse_ident_list("(a,)", no_trailing),
+            (list(&["a"]), true, 1)
+        );
+    }
+
+    #[test]
+    fn meta_seq_errors_are_returned() {
+        let (sess, _diagnostics) = ParseSess::new_for_batch(FilePathMapping::empty());
+        let parse = |src: &str| {
+            let mut parser = string_to_parser(&sess, src.to_string());
+            let result = parser.parse_meta_item();
+            assert_eq!(parser.token, token::Eof);
+            result
+        };
+
+        // The whole list is consumed, and its first error returned.
+        let mut err = parse("foo(a, = 1, b = \"c\" d)").unwrap_err();
+        assert_eq!(
+            err.message(),
+            "expected unsuffixed literal or identifier, found the `=` token"
+        );
+        err.cancel();
+
+        // The error of a nested list is cancelled by `parse_meta_item_inner`
+        // in favour of its own.
+        let mut err = parse("foo(bar(= 1), c)").unwrap_err();
+        assert_eq!(
+            err.message(),
+            "expected unsuffixed literal or identifier, found the `,` token"
+        );
+        err.cancel();
+        assert_eq!(sess.span_diagnostic.err_count(), 0);
+    }
+
+    /// Parse `src` as a meta item, returning it along with the JSON
+    /// diagnostics that were emitted.
+    fn parse_meta_with_json(src: &str) -> (ast::MetaItem, Vec<serde_json::Value>) {
+        let output = SharedBuffer::default();
+        let sess = ParseSess::with_json_emitter(FilePathMapping::empty(), Box::new(output.clone()));
+        let meta = panictry!(string_to_parser(&sess, src.to_string()).parse_meta_item());
+        (meta, output.json_diagnostics())
+    }
+
+    fn suggested_replacements(suggestion: &serde_json::Value) -> Vec<(u64, u64, String)> {
+        suggestion["spans"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|span| {
+                (
+                    span["byte_start"].as_u64().unwrap(),
+                    span["byte_end"].as_u64().unwrap(),
+                    span["suggested_replacement"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn adjacent_str_lits_in_doc_attr() {
+        let (meta, diagnostics) = parse_meta_with_json(r#"doc = "a" r"b" "c\n""#);
+        assert_eq!(meta.value_str().unwrap(), "abc\n");
+        match meta.node {
+            ast::MetaItemKind::NameValue(ref lit) => assert_eq!(lit.span, sp(6, 20)),
+            _ => panic!("expected a name-value meta item"),
+        }
+
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        assert_eq!(
+            diagnostic["message"],
+            "adjacent string literals are not allowed in attributes"
+        );
+        let suggestions = diagnostic["children"].as_array().unwrap();
+        assert_eq!(suggestions.len(), 2);
+        assert_eq!(
+            suggested_replacements(&suggestions[0]),
+            [
+                (6, 6, "concat!(".to_string()),
+                (9, 10, ", ".to_string()),
+                (14, 15, ", ".to_string()),
+                (20, 20, ")".to_string()),
+            ]
+        );
+        assert_eq!(
+            suggested_replacements(&suggestions[1]),
+            [(6, 20, r#""abc\n""#.to_string())]
+        );
+    }
+
+    #[test]
+    fn adjacent_str_lits_in_name_value_list() {
+        let (meta, diagnostics) = parse_meta_with_json(r#"foo(bar = "a" "b", baz = "c")"#);
+        let list = meta.meta_item_list().unwrap();
+        let values: Vec<_> = list
+            .iter()
+            .map(|item| item.value_str().unwrap().to_string())
+            .collect();
+        assert_eq!(values, ["ab", "c"]);
+
+        assert_eq!(diagnostics.len(), 1);
+        let suggestions = diagnostics[0]["children"].as_array().unwrap();
+        assert_eq!(
+            suggested_replacements(&suggestions[1]),
+            [(10, 17, r#""ab""#.to_string())]
+        );
+    }
+
+    fn json_sess(output: &SharedBuffer) -> ParseSess {
+        ParseSess::with_json_emitter(FilePathMapping::empty(), Box::new(output.clone()))
+    }
+
+    #[test]
+    fn parse_item_detailed() {
+        use crate::parse::parser::NoItemReason;
+
+        let parse = |source: &str| {
+            let output = SharedBuffer::default();
+            let sess = json_sess(&output);
+            let outcome = parse_item_detailed_from_source_str(
+                "item.rs".to_string(),
+                source.to_string(),
+                &sess,
+            )
+            .unwrap();
+            let codes = output
+                .json_diagnostics()
+                .iter()
+                .map(|d| d["code"]["code"].as_str().unwrap_or("").to_string())
+                .collect::<Vec<_>>();
+            match outcome {
+                ItemParseOutcome::Item(item) => (Ok(item.ident.to_string()), codes),
+                ItemParseOutcome::NoItem { reason, stopped_at } => {
+                    (Err((reason, stopped_at.lo.0, stopped_at.hi.0)), codes)
+                }
+            }
+        };
+
+        assert_eq!(parse("fn f() {}"), (Ok("f".to_string()), vec![]));
+        assert_eq!(parse(""), (Err((NoItemReason::Eof, 0, 0)), vec![]));
+        assert_eq!(
+            parse("  // a comment\n"),
+            (Err((NoItemReason::Eof, 15, 15)), vec![])
+        );
+        assert_eq!(
+            parse("/// docs\n"),
+            (
+                Err((NoItemReason::DanglingAttributes, 8, 8)),
+                vec!["E0585".to_string()]
+            )
+        );
+        assert_eq!(
+            parse("#[inline]\n1 + 2"),
+            (
+                Err((NoItemReason::DanglingAttributes, 10, 11)),
+                vec!["".to_string()]
+            )
+        );
+        assert_eq!(
+            parse("1 + 2"),
+            (Err((NoItemReason::NotAnItem, 0, 1)), vec![])
+        );
+
+        // The old API cannot tell these apart.
+        let sess = ParseSess::new(FilePathMapping::empty());
+        assert!(
+            parse_item_from_source_str("a.rs".to_string(), "".to_string(), &sess)
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            parse_item_from_source_str("b.rs".to_string(), "1 + 2".to_string(), &sess)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn deeply_nested_parens() {
+        let output = SharedBuffer::default();
+        let depth = 10_000;
+        let src = format!(
+            "fn main() {{ {}1{} + 2; }}",
+            "(".repeat(depth),
+            ")".repeat(depth)
+        );
+        let sess = json_sess(&output);
+        let krate = parse_crate_from_source_str("nested".to_string(), src, &sess).unwrap();
+        assert_eq!(krate.module.items.len(), 1);
+
+        // The innermost delimiter within the limit is the `{` followed by 255
+        // parentheses; the next one is reported and skipped.
+        let diagnostics = output.json_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["message"], "delimiter nesting too deep");
+        let lo = ("fn main() { ".len() + DEFAULT_NESTING_LIMIT - 1) as u64;
+        assert_eq!(diagnostics[0]["spans"][0]["byte_start"], lo);
+        assert_eq!(diagnostics[0]["spans"][0]["byte_end"], lo + 1);
+    }
+
+    #[test]
+    fn nesting_up_to_the_limit() {
+        // Each of these takes the most stack per level of nesting, and is
+        // parsed on the test thread's default stack.
+        let depth = DEFAULT_NESTING_LIMIT - 2;
+        let sources = [
+            format!(
+                "fn main() {{ {}1{} }}",
+                "if a { ".repeat(depth),
+                " }".repeat(depth)
+            ),
+            format!(
+                "fn main() {{ {}1{}; }}",
+                "S { a: ".repeat(depth),
+                " }".repeat(depth)
+            ),
+            format!("type T = {}u8{};", "(".repeat(depth), ",)".repeat(depth)),
+            format!(
+                "fn main() {{ let {}x{} = 1; }}",
+                "(".repeat(depth),
+                ",)".repeat(depth)
+            ),
+            format!("{}{}", "mod m { ".repeat(depth), "}".repeat(depth)),
+        ];
+        for src in &sources {
+            let output = SharedBuffer::default();
+            let sess = json_sess(&output);
+            let krate =
+                parse_crate_from_source_str("nested".to_string(), src.clone(), &sess).unwrap();
+            assert_eq!(krate.module.items.len(), 1);
+            assert!(
+                output.json_diagnostics().is_empty(),
+                "{:?}",
+                output.json_diagnostics()
+            );
+        }
+    }
+
+    #[test]
+    fn deeply_nested_items() {
+        // Parsed on the test thread's default stack, past the delimiter
+        // nesting limit.
+        for open in &["fn f() { ", "mod m { fn f() { ", "impl S { fn f() { "] {
+            let depth = 1_000;
+            let close = "} ".repeat(open.matches('{').count());
+            let src = format!("{}{}", open.repeat(depth), close.repeat(depth));
+            let output = SharedBuffer::default();
+            let sess = json_sess(&output);
+            let krate = parse_crate_from_source_str("nested".to_string(), src, &sess).unwrap();
+            assert_eq!(krate.module.items.len(), 1);
+
+            let diagnostics = output.json_diagnostics();
+            assert!(!diagnostics.is_empty());
+            assert!(diagnostics
+                .iter()
+                .all(|d| d["message"].as_str().unwrap().ends_with("nesting too deep")));
+        }
+    }
+
+    #[test]
+    fn nesting_limit_without_delimiters() {
+        let output = SharedBuffer::default();
+        let mut sess = json_sess(&output);
+        sess.set_nesting_limit(4);
+
+        let mut parser = string_to_parser(&sess, "&&&&&&&&u8".to_string());
+        let ty = panictry!(parser.parse_ty());
+        assert_eq!(pprust::ty_to_string(&ty), "&&&&?");
+        assert_eq!(parser.token, token::Eof);
+
+        let mut parser = string_to_parser(&sess, "|| || || || || 1".to_string());
+        let expr = panictry!(parser.parse_expr());
+        assert_eq!(pprust::expr_to_string(&expr), "|| || || || ()");
+        assert_eq!(parser.token, token::Eof);
+
+        for &(src, expected) in &[
+            ("!!!!!!!!x", "!!!!()"),
+            ("a = a = a = a = a = a", "a = a = a = a = ()"),
+        ] {
+            let mut parser = string_to_parser(&sess, src.to_string());
+            let expr = panictry!(parser.parse_expr());
+            assert_eq!(pprust::expr_to_string(&expr), expected);
+            assert_eq!(parser.token, token::Eof);
+        }
+
+        let messages: Vec<_> = output
+            .json_diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic["message"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "type nesting too deep",
+                "expression nesting too deep",
+                "expression nesting too deep",
+                "expression nesting too deep",
+            ]
+        );
+    }
+
+    #[test]
+    fn nesting_limit_skips_to_close_delim() {
+        let output = SharedBuffer::default();
+        let mut sess = json_sess(&output);
+        sess.set_nesting_limit(2);
+
+        let src = "fn f() { g(|| || x, y); h() }";
+        let mut parser = string_to_parser(&sess, src.to_string());
+        let item = panictry!(parser.parse_item()).unwrap();
+        assert_eq!(pprust::item_to_string(&item), "fn f() { g(|| || ()); h() }");
+
+        let diagnostics = output.json_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["message"], "expression nesting too deep");
+        assert_eq!(diagnostics[0]["spans"][0]["byte_start"], 10);
+    }
+
+    fn expect_lexing_error(src: &str, message: &str, lo: u32, hi: u32) {
+        let output = SharedBuffer::default();
+        let sess = json_sess(&output);
+        let name = "unterminated".to_string();
+        let mut err = match maybe_parse_stream_from_source_str(name, src.to_string(), &sess) {
+            Ok(_) => panic!("expected a lexing error in {:?}", src),
+            Err(err) => err,
+        };
+        assert_eq!(err.message(), message);
+        assert_eq!(err.span.primary_span(), Some(sp(lo, hi)));
+        err.cancel();
+        assert!(output.json_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn maybe_stream_unterminated_str() {
+        let src = "fn f() { let s = \"abc";
+        expect_lexing_error(src, "unterminated double quote string", 17, 21);
+    }
+
+    #[test]
+    fn maybe_stream_unterminated_first_token() {
+        expect_lexing_error("/* abc", "unterminated block comment", 0, 6);
+        expect_lexing_error("'\\u{12", "unterminated unicode escape (found EOF)", 4, 6);
+    }
+
+    #[test]
+    fn no_panic_returns_lexing_errors() {
+        let output = SharedBuffer::default();
+        let sess = json_sess(&output);
+        let src = "fn f() {}\nfn g() { '\\u{12 }".to_string();
+        let mut err = match parse_crate_from_source_str_no_panic("lex".to_string(), src, &sess) {
+            Ok(_) => panic!("expected a lexing error"),
+            Err(err) => err,
+        };
+        assert_eq!(
+            err.message(),
+            "character literal may only contain one codepoint: '\\u{12 }"
+        );
+        err.cancel();
+
+        let src = "fn f() { let x = 1 + ; }".to_string();
+        let krate = panictry!(parse_crate_from_source_str_no_panic(
+            "recovered".to_string(),
+            src,
+            &sess
+        ));
+        assert_eq!(krate.module.items.len(), 1);
+    }
+
+    // Parses `src` as an expression, returning it pretty-printed along with
+    // the message and byte range of each error.
+    fn expr_with_errors(src: &str) -> (String, Vec<(String, u64, u64)>) {
+        let output = SharedBuffer::default();
+        let sess = json_sess(&output);
+        let expr = panictry!(parse_expr_from_source_str(
+            "numbers".to_string(),
+            src.to_string(),
+            &sess
+        ));
+        let errors = output
+            .json_diagnostics()
+            .iter()
+            .map(|d| {
+                let span = &d["spans"][0];
+                (
+                    d["message"].as_str().unwrap().to_string(),
+                    span["byte_start"].as_u64().unwrap(),
+                    span["byte_end"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        (pprust::expr_to_string(&expr), errors)
+    }
+
+    #[test]
+    fn invalid_numeric_literals() {
+        let error = |message: &str, lo, hi| vec![(message.to_string(), lo, hi)];
+        let prefix = "missing digits after base prefix";
+        assert_eq!(expr_with_errors("0b").1, error(prefix, 0, 2));
+        assert_eq!(expr_with_errors("0x_").1, error(prefix, 0, 2));
+        assert_eq!(expr_with_errors("1 + 0o").1, error(prefix, 4, 6));
+
+        let exponent = "expected at least one digit in exponent";
+        assert_eq!(expr_with_errors("1e+").1, error(exponent, 1, 3));
+        assert_eq!(expr_with_errors("1.5E").1, error(exponent, 3, 4));
+        assert_eq!(expr_with_errors("2e-_").1, error(exponent, 1, 3));
+
+        let (expr, errors) = expr_with_errors("1.2.3");
+        assert_eq!(expr, "1.2.3");
+        assert_eq!(errors, error("unexpected second decimal point", 3, 4));
+    }
+
+    #[test]
+    fn valid_numeric_literals() {
+        for src in &[
+            "1.2.clone()",
+            "1e10",
+            "1.5e-3",
+            "1000.0",
+            "0..2",
+            "12.max(3)",
+        ] {
+            let (expr, errors) = expr_with_errors(src);
+            assert_eq!(&expr, src);
+            assert!(errors.is_empty(), "{}: {:?}", src, errors);
+        }
+    }
+
+    #[test]
+    fn byte_and_c_string_literals() {
+        use crate::ast::LitKind;
+
+        let lit = |src: &str| match string_to_expr(src.to_string()).node {
+            ast::ExprKind::Lit(ref lit) => lit.node.clone(),
+            ref node => panic!("expected a literal, got {:?}", node),
+        };
+        let byte_str = |bytes: &[u8]| LitKind::ByteStr(Rc::new(bytes.to_vec()));
+        let c_str = |bytes: &[u8]| LitKind::CStr(Rc::new(bytes.to_vec()));
+        let cases = [
+            ("b\"a\\x7f\\n\"", byte_str(b"a\x7f\n")),
+            ("b\"a\\\n    b\"", byte_str(b"ab")),
+            ("br\"a\\n\"", byte_str(b"a\\n")),
+            ("br#\"a\r\nb\"#", byte_str(b"a\nb")),
+            ("c\"abc\"", c_str(b"abc\0")),
+            ("c\"\\xff\\u{e9}\\t\"", c_str(b"\xff\xc3\xa9\t\0")),
+            ("c\"a\\\n    b\r\nc\"", c_str(b"ab\nc\0")),
+            ("cr\"a\\n\"", c_str(b"a\\n\0")),
+            ("cr##\"a\"#b\r\n\"##", c_str(b"a\"#b\n\0")),
+        ];
+        for &(src, ref expected) in &cases {
+            assert_eq!(&lit(src), expected, "{}", src);
+            let printed = pprust::expr_to_string(&string_to_expr(src.to_string()));
+            assert_eq!(&lit(&printed), expected, "{} printed as {}", src, printed);
+        }
+
+        for src in &[
+            "b\"a\\n\"",
+            "br#\"a\"#",
+            "c\"a\\x7f\"",
+            "cr\"a\"",
+            "cr##\"a\"#\"##",
+        ] {
+            let tts = string_to_stream(src.to_string())
+                .trees()
+                .collect::<Vec<_>>();
+            match tts[..] {
+                [TokenTree::Token(_, ref tok)] => assert_eq!(pprust::token_to_string(tok), *src),
+                _ => panic!("expected one token for {}, got {:?}", src, tts),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_c_string_literals() {
+        let error = |lo, hi| {
+            vec![(
+                "null characters in C string literals are not supported".to_string(),
+                lo,
+                hi,
+            )]
+        };
+        assert_eq!(expr_with_errors("c\"a\\0b\"").1, error(3, 5));
+        assert_eq!(expr_with_errors("c\"\\x00\"").1, error(2, 6));
+        assert_eq!(expr_with_errors("c\"ab\\u{0}\"").1, error(4, 9));
+        assert_eq!(expr_with_errors("c\"\0\"").1, error(2, 3));
+        assert_eq!(expr_with_errors("cr#\"a\0\"#").1, error(5, 6));
+        assert!(expr_with_errors("c\"\\x80\\u{10}\"").1.is_empty());
+
+        let (_, errors) = expr_with_errors("br\"a\rb\"");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .0
+            .starts_with("bare CR not allowed in raw byte string"));
+    }
+
+    #[test]
+    fn unescaping_invalid_literals() {
+        // The lexer reports all of these, but unescaping them mustn't panic.
+        assert_eq!(char_lit("a"), ('a', 1));
+        assert_eq!(char_lit("\\u{41}"), ('A', 6));
+        assert_eq!(char_lit(""), (INVALID_CHAR, 1));
+        assert_eq!(char_lit("\\"), (INVALID_CHAR, 1));
+        assert_eq!(char_lit("\\q"), (INVALID_CHAR, 2));
+        assert_eq!(char_lit("\\é"), (INVALID_CHAR, 2));
+        assert_eq!(char_lit("\\x8"), (INVALID_CHAR, 2));
+        assert_eq!(char_lit("\\u}"), (INVALID_CHAR, 2));
+        assert_eq!(char_lit("\\u{d800}"), (INVALID_CHAR, 8));
+        assert_eq!(char_lit("\\u{é}"), (INVALID_CHAR, 5));
+        assert_eq!(byte_lit(""), (INVALID_BYTE, 1));
+        assert_eq!(byte_lit("é"), ("é".as_bytes()[0], 1));
+        assert_eq!(byte_lit("\\u"), (INVALID_BYTE, 2));
+        assert_eq!(byte_lit("\\xZZ"), (INVALID_BYTE, 2));
+
+        assert_eq!(str_lit("a\\"), "a\u{fffd}");
+        assert_eq!(str_lit("a\\\rb"), "ab");
+        assert_eq!(str_lit("a\rb\r"), "a\rb\r");
+        assert_eq!(str_lit("a\r\nb"), "a\nb");
+        assert_eq!(str_lit("\\u{d800}x"), "\u{fffd}x");
+        assert_eq!(raw_str_lit("a\r"), "a\r");
+        assert_eq!(*byte_str_lit("a\\"), b"a?");
+        assert_eq!(*byte_str_lit("a\rb\r\n"), b"a\rb\n");
+        assert_eq!(*byte_str_lit("\\xZZ"), b"?ZZ");
+        assert_eq!(*c_str_lit("\\x4"), b"\xef\xbf\xbd4\0");
+        assert_eq!(*c_str_lit("a\r\\"), b"a\r\xef\xbf\xbd\0");
+    }
+
+    #[test]
+    fn variadic_abis() {
+        let errors = |src: &str| {
+            let output = SharedBuffer::default();
+            let sess = json_sess(&output);
+            let item = panictry!(parse_item_from_source_str(
+                "variadic".to_string(),
+                src.to_string(),
+                &sess
+            ));
+            assert!(item.is_some());
+            output
+                .json_diagnostics()
+                .iter()
+                .map(|d| {
+                    (
+                        d["message"].as_str().unwrap().to_string(),
+                        d["spans"][0]["byte_start"].as_u64().unwrap(),
+                        d["children"][0]["message"].as_str().map(str::to_string),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let rejected = |abi: &str, pos| {
+            vec![(
+                format!(
+                    "variadic arguments are not supported by the \"{}\" ABI",
+                    abi
+                ),
+                pos,
+                Some("only the \"cdecl\" and \"C\" ABIs support them".to_string()),
+            )]
+        };
+
+        assert_eq!(
+            errors("extern \"Rust\" fn f(x: u8, ...) {}"),
+            rejected("Rust", 26)
+        );
+        assert_eq!(
+            errors("extern \"stdcall\" { fn f(x: u8, ...); }"),
+            rejected("stdcall", 31)
+        );
+        assert_eq!(
+            errors("type F = extern \"system\" fn(u8, ...);"),
+            rejected("system", 32)
+        );
+        assert!(errors("extern { fn f(x: u8, ...); }").is_empty());
+        assert!(errors("extern \"cdecl\" { fn f(x: u8, ...); }").is_empty());
+        assert!(errors("type F = unsafe extern fn(u8, ...);").is_empty());
+    }
+
+    #[test]
+    fn reparse_replaced_filemap() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let cm = sess.codemap();
+        let old = cm.new_filemap("lib.rs".into(), "fn a() {}\nfn b() {}".to_string());
+        let old_item = filemap_to_parser(&sess, old.clone())
+            .parse_item()
+            .unwrap()
+            .unwrap();
+        assert_eq!(cm.span_to_snippet(old_item.span).unwrap(), "fn a() {}");
+
+        let new = cm.replace_filemap(&old, "\nfn c(x: u8) {}".to_string());
+        let new_item = filemap_to_parser(&sess, new.clone())
+            .parse_item()
+            .unwrap()
+            .unwrap();
+        assert_eq!(new_item.ident.name, "c");
+        assert_eq!(cm.span_to_snippet(new_item.span).unwrap(), "fn c(x: u8) {}");
+        assert_eq!(cm.span_to_string(new_item.span), "lib.rs:2:1: 2:15");
+        assert!(cm.span_to_snippet(old_item.span).is_err());
+    }
+
+    #[test]
+    fn async_await_round_trip() {
+        let src = "async fn f(x: u8) -> u8 { let y = async move { x }; g().await + y.await }";
+        let item = string_to_item(src.to_string()).unwrap();
+        match item.node {
+            ast::ItemKind::Fn(_, _, _, ast::IsAsync::Async, ..) => {}
+            _ => panic!("expected an async fn"),
+        }
+        let printed = item_to_string(&item);
+        assert!(printed.starts_with("async fn f(x: u8) -> u8 {"));
+        assert!(printed.contains("async move { x }"));
+        assert!(printed.contains("g().await + y.await"));
+        let reparsed = string_to_item(printed.clone()).unwrap();
+        assert_eq!(item_to_string(&reparsed), printed);
+
+        let item = string_to_item("impl S { async unsafe fn m(&self) {} }".to_string()).unwrap();
+        let printed = item_to_string(&item);
+        assert!(printed.contains("async unsafe fn m(&self)"));
+        assert_eq!(
+            item_to_string(&string_to_item(printed.clone()).unwrap()),
+            printed
+        );
+
+        // `.await` followed by call parentheses is still a method call.
+        let expr = string_to_expr("x.await()".to_string());
+        match expr.node {
+            ast::ExprKind::MethodCall(..) => {}
+            _ => panic!("expected a method call"),
+        }
+    }
+
+    #[test]
+    fn async_and_await_are_identifiers() {
+        for src in &["let await = 3;", "let async = 1;", "await + async;"] {
+            let stmt = string_to_stmt(src.to_string()).unwrap();
+            assert_eq!(pprust::stmt_to_string(&stmt), *src);
+        }
+        let item = string_to_item("fn await(async: u8) {}".to_string()).unwrap();
+        assert_eq!(item.ident.name.as_str(), "await");
+    }
+
+    #[test]
+    fn let_else_round_trip() {
+        let stmt = string_to_stmt("let Some(x) = opt else { return };".to_string()).unwrap();
+        match stmt.node {
+            ast::StmtKind::Local(ref local) => assert!(local.els.is_some()),
+            _ => panic!("expected a `let` statement"),
+        }
+        let printed = pprust::stmt_to_string(&stmt);
+        assert_eq!(printed, "let Some(x) = opt else { return };");
+        let reparsed = string_to_stmt(printed.clone()).unwrap();
+        assert_eq!(pprust::stmt_to_string(&reparsed), printed);
+
+        // An `else` belonging to the initializer is not a `let...else`.
+        let stmt = string_to_stmt("let x = if a { b } else { c };".to_string()).unwrap();
+        match stmt.node {
+            ast::StmtKind::Local(ref local) => assert!(local.els.is_none()),
+            _ => panic!("expected a `let` statement"),
+        }
+    }
+
+    #[test]
+    fn let_chain_round_trip() {
+        let round_trip = |src: &str, expected: &str| {
+            let expr = string_to_expr(src.to_string());
+            let printed = pprust::expr_to_string(&expr);
+            assert_eq!(printed, expected);
+            let reparsed = string_to_expr(printed.clone());
+            assert_eq!(pprust::expr_to_string(&reparsed), printed);
+            expr
+        };
+
+        let expr = round_trip(
+            "if let Some(a) = x && a > 3 && let Ok(b) = a.f() { b }",
+            "if let Some(a) = x && a > 3 && let Ok(b) = a.f() { b }",
+        );
+        match expr.node {
+            ast::ExprKind::If(ref cond, ..) => match cond.node {
+                ast::ExprKind::Binary(op, _, ref rhs) => {
+                    assert_eq!(op.node, ast::BinOpKind::And);
+                    match rhs.node {
+                        ast::ExprKind::Let(..) => {}
+                        _ => panic!("expected a `let` operand"),
+                    }
+                }
+                _ => panic!("expected a `&&` chain"),
+            },
+            _ => panic!("expected an `if`"),
+        }
+
+        // Without a second `let`, the `&&`s belong to the scrutinee.
+        let expr = round_trip("if let P = a && b {}", "if let P = a && b { }");
+        match expr.node {
+            ast::ExprKind::IfLet(_, ref scrutinee, ..) => match scrutinee.node {
+                ast::ExprKind::Binary(op, ..) => assert_eq!(op.node, ast::BinOpKind::And),
+                _ => panic!("expected `a && b`"),
+            },
+            _ => panic!("expected an `if let`"),
+        }
+        let item = string_to_item("fn f() { if let true = a && b || c {} }".to_string());
+        assert!(item.is_some());
+
+        let expr = round_trip(
+            "'l: while let Some(b) = it.next() && let Ok(c) = b && c { }",
+            "'l: while let Some(b) = it.next() && let Ok(c) = b && c { }",
+        );
+        match expr.node {
+            ast::ExprKind::While(_, _, Some(_)) => {}
+            _ => panic!("expected a labeled `while`"),
+        }
+
+        // A lone binding keeps its scrutinee's `||` operands, as before chains.
+        let expr = round_trip("if let A = b || c && d {}", "if let A = b || c && d { }");
+        match expr.node {
+            ast::ExprKind::IfLet(_, ref scrutinee, ..) => match scrutinee.node {
+                ast::ExprKind::Binary(op, ..) => assert_eq!(op.node, ast::BinOpKind::Or),
+                _ => panic!("expected `b || c && d`"),
+            },
+            _ => panic!("expected an `if let`"),
+        }
+
+        round_trip(
+            "if let A = (b && c) && let B = (d..e) && f == 1 {} else if let C = g && h {}",
+            "if let A = (b && c) && let B = (d..e) && f == 1 { } else if let C = g && h { }",
+        );
+
+        // Blocks in the operands don't end the condition.
+        let expr = round_trip(
+            "if let A = unsafe { x } && let B = y {}",
+            "if let A = unsafe { x } && let B = y { }",
+        );
+        match expr.node {
+            ast::ExprKind::If(ref cond, ..) => match cond.node {
+                ast::ExprKind::Binary(_, ref lhs, _) => match lhs.node {
+                    ast::ExprKind::Let(_, ref scrutinee) => match scrutinee.node {
+                        ast::ExprKind::Block(..) => {}
+                        _ => panic!("expected `unsafe {{ x }}`"),
+                    },
+                    _ => panic!("expected a `let` operand"),
+                },
+                _ => panic!("expected a `&&` chain"),
+            },
+            _ => panic!("expected an `if`"),
+        }
+        round_trip(
+            "while let A = match x { _ => y } && z && let B = w {}",
+            "while let A = match x { _ => y, } && z && let B = w { }",
+        );
+    }
+
+    #[test]
+    fn let_chain_must_start_with_let() {
+        let (sess, _diagnostics) = ParseSess::new_for_batch(FilePathMapping::empty());
+        for &(src, cond) in &[
+            ("if x && let Some(y) = z {}", "x"),
+            ("while a.b() && c && let Some(y) = z {}", "a.b() && c"),
+        ] {
+            let mut err = string_to_parser(&sess, src.to_string())
+                .parse_expr()
+                .unwrap_err();
+            assert_eq!(err.message(), "let chains must start with `let`");
+            let span = err.span.primary_span().unwrap();
+            assert_eq!(sess.codemap().span_to_snippet(span).unwrap(), "let");
+            let labels: Vec<_> = err
+                .span
+                .span_labels()
+                .into_iter()
+                .filter(|label| !label.is_primary)
+                .map(|label| sess.codemap().span_to_snippet(label.span).unwrap())
+                .collect();
+            assert_eq!(labels, [cond]);
+            err.cancel();
+        }
+    }
+
+    #[test]
+    fn let_else_errors() {
+        let (printed, errors) =
+            expr_with_errors("{ let Some(x) = y else if c { return } else { panic!() }; x }");
+        assert_eq!(
+            errors,
+            vec![(
+                "conditional `else if` is not supported for `let...else`".to_string(),
+                23,
+                56
+            )]
+        );
+        assert!(printed.contains("else { if c { return } else { panic!() } }"));
+
+        let (_, errors) = expr_with_errors("{ let Some(x) = y else; x }");
+        assert_eq!(
+            errors,
+            vec![(
+                "expected `{` after `else` in a `let...else` statement, found the `;` token"
+                    .to_string(),
+                22,
+                23
+            )]
+        );
+    }
+
+    #[test]
+    fn macro_def_round_trip() {
+        for &(src, expected) in &[
+            ("macro m() {}", "macro m() {}"),
+            (
+                "pub macro m($x:expr) { $x + 1 }",
+                "pub macro m($x: expr) {$x + 1}",
+            ),
+            (
+                "pub(crate) macro m { () => {} ($e:expr) => { $e } }",
+                "pub(crate) macro m { () => {} ($e: expr) => {$e} }",
+            ),
+        ] {
+            let item = string_to_item(src.to_string()).unwrap().unwrap();
+            match item.node {
+                ast::ItemKind::MacroDef(ref def) => assert!(!def.legacy),
+                _ => panic!("expected a macro definition"),
+            }
+            let printed = item_to_string(&item);
+            assert_eq!(printed, expected);
+            let reparsed = string_to_item(printed.clone()).unwrap().unwrap();
+            assert_eq!(item_to_string(&reparsed), printed);
+        }
+
+        let item = string_to_item("macro_rules! m { () => { } }".to_string())
+            .unwrap()
+            .unwrap();
+        match item.node {
+            ast::ItemKind::MacroDef(ref def) => assert!(def.legacy),
+            _ => panic!("expected a macro definition"),
+        }
+    }
+
+    #[test]
+    fn auto_trait_round_trip() {
+        for &(src, expected) in &[
+            ("auto trait Send {}", "auto trait Send { }"),
+            (
+                "pub unsafe auto trait Sync {}",
+                "pub unsafe auto trait Sync { }",
+            ),
+            (
+                "auto trait Marker: Other where Self: Sized {}",
+                "auto trait Marker: Other where Self: Sized { }",
+            ),
+        ] {
+            let item = string_to_item(src.to_string()).unwrap().unwrap();
+            match item.node {
+                ast::ItemKind::Trait(ast::IsAuto::Yes, ..) => {}
+                _ => panic!("expected an auto trait"),
+            }
+            let printed = item_to_string(&item);
+            assert_eq!(printed, expected);
+            let reparsed = string_to_item(printed.clone()).unwrap().unwrap();
+            assert_eq!(item_to_string(&reparsed), printed);
+        }
+
+        // `auto` is only a keyword before `trait`.
+        let item = string_to_item("fn auto(auto: u8) {}".to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!(item.ident.name.as_str(), "auto");
+        let stmt = string_to_stmt("auto + 1;".to_string()).unwrap();
+        assert_eq!(pprust::stmt_to_string(&stmt), "auto + 1;");
+    }
+
+    #[test]
+    fn auto_trait_errors() {
+        let output = SharedBuffer::default();
+        let sess = json_sess(&output);
+        let src = "auto trait T<U> where U: Copy { fn f(); type A; }";
+        let item = panictry!(string_to_parser(&sess, src.to_string()).parse_item()).unwrap();
+        let errors = output
+            .json_diagnostics()
+            .iter()
+            .map(|d| {
+                let span = &d["spans"][0];
+                (
+                    d["code"]["code"].as_str().unwrap().to_string(),
+                    span["byte_start"].as_u64().unwrap(),
+                    span["byte_end"].as_u64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [("E0567".to_string(), 12, 15), ("E0380".to_string(), 32, 47)]
+        );
+        // The items are kept so that the trait can still be printed.
+        let printed = item_to_string(&item);
+        assert!(
+            printed.starts_with("auto trait T<U> where U: Copy {"),
+            "{}",
+            printed
+        );
+        assert!(printed.contains("fn f();"), "{}", printed);
+    }
+
+    #[test]
+    fn extern_type_round_trip() {
+        let src = "extern \"C\" { type Opaque; pub type Other; fn f(x: *const Opaque); }";
+        let item = string_to_item(src.to_string()).unwrap().unwrap();
+        let kinds = match item.node {
+            ast::ItemKind::ForeignMod(ref m) => m
+                .items
+                .iter()
+                .map(|i| i.node.descriptive_variant().to_string())
+                .collect::<Vec<_>>(),
+            _ => panic!("expected an extern block"),
+        };
+        assert_eq!(kinds, ["foreign type", "foreign type", "foreign function"]);
+        let printed = item_to_string(&item);
+        assert!(printed.contains("type Opaque;"), "{}", printed);
+        assert!(printed.contains("pub type Other;"), "{}", printed);
+        let reparsed = string_to_item(printed.clone()).unwrap().unwrap();
+        assert_eq!(item_to_string(&reparsed), printed);
+    }
+
+    #[test]
+    fn trait_alias_round_trip() {
+        for src in &[
+            "trait A = B + Send;",
+            "pub trait C<T> = Iterator<Item = T> + 'static where T: Clone;",
+            "trait D<'a, T: ?Sized> = Fn(&'a T) -> &'a T;",
+        ] {
+            let item = string_to_item(src.to_string()).unwrap().unwrap();
+            match item.node {
+                ast::ItemKind::TraitAlias(..) => {}
+                _ => panic!("expected a trait alias: {}", src),
+            }
+            let printed = item_to_string(&item);
+            assert_eq!(printed, *src);
+            let reparsed = string_to_item(printed.clone()).unwrap().unwrap();
+            assert_eq!(item_to_string(&reparsed), printed);
+        }
+    }
+
+    #[test]
+    fn trait_alias_errors() {
+        let parse = |src: &str| {
+            let output = SharedBuffer::default();
+            let sess = json_sess(&output);
+            let item = panictry!(string_to_parser(&sess, src.to_string()).parse_item()).unwrap();
+            let errors = output
+                .json_diagnostics()
+                .iter()
+                .map(|d| {
+                    let span = d["spans"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .find(|span| span["is_primary"] == true)
+                        .unwrap()
+                        .clone();
+                    let suggestion = &d["children"][0]["spans"][0];
+                    (
+                        d["message"].as_str().unwrap().to_string(),
+                        span["byte_start"].as_u64().unwrap(),
+                        suggestion["byte_start"].as_u64(),
+                        suggestion["suggested_replacement"]
+                            .as_str()
+                            .map(str::to_string),
+                    )
+                })
+                .collect::<Vec<_>>();
+            (item.node.descriptive_variant().to_string(), errors)
+        };
+        let error = |message: &str, lo, sugg: Option<(u64, &str)>| {
+            (
+                message.to_string(),
+                lo,
+                sugg.map(|s| s.0),
+                sugg.map(|s| s.1.to_string()),
+            )
+        };
+
+        // An empty body was probably not meant to be there.
+        assert_eq!(
+            parse("trait A = B {}"),
+            (
+                "trait alias".to_string(),
+                vec![error(
+                    "trait aliases cannot have a body",
+                    12,
+                    Some((12, ";"))
+                )]
+            )
+        );
+        // Items mean the `=` should have been a `:`.
+        assert_eq!(
+            parse("trait A = B { fn f(); }"),
+            (
+                "trait".to_string(),
+                vec![error(
+                    "trait aliases cannot have a body",
+                    12,
+                    Some((8, ":"))
+                )]
+            )
+        );
+        assert_eq!(
+            parse("unsafe trait A = B;"),
+            (
+                "trait alias".to_string(),
+                vec![error("trait aliases cannot be `unsafe`", 13, None)]
+            )
+        );
+    }
+
+    #[test]
+    fn macro_def_in_statement_position() {
+        let (_, errors) = expr_with_errors("{ macro m() { 1 } m!() }");
+        assert_eq!(
+            errors,
+            vec![(
+                "`macro` definitions are not allowed in statement position".to_string(),
+                2,
+                17
+            )]
+        );
+
+        let (_, errors) = expr_with_errors("{ macro_rules! m { () => { 1 } } m!() }");
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn path_segment_spans() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let snippet = |span| sess.codemap().span_to_snippet(span).unwrap();
+
+        let mut parser = string_to_parser(&sess, "<T as ::std::vec::Vec<u8>>::Item".to_string());
+        let ty = panictry!(parser.parse_ty());
+        let path = match ty.node {
+            ast::TyKind::Path(Some(_), ref path) => path,
+            _ => panic!("expected a qualified path"),
+        };
+        let segments: Vec<_> = path.segments.iter().map(|s| snippet(s.span)).collect();
+        assert_eq!(segments, ["", "std", "vec", "Vec", "Item"]);
+        let parameters = path.segments[3].parameters.as_ref().unwrap();
+        assert_eq!(snippet(parameters.span()), "<u8>");
+
+        let mut parser = string_to_parser(&sess, "a::b::<Vec<u8>>::c(x)".to_string());
+        let expr = panictry!(parser.parse_expr());
+        let path = match expr.node {
+            ast::ExprKind::Call(ref callee, _) => match callee.node {
+                ast::ExprKind::Path(None, ref path) => path,
+                _ => panic!("expected a path"),
+            },
+            _ => panic!("expected a call"),
+        };
+        let segments: Vec<_> = path.segments.iter().map(|s| snippet(s.span)).collect();
+        assert_eq!(segments, ["a", "b", "c"]);
+        let parameters = path.segments[1].parameters.as_ref().unwrap();
+        assert_eq!(snippet(parameters.span()), "<Vec<u8>>");
+
+        let mut parser = string_to_parser(&sess, "Fn(u8) -> u8".to_string());
+        let ty = panictry!(parser.parse_ty());
+        match ty.node {
+            ast::TyKind::Path(None, ref path) => {
+                let parameters = path.segments[0].parameters.as_ref().unwrap();
+                assert_eq!(snippet(path.segments[0].span), "Fn");
+                assert_eq!(snippet(parameters.span()), "(u8) -> u8");
+            }
+            _ => panic!("expected a path"),
+        }
+    }
+
+    #[test]
+    fn emitter_writer_to_buffer() {
+        let render = |color_config| {
+            let output = SharedBuffer::default();
+            let sess = ParseSess::with_emitter_writer(
+                FilePathMapping::empty(),
+                Box::new(output.clone()),
+                color_config,
+            );
+            let src = "fn f() { let x = ; }".to_string();
+            let result = parse_crate_from_source_str("render.rs".to_string(), src, &sess);
+            if let Err(mut err) = result {
+                err.emit();
+            }
+            output.contents()
+        };
+
+        let plain = render(ColorConfig::Never);
+        assert!(plain.starts_with("error: expected expression, found the `;` token\n"));
+        assert!(plain.contains("--> render.rs:1:18"));
+        assert!(plain.contains("fn f() { let x = ; }"));
+        assert!(!plain.contains('\x1b'));
+        assert_eq!(render(ColorConfig::Auto), plain);
+
+        let colored = render(ColorConfig::Always);
+        assert!(colored.starts_with("\x1b[1m\x1b[91merror"));
+        assert!(colored.contains("\x1b[0m"));
+        assert!(colored.contains("fn f() { let x = ; }"));
+    }
+
+    /// A diagnostic message and the replacements it suggests.
+    type Suggested = (String, Vec<(u64, u64, String)>);
+
+    /// Parse the item `src`, returning it pretty-printed along with the
+    /// messages and suggested replacements of the diagnostics emitted.
+    fn item_with_suggestions(src: &str) -> (String, Vec<Suggested>) {
+        let output = SharedBuffer::default();
+        let sess = json_sess(&output);
+        let item = panictry!(string_to_parser(&sess, src.to_string()).parse_item()).unwrap();
+        let diagnostics = output
+            .json_diagnostics()
+            .iter()
+            .map(|d| {
+                let suggestions = d["children"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .flat_map(suggested_replacements)
+                    .collect();
+                (d["message"].as_str().unwrap().to_string(), suggestions)
+            })
+            .collect();
+        (item_to_string(&item), diagnostics)
+    }
+
+    #[test]
+    fn trailing_plus_in_trait_object() {
+        let trailing_plus = |lo, hi| {
+            vec![(
+                "trailing `+` in trait object bounds".to_string(),
+                vec![(lo, hi, String::new())],
+            )]
+        };
+
+        let (item, diagnostics) = item_with_suggestions("fn f(x: Box<Trait+>) {}");
+        assert_eq!(diagnostics, trailing_plus(17, 18));
+        assert!(item.contains("Box< Trait>"), "{}", item);
+
+        let (item, diagnostics) = item_with_suggestions("fn f(x: Box<'a + Trait + Send +>) {}");
+        assert_eq!(diagnostics, trailing_plus(30, 31));
+        assert!(item.contains("Box< 'a + Trait + Send>"), "{}", item);
+
+        // Trailing `+` is fine in type parameter bounds.
+        let (item, diagnostics) = item_with_suggestions("fn f<T: Clone+>(x: Box<Trait + Send>) {}");
+        assert_eq!(diagnostics, []);
+        assert!(item.contains("fn f<T: Clone>"), "{}", item);
+    }
+
+    #[test]
+    fn empty_generic_args() {
+        let (item, diagnostics) = item_with_suggestions("fn f(x: Vec<>) -> u8 { 0 }");
+        assert_eq!(
+            diagnostics,
+            [(
+                "empty generic argument list".to_string(),
+                vec![(11, 13, String::new())],
+            )]
+        );
+        assert!(item.contains("fn f(x: Vec) -> u8"), "{}", item);
+
+        let (item, diagnostics) = item_with_suggestions("fn f() { let v = Vec::<>::new(); }");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].1, [(22, 24, String::new())]);
+        assert!(item.contains("let v = Vec::new();"), "{}", item);
+    }
+
+    /// The source text of the attributes of the crate and its items.
+    fn attr_snippets(src: &str) -> Vec<String> {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let krate = parse_crate_from_source_str("attrs".to_string(), src.to_string(), &sess)
+            .unwrap_or_else(|mut e| {
+                e.emit();
+                panic!("{:?} failed to parse", src)
+            });
+        krate
+            .attrs
+            .iter()
+            .chain(krate.module.items.iter().flat_map(|item| item.attrs.iter()))
+            .map(|attr| sess.codemap().span_to_snippet(attr.span).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn attribute_spans_cover_source_text() {
+        // Inner attributes, including at the very start of the file.
+        assert_eq!(
+            attr_snippets("#![feature(x)]\nfn f() {}"),
+            ["#![feature(x)]"]
+        );
+        assert_eq!(
+            attr_snippets("\u{feff}#![a]\n#![b = \"c\"]"),
+            ["#![a]", "#![b = \"c\"]"]
+        );
+        assert_eq!(
+            attr_snippets("#!/usr/bin/env run\n#![allow(x)]\nfn f() {}"),
+            ["#![allow(x)]"]
+        );
+
+        // Whitespace between the `#!` and the `[` is part of the attribute.
+        // At the very start of the file, only `#![` starts an attribute
+        // rather than a shebang.
+        assert_eq!(
+            attr_snippets("mod m {\n    #! [a]\n    #!\n    [b]\n}"),
+            ["#! [a]", "#!\n    [b]"]
+        );
+        assert!(attr_snippets("#! [feature(x)]\nfn f() {}").is_empty());
+
+        // Outer attributes.
+        assert_eq!(
+            attr_snippets("#[a]\n# [b(c, d)]\n#[e = \"]\"]\nfn f() {}"),
+            ["#[a]", "# [b(c, d)]", "#[e = \"]\"]"]
+        );
+
+        // Doc comments cover the whole comment.
+        assert_eq!(
+            attr_snippets(
+                "//! inner\n/*! block\n inner */\n/// outer\n/** block\n outer */\nfn f() {}"
+            ),
+            [
+                "//! inner",
+                "/*! block\n inner */",
+                "/// outer",
+                "/** block\n outer */"
+            ]
+        );
+    }
+
+    #[test]
+    fn mod_sep_typo_for_colon() {
+        let colon = |lo, hi| {
+            vec![(
+                "expected `:`, found `::`".to_string(),
+                vec![(lo, hi, ":".to_string())],
+            )]
+        };
+
+        let (item, diagnostics) = item_with_suggestions("fn f() { Foo { x:: 3, y: 4 }; }");
+        assert_eq!(diagnostics, colon(16, 18));
+        assert!(item.contains("Foo{x: 3, y: 4,}"), "{}", item);
+
+        let (item, diagnostics) = item_with_suggestions("fn f() { let x :: i32 = 1; }");
+        assert_eq!(diagnostics, colon(15, 17));
+        assert!(item.contains("let x: i32 = 1;"), "{}", item);
+
+        let (item, diagnostics) = item_with_suggestions("fn f(x:: i32, y: u8) {}");
+        assert_eq!(diagnostics, colon(6, 8));
+        assert!(item.contains("fn f(x: i32, y: u8)"), "{}", item);
+
+        // Unspaced `::` after a binding is still a path pattern.
+        let (item, diagnostics) = item_with_suggestions("fn f() { let x::Y = 1; }");
+        assert_eq!(diagnostics, []);
+        assert!(item.contains("let x::Y = 1;"), "{}", item);
+    }
+
+    #[test]
+    fn colon_typo_for_mod_sep() {
+        let (item, diagnostics) = item_with_suggestions("fn f() { let v = Vec:new(); g(v); }");
+        assert_eq!(
+            diagnostics,
+            [(
+                "expected `::`, found `:`".to_string(),
+                vec![(20, 21, "::".to_string())],
+            )]
+        );
+        assert!(item.contains("let v = Vec::new();"), "{}", item);
+
+        let (item, diagnostics) = item_with_suggestions("fn f() { std:mem::swap(a, b).c; }");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].1, [(12, 13, "::".to_string())]);
+        assert!(item.contains("std::mem::swap(a, b).c;"), "{}", item);
+
+        // Spaced, it is type ascription.
+        let (item, diagnostics) = item_with_suggestions("fn f() { let v = x: u8; }");
+        assert_eq!(diagnostics, []);
+        assert!(item.contains("let v = x: u8;"), "{}", item);
+    }
+
+    #[test]
+    fn attr_value_spans() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let source = "#[export_name = \"1bad\"]\n#[foo(bar, \"lit\", baz = 2)]\n/// doc\nfn f() {}";
+        let item = parse_item_from_source_str("<attrs>".to_string(), source.to_string(), &sess)
+            .unwrap()
+            .unwrap();
+        let snippet = |span| sess.codemap().span_to_snippet(span).unwrap();
+
+        let export_name = item.attrs[0].value_str_spanned().unwrap();
+        assert_eq!(export_name.node, "1bad");
+        assert_eq!(snippet(export_name.span), "\"1bad\"");
+
+        let meta = item.attrs[1].meta().unwrap();
+        let items: Vec<_> = meta
+            .meta_item_list_spanned()
+            .unwrap()
+            .map(|(span, _)| snippet(span))
+            .collect();
+        assert_eq!(items, ["bar", "\"lit\"", "baz = 2"]);
+        let baz = meta.meta_item_list().unwrap()[2].meta_item().unwrap();
+        assert_eq!(snippet(baz.name_value_literal().unwrap().span), "2");
+        assert_eq!(baz.value_str_spanned(), None);
+
+        let doc = item.attrs[2].value_str_spanned().unwrap();
+        assert_eq!(snippet(doc.span), "/// doc");
+    }
+
+    #[test]
+    fn export_name_error_points_at_value() {
+        let output = SharedBuffer::default();
+        let sess = json_sess(&output);
+        let source = "#[export_name = 1]\nfn f() {}";
+        let item = parse_item_from_source_str("<attrs>".to_string(), source.to_string(), &sess)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            crate::attr::find_export_name_attr(&sess.span_diagnostic, &item.attrs),
+            None
+        );
+
+        let diagnostics = output.json_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        let span = &diagnostics[0]["spans"][0];
+        assert_eq!(
+            (span["byte_start"].as_u64(), span["byte_end"].as_u64()),
+            (Some(16), Some(17))
+        );
+    }
+
+    #[test]
+    fn token_descriptions_in_errors() {
+        let message = |src: &str| {
+            let output = SharedBuffer::default();
+            let sess = json_sess(&output);
+            let result = parse_item_from_source_str("descr".to_string(), src.to_string(), &sess);
+            if let Err(mut err) = result {
+                err.emit();
+            }
+            output.json_diagnostics()[0]["message"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(message("fn f() -> "), "expected type, found end of input");
+        assert_eq!(
+            message("fn f(/// doc\n x: u8) {}"),
+            "expected pattern, found doc comment"
+        );
+        assert_eq!(
+            message("fn f() { match x { => } }"),
+            "expected pattern, found the `=>` token"
+        );
+        assert_eq!(
+            message("struct S fn"),
+            "expected `where`, `{`, `(`, or `;` after struct name, found keyword `fn`"
+        );
+        assert_eq!(
+            message("fn f() { let a = [1 2]; }"),
+            "expected one of `,`, `.`, `;`, `?`, `]`, or an operator, found `2`"
+        );
+
+        let expr = string_to_expr("a + b".to_string());
+        let interpolated = token::Interpolated(Rc::new(token::NtExpr(expr)));
+        assert_eq!(interpolated.describe(), "an interpolated expression");
+        assert_eq!(token::FatArrow.describe(), "the `=>` token");
+        assert_eq!(token::Eof.describe(), "end of input");
+        assert_eq!(
+            token::Ident(Ident::from_str("fn")).describe(),
+            "keyword `fn`"
+        );
+        assert_eq!(token::Ident(Ident::from_str("x")).describe(), "`x`");
+    }
+
+    #[test]
+    fn keyword_names() {
+        let errors = |src: &str| {
+            let output = SharedBuffer::default();
+            let sess = json_sess(&output);
+            let result = parse_crate_from_source_str("kw".to_string(), src.to_string(), &sess);
+            if let Err(mut err) = result {
+                err.emit();
+            }
+            output
+                .json_diagnostics()
+                .iter()
+                .map(|d| {
+                    let span = &d["spans"][0];
+                    let suggestion = &d["children"][0]["spans"][0]["suggested_replacement"];
+                    (
+                        d["message"].as_str().unwrap().to_string(),
+                        span["byte_start"].as_u64().unwrap(),
+                        span["byte_end"].as_u64().unwrap(),
+                        suggestion.as_str().map(str::to_string),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let error = |message: &str, lo, hi, suggestion: Option<&str>| {
+            (message.to_string(), lo, hi, suggestion.map(str::to_string))
+        };
+
+        assert_eq!(
+            errors("fn f<'fn>(x: &'fn u8, y: &'static u8) {}"),
+            [
+                error("lifetimes cannot use keyword names", 5, 8, Some("'fn_")),
+                error("lifetimes cannot use keyword names", 14, 17, Some("'fn_")),
+            ]
+        );
+        assert_eq!(
+            errors("fn f() { 'while: loop { break 'while; } 'static: loop {} }"),
+            [
+                error("labels cannot use keyword names", 9, 15, Some("'while_")),
+                error("labels cannot use keyword names", 30, 36, Some("'while_")),
+                error("labels cannot use keyword names", 40, 47, Some("'static_")),
+            ]
+        );
+        assert_eq!(
+            errors("struct S { type: u8 }"),
+            [error(
+                "expected identifier, found keyword `type`",
+                11,
+                15,
+                None
+            )]
+        );
+        assert_eq!(
+            errors("fn f() { s.type; s.fn(); s.priv; }"),
+            [
+                error("expected identifier, found keyword `type`", 11, 15, None),
+                error("expected identifier, found keyword `fn`", 19, 21, None),
+                error("`priv` is a reserved keyword", 27, 31, None),
+            ]
+        );
+        assert_eq!(
+            errors("impl S { fn type(&self) {} }"),
+            [error(
+                "expected identifier, found keyword `type`",
+                12,
+                16,
+                None
+            )]
+        );
+        assert!(errors("fn f<'a>() { 'a: loop { break 'a; } self.x.await; }").is_empty());
+    }
+
+    fn error_summary(errors: usize, warnings: usize) -> serde_json::Value {
+        let output = SharedBuffer::default();
+        let sess = json_sess(&output);
+        for i in 0..errors {
+            sess.span_diagnostic.err(&format!("error {}", i));
+        }
+        for i in 0..warnings {
+            sess.span_diagnostic.warn(&format!("warning {}", i));
+        }
+        assert_eq!(sess.span_diagnostic.err_count(), errors);
+        assert_eq!(sess.span_diagnostic.warn_count(), warnings);
+        sess.span_diagnostic.print_error_summary();
+        assert_eq!(sess.span_diagnostic.err_count(), errors);
+        assert_eq!(sess.span_diagnostic.warn_count(), warnings);
+
+        let mut diagnostics = output.json_diagnostics();
+        assert_eq!(diagnostics.len(), errors + warnings + 1);
+        let summary = diagnostics.pop().unwrap();
+        assert!(diagnostics.iter().all(|d| d.get("is_summary").is_none()));
+        assert_eq!(summary["is_summary"], true);
+        assert!(summary["spans"].is_null());
+        summary
+    }
+
+    #[test]
+    fn print_error_summary() {
+        let output = SharedBuffer::default();
+        json_sess(&output).span_diagnostic.print_error_summary();
+        assert!(output.json_diagnostics().is_empty());
+
+        let summary = |errors, warnings| {
+            let summary = error_summary(errors, warnings);
+            (
+                summary["level"].as_str().unwrap().to_string(),
+                summary["message"].as_str().unwrap().to_string(),
+            )
+        };
+        let expected = |level: &str, message: &str| (level.to_string(), message.to_string());
+        assert_eq!(summary(0, 1), expected("warning", "1 warning emitted"));
+        assert_eq!(summary(0, 3), expected("warning", "3 warnings emitted"));
+        assert_eq!(
+            summary(1, 0),
+            expected("error", "aborting due to previous error")
+        );
+        assert_eq!(
+            summary(2, 0),
+            expected("error", "aborting due to 2 previous errors")
+        );
+        assert_eq!(
+            summary(2, 1),
+            expected(
+                "error",
+                "aborting due to 2 previous errors; 1 warning emitted"
+            )
+        );
+    }
+
+    #[test]
+    fn exit_status() {
+        use crate::errors::ExitPolicy;
+
+        let lenient = ExitPolicy::default();
+        let strict = ExitPolicy {
+            warnings_are_errors: true,
+        };
+        let status = |f: &dyn Fn(&Handler)| {
+            let output = SharedBuffer::default();
+            let sess = json_sess(&output);
+            sess.codemap().new_filemap_and_lines("a.rs", "fn f() {}");
+            f(&sess.span_diagnostic);
+            (
+                sess.span_diagnostic.exit_status(lenient),
+                sess.span_diagnostic.exit_status(strict),
+            )
+        };
+
+        assert_eq!(status(&|_| {}), (0, 0));
+        assert_eq!(status(&|h| h.note_without_error("note")), (0, 0));
+        assert_eq!(status(&|h| h.warn("warning")), (0, 2));
+        assert_eq!(
+            status(&|h| {
+                h.warn("warning");
+                h.err("error");
+            }),
+            (1, 1)
+        );
+        assert_eq!(status(&|h| h.struct_err("error").emit()), (1, 1));
+        assert_eq!(
+            status(&|h| h.struct_warn("downgraded").cancel_if(true).emit()),
+            (0, 0)
+        );
+        assert_eq!(
+            status(&|h| {
+                h.err("error");
+                let _ = h.fatal("fatal");
+            }),
+            (101, 101)
+        );
+        assert_eq!(
+            status(&|h| h.span_bug_no_panic(sp(0, 0), "bug")),
+            (101, 101)
+        );
+        assert_eq!(
+            status(&|h| h.delay_span_bug(sp(0, 0), "delayed")),
+            (101, 101)
+        );
+        // The delayed bug is only reported if no error explains it.
+        assert_eq!(
+            status(&|h| {
+                h.delay_span_bug(sp(0, 0), "delayed");
+                h.err("error");
+            }),
+            (1, 1)
+        );
+    }
+
+    #[test]
+    fn duplicate_diagnostics() {
+        let output = SharedBuffer::default();
+        let sess = json_sess(&output);
+        sess.codemap().new_filemap_and_lines("a.rs", "fn f() {}");
+        let handler = &sess.span_diagnostic;
+        handler.span_err(sp(0, 3), "duplicate");
+        handler.span_err(sp(0, 3), "duplicate");
+        assert_eq!(handler.err_count(), 1);
+        assert_eq!(output.json_diagnostics().len(), 1);
+
+        handler.span_err(sp(1, 3), "duplicate");
+        handler
+            .struct_span_err(sp(0, 3), "duplicate")
+            .note("with a note")
+            .emit();
+        handler
+            .struct_span_err(sp(0, 3), "duplicate")
+            .span_suggestion(sp(0, 3), "with a suggestion", "x".to_string())
+            .emit();
+        assert_eq!(handler.err_count(), 4);
+
+        handler.set_deduplicate_diagnostics(false);
+        handler.span_err(sp(0, 3), "duplicate");
+        assert_eq!(handler.err_count(), 5);
+        assert_eq!(output.json_diagnostics().len(), 5);
+    }
+
+    #[test]
+    fn flush_sorted_diagnostics() {
+        let output = SharedBuffer::default();
+        let sess = json_sess(&output);
+        sess.codemap().new_filemap_and_lines("a.rs", "fn f() {}");
+        sess.codemap().new_filemap_and_lines("b.rs", "fn g() {}");
+        let handler = &sess.span_diagnostic;
+        handler.buffer_diagnostics(true);
+
+        handler.span_warn(sp(13, 14), "second file");
+        handler.span_warn(sp(3, 4), "first file, warning");
+        handler.span_err(sp(3, 4), "first file, error");
+        handler.span_err(sp(0, 2), "first file, start");
+        handler.span_note_without_error(sp(13, 14), "second file, note");
+        handler.span_warn(sp(13, 14), "second file, same key");
+        handler.err("no span");
+
+        // Counted when buffered, but not emitted until flushed.
+        assert_eq!(handler.err_count(), 3);
+        assert_eq!(handler.warn_count(), 3);
+        assert!(output.json_diagnostics().is_empty());
+
+        handler.flush_sorted();
+        let messages = |output: &SharedBuffer| {
+            output
+                .json_diagnostics()
+                .iter()
+                .map(|d| d["message"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            messages(&output),
+            [
+                "first file, start",
+                "first file, error",
+                "first file, warning",
+                "second file",
+                "second file, same key",
+                "second file, note",
+                "no span",
+            ]
+        );
+
+        // Buffering stays on until disabled, which flushes.
+        let output = SharedBuffer::default();
+        let sess = json_sess(&output);
+        sess.codemap().new_filemap_and_lines("a.rs", "fn f() {}");
+        let handler = &sess.span_diagnostic;
+        handler.buffer_diagnostics(true);
+        handler.span_warn(sp(3, 4), "later");
+        handler.span_warn(sp(0, 2), "earlier");
+        assert!(output.json_diagnostics().is_empty());
+        handler.buffer_diagnostics(false);
+        assert_eq!(messages(&output), ["earlier", "later"]);
+        handler.span_warn(sp(0, 1), "unbuffered");
+        assert_eq!(messages(&output), ["earlier", "later", "unbuffered"]);
+
+        // The error summary comes after the buffered diagnostics.
+        let output = SharedBuffer::default();
+        let sess = json_sess(&output);
+        let handler = &sess.span_diagnostic;
+        handler.buffer_diagnostics(true);
+        handler.err("buffered");
+        handler.print_error_summary();
+        assert_eq!(
+            messages(&output),
+            ["buffered", "aborting due to previous error"]
+        );
+    }
+
+    #[test]
+    fn report_unused_attrs_skips_consumed() {
+        let output = SharedBuffer::default();
+        let sess = json_sess(&output);
+        let src = "#![no_std]\n\
+                   /// Docs.\n\
+                   #[rustfmt::skip]\n\
+                   #[inlnie]\n\
+                   fn f() {}";
+        let krate =
+            parse_crate_from_source_str("attrs".to_string(), src.to_string(), &sess).unwrap();
+        assert_eq!(crate::std_inject::injected_crate_name(&krate), Some("core"));
+        sess.report_unused_attrs(&krate);
+
+        let diagnostics = output.json_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["level"], "warning");
+        assert_eq!(diagnostics[0]["message"], "unused attribute");
+        let lo = src.find("#[inlnie]").unwrap() as u64;
+        assert_eq!(diagnostics[0]["spans"][0]["byte_start"], lo);
+        assert_eq!(diagnostics[0]["spans"][0]["byte_end"], lo + 9);
+    }
+
+    fn parse_item_with_json(src: &str) -> (P<ast::Item>, Vec<serde_json::Value>) {
+        let output = SharedBuffer::default();
+        let sess = json_sess(&output);
+        let item = parse_item_from_source_str("abi".to_string(), src.to_string(), &sess);
+        (panictry!(item).unwrap(), output.json_diagnostics())
+    }
+
+    #[test]
+    fn misspelled_abi() {
+        let (item, diagnostics) = parse_item_with_json("extern \"stdcal\" { fn f(); }");
+        assert_eq!(item_to_string(&item), "extern \"C\" {\n    fn f();\n}");
+
+        assert_eq!(diagnostics.len(), 1);
+        let message = diagnostics[0]["message"].as_str().unwrap();
+        assert!(message.starts_with("invalid ABI: expected one of [cdecl, stdcall, "));
+        assert!(message.ends_with("found `stdcal`"));
+        let children = diagnostics[0]["children"].as_array().unwrap();
+        assert_eq!(children.len(), 1);
+        assert_eq!(
+            suggested_replacements(&children[0]),
+            [(7, 15, r#""stdcall""#.to_string())]
+        );
+    }
+
+    #[test]
+    fn unknown_abi() {
+        let (item, diagnostics) = parse_item_with_json("extern \"made-up\" fn f() { }");
+        assert_eq!(item_to_string(&item), "extern \"C\" fn f() { }");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["spans"][0]["label"], "invalid ABI");
+        assert!(diagnostics[0]["children"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn known_and_default_abis() {
+        let (item, diagnostics) = parse_item_with_json("extern { fn f(); }");
+        assert_eq!(item_to_string(&item), "extern \"C\" {\n    fn f();\n}");
+        assert!(diagnostics.is_empty());
+
+        let src = "extern \"system\" fn f() { }";
+        let (item, diagnostics) = parse_item_with_json(src);
+        assert_eq!(item_to_string(&item), src);
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn unclosed_delimiter_labels() {
+        let labels = |src: &str| {
+            let output = SharedBuffer::default();
+            let sess = json_sess(&output);
+            if let Err(mut err) =
+                parse_item_from_source_str("delims".to_string(), src.to_string(), &sess)
+            {
+                err.emit();
+            }
+            let diagnostics = output.json_diagnostics();
+            assert!(!diagnostics.is_empty());
+            diagnostics
+                .iter()
+                .flat_map(|d| d["spans"].as_array().unwrap().clone())
+                .filter_map(|span| {
+                    let label = span["label"].as_str()?;
+                    Some((
+                        label.to_string(),
+                        span["byte_start"].as_u64().unwrap(),
+                        span["byte_end"].as_u64().unwrap(),
+                    ))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let found = labels("fn f() { foo(a b) }");
+        assert!(found.contains(&("to close this `(`".to_string(), 12, 13)));
+        let found = labels("fn f() { let x = [1, 2 3]; }");
+        assert!(found.contains(&("to close this `[`".to_string(), 17, 18)));
+        // `.` is not where the closing delimiter could go.
+        let found = labels("struct S { x: u8 . }");
+        assert!(!found.iter().any(|label| label.0.starts_with("to close")));
+    }
+
+    #[test]
+    fn delimiter_spans() {
+        let trees = string_to_stream("f(a, [b])".to_string())
+            .trees()
+            .collect::<Vec<_>>();
+        match trees[1] {
+            TokenTree::Delimited(span, ref delimited) => {
+                assert_eq!(span, DelimSpan::from_pair(sp(1, 2), sp(8, 9)));
+                assert_eq!(span.entire(), sp(1, 9));
+                assert_eq!(trees[1].span(), sp(1, 9));
+                let inner = delimited.stream().trees().collect::<Vec<_>>();
+                assert_eq!(inner[2].span(), sp(5, 8));
+            }
+            ref tree => panic!("expected a delimited tree, got {:?}", tree),
+        }
+    }
+
+    /// Parses a single interpolated `nt` token with `f`, requiring that it is
+    /// consumed entirely.
+    fn parse_interpolated<T, F>(nt: token::Nonterminal, f: F) -> T
+    where
+        F: for<'a> FnOnce(&mut Parser<'a>) -> PResult<'a, T>,
+    {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        // The parser needs the interpolation site to resolve to a file, but
+        // its span should not leak into what it parses.
+        let filemap = sess
+            .codemap()
+            .new_filemap("site".into(), " ".repeat(100) + "$x");
+        let site = sp(filemap.end_pos.0 - 2, filemap.end_pos.0);
+        let tts = vec![TokenTree::Token(site, token::Interpolated(Rc::new(nt)))];
+        let mut p = new_parser_from_tts(&sess, tts);
+        let result = panictry!(f(&mut p));
+        assert_eq!(p.token, token::Eof);
+        result
+    }
+
+    #[test]
+    fn interpolated_expr_keeps_span_and_attrs() {
+        let expr = string_to_expr("#[cfg(test)] f(x)".to_string());
+        assert_eq!(expr.span, sp(0, 17));
+        assert_eq!(expr.span_without_attrs(), sp(13, 17));
+        assert_eq!(expr.attrs.len(), 1);
+
+        let back = parse_interpolated(token::NtExpr(expr.clone()), |p| p.parse_expr());
+        assert_eq!(back.span, expr.span);
+        assert_eq!(back.attrs[..], expr.attrs[..]);
+        assert_eq!(back.attrs[0].span, sp(0, 12));
+        assert!(back == expr);
+    }
+
+    #[test]
+    fn interpolated_literal() {
+        let lit = string_to_expr("-1".to_string());
+        let back = parse_interpolated(token::NtLiteral(lit.clone()), |p| p.parse_expr());
+        assert!(back == lit);
+
+        let pat = parse_interpolated(token::NtLiteral(lit.clone()), |p| p.parse_pat());
+        match pat.node {
+            PatKind::Lit(ref e) => assert!(*e == lit),
+            ref node => panic!("expected a literal pattern, got {:?}", node),
+        }
+
+        let lit = string_to_expr("'a'".to_string());
+        let back = parse_interpolated(token::NtLiteral(lit.clone()), |p| p.parse_lit());
+        assert_eq!(back.node, ast::LitKind::Char('a'));
+        assert_eq!(
+            token::Interpolated(Rc::new(token::NtLiteral(lit))).describe(),
+            "an interpolated literal"
+        );
+    }
+
+    #[test]
+    fn interpolated_path_and_block_exprs() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let path = panictry!(
+            string_to_parser(&sess, "a::b".to_string()).parse_path(parser::PathStyle::Expr)
+        );
+        let expr = parse_interpolated(token::NtPath(path.clone()), |p| p.parse_expr());
+        assert_eq!(expr.span, path.span);
+        match expr.node {
+            ast::ExprKind::Path(None, ref p) => assert_eq!(*p, path),
+            ref node => panic!("expected a path expression, got {:?}", node),
+        }
+
+        let block = panictry!(string_to_parser(&sess, "{ x }".to_string()).parse_block());
+        let expr = parse_interpolated(token::NtBlock(block.clone()), |p| p.parse_expr());
+        assert_eq!(expr.span, block.span);
+        match expr.node {
+            ast::ExprKind::Block(ref b) => assert!(*b == block),
+            ref node => panic!("expected a block expression, got {:?}", node),
+        }
+    }
+
+    #[test]
+    fn interpolated_ty_pat_and_block() {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let ty = panictry!(string_to_parser(&sess, "&'a [u8]".to_string()).parse_ty());
+        let back = parse_interpolated(token::NtTy(ty.clone()), |p| p.parse_ty());
+        assert_eq!(back.span, ty.span);
+        assert!(back == ty);
+
+        let pat = panictry!(string_to_parser(&sess, "Some(ref x)".to_string()).parse_pat());
+        let back = parse_interpolated(token::NtPat(pat.clone()), |p| p.parse_pat());
+        assert_eq!(back.span, pat.span);
+        assert!(back == pat);
+
+        let block = panictry!(string_to_parser(&sess, "{ f(); }".to_string()).parse_block());
+        let back = parse_interpolated(token::NtBlock(block.clone()), |p| p.parse_block());
+        assert_eq!(back.span, block.span);
+        assert!(back == block);
+    }
 }
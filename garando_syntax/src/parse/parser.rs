@@ -3,6 +3,7 @@ use crate::ast::Block;
 use crate::ast::Defaultness;
 use crate::ast::EnumDef;
 use crate::ast::Local;
+use crate::ast::MacDelimiter;
 use crate::ast::MacStmtStyle;
 use crate::ast::Mac_;
 use crate::ast::RangeEnd;
@@ -13,11 +14,11 @@ use crate::ast::{Arg, Arm, Attribute, BindingMode, Mod, TraitItemKind};
 use crate::ast::{AttrStyle, BareFnTy};
 use crate::ast::{BinOpKind, UnOp};
 use crate::ast::{BlockCheckMode, CaptureBy};
-use crate::ast::{Constness, Crate};
+use crate::ast::{Constness, Crate, IsAsync};
 use crate::ast::{Expr, ExprKind, RangeLimits};
 use crate::ast::{Field, FnDecl};
 use crate::ast::{ForeignItem, ForeignItemKind, FunctionRetTy};
-use crate::ast::{Ident, ImplItem, Item, ItemKind};
+use crate::ast::{Ident, ImplItem, IsAuto, Item, ItemKind};
 use crate::ast::{Lifetime, LifetimeDef, Lit, LitKind, UintTy};
 use crate::ast::{MutTy, Mutability};
 use crate::ast::{Pat, PatKind, PathSegment};
@@ -29,7 +30,7 @@ use crate::ast::{TraitItem, TraitRef};
 use crate::ast::{Ty, TyKind, TyParam, TyParamBounds, TypeBinding};
 use crate::ast::{ViewPath, ViewPathGlob, ViewPathList, ViewPathSimple};
 use crate::ast::{Visibility, WhereClause};
-use crate::codemap::{self, respan, CodeMap, Spanned};
+use crate::codemap::{self, respan, CodeMap, FileName, Spanned};
 use crate::errors::{self, DiagnosticBuilder};
 use crate::parse::common::SeqSep;
 use crate::parse::lexer::comments::{doc_comment_style, strip_doc_comment_decoration};
@@ -37,12 +38,13 @@ use crate::parse::lexer::TokenAndSpan;
 use crate::parse::obsolete::ObsoleteSyntax;
 use crate::parse::PResult;
 use crate::parse::{self, classify, token};
-use crate::parse::{new_sub_parser_from_file, Directory, DirectoryOwnership, ParseSess};
+use crate::parse::{maybe_new_sub_parser_from_file, Directory, DirectoryOwnership, ParseSess};
 use crate::print::pprust;
 use crate::ptr::P;
 use crate::symbol::{keywords, Symbol};
 use crate::syntax_pos::{self, BytePos, Span};
-use crate::tokenstream::{self, Delimited, ThinTokenStream, TokenStream, TokenTree};
+use crate::tokenstream::{self, DelimSpan, Delimited, ThinTokenStream, TokenStream, TokenTree};
+use crate::util::lev_distance::find_best_match_for_name;
 use crate::util::parser::{AssocOp, Fixity};
 use crate::util::ThinVec;
 use crate::{ast, attr};
@@ -92,6 +94,30 @@ pub enum BlockMode {
     Ignore,
 }
 
+/// The result of `Parser::parse_item_detailed`.
+#[derive(Debug)]
+pub enum ItemParseOutcome {
+    Item(P<Item>),
+    /// No item was parsed. The parser stopped at the token at `stopped_at`,
+    /// which is the end of the input for `NoItemReason::Eof`.
+    NoItem {
+        reason: NoItemReason,
+        stopped_at: Span,
+    },
+}
+
+/// Why `Parser::parse_item_detailed` found no item.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoItemReason {
+    /// The input ended before anything but comments.
+    Eof,
+    /// Attributes or doc comments were parsed but not followed by an item.
+    /// An error has been emitted for them.
+    DanglingAttributes,
+    /// The next token cannot start an item, e.g. it starts an expression.
+    NotAnItem,
+}
+
 /// Possibly accept an `token::Interpolated` expression (a pre-parsed expression
 /// dropped into the token stream, which happens while parsing the result of
 /// macro expansion). Placement of these is not as complex as I feared it would
@@ -101,19 +127,19 @@ macro_rules! maybe_whole_expr {
     ($p:expr) => {
         if let token::Interpolated(nt) = $p.token.clone() {
             match *nt {
-                token::NtExpr(ref e) => {
+                token::NtExpr(ref e) | token::NtLiteral(ref e) => {
                     $p.bump();
                     return Ok((*e).clone());
                 }
                 token::NtPath(ref path) => {
                     $p.bump();
-                    let span = $p.span;
+                    let span = path.span;
                     let kind = ExprKind::Path(None, (*path).clone());
                     return Ok($p.mk_expr(span, kind, ThinVec::new()));
                 }
                 token::NtBlock(ref block) => {
                     $p.bump();
-                    let span = $p.span;
+                    let span = block.span;
                     let kind = ExprKind::Block((*block).clone());
                     return Ok($p.mk_expr(span, kind, ThinVec::new()));
                 }
@@ -182,6 +208,8 @@ pub struct Parser<'a> {
     pub desugar_doc_comments: bool,
     /// Whether we should configure out of line modules as we parse.
     pub cfg_mods: bool,
+    /// How many expressions, types and patterns we are currently nested in.
+    nesting_depth: usize,
 }
 
 struct TokenCursor {
@@ -191,14 +219,14 @@ struct TokenCursor {
 
 struct TokenCursorFrame {
     delim: token::DelimToken,
-    span: Span,
+    span: DelimSpan,
     open_delim: bool,
     tree_cursor: tokenstream::Cursor,
     close_delim: bool,
 }
 
 impl TokenCursorFrame {
-    fn new(sp: Span, delimited: &Delimited) -> Self {
+    fn new(sp: DelimSpan, delimited: &Delimited) -> Self {
         TokenCursorFrame {
             delim: delimited.delim,
             span: sp,
@@ -273,7 +301,7 @@ impl TokenCursor {
         }
 
         let body = TokenTree::Delimited(
-            sp,
+            DelimSpan::from_single(sp),
             Delimited {
                 delim: token::Bracket,
                 tts: [
@@ -297,7 +325,7 @@ impl TokenCursor {
         self.stack.push(mem::replace(
             &mut self.frame,
             TokenCursorFrame::new(
-                sp,
+                DelimSpan::from_single(sp),
                 &Delimited {
                     delim: token::NoDelim,
                     tts: if doc_comment_style(&name.as_str()) == AttrStyle::Inner {
@@ -339,6 +367,7 @@ pub enum TokenType {
 impl TokenType {
     fn to_string(&self) -> String {
         match *self {
+            TokenType::Token(token::Eof) => token::Eof.describe(),
             TokenType::Token(ref t) => format!("`{}`", Parser::token_to_string(t)),
             TokenType::Keyword(kw) => format!("`{}`", kw.name()),
             TokenType::Operator => "an operator".to_string(),
@@ -471,6 +500,17 @@ impl From<P<Expr>> for LhsExpr {
     }
 }
 
+/// `span` extended back over the first of `attrs` if it is an outer attribute
+/// in front of it.
+fn span_with_outer_attrs(span: Span, attrs: &[Attribute]) -> Span {
+    match attrs.first() {
+        Some(attr) if attr.style == AttrStyle::Outer && attr.span.lo < span.lo => {
+            attr.span.to(span)
+        }
+        _ => span,
+    }
+}
+
 /// Create a placeholder argument.
 fn dummy_arg(span: Span) -> Arg {
     let spanned = Spanned {
@@ -491,6 +531,7 @@ fn dummy_arg(span: Span) -> Arg {
         ty: P(ty),
         pat: pat,
         id: ast::DUMMY_NODE_ID,
+        span: span,
     }
 }
 
@@ -520,7 +561,7 @@ impl<'a> Parser<'a> {
             expected_tokens: Vec::new(),
             token_cursor: TokenCursor {
                 frame: TokenCursorFrame::new(
-                    syntax_pos::DUMMY_SP,
+                    DelimSpan::dummy(),
                     &Delimited {
                         delim: token::NoDelim,
                         tts: tokens.into(),
@@ -530,6 +571,7 @@ impl<'a> Parser<'a> {
             },
             desugar_doc_comments: desugar_doc_comments,
             cfg_mods: true,
+            nesting_depth: 0,
         };
 
         let tok = parser.next_tok();
@@ -569,22 +611,15 @@ impl<'a> Parser<'a> {
         Parser::token_to_string(&self.token)
     }
 
+    /// Describe the current token for diagnostics, see `Token::describe`
     pub fn this_token_descr(&self) -> String {
-        let s = self.this_token_to_string();
-        if self.token.is_strict_keyword() {
-            format!("keyword `{}`", s)
-        } else if self.token.is_reserved_keyword() {
-            format!("reserved keyword `{}`", s)
-        } else {
-            format!("`{}`", s)
-        }
+        self.token.describe()
     }
 
     pub fn unexpected_last<T>(&self, t: &token::Token) -> PResult<'a, T> {
-        let token_str = Parser::token_to_string(t);
         Err(self.span_fatal(
             self.prev_span,
-            &format!("unexpected token: `{}`", token_str),
+            &format!("unexpected token: {}", t.describe()),
         ))
     }
 
@@ -603,12 +638,12 @@ impl<'a> Parser<'a> {
                 self.bump();
                 Ok(())
             } else {
-                let token_str = Parser::token_to_string(t);
-                let this_token_str = self.this_token_to_string();
-                Err(self.fatal(&format!(
-                    "expected `{}`, found `{}`",
-                    token_str, this_token_str
-                )))
+                let token_str = TokenType::Token(t.clone()).to_string();
+                let this_token_str = self.this_token_descr();
+                let mut err =
+                    self.fatal(&format!("expected {}, found {}", token_str, this_token_str));
+                self.label_unclosed_delim(&mut err, &[TokenType::Token(t.clone())]);
+                Err(err)
             }
         } else {
             self.expect_one_of(unsafe { slice::from_raw_parts(t, 1) }, &[])
@@ -655,7 +690,7 @@ impl<'a> Parser<'a> {
             expected.sort_by(|a, b| a.to_string().cmp(&b.to_string()));
             expected.dedup();
             let expect = tokens_to_string(&expected[..]);
-            let actual = self.this_token_to_string();
+            let actual = self.this_token_descr();
             let (msg_exp, (label_sp, label_exp)) = if expected.len() > 1 {
                 let short_expect = if expected.len() > 6 {
                     format!("{} possible tokens", expected.len())
@@ -663,7 +698,7 @@ impl<'a> Parser<'a> {
                     expect.clone()
                 };
                 (
-                    format!("expected one of {}, found `{}`", expect, actual),
+                    format!("expected one of {}, found {}", expect, actual),
                     (
                         self.prev_span.next_point(),
                         format!("expected one of {} here", short_expect),
@@ -671,12 +706,12 @@ impl<'a> Parser<'a> {
                 )
             } else if expected.is_empty() {
                 (
-                    format!("unexpected token: `{}`", actual),
+                    format!("unexpected token: {}", actual),
                     (self.prev_span, "unexpected token after this".to_string()),
                 )
             } else {
                 (
-                    format!("expected {}, found `{}`", expect, actual),
+                    format!("expected {}, found {}", expect, actual),
                     (
                         self.prev_span.next_point(),
                         format!("expected {} here", expect),
@@ -696,10 +731,26 @@ impl<'a> Parser<'a> {
                 err.span_label(sp, label_exp);
                 err.span_label(self.span, "unexpected token");
             }
+            self.label_unclosed_delim(&mut err, &expected);
             Err(err)
         }
     }
 
+    /// Points `err` at the opening delimiter of the innermost delimited
+    /// group when the group's closing delimiter was one of the `expected`
+    /// tokens.
+    fn label_unclosed_delim(&self, err: &mut DiagnosticBuilder, expected: &[TokenType]) {
+        let frame = match self.innermost_delim_frame() {
+            Some(frame) if frame.delim != token::NoDelim => frame,
+            _ => return,
+        };
+        let close = TokenType::Token(token::CloseDelim(frame.delim));
+        if frame.span.open != syntax_pos::DUMMY_SP && expected.contains(&close) {
+            let open = Parser::token_to_string(&token::OpenDelim(frame.delim));
+            err.span_label(frame.span.open, format!("to close this `{}`", open));
+        }
+    }
+
     /// returns the span of expr, if it was not interpolated or the span of the interpolated token
     fn interpolated_or_expr_span(
         &self,
@@ -726,8 +777,8 @@ impl<'a> Parser<'a> {
                 self.span_fatal_err(self.prev_span, Error::UselessDocComment)
             } else {
                 let mut err = self.fatal(&format!(
-                    "expected identifier, found `{}`",
-                    self.this_token_to_string()
+                    "expected identifier, found {}",
+                    self.this_token_descr()
                 ));
                 if self.token == token::Underscore {
                     err.note("`_` is a wildcard pattern, not an identifier");
@@ -1074,6 +1125,9 @@ impl<'a> Parser<'a> {
                 if first {
                     first = false;
                 } else {
+                    // The sequence could also have ended here.
+                    let kets = kets.iter().map(|&k| TokenType::Token(k.clone()));
+                    self.expected_tokens.extend(kets);
                     if let Err(e) = self.expect(t) {
                         fe(e);
                         break;
@@ -1117,6 +1171,84 @@ impl<'a> Parser<'a> {
         Ok(result)
     }
 
+    /// Parses a list of elements with `parse_elem`, between `open` and `close`
+    /// and separated by `sep`, such as the `(a, b = "c",)` of an attribute.
+    /// Returns the elements, whether the list ended with a separator, and
+    /// the errors recovered from, which the caller must emit or cancel:
+    ///
+    /// * a missing separator is reported, and the next element parsed as if
+    ///   it were there,
+    /// * an element that fails to parse is reported and skipped, along with
+    ///   the tokens up to the next separator or `close`,
+    /// * a trailing separator that `sep` doesn't allow is reported.
+    ///
+    /// So only a missing `open` or `close` is returned as an error.
+    /// `parse_elem` must consume the tokens of an element up to the next
+    /// separator or `close`, and no further.
+    pub fn parse_delimited_seq<T, F>(
+        &mut self,
+        open: &token::Token,
+        close: &token::Token,
+        sep: SeqSep,
+        mut parse_elem: F,
+    ) -> PResult<'a, (Vec<T>, bool, Vec<DiagnosticBuilder<'a>>)>
+    where
+        F: FnMut(&mut Parser<'a>) -> PResult<'a, T>,
+    {
+        self.expect(open)?;
+        let mut elems = Vec::new();
+        let mut errors = Vec::new();
+        let mut trailing_sep = false;
+        loop {
+            if self.check(close) {
+                break;
+            }
+            match self.token {
+                token::CloseDelim(..) | token::Eof => break,
+                _ => {}
+            }
+            let elem_lo = self.span;
+            let mut kets = vec![close];
+            kets.extend(sep.sep.as_ref());
+            match parse_elem(self) {
+                Ok(elem) => elems.push(elem),
+                Err(err) => {
+                    errors.push(err);
+                    self.eat_to_tokens(&kets);
+                }
+            }
+
+            trailing_sep = false;
+            let t = match sep.sep {
+                Some(ref t) => t,
+                None => continue,
+            };
+            if self.eat(t) {
+                trailing_sep = true;
+            } else if self.check(close) {
+                break;
+            } else {
+                self.expected_tokens.push(TokenType::Token(close.clone()));
+                if let Err(err) = self.expect(t) {
+                    errors.push(err);
+                }
+                // Keep an element that consumes nothing from looping forever.
+                if self.span == elem_lo {
+                    self.eat_to_tokens(&kets);
+                    trailing_sep = self.eat(t);
+                }
+            }
+        }
+
+        if trailing_sep && !sep.trailing_sep_allowed {
+            let sep_str = pprust::token_to_string(sep.sep.as_ref().unwrap());
+            let msg = format!("unexpected trailing `{}`", sep_str);
+            errors.push(self.diagnostic().struct_span_err(self.prev_span, &msg));
+        }
+        self.expect(close)?;
+        Ok((elems, trailing_sep, errors))
+    }
+
     // NB: Do not use this function unless you actually plan to place the
     // spanned list in the AST.
     pub fn parse_seq<T, F>(
@@ -1198,6 +1330,47 @@ impl<'a> Parser<'a> {
             },
         )
     }
+
+    /// The span of the token `dist` tokens ahead, see `look_ahead`.
+    fn look_ahead_span(&self, dist: usize) -> Span {
+        if dist == 0 {
+            return self.span;
+        }
+
+        match self.token_cursor.frame.tree_cursor.look_ahead(dist - 1) {
+            Some(TokenTree::Token(span, _)) => span,
+            Some(TokenTree::Delimited(span, _)) => span.open,
+            None => self.look_ahead_span(dist - 1),
+        }
+    }
+
+    /// Is the parser at `ident ::` with whitespace around the `::`? Where a
+    /// binding and a `:` are expected that is most likely a typo for `ident:`
+    /// rather than the start of a path, which is rarely spaced that way.
+    fn is_ident_with_spaced_mod_sep(&self) -> bool {
+        if !self.token.is_ident()
+            || self.token.is_any_keyword()
+            || !self.look_ahead(1, |t| *t == token::ModSep)
+        {
+            return false;
+        }
+        let mod_sep = self.look_ahead_span(1);
+        self.span.hi != mod_sep.lo || mod_sep.hi != self.look_ahead_span(2).lo
+    }
+
+    /// Eat a `::` written where a `:` is expected, reporting it with a
+    /// suggestion to use a single colon.
+    fn eat_mod_sep_as_colon(&mut self) -> bool {
+        if self.token != token::ModSep {
+            return false;
+        }
+        self.diagnostic()
+            .struct_span_err(self.span, "expected `:`, found `::`")
+            .span_suggestion(self.span, "use a single colon", ":".to_string())
+            .emit();
+        self.bump();
+        true
+    }
     pub fn fatal(&self, m: &str) -> DiagnosticBuilder<'a> {
         self.sess.span_diagnostic.struct_span_fatal(self.span, m)
     }
@@ -1254,11 +1427,28 @@ impl<'a> Parser<'a> {
 
     fn get_label(&mut self) -> ast::Ident {
         match self.token {
-            token::Lifetime(ref ident) => *ident,
+            token::Lifetime(ident) => {
+                self.check_lifetime_name(ident, self.span, "label");
+                ident
+            }
             _ => self.bug("not a lifetime"),
         }
     }
 
+    /// Signals an error if `ident`, the name of a lifetime or label at `span`
+    /// including its `'`, is a keyword.
+    fn check_lifetime_name(&self, ident: Ident, span: Span, what: &str) {
+        let name = ident.name.as_str();
+        if !token::Ident(Ident::from_str(&name[1..])).is_reserved_ident() {
+            return;
+        }
+        self.diagnostic()
+            .struct_span_err(span, &format!("{}s cannot use keyword names", what))
+            .span_label(span, format!("`{}` is a keyword", &name[1..]))
+            .span_suggestion(span, &format!("rename the {}", what), format!("{}_", name))
+            .emit();
+    }
+
     /// parse a TyKind::BareFn type:
     pub fn parse_ty_bare_fn(&mut self, lifetime_defs: Vec<LifetimeDef>) -> PResult<'a, TyKind> {
         /*
@@ -1281,7 +1471,7 @@ impl<'a> Parser<'a> {
         };
 
         self.expect_keyword(keywords::Fn)?;
-        let (inputs, variadic) = self.parse_fn_args(false, true)?;
+        let (inputs, variadic) = self.parse_fn_args(false, true, abi)?;
         let ret_ty = self.parse_ret_ty()?;
         let decl = P(FnDecl {
             inputs: inputs,
@@ -1334,7 +1524,7 @@ impl<'a> Parser<'a> {
                 None
             };
             (ident, TraitItemKind::Const(ty, default))
-        } else if self.token.is_path_start() {
+        } else if self.token.is_path_start() && !self.is_async_fn() {
             // trait item macro.
             // code copied from parse_macro_use_or_failure... abstraction!
             let prev_span = self.prev_span;
@@ -1351,20 +1541,14 @@ impl<'a> Parser<'a> {
 
             // eat a matched-delimiter token tree:
             let (delim, tts) = self.expect_delimited_token_tree()?;
-            if delim != token::Brace {
+            if delim != MacDelimiter::Brace {
                 self.expect(&token::Semi)?
             }
 
-            let mac = respan(
-                lo.to(self.prev_span),
-                Mac_ {
-                    path: pth,
-                    tts: tts,
-                },
-            );
+            let mac = self.mk_mac(lo.to(self.prev_span), pth, delim, tts);
             (keywords::Invalid.ident(), ast::TraitItemKind::Macro(mac))
         } else {
-            let (constness, unsafety, abi) = match self.parse_fn_front_matter() {
+            let (constness, asyncness, unsafety, abi) = match self.parse_fn_front_matter() {
                 Ok(cua) => cua,
                 Err(e) => return Err(e),
             };
@@ -1383,6 +1567,7 @@ impl<'a> Parser<'a> {
             let sig = ast::MethodSig {
                 unsafety: unsafety,
                 constness: constness,
+                asyncness,
                 decl: d,
                 generics: generics,
                 abi: abi,
@@ -1403,8 +1588,8 @@ impl<'a> Parser<'a> {
                     Some(body)
                 }
                 _ => {
-                    let token_str = self.this_token_to_string();
-                    return Err(self.fatal(&format!("expected `;` or `{{`, found `{}`", token_str)));
+                    let token_str = self.this_token_descr();
+                    return Err(self.fatal(&format!("expected `;` or `{{`, found {}", token_str)));
                 }
             };
             (ident, ast::TraitItemKind::Method(sig, body))
@@ -1422,7 +1607,8 @@ impl<'a> Parser<'a> {
     /// Parse optional return type [ -> TY ] in function decl
     pub fn parse_ret_ty(&mut self) -> PResult<'a, FunctionRetTy> {
         if self.eat(&token::RArrow) {
-            Ok(FunctionRetTy::Ty(self.parse_ty_no_plus()?))
+            let arrow = self.prev_span;
+            Ok(FunctionRetTy::Ty(self.parse_ty_no_plus()?, arrow))
         } else {
             Ok(FunctionRetTy::Default(Span {
                 hi: self.span.lo,
@@ -1446,6 +1632,20 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_ty_common(&mut self, allow_plus: bool) -> PResult<'a, P<Ty>> {
+        self.parse_nested(
+            "type",
+            |this| this.parse_ty_common_inner(allow_plus),
+            |_, span| {
+                P(Ty {
+                    id: ast::DUMMY_NODE_ID,
+                    node: TyKind::Err,
+                    span,
+                })
+            },
+        )
+    }
+
+    fn parse_ty_common_inner(&mut self, allow_plus: bool) -> PResult<'a, P<Ty>> {
         maybe_whole!(self, NtTy, |x| x);
 
         let lo = self.span;
@@ -1528,14 +1728,8 @@ impl<'a> Parser<'a> {
             let path = self.parse_path(PathStyle::Type)?;
             if self.eat(&token::Not) {
                 // Macro invocation in type position
-                let (_, tts) = self.expect_delimited_token_tree()?;
-                TyKind::Mac(respan(
-                    lo.to(self.span),
-                    Mac_ {
-                        path: path,
-                        tts: tts,
-                    },
-                ))
+                let (delim, tts) = self.expect_delimited_token_tree()?;
+                TyKind::Mac(self.mk_mac(lo.to(self.span), path, delim, tts))
             } else {
                 // Just a type path or bound list (trait object type) starting with a trait.
                 //   `Type`
@@ -1569,7 +1763,9 @@ impl<'a> Parser<'a> {
             || self.check_lifetime() && self.look_ahead(1, |t| t == &token::BinOp(token::Plus))
         {
             // Bound list (trait object type)
-            TyKind::TraitObject(self.parse_ty_param_bounds_common(allow_plus)?)
+            let bounds = self.parse_ty_param_bounds_common(allow_plus)?;
+            self.warn_trailing_plus_in_bounds();
+            TyKind::TraitObject(bounds)
         } else {
             let msg = format!("expected type, found {}", self.this_token_descr());
             return Err(self.fatal(&msg));
@@ -1600,10 +1796,22 @@ impl<'a> Parser<'a> {
         if parse_plus {
             self.bump(); // `+`
             bounds.append(&mut self.parse_ty_param_bounds()?);
+            self.warn_trailing_plus_in_bounds();
         }
         Ok(TyKind::TraitObject(bounds))
     }
 
+    /// Warns about a `+` ending the bounds of a trait object type just
+    /// parsed, as in `Box<Trait+>`.
+    fn warn_trailing_plus_in_bounds(&self) {
+        if self.prev_token_kind == PrevTokenKind::Plus {
+            self.diagnostic()
+                .struct_span_warn(self.prev_span, "trailing `+` in trait object bounds")
+                .span_suggestion(self.prev_span, "remove the `+`", String::new())
+                .emit();
+        }
+    }
+
     fn maybe_recover_from_bad_type_plus(&mut self, allow_plus: bool, ty: &Ty) -> PResult<'a, ()> {
         // Do not add `+` to expected tokens.
         if !allow_plus || self.token != token::BinOp(token::Plus) {
@@ -1709,14 +1917,21 @@ impl<'a> Parser<'a> {
     pub fn parse_arg_general(&mut self, require_name: bool) -> PResult<'a, Arg> {
         maybe_whole!(self, NtArg, |x| x);
 
+        let lo = self.span;
         let pat = if require_name || self.is_named_argument() {
             debug!(
                 "parse_arg_general parse_pat (require_name:{})",
                 require_name
             );
-            let pat = self.parse_pat()?;
+            let pat = if self.is_ident_with_spaced_mod_sep() {
+                self.parse_ident_pat()?
+            } else {
+                self.parse_pat()?
+            };
 
-            self.expect(&token::Colon)?;
+            if !self.eat_mod_sep_as_colon() {
+                self.expect(&token::Colon)?;
+            }
             pat
         } else {
             debug!("parse_arg_general ident_to_pat");
@@ -1738,6 +1953,7 @@ impl<'a> Parser<'a> {
             ty: t,
             pat: pat,
             id: ast::DUMMY_NODE_ID,
+            span: lo.to(self.prev_span),
         })
     }
 
@@ -1748,6 +1964,7 @@ impl<'a> Parser<'a> {
 
     /// Parse an argument in a lambda header e.g. |arg, arg|
     pub fn parse_fn_block_arg(&mut self) -> PResult<'a, Arg> {
+        let lo = self.span;
         let pat = self.parse_pat()?;
         let t = if self.eat(&token::Colon) {
             self.parse_ty()?
@@ -1762,6 +1979,7 @@ impl<'a> Parser<'a> {
             ty: t,
             pat: pat,
             id: ast::DUMMY_NODE_ID,
+            span: lo.to(self.prev_span),
         })
     }
 
@@ -1777,7 +1995,7 @@ impl<'a> Parser<'a> {
     pub fn parse_lit_token(&mut self) -> PResult<'a, LitKind> {
         let out = match self.token {
             token::Interpolated(ref nt) => match **nt {
-                token::NtExpr(ref v) => match v.node {
+                token::NtExpr(ref v) | token::NtLiteral(ref v) => match v.node {
                     ExprKind::Lit(ref lit) => lit.node.clone(),
                     _ => {
                         return self.unexpected_last(&self.token);
@@ -1826,6 +2044,7 @@ impl<'a> Parser<'a> {
 
     /// matches '-' lit | lit
     pub fn parse_pat_literal_maybe_minus(&mut self) -> PResult<'a, P<Expr>> {
+        maybe_whole!(self, NtLiteral, |e| e);
         let minus_lo = self.span;
         let minus_present = self.eat(&token::BinOp(token::Minus));
         let lo = self.span;
@@ -1919,7 +2138,7 @@ impl<'a> Parser<'a> {
         };
 
         if is_global {
-            segments.insert(0, PathSegment::crate_root());
+            segments.insert(0, PathSegment::crate_root(lo.shrink_to_lo()));
         }
 
         // Assemble the result.
@@ -1973,14 +2192,7 @@ impl<'a> Parser<'a> {
 
             // Parse types, optionally.
             let parameters = if self.eat_lt() {
-                let (lifetimes, types, bindings) = self.parse_generic_args()?;
-                self.expect_gt()?;
-                ast::AngleBracketedParameterData {
-                    lifetimes: lifetimes,
-                    types: types,
-                    bindings: bindings,
-                }
-                .into()
+                self.parse_angle_bracketed_parameters()?.into()
             } else if self.eat(&token::OpenDelim(token::Paren)) {
                 let lo = self.prev_span;
 
@@ -2041,17 +2253,10 @@ impl<'a> Parser<'a> {
             // Check for a type segment.
             if self.eat_lt() {
                 // Consumed `a::b::<`, go look for types
-                let (lifetimes, types, bindings) = self.parse_generic_args()?;
-                self.expect_gt()?;
                 segments.push(PathSegment {
                     identifier: identifier,
                     span: ident_span,
-                    parameters: ast::AngleBracketedParameterData {
-                        lifetimes: lifetimes,
-                        types: types,
-                        bindings: bindings,
-                    }
-                    .into(),
+                    parameters: self.parse_angle_bracketed_parameters()?.into(),
                 });
 
                 // Consumed `a::b::<T,U>`, check for `::` before proceeding
@@ -2065,6 +2270,30 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Parses the generic arguments of a path segment up to and including the
+    /// `>`, the `<` having been eaten. An empty list, as in `Vec<>`, is
+    /// reported and parsed as no arguments at all.
+    fn parse_angle_bracketed_parameters(
+        &mut self,
+    ) -> PResult<'a, ast::AngleBracketedParameterData> {
+        let lo = self.prev_span;
+        let (lifetimes, types, bindings) = self.parse_generic_args()?;
+        self.expect_gt()?;
+        let span = lo.to(self.prev_span);
+        if lifetimes.is_empty() && types.is_empty() && bindings.is_empty() {
+            self.diagnostic()
+                .struct_span_err(span, "empty generic argument list")
+                .span_suggestion(span, "remove the angle brackets", String::new())
+                .emit();
+        }
+        Ok(ast::AngleBracketedParameterData {
+            span,
+            lifetimes,
+            types,
+            bindings,
+        })
+    }
+
     /// Examples:
     /// - `a::b::c`
     pub fn parse_path_segments_without_types(&mut self) -> PResult<'a, Vec<PathSegment>> {
@@ -2096,6 +2325,9 @@ impl<'a> Parser<'a> {
         match self.token {
             token::Lifetime(ident) => {
                 let ident_span = self.span;
+                if ident.name != keywords::StaticLifetime.name() {
+                    self.check_lifetime_name(ident, ident_span, "lifetime");
+                }
                 self.bump();
                 Lifetime {
                     ident: ident,
@@ -2137,6 +2369,12 @@ impl<'a> Parser<'a> {
             self.bump();
             hi = self.prev_span;
             (fieldname, self.parse_expr()?, false)
+        } else if self.token.is_ident() && self.look_ahead(1, |t| t == &token::ModSep) {
+            // A shorthand field can't be a path, so `field::` is a typo for `field:`.
+            let fieldname = self.parse_field_name()?;
+            hi = self.prev_span;
+            self.eat_mod_sep_as_colon();
+            (fieldname, self.parse_expr()?, false)
         } else {
             let fieldname = self.parse_ident()?;
             hi = self.prev_span;
@@ -2158,12 +2396,14 @@ impl<'a> Parser<'a> {
         })
     }
 
+    /// Makes an expression spanning `span` and its outer attributes, if any.
     pub fn mk_expr(&mut self, span: Span, node: ExprKind, attrs: ThinVec<Attribute>) -> P<Expr> {
         P(Expr {
             id: ast::DUMMY_NODE_ID,
             node: node,
-            span: span,
-            attrs: attrs.into(),
+            span: span_with_outer_attrs(span, &attrs),
+            bare_span: span,
+            attrs: attrs,
         })
     }
 
@@ -2217,16 +2457,24 @@ impl<'a> Parser<'a> {
         ExprKind::AssignOp(binop, lhs, rhs)
     }
 
+    /// A macro invocation, counted in the session's `ParseStats`.
+    fn mk_mac(
+        &self,
+        span: Span,
+        path: ast::Path,
+        delim: MacDelimiter,
+        tts: ThinTokenStream,
+    ) -> ast::Mac {
+        self.sess.record_stats(|stats| stats.macro_invocations += 1);
+        respan(span, Mac_ { path, delim, tts })
+    }
+
     pub fn mk_mac_expr(&mut self, span: Span, m: Mac_, attrs: ThinVec<Attribute>) -> P<Expr> {
-        P(Expr {
-            id: ast::DUMMY_NODE_ID,
-            node: ExprKind::Mac(codemap::Spanned {
-                node: m,
-                span: span,
-            }),
+        let node = ExprKind::Mac(codemap::Spanned {
+            node: m,
             span: span,
-            attrs: attrs,
-        })
+        });
+        self.mk_expr(span, node, attrs)
     }
 
     pub fn mk_lit_u32(&mut self, i: u32, attrs: ThinVec<Attribute>) -> P<Expr> {
@@ -2239,18 +2487,23 @@ impl<'a> Parser<'a> {
         P(Expr {
             id: ast::DUMMY_NODE_ID,
             node: ExprKind::Lit(lv_lit),
-            span: *span,
+            span: span_with_outer_attrs(*span, &attrs),
+            bare_span: *span,
             attrs: attrs,
         })
     }
 
-    fn expect_delimited_token_tree(&mut self) -> PResult<'a, (token::DelimToken, ThinTokenStream)> {
-        match self.token {
-            token::OpenDelim(delim) => match self.parse_token_tree() {
+    fn expect_delimited_token_tree(&mut self) -> PResult<'a, (MacDelimiter, ThinTokenStream)> {
+        let delim = match self.token {
+            token::OpenDelim(delim) => MacDelimiter::from_token(delim),
+            _ => None,
+        };
+        match delim {
+            Some(delim) => match self.parse_token_tree() {
                 TokenTree::Delimited(_, delimited) => Ok((delim, delimited.stream().into())),
                 _ => unreachable!(),
             },
-            _ => Err(self.fatal("expected open delimiter")),
+            None => Err(self.fatal("expected open delimiter")),
         }
     }
 
@@ -2427,6 +2680,10 @@ impl<'a> Parser<'a> {
                     let lo = self.prev_span;
                     return self.parse_catch_expr(lo, attrs);
                 }
+                if self.is_async_block() {
+                    assert!(self.eat_keyword(keywords::Async));
+                    return self.parse_async_block(lo, attrs);
+                }
                 if self.eat_keyword(keywords::Return) {
                     if self.token.can_begin_expr() {
                         let e = self.parse_expr()?;
@@ -2470,16 +2727,10 @@ impl<'a> Parser<'a> {
                     // `!`, as an operator, is prefix, so we know this isn't that
                     if self.eat(&token::Not) {
                         // MACRO INVOCATION expression
-                        let (_, tts) = self.expect_delimited_token_tree()?;
+                        let (delim, tts) = self.expect_delimited_token_tree()?;
                         let hi = self.prev_span;
-                        return Ok(self.mk_mac_expr(
-                            lo.to(hi),
-                            Mac_ {
-                                path: pth,
-                                tts: tts,
-                            },
-                            attrs,
-                        ));
+                        let mac = self.mk_mac(lo.to(hi), pth, delim, tts);
+                        return Ok(self.mk_mac_expr(lo.to(hi), mac.node, attrs));
                     }
                     if self.check(&token::OpenDelim(token::Brace)) {
                         // This is a struct literal, unless we're prohibited
@@ -2615,6 +2866,7 @@ impl<'a> Parser<'a> {
         self.parse_dot_or_call_expr_with_(e0, lo).map(|expr| {
             expr.map(|mut expr| {
                 attrs.extend::<Vec<_>>(expr.attrs.into());
+                expr.span = span_with_outer_attrs(expr.span, &attrs);
                 expr.attrs = attrs;
                 match expr.node {
                     ExprKind::If(..) | ExprKind::IfLet(..) => {
@@ -2705,9 +2957,18 @@ impl<'a> Parser<'a> {
 
             // expr.f
             if self.eat(&token::Dot) {
+                if self.is_await_suffix() {
+                    // expr.await
+                    self.bump();
+                    let hi = self.prev_span;
+                    e = self.mk_expr(lo.to(hi), ExprKind::Await(e), ThinVec::new());
+                    continue;
+                }
                 match self.token {
                     token::Ident(i) => {
                         let ident_span = self.span;
+                        self.check_strict_keywords();
+                        self.check_reserved_keywords();
                         self.bump();
                         e = self.parse_dot_suffix(i, ident_span, e, lo)?;
                     }
@@ -2767,8 +3028,8 @@ impl<'a> Parser<'a> {
                     }
                     _ => {
                         // FIXME Could factor this out into non_fatal_unexpected or something.
-                        let actual = self.this_token_to_string();
-                        self.span_err(self.span, &format!("unexpected token: `{}`", actual));
+                        let actual = self.this_token_descr();
+                        self.span_err(self.span, &format!("unexpected token: {}", actual));
 
                         let dot_span = self.prev_span;
                         e = self.parse_dot_suffix(keywords::Invalid.ident(), dot_span, e, lo)?;
@@ -2838,7 +3099,7 @@ impl<'a> Parser<'a> {
                     &mut self.token_cursor.frame,
                     self.token_cursor.stack.pop().unwrap(),
                 );
-                self.span = frame.span;
+                self.span = frame.span.entire();
                 self.bump();
                 TokenTree::Delimited(
                     frame.span,
@@ -2889,14 +3150,14 @@ impl<'a> Parser<'a> {
         let (hi, ex) = match self.token {
             token::Not => {
                 self.bump();
-                let e = self.parse_prefix_expr(None);
+                let e = self.parse_prefix_operand();
                 let (span, e) = self.interpolated_or_expr_span(e)?;
                 (span, self.mk_unary(UnOp::Not, e))
             }
             // Suggest `!` for bitwise negation when encountering a `~`
             token::Tilde => {
                 self.bump();
-                let e = self.parse_prefix_expr(None);
+                let e = self.parse_prefix_operand();
                 let (span, e) = self.interpolated_or_expr_span(e)?;
                 let span_of_tilde = lo;
                 let mut err = self
@@ -2909,20 +3170,20 @@ impl<'a> Parser<'a> {
             }
             token::BinOp(token::Minus) => {
                 self.bump();
-                let e = self.parse_prefix_expr(None);
+                let e = self.parse_prefix_operand();
                 let (span, e) = self.interpolated_or_expr_span(e)?;
                 (span, self.mk_unary(UnOp::Neg, e))
             }
             token::BinOp(token::Star) => {
                 self.bump();
-                let e = self.parse_prefix_expr(None);
+                let e = self.parse_prefix_operand();
                 let (span, e) = self.interpolated_or_expr_span(e)?;
                 (span, self.mk_unary(UnOp::Deref, e))
             }
             token::BinOp(token::And) | token::AndAnd => {
                 self.expect_and()?;
                 let m = self.parse_mutability();
-                let e = self.parse_prefix_expr(None);
+                let e = self.parse_prefix_operand();
                 let (span, e) = self.interpolated_or_expr_span(e)?;
                 (span, ExprKind::AddrOf(m, e))
             }
@@ -2937,7 +3198,7 @@ impl<'a> Parser<'a> {
             }
             token::Ident(..) if self.token.is_keyword(keywords::Box) => {
                 self.bump();
-                let e = self.parse_prefix_expr(None);
+                let e = self.parse_prefix_operand();
                 let (span, e) = self.interpolated_or_expr_span(e)?;
                 (span, ExprKind::Box(e))
             }
@@ -2946,6 +3207,28 @@ impl<'a> Parser<'a> {
         return Ok(self.mk_expr(lo.to(hi), ex, attrs));
     }
 
+    /// Parse the operand of a prefix operator. Each operator counts as a
+    /// level of nesting, so that long chains like `!!!!x` are bounded.
+    fn parse_prefix_operand(&mut self) -> PResult<'a, P<Expr>> {
+        self.parse_nested(
+            "expression",
+            |this| this.parse_prefix_expr(None),
+            |this, span| this.mk_expr(span, ExprKind::Tup(Vec::new()), ThinVec::new()),
+        )
+    }
+
+    /// Parse the right operand of a right-associative operator or a range,
+    /// which recurse rather than loop in `parse_assoc_expr_with`. Each
+    /// operand counts as a level of nesting, so that `a = b = c = ...` and
+    /// `.. .. ..` are bounded.
+    fn parse_assoc_operand(&mut self, min_prec: usize) -> PResult<'a, P<Expr>> {
+        self.parse_nested(
+            "expression",
+            |this| this.parse_assoc_expr_with(min_prec, LhsExpr::NotYetParsed),
+            |this, span| this.mk_expr(span, ExprKind::Tup(Vec::new()), ThinVec::new()),
+        )
+    }
+
     /// Parse an associative expression
     ///
     /// This parses an expression accounting for associativity and precedence of the operators in
@@ -3008,6 +3291,10 @@ impl<'a> Parser<'a> {
                 );
                 continue;
             } else if op == AssocOp::Colon {
+                if self.is_path_sep_typo(&lhs, cur_op_span) {
+                    lhs = self.recover_path_sep_typo(lhs, cur_op_span)?;
+                    continue;
+                }
                 let rhs = self.parse_ty_no_plus()?;
                 lhs = self.mk_expr(
                     lhs_span.to(rhs.span),
@@ -3022,7 +3309,7 @@ impl<'a> Parser<'a> {
                 // We have 2 alternatives here: `x..y`/`x...y` and `x..`/`x...` The other
                 // two variants are handled with `parse_prefix_range_expr` call above.
                 let rhs = if self.is_at_start_of_range_notation_rhs() {
-                    Some(self.parse_assoc_expr_with(op.precedence() + 1, LhsExpr::NotYetParsed)?)
+                    Some(self.parse_assoc_operand(op.precedence() + 1)?)
                 } else {
                     None
                 };
@@ -3048,7 +3335,7 @@ impl<'a> Parser<'a> {
             let rhs = match op.fixity() {
                 Fixity::Right => self
                     .with_res(restrictions - Restrictions::RESTRICTION_STMT_EXPR, |this| {
-                        this.parse_assoc_expr_with(op.precedence(), LhsExpr::NotYetParsed)
+                        this.parse_assoc_operand(op.precedence())
                     }),
                 Fixity::Left => self
                     .with_res(restrictions - Restrictions::RESTRICTION_STMT_EXPR, |this| {
@@ -3116,6 +3403,39 @@ impl<'a> Parser<'a> {
         Ok(lhs)
     }
 
+    /// Is the `:` at `colon_span`, just eaten after the path expression `lhs`,
+    /// a typo for `::`? Type ascription is spaced like `x: T`, while a `:`
+    /// with no whitespace on either side reads as `path:segment`.
+    fn is_path_sep_typo(&self, lhs: &Expr, colon_span: Span) -> bool {
+        match lhs.node {
+            ExprKind::Path(None, _) => {}
+            _ => return false,
+        }
+        self.token.is_ident()
+            && !self.token.is_any_keyword()
+            && lhs.span.hi == colon_span.lo
+            && colon_span.hi == self.span.lo
+    }
+
+    /// Report the `:` at `colon_span` as a typo for `::` and continue parsing
+    /// the path expression `lhs` as if `::` had been written.
+    fn recover_path_sep_typo(&mut self, lhs: P<Expr>, colon_span: Span) -> PResult<'a, P<Expr>> {
+        self.diagnostic()
+            .struct_span_err(colon_span, "expected `::`, found `:`")
+            .span_suggestion(colon_span, "use a path separator", "::".to_string())
+            .emit();
+        let lhs = lhs.unwrap();
+        let mut path = match lhs.node {
+            ExprKind::Path(None, path) => path,
+            _ => unreachable!(),
+        };
+        let rest = self.parse_path(PathStyle::Expr)?;
+        path.span = path.span.to(rest.span);
+        path.segments.extend(rest.segments);
+        let expr = self.mk_expr(path.span, ExprKind::Path(None, path), ThinVec::new());
+        self.parse_dot_or_call_expr_with(expr, lhs.span, lhs.attrs)
+    }
+
     /// Produce an error if comparison operators are chained (RFC #558).
     /// We only need to check lhs, not rhs, because all comparison ops
     /// have same precedence and are left-associative
@@ -3158,13 +3478,10 @@ impl<'a> Parser<'a> {
         let opt_end = if self.is_at_start_of_range_notation_rhs() {
             // RHS must be parsed with more associativity than the dots.
             let next_prec = AssocOp::from_token(&tok).unwrap().precedence() + 1;
-            Some(
-                self.parse_assoc_expr_with(next_prec, LhsExpr::NotYetParsed)
-                    .map(|x| {
-                        hi = x.span;
-                        x
-                    })?,
-            )
+            Some(self.parse_assoc_operand(next_prec).map(|x| {
+                hi = x.span;
+                x
+            })?)
         } else {
             None
         };
@@ -3198,7 +3515,7 @@ impl<'a> Parser<'a> {
             return self.parse_if_let_expr(attrs);
         }
         let lo = self.prev_span;
-        let cond = self.parse_expr_res(Restrictions::RESTRICTION_NO_STRUCT_LITERAL, None)?;
+        let cond = self.parse_cond_expr()?;
         let thn = self.parse_block()?;
         let mut els: Option<P<Expr>> = None;
         let mut hi = thn.span;
@@ -3213,10 +3530,7 @@ impl<'a> Parser<'a> {
     /// Parse an 'if let' expression ('if' token already eaten)
     pub fn parse_if_let_expr(&mut self, attrs: ThinVec<Attribute>) -> PResult<'a, P<Expr>> {
         let lo = self.prev_span;
-        self.expect_keyword(keywords::Let)?;
-        let pat = self.parse_pat()?;
-        self.expect(&token::Eq)?;
-        let expr = self.parse_expr_res(Restrictions::RESTRICTION_NO_STRUCT_LITERAL, None)?;
+        let cond = self.parse_let_chain()?;
         let thn = self.parse_block()?;
         let (hi, els) = if self.eat_keyword(keywords::Else) {
             let expr = self.parse_else_expr()?;
@@ -3224,7 +3538,112 @@ impl<'a> Parser<'a> {
         } else {
             (thn.span, None)
         };
-        Ok(self.mk_expr(lo.to(hi), ExprKind::IfLet(pat, expr, thn, els), attrs))
+        let node = cond.and_then(|cond| match cond.node {
+            ExprKind::Let(pat, expr) => ExprKind::IfLet(pat, expr, thn, els),
+            node => ExprKind::If(P(Expr { node, ..cond }), thn, els),
+        });
+        Ok(self.mk_expr(lo.to(hi), node, attrs))
+    }
+
+    /// Parse the condition of an `if let` or `while let`: a `let` binding,
+    /// or a chain of operands joined with `&&` that starts with one, such as
+    /// `let Some(a) = x && a > 3 && let Ok(b) = a.f()`. The bindings in a
+    /// chain are `ExprKind::Let` operands of the `&&` expressions. Without a
+    /// second `let`, the condition is a single binding whose scrutinee is a
+    /// whole expression, `&&` and `||` included, as before chains.
+    fn parse_let_chain(&mut self) -> PResult<'a, P<Expr>> {
+        let lo = self.span;
+        self.expect_keyword(keywords::Let)?;
+        let pat = self.parse_pat()?;
+        self.expect(&token::Eq)?;
+        let scrutinee = self.parse_let_chain_operand(LhsExpr::NotYetParsed)?;
+        // The operands after the scrutinee, up to a `&&` followed by `let`.
+        let mut operands = Vec::new();
+        while self.check(&token::AndAnd) && !self.look_ahead(1, |t| t.is_keyword(keywords::Let)) {
+            self.bump();
+            let op_span = self.prev_span;
+            operands.push((
+                op_span,
+                self.parse_let_chain_operand(LhsExpr::NotYetParsed)?,
+            ));
+        }
+
+        if !self.check(&token::AndAnd) {
+            let mut scrutinee = scrutinee;
+            for (op_span, rhs) in operands {
+                scrutinee = self.mk_and(scrutinee, op_span, rhs);
+            }
+            let scrutinee = self.with_res(Restrictions::RESTRICTION_NO_STRUCT_LITERAL, |this| {
+                this.parse_assoc_expr_with(0, LhsExpr::AlreadyParsed(scrutinee))
+            })?;
+            let span = lo.to(scrutinee.span);
+            return Ok(self.mk_expr(span, ExprKind::Let(pat, scrutinee), ThinVec::new()));
+        }
+
+        let span = lo.to(scrutinee.span);
+        let mut cond = self.mk_expr(span, ExprKind::Let(pat, scrutinee), ThinVec::new());
+        for (op_span, rhs) in operands {
+            cond = self.mk_and(cond, op_span, rhs);
+        }
+        while self.eat(&token::AndAnd) {
+            let op_span = self.prev_span;
+            let rhs = if self.check_keyword(keywords::Let) {
+                self.parse_let_operand()?
+            } else {
+                self.parse_let_chain_operand(LhsExpr::NotYetParsed)?
+            };
+            cond = self.mk_and(cond, op_span, rhs);
+        }
+        Ok(cond)
+    }
+
+    /// Parse the condition of an `if` or `while` that doesn't start with
+    /// `let`. Let chains must, so a `let` after one of its `&&`s is an error.
+    fn parse_cond_expr(&mut self) -> PResult<'a, P<Expr>> {
+        let mut cond = self.parse_let_chain_operand(LhsExpr::NotYetParsed)?;
+        while self.check(&token::AndAnd) {
+            if self.look_ahead(1, |t| t.is_keyword(keywords::Let)) {
+                let let_span = self.look_ahead_span(1);
+                let mut err = self
+                    .diagnostic()
+                    .struct_span_err(let_span, "let chains must start with `let`");
+                err.span_label(let_span, "`let` after `&&` in a condition");
+                err.span_label(cond.span, "this condition doesn't start with `let`");
+                return Err(err);
+            }
+            self.bump();
+            let op_span = self.prev_span;
+            let rhs = self.parse_let_chain_operand(LhsExpr::NotYetParsed)?;
+            cond = self.mk_and(cond, op_span, rhs);
+        }
+        self.with_res(Restrictions::RESTRICTION_NO_STRUCT_LITERAL, |this| {
+            this.parse_assoc_expr_with(0, LhsExpr::AlreadyParsed(cond))
+        })
+    }
+
+    fn mk_and(&mut self, lhs: P<Expr>, op_span: Span, rhs: P<Expr>) -> P<Expr> {
+        let span = lhs.span.to(rhs.span);
+        let binary = self.mk_binary(respan(op_span, BinOpKind::And), lhs, rhs);
+        self.mk_expr(span, binary, ThinVec::new())
+    }
+
+    /// Parse `let pat = expr` in a chain, where the scrutinee ends at a `&&`,
+    /// which continues the chain.
+    fn parse_let_operand(&mut self) -> PResult<'a, P<Expr>> {
+        let lo = self.span;
+        self.expect_keyword(keywords::Let)?;
+        let pat = self.parse_pat()?;
+        self.expect(&token::Eq)?;
+        let expr = self.parse_let_chain_operand(LhsExpr::NotYetParsed)?;
+        Ok(self.mk_expr(lo.to(expr.span), ExprKind::Let(pat, expr), ThinVec::new()))
+    }
+
+    /// Parse an operand of a `let` chain, which binds more tightly than `&&`.
+    fn parse_let_chain_operand(&mut self, lhs: LhsExpr) -> PResult<'a, P<Expr>> {
+        let prec = AssocOp::LAnd.precedence() + 1;
+        self.with_res(Restrictions::RESTRICTION_NO_STRUCT_LITERAL, |this| {
+            this.parse_assoc_expr_with(prec, lhs)
+        })
     }
 
     // `move |args| expr`
@@ -3256,7 +3675,13 @@ impl<'a> Parser<'a> {
     // `else` token already eaten
     pub fn parse_else_expr(&mut self) -> PResult<'a, P<Expr>> {
         if self.eat_keyword(keywords::If) {
-            return self.parse_if_expr(ThinVec::new());
+            // An `else if` nests the rest of the chain, so each one counts
+            // towards the nesting limit.
+            return self.parse_nested(
+                "expression",
+                |this| this.parse_if_expr(ThinVec::new()),
+                |this, span| this.mk_expr(span, ExprKind::Tup(Vec::new()), ThinVec::new()),
+            );
         } else {
             let blk = self.parse_block()?;
             return Ok(self.mk_expr(blk.span, ExprKind::Block(blk), ThinVec::new()));
@@ -3296,7 +3721,7 @@ impl<'a> Parser<'a> {
         if self.token.is_keyword(keywords::Let) {
             return self.parse_while_let_expr(opt_ident, span_lo, attrs);
         }
-        let cond = self.parse_expr_res(Restrictions::RESTRICTION_NO_STRUCT_LITERAL, None)?;
+        let cond = self.parse_cond_expr()?;
         let (iattrs, body) = self.parse_inner_attrs_and_block()?;
         attrs.extend(iattrs);
         let span = span_lo.to(body.span);
@@ -3310,14 +3735,15 @@ impl<'a> Parser<'a> {
         span_lo: Span,
         mut attrs: ThinVec<Attribute>,
     ) -> PResult<'a, P<Expr>> {
-        self.expect_keyword(keywords::Let)?;
-        let pat = self.parse_pat()?;
-        self.expect(&token::Eq)?;
-        let expr = self.parse_expr_res(Restrictions::RESTRICTION_NO_STRUCT_LITERAL, None)?;
+        let cond = self.parse_let_chain()?;
         let (iattrs, body) = self.parse_inner_attrs_and_block()?;
         attrs.extend(iattrs);
         let span = span_lo.to(body.span);
-        return Ok(self.mk_expr(span, ExprKind::WhileLet(pat, expr, body, opt_ident), attrs));
+        let node = cond.and_then(|cond| match cond.node {
+            ExprKind::Let(pat, expr) => ExprKind::WhileLet(pat, expr, body, opt_ident),
+            node => ExprKind::While(P(Expr { node, ..cond }), body, opt_ident),
+        });
+        return Ok(self.mk_expr(span, node, attrs));
     }
 
     // parse `loop {...}`, `loop` token already eaten
@@ -3344,6 +3770,26 @@ impl<'a> Parser<'a> {
         Ok(self.mk_expr(span_lo.to(body.span), ExprKind::Catch(body), attrs))
     }
 
+    /// Parse an `async {...}` or `async move {...}` block (`async` token already eaten)
+    pub fn parse_async_block(
+        &mut self,
+        span_lo: Span,
+        mut attrs: ThinVec<Attribute>,
+    ) -> PResult<'a, P<Expr>> {
+        let capture_clause = if self.eat_keyword(keywords::Move) {
+            CaptureBy::Value
+        } else {
+            CaptureBy::Ref
+        };
+        let (iattrs, body) = self.parse_inner_attrs_and_block()?;
+        attrs.extend(iattrs);
+        Ok(self.mk_expr(
+            span_lo.to(body.span),
+            ExprKind::Async(capture_clause, body),
+            attrs,
+        ))
+    }
+
     // `match` token already eaten
     fn parse_match_expr(&mut self, mut attrs: ThinVec<Attribute>) -> PResult<'a, P<Expr>> {
         let match_span = self.prev_span;
@@ -3434,7 +3880,62 @@ impl<'a> Parser<'a> {
         r: Restrictions,
         already_parsed_attrs: Option<ThinVec<Attribute>>,
     ) -> PResult<'a, P<Expr>> {
-        self.with_res(r, |this| this.parse_assoc_expr(already_parsed_attrs))
+        self.parse_nested(
+            "expression",
+            |this| this.with_res(r, |this| this.parse_assoc_expr(already_parsed_attrs)),
+            |this, span| this.mk_expr(span, ExprKind::Tup(Vec::new()), ThinVec::new()),
+        )
+    }
+
+    /// Parse something nested one level deeper with `f`. Once the session's
+    /// nesting limit is reached, an error is reported instead, the rest of
+    /// the innermost delimited group is skipped, and `recover` builds a
+    /// placeholder covering the skipped tokens.
+    fn parse_nested<T, F, R>(&mut self, what: &str, f: F, recover: R) -> PResult<'a, T>
+    where
+        F: FnOnce(&mut Self) -> PResult<'a, T>,
+        R: FnOnce(&mut Self, Span) -> T,
+    {
+        if self.nesting_depth >= self.sess.nesting_limit() {
+            let msg = format!("{} nesting too deep", what);
+            let open_delim = self.innermost_open_delim_span();
+            self.diagnostic().span_err(open_delim, &msg);
+
+            let lo = self.span;
+            while !matches!(self.token, token::CloseDelim(..) | token::Eof) {
+                self.parse_token_tree();
+            }
+            let span = if self.span == lo {
+                lo
+            } else {
+                lo.to(self.prev_span)
+            };
+            return Ok(recover(self, span));
+        }
+
+        self.nesting_depth += 1;
+        let result = parse::ensure_sufficient_stack(|| f(self));
+        self.nesting_depth -= 1;
+        result
+    }
+
+    /// The token cursor frame of the innermost group enclosing the current
+    /// token.
+    fn innermost_delim_frame(&self) -> Option<&TokenCursorFrame> {
+        match self.token {
+            // The cursor has already entered the group this token opens.
+            token::OpenDelim(..) => self.token_cursor.stack.last(),
+            _ => Some(&self.token_cursor.frame),
+        }
+    }
+
+    /// The span of the innermost delimiter enclosing the current token, or
+    /// of the current token if it is not delimited.
+    fn innermost_open_delim_span(&self) -> Span {
+        match self.innermost_delim_frame() {
+            Some(frame) if frame.delim != token::NoDelim => frame.span.open,
+            _ => self.span,
+        }
     }
 
     /// Parse the RHS of a local variable declaration (e.g. '= 14;')
@@ -3568,8 +4069,8 @@ impl<'a> Parser<'a> {
             if self.check(&token::DotDot) {
                 self.bump();
                 if self.token != token::CloseDelim(token::Brace) {
-                    let token_str = self.this_token_to_string();
-                    return Err(self.fatal(&format!("expected `{}`, found `{}`", "}", token_str)));
+                    let token_str = self.this_token_descr();
+                    return Err(self.fatal(&format!("expected `{}`, found {}", "}", token_str)));
                 }
                 etc = true;
                 break;
@@ -3675,6 +4176,16 @@ impl<'a> Parser<'a> {
 
     /// Parse a pattern.
     pub fn parse_pat(&mut self) -> PResult<'a, P<Pat>> {
+        self.parse_nested("pattern", Parser::parse_pat_inner, |_, span| {
+            P(Pat {
+                id: ast::DUMMY_NODE_ID,
+                node: PatKind::Wild,
+                span,
+            })
+        })
+    }
+
+    fn parse_pat_inner(&mut self) -> PResult<'a, P<Pat>> {
         maybe_whole!(self, NtPat, |x| x);
 
         let lo = self.span;
@@ -3745,14 +4256,8 @@ impl<'a> Parser<'a> {
                         token::Not if qself.is_none() => {
                             // Parse macro invocation
                             self.bump();
-                            let (_, tts) = self.expect_delimited_token_tree()?;
-                            let mac = respan(
-                                lo.to(self.prev_span),
-                                Mac_ {
-                                    path: path,
-                                    tts: tts,
-                                },
-                            );
+                            let (delim, tts) = self.expect_delimited_token_tree()?;
+                            let mac = self.mk_mac(lo.to(self.prev_span), path, delim, tts);
                             pat = PatKind::Mac(mac);
                         }
                         token::DotDotDot | token::DotDot => {
@@ -3829,6 +4334,18 @@ impl<'a> Parser<'a> {
         }))
     }
 
+    /// Parse a by-value binding of a bare identifier, without looking for a
+    /// path after it.
+    fn parse_ident_pat(&mut self) -> PResult<'a, P<Pat>> {
+        let lo = self.span;
+        let node = self.parse_pat_ident(BindingMode::ByValue(Mutability::Immutable))?;
+        Ok(P(Pat {
+            id: ast::DUMMY_NODE_ID,
+            node,
+            span: lo.to(self.prev_span),
+        }))
+    }
+
     /// Parse ident or ident @ pat
     /// used by the copy foo and ref foo patterns to give a good
     /// error message when parsing mistakes like ref foo(a,b)
@@ -3861,24 +4378,90 @@ impl<'a> Parser<'a> {
     /// Parse a local variable declaration
     fn parse_local(&mut self, attrs: ThinVec<Attribute>) -> PResult<'a, P<Local>> {
         let lo = self.span;
-        let pat = self.parse_pat()?;
+        let pat = if self.is_ident_with_spaced_mod_sep() {
+            self.parse_ident_pat()?
+        } else {
+            self.parse_pat()?
+        };
 
-        let ty = if self.eat(&token::Colon) {
+        let ty = if self.eat(&token::Colon) || self.eat_mod_sep_as_colon() {
             Some(self.parse_ty()?)
         } else {
             None
         };
         let init = self.parse_initializer()?;
+        let els = if init.is_some() && self.eat_keyword(keywords::Else) {
+            Some(self.parse_let_else_block()?)
+        } else {
+            None
+        };
         Ok(P(ast::Local {
             ty: ty,
             pat: pat,
             init: init,
+            els,
             id: ast::DUMMY_NODE_ID,
             span: lo.to(self.prev_span),
             attrs: attrs,
         }))
     }
 
+    /// Parse the block of a `let ... else { ... }` statement (`else` token already eaten)
+    fn parse_let_else_block(&mut self) -> PResult<'a, P<Block>> {
+        if self.token.is_keyword(keywords::If) {
+            // Recover from `let ... else if ...` by treating the `if` as the body of the block.
+            let lo = self.span;
+            let expr = self.parse_expr()?;
+            let span = lo.to(self.prev_span);
+            self.diagnostic()
+                .struct_span_err(
+                    span,
+                    "conditional `else if` is not supported for `let...else`",
+                )
+                .multipart_suggestion(
+                    "try placing this code inside a block",
+                    vec![
+                        (
+                            Span {
+                                hi: span.lo,
+                                ..span
+                            },
+                            "{ ".to_string(),
+                        ),
+                        (
+                            Span {
+                                lo: span.hi,
+                                ..span
+                            },
+                            " }".to_string(),
+                        ),
+                    ],
+                )
+                .emit();
+            return Ok(P(ast::Block {
+                stmts: vec![Stmt {
+                    id: ast::DUMMY_NODE_ID,
+                    node: StmtKind::Expr(expr),
+                    span,
+                }],
+                id: ast::DUMMY_NODE_ID,
+                rules: BlockCheckMode::Default,
+                span,
+            }));
+        }
+        if !self.check(&token::OpenDelim(token::Brace)) {
+            let token_str = self.this_token_descr();
+            let mut err = self.fatal(&format!(
+                "expected `{{` after `else` in a `let...else` statement, found {}",
+                token_str
+            ));
+            err.span_label(self.span, "expected a block");
+            err.help("the `else` block must diverge, e.g. `else { return; }`");
+            return Err(err);
+        }
+        self.parse_block()
+    }
+
     /// Parse a structure field
     fn parse_name_and_ty(
         &mut self,
@@ -3901,15 +4484,20 @@ impl<'a> Parser<'a> {
 
     /// Emit an expected item after attributes error.
     fn expected_item_err(&self, attrs: &[Attribute]) {
-        let message = match attrs.last() {
+        match attrs.last() {
             Some(&Attribute {
                 is_sugared_doc: true,
+                span,
                 ..
-            }) => "expected item after doc comment",
-            _ => "expected item after attributes",
-        };
-
-        self.span_err(self.prev_span, message);
+            }) if self.token == token::Eof => {
+                self.span_fatal_err(span, Error::UselessDocComment).emit();
+            }
+            Some(&Attribute {
+                is_sugared_doc: true,
+                ..
+            }) => self.span_err(self.prev_span, "expected item after doc comment"),
+            _ => self.span_err(self.prev_span, "expected item after attributes"),
+        }
     }
 
     /// Parse a statement. This stops just before trailing semicolons on everything but items.
@@ -4012,11 +4600,41 @@ impl<'a> Parser<'a> {
         !self.restrictions.contains(Restrictions::RESTRICTION_NO_STRUCT_LITERAL)
     }
 
+    fn is_async_block(&mut self) -> bool {
+        self.token.is_keyword(keywords::Async) &&
+        (self.look_ahead(1, |t| *t == token::OpenDelim(token::Brace)) ||
+         (self.look_ahead(1, |t| t.is_keyword(keywords::Move)) &&
+          self.look_ahead(2, |t| *t == token::OpenDelim(token::Brace)))) &&
+
+        // prevent `while async {} {}`, `if async {} {} else {}`, etc.
+        !self.restrictions.contains(Restrictions::RESTRICTION_NO_STRUCT_LITERAL)
+    }
+
+    /// `await` is only a keyword directly after a `.`; `x.await()` is still a method call.
+    fn is_await_suffix(&mut self) -> bool {
+        self.token.is_keyword(keywords::Await)
+            && !self.look_ahead(1, |t| {
+                *t == token::OpenDelim(token::Paren) || *t == token::ModSep
+            })
+    }
+
+    fn is_async_fn(&mut self) -> bool {
+        self.token.is_keyword(keywords::Async)
+            && self.look_ahead(1, |t| {
+                t.is_keyword(keywords::Fn) || t.is_keyword(keywords::Unsafe)
+            })
+    }
+
     fn is_union_item(&self) -> bool {
         self.token.is_keyword(keywords::Union)
             && self.look_ahead(1, |t| t.is_ident() && !t.is_any_keyword())
     }
 
+    fn is_auto_trait_item(&self) -> bool {
+        self.token.is_keyword(keywords::Auto)
+            && self.look_ahead(1, |t| t.is_keyword(keywords::Trait))
+    }
+
     fn is_defaultness(&self) -> bool {
         // `pub` is included for better error messages
         self.token.is_keyword(keywords::Default)
@@ -4093,7 +4711,7 @@ impl<'a> Parser<'a> {
 
                 let ident = self.parse_ident()?;
                 let (delim, tokens) = self.expect_delimited_token_tree()?;
-                if delim != token::Brace {
+                if delim != MacDelimiter::Brace {
                     if !self.eat(&token::Semi) {
                         let msg = "macros that expand to items must either \
                                    be surrounded with braces or followed by a semicolon";
@@ -4125,10 +4743,37 @@ impl<'a> Parser<'a> {
     fn parse_stmt_without_recovery(
         &mut self,
         macro_legacy_warnings: bool,
+    ) -> PResult<'a, Option<Stmt>> {
+        // Statements nest through the expressions and items in them, which
+        // count towards the nesting limit.
+        parse::ensure_sufficient_stack(|| {
+            self.parse_stmt_without_recovery_inner(macro_legacy_warnings)
+        })
+    }
+
+    fn parse_stmt_without_recovery_inner(
+        &mut self,
+        macro_legacy_warnings: bool,
     ) -> PResult<'a, Option<Stmt>> {
         maybe_whole!(self, NtStmt, |x| Some(x));
 
         let attrs = self.parse_outer_attributes()?;
+        let attrs_span = attrs.first().map(|attr| attr.span);
+        let stmt = self.parse_stmt_without_recovery_(attrs, macro_legacy_warnings)?;
+        // Like expressions, statements cover their outer attributes.
+        Ok(stmt.map(|mut stmt| {
+            if let Some(attrs_span) = attrs_span {
+                stmt.span = attrs_span.to(stmt.span);
+            }
+            stmt
+        }))
+    }
+
+    fn parse_stmt_without_recovery_(
+        &mut self,
+        attrs: Vec<Attribute>,
+        macro_legacy_warnings: bool,
+    ) -> PResult<'a, Option<Stmt>> {
         let lo = self.span;
 
         Ok(Some(if self.eat_keyword(keywords::Let) {
@@ -4138,15 +4783,27 @@ impl<'a> Parser<'a> {
                 span: lo.to(self.prev_span),
             }
         } else if let Some(macro_def) = self.eat_macro_def(&attrs, &Visibility::Inherited)? {
+            if let ItemKind::MacroDef(ast::MacroDef { legacy: false, .. }) = macro_def.node {
+                self.diagnostic()
+                    .struct_span_err(
+                        macro_def.span,
+                        "`macro` definitions are not allowed in statement position",
+                    )
+                    .help("move this definition out of the block and into a module")
+                    .emit();
+            }
             Stmt {
                 id: ast::DUMMY_NODE_ID,
                 node: StmtKind::Item(macro_def),
                 span: lo.to(self.prev_span),
             }
-        // Starts like a simple path, but not a union item.
+        // Starts like a simple path, but not a union item, an auto trait or an `async` fn or block.
         } else if self.token.is_path_start()
             && !self.token.is_qpath_start()
             && !self.is_union_item()
+            && !self.is_auto_trait_item()
+            && !self.is_async_fn()
+            && !self.is_async_block()
         {
             let pth = self.parse_path(PathStyle::Expr)?;
 
@@ -4179,8 +4836,8 @@ impl<'a> Parser<'a> {
             // check that we're pointing at delimiters (need to check
             // again after the `if`, because of `parse_ident`
             // consuming more tokens).
-            let delim = match self.token {
-                token::OpenDelim(delim) => delim,
+            match self.token {
+                token::OpenDelim(_) => {}
                 _ => {
                     // we only expect an ident if we didn't parse one
                     // above.
@@ -4189,32 +4846,26 @@ impl<'a> Parser<'a> {
                     } else {
                         ""
                     };
-                    let tok_str = self.this_token_to_string();
+                    let tok_str = self.this_token_descr();
                     return Err(self.fatal(&format!(
-                        "expected {}`(` or `{{`, found `{}`",
+                        "expected {}`(` or `{{`, found {}",
                         ident_str, tok_str
                     )));
                 }
-            };
+            }
 
-            let (_, tts) = self.expect_delimited_token_tree()?;
+            let (delim, tts) = self.expect_delimited_token_tree()?;
             let hi = self.prev_span;
 
-            let style = if delim == token::Brace {
+            let style = if delim == MacDelimiter::Brace {
                 MacStmtStyle::Braces
             } else {
                 MacStmtStyle::NoBraces
             };
 
             if id.name == keywords::Invalid.name() {
-                let mac = respan(
-                    lo.to(hi),
-                    Mac_ {
-                        path: pth,
-                        tts: tts,
-                    },
-                );
-                let node = if delim == token::Brace
+                let mac = self.mk_mac(lo.to(hi), pth, delim, tts);
+                let node = if delim == MacDelimiter::Brace
                     || self.token == token::Semi
                     || self.token == token::Eof
                 {
@@ -4275,13 +4926,7 @@ impl<'a> Parser<'a> {
                         self.mk_item(
                             span,
                             id, /*id is good here*/
-                            ItemKind::Mac(respan(
-                                span,
-                                Mac_ {
-                                    path: pth,
-                                    tts: tts,
-                                },
-                            )),
+                            ItemKind::Mac(self.mk_mac(span, pth, delim, tts)),
                             Visibility::Inherited,
                             attrs,
                         )
@@ -4294,7 +4939,13 @@ impl<'a> Parser<'a> {
                 &mut self.directory.ownership,
                 DirectoryOwnership::UnownedViaBlock,
             );
-            let item = self.parse_item_(attrs.clone(), false, true)?;
+            // Items in blocks are the ones nested in other items, other than
+            // modules.
+            let item = self.parse_nested(
+                "item",
+                |this| this.parse_item_(attrs.clone(), false, true),
+                |_, _| None,
+            )?;
             self.directory.ownership = old_directory_ownership;
 
             match item {
@@ -4355,8 +5006,8 @@ impl<'a> Parser<'a> {
 
         if !self.eat(&token::OpenDelim(token::Brace)) {
             let sp = self.span;
-            let tok = self.this_token_to_string();
-            let mut e = self.span_fatal(sp, &format!("expected `{{`, found `{}`", tok));
+            let tok = self.this_token_descr();
+            let mut e = self.span_fatal(sp, &format!("expected `{{`, found {}", tok));
 
             // Check to see if the user has written something like
             //
@@ -4407,18 +5058,22 @@ impl<'a> Parser<'a> {
     /// Parse the rest of a block expression or function body
     /// Precondition: already parsed the '{'.
     fn parse_block_tail(&mut self, lo: Span, s: BlockCheckMode) -> PResult<'a, P<Block>> {
-        let mut stmts = vec![];
-
-        while !self.eat(&token::CloseDelim(token::Brace)) {
-            if let Some(stmt) = self.parse_full_stmt(false)? {
-                stmts.push(stmt);
-            } else if self.token == token::Eof {
-                break;
-            } else {
-                // Found only `;` or `}`.
-                continue;
-            };
-        }
+        // Like statements, blocks count towards the nesting limit through
+        // what is nested in them.
+        let stmts = parse::ensure_sufficient_stack(|| -> PResult<'a, _> {
+            let mut stmts = vec![];
+            while !self.eat(&token::CloseDelim(token::Brace)) {
+                if let Some(stmt) = self.parse_full_stmt(false)? {
+                    stmts.push(stmt);
+                } else if self.token == token::Eof {
+                    break;
+                } else {
+                    // Found only `;` or `}`.
+                    continue;
+                };
+            }
+            Ok(stmts)
+        })?;
 
         Ok(P(ast::Block {
             stmts: stmts,
@@ -4428,6 +5083,21 @@ impl<'a> Parser<'a> {
         }))
     }
 
+    /// Parse statements up to the end of the input, as in the body of a
+    /// block. A final expression without a semicolon is kept as a
+    /// `StmtKind::Expr`, like the tail expression of a block.
+    pub fn parse_stmts_until_eof(&mut self) -> PResult<'a, Vec<Stmt>> {
+        let mut stmts = vec![];
+        while self.token != token::Eof {
+            // `None` means only `;` was found, or a statement was reported
+            // and skipped.
+            if let Some(stmt) = self.parse_full_stmt(false)? {
+                stmts.push(stmt);
+            }
+        }
+        Ok(stmts)
+    }
+
     /// Parse a statement, including the trailing semicolon.
     pub fn parse_full_stmt(&mut self, macro_legacy_warnings: bool) -> PResult<'a, Option<Stmt>> {
         let mut stmt = match self.parse_stmt_(macro_legacy_warnings) {
@@ -4470,7 +5140,7 @@ impl<'a> Parser<'a> {
     fn warn_missing_semicolon(&self) {
         self.diagnostic()
             .struct_span_warn(self.span, {
-                &format!("expected `;`, found `{}`", self.this_token_to_string())
+                &format!("expected `;`, found {}", self.this_token_descr())
             })
             .note({
                 "This was erroneously allowed and will become a hard error in a future release"
@@ -4655,6 +5325,7 @@ impl<'a> Parser<'a> {
                 where_clause: WhereClause {
                     id: ast::DUMMY_NODE_ID,
                     predicates: Vec::new(),
+                    span: syntax_pos::DUMMY_SP,
                 },
                 span: span_lo.to(self.prev_span),
             })
@@ -4726,11 +5397,13 @@ impl<'a> Parser<'a> {
         let mut where_clause = WhereClause {
             id: ast::DUMMY_NODE_ID,
             predicates: Vec::new(),
+            span: syntax_pos::DUMMY_SP,
         };
 
         if !self.eat_keyword(keywords::Where) {
             return Ok(where_clause);
         }
+        let lo = self.prev_span;
 
         // This is a temporary future proofing.
         //
@@ -4812,6 +5485,11 @@ impl<'a> Parser<'a> {
             }
         }
 
+        // Any trailing comma is not part of the clause.
+        where_clause.span = match where_clause.predicates.last() {
+            Some(predicate) => lo.to(predicate.span()),
+            None => lo,
+        };
         Ok(where_clause)
     }
 
@@ -4819,6 +5497,7 @@ impl<'a> Parser<'a> {
         &mut self,
         named_args: bool,
         allow_variadic: bool,
+        abi: Abi,
     ) -> PResult<'a, (Vec<Arg>, bool)> {
         let sp = self.span;
         let mut variadic = false;
@@ -4828,8 +5507,24 @@ impl<'a> Parser<'a> {
             SeqSep::trailing_allowed(token::Comma),
             |p| {
                 if p.token == token::DotDotDot {
+                    let dots_span = p.span;
                     p.bump();
-                    if allow_variadic {
+                    if !abi.supports_varargs() {
+                        let msg =
+                            format!("variadic arguments are not supported by the {} ABI", abi);
+                        let abis = Abi::all()
+                            .iter()
+                            .filter(|abi| abi.supports_varargs())
+                            .map(|abi| abi.to_string())
+                            .collect::<Vec<_>>();
+                        p.diagnostic()
+                            .struct_span_err(dots_span, &msg)
+                            .help(&format!(
+                                "only the {} ABIs support them",
+                                abis.join(" and ")
+                            ))
+                            .emit();
+                    } else if allow_variadic {
                         if p.token != token::CloseDelim(token::Paren) {
                             let span = p.span;
                             p.span_err(
@@ -4873,8 +5568,9 @@ impl<'a> Parser<'a> {
     }
 
     /// Parse the argument list and result type of a function declaration
-    pub fn parse_fn_decl(&mut self, allow_variadic: bool) -> PResult<'a, P<FnDecl>> {
-        let (args, variadic) = self.parse_fn_args(true, allow_variadic)?;
+    /// with the given ABI.
+    pub fn parse_fn_decl(&mut self, allow_variadic: bool, abi: Abi) -> PResult<'a, P<FnDecl>> {
+        let (args, variadic) = self.parse_fn_args(true, allow_variadic, abi)?;
         let ret_ty = self.parse_ret_ty()?;
 
         Ok(P(FnDecl {
@@ -5094,15 +5790,16 @@ impl<'a> Parser<'a> {
         &mut self,
         unsafety: Unsafety,
         constness: Spanned<Constness>,
+        asyncness: IsAsync,
         abi: abi::Abi,
     ) -> PResult<'a, ItemInfo> {
         let (ident, mut generics) = self.parse_fn_header()?;
-        let decl = self.parse_fn_decl(false)?;
+        let decl = self.parse_fn_decl(false, abi)?;
         generics.where_clause = self.parse_where_clause()?;
         let (inner_attrs, body) = self.parse_inner_attrs_and_block()?;
         Ok((
             ident,
-            ItemKind::Fn(decl, unsafety, constness, abi, generics, body),
+            ItemKind::Fn(decl, unsafety, constness, asyncness, abi, generics, body),
             Some(inner_attrs),
         ))
     }
@@ -5120,13 +5817,20 @@ impl<'a> Parser<'a> {
     /// - `const fn`
     /// - `unsafe fn`
     /// - `const unsafe fn`
+    /// - `async fn`
     /// - `extern fn`
     /// - etc
     pub fn parse_fn_front_matter(
         &mut self,
-    ) -> PResult<'a, (Spanned<ast::Constness>, ast::Unsafety, abi::Abi)> {
+    ) -> PResult<'a, (Spanned<ast::Constness>, IsAsync, ast::Unsafety, abi::Abi)> {
         let is_const_fn = self.eat_keyword(keywords::Const);
         let const_span = self.prev_span;
+        let asyncness = if !is_const_fn && self.is_async_fn() {
+            self.bump();
+            IsAsync::Async
+        } else {
+            IsAsync::NotAsync
+        };
         let unsafety = self.parse_unsafety()?;
         let (constness, unsafety, abi) = if is_const_fn {
             (respan(const_span, Constness::Const), unsafety, Abi::Rust)
@@ -5139,7 +5843,7 @@ impl<'a> Parser<'a> {
             (respan(self.prev_span, Constness::NotConst), unsafety, abi)
         };
         self.expect_keyword(keywords::Fn)?;
-        Ok((constness, unsafety, abi))
+        Ok((constness, asyncness, unsafety, abi))
     }
 
     /// Parse an impl item.
@@ -5246,7 +5950,7 @@ impl<'a> Parser<'a> {
         at_end: &mut bool,
     ) -> PResult<'a, (Ident, Vec<ast::Attribute>, ast::ImplItemKind)> {
         // code copied from parse_macro_use_or_failure... abstraction!
-        if self.token.is_path_start() {
+        if self.token.is_path_start() && !self.is_async_fn() {
             // Method macro.
 
             let prev_span = self.prev_span;
@@ -5266,24 +5970,18 @@ impl<'a> Parser<'a> {
             // eat a matched-delimiter token tree:
             *at_end = true;
             let (delim, tts) = self.expect_delimited_token_tree()?;
-            if delim != token::Brace {
+            if delim != MacDelimiter::Brace {
                 self.expect(&token::Semi)?
             }
 
-            let mac = respan(
-                lo.to(self.prev_span),
-                Mac_ {
-                    path: pth,
-                    tts: tts,
-                },
-            );
+            let mac = self.mk_mac(lo.to(self.prev_span), pth, delim, tts);
             Ok((
                 keywords::Invalid.ident(),
                 vec![],
                 ast::ImplItemKind::Macro(mac),
             ))
         } else {
-            let (constness, unsafety, abi) = self.parse_fn_front_matter()?;
+            let (constness, asyncness, unsafety, abi) = self.parse_fn_front_matter()?;
             let ident = self.parse_ident()?;
             let mut generics = self.parse_generics()?;
             let decl = self.parse_fn_decl_with_self(|p| p.parse_arg())?;
@@ -5299,6 +5997,7 @@ impl<'a> Parser<'a> {
                         abi: abi,
                         unsafety: unsafety,
                         constness: constness,
+                        asyncness,
                         decl: decl,
                     },
                     body,
@@ -5307,11 +6006,16 @@ impl<'a> Parser<'a> {
         }
     }
 
-    /// Parse trait Foo { ... }
-    fn parse_item_trait(&mut self, unsafety: Unsafety) -> PResult<'a, ItemInfo> {
+    /// Parse trait Foo { ... } or auto trait Foo {}
+    fn parse_item_trait(&mut self, is_auto: IsAuto, unsafety: Unsafety) -> PResult<'a, ItemInfo> {
+        let ident_span = self.span;
         let ident = self.parse_ident()?;
         let mut tps = self.parse_generics()?;
 
+        if self.eat(&token::Eq) {
+            return self.parse_item_trait_alias(ident, ident_span, tps, is_auto, unsafety);
+        }
+
         // Parse optional colon and supertrait bounds.
         let bounds = if self.eat(&token::Colon) {
             self.parse_ty_param_bounds()?
@@ -5322,6 +6026,39 @@ impl<'a> Parser<'a> {
         tps.where_clause = self.parse_where_clause()?;
 
         self.expect(&token::OpenDelim(token::Brace))?;
+        let trait_items = self.parse_trait_items();
+
+        if is_auto == IsAuto::Yes {
+            if tps.is_parameterized() {
+                struct_span_err!(
+                    self.sess.span_diagnostic,
+                    tps.span,
+                    E0567,
+                    "auto traits cannot have generic parameters"
+                )
+                .emit();
+            }
+            if let Some(first) = trait_items.first() {
+                let items_span = first.span.to(trait_items.last().unwrap().span);
+                struct_span_err!(
+                    self.sess.span_diagnostic,
+                    items_span,
+                    E0380,
+                    "auto traits cannot have methods or associated items"
+                )
+                .emit();
+            }
+        }
+
+        Ok((
+            ident,
+            ItemKind::Trait(is_auto, unsafety, tps, bounds, trait_items),
+            None,
+        ))
+    }
+
+    /// Parses the items of a trait up to and including its closing brace.
+    fn parse_trait_items(&mut self) -> Vec<TraitItem> {
         let mut trait_items = vec![];
         while !self.eat(&token::CloseDelim(token::Brace)) {
             let mut at_end = false;
@@ -5335,11 +6072,62 @@ impl<'a> Parser<'a> {
                 }
             }
         }
-        Ok((
-            ident,
-            ItemKind::Trait(unsafety, tps, bounds, trait_items),
-            None,
-        ))
+        trait_items
+    }
+
+    /// Parses the rest of a trait alias, `trait Foo<T> = Bar + Baz where T: Quux;`,
+    /// after the `=`.
+    fn parse_item_trait_alias(
+        &mut self,
+        ident: Ident,
+        ident_span: Span,
+        mut tps: ast::Generics,
+        is_auto: IsAuto,
+        unsafety: Unsafety,
+    ) -> PResult<'a, ItemInfo> {
+        let eq_span = self.prev_span;
+        let bounds = self.parse_ty_param_bounds()?;
+        tps.where_clause = self.parse_where_clause()?;
+
+        if is_auto == IsAuto::Yes {
+            self.span_err(ident_span, "trait aliases cannot be `auto`");
+        }
+        if unsafety == Unsafety::Unsafe {
+            self.span_err(ident_span, "trait aliases cannot be `unsafe`");
+        }
+
+        if !self.check(&token::OpenDelim(token::Brace)) {
+            self.expect(&token::Semi)?;
+            return Ok((ident, ItemKind::TraitAlias(tps, bounds), None));
+        }
+
+        // A body after the bounds: either the `=` should have been a `:`, or
+        // there shouldn't be a body.
+        let body_lo = self.span;
+        self.bump();
+        let trait_items = self.parse_trait_items();
+        let body_span = body_lo.to(self.prev_span);
+        let mut err = self
+            .diagnostic()
+            .struct_span_err(body_span, "trait aliases cannot have a body");
+        err.span_label(eq_span, "this `=` declares a trait alias");
+        if trait_items.is_empty() {
+            err.span_suggestion(body_span, "remove the braces", ";".to_string());
+            err.emit();
+            Ok((ident, ItemKind::TraitAlias(tps, bounds), None))
+        } else {
+            err.span_suggestion(
+                eq_span,
+                "to declare a trait with supertraits instead, use `:`",
+                ":".to_string(),
+            );
+            err.emit();
+            Ok((
+                ident,
+                ItemKind::Trait(is_auto, unsafety, tps, bounds, trait_items),
+                None,
+            ))
+        }
     }
 
     /// Parses items implementations variants
@@ -5514,10 +6302,10 @@ impl<'a> Parser<'a> {
             self.expect(&token::Semi)?;
             body
         } else {
-            let token_str = self.this_token_to_string();
+            let token_str = self.this_token_descr();
             return Err(self.fatal(&format!(
                 "expected `where`, `{{`, `(`, or `;` after struct \
-                                            name, found `{}`",
+                                            name, found {}",
                 token_str
             )));
         };
@@ -5537,10 +6325,10 @@ impl<'a> Parser<'a> {
         } else if self.token == token::OpenDelim(token::Brace) {
             VariantData::Struct(self.parse_record_struct_body()?, ast::DUMMY_NODE_ID)
         } else {
-            let token_str = self.this_token_to_string();
+            let token_str = self.this_token_descr();
             return Err(self.fatal(&format!(
                 "expected `where` or `{{` after union \
-                                            name, found `{}`",
+                                            name, found {}",
                 token_str
             )));
         };
@@ -5561,10 +6349,10 @@ impl<'a> Parser<'a> {
 
             self.bump();
         } else {
-            let token_str = self.this_token_to_string();
+            let token_str = self.this_token_descr();
             return Err(self.fatal(&format!(
                 "expected `where`, or `{{` after struct \
-                                name, found `{}`",
+                                name, found {}",
                 token_str
             )));
         }
@@ -5617,10 +6405,7 @@ impl<'a> Parser<'a> {
             _ => {
                 return Err(self.span_fatal_help(
                     self.span,
-                    &format!(
-                        "expected `,`, or `}}`, found `{}`",
-                        self.this_token_to_string()
-                    ),
+                    &format!("expected `,`, or `}}`, found {}", self.this_token_descr()),
                     "struct fields should be separated by commas",
                 ))
             }
@@ -5719,12 +6504,20 @@ impl<'a> Parser<'a> {
     fn parse_mod_items(&mut self, term: &token::Token, inner_lo: Span) -> PResult<'a, Mod> {
         let mut items = vec![];
         while let Some(item) = self.parse_item()? {
+            let name = if item.ident.name == keywords::Invalid.name() {
+                None
+            } else {
+                Some(item.ident.name)
+            };
+            if self.sess.item_parsed(name, item.span).is_break() {
+                return Err(self.fatal("parsing cancelled"));
+            }
             items.push(item);
         }
 
         if !self.eat(term) {
-            let token_str = self.this_token_to_string();
-            return Err(self.fatal(&format!("expected item, found `{}`", token_str)));
+            let token_str = self.this_token_descr();
+            return Err(self.fatal(&format!("expected item, found {}", token_str)));
         }
 
         let hi = if self.span == syntax_pos::DUMMY_SP {
@@ -5810,8 +6603,23 @@ impl<'a> Parser<'a> {
 
             self.expect(&token::OpenDelim(token::Brace))?;
             let mod_inner_lo = self.span;
-            let attrs = self.parse_inner_attributes()?;
-            let module = self.parse_mod_items(&token::CloseDelim(token::Brace), mod_inner_lo)?;
+            let (attrs, module) = self.parse_nested(
+                "module",
+                |this| {
+                    let attrs = this.parse_inner_attributes()?;
+                    let module =
+                        this.parse_mod_items(&token::CloseDelim(token::Brace), mod_inner_lo)?;
+                    Ok((attrs, module))
+                },
+                |this, span| {
+                    this.eat(&token::CloseDelim(token::Brace));
+                    let module = ast::Mod {
+                        inner: span,
+                        items: Vec::new(),
+                    };
+                    (Vec::new(), module)
+                },
+            )?;
 
             self.directory = old_directory;
             Ok((id, ItemKind::Mod(module), Some(attrs)))
@@ -5916,20 +6724,21 @@ impl<'a> Parser<'a> {
                 .diagnostic()
                 .struct_span_err(id_sp, "cannot declare a new module at this location");
             if id_sp != syntax_pos::DUMMY_SP {
-                let src_path = PathBuf::from(self.sess.codemap().span_to_filename(id_sp));
-                if let Some(stem) = src_path.file_stem() {
-                    let mut dest_path = src_path.clone();
-                    dest_path.set_file_name(stem);
-                    dest_path.push("mod.rs");
-                    err.span_note(
-                        id_sp,
-                        &format!(
-                            "maybe move this module `{}` to its own \
-                                            directory via `{}`",
-                            src_path.to_string_lossy(),
-                            dest_path.to_string_lossy()
-                        ),
-                    );
+                if let FileName::Real(src_path) = self.sess.codemap().span_to_filename(id_sp) {
+                    if let Some(stem) = src_path.file_stem() {
+                        let mut dest_path = src_path.clone();
+                        dest_path.set_file_name(stem);
+                        dest_path.push("mod.rs");
+                        err.span_note(
+                            id_sp,
+                            &format!(
+                                "maybe move this module `{}` to its own \
+                                                directory via `{}`",
+                                src_path.to_string_lossy(),
+                                dest_path.to_string_lossy()
+                            ),
+                        );
+                    }
                 }
             }
             if paths.path_exists {
@@ -5956,7 +6765,7 @@ impl<'a> Parser<'a> {
         name: String,
         id_sp: Span,
     ) -> PResult<'a, (ast::ItemKind, Vec<ast::Attribute>)> {
-        let mut included_mod_stack = self.sess.included_mod_stack.borrow_mut();
+        let included_mod_stack = self.sess.included_mod_stack();
         if let Some(i) = included_mod_stack.iter().position(|p| *p == path) {
             let mut err = String::from("circular modules: ");
             let len = included_mod_stack.len();
@@ -5967,17 +6776,27 @@ impl<'a> Parser<'a> {
             err.push_str(&path.to_string_lossy());
             return Err(self.span_fatal(id_sp, &err[..]));
         }
-        included_mod_stack.push(path.clone());
         drop(included_mod_stack);
-
-        let mut p0 =
-            new_sub_parser_from_file(self.sess, &path, directory_ownership, Some(name), id_sp);
-        p0.cfg_mods = self.cfg_mods;
-        let mod_inner_lo = p0.span;
-        let mod_attrs = p0.parse_inner_attributes()?;
-        let m0 = p0.parse_mod_items(&token::Eof, mod_inner_lo)?;
-        self.sess.included_mod_stack.borrow_mut().pop();
-        Ok((ast::ItemKind::Mod(m0), mod_attrs))
+        self.sess.push_included_mod(path.clone(), id_sp);
+
+        // Errors reading or lexing the file are returned like any other, so
+        // that they don't abort callers that carry on after fatal errors.
+        let p0 = maybe_new_sub_parser_from_file(
+            self.sess,
+            &path,
+            directory_ownership,
+            Some(name),
+            id_sp,
+        );
+        let result = p0.and_then(|mut p0| {
+            p0.cfg_mods = self.cfg_mods;
+            let mod_inner_lo = p0.span;
+            let mod_attrs = p0.parse_inner_attributes()?;
+            let m0 = p0.parse_mod_items(&token::Eof, mod_inner_lo)?;
+            Ok((ast::ItemKind::Mod(m0), mod_attrs))
+        });
+        self.sess.pop_included_mod();
+        result
     }
 
     /// Parse a function declaration from a foreign module
@@ -5986,11 +6805,12 @@ impl<'a> Parser<'a> {
         vis: ast::Visibility,
         lo: Span,
         attrs: Vec<Attribute>,
+        abi: Abi,
     ) -> PResult<'a, ForeignItem> {
         self.expect_keyword(keywords::Fn)?;
 
         let (ident, mut generics) = self.parse_fn_header()?;
-        let decl = self.parse_fn_decl(true)?;
+        let decl = self.parse_fn_decl(true, abi)?;
         generics.where_clause = self.parse_where_clause()?;
         let hi = self.span;
         self.expect(&token::Semi)?;
@@ -6029,6 +6849,28 @@ impl<'a> Parser<'a> {
         })
     }
 
+    /// Parse a type from a foreign module
+    fn parse_item_foreign_type(
+        &mut self,
+        vis: ast::Visibility,
+        lo: Span,
+        attrs: Vec<Attribute>,
+    ) -> PResult<'a, ForeignItem> {
+        self.expect_keyword(keywords::Type)?;
+
+        let ident = self.parse_ident()?;
+        let hi = self.span;
+        self.expect(&token::Semi)?;
+        Ok(ast::ForeignItem {
+            ident: ident,
+            attrs: attrs,
+            node: ForeignItemKind::Ty,
+            id: ast::DUMMY_NODE_ID,
+            span: lo.to(hi),
+            vis: vis,
+        })
+    }
+
     /// Parse extern crate links
     ///
     /// # Examples
@@ -6083,7 +6925,7 @@ impl<'a> Parser<'a> {
         attrs.extend(self.parse_inner_attributes()?);
 
         let mut foreign_items = vec![];
-        while let Some(item) = self.parse_foreign_item()? {
+        while let Some(item) = self.parse_foreign_item(abi)? {
             foreign_items.push(item);
         }
         self.expect(&token::CloseDelim(token::Brace))?;
@@ -6194,15 +7036,28 @@ impl<'a> Parser<'a> {
                     Some(abi) => Ok(Some(abi)),
                     None => {
                         let prev_span = self.prev_span;
-                        self.span_err(
+                        let names = abi::all_names();
+                        let mut err = self.diagnostic().struct_span_err(
                             prev_span,
                             &format!(
                                 "invalid ABI: expected one of [{}], \
                                      found `{}`",
-                                abi::all_names().join(", "),
+                                names.join(", "),
                                 s
                             ),
                         );
+                        err.span_label(prev_span, "invalid ABI");
+                        let names: Vec<_> = names.into_iter().map(Symbol::intern).collect();
+                        if let Some(name) =
+                            find_best_match_for_name(names.iter(), &s.as_str(), None)
+                        {
+                            err.span_suggestion(
+                                prev_span,
+                                "there is an ABI with a similar name",
+                                format!("\"{}\"", name),
+                            );
+                        }
+                        err.emit();
                         Ok(None)
                     }
                 }
@@ -6220,6 +7075,19 @@ impl<'a> Parser<'a> {
         attrs: Vec<Attribute>,
         macros_allowed: bool,
         attributes_allowed: bool,
+    ) -> PResult<'a, Option<P<Item>>> {
+        let item = self.parse_item_implementation(attrs, macros_allowed, attributes_allowed)?;
+        if item.is_some() {
+            self.sess.record_stats(|stats| stats.items += 1);
+        }
+        Ok(item)
+    }
+
+    fn parse_item_implementation(
+        &mut self,
+        attrs: Vec<Attribute>,
+        macros_allowed: bool,
+        attributes_allowed: bool,
     ) -> PResult<'a, Option<P<Item>>> {
         maybe_whole!(self, NtItem, |item| {
             let mut item = item.unwrap();
@@ -6258,6 +7126,7 @@ impl<'a> Parser<'a> {
                 let (ident, item_, extra_attrs) = self.parse_item_fn(
                     Unsafety::Normal,
                     respan(fn_span, Constness::NotConst),
+                    IsAsync::NotAsync,
                     abi,
                 )?;
                 let prev_span = self.prev_span;
@@ -6309,8 +7178,12 @@ impl<'a> Parser<'a> {
                     Unsafety::Normal
                 };
                 self.bump();
-                let (ident, item_, extra_attrs) =
-                    self.parse_item_fn(unsafety, respan(const_span, Constness::Const), Abi::Rust)?;
+                let (ident, item_, extra_attrs) = self.parse_item_fn(
+                    unsafety,
+                    respan(const_span, Constness::Const),
+                    IsAsync::NotAsync,
+                    Abi::Rust,
+                )?;
                 let prev_span = self.prev_span;
                 let item = self.mk_item(
                     lo.to(prev_span),
@@ -6342,12 +7215,19 @@ impl<'a> Parser<'a> {
             return Ok(Some(item));
         }
         if self.check_keyword(keywords::Unsafe)
-            && self.look_ahead(1, |t| t.is_keyword(keywords::Trait))
+            && (self.look_ahead(1, |t| t.is_keyword(keywords::Trait))
+                || self.look_ahead(1, |t| t.is_keyword(keywords::Auto)))
         {
             // UNSAFE TRAIT ITEM
             self.expect_keyword(keywords::Unsafe)?;
+            let is_auto = if self.eat_keyword(keywords::Auto) {
+                IsAuto::Yes
+            } else {
+                IsAuto::No
+            };
             self.expect_keyword(keywords::Trait)?;
-            let (ident, item_, extra_attrs) = self.parse_item_trait(ast::Unsafety::Unsafe)?;
+            let (ident, item_, extra_attrs) =
+                self.parse_item_trait(is_auto, ast::Unsafety::Unsafe)?;
             let prev_span = self.prev_span;
             let item = self.mk_item(
                 lo.to(prev_span),
@@ -6380,6 +7260,28 @@ impl<'a> Parser<'a> {
             );
             return Ok(Some(item));
         }
+        if self.is_async_fn() {
+            // ASYNC FUNCTION ITEM
+            self.bump();
+            let unsafety = self.parse_unsafety()?;
+            self.expect_keyword(keywords::Fn)?;
+            let fn_span = self.prev_span;
+            let (ident, item_, extra_attrs) = self.parse_item_fn(
+                unsafety,
+                respan(fn_span, Constness::NotConst),
+                IsAsync::Async,
+                Abi::Rust,
+            )?;
+            let prev_span = self.prev_span;
+            let item = self.mk_item(
+                lo.to(prev_span),
+                ident,
+                item_,
+                visibility,
+                maybe_append(attrs, extra_attrs),
+            );
+            return Ok(Some(item));
+        }
         if self.check_keyword(keywords::Fn) {
             // FUNCTION ITEM
             self.bump();
@@ -6387,6 +7289,7 @@ impl<'a> Parser<'a> {
             let (ident, item_, extra_attrs) = self.parse_item_fn(
                 Unsafety::Normal,
                 respan(fn_span, Constness::NotConst),
+                IsAsync::NotAsync,
                 Abi::Rust,
             )?;
             let prev_span = self.prev_span;
@@ -6411,8 +7314,12 @@ impl<'a> Parser<'a> {
             };
             self.expect_keyword(keywords::Fn)?;
             let fn_span = self.prev_span;
-            let (ident, item_, extra_attrs) =
-                self.parse_item_fn(Unsafety::Unsafe, respan(fn_span, Constness::NotConst), abi)?;
+            let (ident, item_, extra_attrs) = self.parse_item_fn(
+                Unsafety::Unsafe,
+                respan(fn_span, Constness::NotConst),
+                IsAsync::NotAsync,
+                abi,
+            )?;
             let prev_span = self.prev_span;
             let item = self.mk_item(
                 lo.to(prev_span),
@@ -6462,9 +7369,16 @@ impl<'a> Parser<'a> {
             );
             return Ok(Some(item));
         }
-        if self.eat_keyword(keywords::Trait) {
+        if self.check_keyword(keywords::Trait) || self.is_auto_trait_item() {
             // TRAIT ITEM
-            let (ident, item_, extra_attrs) = self.parse_item_trait(ast::Unsafety::Normal)?;
+            let is_auto = if self.eat_keyword(keywords::Auto) {
+                IsAuto::Yes
+            } else {
+                IsAuto::No
+            };
+            self.expect_keyword(keywords::Trait)?;
+            let (ident, item_, extra_attrs) =
+                self.parse_item_trait(is_auto, ast::Unsafety::Normal)?;
             let prev_span = self.prev_span;
             let item = self.mk_item(
                 lo.to(prev_span),
@@ -6529,7 +7443,7 @@ impl<'a> Parser<'a> {
     }
 
     /// Parse a foreign item.
-    fn parse_foreign_item(&mut self) -> PResult<'a, Option<ForeignItem>> {
+    pub fn parse_foreign_item(&mut self, abi: Abi) -> PResult<'a, Option<ForeignItem>> {
         let attrs = self.parse_outer_attributes()?;
         let lo = self.span;
         let visibility = self.parse_visibility(false)?;
@@ -6540,13 +7454,31 @@ impl<'a> Parser<'a> {
         }
         if self.check_keyword(keywords::Fn) {
             // FOREIGN FUNCTION ITEM
-            return Ok(Some(self.parse_item_foreign_fn(visibility, lo, attrs)?));
+            return Ok(Some(
+                self.parse_item_foreign_fn(visibility, lo, attrs, abi)?,
+            ));
+        }
+        if self.check_keyword(keywords::Type) {
+            // FOREIGN TYPE ITEM
+            return Ok(Some(self.parse_item_foreign_type(visibility, lo, attrs)?));
         }
 
-        // FIXME #5668: this will occur for a macro invocation:
+        // FOREIGN MACRO INVOCATION
         match self.parse_macro_use_or_failure(attrs, true, false, lo, visibility)? {
             Some(item) => {
-                return Err(self.span_fatal(item.span, "macros cannot expand to foreign items"));
+                let item = item.unwrap();
+                let mac = match item.node {
+                    ItemKind::Mac(mac) => mac,
+                    _ => unreachable!(),
+                };
+                Ok(Some(ForeignItem {
+                    ident: item.ident,
+                    attrs: item.attrs,
+                    node: ForeignItemKind::Macro(mac),
+                    id: item.id,
+                    span: item.span,
+                    vis: item.vis,
+                }))
             }
             None => Ok(None),
         }
@@ -6583,7 +7515,7 @@ impl<'a> Parser<'a> {
             };
             // eat a matched-delimiter token tree:
             let (delim, tts) = self.expect_delimited_token_tree()?;
-            if delim != token::Brace {
+            if delim != MacDelimiter::Brace {
                 if !self.eat(&token::Semi) {
                     let prev_span = self.prev_span;
                     self.span_err(
@@ -6596,13 +7528,7 @@ impl<'a> Parser<'a> {
             }
 
             let hi = self.prev_span;
-            let mac = respan(
-                mac_lo.to(hi),
-                Mac_ {
-                    path: pth,
-                    tts: tts,
-                },
-            );
+            let mac = self.mk_mac(mac_lo.to(hi), pth, delim, tts);
             let item = self.mk_item(lo.to(hi), id, ItemKind::Mac(mac), visibility, attrs);
             return Ok(Some(item));
         }
@@ -6627,6 +7553,33 @@ impl<'a> Parser<'a> {
         self.parse_item_(attrs, true, false)
     }
 
+    /// Like `parse_item`, but says why no item was found.
+    pub fn parse_item_detailed(&mut self) -> PResult<'a, ItemParseOutcome> {
+        let attrs = self.parse_outer_attributes()?;
+        let had_attrs = !attrs.is_empty();
+        Ok(match self.parse_item_(attrs, true, false)? {
+            Some(item) => ItemParseOutcome::Item(item),
+            None => ItemParseOutcome::NoItem {
+                reason: if had_attrs {
+                    NoItemReason::DanglingAttributes
+                } else if self.token == token::Eof {
+                    NoItemReason::Eof
+                } else {
+                    NoItemReason::NotAnItem
+                },
+                // The span of `Eof` may cover the last token.
+                stopped_at: if self.token == token::Eof {
+                    Span {
+                        lo: self.span.hi,
+                        ..self.span
+                    }
+                } else {
+                    self.span
+                },
+            },
+        })
+    }
+
     fn parse_path_list_items(&mut self) -> PResult<'a, Vec<ast::PathListItem>> {
         self.parse_unspanned_seq(
             &token::OpenDelim(token::Brace),
@@ -6673,7 +7626,7 @@ impl<'a> Parser<'a> {
             // `{foo, bar}`, `::{foo, bar}`, `*`, or `::*`.
             self.eat(&token::ModSep);
             let prefix = ast::Path {
-                segments: vec![PathSegment::crate_root()],
+                segments: vec![PathSegment::crate_root(lo.shrink_to_lo())],
                 span: lo.to(self.span),
             };
             let view_path_kind = if self.eat(&token::BinOp(token::Star)) {
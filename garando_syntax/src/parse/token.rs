@@ -5,6 +5,7 @@ pub use self::Nonterminal::*;
 pub use self::Token::*;
 
 use crate::ast::{self};
+use crate::print::pprust;
 use crate::ptr::P;
 use crate::symbol::keywords;
 use crate::tokenstream::TokenTree;
@@ -65,6 +66,8 @@ pub enum Lit {
     StrRaw(ast::Name, usize), /* raw str delimited by n hash symbols */
     ByteStr(ast::Name),
     ByteStrRaw(ast::Name, usize), /* raw byte str delimited by n hash symbols */
+    CStr(ast::Name),
+    CStrRaw(ast::Name, usize), /* raw C str delimited by n hash symbols */
 }
 
 impl Lit {
@@ -76,6 +79,7 @@ impl Lit {
             Float(_) => "float",
             Str_(_) | StrRaw(..) => "string",
             ByteStr(_) | ByteStrRaw(..) => "byte string",
+            CStr(_) | CStrRaw(..) => "C string",
         }
     }
 }
@@ -212,7 +216,7 @@ impl Token {
             ModSep                      | // global path
             Pound                       => true, // expression attributes
             Interpolated(ref nt) => match **nt {
-                NtIdent(..) | NtExpr(..) | NtBlock(..) | NtPath(..) => true,
+                NtIdent(..) | NtExpr(..) | NtLiteral(..) | NtBlock(..) | NtPath(..) => true,
                 _ => false,
             },
             _ => false,
@@ -355,6 +359,27 @@ impl Token {
             _ => false,
         }
     }
+
+    /// Returns `true` if the token is an identifier that can't be used as a
+    /// name: a strict or reserved keyword, or `{{root}}`.
+    pub fn is_reserved_ident(&self) -> bool {
+        self.is_any_keyword() || self.is_keyword(keywords::CrateRoot)
+    }
+
+    /// Returns a description of the token for use in diagnostics, such as
+    /// "end of input", "keyword `fn`" or "the `=>` token".
+    pub fn describe(&self) -> String {
+        let text = pprust::token_to_string(self);
+        match *self {
+            Eof => "end of input".to_string(),
+            DocComment(..) => "doc comment".to_string(),
+            Interpolated(ref nt) => format!("an interpolated {}", nt.descr()),
+            Ident(..) if self.is_strict_keyword() => format!("keyword `{}`", text),
+            Ident(..) if self.is_reserved_keyword() => format!("reserved keyword `{}`", text),
+            Ident(..) | Lifetime(..) | Literal(..) => format!("`{}`", text),
+            _ => format!("the `{}` token", text),
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -365,6 +390,8 @@ pub enum Nonterminal {
     NtStmt(ast::Stmt),
     NtPat(P<ast::Pat>),
     NtExpr(P<ast::Expr>),
+    /// A literal, possibly preceded by `-`.
+    NtLiteral(P<ast::Expr>),
     NtTy(P<ast::Ty>),
     NtIdent(ast::SpannedIdent),
     /// Stuff inside brackets for attributes
@@ -381,6 +408,32 @@ pub enum Nonterminal {
     NtArg(ast::Arg),
 }
 
+impl Nonterminal {
+    /// The kind of syntax this nonterminal holds, e.g. "expression".
+    pub fn descr(&self) -> &'static str {
+        match *self {
+            NtItem(..) => "item",
+            NtBlock(..) => "block",
+            NtStmt(..) => "statement",
+            NtPat(..) => "pattern",
+            NtExpr(..) => "expression",
+            NtLiteral(..) => "literal",
+            NtTy(..) => "type",
+            NtIdent(..) => "identifier",
+            NtMeta(..) => "meta item",
+            NtPath(..) => "path",
+            NtVis(..) => "visibility",
+            NtTT(..) => "token tree",
+            NtArm(..) => "match arm",
+            NtImplItem(..) => "impl item",
+            NtTraitItem(..) => "trait item",
+            NtGenerics(..) => "generics",
+            NtWhereClause(..) => "where clause",
+            NtArg(..) => "argument",
+        }
+    }
+}
+
 impl fmt::Debug for Nonterminal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -389,6 +442,7 @@ impl fmt::Debug for Nonterminal {
             NtStmt(..) => f.pad("NtStmt(..)"),
             NtPat(..) => f.pad("NtPat(..)"),
             NtExpr(..) => f.pad("NtExpr(..)"),
+            NtLiteral(..) => f.pad("NtLiteral(..)"),
             NtTy(..) => f.pad("NtTy(..)"),
             NtIdent(..) => f.pad("NtIdent(..)"),
             NtMeta(..) => f.pad("NtMeta(..)"),
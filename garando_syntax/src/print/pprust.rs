@@ -5,7 +5,7 @@ use crate::ast::Attribute;
 use crate::ast::{self, BlockCheckMode, PatKind, RangeEnd};
 use crate::ast::{RegionTyParamBound, SelfKind, TraitBoundModifier, TraitTyParamBound};
 use crate::attr;
-use crate::codemap::{self, CodeMap};
+use crate::codemap::CodeMap;
 use crate::parse::lexer::comments;
 use crate::parse::token::{self, BinOpToken, Token};
 use crate::parse::{self, ParseSess};
@@ -200,6 +200,58 @@ pub fn binop_to_string(op: BinOpToken) -> &'static str {
     }
 }
 
+/// Tokens printed without a space before them in token streams.
+const NO_SPACE_BEFORE: &[Token] = &[
+    token::Comma,
+    token::Semi,
+    token::Dot,
+    token::Colon,
+    token::ModSep,
+    token::Question,
+];
+
+/// Tokens printed without a space after them in token streams.
+const NO_SPACE_AFTER: &[Token] = &[token::Dot, token::ModSep, token::Dollar, token::Pound];
+
+/// Tokens that lex as a different token when written next to each other,
+/// and so always keep a space between them.
+const GLUING: &[Token] = &[
+    token::Dot,
+    token::DotDot,
+    token::DotDotDot,
+    token::Colon,
+    token::ModSep,
+];
+
+/// Whether to print a space between two adjacent token trees.
+fn space_between(prev: &TokenTree, next: &TokenTree) -> bool {
+    // An identifier that can name a macro or a function.
+    let is_name = |tok: &Token| tok.is_ident() && !tok.is_any_keyword();
+    let is_group = |tt: &TokenTree| match *tt {
+        TokenTree::Delimited(_, ref delimed) => delimed.delim != token::Brace,
+        TokenTree::Token(..) => false,
+    };
+    match (prev, next) {
+        (TokenTree::Token(_, a), TokenTree::Token(_, b))
+            if GLUING.contains(a) && GLUING.contains(b) =>
+        {
+            true
+        }
+        // `1 .max(2)`, where `1.` would lex as a float.
+        (
+            TokenTree::Token(_, token::Literal(token::Integer(_), None)),
+            TokenTree::Token(_, token::Dot),
+        ) => true,
+        (_, TokenTree::Token(_, b)) if NO_SPACE_BEFORE.contains(b) => false,
+        (TokenTree::Token(_, a), _) if NO_SPACE_AFTER.contains(a) => false,
+        // `name!`, `name(..)` and `name[..]`.
+        (TokenTree::Token(_, a), TokenTree::Token(_, token::Not)) => !is_name(a),
+        (TokenTree::Token(_, a), _) if is_group(next) => !is_name(a) && *a != token::Not,
+        // `f(..)(..)` and `a[..][..]`.
+        _ => !(is_group(prev) && is_group(next)),
+    }
+}
+
 pub fn token_to_string(tok: &Token) -> String {
     match *tok {
         token::Eq => "=".to_string(),
@@ -257,6 +309,12 @@ pub fn token_to_string(tok: &Token) -> String {
                     delim = repeat("#", n),
                     string = s
                 ),
+                token::CStr(v) => format!("c\"{}\"", v),
+                token::CStrRaw(s, n) => format!(
+                    "cr{delim}\"{string}\"{delim}",
+                    delim = repeat("#", n),
+                    string = s
+                ),
             };
 
             if let Some(s) = suf {
@@ -281,6 +339,7 @@ pub fn token_to_string(tok: &Token) -> String {
 
         token::Interpolated(ref nt) => match **nt {
             token::NtExpr(ref e) => expr_to_string(e),
+            token::NtLiteral(ref e) => expr_to_string(e),
             token::NtMeta(ref e) => meta_item_to_string(e),
             token::NtTy(ref e) => ty_to_string(e),
             token::NtPath(ref e) => path_to_string(e),
@@ -394,6 +453,7 @@ pub fn fun_to_string(
             decl,
             unsafety,
             constness,
+            ast::IsAsync::NotAsync,
             Abi::Rust,
             Some(name),
             generics,
@@ -439,7 +499,7 @@ pub fn arg_to_string(arg: &ast::Arg) -> String {
 }
 
 pub fn mac_to_string(arg: &ast::Mac) -> String {
-    to_string(|s| s.print_mac(arg, crate::parse::token::Paren))
+    to_string(|s| s.print_mac(arg))
 }
 
 pub fn visibility_qualified(vis: &ast::Visibility, s: &str) -> String {
@@ -681,6 +741,15 @@ pub trait PrintState<'a> {
                 }
                 word(self.writer(), &format!("b\"{}\"", escaped))
             }
+            ast::LitKind::CStr(ref v) => {
+                let mut escaped: String = String::new();
+                // The terminating NUL isn't written out, but a literal built
+                // without one is printed in full.
+                for &ch in v.strip_suffix(&[0]).unwrap_or(v) {
+                    escaped.extend(ascii::escape_default(ch).map(|c| c as char));
+                }
+                word(self.writer(), &format!("c\"{}\"", escaped))
+            }
         }
     }
 
@@ -772,8 +841,10 @@ pub trait PrintState<'a> {
                         word(self.writer(), &segment.identifier.name.as_str())?;
                     }
                 }
-                space(self.writer())?;
-                self.print_tts(attr.tokens.clone())?;
+                if !attr.tokens.is_empty() {
+                    space(self.writer())?;
+                    self.print_tts(attr.tokens.clone())?;
+                }
             }
             word(self.writer(), "]")
         }
@@ -825,9 +896,7 @@ pub trait PrintState<'a> {
             }
             TokenTree::Delimited(_, ref delimed) => {
                 word(self.writer(), &token_to_string(&delimed.open_token()))?;
-                space(self.writer())?;
                 self.print_tts(delimed.stream())?;
-                space(self.writer())?;
                 word(self.writer(), &token_to_string(&delimed.close_token()))
             }
         }
@@ -835,11 +904,15 @@ pub trait PrintState<'a> {
 
     fn print_tts(&mut self, tts: tokenstream::TokenStream) -> io::Result<()> {
         self.ibox(0)?;
-        for (i, tt) in tts.into_trees().enumerate() {
-            if i != 0 {
-                space(self.writer())?;
+        let mut prev = None;
+        for tt in tts.into_trees() {
+            if let Some(ref prev) = prev {
+                if space_between(prev, &tt) {
+                    space(self.writer())?;
+                }
             }
-            self.print_tt(tt)?;
+            self.print_tt(tt.clone())?;
+            prev = Some(tt);
         }
         self.end()
     }
@@ -1062,6 +1135,7 @@ impl<'a> State<'a> {
                     where_clause: ast::WhereClause {
                         id: ast::DUMMY_NODE_ID,
                         predicates: Vec::new(),
+                        span: syntax_pos::DUMMY_SP,
                     },
                     span: syntax_pos::DUMMY_SP,
                 };
@@ -1099,7 +1173,7 @@ impl<'a> State<'a> {
                 word(&mut self.s, "Self")?;
             }
             ast::TyKind::Mac(ref m) => {
-                self.print_mac(m, token::Paren)?;
+                self.print_mac(m)?;
             }
         }
         self.end()
@@ -1116,6 +1190,7 @@ impl<'a> State<'a> {
                     decl,
                     ast::Unsafety::Normal,
                     ast::Constness::NotConst,
+                    ast::IsAsync::NotAsync,
                     Abi::Rust,
                     Some(item.ident),
                     generics,
@@ -1137,6 +1212,14 @@ impl<'a> State<'a> {
                 self.end()?; // end the head-ibox
                 self.end() // end the outer cbox
             }
+            ast::ForeignItemKind::Ty => {
+                self.head(&visibility_qualified(&item.vis, "type"))?;
+                self.print_ident(item.ident)?;
+                word(&mut self.s, ";")?;
+                self.end()?; // end the head-ibox
+                self.end() // end the outer cbox
+            }
+            ast::ForeignItemKind::Macro(ref m) => self.print_item_mac(m, keywords::Invalid.ident()),
         }
     }
 
@@ -1240,12 +1323,21 @@ impl<'a> State<'a> {
                 word(&mut self.s, ";")?;
                 self.end()?; // end the outer cbox
             }
-            ast::ItemKind::Fn(ref decl, unsafety, constness, abi, ref typarams, ref body) => {
+            ast::ItemKind::Fn(
+                ref decl,
+                unsafety,
+                constness,
+                asyncness,
+                abi,
+                ref typarams,
+                ref body,
+            ) => {
                 self.head("")?;
                 self.print_fn(
                     decl,
                     unsafety,
                     constness.node,
+                    asyncness,
                     abi,
                     Some(item.ident),
                     typarams,
@@ -1353,10 +1445,11 @@ impl<'a> State<'a> {
                 }
                 self.bclose(item.span)?;
             }
-            ast::ItemKind::Trait(unsafety, ref generics, ref bounds, ref trait_items) => {
+            ast::ItemKind::Trait(is_auto, unsafety, ref generics, ref bounds, ref trait_items) => {
                 self.head("")?;
                 self.print_visibility(&item.vis)?;
                 self.print_unsafety(unsafety)?;
+                self.print_is_auto(is_auto)?;
                 self.word_nbsp("trait")?;
                 self.print_ident(item.ident)?;
                 self.print_generics(generics)?;
@@ -1379,15 +1472,52 @@ impl<'a> State<'a> {
                 }
                 self.bclose(item.span)?;
             }
-            ast::ItemKind::Mac(codemap::Spanned { ref node, .. }) => {
-                self.print_path(&node.path, false, 0, false)?;
-                word(&mut self.s, "! ")?;
+            ast::ItemKind::TraitAlias(ref generics, ref bounds) => {
+                self.ibox(INDENT_UNIT)?;
+                self.ibox(0)?;
+                self.word_nbsp(&visibility_qualified(&item.vis, "trait"))?;
                 self.print_ident(item.ident)?;
-                self.cbox(INDENT_UNIT)?;
-                self.popen()?;
-                self.print_tts(node.stream())?;
-                self.pclose()?;
+                self.print_generics(generics)?;
+                self.end()?; // end the inner ibox
+
+                space(&mut self.s)?;
+                if bounds.is_empty() {
+                    word(&mut self.s, "=")?;
+                } else {
+                    self.print_bounds("=", &bounds[..])?;
+                }
+                self.print_where_clause(&generics.where_clause)?;
                 word(&mut self.s, ";")?;
+                self.end()?; // end the outer ibox
+            }
+            ast::ItemKind::Mac(ref m) => {
+                self.print_item_mac(m, item.ident)?;
+            }
+            ast::ItemKind::MacroDef(ref def) if !def.legacy => {
+                self.print_visibility(&item.vis)?;
+                word(&mut self.s, "macro ")?;
+                self.print_ident(item.ident)?;
+                self.cbox(INDENT_UNIT)?;
+                // `macro name(args) { body }` is parsed as `(args) => { body }`,
+                // so print that shape back in its original form.
+                let trees: Vec<TokenTree> = def.stream().into_trees().collect();
+                match trees[..] {
+                    [TokenTree::Delimited(_, ref args), TokenTree::Token(_, token::FatArrow), TokenTree::Delimited(_, ref body)]
+                        if args.delim == token::Paren && body.delim == token::Brace =>
+                    {
+                        self.print_tt(trees[0].clone())?;
+                        space(&mut self.s)?;
+                        self.print_tt(trees[2].clone())?;
+                    }
+                    _ => {
+                        space(&mut self.s)?;
+                        word(&mut self.s, "{")?;
+                        space(&mut self.s)?;
+                        self.print_tts(def.stream())?;
+                        space(&mut self.s)?;
+                        word(&mut self.s, "}")?;
+                    }
+                }
                 self.end()?;
             }
             ast::ItemKind::MacroDef(ref tts) => {
@@ -1560,6 +1690,7 @@ impl<'a> State<'a> {
             &m.decl,
             m.unsafety,
             m.constness.node,
+            m.asyncness,
             m.abi,
             Some(ident),
             &m.generics,
@@ -1600,16 +1731,8 @@ impl<'a> State<'a> {
                     default.as_ref().map(|ty| &**ty),
                 )?;
             }
-            ast::TraitItemKind::Macro(codemap::Spanned { ref node, .. }) => {
-                // code copied from ItemKind::Mac:
-                self.print_path(&node.path, false, 0, false)?;
-                word(&mut self.s, "! ")?;
-                self.cbox(INDENT_UNIT)?;
-                self.popen()?;
-                self.print_tts(node.stream())?;
-                self.pclose()?;
-                word(&mut self.s, ";")?;
-                self.end()?
+            ast::TraitItemKind::Macro(ref m) => {
+                self.print_item_mac(m, keywords::Invalid.ident())?;
             }
         }
         self.ann.post(self, NodeSubItem(ti.id))
@@ -1634,16 +1757,8 @@ impl<'a> State<'a> {
             ast::ImplItemKind::Type(ref ty) => {
                 self.print_associated_type(ii.ident, None, Some(ty))?;
             }
-            ast::ImplItemKind::Macro(codemap::Spanned { ref node, .. }) => {
-                // code copied from ItemKind::Mac:
-                self.print_path(&node.path, false, 0, false)?;
-                word(&mut self.s, "! ")?;
-                self.cbox(INDENT_UNIT)?;
-                self.popen()?;
-                self.print_tts(node.stream())?;
-                self.pclose()?;
-                word(&mut self.s, ";")?;
-                self.end()?
+            ast::ImplItemKind::Macro(ref m) => {
+                self.print_item_mac(m, keywords::Invalid.ident())?;
             }
         }
         self.ann.post(self, NodeSubItem(ii.id))
@@ -1666,6 +1781,12 @@ impl<'a> State<'a> {
                     self.word_space("=")?;
                     self.print_expr(init)?;
                 }
+                if let Some(ref els) = loc.els {
+                    self.cbox(INDENT_UNIT)?;
+                    self.ibox(0)?;
+                    word(&mut self.s, " else ")?;
+                    self.print_block(els)?;
+                }
                 word(&mut self.s, ";")?;
                 self.end()?;
             }
@@ -1686,11 +1807,7 @@ impl<'a> State<'a> {
                 let (ref mac, style, ref attrs) = **mac;
                 self.space_if_not_bol()?;
                 self.print_outer_attributes(attrs)?;
-                let delim = match style {
-                    ast::MacStmtStyle::Braces => token::Brace,
-                    _ => token::Paren,
-                };
-                self.print_mac(mac, delim)?;
+                self.print_mac(mac)?;
                 if style == ast::MacStmtStyle::Semicolon {
                     word(&mut self.s, ";")?;
                 }
@@ -1838,24 +1955,46 @@ impl<'a> State<'a> {
         self.print_else(elseopt)
     }
 
-    pub fn print_mac(&mut self, m: &ast::Mac, delim: token::DelimToken) -> io::Result<()> {
+    pub fn print_mac(&mut self, m: &ast::Mac) -> io::Result<()> {
+        self.print_path(&m.node.path, false, 0, false)?;
+        word(&mut self.s, "!")?;
+        self.print_mac_args(m)
+    }
+
+    /// Print a macro invocation in item position: `path! ident (args);`.
+    fn print_item_mac(&mut self, m: &ast::Mac, ident: ast::Ident) -> io::Result<()> {
         self.print_path(&m.node.path, false, 0, false)?;
         word(&mut self.s, "!")?;
-        match delim {
-            token::Paren => self.popen()?,
-            token::Bracket => word(&mut self.s, "[")?,
-            token::Brace => {
+        if ident.name != keywords::Invalid.name() {
+            self.nbsp()?;
+            self.print_ident(ident)?;
+        }
+        self.cbox(INDENT_UNIT)?;
+        self.print_mac_args(m)?;
+        if m.node.delim != ast::MacDelimiter::Brace {
+            word(&mut self.s, ";")?;
+        }
+        self.end()
+    }
+
+    /// Print the arguments of a macro invocation between the delimiters it
+    /// was written with.
+    fn print_mac_args(&mut self, m: &ast::Mac) -> io::Result<()> {
+        match m.node.delim {
+            ast::MacDelimiter::Parenthesis => self.popen()?,
+            ast::MacDelimiter::Bracket => word(&mut self.s, "[")?,
+            ast::MacDelimiter::Brace => {
+                self.nbsp()?;
                 self.head("")?;
                 self.bopen()?;
+                space(&mut self.s)?;
             }
-            token::NoDelim => {}
         }
         self.print_tts(m.node.stream())?;
-        match delim {
-            token::Paren => self.pclose(),
-            token::Bracket => word(&mut self.s, "]"),
-            token::Brace => self.bclose(m.span),
-            token::NoDelim => Ok(()),
+        match m.node.delim {
+            ast::MacDelimiter::Parenthesis => self.pclose(),
+            ast::MacDelimiter::Bracket => word(&mut self.s, "]"),
+            ast::MacDelimiter::Brace => self.bclose(m.span),
         }
     }
 
@@ -2107,6 +2246,28 @@ impl<'a> State<'a> {
             ast::ExprKind::IfLet(ref pat, ref expr, ref blk, ref elseopt) => {
                 self.print_if_let(pat, expr, blk, elseopt.as_ref().map(|e| &**e))?;
             }
+            ast::ExprKind::Let(ref pat, ref expr) => {
+                self.word_nbsp("let")?;
+                self.print_pat(pat)?;
+                space(&mut self.s)?;
+                self.word_space("=")?;
+                // The scrutinee ends at the `&&` that continues the chain.
+                let needs_par = match expr.node {
+                    ast::ExprKind::Binary(op, ..) => {
+                        AssocOp::from_ast_binop(op.node).precedence() <= AssocOp::LAnd.precedence()
+                    }
+                    ast::ExprKind::Cast(..) | ast::ExprKind::Type(..) => false,
+                    ast::ExprKind::Range(..) => true,
+                    _ => needs_parentheses(expr),
+                };
+                if needs_par {
+                    self.popen()?;
+                }
+                self.print_expr(expr)?;
+                if needs_par {
+                    self.pclose()?;
+                }
+            }
             ast::ExprKind::While(ref test, ref blk, opt_ident) => {
                 if let Some(ident) = opt_ident {
                     self.print_ident(ident.node)?;
@@ -2318,7 +2479,7 @@ impl<'a> State<'a> {
 
                 self.pclose()?;
             }
-            ast::ExprKind::Mac(ref m) => self.print_mac(m, token::Paren)?,
+            ast::ExprKind::Mac(ref m) => self.print_mac(m)?,
             ast::ExprKind::Paren(ref e) => {
                 self.popen()?;
                 self.print_inner_attributes_inline(attrs)?;
@@ -2334,6 +2495,15 @@ impl<'a> State<'a> {
                 space(&mut self.s)?;
                 self.print_block_with_attrs(blk, attrs)?
             }
+            ast::ExprKind::Async(capture_clause, ref blk) => {
+                self.head("async")?;
+                self.print_capture_clause(capture_clause)?;
+                self.print_block_with_attrs(blk, attrs)?
+            }
+            ast::ExprKind::Await(ref e) => {
+                self.print_expr(e)?;
+                word(&mut self.s, ".await")?
+            }
         }
         self.ann.post(self, NodeExpr(expr))?;
         self.end()
@@ -2349,6 +2519,12 @@ impl<'a> State<'a> {
     }
 
     pub fn print_ident(&mut self, ident: ast::Ident) -> io::Result<()> {
+        let token = token::Ident(ident);
+        debug_assert!(
+            !token.is_reserved_ident() || token.is_path_segment_keyword(),
+            "printing the keyword `{}` as an identifier",
+            ident
+        );
         word(&mut self.s, &ident.name.as_str())?;
         self.ann.post(self, NodeIdent(&ident))
     }
@@ -2617,7 +2793,7 @@ impl<'a> State<'a> {
                 self.commasep(Inconsistent, &after[..], |s, p| s.print_pat(p))?;
                 word(&mut self.s, "]")?;
             }
-            PatKind::Mac(ref m) => self.print_mac(m, token::Paren)?,
+            PatKind::Mac(ref m) => self.print_mac(m)?,
         }
         self.ann.post(self, NodePat(pat))
     }
@@ -2690,17 +2866,19 @@ impl<'a> State<'a> {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn print_fn(
         &mut self,
         decl: &ast::FnDecl,
         unsafety: ast::Unsafety,
         constness: ast::Constness,
+        asyncness: ast::IsAsync,
         abi: abi::Abi,
         name: Option<ast::Ident>,
         generics: &ast::Generics,
         vis: &ast::Visibility,
     ) -> io::Result<()> {
-        self.print_fn_header_info(unsafety, constness, abi, vis)?;
+        self.print_fn_header_info(unsafety, constness, asyncness, abi, vis)?;
 
         if let Some(name) = name {
             self.nbsp()?;
@@ -2734,7 +2912,7 @@ impl<'a> State<'a> {
         self.space_if_not_bol()?;
         self.word_space("->")?;
         match decl.output {
-            ast::FunctionRetTy::Ty(ref ty) => {
+            ast::FunctionRetTy::Ty(ref ty, _) => {
                 self.print_type(ty)?;
                 self.maybe_print_comment(ty.span.lo)
             }
@@ -2895,7 +3073,13 @@ impl<'a> State<'a> {
             ast::ViewPathSimple(ident, ref path) => {
                 self.print_path(path, false, 0, true)?;
 
-                if path.segments.last().unwrap().identifier.name != ident.name {
+                if !path
+                    .segments
+                    .last()
+                    .unwrap()
+                    .identifier
+                    .unhygienic_eq(ident)
+                {
                     space(&mut self.s)?;
                     self.word_space("as")?;
                     self.print_ident(ident)?;
@@ -2977,12 +3161,12 @@ impl<'a> State<'a> {
         self.word_space("->")?;
         match decl.output {
             ast::FunctionRetTy::Default(..) => unreachable!(),
-            ast::FunctionRetTy::Ty(ref ty) => self.print_type(ty)?,
+            ast::FunctionRetTy::Ty(ref ty, _) => self.print_type(ty)?,
         }
         self.end()?;
 
         match decl.output {
-            ast::FunctionRetTy::Ty(ref output) => self.maybe_print_comment(output.span.lo),
+            ast::FunctionRetTy::Ty(ref output, _) => self.maybe_print_comment(output.span.lo),
             _ => Ok(()),
         }
     }
@@ -3006,6 +3190,7 @@ impl<'a> State<'a> {
             where_clause: ast::WhereClause {
                 id: ast::DUMMY_NODE_ID,
                 predicates: Vec::new(),
+                span: syntax_pos::DUMMY_SP,
             },
             span: syntax_pos::DUMMY_SP,
         };
@@ -3013,6 +3198,7 @@ impl<'a> State<'a> {
             decl,
             unsafety,
             ast::Constness::NotConst,
+            ast::IsAsync::NotAsync,
             abi,
             name,
             &generics,
@@ -3086,6 +3272,7 @@ impl<'a> State<'a> {
         &mut self,
         unsafety: ast::Unsafety,
         constness: ast::Constness,
+        asyncness: ast::IsAsync,
         abi: Abi,
         vis: &ast::Visibility,
     ) -> io::Result<()> {
@@ -3096,6 +3283,10 @@ impl<'a> State<'a> {
             ast::Constness::Const => self.word_nbsp("const")?,
         }
 
+        if asyncness.is_async() {
+            self.word_nbsp("async")?;
+        }
+
         self.print_unsafety(unsafety)?;
 
         if abi != Abi::Rust {
@@ -3112,6 +3303,13 @@ impl<'a> State<'a> {
             ast::Unsafety::Unsafe => self.word_nbsp("unsafe"),
         }
     }
+
+    pub fn print_is_auto(&mut self, s: ast::IsAuto) -> io::Result<()> {
+        match s {
+            ast::IsAuto::Yes => self.word_nbsp("auto"),
+            ast::IsAuto::No => Ok(()),
+        }
+    }
 }
 
 fn repeat(s: &str, n: usize) -> String {
@@ -3125,6 +3323,7 @@ mod tests {
     use crate::ast;
     use crate::codemap;
     use crate::syntax_pos;
+    use std::rc::Rc;
 
     #[test]
     fn test_fun_to_string() {
@@ -3166,4 +3365,109 @@ mod tests {
         let varstr = variant_to_string(&var);
         assert_eq!(varstr, "principal_skinner");
     }
+
+    /// Check that the macro invocation `mac` prints as `expected`, and that
+    /// reparsing that with `parse` gives back the same delimiter and tokens.
+    fn check_mac_round_trip<F>(
+        mac: &ast::Mac,
+        expected: &str,
+        print: F,
+        parse: fn(&str) -> ast::Mac,
+    ) where
+        F: Fn(&mut State, &ast::Mac) -> io::Result<()>,
+    {
+        let printed = to_string(|s| print(s, mac));
+        assert_eq!(printed, expected);
+        let reparsed = parse(&printed);
+        assert_eq!(reparsed.node.delim, mac.node.delim);
+        assert!(reparsed.node.stream().eq_unspanned(&mac.node.stream()));
+    }
+
+    #[test]
+    fn c_str_lit_to_string() {
+        let lit = |bytes: &[u8]| ast::Lit {
+            node: ast::LitKind::CStr(Rc::new(bytes.to_vec())),
+            span: syntax_pos::DUMMY_SP,
+        };
+        assert_eq!(lit_to_string(&lit(b"a\n\0")), "c\"a\\n\"");
+        assert_eq!(lit_to_string(&lit(b"\0")), "c\"\"");
+        assert_eq!(lit_to_string(&lit(b"")), "c\"\"");
+    }
+
+    #[test]
+    fn path_segment_keywords() {
+        use crate::testing::string_to_item;
+
+        for src in &[
+            "use self::a;",
+            "use super::b;",
+            "fn f() -> Self { ::c::d(self.e) }",
+        ] {
+            let item = string_to_item(src.to_string()).unwrap();
+            assert_eq!(item_to_string(&item), *src);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "printing the keyword `type` as an identifier")]
+    fn keyword_as_ident() {
+        let ident = ast::Ident::from_str("type");
+        to_string(|s| s.print_ident(ident));
+    }
+
+    #[test]
+    fn mac_round_trip() {
+        use crate::testing::{string_to_expr, string_to_item};
+
+        fn expr_mac(src: &str) -> ast::Mac {
+            match string_to_expr(src.to_string()).node {
+                ast::ExprKind::Mac(ref mac) => mac.clone(),
+                _ => panic!("expected a macro invocation: {}", src),
+            }
+        }
+        fn item_mac(src: &str) -> ast::Mac {
+            match string_to_item(src.to_string()).unwrap().node {
+                ast::ItemKind::Mac(ref mac) => mac.clone(),
+                _ => panic!("expected a macro invocation: {}", src),
+            }
+        }
+
+        for &(src, expected) in &[
+            ("vec![1, 2]", "vec![1, 2]"),
+            ("vec! [ 1 , 2 ]", "vec![1, 2]"),
+            (r#"println!("{}", x)"#, r#"println!("{}", x)"#),
+            (
+                "f!(a.b(c)[0], d::e, x?, 1 .max(2), &-y)",
+                "f!(a.b(c)[0], d::e, x?, 1 .max(2), & - y)",
+            ),
+            (
+                "f!(a + b * c == d, $x:expr, #[attr], m!{})",
+                "f!(a + b * c == d, $x: expr, #[attr], m! {})",
+            ),
+            ("f!(a : :b, c . .d, !(e))", "f!(a: : b, c. .d, !(e))"),
+            ("m! { a { b; [c] } }", "m! { a {b; [c]} }"),
+        ] {
+            let mac = expr_mac(src);
+            check_mac_round_trip(&mac, expected, |s, mac| s.print_mac(mac), expr_mac);
+        }
+
+        for &(src, ident, expected) in &[
+            ("m!(x);", "", "m!(x);"),
+            ("m! name [x];", "name", "m! name[x];"),
+            ("m! { struct A { x: u8 } }", "", "m! { struct A {x: u8} }"),
+        ] {
+            let mac = item_mac(src);
+            let ident = match ident {
+                "" => keywords::Invalid.ident(),
+                ident => ast::Ident::from_str(ident),
+            };
+            check_mac_round_trip(
+                &mac,
+                expected,
+                |s, mac| s.print_item_mac(mac, ident),
+                item_mac,
+            );
+        }
+    }
 }
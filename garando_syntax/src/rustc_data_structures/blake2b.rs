@@ -138,6 +138,31 @@ fn blake2b_compress(ctx: &mut Blake2bCtx, last: bool) {
     }
 }
 
+fn blake2b_new(outlen: usize, key: &[u8]) -> Blake2bCtx {
+    assert!(outlen > 0 && outlen <= 64 && key.len() <= 64);
+
+    let mut ctx = Blake2bCtx {
+        b: [0; 128],
+        h: BLAKE2B_IV,
+        t: [0; 2],
+        c: 0,
+        outlen: outlen as u16,
+        finalized: false,
+
+        #[cfg(debug_assertions)]
+        fnv_hash: 0xcbf29ce484222325,
+    };
+
+    ctx.h[0] ^= 0x01010000 ^ ((key.len() << 8) as u64) ^ (outlen as u64);
+
+    if !key.is_empty() {
+        blake2b_update(&mut ctx, key);
+        ctx.c = ctx.b.len();
+    }
+
+    ctx
+}
+
 fn blake2b_update(ctx: &mut Blake2bCtx, mut data: &[u8]) {
     assert!(!ctx.finalized, "Blake2bCtx already finalized");
 
@@ -232,6 +257,10 @@ impl ::std::hash::Hasher for Blake2bHasher {
 }
 
 impl Blake2bHasher {
+    pub fn new(outlen: usize, key: &[u8]) -> Blake2bHasher {
+        Blake2bHasher(blake2b_new(outlen, key))
+    }
+
     pub fn finalize(&mut self) -> &[u8] {
         if !self.0.finalized {
             blake2b_final(&mut self.0);
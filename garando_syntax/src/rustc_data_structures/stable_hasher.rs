@@ -53,6 +53,14 @@ impl StableHasherResult for [u8; 20] {
     }
 }
 
+impl StableHasherResult for u128 {
+    fn finish(mut hasher: StableHasher<Self>) -> Self {
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(hasher.state.finalize());
+        u128::from_le_bytes(bytes)
+    }
+}
+
 impl StableHasherResult for u64 {
     fn finish(mut hasher: StableHasher<Self>) -> Self {
         hasher.state.finalize();
@@ -60,6 +68,26 @@ impl StableHasherResult for u64 {
     }
 }
 
+impl<W: StableHasherResult> StableHasher<W> {
+    pub fn new() -> Self {
+        StableHasher {
+            state: Blake2bHasher::new(::std::mem::size_of::<W>(), &[]),
+            bytes_hashed: 0,
+            width: PhantomData,
+        }
+    }
+
+    pub fn finish(self) -> W {
+        W::finish(self)
+    }
+}
+
+impl<W: StableHasherResult> Default for StableHasher<W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<W> StableHasher<W> {
     #[inline]
     fn write_uleb128(&mut self, value: u64) {
@@ -113,6 +141,11 @@ impl<W> Hasher for StableHasher<W> {
         self.write_uleb128(i);
     }
 
+    #[inline]
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
     #[inline]
     fn write_usize(&mut self, i: usize) {
         self.write_uleb128(i as u64);
@@ -139,6 +172,11 @@ impl<W> Hasher for StableHasher<W> {
         self.write_ileb128(i);
     }
 
+    #[inline]
+    fn write_i128(&mut self, i: i128) {
+        self.write(&i.to_le_bytes());
+    }
+
     #[inline]
     fn write_isize(&mut self, i: isize) {
         self.write_ileb128(i as i64);
@@ -172,12 +210,14 @@ impl_stable_hash_via_hash!(i8);
 impl_stable_hash_via_hash!(i16);
 impl_stable_hash_via_hash!(i32);
 impl_stable_hash_via_hash!(i64);
+impl_stable_hash_via_hash!(i128);
 impl_stable_hash_via_hash!(isize);
 
 impl_stable_hash_via_hash!(u8);
 impl_stable_hash_via_hash!(u16);
 impl_stable_hash_via_hash!(u32);
 impl_stable_hash_via_hash!(u64);
+impl_stable_hash_via_hash!(u128);
 impl_stable_hash_via_hash!(usize);
 
 impl_stable_hash_via_hash!(char);
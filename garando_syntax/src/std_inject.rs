@@ -7,18 +7,24 @@ use crate::symbol::{keywords, Symbol};
 use crate::syntax_pos::{Span, DUMMY_SP};
 use crate::tokenstream::TokenStream;
 
-/// Craft a span that will be ignored by the stability lint's
-/// call to codemap's `is_internal` check.
+/// The name in the `ExpnInfo` of the spans of the items and attributes added
+/// by `inject_crates_ref`, which identifies them after expansion.
+pub const STD_INJECT: &str = "std_inject";
+
+/// Craft a span that `CodeMap::span_allows_unstable` considers internal,
+/// so that the stability lint ignores it.
 /// The expanded code uses the unstable `#[prelude_import]` attribute.
 fn ignored_span(sp: Span) -> Span {
     let mark = Mark::fresh(Mark::root());
     mark.set_expn_info(ExpnInfo {
         call_site: DUMMY_SP,
         callee: NameAndSpan {
-            format: MacroAttribute(Symbol::intern("std_inject")),
+            format: MacroAttribute(Symbol::intern(STD_INJECT)),
             span: None,
             allow_internal_unstable: true,
+            allow_internal_unsafe: false,
         },
+        output_span: None,
     });
     Span {
         ctxt: SyntaxContext::empty().apply_mark(mark),
@@ -36,47 +42,114 @@ pub fn injected_crate_name(krate: &ast::Crate) -> Option<&'static str> {
     }
 }
 
-pub fn maybe_inject_crates_ref(mut krate: ast::Crate, alt_std_name: Option<String>) -> ast::Crate {
+/// What `inject_crates_ref` adds to a crate that isn't `#![no_core]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InjectConfig<'a> {
+    /// The crate linked as `std` (or `core` under `#![no_std]`), if not that.
+    pub crate_name_override: Option<String>,
+    /// The module glob-imported as the prelude, as path segments from the
+    /// crate root, instead of `std::prelude::v1` (or `core::prelude::v1`).
+    pub prelude_path: Option<Vec<&'a str>>,
+    /// Whether the injected `extern crate` is `#[macro_use]`.
+    pub add_macro_use: bool,
+}
+
+impl<'a> Default for InjectConfig<'a> {
+    fn default() -> Self {
+        InjectConfig {
+            crate_name_override: None,
+            prelude_path: None,
+            add_macro_use: true,
+        }
+    }
+}
+
+/// A crate after `inject_crates_ref`.
+#[derive(Debug)]
+pub struct Injected {
+    pub krate: ast::Crate,
+    /// The name the injected crate is known by in `krate`, `std` or `core`,
+    /// as returned by `injected_crate_name`, or `None` if nothing was injected.
+    pub crate_name: Option<&'static str>,
+}
+
+pub fn maybe_inject_crates_ref(krate: ast::Crate, alt_std_name: Option<String>) -> ast::Crate {
+    let config = InjectConfig {
+        crate_name_override: alt_std_name,
+        ..InjectConfig::default()
+    };
+    inject_crates_ref(krate, &config).krate
+}
+
+/// Adds the `extern crate std;` (or `core`) and prelude import that every
+/// crate starts with, as configured by `config`.
+pub fn inject_crates_ref(mut krate: ast::Crate, config: &InjectConfig) -> Injected {
     let name = match injected_crate_name(&krate) {
         Some(name) => name,
-        None => return krate,
+        None => {
+            return Injected {
+                krate,
+                crate_name: None,
+            }
+        }
     };
 
-    let crate_name = Symbol::intern(&alt_std_name.unwrap_or_else(|| name.to_string()));
+    let crate_name = Symbol::intern(
+        config
+            .crate_name_override
+            .as_ref()
+            .map_or(name, |name| &name[..]),
+    );
 
+    // The injected attributes are for name resolution, so they are never
+    // reported as unused.
+    let mut attrs = vec![];
+    if config.add_macro_use {
+        let macro_use = attr::mk_attr_outer(
+            DUMMY_SP,
+            attr::mk_attr_id(),
+            attr::mk_word_item(Symbol::intern("macro_use")),
+        );
+        attr::mark_used(&macro_use);
+        attrs.push(macro_use);
+    }
     krate.module.items.insert(
         0,
         P(ast::Item {
-            attrs: vec![attr::mk_attr_outer(
-                DUMMY_SP,
-                attr::mk_attr_id(),
-                attr::mk_word_item(Symbol::intern("macro_use")),
-            )],
+            attrs,
             vis: ast::Visibility::Inherited,
             node: ast::ItemKind::ExternCrate(Some(crate_name)),
             ident: ast::Ident::from_str(name),
             id: ast::DUMMY_NODE_ID,
-            span: DUMMY_SP,
+            span: ignored_span(DUMMY_SP),
         }),
     );
 
     let span = ignored_span(DUMMY_SP);
+    let prelude_import = ast::Attribute {
+        style: ast::AttrStyle::Outer,
+        path: ast::Path::from_ident(span, ast::Ident::from_str("prelude_import")),
+        tokens: TokenStream::empty(),
+        id: attr::mk_attr_id(),
+        is_sugared_doc: false,
+        span: span,
+    };
+    attr::mark_used(&prelude_import);
+    let default_prelude_path = [name, "prelude", "v1"];
+    let prelude_path = match config.prelude_path {
+        Some(ref path) => &path[..],
+        None => &default_prelude_path[..],
+    };
     krate.module.items.insert(
         0,
         P(ast::Item {
-            attrs: vec![ast::Attribute {
-                style: ast::AttrStyle::Outer,
-                path: ast::Path::from_ident(span, ast::Ident::from_str("prelude_import")),
-                tokens: TokenStream::empty(),
-                id: attr::mk_attr_id(),
-                is_sugared_doc: false,
-                span: span,
-            }],
+            attrs: vec![prelude_import],
             vis: ast::Visibility::Inherited,
             node: ast::ItemKind::Use(P(codemap::dummy_spanned(ast::ViewPathGlob(ast::Path {
-                segments: ["{{root}}", name, "prelude", "v1"]
-                    .iter()
-                    .map(|name| ast::PathSegment::from_ident(ast::Ident::from_str(name), DUMMY_SP))
+                segments: Some(&"{{root}}")
+                    .into_iter()
+                    .chain(prelude_path)
+                    .map(|name| ast::PathSegment::from_ident(ast::Ident::from_str(name), span))
                     .collect(),
                 span: span,
             })))),
@@ -86,5 +159,92 @@ pub fn maybe_inject_crates_ref(mut krate: ast::Crate, alt_std_name: Option<Strin
         }),
     );
 
-    krate
+    Injected {
+        krate,
+        crate_name: Some(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codemap::FilePathMapping;
+    use crate::parse::{self, ParseSess};
+
+    fn parse_crate(src: &str) -> ast::Crate {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        parse::parse_crate_from_source_str("lib.rs".to_string(), src.to_string(), &sess).unwrap()
+    }
+
+    fn prelude_segments(item: &ast::Item) -> Vec<(String, Span)> {
+        match item.node {
+            ast::ItemKind::Use(ref vp) => match vp.node {
+                ast::ViewPathGlob(ref path) => {
+                    assert!(path.span.allows_unstable());
+                    path.segments
+                        .iter()
+                        .map(|seg| (seg.identifier.name.to_string(), seg.span))
+                        .collect()
+                }
+                ref vp => panic!("expected a glob import, found {:?}", vp),
+            },
+            ref node => panic!("expected a use item, found {:?}", node),
+        }
+    }
+
+    #[test]
+    fn default_injection() {
+        let krate = maybe_inject_crates_ref(parse_crate("#![no_std] fn f() {}"), None);
+        let segments: Vec<_> = prelude_segments(&krate.module.items[0])
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(segments, ["{{root}}", "core", "prelude", "v1"]);
+
+        let extern_crate = &krate.module.items[1];
+        assert_eq!(extern_crate.ident.name.to_string(), "core");
+        assert_eq!(
+            extern_crate.node,
+            ast::ItemKind::ExternCrate(Some(Symbol::intern("core")))
+        );
+        assert!(attr::contains_name(&extern_crate.attrs, "macro_use"));
+        assert_eq!(krate.module.items.len(), 3);
+    }
+
+    #[test]
+    fn configured_injection() {
+        let config = InjectConfig {
+            crate_name_override: Some("my_std".to_string()),
+            prelude_path: Some(vec!["std", "my_prelude"]),
+            add_macro_use: false,
+        };
+        let injected = inject_crates_ref(parse_crate("fn f() {}"), &config);
+        assert_eq!(injected.crate_name, Some("std"));
+
+        let items = &injected.krate.module.items;
+        let segments = prelude_segments(&items[0]);
+        let names: Vec<_> = segments.iter().map(|&(ref name, _)| &name[..]).collect();
+        assert_eq!(names, ["{{root}}", "std", "my_prelude"]);
+        for &(_, span) in &segments {
+            assert_ne!(span.ctxt, SyntaxContext::empty());
+            assert!(span.allows_unstable());
+        }
+        assert!(attr::contains_name(&items[0].attrs, "prelude_import"));
+
+        assert_eq!(
+            items[1].node,
+            ast::ItemKind::ExternCrate(Some(Symbol::intern("my_std")))
+        );
+        assert!(items[1].attrs.is_empty());
+    }
+
+    #[test]
+    fn no_core_is_left_alone() {
+        let injected = inject_crates_ref(
+            parse_crate("#![no_core] fn f() {}"),
+            &InjectConfig::default(),
+        );
+        assert_eq!(injected.crate_name, None);
+        assert_eq!(injected.krate.module.items.len(), 1);
+    }
 }
@@ -0,0 +1,255 @@
+//! Where to insert the code added by a diagnostic suggestion, such as a
+//! missing attribute or `use` item.
+//!
+//! The spans returned are zero-width, for a `span_suggestion` that inserts
+//! its text rather than replacing any.
+
+use crate::ast;
+use crate::codemap::CodeMap;
+use crate::syntax_pos::{BytePos, Span, NO_EXPANSION};
+
+/// Where to add an outer attribute to the item spanning `item_span`, which
+/// like the span of a parsed item starts after its `existing_attrs`: right
+/// after the item's doc comments, ahead of any other attributes. Insert the
+/// attribute followed by a newline and the indentation of the item.
+pub fn suggest_insertion_span_for_attribute(
+    item_span: Span,
+    existing_attrs: &[ast::Attribute],
+) -> Span {
+    let outer = existing_attrs
+        .iter()
+        .filter(|attr| attr.style == ast::AttrStyle::Outer);
+    let docs_hi = outer
+        .clone()
+        .filter(|attr| attr.is_sugared_doc)
+        .map(|attr| attr.span.hi)
+        .max();
+    let lo = outer
+        .filter(|attr| !attr.is_sugared_doc && docs_hi.is_none_or(|hi| attr.span.lo >= hi))
+        .map(|attr| attr.span.lo)
+        .min()
+        .unwrap_or(item_span.lo);
+    Span {
+        lo,
+        hi: lo,
+        ctxt: item_span.ctxt,
+    }
+}
+
+/// Where to add a `use` item to `module`: on the line after its last `use`
+/// item, or if it has none, on the line of its first item, ahead of that
+/// item's attributes and doc comments, or if it has no items, after its inner
+/// attributes. Insert the `use` item at the indentation of the module's items
+/// and followed by a newline, and by a blank line as well if `module` has no
+/// `use` items yet. The span is at the start of a line, unless the module has
+/// other code on that line.
+pub fn suggest_insertion_span_for_use(module: &ast::Mod, cm: &CodeMap) -> Span {
+    // Only the items written in the source, not those added by expansion.
+    let mut items = module
+        .items
+        .iter()
+        .filter(|item| item.span.ctxt == NO_EXPANSION);
+    let last_use = items
+        .clone()
+        .filter(|item| match item.node {
+            ast::ItemKind::Use(..) => true,
+            _ => false,
+        })
+        .last();
+
+    if let Some(item) = last_use {
+        return start_of_next_line(cm, item.span);
+    }
+    if let Some(item) = items.next() {
+        let lo = item
+            .attrs
+            .iter()
+            .filter(|attr| attr.style == ast::AttrStyle::Outer)
+            .map(|attr| attr.span.lo)
+            .fold(item.span.lo, ::std::cmp::min);
+        return start_of_line(cm, Span { lo, ..item.span });
+    }
+    if module.inner.hi <= module.inner.lo {
+        return module.inner.shrink_to_lo();
+    }
+    // With no items, only inner attributes come before the end of `inner`,
+    // which is the closing brace of a module declared inline.
+    let end = Span {
+        lo: module.inner.hi - BytePos(1),
+        ..module.inner
+    };
+    let line = cm.span_extend_to_line_boundaries(end);
+    let last_line = cm.span_to_snippet(Span { hi: end.hi, ..line });
+    match last_line {
+        Ok(ref text) if text.ends_with('}') && !text.trim_start().starts_with("//") => {
+            start_of_line(cm, end)
+        }
+        _ => start_of_next_line(cm, module.inner),
+    }
+}
+
+/// The start of the line following `sp` if there is nothing more on the line
+/// `sp` ends on, or else the end of `sp`.
+fn start_of_next_line(cm: &CodeMap, sp: Span) -> Span {
+    let end = Span { lo: sp.hi, ..sp };
+    let line = cm.span_extend_to_line_boundaries(end);
+    match cm.span_to_snippet(Span { hi: line.hi, ..end }) {
+        Ok(ref rest) if rest.ends_with('\n') && rest.trim().is_empty() => Span {
+            lo: line.hi,
+            ..line
+        },
+        _ => end,
+    }
+}
+
+/// The start of the line `sp` starts on if there is nothing else before `sp`
+/// on it, or else the start of `sp`.
+fn start_of_line(cm: &CodeMap, sp: Span) -> Span {
+    let start = sp.shrink_to_lo();
+    let line = cm.span_extend_to_line_boundaries(start);
+    match cm.span_to_snippet(Span {
+        lo: line.lo,
+        ..start
+    }) {
+        Ok(ref before) if before.trim().is_empty() => line.shrink_to_lo(),
+        _ => start,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codemap::{self, FilePathMapping};
+    use crate::parse::{self, ParseSess};
+
+    /// Parses `src` as a crate and inserts `text` where `f` says to, in the
+    /// innermost module named `m` if there is one.
+    fn insert<F>(src: &str, text: &str, f: F) -> String
+    where
+        F: FnOnce(&ast::Mod, &CodeMap) -> Span,
+    {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let krate = parse::parse_crate_from_source_str("s.rs".to_string(), src.to_string(), &sess)
+            .unwrap_or_else(|mut e| {
+                e.emit();
+                panic!("{:?} failed to parse", src)
+            });
+        let mut module = &krate.module;
+        while let Some(inner) = module.items.iter().find_map(|item| match item.node {
+            ast::ItemKind::Mod(ref inner) if item.ident.name == "m" => Some(inner),
+            _ => None,
+        }) {
+            module = inner;
+        }
+
+        let cm = sess.codemap();
+        let sp = f(module, cm);
+        assert_eq!(sp.lo, sp.hi);
+        let file = cm.lookup_char_pos(sp.lo).file;
+        codemap::apply_edits(cm, &file, &[(sp, text.to_string())]).unwrap()
+    }
+
+    fn insert_use(src: &str, text: &str) -> String {
+        insert(src, text, |module, cm| {
+            suggest_insertion_span_for_use(module, cm)
+        })
+    }
+
+    /// Inserts `text` before the last item of the innermost module `m`.
+    fn insert_attr(src: &str, text: &str) -> String {
+        insert(src, text, |module, _| {
+            let item = module.items.last().unwrap();
+            suggest_insertion_span_for_attribute(item.span, &item.attrs)
+        })
+    }
+
+    #[test]
+    fn use_after_existing_uses() {
+        assert_eq!(
+            insert_use(
+                "use a::b;\nuse c::{d, e};\n\nfn f() {}\n",
+                "use std::fmt::Debug;\n"
+            ),
+            "use a::b;\nuse c::{d, e};\nuse std::fmt::Debug;\n\nfn f() {}\n"
+        );
+        assert_eq!(
+            insert_use(
+                "fn f() {}\n\nmod m {\n    use a;\n\n    struct S;\n}\n",
+                "    use b;\n"
+            ),
+            "fn f() {}\n\nmod m {\n    use a;\n    use b;\n\n    struct S;\n}\n"
+        );
+        // The last `use` item is followed by more code on its line.
+        assert_eq!(
+            insert_use("use a; fn f() {}\n", " use b;"),
+            "use a; use b; fn f() {}\n"
+        );
+    }
+
+    #[test]
+    fn use_without_existing_uses() {
+        assert_eq!(
+            insert_use(
+                "#![allow(unused)]\n\n/// Docs.\n#[inline]\nfn f() {}\n",
+                "use std::fmt::Debug;\n\n"
+            ),
+            "#![allow(unused)]\n\nuse std::fmt::Debug;\n\n/// Docs.\n#[inline]\nfn f() {}\n"
+        );
+        assert_eq!(
+            insert_use(
+                "mod m {\n    #![allow(unused)]\n    fn f() {}\n}\n",
+                "    use a;\n\n"
+            ),
+            "mod m {\n    #![allow(unused)]\n    use a;\n\n    fn f() {}\n}\n"
+        );
+        assert_eq!(
+            insert_use("mod m { fn f() {} }\n", "use a; "),
+            "mod m { use a; fn f() {} }\n"
+        );
+    }
+
+    #[test]
+    fn use_without_items() {
+        assert_eq!(
+            insert_use("//! Docs.\n#![allow(unused)]\n\n// The end.\n", "use a;\n"),
+            "//! Docs.\n#![allow(unused)]\nuse a;\n\n// The end.\n"
+        );
+        assert_eq!(
+            insert_use("mod m {\n}\n", "    use a;\n"),
+            "mod m {\n    use a;\n}\n"
+        );
+        assert_eq!(
+            insert_use("mod m {\n    //! {}\n}\n", "    use a;\n"),
+            "mod m {\n    //! {}\n    use a;\n}\n"
+        );
+        assert_eq!(insert_use("//! {}\n", "use a;\n"), "//! {}\nuse a;\n");
+        assert_eq!(insert_use("", "use a;\n"), "use a;\n");
+    }
+
+    #[test]
+    fn attribute_after_doc_comments() {
+        assert_eq!(
+            insert_attr("struct S;\n", "#[derive(Clone)]\n"),
+            "#[derive(Clone)]\nstruct S;\n"
+        );
+        assert_eq!(
+            insert_attr(
+                "/// One.\n/// Two.\n#[repr(C)]\nstruct S;\n",
+                "#[derive(Clone)]\n"
+            ),
+            "/// One.\n/// Two.\n#[derive(Clone)]\n#[repr(C)]\nstruct S;\n"
+        );
+        assert_eq!(
+            insert_attr(
+                "mod m {\n    fn f() {}\n\n    /** Docs. */\n    pub struct S;\n}\n",
+                "#[derive(Clone)]\n    "
+            ),
+            "mod m {\n    fn f() {}\n\n    /** Docs. */\n    #[derive(Clone)]\n    pub struct S;\n}\n"
+        );
+        // An attribute ahead of the doc comments stays there.
+        assert_eq!(
+            insert_attr("#[cfg(test)]\n/// Docs.\nfn f() {}\n", "#[inline]\n"),
+            "#[cfg(test)]\n/// Docs.\n#[inline]\nfn f() {}\n"
+        );
+    }
+}
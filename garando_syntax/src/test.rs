@@ -121,7 +121,7 @@ impl<'a> fold::Folder for TestHarnessGenerator<'a> {
 
         if is_test_fn(&self.cx, &i) || is_bench_fn(&self.cx, &i) {
             match i.node {
-                ast::ItemKind::Fn(_, ast::Unsafety::Unsafe, _, _, _, _) => {
+                ast::ItemKind::Fn(_, ast::Unsafety::Unsafe, _, _, _, _, _) => {
                     let diag = self.cx.span_diagnostic;
                     panic!(diag.span_fatal(i.span, "unsafe functions cannot be used for tests"));
                 }
@@ -318,7 +318,9 @@ fn generate_test_harness(
             format: MacroAttribute(Symbol::intern("test")),
             span: None,
             allow_internal_unstable: true,
+            allow_internal_unsafe: false,
         },
+        output_span: None,
     });
 
     TestHarnessGenerator {
@@ -351,10 +353,10 @@ fn is_test_fn(cx: &TestCtxt, i: &ast::Item) -> bool {
 
     fn has_test_signature(i: &ast::Item) -> HasTestSignature {
         match i.node {
-            ast::ItemKind::Fn(ref decl, _, _, _, ref generics, _) => {
+            ast::ItemKind::Fn(ref decl, _, _, _, _, ref generics, _) => {
                 let no_output = match decl.output {
                     ast::FunctionRetTy::Default(..) => true,
-                    ast::FunctionRetTy::Ty(ref t) if t.node == ast::TyKind::Tup(vec![]) => true,
+                    ast::FunctionRetTy::Ty(ref t, _) if t.node == ast::TyKind::Tup(vec![]) => true,
                     _ => false,
                 };
                 if decl.inputs.is_empty() && no_output && !generics.is_parameterized() {
@@ -387,11 +389,11 @@ fn is_bench_fn(cx: &TestCtxt, i: &ast::Item) -> bool {
 
     fn has_test_signature(i: &ast::Item) -> bool {
         match i.node {
-            ast::ItemKind::Fn(ref decl, _, _, _, ref generics, _) => {
+            ast::ItemKind::Fn(ref decl, _, _, _, _, ref generics, _) => {
                 let input_cnt = decl.inputs.len();
                 let no_output = match decl.output {
                     ast::FunctionRetTy::Default(..) => true,
-                    ast::FunctionRetTy::Ty(ref t) if t.node == ast::TyKind::Tup(vec![]) => true,
+                    ast::FunctionRetTy::Ty(ref t, _) if t.node == ast::TyKind::Tup(vec![]) => true,
                     _ => false,
                 };
                 let tparm_cnt = generics.ty_params.len();
@@ -531,6 +533,7 @@ fn mk_main(cx: &mut TestCtxt) -> P<ast::Item> {
         ecx.fn_decl(vec![], main_ret_ty),
         ast::Unsafety::Normal,
         dummy_spanned(ast::Constness::NotConst),
+        ast::IsAsync::NotAsync,
         crate::abi::Abi::Rust,
         ast::Generics::default(),
         main_body,
@@ -683,10 +686,12 @@ fn mk_test_descs(cx: &TestCtxt) -> P<ast::Expr> {
                         .collect(),
                 ),
                 span: DUMMY_SP,
+                bare_span: DUMMY_SP,
                 attrs: ast::ThinVec::new(),
             }),
         ),
         span: DUMMY_SP,
+        bare_span: DUMMY_SP,
         attrs: ast::ThinVec::new(),
     })
 }
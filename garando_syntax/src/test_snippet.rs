@@ -1,12 +1,9 @@
 use crate::codemap::{CodeMap, FilePathMapping};
-use crate::errors::emitter::EmitterWriter;
-use crate::errors::Handler;
-use crate::syntax_pos::{BytePos, MultiSpan, Span, NO_EXPANSION};
-use std::io;
-use std::io::prelude::*;
+use crate::errors::emitter::{EmitterWriter, DEFAULT_TAB_WIDTH};
+use crate::errors::{self, ColorConfig, Handler, LabelStyle};
+use crate::syntax_pos::{BytePos, MultiSpan, Span, StringPart, NO_EXPANSION};
+use crate::testing::SharedBuffer;
 use std::rc::Rc;
-use std::str;
-use std::sync::{Arc, Mutex};
 
 /// Identify a position in the text by the Nth occurrence of a string.
 struct Position {
@@ -20,25 +17,22 @@ struct SpanLabel {
     label: &'static str,
 }
 
-struct Shared<T: Write> {
-    data: Arc<Mutex<T>>,
+fn test_harness(file_text: &str, span_labels: Vec<SpanLabel>, expected_output: &str) {
+    tab_width_harness(file_text, span_labels, DEFAULT_TAB_WIDTH, expected_output)
 }
 
-impl<T: Write> Write for Shared<T> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.data.lock().unwrap().write(buf)
-    }
-
-    fn flush(&mut self) -> io::Result<()> {
-        self.data.lock().unwrap().flush()
-    }
-}
-
-fn test_harness(file_text: &str, span_labels: Vec<SpanLabel>, expected_output: &str) {
-    let output = Arc::new(Mutex::new(Vec::new()));
+/// Like `test_harness`, but shows each tab in the source as `tab_width`
+/// spaces.
+fn tab_width_harness(
+    file_text: &str,
+    span_labels: Vec<SpanLabel>,
+    tab_width: usize,
+    expected_output: &str,
+) {
+    let output = SharedBuffer::default();
 
     let code_map = Rc::new(CodeMap::new(FilePathMapping::empty()));
-    code_map.new_filemap_and_lines("test.rs", &file_text);
+    new_test_filemap(&code_map, file_text);
 
     let primary_span = make_span(&file_text, &span_labels[0].start, &span_labels[0].end);
     let mut msp = MultiSpan::from_span(primary_span);
@@ -49,12 +43,8 @@ fn test_harness(file_text: &str, span_labels: Vec<SpanLabel>, expected_output: &
         println!("text: {:?}", code_map.span_to_snippet(span));
     }
 
-    let emitter = EmitterWriter::new(
-        Box::new(Shared {
-            data: output.clone(),
-        }),
-        Some(code_map.clone()),
-    );
+    let emitter =
+        EmitterWriter::new(Box::new(output.clone()), Some(code_map.clone())).tab_width(tab_width);
     let handler = Handler::with_emitter(true, false, Box::new(emitter));
     handler.span_err(msp, "foo");
 
@@ -64,14 +54,69 @@ fn test_harness(file_text: &str, span_labels: Vec<SpanLabel>, expected_output: &
     );
     let expected_output = &expected_output[1..];
 
-    let bytes = output.lock().unwrap();
-    let actual_output = str::from_utf8(&bytes).unwrap();
+    let actual_output = output.contents();
     println!("expected output:\n------\n{}------", expected_output);
     println!("actual output:\n------\n{}------", actual_output);
 
     assert!(expected_output == actual_output)
 }
 
+/// Like `test_harness`, but renders the labels with `errors::render_snippet`.
+fn snippet_harness(
+    file_text: &str,
+    span_labels: Vec<SpanLabel>,
+    label_style: LabelStyle,
+    expected_output: &str,
+) {
+    let code_map = CodeMap::new(FilePathMapping::empty());
+    new_test_filemap(&code_map, file_text);
+
+    let primary_span = make_span(&file_text, &span_labels[0].start, &span_labels[0].end);
+    let mut msp = MultiSpan::from_span(primary_span);
+    for span_label in span_labels {
+        let span = make_span(&file_text, &span_label.start, &span_label.end);
+        msp.push_span_label(span, span_label.label.to_string());
+    }
+
+    let actual_output = errors::render_snippet(&code_map, &msp, label_style);
+    println!("actual output:\n------\n{}------", actual_output);
+    assert_eq!(actual_output, &expected_output[1..]);
+}
+
+/// Emits an error at the first part's span, suggesting `parts` with `msg`.
+fn suggestion_harness(file_text: &str, msg: &str, parts: Vec<(Span, &str)>, expected_output: &str) {
+    let output = SharedBuffer::default();
+
+    let code_map = Rc::new(CodeMap::new(FilePathMapping::empty()));
+    code_map.new_filemap_and_lines("test.rs", &file_text);
+
+    let emitter = EmitterWriter::new(Box::new(output.clone()), Some(code_map.clone()));
+    let handler = Handler::with_emitter(true, false, Box::new(emitter));
+    let parts: Vec<_> = parts
+        .into_iter()
+        .map(|(sp, s)| (sp, s.to_string()))
+        .collect();
+    handler
+        .struct_span_err(parts[0].0, "foo")
+        .multipart_suggestion(msg, parts)
+        .emit();
+
+    let actual_output = output.contents();
+    println!("actual output:\n------\n{}------", actual_output);
+    assert_eq!(actual_output, &expected_output[1..]);
+}
+
+/// Adds `file_text` to `code_map` as `test.rs`, with its lines and multibyte
+/// characters recorded as the lexer would.
+fn new_test_filemap(code_map: &CodeMap, file_text: &str) {
+    let fm = code_map.new_filemap_and_lines("test.rs", file_text);
+    for (i, c) in file_text.char_indices() {
+        if c.len_utf8() > 1 {
+            fm.record_multibyte_char(fm.start_pos + BytePos(i as u32), c.len_utf8());
+        }
+    }
+}
+
 fn make_span(file_text: &str, start: &Position, end: &Position) -> Span {
     let start = make_pos(file_text, start);
     let end = make_pos(file_text, end) + end.string.len(); // just after matching thing ends
@@ -89,7 +134,7 @@ fn make_pos(file_text: &str, pos: &Position) -> usize {
     for _ in 0..pos.count {
         if let Some(n) = remainder.find(&pos.string) {
             offset += n;
-            remainder = &remainder[n + 1..];
+            remainder = &remainder[n + pos.string.chars().next().map_or(1, char::len_utf8)..];
         } else {
             panic!(
                 "failed to find {} instances of {:?} in {:?}",
@@ -1092,3 +1137,671 @@ error: foo
 "#,
     );
 }
+
+/// Emits an error whose spans point into two files: a call of `bar()` in
+/// `a.rs` and the definition of `bar` in `b.rs`.
+fn test_multiple_files_harness<F>(make_msp: F, expected_output: &str)
+where
+    F: FnOnce(Span, Span) -> MultiSpan,
+{
+    color_multiple_files_harness(make_msp, ColorConfig::Never, expected_output)
+}
+
+/// Like `test_multiple_files_harness`, but styles the output with ANSI escape
+/// codes for `ColorConfig::Always`.
+fn color_multiple_files_harness<F>(make_msp: F, color: ColorConfig, expected_output: &str)
+where
+    F: FnOnce(Span, Span) -> MultiSpan,
+{
+    let output = SharedBuffer::default();
+
+    let code_map = Rc::new(CodeMap::new(FilePathMapping::empty()));
+    let a_text = "fn foo() {\n    bar();\n}\n";
+    let b_text = "\nfn bar() {}\n";
+    let a = code_map.new_filemap_and_lines("a.rs", a_text);
+    let b = code_map.new_filemap_and_lines("b.rs", b_text);
+
+    let call = Position {
+        string: "bar()",
+        count: 1,
+    };
+    let def = Position {
+        string: "fn bar()",
+        count: 1,
+    };
+    let call_span = make_span(a_text, &call, &call);
+    let call_span = Span {
+        lo: call_span.lo + a.start_pos,
+        hi: call_span.hi + a.start_pos,
+        ..call_span
+    };
+    let def_span = make_span(b_text, &def, &def);
+    let def_span = Span {
+        lo: def_span.lo + b.start_pos,
+        hi: def_span.hi + b.start_pos,
+        ..def_span
+    };
+
+    let emitter =
+        EmitterWriter::with_color(Box::new(output.clone()), color, Some(code_map.clone()));
+    let handler = Handler::with_emitter(true, false, Box::new(emitter));
+    handler.span_err(make_msp(call_span, def_span), "foo");
+
+    assert!(
+        expected_output.starts_with('\n'),
+        "expected output should begin with newline"
+    );
+    let expected_output = &expected_output[1..];
+
+    let actual_output = output.contents();
+    println!("expected output:\n------\n{}------", expected_output);
+    println!("actual output:\n------\n{}------", actual_output);
+
+    assert!(expected_output == actual_output)
+}
+
+#[test]
+fn label_in_other_file() {
+    test_multiple_files_harness(
+        |call_span, def_span| {
+            let mut msp = MultiSpan::from_span(call_span);
+            msp.push_span_label(call_span, "called here".to_string());
+            msp.push_span_label(def_span, "defined here".to_string());
+            msp
+        },
+        r#"
+error: foo
+ --> a.rs:2:5
+  |
+2 |     bar();
+  |     ^^^^^ called here
+  | 
+ ::: b.rs:2:1
+  |
+2 | fn bar() {}
+  | -------- defined here
+
+"#,
+    );
+}
+
+#[test]
+fn primary_spans_in_multiple_files() {
+    test_multiple_files_harness(
+        |call_span, def_span| {
+            let mut msp = MultiSpan::from_spans(vec![def_span, call_span]);
+            msp.push_span_label(call_span, "called here".to_string());
+            msp.push_span_label(def_span, "defined here".to_string());
+            msp
+        },
+        r#"
+error: foo
+ --> b.rs:2:1
+  |
+2 | fn bar() {}
+  | ^^^^^^^^ defined here
+  | 
+ --> a.rs:2:5
+  |
+2 |     bar();
+  |     ^^^^^ called here
+
+"#,
+    );
+}
+
+#[test]
+fn styled_label() {
+    color_multiple_files_harness(
+        |call_span, _| {
+            let mut msp = MultiSpan::from_span(call_span);
+            msp.push_styled_span_label(
+                call_span,
+                vec![
+                    StringPart::Normal("call to `".to_string()),
+                    StringPart::Highlighted("bar".to_string()),
+                    StringPart::Normal("` here".to_string()),
+                ],
+            );
+            msp
+        },
+        ColorConfig::Always,
+        // The highlighted part of the label is bold, but not red like the
+        // rest of it.
+        concat!(
+            "\n",
+            "\x1b[1m\x1b[91merror\x1b[0m\x1b[1m: foo\x1b[0m\n",
+            " \x1b[0m\x1b[1m\x1b[94m--> \x1b[0ma.rs:2:5\x1b[0m\n",
+            "  \x1b[0m\x1b[1m\x1b[94m|\x1b[0m\n",
+            "\x1b[1m\x1b[94m2\x1b[0m \x1b[0m\x1b[1m\x1b[94m| \x1b[0m    bar();\x1b[0m\n",
+            "  \x1b[0m\x1b[1m\x1b[94m| \x1b[0m    \x1b[0m\x1b[1m\x1b[91m^^^^^\x1b[0m ",
+            "\x1b[0m\x1b[1m\x1b[91mcall to `\x1b[0m\x1b[1mbar\x1b[0m\x1b[1m\x1b[91m` here\x1b[0m\n",
+            "\n",
+        ),
+    );
+}
+
+#[test]
+fn unannotated_lines_between_labels() {
+    test_harness(
+        r#"
+fn main() {
+    let a = 1;
+    let b = 2;
+    let c = 3;
+    let d = 4;
+    let e = 5;
+    let f = 6;
+}
+"#,
+        vec![
+            SpanLabel {
+                start: Position {
+                    string: "1",
+                    count: 1,
+                },
+                end: Position {
+                    string: "1",
+                    count: 1,
+                },
+                label: "first",
+            },
+            SpanLabel {
+                start: Position {
+                    string: "3",
+                    count: 1,
+                },
+                end: Position {
+                    string: "3",
+                    count: 1,
+                },
+                label: "one line after",
+            },
+            SpanLabel {
+                start: Position {
+                    string: "6",
+                    count: 1,
+                },
+                end: Position {
+                    string: "6",
+                    count: 1,
+                },
+                label: "two lines after",
+            },
+        ],
+        r#"
+error: foo
+ --> test.rs:3:13
+  |
+3 |     let a = 1;
+  |             ^ first
+4 |     let b = 2;
+5 |     let c = 3;
+  |             - one line after
+...
+8 |     let f = 6;
+  |             - two lines after
+
+"#,
+    );
+}
+
+#[test]
+fn tab_indented_line() {
+    let label = || SpanLabel {
+        start: Position {
+            string: "bar",
+            count: 1,
+        },
+        end: Position {
+            string: "(1)",
+            count: 1,
+        },
+        label: "here",
+    };
+    test_harness(
+        "\nfn foo() {\n\t\tlet x = bar(1);\n}\n",
+        vec![label()],
+        r#"
+error: foo
+ --> test.rs:3:11
+  |
+3 |         let x = bar(1);
+  |                 ^^^^^^ here
+
+"#,
+    );
+    tab_width_harness(
+        "\nfn foo() {\n\t\tlet x = bar(1);\n}\n",
+        vec![label()],
+        2,
+        r#"
+error: foo
+ --> test.rs:3:11
+  |
+3 |     let x = bar(1);
+  |             ^^^^^^ here
+
+"#,
+    );
+}
+
+#[test]
+fn tab_indented_multiline_span() {
+    test_harness(
+        "\nfn foo() {\n\tif x {\n\t\tbar();\n\t}\n}\n",
+        vec![
+            SpanLabel {
+                start: Position {
+                    string: "if",
+                    count: 1,
+                },
+                end: Position {
+                    string: "}",
+                    count: 1,
+                },
+                label: "this `if`",
+            },
+            SpanLabel {
+                start: Position {
+                    string: "bar",
+                    count: 1,
+                },
+                end: Position {
+                    string: "bar",
+                    count: 1,
+                },
+                label: "call",
+            },
+        ],
+        r#"
+error: foo
+ --> test.rs:3:2
+  |
+3 | /     if x {
+4 | |         bar();
+  | |         --- call
+5 | |     }
+  | |_____^ this `if`
+
+"#,
+    );
+}
+
+#[test]
+fn snippet_single_span() {
+    let file_text = r#"
+fn main() {
+    let x = vec![1, 2, 3];
+}
+"#;
+    let label = || SpanLabel {
+        start: Position {
+            string: "vec!",
+            count: 1,
+        },
+        end: Position {
+            string: "]",
+            count: 1,
+        },
+        label: "expected `u8`",
+    };
+    snippet_harness(
+        file_text,
+        vec![label()],
+        LabelStyle::Gutter,
+        r#"
+3 |     let x = vec![1, 2, 3];
+  |             ^^^^^^^^^^^^^ expected `u8`
+"#,
+    );
+    snippet_harness(
+        file_text,
+        vec![label()],
+        LabelStyle::NoGutter,
+        r#"
+    let x = vec![1, 2, 3];
+            ^^^^^^^^^^^^^ expected `u8`
+"#,
+    );
+}
+
+#[test]
+fn snippet_labels_on_same_line() {
+    let file_text = r#"
+fn main() {
+    vec.push(vec.pop().unwrap());
+}
+"#;
+    let labels = || {
+        vec![
+            SpanLabel {
+                start: Position {
+                    string: "vec.pop",
+                    count: 1,
+                },
+                end: Position {
+                    string: "pop()",
+                    count: 1,
+                },
+                label: "error occurs here",
+            },
+            SpanLabel {
+                start: Position {
+                    string: "vec",
+                    count: 1,
+                },
+                end: Position {
+                    string: "vec",
+                    count: 1,
+                },
+                label: "previous borrow of `vec` occurs here",
+            },
+            SpanLabel {
+                start: Position {
+                    string: ";",
+                    count: 1,
+                },
+                end: Position {
+                    string: ";",
+                    count: 1,
+                },
+                label: "previous borrow ends here",
+            },
+        ]
+    };
+    snippet_harness(
+        file_text,
+        labels(),
+        LabelStyle::Gutter,
+        r#"
+3 |     vec.push(vec.pop().unwrap());
+  |     ---      ^^^^^^^^^          - previous borrow ends here
+  |     |        |
+  |     |        error occurs here
+  |     previous borrow of `vec` occurs here
+"#,
+    );
+    snippet_harness(
+        file_text,
+        labels(),
+        LabelStyle::NoGutter,
+        r#"
+    vec.push(vec.pop().unwrap());
+    ---      ^^^^^^^^^          - previous borrow ends here
+    |        |
+    |        error occurs here
+    previous borrow of `vec` occurs here
+"#,
+    );
+}
+
+#[test]
+fn snippet_multiline_span() {
+    let file_text = r#"
+fn foo() {
+    bar();
+}
+"#;
+    let label = || SpanLabel {
+        start: Position {
+            string: "fn",
+            count: 1,
+        },
+        end: Position {
+            string: "}",
+            count: 1,
+        },
+        label: "this function",
+    };
+    snippet_harness(
+        file_text,
+        vec![label()],
+        LabelStyle::Gutter,
+        r#"
+2 | / fn foo() {
+3 | |     bar();
+4 | | }
+  | |_^ this function
+"#,
+    );
+    snippet_harness(
+        file_text,
+        vec![label()],
+        LabelStyle::NoGutter,
+        r#"
+/ fn foo() {
+|     bar();
+| }
+|_^ this function
+"#,
+    );
+}
+
+/// The span of the `count`th occurrence of `string`, counting from one.
+fn find_span(file_text: &str, string: &str, count: usize) -> Span {
+    let (start, _) = file_text.match_indices(string).nth(count - 1).unwrap();
+    Span {
+        lo: BytePos(start as u32),
+        hi: BytePos((start + string.len()) as u32),
+        ctxt: NO_EXPANSION,
+    }
+}
+
+fn empty_span_after(file_text: &str, string: &str, count: usize) -> Span {
+    let span = find_span(file_text, string, count);
+    Span {
+        lo: span.hi,
+        ..span
+    }
+}
+
+#[test]
+fn suggestion_deletion_label() {
+    let file_text = "fn foo() {\n    let x = 1;;\n}\n";
+    suggestion_harness(
+        file_text,
+        "remove the extra semicolon",
+        vec![(find_span(file_text, ";", 2), "")],
+        r#"
+error: foo
+ --> test.rs:2:15
+  |
+2 |     let x = 1;;
+  |               ^ help: remove the extra semicolon: remove this
+
+"#,
+    );
+}
+
+#[test]
+fn suggestion_insertion_and_replacement_labels() {
+    let file_text = "fn foo() {\n    let x = 1\n}\n";
+    suggestion_harness(
+        file_text,
+        "add a semicolon",
+        vec![(empty_span_after(file_text, "1", 1), ";")],
+        r#"
+error: foo
+ --> test.rs:2:14
+  |
+2 |     let x = 1
+  |              ^ help: add a semicolon `;`
+
+"#,
+    );
+    suggestion_harness(
+        file_text,
+        "try",
+        vec![(find_span(file_text, "x", 1), "y")],
+        r#"
+error: foo
+ --> test.rs:2:9
+  |
+2 |     let x = 1
+  |         ^ help: try `y`
+
+"#,
+    );
+}
+
+#[test]
+fn suggestion_deletion_marks_removed_code() {
+    let file_text = "fn foo() {\n    let x = (1);\n}\n";
+    suggestion_harness(
+        file_text,
+        "remove the parentheses",
+        vec![
+            (find_span(file_text, "(", 2), ""),
+            (find_span(file_text, ")", 2), ""),
+        ],
+        r#"
+error: foo
+ --> test.rs:2:13
+  |
+2 |     let x = (1);
+  |             ^
+  |
+help: remove the parentheses
+  |
+2 |     let x = (1);
+  |             - - remove this
+  |             |
+  |             remove this
+
+"#,
+    );
+}
+
+#[test]
+fn suggestion_insertion_is_spliced() {
+    let file_text = "fn foo() {\n    let x = 1 + 2;\n}\n";
+    suggestion_harness(
+        file_text,
+        "add parentheses",
+        vec![
+            (empty_span_after(file_text, "= ", 1), "("),
+            (empty_span_after(file_text, "2", 1), ")"),
+        ],
+        r#"
+error: foo
+ --> test.rs:2:13
+  |
+2 |     let x = 1 + 2;
+  |             ^
+  |
+help: add parentheses
+  |     let x = (1 + 2);
+
+"#,
+    );
+}
+
+#[test]
+fn suggestion_as_note_keeps_its_place() {
+    let file_text = "fn foo() {\n    let x = 1\n}\n";
+    let output = SharedBuffer::default();
+    let code_map = Rc::new(CodeMap::new(FilePathMapping::empty()));
+    code_map.new_filemap_and_lines("test.rs", file_text);
+    let emitter = EmitterWriter::new(Box::new(output.clone()), Some(code_map.clone()));
+    let handler = Handler::with_emitter(true, false, Box::new(emitter));
+
+    handler
+        .struct_span_err(find_span(file_text, "1", 1), "foo")
+        .note("before")
+        .span_suggestion_as_note(
+            empty_span_after(file_text, "1", 1),
+            "add a semicolon",
+            ";".to_string(),
+        )
+        .note("after")
+        .emit();
+
+    let actual_output = output.contents();
+    println!("actual output:\n------\n{}------", actual_output);
+    assert_eq!(
+        actual_output,
+        &r#"
+error: foo
+ --> test.rs:2:13
+  |
+2 |     let x = 1
+  |             ^
+  |
+  = note: before
+note: add a semicolon
+  |     let x = 1;
+  = note: after
+
+"#[1..]
+    );
+}
+
+#[test]
+fn wide_characters() {
+    let file_text = "\nfn foo() {\n    let 名前 = \"🦀🦀\"; bar(名前);\n}\n";
+    let labels = || {
+        vec![
+            SpanLabel {
+                start: Position {
+                    string: "bar",
+                    count: 1,
+                },
+                end: Position {
+                    string: "名前)",
+                    count: 1,
+                },
+                label: "call",
+            },
+            SpanLabel {
+                start: Position {
+                    string: "名前",
+                    count: 1,
+                },
+                end: Position {
+                    string: "名前",
+                    count: 1,
+                },
+                label: "name",
+            },
+            SpanLabel {
+                start: Position {
+                    string: "\"🦀",
+                    count: 1,
+                },
+                end: Position {
+                    string: "🦀\"",
+                    count: 1,
+                },
+                label: "crabs",
+            },
+        ]
+    };
+    test_harness(
+        file_text,
+        labels(),
+        r#"
+error: foo
+ --> test.rs:3:20
+  |
+3 |     let 名前 = "🦀🦀"; bar(名前);
+  |         ----   ------  ^^^^^^^^^ call
+  |         |      |
+  |         |      crabs
+  |         name
+
+"#,
+    );
+    snippet_harness(
+        file_text,
+        labels(),
+        LabelStyle::NoGutter,
+        r#"
+    let 名前 = "🦀🦀"; bar(名前);
+        ----   ------  ^^^^^^^^^ call
+        |      |
+        |      crabs
+        name
+"#,
+    );
+}
@@ -0,0 +1,301 @@
+//! Helpers for parsing snippets of source code in tests.
+//!
+//! Each `string_to_*` helper parses its input in a fresh `ParseSess` and
+//! panics if parsing fails or emits any error. The `try_string_to_*` variants
+//! take an explicit `ParseSess` and return the parser's result instead; note
+//! that lexer errors are still fatal and unwind with a `FatalError` panic.
+//!
+//! `SharedBuffer` collects the output of an emitter, and `json_diagnostics`
+//! reads back what a `JsonEmitter` wrote.
+
+use crate::ast::{self, Ident};
+use crate::codemap::FilePathMapping;
+use crate::parse::lexer::{self, StringReader};
+use crate::parse::parser::Parser;
+use crate::parse::{filemap_to_stream, new_parser_from_source_str, PResult, ParseSess};
+use crate::ptr::P;
+use crate::tokenstream::TokenStream;
+use std::io::{self, Write};
+use std::iter::Peekable;
+use std::sync::{Arc, Mutex};
+
+/// Map a string to tts, using a made-up filename.
+pub fn string_to_stream(source_str: String) -> TokenStream {
+    let ps = ParseSess::new(FilePathMapping::empty());
+    filemap_to_stream(&ps, ps.codemap().new_filemap("bogofile".into(), source_str))
+}
+
+/// Map a string to tts, returning an error for unbalanced delimiters.
+pub fn try_string_to_stream(ps: &ParseSess, source_str: String) -> PResult<'_, TokenStream> {
+    let filemap = ps.codemap().new_filemap("bogofile".into(), source_str);
+    let mut srdr = StringReader::new(ps, filemap);
+    srdr.real_token();
+    srdr.parse_all_token_trees()
+}
+
+/// Map string to parser (via tts).
+pub fn string_to_parser(ps: &ParseSess, source_str: String) -> Parser<'_> {
+    new_parser_from_source_str(ps, "bogofile".into(), source_str)
+}
+
+/// Parse a string with `f`, panicking if `f` fails or if any error is emitted
+/// while parsing, even one the parser recovered from.
+pub fn with_error_checking_parse<'a, T, F>(s: String, ps: &'a ParseSess, f: F) -> T
+where
+    F: FnOnce(&mut Parser<'a>) -> PResult<'a, T>,
+{
+    let errors = ps.span_diagnostic.err_count();
+    let mut p = string_to_parser(ps, s);
+    let x = panictry!(f(&mut p));
+    assert_eq!(
+        ps.span_diagnostic.err_count(),
+        errors,
+        "errors were emitted while parsing"
+    );
+    x
+}
+
+macro_rules! string_to {
+    ($(#[$attr:meta])* $name:ident, $try_name:ident, $ty:ty, $parse:ident) => {
+        $(#[$attr])*
+        pub fn $name(source_str: String) -> $ty {
+            let ps = ParseSess::new(FilePathMapping::empty());
+            with_error_checking_parse(source_str, &ps, |p| p.$parse())
+        }
+
+        $(#[$attr])*
+        pub fn $try_name(ps: &ParseSess, source_str: String) -> PResult<'_, $ty> {
+            string_to_parser(ps, source_str).$parse()
+        }
+    };
+}
+
+string_to!(
+    /// Parse a string, return a crate.
+    string_to_crate,
+    try_string_to_crate,
+    ast::Crate,
+    parse_crate_mod
+);
+string_to!(
+    /// Parse a string, return an expr.
+    string_to_expr,
+    try_string_to_expr,
+    P<ast::Expr>,
+    parse_expr
+);
+string_to!(
+    /// Parse a string, return an item.
+    string_to_item,
+    try_string_to_item,
+    Option<P<ast::Item>>,
+    parse_item
+);
+string_to!(
+    /// Parse a string, return a stmt.
+    string_to_stmt,
+    try_string_to_stmt,
+    Option<ast::Stmt>,
+    parse_stmt
+);
+string_to!(
+    /// Parse a string, return a pat.
+    string_to_pat,
+    try_string_to_pat,
+    P<ast::Pat>,
+    parse_pat
+);
+string_to!(
+    /// Parse a string, return a type.
+    string_to_ty,
+    try_string_to_ty,
+    P<ast::Ty>,
+    parse_ty
+);
+string_to!(
+    /// Parse a string, return a block, including its braces.
+    string_to_block,
+    try_string_to_block,
+    P<ast::Block>,
+    parse_block
+);
+
+/// An in-memory destination for an emitter. Clones share the buffer, so one
+/// can be given to the emitter and another kept to read its output.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    /// Everything written so far.
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+
+    /// The JSON diagnostics written so far.
+    pub fn json_diagnostics(&self) -> Vec<serde_json::Value> {
+        json_diagnostics(&self.contents())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The diagnostics in `output` of a `JsonEmitter`, which writes one per line.
+pub fn json_diagnostics(output: &str) -> Vec<serde_json::Value> {
+    output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+/// Convert a vector of strings to a vector of Ident's
+pub fn strs_to_idents(ids: Vec<&str>) -> Vec<Ident> {
+    ids.iter().map(|u| Ident::from_str(*u)).collect()
+}
+
+/// Does the given string match the pattern? whitespace in the first string
+/// may be deleted or replaced with other whitespace to match the pattern.
+/// This function is relatively Unicode-ignorant; fortunately, the careful design
+/// of UTF-8 mitigates this ignorance. It doesn't do NKF-normalization(?).
+pub fn matches_codepattern(a: &str, b: &str) -> bool {
+    let mut a_iter = a.chars().peekable();
+    let mut b_iter = b.chars().peekable();
+
+    loop {
+        let (a, b) = match (a_iter.peek(), b_iter.peek()) {
+            (None, None) => return true,
+            (None, _) => return false,
+            (Some(&a), None) => {
+                if is_pattern_whitespace(a) {
+                    break; // trailing whitespace check is out of loop for borrowck
+                } else {
+                    return false;
+                }
+            }
+            (Some(&a), Some(&b)) => (a, b),
+        };
+
+        if is_pattern_whitespace(a) && is_pattern_whitespace(b) {
+            // skip whitespace for a and b
+            scan_for_non_ws_or_end(&mut a_iter);
+            scan_for_non_ws_or_end(&mut b_iter);
+        } else if is_pattern_whitespace(a) {
+            // skip whitespace for a
+            scan_for_non_ws_or_end(&mut a_iter);
+        } else if a == b {
+            a_iter.next();
+            b_iter.next();
+        } else {
+            return false;
+        }
+    }
+
+    // check if a has *only* trailing whitespace
+    a_iter.all(is_pattern_whitespace)
+}
+
+/// Advances the given peekable `Iterator` until it reaches a non-whitespace character
+fn scan_for_non_ws_or_end<I: Iterator<Item = char>>(iter: &mut Peekable<I>) {
+    while lexer::is_pattern_whitespace(iter.peek().cloned()) {
+        iter.next();
+    }
+}
+
+pub fn is_pattern_whitespace(c: char) -> bool {
+    lexer::is_pattern_whitespace(Some(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codemap::CodeMap;
+    use crate::errors::emitter::EmitterWriter;
+    use crate::errors::Handler;
+    use crate::print::pprust;
+    use std::io;
+    use std::rc::Rc;
+
+    fn quiet_sess() -> ParseSess {
+        let cm = Rc::new(CodeMap::new(FilePathMapping::empty()));
+        let emitter = EmitterWriter::new(Box::new(io::sink()), Some(cm.clone()));
+        ParseSess::with_span_handler(Handler::with_emitter(true, false, Box::new(emitter)), cm)
+    }
+
+    #[test]
+    fn try_variants_return_errors() {
+        let ps = quiet_sess();
+        try_string_to_expr(&ps, "1 +".to_string())
+            .unwrap_err()
+            .cancel();
+        try_string_to_ty(&ps, "-> u8".to_string())
+            .unwrap_err()
+            .cancel();
+        try_string_to_block(&ps, "let x = 1;".to_string())
+            .unwrap_err()
+            .cancel();
+        try_string_to_pat(&ps, "+".to_string())
+            .unwrap_err()
+            .cancel();
+        try_string_to_stream(&ps, "a ]".to_string())
+            .unwrap_err()
+            .cancel();
+        assert!(try_string_to_item(&ps, "1".to_string()).unwrap().is_none());
+    }
+
+    #[test]
+    fn string_to_helpers() {
+        assert_eq!(
+            pprust::ty_to_string(&string_to_ty("Vec<u8>".to_string())),
+            "Vec<u8>"
+        );
+        assert_eq!(string_to_block("{ 1 }".to_string()).stmts.len(), 1);
+        assert_eq!(
+            pprust::pat_to_string(&string_to_pat("(a, _)".to_string())),
+            "(a, _)"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "errors were emitted while parsing")]
+    fn error_checking_parse_rejects_recovered_errors() {
+        string_to_crate("fn f() { let 🦀 = 1; }".to_string());
+    }
+
+    #[test]
+    fn eqmodws() {
+        assert_eq!(matches_codepattern("", ""), true);
+        assert_eq!(matches_codepattern("", "a"), false);
+        assert_eq!(matches_codepattern("a", ""), false);
+        assert_eq!(matches_codepattern("a", "a"), true);
+        assert_eq!(matches_codepattern("a b", "a   \n\t\r  b"), true);
+        assert_eq!(matches_codepattern("a b ", "a   \n\t\r  b"), true);
+        assert_eq!(matches_codepattern("a b", "a   \n\t\r  b "), false);
+        assert_eq!(matches_codepattern("a   b", "a b"), true);
+        assert_eq!(matches_codepattern("ab", "a b"), false);
+        assert_eq!(matches_codepattern("a   b", "ab"), true);
+        assert_eq!(matches_codepattern(" a   b", "ab"), true);
+    }
+
+    #[test]
+    fn pattern_whitespace() {
+        assert_eq!(matches_codepattern("", "\x0C"), false);
+        //assert_eq!(matches_codepattern("a b ","a   \u{0085}\n\t\r  b"),true);
+        assert_eq!(matches_codepattern("a b", "a   \u{0085}\n\t\r  b "), false);
+    }
+
+    #[test]
+    fn non_pattern_whitespace() {
+        // These have the property 'White_Space' but not 'Pattern_White_Space'
+        assert_eq!(matches_codepattern("a b", "a\u{2002}b"), false);
+        assert_eq!(matches_codepattern("a   b", "a\u{2002}b"), false);
+        assert_eq!(matches_codepattern("\u{205F}a   b", "ab"), false);
+        assert_eq!(matches_codepattern("a  \u{3000}b", "ab"), false);
+    }
+}
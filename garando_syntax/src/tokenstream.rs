@@ -17,7 +17,7 @@ use crate::ext::tt::{macro_parser, quoted};
 use crate::parse::token::{self, Token};
 use crate::parse::Directory;
 use crate::print::pprust;
-use crate::syntax_pos::{BytePos, Span, DUMMY_SP};
+use crate::syntax_pos::{Span, DUMMY_SP};
 use crate::util::RcSlice;
 
 use std::hash::{self, Hash};
@@ -46,29 +46,13 @@ impl Delimited {
     }
 
     /// Returns the opening delimiter as a token tree.
-    pub fn open_tt(&self, span: Span) -> TokenTree {
-        let open_span = if span == DUMMY_SP {
-            DUMMY_SP
-        } else {
-            Span {
-                hi: span.lo + BytePos(self.delim.len() as u32),
-                ..span
-            }
-        };
-        TokenTree::Token(open_span, self.open_token())
+    pub fn open_tt(&self, span: DelimSpan) -> TokenTree {
+        TokenTree::Token(span.open, self.open_token())
     }
 
     /// Returns the closing delimiter as a token tree.
-    pub fn close_tt(&self, span: Span) -> TokenTree {
-        let close_span = if span == DUMMY_SP {
-            DUMMY_SP
-        } else {
-            Span {
-                lo: span.hi - BytePos(self.delim.len() as u32),
-                ..span
-            }
-        };
-        TokenTree::Token(close_span, self.close_token())
+    pub fn close_tt(&self, span: DelimSpan) -> TokenTree {
+        TokenTree::Token(span.close, self.close_token())
     }
 
     /// Returns the token trees inside the delimiters.
@@ -77,6 +61,40 @@ impl Delimited {
     }
 }
 
+/// The spans of the opening and closing delimiters of a `Delimited`.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash, Debug)]
+pub struct DelimSpan {
+    pub open: Span,
+    pub close: Span,
+}
+
+impl DelimSpan {
+    /// Uses `sp` for both delimiters, for groups that were not lexed from
+    /// source and so have no separate delimiter tokens.
+    pub fn from_single(sp: Span) -> Self {
+        DelimSpan {
+            open: sp,
+            close: sp,
+        }
+    }
+
+    pub fn from_pair(open: Span, close: Span) -> Self {
+        DelimSpan { open, close }
+    }
+
+    pub fn dummy() -> Self {
+        Self::from_single(DUMMY_SP)
+    }
+
+    /// The span covering both delimiters and everything between them.
+    pub fn entire(self) -> Span {
+        Span {
+            hi: self.close.hi,
+            ..self.open
+        }
+    }
+}
+
 /// When the main rust parser encounters a syntax-extension invocation, it
 /// parses the arguments to the invocation as a token-tree. This is a very
 /// loose structure, such that all sorts of different AST-fragments can
@@ -94,7 +112,7 @@ pub enum TokenTree {
     /// A single token
     Token(Span, token::Token),
     /// A delimited sequence of token trees
-    Delimited(Span, Delimited),
+    Delimited(DelimSpan, Delimited),
 }
 
 impl TokenTree {
@@ -131,7 +149,8 @@ impl TokenTree {
     /// Retrieve the TokenTree's span.
     pub fn span(&self) -> Span {
         match *self {
-            TokenTree::Token(sp, _) | TokenTree::Delimited(sp, _) => sp,
+            TokenTree::Token(sp, _) => sp,
+            TokenTree::Delimited(sp, _) => sp.entire(),
         }
     }
 
@@ -150,6 +169,13 @@ impl TokenTree {
 /// The goal is for procedural macros to work with `TokenStream`s and `TokenTree`s
 /// instead of a representation of the abstract syntax tree.
 /// Today's `TokenTree`s can still contain AST via `Token::Interpolated` for back-compat.
+///
+/// Cloning a `TokenStream` is shallow: its segments, and the contents of each
+/// `Delimited` in it, are shared behind `Rc`s, so a clone only bumps reference
+/// counts however deeply the stream nests. `TokenTree`s clone the same way,
+/// `Token::Interpolated` included. Code that passes a stream through mostly
+/// unchanged should keep the parts it doesn't change, rather than collecting
+/// `trees()` and rebuilding them.
 #[derive(Clone, Debug)]
 pub struct TokenStream {
     kind: TokenStreamKind,
@@ -450,7 +476,7 @@ mod tests {
     use crate::parse::token::Token;
     use crate::syntax::ast::Ident;
     use crate::syntax_pos::{BytePos, Span, NO_EXPANSION};
-    use crate::util::parser_testing::string_to_stream;
+    use crate::testing::string_to_stream;
 
     fn string_to_ts(string: &str) -> TokenStream {
         string_to_stream(string.to_owned())
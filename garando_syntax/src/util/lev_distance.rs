@@ -1,5 +1,6 @@
 use crate::symbol::Symbol;
 use std::cmp;
+use std::mem;
 
 /// To find the Levenshtein distance between two strings
 pub fn lev_distance(a: &str, b: &str) -> usize {
@@ -59,6 +60,65 @@ where
         .map(|(s, _)| s) // and return only the string
 }
 
+/// The Damerau-Levenshtein distance between two strings, where swapping two
+/// adjacent characters counts as a single edit, or `None` if it is greater
+/// than `limit`. Gives up as soon as every alignment exceeds the limit.
+pub fn bounded_damerau_lev_distance(a: &str, b: &str, limit: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > limit {
+        return None;
+    }
+
+    // The last three rows of the distance matrix.
+    let mut before_prev = vec![0; b.len() + 1];
+    let mut prev: Vec<_> = (0..b.len() + 1).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for i in 1..a.len() + 1 {
+        current[0] = i;
+        let mut row_min = i;
+        for j in 1..b.len() + 1 {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut dist = cmp::min(cmp::min(prev[j], current[j - 1]) + 1, prev[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                dist = cmp::min(dist, before_prev[j - 2] + 1);
+            }
+            current[j] = dist;
+            row_min = cmp::min(row_min, dist);
+        }
+        if row_min > limit {
+            return None;
+        }
+        mem::swap(&mut before_prev, &mut prev);
+        mem::swap(&mut prev, &mut current);
+    }
+
+    Some(prev[b.len()]).filter(|&dist| dist <= limit)
+}
+
+/// Like `find_best_match_for_name`, but for plain strings and counting a
+/// transposition of adjacent characters as one edit. Candidates equal to
+/// `lookup` are never returned.
+pub fn find_best_match_for_str<'a, T>(
+    candidates: T,
+    lookup: &str,
+    dist: Option<usize>,
+) -> Option<&'a str>
+where
+    T: IntoIterator<Item = &'a str>,
+{
+    let max_dist = dist.unwrap_or_else(|| cmp::max(lookup.len(), 3) / 3);
+    candidates
+        .into_iter()
+        .filter(|&candidate| candidate != lookup)
+        .filter_map(|candidate| {
+            bounded_damerau_lev_distance(lookup, candidate, max_dist).map(|dist| (candidate, dist))
+        })
+        .min_by_key(|&(_, dist)| dist)
+        .map(|(candidate, _)| candidate)
+}
+
 #[test]
 fn test_lev_distance() {
     use std::char::{from_u32, MAX};
@@ -80,3 +140,42 @@ fn test_lev_distance() {
     assert_eq!(lev_distance(b, c), 1);
     assert_eq!(lev_distance(c, b), 1);
 }
+
+#[test]
+fn test_bounded_damerau_lev_distance() {
+    assert_eq!(bounded_damerau_lev_distance("", "", 0), Some(0));
+    assert_eq!(bounded_damerau_lev_distance("abc", "", 3), Some(3));
+    assert_eq!(bounded_damerau_lev_distance("abc", "", 2), None);
+    assert_eq!(
+        bounded_damerau_lev_distance("taget_os", "target_os", 1),
+        Some(1)
+    );
+    // A transposition is a single edit, unlike with `lev_distance`.
+    assert_eq!(bounded_damerau_lev_distance("allwo", "allow", 1), Some(1));
+    assert_eq!(lev_distance("allwo", "allow"), 2);
+    assert_eq!(bounded_damerau_lev_distance("ca", "abc", 3), Some(3));
+    assert_eq!(
+        bounded_damerau_lev_distance("kitten", "sitting", 3),
+        Some(3)
+    );
+    assert_eq!(bounded_damerau_lev_distance("kitten", "sitting", 2), None);
+    assert_eq!(bounded_damerau_lev_distance("häd", "hdä", 1), Some(1));
+
+    let names = ["allow", "deny", "forbid", "warn"];
+    assert_eq!(
+        find_best_match_for_str(names.iter().cloned(), "allw", None),
+        Some("allow")
+    );
+    assert_eq!(
+        find_best_match_for_str(names.iter().cloned(), "dney", None),
+        Some("deny")
+    );
+    assert_eq!(
+        find_best_match_for_str(names.iter().cloned(), "allow", None),
+        None
+    );
+    assert_eq!(
+        find_best_match_for_str(names.iter().cloned(), "frobnicate", None),
+        None
+    );
+}
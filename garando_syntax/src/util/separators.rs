@@ -0,0 +1,123 @@
+//! Separator details of the source that the AST does not keep, recovered by
+//! lexing the source again, for tools that reformat it.
+
+use crate::codemap::{CodeMap, FilePathMapping};
+use crate::parse::{self, token, ParseSess};
+use crate::syntax_pos::Span;
+use crate::tokenstream::TokenTree;
+
+/// Whether the delimited group spanning `span`, e.g. the `(..)` of a call or
+/// the `{..}` of a struct's fields, ends with a comma before its closing
+/// delimiter. Comments, including doc comments, are ignored, and an empty
+/// group has no trailing comma. Returns `None` if the source of `span` is not
+/// available or is not exactly one well-formed delimited group.
+pub fn has_trailing_separator(cm: &CodeMap, span: Span) -> Option<bool> {
+    let snippet = cm.span_to_snippet(span).ok()?;
+    let (sess, _diagnostics) = ParseSess::new_for_batch(FilePathMapping::empty());
+    let stream =
+        match parse::maybe_parse_stream_from_source_str("<group>".to_string(), snippet, &sess) {
+            Ok(stream) => stream,
+            Err(mut err) => {
+                err.cancel();
+                return None;
+            }
+        };
+    if sess.span_diagnostic.has_errors() {
+        return None;
+    }
+
+    let mut trees = stream.trees();
+    let delimited = match (trees.next(), trees.next()) {
+        (Some(TokenTree::Delimited(_, delimited)), None) => delimited,
+        _ => return None,
+    };
+    let last = delimited
+        .stream()
+        .trees()
+        .filter(|tree| match *tree {
+            TokenTree::Token(_, token::DocComment(..)) => false,
+            _ => true,
+        })
+        .last();
+    Some(match last {
+        Some(TokenTree::Token(_, token::Comma)) => true,
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax_pos::{BytePos, NO_EXPANSION};
+
+    /// Whether the group in `src` starting at the first `open` and ending at
+    /// the last `close` has a trailing comma.
+    fn trailing(src: &str, open: char, close: char) -> Option<bool> {
+        let cm = CodeMap::new(FilePathMapping::empty());
+        let fm = cm.new_filemap("group.rs".into(), src.to_string());
+        let lo = src.find(open).unwrap() as u32;
+        let hi = src.rfind(close).unwrap() as u32 + 1;
+        let span = Span {
+            lo: fm.start_pos + BytePos(lo),
+            hi: fm.start_pos + BytePos(hi),
+            ctxt: NO_EXPANSION,
+        };
+        has_trailing_separator(&cm, span)
+    }
+
+    #[test]
+    fn empty_and_single_element() {
+        assert_eq!(trailing("f()", '(', ')'), Some(false));
+        assert_eq!(trailing("struct S {}", '{', '}'), Some(false));
+        assert_eq!(trailing("f(a)", '(', ')'), Some(false));
+        assert_eq!(trailing("f(a,)", '(', ')'), Some(true));
+        assert_eq!(trailing("[\n    1,\n]", '[', ']'), Some(true));
+        // A tuple of one element needs its comma, but has it all the same.
+        assert_eq!(trailing("let t = (a,);", '(', ')'), Some(true));
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(trailing("fn f(a: u8, b: u8) {}", '(', ')'), Some(false));
+        assert_eq!(trailing("fn f(a: u8, b: u8,) {}", '(', ')'), Some(true));
+        assert_eq!(
+            trailing("enum E {\n    A,\n    B(u8, u16),\n}", '{', '}'),
+            Some(true)
+        );
+        assert_eq!(
+            trailing("match x {\n    1 => {}\n    _ => {}\n}", '{', '}'),
+            Some(false)
+        );
+        assert_eq!(
+            trailing("match x {\n    1 => a,\n    _ => b,\n}", '{', '}'),
+            Some(true)
+        );
+        // Only the group's own tokens count, not those of nested groups.
+        assert_eq!(trailing("f(a, g(b,))", '(', ')'), Some(false));
+    }
+
+    #[test]
+    fn comments_before_close() {
+        assert_eq!(trailing("f(a, /* b */)", '(', ')'), Some(true));
+        assert_eq!(trailing("f(a /* , */)", '(', ')'), Some(false));
+        assert_eq!(
+            trailing("struct S {\n    a: u8, // last\n}", '{', '}'),
+            Some(true)
+        );
+        assert_eq!(
+            trailing("struct S {\n    a: u8\n    // , b: u8,\n}", '{', '}'),
+            Some(false)
+        );
+        assert_eq!(
+            trailing("struct S {\n    a: u8,\n    /// Doc.\n}", '{', '}'),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn not_a_group() {
+        assert_eq!(trailing("(a) (b)", '(', ')'), None);
+        assert_eq!(trailing("f(a, \"b)", '(', ')'), None);
+        assert_eq!(trailing("a, b", 'a', 'b'), None);
+    }
+}
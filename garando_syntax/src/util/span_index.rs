@@ -0,0 +1,370 @@
+//! An index from source positions to the AST nodes spanning them, for tools
+//! such as editors that ask which node is at a position many times over.
+//!
+//! The index holds no references into the crate it was built from, so it
+//! goes stale rather than dangling: build it again after each parse, or after
+//! folding the crate, since either can move nodes and change their ids.
+
+use crate::ast::{self, NodeId};
+use crate::syntax_pos::{BytePos, Span, NO_EXPANSION};
+use crate::visit::{self, Visitor};
+
+/// The categories of node a `SpanIndex` holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NodeKind {
+    Item,
+    ForeignItem,
+    TraitItem,
+    ImplItem,
+    Block,
+    Stmt,
+    Local,
+    Expr,
+    Pat,
+    Ty,
+}
+
+const NODE_KINDS: usize = 10;
+
+/// A node found in a `SpanIndex`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NodeRef {
+    pub kind: NodeKind,
+    pub span: Span,
+    /// The node's id, if ids have been assigned, e.g. by expansion with a
+    /// resolver. Otherwise the node is identified by its kind and span alone.
+    pub id: Option<NodeId>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    node: NodeRef,
+    /// The position of the node in a preorder walk of the crate.
+    order: usize,
+    /// The index of the innermost node of the same kind containing this one.
+    parent: Option<usize>,
+}
+
+/// The nodes of a crate, in sorted arrays per `NodeKind`, from which the
+/// nodes spanning a position are found in O(log n + d) time, where `d` is
+/// how deeply the nodes of one kind nest at that position.
+///
+/// Only nodes written in the source are indexed, not those whose span comes
+/// from an expansion or is empty. Nodes are expected to nest, as those of a
+/// parsed crate do; a node only partly overlapping another of its kind may be
+/// missed by queries at positions within both.
+#[derive(Clone, Debug, Default)]
+pub struct SpanIndex {
+    entries: [Vec<Entry>; NODE_KINDS],
+}
+
+impl SpanIndex {
+    pub fn build(krate: &ast::Crate) -> SpanIndex {
+        let mut builder = Builder { nodes: Vec::new() };
+        visit::walk_crate(&mut builder, krate);
+
+        let mut index = SpanIndex::default();
+        for (order, node) in builder.nodes.into_iter().enumerate() {
+            index.entries[node.kind as usize].push(Entry {
+                node,
+                order,
+                parent: None,
+            });
+        }
+        for entries in &mut index.entries {
+            entries.sort_by_key(|entry| (entry.node.span.lo, entry.order));
+            // The nodes containing the current one, innermost last.
+            let mut enclosing: Vec<usize> = Vec::new();
+            for i in 0..entries.len() {
+                let hi = entries[i].node.span.hi;
+                while let Some(&j) = enclosing.last() {
+                    if entries[j].node.span.hi >= hi {
+                        break;
+                    }
+                    enclosing.pop();
+                }
+                entries[i].parent = enclosing.last().cloned();
+                enclosing.push(i);
+            }
+        }
+        index
+    }
+
+    /// The number of nodes indexed.
+    pub fn len(&self) -> usize {
+        self.entries.iter().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The innermost node whose span contains `pos`, i.e. with `lo <= pos`
+    /// and `pos < hi`.
+    pub fn query_innermost(&self, pos: BytePos) -> Option<NodeRef> {
+        (0..NODE_KINDS)
+            .filter_map(|kind| self.containing(kind, pos).next())
+            .max_by_key(|entry| entry.order)
+            .map(|entry| entry.node)
+    }
+
+    /// The innermost node of the given kind whose span contains `pos`.
+    pub fn query_innermost_of(&self, kind: NodeKind, pos: BytePos) -> Option<NodeRef> {
+        self.containing(kind as usize, pos)
+            .next()
+            .map(|entry| entry.node)
+    }
+
+    /// The nodes whose span contains `pos`, outermost first.
+    pub fn query_all(&self, pos: BytePos) -> Vec<NodeRef> {
+        let mut entries: Vec<_> = (0..NODE_KINDS)
+            .flat_map(|kind| self.containing(kind, pos))
+            .collect();
+        entries.sort_by_key(|entry| entry.order);
+        entries.into_iter().map(|entry| entry.node).collect()
+    }
+
+    /// The nodes of the given kind containing `pos`, innermost first.
+    fn containing<'a>(&'a self, kind: usize, pos: BytePos) -> impl Iterator<Item = &'a Entry> {
+        let entries = &self.entries[kind];
+        // The last node starting at or before `pos` contains it, or else the
+        // nodes containing `pos` are among those enclosing it.
+        let start = entries.partition_point(|entry| entry.node.span.lo <= pos);
+        let mut next = start.checked_sub(1);
+        ::std::iter::from_fn(move || {
+            while let Some(i) = next {
+                let entry = &entries[i];
+                next = entry.parent;
+                if pos < entry.node.span.hi {
+                    return Some(entry);
+                }
+            }
+            None
+        })
+    }
+}
+
+/// Collects the nodes to index in preorder.
+struct Builder {
+    nodes: Vec<NodeRef>,
+}
+
+impl Builder {
+    fn add(&mut self, kind: NodeKind, span: Span, id: NodeId) {
+        if span.ctxt != NO_EXPANSION || span.lo >= span.hi {
+            return;
+        }
+        self.nodes.push(NodeRef {
+            kind,
+            span,
+            id: if id == ast::DUMMY_NODE_ID {
+                None
+            } else {
+                Some(id)
+            },
+        });
+    }
+}
+
+impl<'ast> Visitor<'ast> for Builder {
+    fn visit_item(&mut self, i: &'ast ast::Item) {
+        self.add(NodeKind::Item, i.span, i.id);
+        visit::walk_item(self, i);
+    }
+    fn visit_foreign_item(&mut self, i: &'ast ast::ForeignItem) {
+        self.add(NodeKind::ForeignItem, i.span, i.id);
+        visit::walk_foreign_item(self, i);
+    }
+    fn visit_trait_item(&mut self, ti: &'ast ast::TraitItem) {
+        self.add(NodeKind::TraitItem, ti.span, ti.id);
+        visit::walk_trait_item(self, ti);
+    }
+    fn visit_impl_item(&mut self, ii: &'ast ast::ImplItem) {
+        self.add(NodeKind::ImplItem, ii.span, ii.id);
+        visit::walk_impl_item(self, ii);
+    }
+    fn visit_block(&mut self, b: &'ast ast::Block) {
+        self.add(NodeKind::Block, b.span, b.id);
+        visit::walk_block(self, b);
+    }
+    fn visit_stmt(&mut self, s: &'ast ast::Stmt) {
+        self.add(NodeKind::Stmt, s.span, s.id);
+        visit::walk_stmt(self, s);
+    }
+    fn visit_local(&mut self, l: &'ast ast::Local) {
+        self.add(NodeKind::Local, l.span, l.id);
+        visit::walk_local(self, l);
+    }
+    fn visit_expr(&mut self, ex: &'ast ast::Expr) {
+        self.add(NodeKind::Expr, ex.span, ex.id);
+        visit::walk_expr(self, ex);
+    }
+    fn visit_pat(&mut self, p: &'ast ast::Pat) {
+        self.add(NodeKind::Pat, p.span, p.id);
+        visit::walk_pat(self, p);
+    }
+    fn visit_ty(&mut self, t: &'ast ast::Ty) {
+        self.add(NodeKind::Ty, t.span, t.id);
+        visit::walk_ty(self, t);
+    }
+    fn visit_mac(&mut self, mac: &'ast ast::Mac) {
+        visit::walk_mac(self, mac);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codemap::FilePathMapping;
+    use crate::parse::{self, ParseSess};
+    use std::time::{Duration, Instant};
+
+    fn parse(src: &str) -> (ParseSess, ast::Crate) {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let krate = parse::parse_crate_from_source_str("a.rs".to_string(), src.to_string(), &sess)
+            .unwrap_or_else(|mut e| {
+                e.emit();
+                panic!("{:?} failed to parse", src)
+            });
+        (sess, krate)
+    }
+
+    /// The nodes containing `pos`, found by walking the whole crate.
+    fn naive_query_all(krate: &ast::Crate, pos: BytePos) -> Vec<NodeRef> {
+        let mut builder = Builder { nodes: Vec::new() };
+        visit::walk_crate(&mut builder, krate);
+        builder
+            .nodes
+            .into_iter()
+            .filter(|node| node.span.lo <= pos && pos < node.span.hi)
+            .collect()
+    }
+
+    fn kinds_and_snippets(sess: &ParseSess, nodes: &[NodeRef]) -> Vec<(NodeKind, String)> {
+        nodes
+            .iter()
+            .map(|node| {
+                let snippet = sess.codemap().span_to_snippet(node.span).unwrap();
+                (node.kind, snippet)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nodes_at_position() {
+        let src = "fn f(x: u8) -> u8 {\n    let y = x + 1;\n    y * 2\n}\n\
+                   trait T { fn g(&self); }\n";
+        let (sess, krate) = parse(src);
+        let index = SpanIndex::build(&krate);
+        let at = |text: &str| BytePos(src.find(text).unwrap() as u32);
+
+        let innermost = index.query_innermost(at("x + 1")).unwrap();
+        assert_eq!(innermost.kind, NodeKind::Expr);
+        assert_eq!(sess.codemap().span_to_snippet(innermost.span).unwrap(), "x");
+        assert_eq!(innermost.id, None);
+        assert_eq!(
+            kinds_and_snippets(&sess, &index.query_all(at("+ 1"))),
+            [
+                (NodeKind::Item, &src[..src.find("\ntrait").unwrap()]),
+                (
+                    NodeKind::Block,
+                    &src[src.find('{').unwrap()..src.find("\ntrait").unwrap()]
+                ),
+                (NodeKind::Stmt, "let y = x + 1;"),
+                (NodeKind::Local, "y = x + 1"),
+                (NodeKind::Expr, "x + 1"),
+            ]
+            .iter()
+            .map(|&(kind, snippet)| (kind, snippet.to_string()))
+            .collect::<Vec<_>>()
+        );
+
+        let ty = index.query_innermost_of(NodeKind::Ty, at("u8)")).unwrap();
+        assert_eq!(sess.codemap().span_to_snippet(ty.span).unwrap(), "u8");
+        let trait_item = index.query_innermost(at("fn g")).unwrap();
+        assert_eq!(trait_item.kind, NodeKind::TraitItem);
+
+        // Between the items, and past the end of the crate.
+        assert_eq!(index.query_innermost(at("\ntrait")), None);
+        assert_eq!(index.query_all(BytePos(src.len() as u32 + 10)), []);
+    }
+
+    #[test]
+    fn unexpanded_macros_and_ids() {
+        let src = "fn f() { m!(a b); let v = vec![1]; }";
+        let (_sess, mut krate) = parse(src);
+        let index = SpanIndex::build(&krate);
+        let innermost = index.query_innermost(BytePos(src.find("1]").unwrap() as u32));
+        assert_eq!(innermost.map(|node| node.kind), Some(NodeKind::Expr));
+
+        krate.module.items[0] = krate.module.items[0].clone().map(|mut item| {
+            item.id = NodeId::new(7);
+            item
+        });
+        let index = SpanIndex::build(&krate);
+        let item = index
+            .query_innermost_of(NodeKind::Item, BytePos(0))
+            .unwrap();
+        assert_eq!(item.id, Some(NodeId::new(7)));
+    }
+
+    /// A crate of a few hundred functions, and positions throughout it.
+    fn large_crate() -> (ast::Crate, Vec<BytePos>) {
+        let mut src = String::new();
+        for i in 0..300 {
+            src.push_str(&format!(
+                "fn f{0}(a: u32, b: (u32, u32)) -> u32 {{\n    \
+                 let c = if a > {0} {{ a * (b.0 + {0}) }} else {{ b.1 }};\n    \
+                 match c {{ 0 => 1, n => [n, n + 1][0] }}\n}}\n",
+                i
+            ));
+        }
+        let (_sess, krate) = parse(&src);
+        let positions = (0..src.len() as u32).step_by(97).map(BytePos).collect();
+        (krate, positions)
+    }
+
+    /// Compares the nodes the index finds with a walk of the whole crate, on
+    /// a large crate.
+    #[test]
+    fn matches_naive_walk() {
+        let (krate, positions) = large_crate();
+        let index = SpanIndex::build(&krate);
+        assert!(index.len() > 10_000, "{}", index.len());
+
+        for &pos in &positions {
+            let expected = naive_query_all(&krate, pos);
+            assert_eq!(index.query_all(pos), expected, "at {:?}", pos);
+            assert_eq!(index.query_innermost(pos), expected.last().cloned());
+        }
+    }
+
+    /// Compares the time queries take with the index and with a walk of the
+    /// whole crate. Ignored by default, since timings depend on the machine
+    /// and its load; run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn faster_than_naive_walk() {
+        let (krate, positions) = large_crate();
+        let index = SpanIndex::build(&krate);
+
+        let mut naive_time = Duration::default();
+        let mut index_time = Duration::default();
+        for &pos in &positions {
+            let start = Instant::now();
+            naive_query_all(&krate, pos);
+            naive_time += start.elapsed();
+
+            let start = Instant::now();
+            index.query_all(pos);
+            index.query_innermost(pos);
+            index_time += start.elapsed();
+        }
+        assert!(
+            index_time * 10 < naive_time,
+            "index: {:?}, naive walk: {:?}",
+            index_time,
+            naive_time
+        );
+    }
+}
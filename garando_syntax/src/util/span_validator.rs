@@ -0,0 +1,265 @@
+//! Checks the spans of a crate for the invariants the rest of the library
+//! relies on, as a debugging aid for folders and parser changes.
+
+use crate::ast::*;
+use crate::codemap::CodeMap;
+use crate::syntax_pos::{Span, DUMMY_SP, NO_EXPANSION};
+use crate::visit::{self, FnKind, Visitor};
+
+/// A span breaking one of the invariants checked by `validate`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpanViolation {
+    /// The kind of node the span belongs to, e.g. `"expr"`.
+    pub kind: &'static str,
+    pub span: Span,
+    pub description: String,
+}
+
+/// Checks that every span in `krate`:
+///
+/// * is not `DUMMY_SP`, unless it comes from an expansion,
+/// * has `lo <= hi`,
+/// * lies within a single filemap of `cm`,
+/// * lies within the span of its enclosing item or module, unless either
+///   comes from an expansion, where that legitimately fails. Attributes and
+///   out-of-line modules are exempt as well.
+pub fn validate(krate: &Crate, cm: &CodeMap) -> Vec<SpanViolation> {
+    let mut validator = SpanValidator {
+        cm,
+        parents: Vec::new(),
+        violations: Vec::new(),
+    };
+    validator.check("crate", krate.span);
+    visit::walk_crate(&mut validator, krate);
+    validator.violations
+}
+
+struct SpanValidator<'a> {
+    cm: &'a CodeMap,
+    /// The spans of the enclosing items and modules, innermost last.
+    parents: Vec<Span>,
+    violations: Vec<SpanViolation>,
+}
+
+impl<'a> SpanValidator<'a> {
+    fn violation(&mut self, kind: &'static str, span: Span, description: String) {
+        self.violations.push(SpanViolation {
+            kind,
+            span,
+            description,
+        });
+    }
+
+    /// The index of the filemap `span` lies within, if there is one.
+    fn filemap_idx(&self, span: Span) -> Option<usize> {
+        self.cm
+            .files()
+            .iter()
+            .position(|fm| fm.start_pos <= span.lo && span.hi <= fm.end_pos)
+    }
+
+    /// Check `span` on its own, returning whether it is valid.
+    fn check_alone(&mut self, kind: &'static str, span: Span) -> bool {
+        if span.ctxt == NO_EXPANSION && span.source_equal(&DUMMY_SP) {
+            self.violation(kind, span, "dummy span".to_string());
+            return false;
+        }
+        if span.lo > span.hi {
+            let description = format!("inverted span ({} > {})", span.lo.0, span.hi.0);
+            self.violation(kind, span, description);
+            return false;
+        }
+        if self.filemap_idx(span).is_none() {
+            let description = "not within a single filemap".to_string();
+            self.violation(kind, span, description);
+            return false;
+        }
+        true
+    }
+
+    fn check(&mut self, kind: &'static str, span: Span) {
+        if !self.check_alone(kind, span) {
+            return;
+        }
+        if let Some(&parent) = self.parents.last() {
+            let expanded = span.ctxt != NO_EXPANSION || parent.ctxt != NO_EXPANSION;
+            let contained = parent.lo <= span.lo && span.hi <= parent.hi;
+            if !expanded && !contained {
+                let description = format!(
+                    "not within its parent's span ({}..{})",
+                    parent.lo.0, parent.hi.0
+                );
+                self.violation(kind, span, description);
+            }
+        }
+    }
+
+    fn with_parent<F: FnOnce(&mut Self)>(&mut self, span: Span, f: F) {
+        self.parents.push(span);
+        f(self);
+        self.parents.pop();
+    }
+}
+
+impl<'a, 'ast> Visitor<'ast> for SpanValidator<'a> {
+    fn visit_mod(&mut self, m: &'ast Mod, _s: Span, _attrs: &[Attribute], _n: NodeId) {
+        // The items of an out-of-line module are in its own file rather
+        // than within the `mod foo;` item.
+        let out_of_line = match self.parents.last() {
+            Some(&parent) => self.filemap_idx(parent) != self.filemap_idx(m.inner),
+            None => false,
+        };
+        if out_of_line {
+            self.check_alone("mod", m.inner);
+        } else {
+            self.check("mod", m.inner);
+        }
+        self.with_parent(m.inner, |this| visit::walk_mod(this, m));
+    }
+    fn visit_item(&mut self, i: &'ast Item) {
+        self.check("item", i.span);
+        self.with_parent(i.span, |this| visit::walk_item(this, i));
+    }
+    fn visit_foreign_item(&mut self, i: &'ast ForeignItem) {
+        self.check("foreign item", i.span);
+        self.with_parent(i.span, |this| visit::walk_foreign_item(this, i));
+    }
+    fn visit_trait_item(&mut self, ti: &'ast TraitItem) {
+        self.check("trait item", ti.span);
+        self.with_parent(ti.span, |this| visit::walk_trait_item(this, ti));
+    }
+    fn visit_impl_item(&mut self, ii: &'ast ImplItem) {
+        self.check("impl item", ii.span);
+        self.with_parent(ii.span, |this| visit::walk_impl_item(this, ii));
+    }
+    fn visit_fn(&mut self, fk: FnKind<'ast>, fd: &'ast FnDecl, s: Span, _: NodeId) {
+        self.check("fn", s);
+        visit::walk_fn(self, fk, fd, s);
+    }
+    fn visit_local(&mut self, l: &'ast Local) {
+        self.check("local", l.span);
+        visit::walk_local(self, l);
+    }
+    fn visit_block(&mut self, b: &'ast Block) {
+        self.check("block", b.span);
+        visit::walk_block(self, b);
+    }
+    fn visit_stmt(&mut self, s: &'ast Stmt) {
+        self.check("stmt", s.span);
+        visit::walk_stmt(self, s);
+    }
+    fn visit_pat(&mut self, p: &'ast Pat) {
+        self.check("pat", p.span);
+        visit::walk_pat(self, p);
+    }
+    fn visit_expr(&mut self, ex: &'ast Expr) {
+        self.check("expr", ex.span);
+        visit::walk_expr(self, ex);
+    }
+    fn visit_ty(&mut self, t: &'ast Ty) {
+        self.check("ty", t.span);
+        visit::walk_ty(self, t);
+    }
+    fn visit_struct_field(&mut self, s: &'ast StructField) {
+        self.check("struct field", s.span);
+        visit::walk_struct_field(self, s);
+    }
+    fn visit_variant(&mut self, v: &'ast Variant, g: &'ast Generics, item_id: NodeId) {
+        self.check("variant", v.span);
+        visit::walk_variant(self, v, g, item_id);
+    }
+    fn visit_lifetime(&mut self, lifetime: &'ast Lifetime) {
+        self.check("lifetime", lifetime.span);
+    }
+    fn visit_path(&mut self, path: &'ast Path, _id: NodeId) {
+        self.check("path", path.span);
+        visit::walk_path(self, path);
+    }
+    fn visit_path_segment(&mut self, path_span: Span, segment: &'ast PathSegment) {
+        self.check("path segment", segment.span);
+        visit::walk_path_segment(self, path_span, segment);
+    }
+    fn visit_attribute(&mut self, attr: &'ast Attribute) {
+        // Outer attributes precede the span of the node they are attached to.
+        self.check_alone("attribute", attr.span);
+    }
+    fn visit_mac(&mut self, mac: &'ast Mac) {
+        // The tokens of an unexpanded invocation aren't checked.
+        self.check("mac", mac.span);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codemap::FilePathMapping;
+    use crate::fold::{self, Folder};
+    use crate::parse::{self, ParseSess};
+    use crate::ptr::P;
+    use crate::syntax_pos::BytePos;
+
+    /// Replaces the span of every literal expression with `f` of it.
+    struct LitSpans<F>(F);
+
+    impl<F: Fn(Span) -> Span> Folder for LitSpans<F> {
+        fn fold_expr(&mut self, e: P<Expr>) -> P<Expr> {
+            e.map(|e| {
+                let mut e = fold::noop_fold_expr(e, self);
+                if let ExprKind::Lit(_) = e.node {
+                    e.span = (self.0)(e.span);
+                }
+                e
+            })
+        }
+    }
+
+    fn violations_with_lit_spans<F: Fn(Span) -> Span>(f: F) -> Vec<(&'static str, String)> {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let src = "fn f() { g(1 + 2); }\nfn h() {}";
+        let krate = parse::parse_crate_from_source_str("a.rs".to_string(), src.to_string(), &sess);
+        let krate = LitSpans(f).fold_crate(krate.unwrap());
+        validate(&krate, sess.codemap())
+            .into_iter()
+            .map(|v| (v.kind, v.description))
+            .collect()
+    }
+
+    #[test]
+    fn valid_spans() {
+        assert_eq!(violations_with_lit_spans(|span| span), []);
+    }
+
+    #[test]
+    fn invalid_spans() {
+        let expr = |description: &str| vec![("expr", description.to_string()); 2];
+
+        let inverted = violations_with_lit_spans(|span| Span {
+            lo: span.hi,
+            hi: span.lo,
+            ..span
+        });
+        assert_eq!(
+            inverted,
+            [
+                ("expr", "inverted span (12 > 11)".to_string()),
+                ("expr", "inverted span (16 > 15)".to_string())
+            ]
+        );
+
+        assert_eq!(violations_with_lit_spans(|_| DUMMY_SP), expr("dummy span"));
+
+        let outside = violations_with_lit_spans(|span| Span {
+            hi: BytePos(1000),
+            ..span
+        });
+        assert_eq!(outside, expr("not within a single filemap"));
+
+        // Into `fn h() {}`.
+        let uncontained = violations_with_lit_spans(|span| Span {
+            lo: span.lo + BytePos(12),
+            hi: span.hi + BytePos(12),
+            ..span
+        });
+        assert_eq!(uncontained, expr("not within its parent's span (0..20)"));
+    }
+}
@@ -0,0 +1,269 @@
+//! Hashing of spans and AST fragments that is stable across processes.
+//!
+//! `Hash` is not suitable for this: a `Symbol` hashes as its interned index,
+//! which depends on the order strings were interned in, and a `Span` as its
+//! position in one particular `CodeMap`. The `HashStable` implementations
+//! here hash symbols by their contents and spans by their file name and
+//! file-relative offsets instead, and ignore syntax contexts.
+
+pub use crate::rustc_data_structures::stable_hasher::{
+    HashStable, StableHasher, StableHasherResult,
+};
+
+use crate::ast::{
+    FloatTy, IntTy, Item, LitIntType, LitKind, MetaItem, MetaItemKind, NestedMetaItemKind,
+    StrStyle, UintTy,
+};
+use crate::codemap::{CodeMap, Spanned};
+use crate::print::pprust;
+use crate::symbol::{Ident, Symbol};
+use crate::syntax_pos::Span;
+
+/// What spans are resolved against while hashing.
+pub struct StableHashingContext<'a> {
+    codemap: &'a CodeMap,
+}
+
+impl<'a> StableHashingContext<'a> {
+    pub fn new(codemap: &'a CodeMap) -> StableHashingContext<'a> {
+        StableHashingContext { codemap }
+    }
+}
+
+/// A value with a hash that is stable across processes, as long as its spans
+/// point into files with the same names and contents.
+pub trait StableHash: for<'a> HashStable<StableHashingContext<'a>> {
+    fn stable_hash(&self, codemap: &CodeMap) -> u128 {
+        let mut hasher = StableHasher::new();
+        self.hash_stable(&mut StableHashingContext::new(codemap), &mut hasher);
+        hasher.finish()
+    }
+}
+
+impl<T: ?Sized + for<'a> HashStable<StableHashingContext<'a>>> StableHash for T {}
+
+/// The stable hash of `item`, including its span. The contents of the item
+/// are hashed in their pretty-printed form, so the spans of its parts are not
+/// taken into account.
+pub fn stable_hash_of_item(item: &Item, codemap: &CodeMap) -> u128 {
+    let mut hcx = StableHashingContext::new(codemap);
+    let mut hasher = StableHasher::new();
+    item.ident.hash_stable(&mut hcx, &mut hasher);
+    item.span.hash_stable(&mut hcx, &mut hasher);
+    pprust::item_to_string(item).hash_stable(&mut hcx, &mut hasher);
+    hasher.finish()
+}
+
+impl<'a> HashStable<StableHashingContext<'a>> for Span {
+    fn hash_stable<W: StableHasherResult>(
+        &self,
+        hcx: &mut StableHashingContext<'a>,
+        hasher: &mut StableHasher<W>,
+    ) {
+        if hcx.codemap.files().is_empty() {
+            return 0u8.hash_stable(hcx, hasher);
+        }
+        let lo = hcx.codemap.lookup_byte_offset(self.lo);
+        1u8.hash_stable(hcx, hasher);
+        // Hashed as the name's text, as when names were plain strings.
+        lo.fm.name.to_string().hash_stable(hcx, hasher);
+        lo.pos.0.hash_stable(hcx, hasher);
+        (self.hi - lo.fm.start_pos).0.hash_stable(hcx, hasher);
+    }
+}
+
+impl<'a> HashStable<StableHashingContext<'a>> for Symbol {
+    fn hash_stable<W: StableHasherResult>(
+        &self,
+        hcx: &mut StableHashingContext<'a>,
+        hasher: &mut StableHasher<W>,
+    ) {
+        (*self.as_str()).hash_stable(hcx, hasher);
+    }
+}
+
+impl<'a> HashStable<StableHashingContext<'a>> for Ident {
+    fn hash_stable<W: StableHasherResult>(
+        &self,
+        hcx: &mut StableHashingContext<'a>,
+        hasher: &mut StableHasher<W>,
+    ) {
+        self.name.hash_stable(hcx, hasher);
+    }
+}
+
+impl<'a, T: HashStable<StableHashingContext<'a>>> HashStable<StableHashingContext<'a>>
+    for Spanned<T>
+{
+    fn hash_stable<W: StableHasherResult>(
+        &self,
+        hcx: &mut StableHashingContext<'a>,
+        hasher: &mut StableHasher<W>,
+    ) {
+        self.node.hash_stable(hcx, hasher);
+        self.span.hash_stable(hcx, hasher);
+    }
+}
+
+// These types do not contain symbols or spans, so their `Hash` is stable.
+macro_rules! impl_stable_hash_via_hash {
+    ($($t:ty),*) => {$(
+        impl<'a> HashStable<StableHashingContext<'a>> for $t {
+            fn hash_stable<W: StableHasherResult>(
+                &self,
+                _: &mut StableHashingContext<'a>,
+                hasher: &mut StableHasher<W>,
+            ) {
+                ::std::hash::Hash::hash(self, hasher);
+            }
+        }
+    )*};
+}
+
+impl_stable_hash_via_hash!(StrStyle, LitIntType, IntTy, UintTy, FloatTy);
+
+impl<'a> HashStable<StableHashingContext<'a>> for LitKind {
+    fn hash_stable<W: StableHasherResult>(
+        &self,
+        hcx: &mut StableHashingContext<'a>,
+        hasher: &mut StableHasher<W>,
+    ) {
+        match *self {
+            LitKind::Str(sym, style) => {
+                0u8.hash_stable(hcx, hasher);
+                sym.hash_stable(hcx, hasher);
+                style.hash_stable(hcx, hasher);
+            }
+            LitKind::ByteStr(ref bytes) => {
+                1u8.hash_stable(hcx, hasher);
+                bytes[..].hash_stable(hcx, hasher);
+            }
+            LitKind::Byte(b) => {
+                2u8.hash_stable(hcx, hasher);
+                b.hash_stable(hcx, hasher);
+            }
+            LitKind::Char(c) => {
+                3u8.hash_stable(hcx, hasher);
+                c.hash_stable(hcx, hasher);
+            }
+            LitKind::Int(n, ty) => {
+                4u8.hash_stable(hcx, hasher);
+                n.hash_stable(hcx, hasher);
+                ty.hash_stable(hcx, hasher);
+            }
+            LitKind::Float(sym, ty) => {
+                5u8.hash_stable(hcx, hasher);
+                sym.hash_stable(hcx, hasher);
+                ty.hash_stable(hcx, hasher);
+            }
+            LitKind::FloatUnsuffixed(sym) => {
+                6u8.hash_stable(hcx, hasher);
+                sym.hash_stable(hcx, hasher);
+            }
+            LitKind::Bool(b) => {
+                7u8.hash_stable(hcx, hasher);
+                b.hash_stable(hcx, hasher);
+            }
+            LitKind::CStr(ref bytes) => {
+                8u8.hash_stable(hcx, hasher);
+                bytes[..].hash_stable(hcx, hasher);
+            }
+        }
+    }
+}
+
+impl<'a> HashStable<StableHashingContext<'a>> for MetaItem {
+    fn hash_stable<W: StableHasherResult>(
+        &self,
+        hcx: &mut StableHashingContext<'a>,
+        hasher: &mut StableHasher<W>,
+    ) {
+        self.name.hash_stable(hcx, hasher);
+        self.node.hash_stable(hcx, hasher);
+        self.span.hash_stable(hcx, hasher);
+    }
+}
+
+impl<'a> HashStable<StableHashingContext<'a>> for MetaItemKind {
+    fn hash_stable<W: StableHasherResult>(
+        &self,
+        hcx: &mut StableHashingContext<'a>,
+        hasher: &mut StableHasher<W>,
+    ) {
+        match *self {
+            MetaItemKind::Word => 0u8.hash_stable(hcx, hasher),
+            MetaItemKind::List(ref items) => {
+                1u8.hash_stable(hcx, hasher);
+                items.hash_stable(hcx, hasher);
+            }
+            MetaItemKind::NameValue(ref lit) => {
+                2u8.hash_stable(hcx, hasher);
+                lit.hash_stable(hcx, hasher);
+            }
+        }
+    }
+}
+
+impl<'a> HashStable<StableHashingContext<'a>> for NestedMetaItemKind {
+    fn hash_stable<W: StableHasherResult>(
+        &self,
+        hcx: &mut StableHashingContext<'a>,
+        hasher: &mut StableHasher<W>,
+    ) {
+        match *self {
+            NestedMetaItemKind::MetaItem(ref item) => {
+                0u8.hash_stable(hcx, hasher);
+                item.hash_stable(hcx, hasher);
+            }
+            NestedMetaItemKind::Literal(ref lit) => {
+                1u8.hash_stable(hcx, hasher);
+                lit.hash_stable(hcx, hasher);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codemap::FilePathMapping;
+    use crate::parse::{self, ParseSess};
+    use std::thread;
+
+    // Every thread has its own interner, so each call starts from fresh
+    // symbol indices; `padding` shifts them by interning unrelated strings.
+    fn hash_in_new_thread(source: &'static str, padding: usize) -> (u128, u128) {
+        thread::spawn(move || {
+            for i in 0..padding {
+                Symbol::intern(&format!("padding{}", i));
+            }
+            let sess = ParseSess::new(FilePathMapping::empty());
+            let item =
+                parse::parse_item_from_source_str("a.rs".to_string(), source.to_string(), &sess)
+                    .unwrap()
+                    .unwrap();
+            let meta = item.attrs[0].meta().unwrap();
+            (
+                stable_hash_of_item(&item, sess.codemap()),
+                meta.stable_hash(sess.codemap()),
+            )
+        })
+        .join()
+        .unwrap()
+    }
+
+    #[test]
+    fn stable_across_interners() {
+        let source = "#[doc = \"answer\"]\nfn f() -> u32 {\n    42\n}\n";
+        let (item, meta) = hash_in_new_thread(source, 0);
+        assert_eq!(hash_in_new_thread(source, 100), (item, meta));
+
+        let changed = "#[doc = \"answer\"]\nfn f() -> u32 {\n    43\n}\n";
+        let (changed_item, changed_meta) = hash_in_new_thread(changed, 0);
+        assert_ne!(changed_item, item);
+        assert_eq!(changed_meta, meta);
+
+        let changed = "#[doc = \"answez\"]\nfn f() -> u32 {\n    42\n}\n";
+        assert_ne!(hash_in_new_thread(changed, 0).1, meta);
+    }
+}
@@ -26,6 +26,7 @@ pub enum FnKind<'a> {
         &'a Generics,
         Unsafety,
         Spanned<Constness>,
+        IsAsync,
         Abi,
         &'a Visibility,
         &'a Block,
@@ -233,6 +234,9 @@ pub fn walk_local<'a, V: Visitor<'a>>(visitor: &mut V, local: &'a Local) {
     visitor.visit_pat(&local.pat);
     walk_list!(visitor, visit_ty, &local.ty);
     walk_list!(visitor, visit_expr, &local.init);
+    if let Some(ref els) = local.els {
+        visitor.visit_block(els);
+    }
 }
 
 pub fn walk_lifetime<'a, V: Visitor<'a>>(visitor: &mut V, lifetime: &'a Lifetime) {
@@ -284,15 +288,22 @@ pub fn walk_item<'a, V: Visitor<'a>>(visitor: &mut V, item: &'a Item) {
             visitor.visit_ty(typ);
             visitor.visit_expr(expr);
         }
-        ItemKind::Fn(ref declaration, unsafety, constness, abi, ref generics, ref body) => visitor
-            .visit_fn(
-                FnKind::ItemFn(
-                    item.ident, generics, unsafety, constness, abi, &item.vis, body,
-                ),
-                declaration,
-                item.span,
-                item.id,
+        ItemKind::Fn(
+            ref declaration,
+            unsafety,
+            constness,
+            asyncness,
+            abi,
+            ref generics,
+            ref body,
+        ) => visitor.visit_fn(
+            FnKind::ItemFn(
+                item.ident, generics, unsafety, constness, asyncness, abi, &item.vis, body,
             ),
+            declaration,
+            item.span,
+            item.id,
+        ),
         ItemKind::Mod(ref module) => visitor.visit_mod(module, item.span, &item.attrs, item.id),
         ItemKind::ForeignMod(ref foreign_module) => {
             walk_list!(visitor, visit_foreign_item, &foreign_module.items);
@@ -326,11 +337,15 @@ pub fn walk_item<'a, V: Visitor<'a>>(visitor: &mut V, item: &'a Item) {
             visitor.visit_generics(generics);
             visitor.visit_variant_data(struct_definition, item.ident, generics, item.id, item.span);
         }
-        ItemKind::Trait(_, ref generics, ref bounds, ref methods) => {
+        ItemKind::Trait(_, _, ref generics, ref bounds, ref methods) => {
             visitor.visit_generics(generics);
             walk_list!(visitor, visit_ty_param_bound, bounds);
             walk_list!(visitor, visit_trait_item, methods);
         }
+        ItemKind::TraitAlias(ref generics, ref bounds) => {
+            visitor.visit_generics(generics);
+            walk_list!(visitor, visit_ty_param_bound, bounds);
+        }
         ItemKind::Mac(ref mac) => visitor.visit_mac(mac),
         ItemKind::MacroDef(ref ts) => visitor.visit_mac_def(ts, item.id),
     }
@@ -427,7 +442,7 @@ pub fn walk_path_segment<'a, V: Visitor<'a>>(
     path_span: Span,
     segment: &'a PathSegment,
 ) {
-    visitor.visit_ident(path_span, segment.identifier);
+    visitor.visit_ident(segment.span, segment.identifier);
     if let Some(ref parameters) = segment.parameters {
         visitor.visit_path_parameters(path_span, parameters);
     }
@@ -513,6 +528,8 @@ pub fn walk_foreign_item<'a, V: Visitor<'a>>(visitor: &mut V, foreign_item: &'a
             visitor.visit_generics(generics)
         }
         ForeignItemKind::Static(ref typ, _) => visitor.visit_ty(typ),
+        ForeignItemKind::Ty => (),
+        ForeignItemKind::Macro(ref mac) => visitor.visit_mac(mac),
     }
 
     walk_list!(visitor, visit_attribute, &foreign_item.attrs);
@@ -580,7 +597,7 @@ pub fn walk_where_predicate<'a, V: Visitor<'a>>(visitor: &mut V, predicate: &'a
 }
 
 pub fn walk_fn_ret_ty<'a, V: Visitor<'a>>(visitor: &mut V, ret_ty: &'a FunctionRetTy) {
-    if let FunctionRetTy::Ty(ref output_ty) = *ret_ty {
+    if let FunctionRetTy::Ty(ref output_ty, _) = *ret_ty {
         visitor.visit_ty(output_ty)
     }
 }
@@ -598,7 +615,7 @@ where
     V: Visitor<'a>,
 {
     match kind {
-        FnKind::ItemFn(_, generics, _, _, _, _, body) => {
+        FnKind::ItemFn(_, generics, _, _, _, _, _, body) => {
             visitor.visit_generics(generics);
             walk_fn_decl(visitor, declaration);
             visitor.visit_block(body);
@@ -773,6 +790,10 @@ pub fn walk_expr<'a, V: Visitor<'a>>(visitor: &mut V, expression: &'a Expr) {
             visitor.visit_block(if_block);
             walk_list!(visitor, visit_expr, optional_else);
         }
+        ExprKind::Let(ref pattern, ref subexpression) => {
+            visitor.visit_pat(pattern);
+            visitor.visit_expr(subexpression);
+        }
         ExprKind::WhileLet(ref pattern, ref subexpression, ref block, ref opt_sp_ident) => {
             visitor.visit_pat(pattern);
             visitor.visit_expr(subexpression);
@@ -850,7 +871,8 @@ pub fn walk_expr<'a, V: Visitor<'a>>(visitor: &mut V, expression: &'a Expr) {
             }
         }
         ExprKind::Try(ref subexpression) => visitor.visit_expr(subexpression),
-        ExprKind::Catch(ref body) => visitor.visit_block(body),
+        ExprKind::Catch(ref body) | ExprKind::Async(_, ref body) => visitor.visit_block(body),
+        ExprKind::Await(ref subexpression) => visitor.visit_expr(subexpression),
     }
 
     visitor.visit_expr_post(expression)
@@ -0,0 +1,73 @@
+//! Parses several unrelated files in one `ParseSess::new_for_batch` session,
+//! checking that a fatal error in one of them doesn't stop the others.
+
+use garando_syntax::codemap::FilePathMapping;
+use garando_syntax::parse::{self, ParseSess};
+
+use std::path::Path;
+
+#[test]
+pub fn fatal_error_does_not_poison_batch() {
+    let (sess, diagnostics) = ParseSess::new_for_batch(FilePathMapping::empty());
+    let files = [
+        ("a.rs", "fn a() {}"),
+        ("b.rs", "fn b() { let s = \"unterminated; }"),
+        ("c.rs", "fn c() -> u8 { 1 }\nstruct C;"),
+    ];
+
+    let mut parsed = Vec::new();
+    for &(name, src) in &files {
+        let filemap = sess.codemap().new_filemap(name.into(), src.to_string());
+        let krate = match parse::maybe_filemap_to_parser(&sess, filemap) {
+            Ok(mut parser) => parser.parse_crate_mod(),
+            Err(e) => Err(e),
+        };
+        match krate {
+            Ok(krate) => parsed.push((name, krate.module.items.len())),
+            Err(mut e) => e.emit(),
+        }
+    }
+
+    assert_eq!(parsed, [("a.rs", 1), ("c.rs", 2)]);
+    let messages: Vec<_> = diagnostics
+        .take_diagnostics()
+        .into_iter()
+        .map(|d| d.message())
+        .collect();
+    assert_eq!(messages, ["unterminated double quote string"]);
+    assert_eq!(sess.span_diagnostic.err_count(), 1);
+}
+
+#[test]
+pub fn fatal_error_in_submodule_does_not_poison_batch() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/batch");
+    let (sess, diagnostics) = ParseSess::new_for_batch(FilePathMapping::empty());
+
+    let mut parsed = Vec::new();
+    for name in &["missing_mod.rs", "bad_submod.rs", "fine.rs"] {
+        let filemap = sess.codemap().load_file(&dir.join(name)).unwrap();
+        let krate = match parse::maybe_filemap_to_parser(&sess, filemap) {
+            Ok(mut parser) => parser.parse_crate_mod(),
+            Err(e) => Err(e),
+        };
+        match krate {
+            Ok(krate) => parsed.push((*name, krate.module.items.len())),
+            Err(mut e) => e.emit(),
+        }
+    }
+
+    assert_eq!(parsed, [("fine.rs", 2)]);
+    let messages: Vec<_> = diagnostics
+        .take_diagnostics()
+        .into_iter()
+        .map(|d| d.message())
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "file not found for module `missing`",
+            "unterminated double quote string",
+        ]
+    );
+    assert!(sess.included_mod_stack().is_empty());
+}
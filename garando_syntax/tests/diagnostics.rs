@@ -0,0 +1,224 @@
+//! Parses, configures and expands each fixture under
+//! `tests/fixtures/diagnostics`, and compares the JSON diagnostics emitted
+//! with the fixture's committed `.stderr.json` file.
+//!
+//! Run with `BLESS=1` to write the current output to the golden files
+//! instead, after checking that the changes in it are intended.
+
+use garando_syntax::ast;
+use garando_syntax::attr;
+use garando_syntax::codemap::FilePathMapping;
+use garando_syntax::config;
+use garando_syntax::errors::{ColorConfig, FatalError, Handler};
+use garando_syntax::ext::base::{Determinacy, ExtCtxt, MacroKind, Resolver, SyntaxExtension};
+use garando_syntax::ext::expand::{
+    Expansion, ExpansionConfig, Invocation, InvocationKind, MacroExpander,
+};
+use garando_syntax::ext::hygiene::Mark;
+use garando_syntax::ext::tt::collect_macro_defs;
+use garando_syntax::feature_gate::Features;
+use garando_syntax::json::JsonEmitter;
+use garando_syntax::parse::{self, ParseSess};
+use garando_syntax::ptr::P;
+use garando_syntax::symbol::Symbol;
+use garando_syntax::testing::{self, SharedBuffer};
+
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Resolves bang macros to the `macro_rules!` definitions of the fixture.
+struct FixtureResolver {
+    macros: HashMap<Symbol, Rc<SyntaxExtension>>,
+}
+
+impl Resolver for FixtureResolver {
+    fn next_node_id(&mut self) -> ast::NodeId {
+        ast::DUMMY_NODE_ID
+    }
+    fn get_module_scope(&mut self, _id: ast::NodeId) -> Mark {
+        Mark::root()
+    }
+    fn eliminate_crate_var(&mut self, item: P<ast::Item>) -> P<ast::Item> {
+        item
+    }
+    fn is_whitelisted_legacy_custom_derive(&self, _name: ast::Name) -> bool {
+        false
+    }
+    fn visit_expansion(&mut self, _mark: Mark, _expansion: &Expansion, _derives: &[Mark]) {}
+    fn add_builtin(&mut self, ident: ast::Ident, ext: Rc<SyntaxExtension>) {
+        self.macros.insert(ident.name, ext);
+    }
+    fn resolve_imports(&mut self) {}
+    fn find_legacy_attr_invoc(
+        &mut self,
+        _attrs: &mut Vec<ast::Attribute>,
+    ) -> Option<ast::Attribute> {
+        None
+    }
+    fn resolve_invoc(
+        &mut self,
+        invoc: &mut Invocation,
+        _scope: Mark,
+        _force: bool,
+    ) -> Result<Option<Rc<SyntaxExtension>>, Determinacy> {
+        match invoc.kind {
+            InvocationKind::Bang { ref mac, .. } => {
+                let name = mac.node.path.segments.last().unwrap().identifier.name;
+                match self.macros.get(&name) {
+                    Some(ext) => Ok(Some(ext.clone())),
+                    None => Err(Determinacy::Determined),
+                }
+            }
+            _ => Err(Determinacy::Determined),
+        }
+    }
+    fn resolve_macro(
+        &mut self,
+        _scope: Mark,
+        _path: &ast::Path,
+        _kind: MacroKind,
+        _force: bool,
+    ) -> Result<Rc<SyntaxExtension>, Determinacy> {
+        Err(Determinacy::Determined)
+    }
+    fn check_unused_macros(&self) {}
+}
+
+/// Runs `krate` through the same steps as a compiler driver would: checking
+/// for duplicate attributes, configuration and macro expansion.
+fn check_crate(krate: ast::Crate, sess: &ParseSess) {
+    attr::check_crate_duplicates(&krate, sess);
+    let (krate, features) = config::features(krate, sess, false);
+
+    let mut resolver = FixtureResolver {
+        macros: HashMap::new(),
+    };
+    let macro_features = RefCell::new(Features::new());
+    for def in collect_macro_defs(&krate) {
+        resolver.add_builtin(def.ident(), Rc::new(def.compile(sess, &macro_features)));
+    }
+    let ecfg = ExpansionConfig::default("fixture".to_string()).features(&features);
+    let mut cx = ExtCtxt::new(sess, ecfg, &mut resolver);
+    MacroExpander::new(&mut cx, false).expand_crate(krate);
+}
+
+/// The JSON diagnostics emitted for the fixture at `path`, one per line.
+fn emit_diagnostics(path: &Path) -> String {
+    let sink = SharedBuffer::default();
+    let cm = Rc::new(garando_syntax::codemap::CodeMap::new(
+        FilePathMapping::empty(),
+    ));
+    let emitter = JsonEmitter::new(Box::new(sink.clone()), None, cm.clone())
+        .with_rendered(ColorConfig::Never);
+    let handler = Handler::with_emitter(true, false, Box::new(emitter));
+    handler.set_continue_after_error(true);
+    let sess = ParseSess::with_span_handler(handler, cm);
+
+    let filemap = sess.codemap().load_file(path).unwrap();
+    // Fatal errors abort the fixture as they would a compilation, once they
+    // have been emitted.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let krate = parse::maybe_filemap_to_parser(&sess, filemap)
+            .and_then(|mut parser| parser.parse_crate_mod());
+        match krate {
+            Ok(krate) => check_crate(krate, &sess),
+            Err(mut e) => e.emit(),
+        }
+    }));
+    if let Err(payload) = result {
+        if !payload.is::<FatalError>() {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    sink.contents()
+}
+
+/// Replaces the fixture directory in every string with `$DIR`, so that the
+/// output doesn't depend on where the repository is checked out, and removes
+/// the byte offsets of spans, which depend on line endings. Lines and columns
+/// locate the spans just as well.
+fn normalize(value: &mut Value, dir: &str) {
+    match *value {
+        Value::String(ref mut s) => {
+            if s.contains(dir) {
+                *s = s.replace(dir, "$DIR");
+            }
+        }
+        Value::Array(ref mut values) => {
+            for value in values {
+                normalize(value, dir);
+            }
+        }
+        Value::Object(ref mut map) => {
+            map.remove("byte_start");
+            map.remove("byte_end");
+            for value in map.values_mut() {
+                normalize(value, dir);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The normalized diagnostics for the fixture at `path`, as a pretty-printed
+/// JSON array.
+fn normalized_diagnostics(path: &Path, dir: &Path) -> String {
+    let dir = dir.display().to_string();
+    let mut diagnostics = testing::json_diagnostics(&emit_diagnostics(path));
+    for value in &mut diagnostics {
+        normalize(value, &dir);
+    }
+    let mut json = serde_json::to_string_pretty(&diagnostics).unwrap();
+    json.push('\n');
+    json
+}
+
+fn fixtures(dir: &Path) -> Vec<PathBuf> {
+    let mut fixtures = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "rs"))
+        .collect::<Vec<_>>();
+    fixtures.sort();
+    fixtures
+}
+
+#[test]
+pub fn fixture_diagnostics() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/diagnostics");
+    let bless = env::var_os("BLESS").map_or(false, |v| v != "0");
+
+    let fixtures = fixtures(&dir);
+    assert!(!fixtures.is_empty(), "no fixtures in {}", dir.display());
+    let mut failures = Vec::new();
+    for fixture in &fixtures {
+        let actual = normalized_diagnostics(fixture, &dir);
+        let golden = fixture.with_extension("stderr.json");
+        if bless {
+            fs::write(&golden, &actual).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&golden) {
+            Ok(ref expected) if *expected == actual => {}
+            Ok(expected) => failures.push(format!(
+                "{} differs from the output:\n--- expected\n{}+++ actual\n{}",
+                golden.display(),
+                expected,
+                actual
+            )),
+            Err(e) => failures.push(format!("{} can't be read: {}", golden.display(), e)),
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{}\n\nrerun with BLESS=1 to update the golden files",
+        failures.join("\n")
+    );
+}
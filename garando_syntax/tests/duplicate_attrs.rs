@@ -0,0 +1,138 @@
+//! Checks `attr::check_crate_duplicates` against each `OnDuplicate` policy,
+//! asserting on the span of the repetition and of the first occurrence.
+
+use garando_syntax::attr::{self, DuplicatePolicy, OnDuplicate};
+use garando_syntax::codemap::FilePathMapping;
+use garando_syntax::errors::Level;
+use garando_syntax::parse::{self, ParseSess};
+
+/// The level, code and message of a diagnostic, with the snippets of its
+/// primary span and of its labelled spans.
+#[derive(Debug, PartialEq)]
+struct Reported {
+    level: Level,
+    code: Option<String>,
+    message: String,
+    primary: String,
+    labels: Vec<(String, String)>,
+}
+
+fn check(src: &str, policy: &DuplicatePolicy) -> (usize, Vec<Reported>) {
+    let (sess, diagnostics) = ParseSess::new_for_batch(FilePathMapping::empty());
+    let krate =
+        match parse::parse_crate_from_source_str("dup.rs".to_string(), src.to_string(), &sess) {
+            Ok(krate) => krate,
+            Err(mut e) => {
+                e.emit();
+                panic!("{:?} failed to parse", src);
+            }
+        };
+    let count = attr::check_crate_duplicates_with_policy(&krate, policy, &sess);
+
+    let cm = sess.codemap();
+    let reported = diagnostics
+        .take_diagnostics()
+        .into_iter()
+        .map(|d| Reported {
+            level: d.level,
+            code: d.code.clone(),
+            message: d.message(),
+            primary: cm.span_to_snippet(d.span.primary_span().unwrap()).unwrap(),
+            labels: d
+                .span
+                .span_labels()
+                .into_iter()
+                .filter(|l| !l.is_primary)
+                .map(|l| (cm.span_to_snippet(l.span).unwrap(), l.label.unwrap()))
+                .collect(),
+        })
+        .collect();
+    (count, reported)
+}
+
+fn previously(snippet: &str) -> Vec<(String, String)> {
+    vec![(snippet.to_string(), "previously specified here".to_string())]
+}
+
+#[test]
+pub fn error_on_duplicate() {
+    let src = "#[path = \"a.rs\"]\n#[path = \"b.rs\"]\nmod m {}";
+    let (count, reported) = check(src, &DuplicatePolicy::default());
+    assert_eq!(count, 1);
+    assert_eq!(
+        reported,
+        [Reported {
+            level: Level::Error,
+            code: Some("E0538".to_string()),
+            message: "multiple `path` attributes".to_string(),
+            primary: "#[path = \"b.rs\"]".to_string(),
+            labels: previously("#[path = \"a.rs\"]"),
+        }]
+    );
+}
+
+#[test]
+pub fn warn_following() {
+    let src = "#[test]\n#[ignore]\n#[ignore = \"slow\"]\nfn t() {}";
+    let (count, reported) = check(src, &DuplicatePolicy::default());
+    assert_eq!(count, 1);
+    assert_eq!(
+        reported,
+        [Reported {
+            level: Level::Warning,
+            code: None,
+            message: "unused attribute".to_string(),
+            primary: "#[ignore = \"slow\"]".to_string(),
+            labels: previously("#[ignore]"),
+        }]
+    );
+}
+
+#[test]
+pub fn future_warn() {
+    // Every repetition points back at the first occurrence.
+    let src = "struct S;\nimpl S {\n    #[inline]\n    #[inline(always)]\n    #[inline(never)]\n    fn f() {}\n}";
+    let (count, reported) = check(src, &DuplicatePolicy::default());
+    assert_eq!(count, 2);
+    let primaries: Vec<_> = reported.iter().map(|r| r.primary.as_str()).collect();
+    assert_eq!(primaries, ["#[inline(always)]", "#[inline(never)]"]);
+    for r in &reported {
+        assert_eq!(r.level, Level::Warning);
+        assert_eq!(r.code.as_deref(), Some("E0538"));
+        assert_eq!(r.message, "multiple `inline` attributes");
+        assert_eq!(r.labels, previously("#[inline]"));
+    }
+}
+
+#[test]
+pub fn duplicates_ok() {
+    let src = "#[repr(C)]\n#[repr(align(8))]\nstruct S {\n    #[doc = \"a\"]\n    #[doc = \"b\"]\n    x: u8,\n}";
+    let (count, reported) = check(src, &DuplicatePolicy::default());
+    assert_eq!(count, 0);
+    assert_eq!(reported, []);
+}
+
+#[test]
+pub fn custom_policy() {
+    let src = "#![macro_use]\n#![macro_use(a)]\nfn f() {\n    #[cfg_attr(a, b)]\n    #[cfg_attr(c, d)]\n    let x = 1;\n}";
+
+    let (count, _) = check(src, &DuplicatePolicy::empty());
+    assert_eq!(count, 0);
+
+    let mut policy = DuplicatePolicy::default();
+    policy.insert("cfg_attr", OnDuplicate::ErrorOnDuplicate);
+    let (count, reported) = check(src, &policy);
+    assert_eq!(count, 2);
+    assert_eq!(reported[0].primary, "#![macro_use(a)]");
+    assert_eq!(reported[0].labels[0].0, "#![macro_use]");
+    assert_eq!(
+        reported[1],
+        Reported {
+            level: Level::Error,
+            code: Some("E0538".to_string()),
+            message: "multiple `cfg_attr` attributes".to_string(),
+            primary: "#[cfg_attr(c, d)]".to_string(),
+            labels: previously("#[cfg_attr(a, b)]"),
+        }
+    );
+}
@@ -0,0 +1,3 @@
+mod unterminated;
+
+fn b() {}
@@ -0,0 +1,5 @@
+fn c() -> u8 {
+    1
+}
+
+struct C;
@@ -0,0 +1,3 @@
+mod missing;
+
+fn a() {}
@@ -0,0 +1 @@
+fn u() { let s = "unterminated; }
@@ -0,0 +1,4 @@
+#[cfg(unix, windows)]
+fn platform() {}
+
+fn main() {}
@@ -0,0 +1,5 @@
+#[path = "a.rs"]
+#[path = "b.rs"]
+mod m {}
+
+fn main() {}
@@ -0,0 +1,16 @@
+macro_rules! make_fn {
+    ($name:ident) => {
+        fn $name() {}
+    };
+}
+
+macro_rules! make_fns {
+    () => {
+        make_fn!(a);
+        make_fn!(2);
+    };
+}
+
+make_fns!();
+
+fn main() {}
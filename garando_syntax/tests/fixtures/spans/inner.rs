@@ -0,0 +1,14 @@
+//! An out-of-line module of the span fixture.
+
+pub fn helper() -> u32 {
+    let values = [3u32, 1, 2];
+    values.iter().cloned().max().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn helper_is_max() {
+        assert_eq!(super::helper(), 3);
+    }
+}
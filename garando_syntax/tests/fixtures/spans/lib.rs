@@ -0,0 +1,144 @@
+//! A medium-sized crate exercising most of the grammar, whose parsed spans
+//! are checked by `tests/span_validation.rs`.
+
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Display};
+
+mod inner;
+
+pub mod shapes {
+    /// A shape with an area.
+    pub trait Shape: Debug {
+        const SIDES: u32;
+        type Unit;
+
+        fn area(&self) -> f64;
+        fn describe(&self) -> String {
+            format!("{} sides, area {}", Self::SIDES, self.area())
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Rect {
+        pub w: f64,
+        pub h: f64,
+    }
+
+    impl Shape for Rect {
+        const SIDES: u32 = 4;
+        type Unit = f64;
+
+        fn area(&self) -> f64 {
+            self.w * self.h
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Token<'a> {
+    Word(&'a str),
+    Number { value: i64, negative: bool },
+    End,
+}
+
+pub struct Counter<K: std::hash::Hash + Eq> {
+    counts: HashMap<K, usize>,
+}
+
+impl<K> Counter<K>
+where
+    K: std::hash::Hash + Eq + Clone,
+{
+    pub fn new() -> Self {
+        Counter {
+            counts: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, key: &K) -> usize {
+        let count = self.counts.entry(key.clone()).or_insert(0);
+        *count += 1;
+        *count
+    }
+}
+
+impl<'a> Display for Token<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Word(w) if !w.is_empty() => write!(f, "{}", w),
+            Token::Word(_) => Ok(()),
+            Token::Number { value, negative: true } => write!(f, "-{}", value),
+            Token::Number { value, .. } => write!(f, "{}", value),
+            Token::End => f.write_str("<end>"),
+        }
+    }
+}
+
+pub fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    for word in input.split_whitespace() {
+        let token = match word.parse::<i64>() {
+            Ok(n) if n < 0 => Token::Number {
+                value: -n,
+                negative: true,
+            },
+            Ok(n) => Token::Number {
+                value: n,
+                negative: false,
+            },
+            Err(_) => Token::Word(word),
+        };
+        tokens.push(token);
+    }
+    tokens.push(Token::End);
+    tokens
+}
+
+pub fn sum<T, I>(items: I) -> T
+where
+    T: Default + std::ops::Add<Output = T>,
+    I: IntoIterator<Item = T>,
+{
+    items.into_iter().fold(T::default(), |acc, x| acc + x)
+}
+
+unsafe fn raw(p: *const u8, len: usize) -> &'static [u8] {
+    std::slice::from_raw_parts(p, len)
+}
+
+extern "C" {
+    fn abs(x: i32) -> i32;
+}
+
+static GREETING: &str = "hello";
+const LIMITS: [u8; 3] = [1, 2, 3];
+
+macro_rules! square {
+    ($x:expr) => {
+        $x * $x
+    };
+}
+
+fn control_flow(n: u32) -> u32 {
+    let mut total = 0;
+    let mut i = 0;
+    'outer: loop {
+        while i < n {
+            i += 1;
+            if i % 2 == 0 {
+                continue;
+            } else if i > 100 {
+                break 'outer;
+            }
+            total += square!(i);
+        }
+        break;
+    }
+    let closure = move |a: u32, b| -> u32 { a.max(b) };
+    let tuple = (total, closure(1, 2), &LIMITS[..], GREETING.len() as u32);
+    let (a, b, ..) = tuple;
+    let r = 0..a;
+    a + b + r.end + unsafe { abs(-1) as u32 } + inner::helper()
+}
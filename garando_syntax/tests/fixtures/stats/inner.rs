@@ -0,0 +1,5 @@
+pub struct S;
+
+impl S {
+    fn f(&self) {}
+}
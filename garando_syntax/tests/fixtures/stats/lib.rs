@@ -0,0 +1,8 @@
+// The crate parsed by `tests/parse_stats.rs`.
+
+mod inner;
+
+fn main() {
+    let v = vec![1, 2];
+    println!("{:?}", v);
+}
@@ -0,0 +1,144 @@
+//! Inputs that have made the parser panic, or that exercise the paths most
+//! likely to, all of which must parse through
+//! `parse_crate_from_source_str_no_panic` without unwinding. Add new cases
+//! to `CORPUS` as one line each.
+
+use garando_syntax::codemap::FilePathMapping;
+use garando_syntax::parse::{self, ParseSess};
+
+use std::io;
+use std::panic;
+use std::thread;
+
+const CORPUS: &[&str] = &[
+    // Unterminated and malformed character literals.
+    "'",
+    "''",
+    "'a",
+    "'ab'",
+    "'\\",
+    "'\\'",
+    "'\\n",
+    "'\\x",
+    "'\\x4",
+    "'\\u{",
+    "'\\u{41",
+    "'\\u{FFFFFFFFF}'",
+    "'\r",
+    "'\\\r",
+    "fn f() { '\\u{110000}'; }",
+    "fn f() { '\\u{d800}'; }",
+    "fn f() { '\\u{}'; }",
+    "fn f() { '\\x80'; }",
+    "fn f() { '\\q'; }",
+    "fn f() { '\\u{_41}'; }",
+    // Byte and byte string escapes.
+    "b'",
+    "b'\\",
+    "b'\\x",
+    "b'ab'",
+    "b'\r",
+    "b'\\r\\n'",
+    "fn f() { b'\\u{41}'; }",
+    "fn f() { b'\u{e9}'; }",
+    "b\"\\",
+    "b\"\\x",
+    "b\"\\x4",
+    "b\"\\u",
+    "fn f() { b\"\\u{41}\"; }",
+    "fn f() { b\"\\xZZ\"; }",
+    "fn f() { b\"\u{e9}\"; }",
+    "fn f() { br\"\u{e9}\"; }",
+    // String escapes and raw strings.
+    "\"\\",
+    "\"\\u{",
+    "\"\\\r",
+    "fn f() { \"\\u{}\"; }",
+    "fn f() { \"\\u{d800}\"; }",
+    "fn f() { \"\\x80\"; }",
+    "fn f() { \"a\rb\"; }",
+    "r#",
+    "r\"",
+    "br\"",
+    "br#",
+    "r##\"a\"#",
+    "#[doc = \"",
+    "extern \"",
+    "extern \"\\",
+    // Numeric literals and field access.
+    "fn f() { 0x; }",
+    "fn f() { 1e+; }",
+    "fn f() { 0x1.0; }",
+    "fn f() { 1u7; }",
+    "fn f() { x.1e1 }",
+    "fn f() { x.0x1 }",
+    "fn f() { 99999999999999999999999999999999999999999; }",
+    // Comments, carriage returns and unusual whitespace at end of input.
+    "/*",
+    "/*\r",
+    "//\r",
+    "\r",
+    "fn f() {}\r",
+    "\u{feff}",
+    "\u{2028}",
+    // Recovery paths at end of input.
+    "fn f(",
+    "fn f<'",
+    "fn f() { let x = ",
+    "fn f() {))",
+    "(]",
+    "#!",
+    "macro_rules! m {",
+    "struct S<",
+];
+
+fn parse_without_unwinding(source: &str) -> thread::Result<()> {
+    let source = source.to_string();
+    panic::catch_unwind(move || {
+        let sess = ParseSess::with_json_emitter(FilePathMapping::empty(), Box::new(io::sink()));
+        let result =
+            parse::parse_crate_from_source_str_no_panic("fuzz.rs".to_string(), source, &sess);
+        if let Err(mut err) = result {
+            err.emit();
+        }
+    })
+}
+
+// Both tests run on the test thread's default stack, which the parser grows
+// as needed to reach the nesting limit.
+#[test]
+pub fn corpus_parses_without_unwinding() {
+    let unwound: Vec<_> = CORPUS
+        .iter()
+        .filter(|source| parse_without_unwinding(source).is_err())
+        .collect();
+    assert!(unwound.is_empty(), "unwound on {:?}", unwound);
+}
+
+#[test]
+pub fn deep_nesting_parses_without_unwinding() {
+    for open in &[
+        "(",
+        "[",
+        "{",
+        "&",
+        "!",
+        "-",
+        "*",
+        "box ",
+        "|| ",
+        "..",
+        "return ",
+        "a = ",
+        "x as &",
+        "if x {} else ",
+        "fn g() { ",
+        "mod m { ",
+        "impl S { fn g() { ",
+        "trait T { fn g() { ",
+        "struct S; impl S { fn g() { ",
+    ] {
+        let source = format!("fn f() {{ {} }}", open.repeat(10_000));
+        assert!(parse_without_unwinding(&source).is_ok(), "{:?}", open);
+    }
+}
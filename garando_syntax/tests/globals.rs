@@ -0,0 +1,50 @@
+//! Runs the other integration tests again, one after the other under a
+//! single `Globals` installed with `GLOBALS.set`, to check that nothing
+//! reaches past it to the thread's own.
+
+#[path = "batch_parsing.rs"]
+mod batch_parsing;
+#[path = "diagnostics.rs"]
+mod diagnostics;
+#[path = "duplicate_attrs.rs"]
+mod duplicate_attrs;
+#[path = "fuzz_corpus.rs"]
+mod fuzz_corpus;
+#[path = "parse_stats.rs"]
+mod parse_stats;
+#[path = "span_validation.rs"]
+mod span_validation;
+#[path = "token_stream_clones.rs"]
+mod token_stream_clones;
+
+use garando_syntax::codemap::{Globals, GLOBALS};
+use garando_syntax::symbol;
+
+#[test]
+fn all_tests_under_one_globals() {
+    let globals = Globals::new();
+    let thread_default = symbol::interner_stats();
+    // Nothing interned in the scope is used after it.
+    unsafe {
+        GLOBALS.set(&globals, || {
+            batch_parsing::fatal_error_does_not_poison_batch();
+            batch_parsing::fatal_error_in_submodule_does_not_poison_batch();
+            diagnostics::fixture_diagnostics();
+            duplicate_attrs::error_on_duplicate();
+            duplicate_attrs::warn_following();
+            duplicate_attrs::future_warn();
+            duplicate_attrs::duplicates_ok();
+            duplicate_attrs::custom_policy();
+            fuzz_corpus::corpus_parses_without_unwinding();
+            fuzz_corpus::deep_nesting_parses_without_unwinding();
+            parse_stats::fixture_stats();
+            parse_stats::stats_are_opt_in();
+            span_validation::fixture_spans_are_valid();
+            token_stream_clones::clones_are_shallow();
+            token_stream_clones::expander_input_is_shared();
+        })
+    };
+    assert_eq!(symbol::interner_stats(), thread_default);
+    let installed = unsafe { GLOBALS.set(&globals, symbol::interner_stats) };
+    assert!(installed.count > thread_default.count);
+}
@@ -0,0 +1,61 @@
+//! Parses a small fixture crate with `ParseSess::enable_stats` and checks the
+//! numbers collected against counts made by hand.
+
+use garando_syntax::codemap::FilePathMapping;
+use garando_syntax::parse::{self, ParseSess, ParseStats};
+
+use std::path::Path;
+
+#[test]
+pub fn fixture_stats() {
+    let sess = ParseSess::new(FilePathMapping::empty());
+    sess.enable_stats();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/stats");
+    if let Err(mut e) = parse::parse_crate_from_file(&dir.join("lib.rs"), &sess) {
+        e.emit();
+        panic!("the fixture failed to parse");
+    }
+
+    let stats = sess.stats();
+    // `lib.rs` has 28 tokens past its comment, and `inner.rs` has 16.
+    assert_eq!(stats.tokens, 28 + 16);
+    assert_eq!(stats.bytes_lexed, 123 + 45);
+    // `mod inner` and `main`, then `S` and its impl.
+    assert_eq!(stats.items, 4);
+    // `vec!` and `println!`.
+    assert_eq!(stats.macro_invocations, 2);
+    let lines: Vec<_> = stats
+        .lines_per_file
+        .iter()
+        .map(|&(ref name, lines)| (name.to_string(), lines))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            (dir.join("lib.rs").display().to_string(), 8),
+            (dir.join("inner.rs").display().to_string(), 5),
+        ]
+    );
+
+    let table = stats.to_string();
+    assert!(table.starts_with("tokens  "), "{}", table);
+    assert!(table.contains("\nmacro invocations  "), "{}", table);
+    assert!(table.ends_with(&format!("\n    5  {}\n", dir.join("inner.rs").display())));
+    assert_eq!(table.lines().count(), 9);
+}
+
+#[test]
+pub fn stats_are_opt_in() {
+    let sess = ParseSess::new(FilePathMapping::empty());
+    let src = "fn f() { g!(); }".to_string();
+    parse::parse_crate_from_source_str("a.rs".to_string(), src.clone(), &sess).unwrap();
+    assert_eq!(sess.stats(), ParseStats::default());
+
+    sess.enable_stats();
+    parse::parse_crate_from_source_str("b.rs".to_string(), src, &sess).unwrap();
+    let stats = sess.stats();
+    assert_eq!(
+        (stats.tokens, stats.items, stats.macro_invocations),
+        (11, 1, 1)
+    );
+}
@@ -0,0 +1,29 @@
+//! Parses a medium-sized fixture crate and checks its spans with
+//! `util::span_validator`, so regressions in span construction are caught.
+
+use garando_syntax::codemap::FilePathMapping;
+use garando_syntax::parse::{self, ParseSess};
+use garando_syntax::util::span_validator;
+
+use std::path::Path;
+
+#[test]
+pub fn fixture_spans_are_valid() {
+    let sess = ParseSess::new(FilePathMapping::empty());
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/spans/lib.rs");
+    let krate = match parse::parse_crate_from_file(&path, &sess) {
+        Ok(krate) => krate,
+        Err(mut e) => {
+            e.emit();
+            panic!("the fixture failed to parse");
+        }
+    };
+    assert_eq!(
+        sess.codemap().files().len(),
+        2,
+        "`mod inner;` was not loaded"
+    );
+
+    let violations = span_validator::validate(&krate, sess.codemap());
+    assert!(violations.is_empty(), "{:#?}", violations);
+}
@@ -0,0 +1,119 @@
+//! Counts the allocations made by cloning nested token streams and passing
+//! them to function-like macro expanders, which should share the streams'
+//! contents rather than copy them.
+
+use garando_syntax::codemap::{FilePathMapping, Span, DUMMY_SP};
+use garando_syntax::ext::base::{
+    DummyResolver, DummyResult, ExtCtxt, MacResult, MacroExpanderFn, TTMacroExpander,
+};
+use garando_syntax::ext::expand::ExpansionConfig;
+use garando_syntax::parse::ParseSess;
+use garando_syntax::testing::string_to_stream;
+use garando_syntax::tokenstream::{TokenStream, TokenTree};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// Counts the allocations made on each thread, so tests running in parallel
+/// don't see each other's.
+struct CountingAllocator;
+
+thread_local!(static ALLOCATIONS: Cell<usize> = Cell::new(0));
+
+fn count_allocation() {
+    let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The result of `f`, and the number of allocations it made.
+fn allocations<T, F: FnOnce() -> T>(f: F) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+/// `depth` nested pairs of each kind of delimiter around `x`, `width` times
+/// over.
+fn nested(depth: usize, width: usize) -> TokenStream {
+    let mut src = String::new();
+    for _ in 0..width {
+        src.push_str("f(");
+        for i in 0..depth {
+            src.push_str(["(", "[", "{"][i % 3]);
+        }
+        src.push('x');
+        for i in (0..depth).rev() {
+            src.push_str([")", "]", "}"][i % 3]);
+        }
+        src.push_str(") ");
+    }
+    string_to_stream(src)
+}
+
+#[test]
+pub fn clones_are_shallow() {
+    let stream = nested(30, 10);
+    let mut clones = Vec::with_capacity(1000);
+    let ((), n) = allocations(|| {
+        for _ in 0..1000 {
+            clones.push(stream.clone());
+        }
+    });
+    assert_eq!(n, 0);
+    assert!(clones.iter().all(|clone| *clone == stream));
+
+    let trees: Vec<TokenTree> = stream.trees().collect();
+    let mut clones = Vec::with_capacity(1000);
+    let ((), n) = allocations(|| {
+        for _ in 0..1000 {
+            clones.push(trees[1].clone());
+        }
+    });
+    assert_eq!(n, 0);
+}
+
+fn expand_dummy<'cx>(_: &'cx mut ExtCtxt, sp: Span, tts: &[TokenTree]) -> Box<dyn MacResult + 'cx> {
+    assert_eq!(tts.len(), 20);
+    DummyResult::any(sp)
+}
+
+#[test]
+pub fn expander_input_is_shared() {
+    let sess = ParseSess::new(FilePathMapping::empty());
+    let mut resolver = DummyResolver;
+    let mut cx = ExtCtxt::new(
+        &sess,
+        ExpansionConfig::default("test".to_string()),
+        &mut resolver,
+    );
+    let expander: MacroExpanderFn = expand_dummy;
+
+    // the top level of the input is collected for the expander, but nothing
+    // inside its delimiters is copied, however deeply they nest
+    let mut expand = |stream: TokenStream| {
+        let (result, n) = allocations(|| expander.expand(&mut cx, DUMMY_SP, stream));
+        drop(result);
+        n
+    };
+    let shallow = expand(nested(0, 10));
+    let deep = expand(nested(30, 10));
+    assert_eq!(shallow, deep);
+}